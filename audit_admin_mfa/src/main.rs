@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::audit::Severity;
+use graph_common::roles::{self, role_severity};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+// Phone methods can be intercepted by SIM swapping; email only verifies self-service
+// password reset and is not an MFA method.
+const PHONE_METHODS: &[&str] = &["mobilePhone", "alternateMobilePhone", "officePhone"];
+const NON_MFA_METHODS: &[&str] = &["email", "securityQuestion", "temporaryAccessPass"];
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_admin_mfa")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports directory role members who are not registered for MFA or only have phone methods, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct RegistrationDetails {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "isMfaRegistered", default)]
+    is_mfa_registered: bool,
+    #[serde(rename = "methodsRegistered", default)]
+    methods_registered: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct AdminFinding {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    severity: Severity,
+    issue: String,
+    #[serde(rename = "methodsRegistered")]
+    methods_registered: Vec<String>,
+    roles: Vec<String>,
+}
+
+struct Scan {
+    findings: Vec<AdminFinding>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// "notRegistered" or "phoneOnly", or None when a method other than phone is registered.
+fn mfa_issue(details: &RegistrationDetails) -> Option<&'static str> {
+    let mfa_methods: Vec<&str> = details
+        .methods_registered
+        .iter()
+        .map(String::as_str)
+        .filter(|method| !NON_MFA_METHODS.contains(method))
+        .collect();
+    if !details.is_mfa_registered || mfa_methods.is_empty() {
+        Some("notRegistered")
+    } else if mfa_methods.iter().all(|method| PHONE_METHODS.contains(method)) {
+        Some("phoneOnly")
+    } else {
+        None
+    }
+}
+
+// The severity of the most privileged role, one step lower when the admin at least has a
+// phone method.
+fn severity(issue: &str, roles: &[String]) -> Severity {
+    let severity = roles.iter().map(|role| role_severity(role)).max().unwrap_or(Severity::Low);
+    match (issue, severity) {
+        ("phoneOnly", Severity::High) => Severity::Medium,
+        ("phoneOnly", _) => Severity::Low,
+        _ => severity,
+    }
+}
+
+async fn registration_details(client: &GraphClient, user_id: &str) -> Result<RegistrationDetails> {
+    client
+        .path(&["reports", "authenticationMethods", "userRegistrationDetails", user_id])
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to fetch the MFA registration of user {}", user_id))
+}
+
+async fn scan(client: &GraphClient, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list role members");
+    let mut admins: Vec<(String, Vec<String>)> = roles::role_assignments(client).await?.into_iter().collect();
+    admins.sort();
+    drop(phase);
+    info!("Checking the MFA registration of {} administrators", admins.len());
+
+    let _phase = client.stats().phase("read registrations");
+    let results: Vec<(&Vec<String>, Result<RegistrationDetails>)> = stream::iter(&admins)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|(user_id, roles)| async move { (roles, registration_details(client, user_id).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut findings = Vec::new();
+    let mut failed = 0;
+    for (roles, result) in results {
+        let details = match result {
+            Ok(details) => details,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let Some(issue) = mfa_issue(&details) else {
+            continue;
+        };
+        let mut roles = roles.clone();
+        roles.sort_by_key(|role| std::cmp::Reverse(role_severity(role)));
+        findings.push(AdminFinding {
+            severity: severity(issue, &roles),
+            user_principal_name: details.user_principal_name,
+            issue: issue.to_string(),
+            methods_registered: details.methods_registered,
+            roles,
+        });
+    }
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.user_principal_name.cmp(&b.user_principal_name))
+    });
+    Ok(Scan {
+        findings,
+        checked,
+        failed,
+        interrupted: checked < admins.len(),
+    })
+}
+
+fn issue_text(issue: &str) -> &'static str {
+    match issue {
+        "phoneOnly" => "phone methods only",
+        _ => "not MFA registered",
+    }
+}
+
+fn render_report(findings: &[AdminFinding], admins: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "administrators": admins,
+                "findings": findings,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,severity,issue,methodsRegistered,roles")?;
+            for finding in findings {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv::field(&finding.user_principal_name),
+                    finding.severity,
+                    finding.issue,
+                    csv::field(&finding.methods_registered.join("; ")),
+                    csv::field(&finding.roles.join("; "))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<9} {:<20} {:<34} ROLES",
+                "USER", "SEVERITY", "ISSUE", "METHODS"
+            )?;
+            for finding in findings {
+                writeln!(
+                    out,
+                    "{:<35} {:<9} {:<20} {:<34} {}",
+                    finding.user_principal_name,
+                    finding.severity.to_string(),
+                    issue_text(&finding.issue),
+                    if finding.methods_registered.is_empty() { "-".to_string() } else { finding.methods_registered.join(", ") },
+                    finding.roles.join(", ")
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} administrators lack strong MFA: {} not registered, {} phone methods only",
+                findings.len(),
+                admins,
+                findings.iter().filter(|finding| finding.issue == "notRegistered").count(),
+                findings.iter().filter(|finding| finding.issue == "phoneOnly").count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_admin_mfa");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Directory.Read.All", "AuditLog.Read.All"])?;
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, config.concurrency as usize, &shutdown).await?;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.findings, scan.checked, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} administrators; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "The MFA registration of {} of {} administrators could not be read; they are missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} administrators could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let scan = scan(&client, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (6, 1, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.findings, scan.checked, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::audit::Severity;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_app_credentials")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports client secrets and certificates of app registrations and service principals that are expired or about to expire, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(0..), help = "Flag credentials that expire within this many days")]
+    days: i64,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryObject {
+    #[serde(rename = "appId")]
+    app_id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "servicePrincipalType")]
+    service_principal_type: Option<String>,
+    #[serde(rename = "passwordCredentials", default)]
+    password_credentials: Vec<Credential>,
+    #[serde(rename = "keyCredentials", default)]
+    key_credentials: Vec<Credential>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Credential {
+    #[serde(rename = "keyId")]
+    key_id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "endDateTime")]
+    end_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+struct ExpiringCredential {
+    severity: Severity,
+    #[serde(rename = "objectType")]
+    object_type: &'static str,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "appId")]
+    app_id: String,
+    #[serde(rename = "credentialType")]
+    credential_type: &'static str,
+    #[serde(rename = "credentialName")]
+    credential_name: String,
+    #[serde(rename = "keyId")]
+    key_id: String,
+    #[serde(rename = "endDateTime")]
+    end_date_time: DateTime<Utc>,
+    #[serde(rename = "daysLeft")]
+    days_left: i64,
+    status: &'static str,
+    replaced: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_objects(client: &GraphClient) -> Result<Vec<(&'static str, DirectoryObject)>> {
+    let applications: Vec<DirectoryObject> = client
+        .path(&["applications"])
+        .select("id,appId,displayName,passwordCredentials,keyCredentials")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch applications")?;
+    let service_principals: Vec<DirectoryObject> = client
+        .path(&["servicePrincipals"])
+        .select("id,appId,displayName,servicePrincipalType,passwordCredentials,keyCredentials")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch service principals")?;
+    // The credentials of managed identities are rotated by Azure.
+    Ok(applications
+        .into_iter()
+        .map(|application| ("application", application))
+        .chain(
+            service_principals
+                .into_iter()
+                .filter(|principal| principal.service_principal_type.as_deref() != Some("ManagedIdentity"))
+                .map(|principal| ("servicePrincipal", principal)),
+        )
+        .collect())
+}
+
+// A credential that expires before the cutoff is replaced when the same object has another
+// credential of the same type that lasts beyond it, so rotation has already happened and only
+// the old one is left to clean up. Expiring credentials that are not replaced will break the
+// app; expired ones that are not replaced already did, or the app is no longer used.
+fn expiring_credentials(
+    objects: Vec<(&'static str, DirectoryObject)>,
+    now: DateTime<Utc>,
+    days: i64,
+) -> Vec<ExpiringCredential> {
+    let cutoff = now + Duration::days(days);
+    let mut expiring = Vec::new();
+    for (object_type, object) in objects {
+        for (credential_type, credentials) in [
+            ("secret", &object.password_credentials),
+            ("certificate", &object.key_credentials),
+        ] {
+            let replaced = credentials
+                .iter()
+                .any(|credential| credential.end_date_time.is_some_and(|end| end > cutoff));
+            // SAML signing certificates are stored as a signing and a verification key with
+            // the same name and expiry, so they are reported once.
+            let mut seen = Vec::new();
+            for credential in credentials {
+                let Some(end) = credential.end_date_time.filter(|end| *end <= cutoff) else {
+                    continue;
+                };
+                if seen.contains(&(&credential.display_name, end)) {
+                    continue;
+                }
+                seen.push((&credential.display_name, end));
+                let status = if end <= now { "expired" } else { "expiring" };
+                expiring.push(ExpiringCredential {
+                    severity: match (status, replaced) {
+                        (_, true) => Severity::Low,
+                        ("expired", false) => Severity::Medium,
+                        _ => Severity::High,
+                    },
+                    object_type,
+                    display_name: object.display_name.clone().unwrap_or_default(),
+                    app_id: object.app_id.clone(),
+                    credential_type,
+                    credential_name: credential.display_name.clone().unwrap_or_default(),
+                    key_id: credential.key_id.clone(),
+                    end_date_time: end,
+                    days_left: (end - now).num_days(),
+                    status,
+                    replaced,
+                });
+            }
+        }
+    }
+    expiring.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.end_date_time.cmp(&b.end_date_time))
+            .then_with(|| a.display_name.cmp(&b.display_name))
+    });
+    expiring
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn render_report(credentials: &[ExpiringCredential], output: &str, days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "days": days,
+                "credentials": credentials,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "severity,objectType,displayName,appId,credentialType,credentialName,keyId,endDateTime,daysLeft,status,replaced")?;
+            for credential in credentials {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    credential.severity,
+                    credential.object_type,
+                    csv::field(&credential.display_name),
+                    credential.app_id,
+                    credential.credential_type,
+                    csv::field(&credential.credential_name),
+                    credential.key_id,
+                    timestamp(credential.end_date_time),
+                    credential.days_left,
+                    credential.status,
+                    credential.replaced
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<9} {:<35} {:<17} {:<12} {:<11} {:>9}  {:<18} CREDENTIAL",
+                "SEVERITY", "APP", "OBJECT", "TYPE", "EXPIRES", "DAYS LEFT", "STATUS"
+            )?;
+            for credential in credentials {
+                writeln!(
+                    out,
+                    "{:<9} {:<35} {:<17} {:<12} {:<11} {:>9}  {:<18} {}",
+                    credential.severity.to_string(),
+                    credential.display_name,
+                    credential.object_type,
+                    credential.credential_type,
+                    credential.end_date_time.format("%Y-%m-%d").to_string(),
+                    credential.days_left,
+                    if credential.replaced { format!("{}, replaced", credential.status) } else { credential.status.to_string() },
+                    if credential.credential_name.is_empty() { "-" } else { &credential.credential_name }
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} credentials expire within {} days, {} already expired; {} are not replaced by a newer credential",
+                credentials.iter().filter(|credential| credential.status == "expiring").count(),
+                days,
+                credentials.iter().filter(|credential| credential.status == "expired").count(),
+                credentials.iter().filter(|credential| !credential.replaced).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_app_credentials");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Application.Read.All"])?;
+
+    let phase = client.stats().phase("list applications");
+    let objects = list_objects(&client).await?;
+    drop(phase);
+    info!("Checking the credentials of {} applications and service principals", objects.len());
+
+    let credentials = expiring_credentials(objects, Utc::now(), config.days);
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&credentials, &config.output, config.days, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let objects = list_objects(&client).await.unwrap();
+        let credentials = expiring_credentials(objects, "2026-10-15T00:00:00Z".parse().unwrap(), 30);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&credentials, output, 30, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_bitlocker_keys")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports Windows devices without an escrowed BitLocker recovery key, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Skip devices that have not signed in for this many days")]
+    inactive_days: i64,
+
+    #[arg(long, help = "List every assessed device, not only devices without a recovery key")]
+    all: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Device {
+    #[serde(rename = "deviceId")]
+    device_id: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "operatingSystemVersion")]
+    operating_system_version: Option<String>,
+    #[serde(rename = "trustType")]
+    trust_type: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "approximateLastSignInDateTime")]
+    approximate_last_sign_in: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecoveryKey {
+    #[serde(rename = "deviceId")]
+    device_id: Option<String>,
+    #[serde(rename = "createdDateTime")]
+    created: Option<DateTime<Utc>>,
+    #[serde(rename = "volumeType")]
+    volume_type: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeviceCoverage {
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "deviceId")]
+    device_id: String,
+    #[serde(rename = "operatingSystemVersion")]
+    operating_system_version: String,
+    #[serde(rename = "joinType")]
+    join_type: &'static str,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<String>,
+    #[serde(rename = "recoveryKeys")]
+    recovery_keys: usize,
+    #[serde(rename = "osVolumeKey")]
+    os_volume_key: bool,
+    #[serde(rename = "lastEscrowed")]
+    last_escrowed: Option<String>,
+    issue: Option<&'static str>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> Option<String> {
+    value.map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+async fn list_windows_devices(client: &GraphClient) -> Result<Vec<Device>> {
+    client
+        .path(&["devices"])
+        .filter("operatingSystem eq 'Windows'")
+        .select("deviceId,displayName,operatingSystemVersion,trustType,accountEnabled,approximateLastSignInDateTime")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch Windows devices")
+}
+
+// Only the key metadata is read; the key itself is returned only when selected explicitly,
+// which needs BitlockerKey.Read.All and is audited as a key read.
+async fn list_recovery_keys(client: &GraphClient) -> Result<Vec<RecoveryKey>> {
+    client
+        .path(&["informationProtection", "bitlocker", "recoveryKeys"])
+        .get_all()
+        .await
+        .context("Failed to fetch BitLocker recovery keys")
+}
+
+// Registered (personal) devices do not escrow their keys to Entra ID, and disabled or inactive
+// devices are not worth recovering, so only enabled joined devices that signed in since the
+// cutoff are assessed. Devices without a key are listed first.
+fn assess(devices: &[Device], keys: &[RecoveryKey], cutoff: DateTime<Utc>) -> Vec<DeviceCoverage> {
+    let mut keys_by_device: HashMap<String, Vec<&RecoveryKey>> = HashMap::new();
+    for key in keys {
+        if let Some(device_id) = &key.device_id {
+            keys_by_device.entry(device_id.to_lowercase()).or_default().push(key);
+        }
+    }
+
+    let mut assessed: Vec<DeviceCoverage> = devices
+        .iter()
+        .filter(|device| device.account_enabled != Some(false))
+        .filter(|device| matches!(device.trust_type.as_deref(), Some("AzureAd") | Some("ServerAd")))
+        .filter(|device| device.approximate_last_sign_in.is_some_and(|last_sign_in| last_sign_in >= cutoff))
+        .map(|device| {
+            let device_id = device.device_id.clone().unwrap_or_default();
+            let keys = keys_by_device.get(&device_id.to_lowercase()).map_or(&[][..], Vec::as_slice);
+            let os_volume_key = keys
+                .iter()
+                .any(|key| key.volume_type.as_deref() == Some("operatingSystemVolume"));
+            let issue = if keys.is_empty() {
+                Some("no recovery key")
+            } else if !os_volume_key {
+                Some("no OS volume key")
+            } else {
+                None
+            };
+            DeviceCoverage {
+                display_name: device.display_name.clone().unwrap_or_default(),
+                device_id,
+                operating_system_version: device.operating_system_version.clone().unwrap_or_default(),
+                join_type: if device.trust_type.as_deref() == Some("ServerAd") {
+                    "hybrid joined"
+                } else {
+                    "joined"
+                },
+                last_sign_in: timestamp(device.approximate_last_sign_in),
+                recovery_keys: keys.len(),
+                os_volume_key,
+                last_escrowed: timestamp(keys.iter().filter_map(|key| key.created).max()),
+                issue,
+            }
+        })
+        .collect();
+    assessed.sort_by_key(|device| (device.issue.is_none(), device.display_name.to_lowercase()));
+    assessed
+}
+
+fn render_report(
+    devices: &[&DeviceCoverage],
+    assessed: &[DeviceCoverage],
+    total: usize,
+    output: &str,
+    inactive_days: i64,
+    run_stats: &Summary,
+) -> Result<String> {
+    let mut out = String::new();
+    let without_key = assessed.iter().filter(|device| device.recovery_keys == 0).count();
+    let without_os_key = assessed.iter().filter(|device| device.issue.is_some()).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "devices": devices,
+                "withoutRecoveryKey": without_key,
+                "withoutOsVolumeKey": without_os_key,
+                "assessedDevices": assessed.len(),
+                "totalWindowsDevices": total,
+                "inactiveDays": inactive_days,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "displayName,deviceId,operatingSystemVersion,joinType,lastSignIn,recoveryKeys,osVolumeKey,lastEscrowed,issue"
+            )?;
+            for device in devices {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv::field(&device.display_name),
+                    device.device_id,
+                    csv::field(&device.operating_system_version),
+                    device.join_type,
+                    device.last_sign_in.as_deref().unwrap_or_default(),
+                    device.recovery_keys,
+                    device.os_volume_key,
+                    device.last_escrowed.as_deref().unwrap_or_default(),
+                    device.issue.unwrap_or_default()
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<30} {:<14} {:<18} {:<12} {:<5} {:<13} ISSUE",
+                "DEVICE", "JOIN TYPE", "VERSION", "LAST SIGN-IN", "KEYS", "LAST ESCROWED"
+            )?;
+            let date = |value: &Option<String>| value.as_deref().map_or("-".to_string(), |value| value[..10].to_string());
+            for device in devices {
+                let line = format!(
+                    "{:<30} {:<14} {:<18} {:<12} {:<5} {:<13} {}",
+                    device.display_name,
+                    device.join_type,
+                    device.operating_system_version,
+                    date(&device.last_sign_in),
+                    device.recovery_keys,
+                    date(&device.last_escrowed),
+                    device.issue.unwrap_or_default()
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} assessed Windows devices have no escrowed recovery key for the OS volume: {} with no key at all, {} with data volume keys only",
+                without_os_key,
+                assessed.len(),
+                without_key,
+                without_os_key - without_key
+            )?;
+            writeln!(
+                out,
+                "{} of {} Windows devices not assessed: disabled, registered only, or no sign-in for {} days",
+                total - assessed.len(),
+                total,
+                inactive_days
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_bitlocker_keys");
+    debug!("Configuration: {:?}", config);
+
+    // The recovery key API only accepts delegated tokens, so a user signs in instead of the
+    // app authenticating with a secret.
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client = GraphClient::from_device_code(&tenant_id, &client_id)
+        .await
+        .context("Failed to obtain access token")?
+        .with_header("ocp-client-name", "audit_bitlocker_keys")
+        .with_header("ocp-client-version", env!("CARGO_PKG_VERSION"));
+    client.require_permissions(&["BitlockerKey.ReadBasic.All", "Device.Read.All"])?;
+
+    let phase = client.stats().phase("list devices");
+    let devices = list_windows_devices(&client).await?;
+    drop(phase);
+    let phase = client.stats().phase("list recovery keys");
+    let keys = list_recovery_keys(&client).await?;
+    drop(phase);
+
+    let assessed = assess(&devices, &keys, Utc::now() - Duration::days(config.inactive_days));
+    let listed: Vec<&DeviceCoverage> = assessed
+        .iter()
+        .filter(|device| config.all || device.issue.is_some())
+        .collect();
+    info!(
+        "{} of {} assessed Windows devices have no OS volume recovery key",
+        assessed.iter().filter(|device| device.issue.is_some()).count(),
+        assessed.len()
+    );
+    print!(
+        "{}",
+        render_report(&listed, &assessed, devices.len(), &config.output, config.inactive_days, &client.stats().summary())?
+    );
+    if config.output != "json" {
+        client.stats().summary().print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let devices = list_windows_devices(&client).await.unwrap();
+        let keys = list_recovery_keys(&client).await.unwrap();
+        let assessed = assess(&devices, &keys, "2026-07-17T00:00:00Z".parse().unwrap());
+        let listed: Vec<&DeviceCoverage> = assessed.iter().filter(|device| device.issue.is_some()).collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&listed, &assessed, devices.len(), output, 90, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_calendar_permissions")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports calendars the whole organization can edit or that are shared with external people, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, help = "Only audit users in this department")]
+    department: Option<String>,
+
+    #[arg(long, help = "List every calendar permission, not only the risky ones")]
+    all: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of mailboxes read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    department: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CalendarPermission {
+    #[serde(rename = "emailAddress")]
+    email_address: Option<EmailAddress>,
+    #[serde(rename = "isInsideOrganization", default)]
+    is_inside_organization: bool,
+    #[serde(rename = "isRemovable", default)]
+    is_removable: bool,
+    role: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct EmailAddress {
+    name: Option<String>,
+    address: Option<String>,
+}
+
+// One row per calendar permission.
+#[derive(Serialize, Debug)]
+struct PermissionRow {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    department: String,
+    #[serde(rename = "sharedWith")]
+    shared_with: String,
+    address: String,
+    role: String,
+    external: bool,
+    issue: Option<&'static str>,
+}
+
+struct Scan {
+    rows: Vec<PermissionRow>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn mailbox_users(client: &GraphClient, department: Option<&str>) -> Result<Vec<User>> {
+    let mut request = client.users();
+    if let Some(department) = department {
+        request = request.filter(&format!("department eq {}", encode::odata_string(department)));
+    }
+    let users: Vec<User> = request
+        .select("userPrincipalName,department,mail")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users.into_iter().filter(|user| user.mail.is_some()).collect())
+}
+
+// Editor and delegate roles can create and change events; the rest only read.
+fn can_edit(role: &str) -> bool {
+    matches!(role, "write" | "delegateWithoutPrivateEventAccess" | "delegateWithPrivateEventAccess")
+}
+
+// The built-in "My Organization" entry has no address and cannot be removed, only lowered
+// to free/busy.
+fn issue(permission: &CalendarPermission) -> Option<&'static str> {
+    let role = permission.role.as_deref().unwrap_or("none");
+    let address = permission.email_address.as_ref().and_then(|email| email.address.as_deref());
+    if role == "none" {
+        None
+    } else if !permission.is_removable && address.is_none() {
+        can_edit(role).then_some("organization can edit")
+    } else if !permission.is_inside_organization {
+        Some("shared externally")
+    } else {
+        None
+    }
+}
+
+async fn calendar_permissions(client: &GraphClient, user: &User) -> Result<Vec<PermissionRow>> {
+    let permissions: Vec<CalendarPermission> = client
+        .user(&user.user_principal_name)
+        .calendar_permissions()
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to read the calendar permissions of {}", user.user_principal_name))?;
+    Ok(permissions
+        .iter()
+        .map(|permission| {
+            let email = permission.email_address.as_ref();
+            PermissionRow {
+                user_principal_name: user.user_principal_name.clone(),
+                department: user.department.clone().unwrap_or_default(),
+                shared_with: email.and_then(|email| email.name.clone()).unwrap_or_default(),
+                address: email
+                    .and_then(|email| email.address.as_deref())
+                    .map(str::to_lowercase)
+                    .unwrap_or_default(),
+                role: permission.role.clone().unwrap_or_else(|| "none".to_string()),
+                external: !permission.is_inside_organization,
+                issue: issue(permission),
+            }
+        })
+        .collect())
+}
+
+async fn scan(client: &GraphClient, department: Option<&str>, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list users");
+    let users = mailbox_users(client, department).await?;
+    drop(phase);
+    info!("Checking the calendar permissions of {} mailboxes", users.len());
+
+    let _phase = client.stats().phase("read calendar permissions");
+    let results: Vec<(&User, Result<Vec<PermissionRow>>)> = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|user| async move { (user, calendar_permissions(client, user).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (_, result) in results {
+        match result {
+            Ok(permissions) => rows.extend(permissions),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    rows.sort_by_key(|row| (row.department.to_lowercase(), row.user_principal_name.to_lowercase()));
+    Ok(Scan {
+        rows,
+        checked,
+        failed,
+        interrupted: checked < users.len(),
+    })
+}
+
+fn render_report(rows: &[&PermissionRow], all_rows: &[PermissionRow], checked: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let count = |issue: &str| all_rows.iter().filter(|row| row.issue == Some(issue)).count();
+    let (organization_editable, external) = (count("organization can edit"), count("shared externally"));
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "permissions": rows,
+                "organizationCanEdit": organization_editable,
+                "sharedExternally": external,
+                "checkedMailboxes": checked,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "department,userPrincipalName,sharedWith,address,role,external,issue")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv::field(&row.department),
+                    csv::field(&row.user_principal_name),
+                    csv::field(&row.shared_with),
+                    csv::field(&row.address),
+                    row.role,
+                    row.external,
+                    row.issue.unwrap_or_default()
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<15} {:<30} {:<35} {:<34} ISSUE",
+                "DEPARTMENT", "USER", "SHARED WITH", "ROLE"
+            )?;
+            for row in rows {
+                let shared_with = if row.address.is_empty() { &row.shared_with } else { &row.address };
+                let line = format!(
+                    "{:<15} {:<30} {:<35} {:<34} {}",
+                    if row.department.is_empty() { "-" } else { &row.department },
+                    row.user_principal_name,
+                    shared_with,
+                    row.role,
+                    row.issue.unwrap_or_default()
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} calendars the whole organization can edit, {} permissions granted to external people, in {} mailboxes",
+                organization_editable, external, checked
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_calendar_permissions");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "Calendars.Read"])?;
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, config.department.as_deref(), config.concurrency as usize, &shutdown).await?;
+    let listed: Vec<&PermissionRow> = scan
+        .rows
+        .iter()
+        .filter(|row| config.all || row.issue.is_some())
+        .collect();
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&listed, &scan.rows, scan.checked, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} mailboxes; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "Calendar permissions could not be read for {} of {} mailboxes; they are missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} mailboxes could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let scan = scan(&client, None, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 1, false));
+        let listed: Vec<&PermissionRow> = scan.rows.iter().filter(|row| row.issue.is_some()).collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&listed, &scan.rows, scan.checked, output, &Summary::fixture()));
+    }
+}
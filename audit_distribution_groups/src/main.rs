@@ -0,0 +1,431 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::audit::Severity;
+use graph_common::csv::Table;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_distribution_groups")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports mail-enabled groups that accept messages from external senders, with their member counts and owners, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_name = "FILE", help = "Exchange Online export of the sender settings of each group (PrimarySmtpAddress, RequireSenderAuthenticationEnabled, AcceptMessagesOnlyFromSendersOrMembers)")]
+    senders: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 50, help = "Rate groups with at least this many members as high when they accept external mail")]
+    large_group: usize,
+
+    #[arg(long, help = "Include groups that only accept internal mail")]
+    all: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+    #[serde(rename = "groupTypes", default)]
+    group_types: Vec<String>,
+    #[serde(rename = "securityEnabled", default)]
+    security_enabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryObject {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "userType")]
+    user_type: Option<String>,
+}
+
+// The sender settings Exchange Online keeps per group, which Graph does not return for
+// distribution lists and mail-enabled security groups.
+#[derive(Debug)]
+struct SenderSettings {
+    require_sender_authentication: bool,
+    accept_only_from: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct GroupReport {
+    severity: Severity,
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    mail: String,
+    #[serde(rename = "groupType")]
+    group_type: &'static str,
+    // "allowed", "restricted" to the senders in acceptOnlyFrom, "internal", or "unknown" when
+    // the group is missing from the sender export.
+    #[serde(rename = "externalSenders")]
+    external_senders: &'static str,
+    #[serde(rename = "acceptOnlyFrom")]
+    accept_only_from: Vec<String>,
+    members: usize,
+    guests: usize,
+    owners: Vec<String>,
+}
+
+struct GroupDetails {
+    members: usize,
+    guests: usize,
+    owners: Vec<String>,
+}
+
+struct Scan {
+    groups: Vec<GroupReport>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Sender settings keyed by lowercase primary SMTP address, from Get-DistributionGroup and
+// Get-UnifiedGroup exported with Export-Csv.
+fn load_sender_settings(path: &Path) -> Result<HashMap<String, SenderSettings>> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_sender_settings(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn parse_sender_settings(content: &str) -> Result<HashMap<String, SenderSettings>> {
+    let table = Table::parse(content.trim_start_matches('\u{feff}'))?;
+    let address = table
+        .column(&["PrimarySmtpAddress", "mail"])
+        .context("The sender export has no PrimarySmtpAddress column")?;
+    let authentication = table
+        .column(&["RequireSenderAuthenticationEnabled"])
+        .context("The sender export has no RequireSenderAuthenticationEnabled column")?;
+    let accept = table.column(&["AcceptMessagesOnlyFromSendersOrMembers"]);
+    let mut settings = HashMap::new();
+    for record in &table.records {
+        let value = record.get(authentication);
+        let require_sender_authentication = match value.to_lowercase().as_str() {
+            "true" => true,
+            "false" => false,
+            _ => return Err(anyhow::anyhow!("Line {}: {:?} is not True or False", record.line, value)),
+        };
+        let accept_only_from = accept
+            .map(|column| {
+                record
+                    .get(column)
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|sender| !sender.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        settings.insert(
+            record.get(address).to_lowercase(),
+            SenderSettings {
+                require_sender_authentication,
+                accept_only_from,
+            },
+        );
+    }
+    Ok(settings)
+}
+
+async fn list_groups(client: &GraphClient) -> Result<Vec<Group>> {
+    client
+        .path(&["groups"])
+        .filter("mailEnabled eq true")
+        .select("id,displayName,mail,groupTypes,securityEnabled")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch mail-enabled groups")
+}
+
+// The users a message to the group reaches, including members of nested groups, and the owners.
+async fn read_group(client: &GraphClient, group: &Group) -> Result<GroupDetails> {
+    let members: Vec<DirectoryObject> = client
+        .path(&["groups", &group.id, "transitiveMembers", "microsoft.graph.user"])
+        .select("id,userType")
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the members of {}", group.id))?;
+    let owners: Vec<DirectoryObject> = client
+        .path(&["groups", &group.id, "owners"])
+        .select("displayName,userPrincipalName")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the owners of {}", group.id))?;
+    Ok(GroupDetails {
+        guests: members
+            .iter()
+            .filter(|member| member.user_type.as_deref() == Some("Guest"))
+            .count(),
+        members: members.len(),
+        owners: owners
+            .into_iter()
+            .filter_map(|owner| owner.user_principal_name.or(owner.display_name))
+            .collect(),
+    })
+}
+
+fn group_type(group: &Group) -> &'static str {
+    if group.group_types.iter().any(|group_type| group_type == "Unified") {
+        "Microsoft 365"
+    } else if group.security_enabled {
+        "mail-enabled security"
+    } else {
+        "distribution"
+    }
+}
+
+// A group open to external senders is high when a message reaches many people or nobody owns
+// the group to notice abuse.
+fn severity(external_senders: &str, members: usize, has_owners: bool, large_group: usize) -> Severity {
+    match external_senders {
+        "allowed" if members >= large_group || !has_owners => Severity::High,
+        "allowed" => Severity::Medium,
+        "restricted" | "unknown" => Severity::Low,
+        _ => Severity::Info,
+    }
+}
+
+async fn scan(
+    client: &GraphClient,
+    senders: Option<&HashMap<String, SenderSettings>>,
+    large_group: usize,
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("list groups");
+    let groups = list_groups(client).await?;
+    drop(phase);
+    info!("Reading the members and owners of {} mail-enabled groups", groups.len());
+
+    let _phase = client.stats().phase("read groups");
+    let results: Vec<(&Group, Result<GroupDetails>)> = stream::iter(&groups)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|group| async move { (group, read_group(client, group).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut reports = Vec::new();
+    let mut failed = 0;
+    for (group, result) in results {
+        let details = match result {
+            Ok(details) => details,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let mail = group.mail.clone().unwrap_or_default();
+        let settings = senders.and_then(|senders| senders.get(&mail.to_lowercase()));
+        let external_senders = match settings {
+            None => "unknown",
+            Some(settings) if settings.require_sender_authentication => "internal",
+            Some(settings) if !settings.accept_only_from.is_empty() => "restricted",
+            Some(_) => "allowed",
+        };
+        reports.push(GroupReport {
+            severity: severity(external_senders, details.members, !details.owners.is_empty(), large_group),
+            id: group.id.clone(),
+            display_name: group.display_name.clone().unwrap_or_default(),
+            mail,
+            group_type: group_type(group),
+            external_senders,
+            accept_only_from: settings.map(|settings| settings.accept_only_from.clone()).unwrap_or_default(),
+            members: details.members,
+            guests: details.guests,
+            owners: details.owners,
+        });
+    }
+    reports.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| b.members.cmp(&a.members))
+            .then_with(|| a.mail.cmp(&b.mail))
+    });
+    Ok(Scan {
+        groups: reports,
+        checked,
+        failed,
+        interrupted: checked < groups.len(),
+    })
+}
+
+fn render_report(groups: &[GroupReport], total: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "groups": groups,
+                "totalGroups": total,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "severity,mail,displayName,groupType,externalSenders,acceptOnlyFrom,members,guests,owners")?;
+            for group in groups {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    group.severity,
+                    csv::field(&group.mail),
+                    csv::field(&group.display_name),
+                    group.group_type,
+                    group.external_senders,
+                    csv::field(&group.accept_only_from.join("; ")),
+                    group.members,
+                    group.guests,
+                    csv::field(&group.owners.join("; "))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<9} {:<35} {:<22} {:<10} {:>7} {:>6}  OWNERS",
+                "SEVERITY", "GROUP", "TYPE", "EXTERNAL", "MEMBERS", "GUESTS"
+            )?;
+            for group in groups {
+                writeln!(
+                    out,
+                    "{:<9} {:<35} {:<22} {:<10} {:>7} {:>6}  {}",
+                    group.severity.to_string(),
+                    group.mail,
+                    group.group_type,
+                    group.external_senders,
+                    group.members,
+                    group.guests,
+                    if group.owners.is_empty() { "none".to_string() } else { group.owners.join(", ") }
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} mail-enabled groups accept external mail ({} without an owner), {} only from listed senders, {} not in the sender export",
+                groups.iter().filter(|group| group.external_senders == "allowed").count(),
+                total,
+                groups
+                    .iter()
+                    .filter(|group| group.external_senders == "allowed" && group.owners.is_empty())
+                    .count(),
+                groups.iter().filter(|group| group.external_senders == "restricted").count(),
+                groups.iter().filter(|group| group.external_senders == "unknown").count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_distribution_groups");
+    debug!("Configuration: {:?}", config);
+
+    let senders = config.senders.as_deref().map(load_sender_settings).transpose()?;
+    if senders.is_none() {
+        warn!("No --senders export given; Graph does not return the sender settings of distribution lists, so every group is reported as unknown");
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Group.Read.All", "User.Read.All"])?;
+
+    let shutdown = service::shutdown_signal();
+    let mut scan = scan(&client, senders.as_ref(), config.large_group, config.concurrency as usize, &shutdown).await?;
+    let total = scan.groups.len();
+    if !config.all {
+        scan.groups.retain(|group| group.severity > Severity::Info);
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.groups, total, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} groups; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "The members or owners of {} of {} groups could not be read; they are missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} groups could not be read", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let senders = load_sender_settings(&golden::testdata("senders.csv")).unwrap();
+        let mut scan = scan(&client, Some(&senders), 5, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (6, 0, false));
+        let total = scan.groups.len();
+        scan.groups.retain(|group| group.severity > Severity::Info);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.groups, total, output, &Summary::fixture()));
+    }
+
+    #[test]
+    fn parses_exchange_sender_exports() {
+        let settings = parse_sender_settings(
+            "\u{feff}\"PrimarySmtpAddress\",\"RequireSenderAuthenticationEnabled\",\"AcceptMessagesOnlyFromSendersOrMembers\"\n\"Sales@contoso.com\",\"False\",\"\"\n\"board@contoso.com\",\"False\",\"chair;partner-contact\"\n",
+        )
+        .unwrap();
+        assert!(!settings["sales@contoso.com"].require_sender_authentication);
+        assert_eq!(settings["board@contoso.com"].accept_only_from, ["chair", "partner-contact"]);
+        assert!(parse_sender_settings("PrimarySmtpAddress,RequireSenderAuthenticationEnabled\nit@contoso.com,maybe\n").is_err());
+    }
+}
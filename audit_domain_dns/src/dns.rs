@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Context, Result};
+use graph_common::encode;
+#[cfg(test)]
+use graph_common::fixtures::Fixtures;
+use log::debug;
+use serde::Deserialize;
+
+pub const DEFAULT_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+
+#[derive(Debug, Clone, Copy)]
+pub enum RecordType {
+    Mx,
+    Txt,
+}
+
+impl RecordType {
+    fn name(self) -> &'static str {
+        match self {
+            RecordType::Mx => "MX",
+            RecordType::Txt => "TXT",
+        }
+    }
+
+    fn code(self) -> u16 {
+        match self {
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<Answer>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Answer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+// Looks up records with the JSON DNS-over-HTTPS API that Cloudflare and Google serve, so the
+// lookups need nothing beyond HTTPS. A resolver with fixtures answers from them instead, keyed
+// by the query string, for example "?name=_dmarc.contoso.com&type=TXT".
+pub struct Resolver {
+    client: reqwest::Client,
+    url: String,
+    #[cfg(test)]
+    fixtures: Option<Fixtures>,
+}
+
+impl Resolver {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('?').to_string(),
+            #[cfg(test)]
+            fixtures: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(fixtures);
+        self
+    }
+
+    // The records of the given type, with TXT strings joined and unquoted. A name that does
+    // not exist has no records.
+    pub async fn lookup(&self, name: &str, record_type: RecordType) -> Result<Vec<String>> {
+        let query = format!("?name={}&type={}", encode::query_value(name), record_type.name());
+        let body = self.fetch(&query, name, record_type).await?;
+        let response: Response = serde_json::from_str(&body)
+            .with_context(|| format!("Failed to parse the {} records of {}", record_type.name(), name))?;
+        // 0 is NOERROR and 3 is NXDOMAIN; anything else means the answer cannot be trusted.
+        match response.status {
+            0 | 3 => {}
+            status => return Err(anyhow!("Lookup of {} {} failed with DNS status {}", record_type.name(), name, status)),
+        }
+        Ok(response
+            .answer
+            .into_iter()
+            .filter(|answer| answer.record_type == record_type.code())
+            .map(|answer| match record_type {
+                RecordType::Txt => unquote(&answer.data),
+                RecordType::Mx => answer.data,
+            })
+            .collect())
+    }
+
+    async fn fetch(&self, query: &str, name: &str, record_type: RecordType) -> Result<String> {
+        #[cfg(test)]
+        if let Some(fixtures) = &self.fixtures {
+            return fixtures.response(query);
+        }
+        debug!("DNS {} {}", record_type.name(), name);
+        self.client
+            .get(format!("{}{}", self.url, query))
+            .header("accept", "application/dns-json")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to look up {} {}", record_type.name(), name))?
+            .text()
+            .await
+            .with_context(|| format!("Failed to read the {} records of {}", record_type.name(), name))
+    }
+}
+
+// TXT data comes as one or more quoted strings, "v=spf1 include:a" " -all", which receivers
+// join without a separator.
+fn unquote(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_string();
+    }
+    let mut text = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => text.extend(chars.next()),
+            c if quoted => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_quoted_txt_strings() {
+        assert_eq!(unquote(r#""v=spf1 include:spf.protection.outlook.com" " -all""#), "v=spf1 include:spf.protection.outlook.com -all");
+        assert_eq!(unquote(r#""say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(unquote("v=DMARC1; p=none"), "v=DMARC1; p=none");
+    }
+}
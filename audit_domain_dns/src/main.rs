@@ -0,0 +1,450 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::{self, Write};
+
+mod dns;
+
+use dns::{RecordType, Resolver};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_domain_dns")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Checks the MX, SPF, DKIM, and DMARC records of every verified domain of the tenant, using the Microsoft API and DNS")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_delimiter = ',', help = "DKIM selectors to check in addition to selector1 and selector2 (repeatable or comma separated)")]
+    dkim_selector: Vec<String>,
+
+    #[arg(long, default_value = dns::DEFAULT_RESOLVER, help = "DNS-over-HTTPS resolver that answers JSON queries")]
+    resolver: String,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of domains checked at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    id: String,
+    #[serde(rename = "isVerified", default)]
+    is_verified: bool,
+    #[serde(rename = "isDefault", default)]
+    is_default: bool,
+    #[serde(rename = "supportedServices", default)]
+    supported_services: Vec<String>,
+}
+
+// Ordered so the status of a domain is the worst of its checks.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Check {
+    status: Status,
+    note: String,
+    records: Vec<String>,
+}
+
+impl Check {
+    fn new(status: Status, note: impl Into<String>, records: Vec<String>) -> Self {
+        Self {
+            status,
+            note: note.into(),
+            records,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DomainReport {
+    domain: String,
+    #[serde(rename = "isDefault")]
+    is_default: bool,
+    // Whether the domain is set up for Exchange Online in the tenant.
+    email: bool,
+    status: Status,
+    mx: Check,
+    spf: Check,
+    dkim: Check,
+    dmarc: Check,
+}
+
+impl DomainReport {
+    fn checks(&self) -> [(&'static str, &Check); 4] {
+        [("MX", &self.mx), ("SPF", &self.spf), ("DKIM", &self.dkim), ("DMARC", &self.dmarc)]
+    }
+}
+
+struct Scan {
+    domains: Vec<DomainReport>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+const EXCHANGE_ONLINE_MX: &str = ".mail.protection.outlook.com";
+const EXCHANGE_ONLINE_SPF: &str = "include:spf.protection.outlook.com";
+const DEFAULT_SELECTORS: &[&str] = &["selector1", "selector2"];
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn check_mx(records: Vec<String>, email: bool) -> Check {
+    let hosts: Vec<String> = records
+        .iter()
+        .map(|record| record.split_whitespace().last().unwrap_or_default().trim_end_matches('.').to_lowercase())
+        .collect();
+    if hosts.is_empty() || hosts.iter().all(|host| host.is_empty()) {
+        return if email {
+            Check::new(Status::Fail, "no MX record", records)
+        } else {
+            Check::new(Status::Pass, "no MX record; not used for mail", records)
+        };
+    }
+    if email && !hosts.iter().any(|host| host.ends_with(EXCHANGE_ONLINE_MX)) {
+        return Check::new(Status::Warn, format!("mail goes to {}, not Exchange Online", hosts.join(", ")), records);
+    }
+    Check::new(Status::Pass, hosts.join(", "), records)
+}
+
+// Only the record itself is checked; include: and redirect= are not followed, so the
+// 10 lookup limit is not verified.
+fn check_spf(txt: &[String], exchange_online: bool) -> Check {
+    let records: Vec<String> = txt
+        .iter()
+        .filter(|record| record.to_lowercase() == "v=spf1" || record.to_lowercase().starts_with("v=spf1 "))
+        .cloned()
+        .collect();
+    match records.len() {
+        0 => return Check::new(Status::Fail, "no SPF record", records),
+        1 => {}
+        count => return Check::new(Status::Fail, format!("{} SPF records; receivers treat this as an error", count), records),
+    }
+    let terms: Vec<String> = records[0].split_whitespace().skip(1).map(str::to_lowercase).collect();
+    let all = terms.iter().find(|term| term.trim_start_matches(['+', '-', '~', '?']) == "all");
+    let (status, mut note) = match all.map(String::as_str) {
+        Some("-all") => (Status::Pass, "-all".to_string()),
+        Some("~all") => (Status::Pass, "~all (soft fail)".to_string()),
+        Some(all) => (Status::Fail, format!("{} lets any server send as the domain", all)),
+        None if terms.iter().any(|term| term.starts_with("redirect=")) => (Status::Pass, "redirect".to_string()),
+        None => (Status::Fail, "no all mechanism".to_string()),
+    };
+    if status == Status::Pass && exchange_online && !terms.iter().any(|term| term == EXCHANGE_ONLINE_SPF) {
+        note = format!("{}; does not {}", note, EXCHANGE_ONLINE_SPF);
+        return Check::new(Status::Warn, note, records);
+    }
+    Check::new(status, note, records)
+}
+
+// An empty p= means the key was revoked.
+fn check_dkim(selectors: &[(String, Vec<String>)], email: bool) -> Check {
+    let mut records = Vec::new();
+    let mut published = Vec::new();
+    for (selector, txt) in selectors {
+        for record in txt {
+            let key = record
+                .split(';')
+                .filter_map(|tag| tag.trim().split_once('='))
+                .find(|(name, _)| name.trim() == "p")
+                .map(|(_, value)| value.trim());
+            if key.is_some_and(|key| !key.is_empty()) {
+                published.push(selector.clone());
+            }
+            records.push(format!("{}: {}", selector, record));
+        }
+    }
+    published.dedup();
+    if !published.is_empty() {
+        return Check::new(Status::Pass, published.join(", "), records);
+    }
+    let names: Vec<&str> = selectors.iter().map(|(selector, _)| selector.as_str()).collect();
+    if email {
+        Check::new(Status::Fail, format!("no DKIM key at {}", names.join(", ")), records)
+    } else {
+        Check::new(Status::Pass, "no DKIM key; not used for mail", records)
+    }
+}
+
+fn check_dmarc(txt: &[String]) -> Check {
+    let records: Vec<String> = txt
+        .iter()
+        .filter(|record| record.to_lowercase().starts_with("v=dmarc1"))
+        .cloned()
+        .collect();
+    match records.len() {
+        0 => return Check::new(Status::Fail, "no DMARC record", records),
+        1 => {}
+        count => return Check::new(Status::Fail, format!("{} DMARC records; receivers ignore all of them", count), records),
+    }
+    let tag = |name: &str| {
+        records[0]
+            .split(';')
+            .filter_map(|tag| tag.trim().split_once('='))
+            .find(|(tag, _)| tag.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_lowercase())
+    };
+    let policy = tag("p").unwrap_or_default();
+    let pct: u8 = tag("pct").and_then(|pct| pct.parse().ok()).unwrap_or(100);
+    let (status, mut note) = match policy.as_str() {
+        "reject" | "quarantine" if pct < 100 => (Status::Warn, format!("p={} for {}% of messages", policy, pct)),
+        "reject" | "quarantine" => (Status::Pass, format!("p={}", policy)),
+        "none" => (Status::Warn, "p=none only monitors".to_string()),
+        "" => (Status::Fail, "no p= policy".to_string()),
+        policy => (Status::Fail, format!("unknown policy p={}", policy)),
+    };
+    if tag("rua").is_none() {
+        note.push_str("; no aggregate reports (rua)");
+    }
+    Check::new(status, note, records)
+}
+
+async fn list_domains(client: &GraphClient) -> Result<Vec<Domain>> {
+    let mut domains: Vec<Domain> = client
+        .path(&["domains"])
+        .select("id,isVerified,isDefault,supportedServices")
+        .get_all()
+        .await
+        .context("Failed to fetch domains")?;
+    // Microsoft runs the DNS of onmicrosoft.com, so its records cannot be changed.
+    domains.retain(|domain| domain.is_verified && !domain.id.to_lowercase().ends_with(".onmicrosoft.com"));
+    domains.sort_by_key(|domain| domain.id.to_lowercase());
+    Ok(domains)
+}
+
+async fn check_domain(resolver: &Resolver, domain: &Domain, selectors: &[String]) -> Result<DomainReport> {
+    let name = domain.id.to_lowercase();
+    let email = domain.supported_services.iter().any(|service| service == "Email");
+    let mx = resolver.lookup(&name, RecordType::Mx).await?;
+    let txt = resolver.lookup(&name, RecordType::Txt).await?;
+    let dmarc = resolver.lookup(&format!("_dmarc.{}", name), RecordType::Txt).await?;
+    let mut dkim = Vec::new();
+    for selector in selectors {
+        let records = resolver.lookup(&format!("{}._domainkey.{}", selector, name), RecordType::Txt).await?;
+        dkim.push((selector.clone(), records));
+    }
+
+    let mx = check_mx(mx, email);
+    let exchange_online = email && mx.status == Status::Pass && !mx.records.is_empty();
+    let mut report = DomainReport {
+        domain: name,
+        is_default: domain.is_default,
+        email,
+        status: Status::Pass,
+        spf: check_spf(&txt, exchange_online),
+        dkim: check_dkim(&dkim, email),
+        dmarc: check_dmarc(&dmarc),
+        mx,
+    };
+    report.status = report.checks().iter().map(|(_, check)| check.status).max().unwrap_or(Status::Pass);
+    Ok(report)
+}
+
+async fn scan(
+    client: &GraphClient,
+    resolver: &Resolver,
+    selectors: &[String],
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("list domains");
+    let domains = list_domains(client).await?;
+    drop(phase);
+    info!("Checking the DNS records of {} verified domains", domains.len());
+
+    let _phase = client.stats().phase("check DNS");
+    let results: Vec<Result<DomainReport>> = stream::iter(&domains)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|domain| check_domain(resolver, domain, selectors))
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut reports = Vec::new();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(Scan {
+        domains: reports,
+        checked,
+        failed,
+        interrupted: checked < domains.len(),
+    })
+}
+
+fn render_report(domains: &[DomainReport], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "domains": domains,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "domain,isDefault,email,status,mx,mxNote,spf,spfNote,dkim,dkimNote,dmarc,dmarcNote")?;
+            for domain in domains {
+                write!(out, "{},{},{},{}", domain.domain, domain.is_default, domain.email, domain.status)?;
+                for (_, check) in domain.checks() {
+                    write!(out, ",{},{}", check.status, csv::field(&check.note))?;
+                }
+                writeln!(out)?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<30} {:<6} {:<6} {:<6} {:<6} DMARC", "DOMAIN", "STATUS", "MX", "SPF", "DKIM")?;
+            for domain in domains {
+                writeln!(
+                    out,
+                    "{:<30} {:<6} {:<6} {:<6} {:<6} {}",
+                    domain.domain,
+                    domain.status.to_string(),
+                    domain.mx.status.to_string(),
+                    domain.spf.status.to_string(),
+                    domain.dkim.status.to_string(),
+                    domain.dmarc.status
+                )?;
+            }
+            for domain in domains.iter().filter(|domain| domain.status > Status::Pass) {
+                writeln!(out)?;
+                writeln!(out, "{}", domain.domain)?;
+                for (name, check) in domain.checks() {
+                    if check.status > Status::Pass {
+                        writeln!(out, "    {:<6} {:<5} {}", name, check.status.to_string(), check.note)?;
+                    }
+                }
+            }
+            writeln!(out)?;
+            let count = |status: Status| domains.iter().filter(|domain| domain.status == status).count();
+            writeln!(
+                out,
+                "{} domains: {} pass, {} with warnings, {} failing",
+                domains.len(),
+                count(Status::Pass),
+                count(Status::Warn),
+                count(Status::Fail)
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_domain_dns");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Domain.Read.All"])?;
+
+    let mut selectors: Vec<String> = DEFAULT_SELECTORS.iter().map(|selector| selector.to_string()).collect();
+    for selector in &config.dkim_selector {
+        if !selectors.iter().any(|known| known.eq_ignore_ascii_case(selector)) {
+            selectors.push(selector.clone());
+        }
+    }
+    let resolver = Resolver::new(&config.resolver);
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, &resolver, &selectors, config.concurrency as usize, &shutdown).await?;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.domains, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} domains; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!("The DNS records of {} of {} domains could not be looked up; they are missing from the report", scan.failed, scan.checked);
+        return Err(anyhow::anyhow!("{} of {} domains could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::fixtures::Fixtures;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let resolver = Resolver::new(dns::DEFAULT_RESOLVER).with_fixtures(Fixtures::load(&golden::testdata("dns.json")).unwrap());
+        let selectors = vec!["selector1".to_string(), "selector2".to_string()];
+        let scan = scan(&client, &resolver, &selectors, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 0, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.domains, output, &Summary::fixture()));
+    }
+
+    #[test]
+    fn rates_spf_and_dmarc_policies() {
+        let txt = |records: &[&str]| records.iter().map(|record| record.to_string()).collect::<Vec<_>>();
+        assert_eq!(check_spf(&txt(&["v=spf1 include:spf.protection.outlook.com -all"]), true).status, Status::Pass);
+        assert_eq!(check_spf(&txt(&["v=spf1 include:_spf.google.com ~all"]), true).status, Status::Warn);
+        assert_eq!(check_spf(&txt(&["v=spf1 +all"]), false).status, Status::Fail);
+        assert_eq!(check_spf(&txt(&["v=spf1 -all", "v=spf1 mx -all"]), false).status, Status::Fail);
+        assert_eq!(check_spf(&txt(&["google-site-verification=abc"]), false).status, Status::Fail);
+
+        assert_eq!(check_dmarc(&txt(&["v=DMARC1; p=reject; rua=mailto:d@contoso.com"])).note, "p=reject");
+        assert_eq!(check_dmarc(&txt(&["v=DMARC1; p=quarantine; pct=25"])).status, Status::Warn);
+        assert_eq!(check_dmarc(&txt(&["v=DMARC1; p=none"])).status, Status::Warn);
+        assert_eq!(check_dmarc(&txt(&["v=DMARC1; rua=mailto:d@contoso.com"])).status, Status::Fail);
+    }
+}
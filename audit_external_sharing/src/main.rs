@@ -0,0 +1,484 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_external_sharing")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports the SharePoint and OneDrive external sharing settings and the SharePoint sites holding sensitivity-labelled content, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "Only scan these site URLs (repeatable or comma separated)")]
+    site: Vec<String>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "List only flagged sites")]
+    flagged: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct TenantSharing {
+    #[serde(rename = "sharingCapability")]
+    sharing_capability: Option<String>,
+    #[serde(rename = "sharingDomainRestrictionMode")]
+    sharing_domain_restriction_mode: Option<String>,
+    #[serde(rename = "sharingAllowedDomainList", default)]
+    sharing_allowed_domain_list: Vec<String>,
+    #[serde(rename = "sharingBlockedDomainList", default)]
+    sharing_blocked_domain_list: Vec<String>,
+    #[serde(rename = "isResharingByExternalUsersEnabled")]
+    is_resharing_by_external_users_enabled: Option<bool>,
+    #[serde(rename = "isRequireAcceptingUserToMatchInvitedUserEnabled")]
+    is_require_accepting_user_to_match_invited_user_enabled: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphSite {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "assignedLabels", default)]
+    assigned_labels: Vec<AssignedLabel>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AssignedLabel {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Drive {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ListItem {
+    fields: Option<LabelFields>,
+}
+
+// The Sensitivity column of a document library: the label id and the label name.
+#[derive(Deserialize, Debug)]
+struct LabelFields {
+    #[serde(rename = "_IpLabelId")]
+    label_id: Option<String>,
+    #[serde(rename = "_DisplayName")]
+    label_name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SiteRow {
+    #[serde(rename = "displayName")]
+    display_name: String,
+    url: String,
+    #[serde(rename = "siteId")]
+    site_id: String,
+    #[serde(rename = "siteLabel")]
+    site_label: Option<String>,
+    items: usize,
+    #[serde(rename = "labelledItems")]
+    labelled_items: usize,
+    #[serde(rename = "itemLabels")]
+    item_labels: Vec<String>,
+    // Labelled content without a site label, while the tenant allows external sharing.
+    flagged: bool,
+}
+
+struct Scan {
+    sites: Vec<SiteRow>,
+    total: usize,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn tenant_sharing(client: &GraphClient) -> Result<TenantSharing> {
+    client
+        .path(&["admin", "sharepoint", "settings"])
+        .select("sharingCapability,sharingDomainRestrictionMode,sharingAllowedDomainList,sharingBlockedDomainList,isResharingByExternalUsersEnabled,isRequireAcceptingUserToMatchInvitedUserEnabled")
+        .get_json()
+        .await
+        .context("Failed to read the SharePoint tenant settings")
+}
+
+fn external_sharing_enabled(tenant: &TenantSharing) -> bool {
+    tenant.sharing_capability.as_deref() != Some("disabled")
+}
+
+fn tenant_findings(tenant: &TenantSharing) -> Vec<String> {
+    let mut findings = Vec::new();
+    if !external_sharing_enabled(tenant) {
+        return findings;
+    }
+    if tenant.sharing_capability.as_deref() == Some("externalUserAndGuestSharing") {
+        findings.push("Anyone links are allowed: files can be shared with people who do not sign in".to_string());
+    }
+    if tenant.sharing_domain_restriction_mode.as_deref().is_none_or(|mode| mode == "none") {
+        findings.push("External sharing is not limited to allowed domains".to_string());
+    }
+    if tenant.is_resharing_by_external_users_enabled == Some(true) {
+        findings.push("Guests can share items they do not own".to_string());
+    }
+    if tenant.is_require_accepting_user_to_match_invited_user_enabled == Some(false) {
+        findings.push("Guests can accept an invitation with a different account than the one invited".to_string());
+    }
+    findings
+}
+
+// OneDrive sites are left out: they follow the tenant setting and are audited per item by
+// audit_sharing_links.
+async fn list_sites(client: &GraphClient, only: &[String]) -> Result<Vec<GraphSite>> {
+    let _phase = client.stats().phase("list sites");
+    let sites: Vec<GraphSite> = client
+        .path(&["sites", "getAllSites"])
+        .select("id,displayName,webUrl")
+        .get_all()
+        .await
+        .context("Failed to list SharePoint sites")?;
+    let only: Vec<String> = only
+        .iter()
+        .map(|url| url.trim_end_matches('/').to_lowercase())
+        .collect();
+    let mut sites: Vec<GraphSite> = sites
+        .into_iter()
+        .filter(|site| {
+            let url = site.web_url.as_deref().unwrap_or_default().trim_end_matches('/').to_lowercase();
+            !url.contains("/personal/") && (only.is_empty() || only.contains(&url))
+        })
+        .collect();
+    for url in &only {
+        if !sites
+            .iter()
+            .any(|site| site.web_url.as_deref().unwrap_or_default().trim_end_matches('/').to_lowercase() == *url)
+        {
+            warn!("No SharePoint site found at {}", url);
+        }
+    }
+    sites.sort_by_key(|site| site.web_url.clone().unwrap_or_default().to_lowercase());
+    Ok(sites)
+}
+
+// A site label is the sensitivity label of the Microsoft 365 group behind the site; only
+// labelled groups are looked up.
+async fn site_labels(client: &GraphClient) -> Result<HashMap<String, String>> {
+    let _phase = client.stats().phase("read group labels");
+    let groups: Vec<Group> = client
+        .path(&["groups"])
+        .filter("groupTypes/any(c:c eq 'Unified')")
+        .select("id,assignedLabels")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch Microsoft 365 groups")?;
+    let labelled: Vec<(Group, String)> = groups
+        .into_iter()
+        .filter_map(|group| {
+            let label = group
+                .assigned_labels
+                .iter()
+                .filter_map(|label| label.display_name.clone())
+                .collect::<Vec<String>>()
+                .join(", ");
+            (!label.is_empty()).then_some((group, label))
+        })
+        .collect();
+    let urls: Vec<String> = labelled
+        .iter()
+        .map(|(group, _)| client.path(&["groups", &group.id, "sites", "root"]).select("id").relative_url())
+        .collect();
+    let sites: Vec<Result<GraphSite>> = client.batch_get(&urls).await?;
+    let mut labels = HashMap::new();
+    for ((group, label), site) in labelled.into_iter().zip(sites) {
+        match site {
+            Ok(site) => {
+                labels.insert(site.id.to_lowercase(), label);
+            }
+            Err(e) if e.to_string().starts_with("HTTP error 404") => debug!("Group {} has no site", group.id),
+            Err(e) => warn!("Failed to find the site of group {}: {:#}", group.id, e),
+        }
+    }
+    Ok(labels)
+}
+
+async fn read_site(client: &GraphClient, site: &GraphSite, site_label: Option<&String>, sharing_enabled: bool) -> Result<SiteRow> {
+    let name = site.display_name.clone().unwrap_or_default();
+    let drives: Vec<Drive> = client
+        .path(&["sites", &site.id, "drives"])
+        .select("id")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the libraries of {}", name))?;
+    let (mut items, mut labelled_items) = (0, 0);
+    let mut item_labels = BTreeSet::new();
+    for drive in &drives {
+        let list: Vec<ListItem> = client
+            .path(&["drives", &drive.id, "list", "items"])
+            .select("id")
+            .query("$expand", "fields($select=_IpLabelId,_DisplayName)")
+            .top(999)
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to read a library of {}", name))?;
+        items += list.len();
+        for fields in list.iter().filter_map(|item| item.fields.as_ref()) {
+            if fields.label_id.as_deref().is_some_and(|id| !id.is_empty()) {
+                labelled_items += 1;
+                item_labels.insert(fields.label_name.clone().unwrap_or_else(|| fields.label_id.clone().unwrap_or_default()));
+            }
+        }
+    }
+    debug!("{} of {} items in {} are labelled", labelled_items, items, name);
+    Ok(SiteRow {
+        display_name: name,
+        url: site.web_url.clone().unwrap_or_default(),
+        site_id: site.id.clone(),
+        site_label: site_label.cloned(),
+        items,
+        labelled_items,
+        item_labels: item_labels.into_iter().collect(),
+        flagged: labelled_items > 0 && site_label.is_none() && sharing_enabled,
+    })
+}
+
+async fn scan(
+    client: &GraphClient,
+    sites: &[GraphSite],
+    labels: &HashMap<String, String>,
+    sharing_enabled: bool,
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Scan {
+    let _phase = client.stats().phase("read libraries");
+    let results: Vec<Result<SiteRow>> = stream::iter(sites)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|site| read_site(client, site, labels.get(&site.id.to_lowercase()), sharing_enabled))
+        .buffered(concurrency)
+        .collect()
+        .await;
+    let checked = results.len();
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(row) => rows.push(row),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    rows.sort_by(|a, b| {
+        b.flagged
+            .cmp(&a.flagged)
+            .then_with(|| b.labelled_items.cmp(&a.labelled_items))
+            .then_with(|| a.url.to_lowercase().cmp(&b.url.to_lowercase()))
+    });
+    Scan {
+        sites: rows,
+        total: sites.len(),
+        checked,
+        failed,
+        interrupted: checked < sites.len(),
+    }
+}
+
+fn yes_no(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    }
+}
+
+fn render_report(scan: &Scan, tenant: &TenantSharing, flagged_only: bool, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let findings = tenant_findings(tenant);
+    let rows: Vec<&SiteRow> = scan.sites.iter().filter(|row| !flagged_only || row.flagged).collect();
+    let labelled = scan.sites.iter().filter(|row| row.labelled_items > 0).count();
+    let flagged = scan.sites.iter().filter(|row| row.flagged).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "tenant": tenant,
+                "findings": findings,
+                "sites": rows,
+                "totalSites": scan.total,
+                "sitesRead": scan.checked - scan.failed,
+                "sitesWithLabelledItems": labelled,
+                "flaggedSites": flagged,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "displayName,url,siteId,siteLabel,items,labelledItems,itemLabels,flagged")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv::field(&row.display_name),
+                    csv::field(&row.url),
+                    csv::field(&row.site_id),
+                    csv::field(row.site_label.as_deref().unwrap_or_default()),
+                    row.items,
+                    row.labelled_items,
+                    csv::field(&row.item_labels.join(";")),
+                    row.flagged
+                )?;
+            }
+        }
+        _ => {
+            let domains = |list: &[String]| if list.is_empty() { "-".to_string() } else { list.join(", ") };
+            writeln!(out, "{:<28} {}", "Tenant sharing level:", tenant.sharing_capability.as_deref().unwrap_or("-"))?;
+            writeln!(out, "{:<28} {}", "Domain restriction:", tenant.sharing_domain_restriction_mode.as_deref().unwrap_or("-"))?;
+            writeln!(out, "{:<28} {}", "Allowed domains:", domains(&tenant.sharing_allowed_domain_list))?;
+            writeln!(out, "{:<28} {}", "Blocked domains:", domains(&tenant.sharing_blocked_domain_list))?;
+            writeln!(out, "{:<28} {}", "Guests can reshare:", yes_no(tenant.is_resharing_by_external_users_enabled))?;
+            writeln!(
+                out,
+                "{:<28} {}",
+                "Invited account must match:",
+                yes_no(tenant.is_require_accepting_user_to_match_invited_user_enabled)
+            )?;
+            for finding in &findings {
+                writeln!(out, "FINDING  {}", finding)?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{:<28} {:>7} {:>8} {:<16} {:<10} ITEM LABELS",
+                "SITE", "ITEMS", "LABELLED", "SITE LABEL", "NOTE"
+            )?;
+            for row in rows {
+                let line = format!(
+                    "{:<28} {:>7} {:>8} {:<16} {:<10} {}",
+                    if row.display_name.is_empty() { &row.url } else { &row.display_name },
+                    row.items,
+                    row.labelled_items,
+                    row.site_label.as_deref().unwrap_or("-"),
+                    if row.flagged { "no label" } else { "" },
+                    row.item_labels.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} sites read: {} with labelled items, {} of them without a site label while external sharing is {}",
+                scan.checked - scan.failed,
+                scan.total,
+                labelled,
+                flagged,
+                if external_sharing_enabled(tenant) { "on" } else { "off" }
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_external_sharing");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&[
+        "SharePointTenantSettings.Read.All",
+        "Sites.Read.All",
+        "Group.Read.All|Directory.Read.All",
+    ])?;
+
+    let tenant = tenant_sharing(&client).await?;
+    let sites = list_sites(&client, &config.site).await?;
+    let labels = site_labels(&client).await?;
+    info!("Reading the libraries of {} sites", sites.len());
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(
+        &client,
+        &sites,
+        &labels,
+        external_sharing_enabled(&tenant),
+        config.concurrency as usize,
+        &shutdown,
+    )
+    .await;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan, &tenant, config.flagged, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} of {} sites; the report above is incomplete", scan.checked, scan.total);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        return Err(anyhow::anyhow!("{} sites could not be read", scan.failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let tenant = tenant_sharing(&client).await.unwrap();
+        let sites = list_sites(&client, &[]).await.unwrap();
+        let labels = site_labels(&client).await.unwrap();
+        let shutdown = service::shutdown_signal();
+        let scan = scan(&client, &sites, &labels, external_sharing_enabled(&tenant), 4, &shutdown).await;
+        assert_eq!(scan.failed, 0);
+        assert_eq!(scan.sites.iter().filter(|row| row.flagged).count(), 2);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan, &tenant, false, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,371 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::csv::Table;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, mail, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_forwarding")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports mailboxes that forward or redirect mail, and whether the destination is external, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "Only report destinations outside the tenant's verified domains")]
+    external_only: bool,
+
+    #[arg(long, value_name = "FILE", help = "CSV of mailbox forwarding (ForwardingSmtpAddress, ForwardingAddress) exported from Exchange Online")]
+    forwarding_export: Option<PathBuf>,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of mailboxes read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    id: String,
+    #[serde(rename = "isVerified")]
+    is_verified: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    mail: Option<String>,
+}
+
+// One row per mailbox, forwarding action, and destination.
+#[derive(Serialize, Debug)]
+struct ForwardingRow {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    source: String,
+    #[serde(rename = "ruleName")]
+    rule_name: String,
+    action: String,
+    destination: String,
+    enabled: bool,
+    external: bool,
+}
+
+struct Scan {
+    rows: Vec<ForwardingRow>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+// Mailbox-level forwarding is an Exchange Online setting that Graph does not expose, so it
+// comes from a CSV exported with Exchange Online PowerShell.
+struct ForwardingExport {
+    entries: HashMap<String, Vec<(String, String)>>,
+}
+
+impl ForwardingExport {
+    fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let table = Table::parse(content.trim_start_matches('\u{feff}'))
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let upn = table.upn_column()?;
+        let mut entries: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for action in ["ForwardingSmtpAddress", "ForwardingAddress"] {
+            let Some(column) = table.column(&[action]) else {
+                continue;
+            };
+            for record in &table.records {
+                let address = record.get(column);
+                let address = address
+                    .get(..5)
+                    .filter(|prefix| prefix.eq_ignore_ascii_case("smtp:"))
+                    .map_or(address, |_| &address[5..]);
+                if !address.is_empty() {
+                    entries
+                        .entry(record.get(upn).to_lowercase())
+                        .or_default()
+                        .push((action.to_string(), address.to_lowercase()));
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    fn for_mailbox(&self, upn: &str) -> &[(String, String)] {
+        self.entries
+            .get(&upn.to_lowercase())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn internal_domains(client: &GraphClient) -> Result<Vec<String>> {
+    let domains: Vec<Domain> = client
+        .path(&["domains"])
+        .select("id,isVerified")
+        .get_all()
+        .await
+        .context("Failed to fetch the tenant's domains")?;
+    Ok(domains
+        .into_iter()
+        .filter(|domain| domain.is_verified)
+        .map(|domain| domain.id.to_lowercase())
+        .collect())
+}
+
+async fn mailbox_users(client: &GraphClient) -> Result<Vec<User>> {
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,mail")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users.into_iter().filter(|user| user.mail.is_some()).collect())
+}
+
+// Subdomains of a verified domain count as internal. A ForwardingAddress that could not be
+// resolved to an address is an Exchange recipient and is treated as internal.
+fn is_external(address: &str, internal_domains: &[String]) -> bool {
+    let Some((_, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    !internal_domains
+        .iter()
+        .any(|internal| domain == internal || domain.ends_with(&format!(".{}", internal)))
+}
+
+async fn forwarding_rules(client: &GraphClient, upn: &str, internal_domains: &[String]) -> Result<Vec<ForwardingRow>> {
+    let mut rows = Vec::new();
+    for rule in mail::list_inbox_rules(client, upn).await? {
+        for (action, destination) in mail::forwarding_actions(&rule) {
+            rows.push(ForwardingRow {
+                user_principal_name: upn.to_string(),
+                source: "inboxRule".to_string(),
+                rule_name: rule["displayName"].as_str().unwrap_or_default().to_string(),
+                action: action.to_string(),
+                external: is_external(&destination, internal_domains),
+                destination,
+                enabled: rule["isEnabled"].as_bool().unwrap_or(false),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+async fn scan(
+    client: &GraphClient,
+    internal_domains: &[String],
+    export: Option<&ForwardingExport>,
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("list users");
+    let users = mailbox_users(client).await?;
+    drop(phase);
+    info!("Checking the inbox rules of {} mailboxes", users.len());
+
+    let _phase = client.stats().phase("read inbox rules");
+    let results: Vec<(&User, Result<Vec<ForwardingRow>>)> = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|user| async move {
+            (user, forwarding_rules(client, &user.user_principal_name, internal_domains).await)
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (user, result) in results {
+        let exported = export
+            .map(|export| export.for_mailbox(&user.user_principal_name))
+            .unwrap_or_default();
+        for (action, destination) in exported {
+            rows.push(ForwardingRow {
+                user_principal_name: user.user_principal_name.clone(),
+                source: "mailbox".to_string(),
+                rule_name: String::new(),
+                action: action.clone(),
+                destination: destination.clone(),
+                enabled: true,
+                external: is_external(destination, internal_domains),
+            });
+        }
+        match result {
+            Ok(rules) => rows.extend(rules),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(Scan {
+        rows,
+        checked,
+        failed,
+        interrupted: checked < users.len(),
+    })
+}
+
+fn render_report(rows: &[ForwardingRow], output: &str, internal_domains: &[String], run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "internalDomains": internal_domains,
+                "forwarding": rows,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,source,ruleName,action,destination,enabled,external")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv::field(&row.user_principal_name),
+                    row.source,
+                    csv::field(&row.rule_name),
+                    row.action,
+                    csv::field(&row.destination),
+                    row.enabled,
+                    row.external
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<10} {:<22} {:<35} {:<9} RULE",
+                "USER", "SOURCE", "ACTION", "DESTINATION", "EXTERNAL"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{:<35} {:<10} {:<22} {:<35} {:<9} {}{}",
+                    row.user_principal_name,
+                    row.source,
+                    row.action,
+                    row.destination,
+                    if row.external { "yes" } else { "no" },
+                    if row.rule_name.is_empty() { "-" } else { &row.rule_name },
+                    if row.enabled { "" } else { " (disabled)" }
+                )?;
+            }
+            let mut mailboxes: Vec<&str> = rows.iter().map(|row| row.user_principal_name.as_str()).collect();
+            mailboxes.dedup();
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} forwarding destinations on {} mailboxes, {} external and enabled",
+                rows.len(),
+                mailboxes.len(),
+                rows.iter().filter(|row| row.external && row.enabled).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_forwarding");
+    debug!("Configuration: {:?}", config);
+
+    let export = config
+        .forwarding_export
+        .as_deref()
+        .map(ForwardingExport::load)
+        .transpose()?;
+    if export.is_none() {
+        warn!("Mailbox forwarding (ForwardingSmtpAddress) is not available through Graph, so only inbox rules are reported; pass --forwarding-export to include it");
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "Domain.Read.All", "MailboxSettings.Read"])?;
+
+    let phase = client.stats().phase("list domains");
+    let internal_domains = internal_domains(&client).await?;
+    drop(phase);
+
+    let shutdown = service::shutdown_signal();
+    let mut scan = scan(&client, &internal_domains, export.as_ref(), config.concurrency as usize, &shutdown).await?;
+    if config.external_only {
+        scan.rows.retain(|row| row.external);
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.rows, &config.output, &internal_domains, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} mailboxes; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "Inbox rules could not be read for {} of {} mailboxes; their forwarding is missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} mailboxes could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let export = ForwardingExport::load(&golden::testdata("forwarding.csv")).unwrap();
+        let internal_domains = internal_domains(&client).await.unwrap();
+        let scan = scan(&client, &internal_domains, Some(&export), 2, &ShutdownSignal::never())
+            .await
+            .unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 1, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.rows, output, &internal_domains, &Summary::fixture()));
+    }
+}
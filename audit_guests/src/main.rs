@@ -0,0 +1,443 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::confirm;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, remediation, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_guests")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports guest users with their invitation state, sign-in activity, groups, and sponsors, and optionally removes stale guests, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Flag guests that have not signed in for this many days")]
+    inactive_days: i64,
+
+    #[arg(long, help = "Delete every guest flagged as stale")]
+    remove_stale: bool,
+
+    #[arg(long, requires = "remove_stale", help = "List the guests that would be deleted without changing them")]
+    dry_run: bool,
+
+    #[arg(short, long, requires = "remove_stale", help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+    #[serde(rename = "externalUserState")]
+    external_user_state: Option<String>,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "signInActivity")]
+    sign_in_activity: Option<SignInActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignInActivity {
+    #[serde(rename = "lastSignInDateTime")]
+    last_sign_in_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastNonInteractiveSignInDateTime")]
+    last_non_interactive_sign_in_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryObject {
+    #[serde(rename = "@odata.type")]
+    odata_type: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Guest {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    mail: String,
+    #[serde(rename = "invitationState")]
+    invitation_state: String,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<DateTime<Utc>>,
+    groups: Vec<String>,
+    sponsors: Vec<String>,
+    stale: bool,
+}
+
+struct GuestDetails {
+    groups: Vec<String>,
+    sponsors: Vec<String>,
+}
+
+struct Scan {
+    guests: Vec<Guest>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_guests(client: &GraphClient) -> Result<Vec<User>> {
+    client
+        .users()
+        .filter("userType eq 'Guest'")
+        .select("id,userPrincipalName,displayName,mail,externalUserState,createdDateTime,signInActivity")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch guest users")
+}
+
+// Groups the guest is a direct member of, and the users or groups sponsoring it.
+async fn read_guest(client: &GraphClient, user: &User) -> Result<GuestDetails> {
+    let member_of: Vec<DirectoryObject> = client
+        .user(&user.id)
+        .member_of()
+        .select("displayName")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the groups of {}", user.user_principal_name))?;
+    let sponsors: Vec<DirectoryObject> = client
+        .user(&user.id)
+        .sponsors()
+        .select("displayName,userPrincipalName")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the sponsors of {}", user.user_principal_name))?;
+    let groups = member_of
+        .into_iter()
+        .filter(|object| object.odata_type.as_deref() == Some("#microsoft.graph.group"))
+        .filter_map(|object| object.display_name)
+        .collect();
+    let sponsors = sponsors
+        .into_iter()
+        .filter_map(|object| object.user_principal_name.or(object.display_name))
+        .collect();
+    Ok(GuestDetails { groups, sponsors })
+}
+
+async fn scan(client: &GraphClient, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list guests");
+    let users = list_guests(client).await?;
+    drop(phase);
+    info!("Reading the groups and sponsors of {} guests", users.len());
+
+    let _phase = client.stats().phase("read guests");
+    let results: Vec<(&User, Result<GuestDetails>)> = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|user| async move { (user, read_guest(client, user).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut guests = Vec::new();
+    let mut failed = 0;
+    for (user, result) in results {
+        let details = match result {
+            Ok(details) => details,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let activity = user.sign_in_activity.as_ref();
+        let last_sign_in = activity
+            .and_then(|activity| activity.last_sign_in_date_time)
+            .max(activity.and_then(|activity| activity.last_non_interactive_sign_in_date_time));
+        guests.push(Guest {
+            id: user.id.clone(),
+            user_principal_name: user.user_principal_name.clone(),
+            display_name: user.display_name.clone().unwrap_or_default(),
+            mail: user.mail.clone().unwrap_or_default(),
+            invitation_state: user.external_user_state.clone().unwrap_or_default(),
+            created_date_time: user.created_date_time,
+            last_sign_in,
+            groups: details.groups,
+            sponsors: details.sponsors,
+            stale: false,
+        });
+    }
+    Ok(Scan {
+        guests,
+        checked,
+        failed,
+        interrupted: checked < users.len(),
+    })
+}
+
+// A guest that never signed in is stale once it was created before the cutoff, which also
+// covers invitations that were never accepted.
+fn flag_stale(guests: &mut [Guest], cutoff: DateTime<Utc>) {
+    for guest in guests {
+        guest.stale = guest
+            .last_sign_in
+            .or(guest.created_date_time)
+            .is_some_and(|last| last < cutoff);
+    }
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value
+        .map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn date(value: Option<DateTime<Utc>>, missing: &str) -> String {
+    value
+        .map(|value| value.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| missing.to_string())
+}
+
+fn render_report(guests: &[Guest], output: &str, inactive_days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "inactiveDays": inactive_days,
+                "guests": guests,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,mail,displayName,invitationState,createdDateTime,lastSignIn,groups,sponsors,stale")?;
+            for guest in guests {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv::field(&guest.user_principal_name),
+                    csv::field(&guest.mail),
+                    csv::field(&guest.display_name),
+                    guest.invitation_state,
+                    timestamp(guest.created_date_time),
+                    timestamp(guest.last_sign_in),
+                    csv::field(&guest.groups.join("; ")),
+                    csv::field(&guest.sponsors.join("; ")),
+                    guest.stale
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<18} {:<11} {:<13} {:>6}  {:<30} NOTE",
+                "GUEST", "STATE", "CREATED", "LAST SIGN-IN", "GROUPS", "SPONSOR"
+            )?;
+            for guest in guests {
+                let line = format!(
+                    "{:<35} {:<18} {:<11} {:<13} {:>6}  {:<30} {}",
+                    if guest.mail.is_empty() { &guest.user_principal_name } else { &guest.mail },
+                    guest.invitation_state,
+                    date(guest.created_date_time, "-"),
+                    date(guest.last_sign_in, "never"),
+                    guest.groups.len(),
+                    guest.sponsors.first().map_or("-", String::as_str),
+                    if guest.stale { "stale" } else { "" }
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} guests: {} inactive for {} days, {} have not accepted their invitation, {} have no sponsor",
+                guests.len(),
+                guests.iter().filter(|guest| guest.stale).count(),
+                inactive_days,
+                guests.iter().filter(|guest| guest.invitation_state == "PendingAcceptance").count(),
+                guests.iter().filter(|guest| guest.sponsors.is_empty()).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn remove_stale(client: &GraphClient, targets: &[&Guest], config: &AppConfig, shutdown: &ShutdownSignal) -> Result<()> {
+    println!();
+    if config.dry_run {
+        for guest in targets {
+            println!("DELETE  {}", guest.user_principal_name);
+        }
+        println!();
+        println!("Processed {} of {} guests: {} to delete", targets.len(), targets.len(), targets.len());
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+    if targets.is_empty() {
+        println!("Nothing to delete.");
+        return Ok(());
+    }
+    if !config.yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        println!("Delete {} stale guests?", targets.len());
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    // Guests already being processed finish; no new ones are started after an interrupt.
+    let _phase = client.stats().phase("delete guests");
+    let mut results = stream::iter(targets)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|guest| async move { (guest, remediation::delete_user(client, &guest.id).await) })
+        .buffer_unordered(config.concurrency as usize);
+    let mut finished = HashSet::new();
+    let mut deleted = 0;
+    let mut failed = 0;
+    while let Some((guest, result)) = results.next().await {
+        finished.insert(&guest.id);
+        match result {
+            Ok(()) => {
+                deleted += 1;
+                println!("OK      {}", guest.user_principal_name);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", guest.user_principal_name, e);
+            }
+        }
+    }
+
+    let interrupted = targets.len() - finished.len();
+    for guest in targets.iter().filter(|guest| !finished.contains(&guest.id)) {
+        println!("SKIPPED {}: not started", guest.user_principal_name);
+    }
+    println!();
+    println!(
+        "Processed {} of {} guests: {} deleted, {} failed, {} skipped",
+        targets.len() - interrupted,
+        targets.len(),
+        deleted,
+        failed,
+        interrupted
+    );
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} guests were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} guests failed", failed, targets.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_guests");
+    debug!("Configuration: {:?}", config);
+
+    if config.remove_stale && config.output != "text" {
+        return Err(anyhow::anyhow!("--remove-stale reports its progress as text and cannot be combined with -o {}", config.output));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let users_permission = if config.remove_stale && !config.dry_run {
+        "User.ReadWrite.All"
+    } else {
+        "User.Read.All"
+    };
+    client.require_permissions(&[users_permission, "GroupMember.Read.All", "AuditLog.Read.All"])?;
+
+    let shutdown = service::shutdown_signal();
+    let mut scan = scan(&client, config.concurrency as usize, &shutdown).await?;
+    flag_stale(&mut scan.guests, Utc::now() - Duration::days(config.inactive_days));
+    print!("{}", render_report(&scan.guests, &config.output, config.inactive_days, &client.stats().summary())?);
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} guests; the report above is incomplete", scan.checked);
+        client.stats().summary().print();
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "Groups or sponsors could not be read for {} of {} guests; they are missing from the report and are not removed",
+            scan.failed,
+            scan.checked
+        );
+    }
+
+    if config.remove_stale {
+        let targets: Vec<&Guest> = scan.guests.iter().filter(|guest| guest.stale).collect();
+        let result = remove_stale(&client, &targets, &config, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+
+    if scan.failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} guests could not be read", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let mut scan = scan(&client, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (5, 1, false));
+        flag_stale(&mut scan.guests, "2026-07-17T00:00:00Z".parse().unwrap());
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.guests, output, 90, &Summary::fixture()));
+    }
+}
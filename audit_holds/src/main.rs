@@ -0,0 +1,482 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::is_object_id;
+use graph_common::csv::Table;
+use graph_common::stats::Summary;
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_holds")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports which mailboxes have a litigation hold, eDiscovery hold, or retention policy applied, and which members of target groups are missing coverage, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_name = "FILE", help = "Exchange Online export of the holds of each mailbox (UserPrincipalName, LitigationHoldEnabled, InPlaceHolds)")]
+    holds: Option<PathBuf>,
+
+    #[arg(short, long, value_name = "GROUP", help = "Group name or object id whose members must be covered (repeatable)")]
+    group: Vec<String>,
+
+    #[arg(long, help = "With --group, also list members that are covered")]
+    all: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EdiscoveryCase {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LegalHold {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "isEnabled", default)]
+    is_enabled: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserSource {
+    email: Option<String>,
+}
+
+// The holds Exchange Online keeps per mailbox, which Graph does not return.
+#[derive(Debug, Clone)]
+struct MailboxHolds {
+    // The UPN, or the primary SMTP address when the export has no UPN.
+    mailbox: String,
+    display_name: String,
+    litigation_hold: bool,
+    // Microsoft 365 retention policies and other holds from InPlaceHolds, without eDiscovery holds.
+    retention_policies: usize,
+    // Org-wide retention policies the mailbox is explicitly excluded from.
+    excluded: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct Coverage {
+    mailbox: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    groups: Vec<String>,
+    // None when the mailbox is not in the Exchange export.
+    #[serde(rename = "litigationHold")]
+    litigation_hold: Option<bool>,
+    #[serde(rename = "ediscoveryHolds")]
+    ediscovery_holds: Vec<String>,
+    #[serde(rename = "retentionPolicies")]
+    retention_policies: Option<usize>,
+    #[serde(rename = "excludedFromPolicies")]
+    excluded_from_policies: usize,
+    // "covered", "not covered", or "unknown" when only eDiscovery holds could be checked.
+    status: &'static str,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Holds keyed by lowercase UPN and primary SMTP address, from Get-Mailbox exported with
+// Export-Csv and InPlaceHolds joined with semicolons.
+fn load_holds(path: &Path) -> Result<HashMap<String, MailboxHolds>> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse_holds(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn parse_holds(content: &str) -> Result<HashMap<String, MailboxHolds>> {
+    let table = Table::parse(content.trim_start_matches('\u{feff}'))?;
+    let upn = table.upn_column().ok();
+    let address = table.column(&["PrimarySmtpAddress"]);
+    if upn.is_none() && address.is_none() {
+        return Err(anyhow::anyhow!("The holds export has no UserPrincipalName or PrimarySmtpAddress column"));
+    }
+    let litigation_hold = table
+        .column(&["LitigationHoldEnabled"])
+        .context("The holds export has no LitigationHoldEnabled column")?;
+    let in_place_holds = table
+        .column(&["InPlaceHolds"])
+        .context("The holds export has no InPlaceHolds column")?;
+    let display_name = table.column(&["DisplayName"]);
+
+    let mut holds = HashMap::new();
+    for record in &table.records {
+        let value = record.get(litigation_hold);
+        let litigation_hold = match value.to_lowercase().as_str() {
+            "true" => true,
+            "false" => false,
+            _ => return Err(anyhow::anyhow!("Line {}: {:?} is not True or False", record.line, value)),
+        };
+        // mbx and skp are retention policies, UniH are eDiscovery case holds (read from Graph
+        // instead), and a leading - marks an org-wide policy the mailbox is excluded from.
+        let entries: Vec<&str> = record
+            .get(in_place_holds)
+            .split([';', ' '])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        let keys: Vec<String> = [upn, address]
+            .into_iter()
+            .flatten()
+            .map(|column| record.get(column).to_lowercase())
+            .filter(|key| !key.is_empty())
+            .collect();
+        let Some(first) = keys.first() else {
+            continue;
+        };
+        let mailbox = MailboxHolds {
+            mailbox: first.clone(),
+            display_name: display_name.map(|column| record.get(column).to_string()).unwrap_or_default(),
+            litigation_hold,
+            retention_policies: entries
+                .iter()
+                .filter(|entry| !entry.starts_with('-') && !entry.to_lowercase().starts_with("unih"))
+                .count(),
+            excluded: entries.iter().filter(|entry| entry.starts_with('-')).count(),
+        };
+        for key in keys {
+            holds.insert(key, mailbox.clone());
+        }
+    }
+    Ok(holds)
+}
+
+// The enabled holds of active eDiscovery (Premium) cases, keyed by the lowercase address of
+// each mailbox they cover, as "case / hold".
+async fn ediscovery_holds(client: &GraphClient) -> Result<HashMap<String, Vec<String>>> {
+    let cases: Vec<EdiscoveryCase> = client
+        .path(&["security", "cases", "ediscoveryCases"])
+        .select("id,displayName,status")
+        .get_all()
+        .await
+        .context("Failed to fetch eDiscovery cases")?;
+    let mut holds: HashMap<String, Vec<String>> = HashMap::new();
+    for case in cases.iter().filter(|case| case.status.as_deref() == Some("active")) {
+        let legal_holds: Vec<LegalHold> = client
+            .path(&["security", "cases", "ediscoveryCases", &case.id, "legalHolds"])
+            .select("id,displayName,isEnabled")
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to fetch the holds of case {}", case.id))?;
+        for hold in legal_holds.iter().filter(|hold| hold.is_enabled) {
+            let sources: Vec<UserSource> = client
+                .path(&["security", "cases", "ediscoveryCases", &case.id, "legalHolds", &hold.id, "userSources"])
+                .select("email")
+                .get_all()
+                .await
+                .with_context(|| format!("Failed to fetch the mailboxes of hold {}", hold.id))?;
+            let name = format!(
+                "{} / {}",
+                case.display_name.as_deref().unwrap_or(&case.id),
+                hold.display_name.as_deref().unwrap_or(&hold.id)
+            );
+            for email in sources.into_iter().filter_map(|source| source.email) {
+                holds.entry(email.to_lowercase()).or_default().push(name.clone());
+            }
+        }
+    }
+    Ok(holds)
+}
+
+async fn resolve_group(client: &GraphClient, group: &str) -> Result<Group> {
+    if is_object_id(group) {
+        return client
+            .path(&["groups", group])
+            .select("id,displayName")
+            .get_json::<Group>()
+            .await
+            .with_context(|| format!("Failed to look up group {}", group));
+    }
+
+    let mut groups = client
+        .path(&["groups"])
+        .filter(&format!("displayName eq {}", encode::odata_string(group)))
+        .select("id,displayName")
+        .get_all::<Group>()
+        .await
+        .with_context(|| format!("Failed to look up group {}", group))?;
+    match groups.len() {
+        0 => Err(anyhow::anyhow!("No group named {}", group)),
+        1 => Ok(groups.remove(0)),
+        count => Err(anyhow::anyhow!(
+            "{} groups are named {}; pass the group's object id instead",
+            count,
+            group
+        )),
+    }
+}
+
+// The mailboxes to report, keyed by lowercase address: the members of the target groups,
+// including nested groups, or every mailbox with a hold when there are no target groups.
+async fn target_mailboxes(
+    client: &GraphClient,
+    groups: &[String],
+    holds: Option<&HashMap<String, MailboxHolds>>,
+    ediscovery: &HashMap<String, Vec<String>>,
+) -> Result<BTreeMap<String, (String, Vec<String>)>> {
+    let mut mailboxes: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    if groups.is_empty() {
+        for mailbox in holds.into_iter().flat_map(HashMap::values) {
+            mailboxes.entry(mailbox.mailbox.clone()).or_insert_with(|| (mailbox.display_name.clone(), Vec::new()));
+        }
+        // eDiscovery sources may use an address the export lists under another key.
+        for address in ediscovery.keys() {
+            let address = holds
+                .and_then(|holds| holds.get(address))
+                .map_or(address, |mailbox| &mailbox.mailbox);
+            mailboxes.entry(address.clone()).or_default();
+        }
+        return Ok(mailboxes);
+    }
+    for name in groups {
+        let group = resolve_group(client, name).await?;
+        let group_name = group.display_name.clone().unwrap_or_else(|| group.id.clone());
+        let members: Vec<User> = client
+            .path(&["groups", &group.id, "transitiveMembers", "microsoft.graph.user"])
+            .select("displayName,userPrincipalName,mail")
+            .top(999)
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to fetch the members of {}", group_name))?;
+        info!("{} has {} members", group_name, members.len());
+        for member in members {
+            let Some(upn) = member.user_principal_name.or(member.mail) else {
+                continue;
+            };
+            let entry = mailboxes
+                .entry(upn.to_lowercase())
+                .or_insert_with(|| (member.display_name.unwrap_or_default(), Vec::new()));
+            entry.1.push(group_name.clone());
+        }
+    }
+    Ok(mailboxes)
+}
+
+fn coverage(
+    mailboxes: BTreeMap<String, (String, Vec<String>)>,
+    holds: Option<&HashMap<String, MailboxHolds>>,
+    ediscovery: &HashMap<String, Vec<String>>,
+) -> Vec<Coverage> {
+    let mut rows: Vec<Coverage> = mailboxes
+        .into_iter()
+        .map(|(address, (display_name, groups))| {
+            let mailbox = holds.and_then(|holds| holds.get(&address));
+            let mut ediscovery_holds: Vec<String> = ediscovery
+                .iter()
+                .filter(|(key, _)| **key == address || holds.and_then(|holds| holds.get(*key)).is_some_and(|mailbox| mailbox.mailbox == address))
+                .flat_map(|(_, names)| names.iter().cloned())
+                .collect();
+            ediscovery_holds.sort();
+            ediscovery_holds.dedup();
+            let covered = !ediscovery_holds.is_empty()
+                || mailbox.is_some_and(|mailbox| mailbox.litigation_hold || mailbox.retention_policies > 0);
+            Coverage {
+                display_name: if display_name.is_empty() {
+                    mailbox.map(|mailbox| mailbox.display_name.clone()).unwrap_or_default()
+                } else {
+                    display_name
+                },
+                mailbox: address,
+                groups,
+                litigation_hold: mailbox.map(|mailbox| mailbox.litigation_hold),
+                retention_policies: mailbox.map(|mailbox| mailbox.retention_policies),
+                excluded_from_policies: mailbox.map_or(0, |mailbox| mailbox.excluded),
+                status: if covered {
+                    "covered"
+                } else if mailbox.is_some() {
+                    "not covered"
+                } else {
+                    "unknown"
+                },
+                ediscovery_holds,
+            }
+        })
+        .collect();
+    rows.sort_by_key(|row| (row.status == "covered", row.status == "unknown", row.mailbox.clone()));
+    rows
+}
+
+fn render_report(rows: &[Coverage], total: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "mailboxes": rows,
+                "totalMailboxes": total,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "mailbox,displayName,groups,litigationHold,ediscoveryHolds,retentionPolicies,excludedFromPolicies,status")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv::field(&row.mailbox),
+                    csv::field(&row.display_name),
+                    csv::field(&row.groups.join("; ")),
+                    row.litigation_hold.map(|hold| hold.to_string()).unwrap_or_default(),
+                    csv::field(&row.ediscovery_holds.join("; ")),
+                    row.retention_policies.map(|count| count.to_string()).unwrap_or_default(),
+                    row.excluded_from_policies,
+                    row.status
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<35} {:<12} {:<9} {:<10} {:<12} EDISCOVERY HOLDS", "MAILBOX", "STATUS", "LIT HOLD", "RETENTION", "EXCLUDED")?;
+            for row in rows {
+                let line = format!(
+                    "{:<35} {:<12} {:<9} {:<10} {:<12} {}",
+                    row.mailbox,
+                    row.status,
+                    match row.litigation_hold {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "-",
+                    },
+                    row.retention_policies.map_or_else(|| "-".to_string(), |count| count.to_string()),
+                    row.excluded_from_policies,
+                    row.ediscovery_holds.join("; ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            let count = |status: &str| rows.iter().filter(|row| row.status == status).count();
+            writeln!(
+                out,
+                "{} of {} mailboxes listed: {} covered, {} not covered, {} unknown",
+                rows.len(),
+                total,
+                count("covered"),
+                count("not covered"),
+                count("unknown")
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn scan(client: &GraphClient, groups: &[String], holds: Option<&HashMap<String, MailboxHolds>>) -> Result<Vec<Coverage>> {
+    let phase = client.stats().phase("read eDiscovery holds");
+    let ediscovery = ediscovery_holds(client).await?;
+    drop(phase);
+    info!("Active eDiscovery holds cover {} mailboxes", ediscovery.len());
+
+    let _phase = client.stats().phase("read target groups");
+    let mailboxes = target_mailboxes(client, groups, holds, &ediscovery).await?;
+    Ok(coverage(mailboxes, holds, &ediscovery))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_holds");
+    debug!("Configuration: {:?}", config);
+
+    let holds = config.holds.as_deref().map(load_holds).transpose()?;
+    if holds.is_none() {
+        warn!("No --holds export given; Graph does not return litigation holds or retention policies, so only eDiscovery holds are checked");
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    if config.group.is_empty() {
+        client.require_permissions(&["eDiscovery.Read.All"])?;
+    } else {
+        client.require_permissions(&["eDiscovery.Read.All", "GroupMember.Read.All"])?;
+    }
+
+    let mut rows = scan(&client, &config.group, holds.as_ref()).await?;
+    let total = rows.len();
+    if !config.group.is_empty() && !config.all {
+        rows.retain(|row| row.status != "covered");
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&rows, total, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let holds = load_holds(&golden::testdata("holds.csv")).unwrap();
+        let client = golden::fixture_client();
+        let groups = vec!["Legal".to_string(), "9b1c2d3e-4f50-4a6b-8c7d-0e1f2a3b4c5d".to_string()];
+        let rows = scan(&client, &groups, Some(&holds)).await.unwrap();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&rows, rows.len(), output, &Summary::fixture()));
+
+        let rows = scan(&client, &[], Some(&holds)).await.unwrap();
+        let rendered = render_report(&rows, rows.len(), "text", &Summary::fixture()).unwrap();
+        golden::assert_matches(&golden::testdata("golden/all_mailboxes.txt"), &rendered);
+    }
+
+    #[test]
+    fn classifies_in_place_holds() {
+        let holds = parse_holds(concat!(
+            "\u{feff}UserPrincipalName,PrimarySmtpAddress,DisplayName,LitigationHoldEnabled,InPlaceHolds\n",
+            "jane.doe@contoso.com,Jane.Doe@contoso.com,Jane Doe,False,mbx1a2b3c4d5e6f:1;UniH0f1e2d3c-4b5a-4968-8776-655443322110;-mbx9f8e7d6c5b4a:2\n",
+            "kim@contoso.com,kim.akers@contoso.com,Kim Akers,True,\n",
+        ))
+        .unwrap();
+        let jane = &holds["jane.doe@contoso.com"];
+        assert_eq!((jane.litigation_hold, jane.retention_policies, jane.excluded), (false, 1, 1));
+        assert!(holds["kim.akers@contoso.com"].litigation_hold);
+        assert_eq!(holds.len(), 3);
+        assert!(parse_holds("UserPrincipalName,LitigationHoldEnabled,InPlaceHolds\njane@contoso.com,Maybe,\n").is_err());
+    }
+}
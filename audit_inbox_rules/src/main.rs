@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::rules::{self, RuleAssessment};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_inbox_rules")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Finds suspicious inbox rules on every mailbox in the tenant using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, help = "Only check this user and show every inbox rule with the reasons it was flagged")]
+    upn: Option<String>,
+
+    #[arg(long, help = "Also report inbox rules that are not suspicious")]
+    all: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of mailboxes read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    mail: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct InboxRule {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(flatten)]
+    assessment: RuleAssessment,
+}
+
+struct Scan {
+    rules: Vec<InboxRule>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn mailbox_users(client: &GraphClient) -> Result<Vec<String>> {
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,mail")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users
+        .into_iter()
+        .filter(|user| user.mail.is_some())
+        .map(|user| user.user_principal_name)
+        .collect())
+}
+
+async fn scan(client: &GraphClient, upns: &[String], concurrency: usize, shutdown: &ShutdownSignal) -> Scan {
+    let _phase = client.stats().phase("read inbox rules");
+    let results: Vec<(&String, Result<Vec<RuleAssessment>>)> = stream::iter(upns)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|upn| async move { (upn, rules::review(client, upn).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut rules = Vec::new();
+    let mut failed = 0;
+    for (upn, result) in results {
+        match result {
+            Ok(assessments) => rules.extend(assessments.into_iter().map(|assessment| InboxRule {
+                user_principal_name: upn.clone(),
+                assessment,
+            })),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Scan {
+        rules,
+        checked,
+        failed,
+        interrupted: checked < upns.len(),
+    }
+}
+
+fn or_dash(values: &[String]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn render_report(rules: &[InboxRule], output: &str, drill_down: bool, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let suspicious = rules.iter().filter(|rule| rule.assessment.suspicious).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "inboxRules": rules,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,id,displayName,isEnabled,suspicious,reasons,forwardsTo,moveToFolder")?;
+            for rule in rules {
+                let assessment = &rule.assessment;
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv::field(&rule.user_principal_name),
+                    csv::field(&assessment.id),
+                    csv::field(&assessment.display_name),
+                    assessment.is_enabled,
+                    assessment.suspicious,
+                    csv::field(&assessment.reasons.join("; ")),
+                    csv::field(&assessment.forwards_to.join("; ")),
+                    csv::field(assessment.move_to_folder.as_deref().unwrap_or_default())
+                )?;
+            }
+        }
+        _ if drill_down => {
+            for rule in rules {
+                let assessment = &rule.assessment;
+                writeln!(
+                    out,
+                    "\"{}\" ({}){}",
+                    assessment.display_name,
+                    if assessment.is_enabled { "enabled" } else { "disabled" },
+                    if assessment.suspicious { "  SUSPICIOUS" } else { "" }
+                )?;
+                writeln!(out, "  {:<14}{}", "Forwards to:", or_dash(&assessment.forwards_to))?;
+                writeln!(out, "  {:<14}{}", "Moves to:", assessment.move_to_folder.as_deref().unwrap_or("-"))?;
+                if assessment.reasons.is_empty() {
+                    writeln!(out, "  {:<14}-", "Reasons:")?;
+                }
+                for (index, reason) in assessment.reasons.iter().enumerate() {
+                    writeln!(out, "  {:<14}{}", if index == 0 { "Reasons:" } else { "" }, reason)?;
+                }
+                writeln!(out)?;
+            }
+            writeln!(out, "{} inbox rules, {} suspicious", rules.len(), suspicious)?;
+        }
+        _ => {
+            writeln!(out, "{:<35} {:<30} {:<8} REASONS", "USER", "RULE", "ENABLED")?;
+            for rule in rules {
+                let assessment = &rule.assessment;
+                writeln!(
+                    out,
+                    "{:<35} {:<30} {:<8} {}",
+                    rule.user_principal_name,
+                    assessment.display_name,
+                    if assessment.is_enabled { "yes" } else { "no" },
+                    if assessment.reasons.is_empty() { "-".to_string() } else { assessment.reasons.join("; ") }
+                )?;
+            }
+            let mut mailboxes: Vec<&str> = rules
+                .iter()
+                .filter(|rule| rule.assessment.suspicious)
+                .map(|rule| rule.user_principal_name.as_str())
+                .collect();
+            mailboxes.dedup();
+            writeln!(out)?;
+            writeln!(out, "{} suspicious inbox rules on {} mailboxes", suspicious, mailboxes.len())?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_inbox_rules");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "MailboxSettings.Read", "Mail.ReadBasic.All"])?;
+
+    let upns = match &config.upn {
+        Some(upn) => vec![upn.clone()],
+        None => {
+            let _phase = client.stats().phase("list users");
+            mailbox_users(&client).await?
+        }
+    };
+    info!("Checking the inbox rules of {} mailboxes", upns.len());
+
+    let shutdown = service::shutdown_signal();
+    let mut scan = scan(&client, &upns, config.concurrency as usize, &shutdown).await;
+    let drill_down = config.upn.is_some();
+    if !drill_down && !config.all {
+        scan.rules.retain(|rule| rule.assessment.suspicious);
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.rules, &config.output, drill_down, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} mailboxes; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "Inbox rules could not be read for {} of {} mailboxes; they are missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} mailboxes could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let upns = mailbox_users(&client).await.unwrap();
+        let mut scan = scan(&client, &upns, 2, &ShutdownSignal::never()).await;
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 1, false));
+        assert_eq!(scan.rules.len(), 5);
+
+        scan.rules.retain(|rule| rule.assessment.suspicious);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.rules, output, false, &Summary::fixture()));
+    }
+
+    #[tokio::test]
+    async fn drill_down_lists_every_rule() {
+        let client = golden::fixture_client();
+        let scan = scan(&client, &["ceo@contoso.com".to_string()], 1, &ShutdownSignal::never()).await;
+        let rendered = render_report(&scan.rules, "text", true, &Summary::fixture()).unwrap();
+        golden::assert_matches(&golden::testdata("golden/drill_down.txt"), &rendered);
+    }
+}
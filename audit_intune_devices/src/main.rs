@@ -0,0 +1,368 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_intune_devices")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports Intune managed devices that are non-compliant, unencrypted, or below a minimum OS version, grouped by owner, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_name = "OS=VERSION", value_parser = parse_min_os, help = "Flag devices of this operating system below this version, such as Windows=10.0.19045 (can be repeated)")]
+    min_os: Vec<(String, String)>,
+
+    #[arg(long, help = "List every managed device, not only flagged ones")]
+    all: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ManagedDevice {
+    id: String,
+    #[serde(rename = "deviceName")]
+    device_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "userDisplayName")]
+    user_display_name: Option<String>,
+    #[serde(rename = "operatingSystem")]
+    operating_system: Option<String>,
+    #[serde(rename = "osVersion")]
+    os_version: Option<String>,
+    #[serde(rename = "complianceState")]
+    compliance_state: Option<String>,
+    #[serde(rename = "isEncrypted")]
+    is_encrypted: Option<bool>,
+    #[serde(rename = "managedDeviceOwnerType")]
+    owner_type: Option<String>,
+    #[serde(rename = "lastSyncDateTime")]
+    last_sync: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct DeviceReport {
+    id: String,
+    #[serde(rename = "deviceName")]
+    device_name: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "userDisplayName")]
+    user_display_name: String,
+    #[serde(rename = "operatingSystem")]
+    operating_system: String,
+    #[serde(rename = "osVersion")]
+    os_version: String,
+    #[serde(rename = "ownerType")]
+    owner_type: String,
+    #[serde(rename = "complianceState")]
+    compliance_state: String,
+    encrypted: bool,
+    #[serde(rename = "minimumOsVersion")]
+    minimum_os_version: Option<String>,
+    #[serde(rename = "lastSync")]
+    last_sync: String,
+    issues: Vec<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn parse_min_os(value: &str) -> Result<(String, String)> {
+    match value.split_once('=') {
+        Some((os, version)) if !os.trim().is_empty() && !version_parts(version).is_empty() => {
+            Ok((os.trim().to_string(), version.trim().to_string()))
+        }
+        _ => Err(anyhow!("expected OS=VERSION, such as Windows=10.0.19045")),
+    }
+}
+
+// The numeric parts of a version, so "14.4.1 (23E224)" compares as 14.4.1. Missing parts count as
+// zero, so 17.4 equals 17.4.0.
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().ok()
+        })
+        .collect()
+}
+
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (version_parts(a), version_parts(b));
+    (0..a.len().max(b.len()))
+        .map(|index| a.get(index).unwrap_or(&0).cmp(b.get(index).unwrap_or(&0)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+// configManager means compliance is evaluated by Configuration Manager, which Graph does not
+// report on, so only the other states are flagged.
+fn compliance_issue(state: &str) -> Option<&'static str> {
+    match state {
+        "compliant" | "configManager" => None,
+        "inGracePeriod" => Some("non-compliant (grace period)"),
+        "noncompliant" => Some("non-compliant"),
+        "conflict" => Some("compliance conflict"),
+        "error" => Some("compliance error"),
+        _ => Some("compliance unknown"),
+    }
+}
+
+async fn list_managed_devices(client: &GraphClient) -> Result<Vec<ManagedDevice>> {
+    client
+        .path(&["deviceManagement", "managedDevices"])
+        .select("id,deviceName,userPrincipalName,userDisplayName,operatingSystem,osVersion,complianceState,isEncrypted,managedDeviceOwnerType,lastSyncDateTime")
+        .get_all()
+        .await
+        .context("Failed to fetch managed devices")
+}
+
+// Sorted by owner, so each owner's devices are listed together; devices without a primary user
+// come last.
+fn assess(devices: Vec<ManagedDevice>, min_os: &[(String, String)]) -> Vec<DeviceReport> {
+    let mut reports: Vec<DeviceReport> = devices
+        .into_iter()
+        .map(|device| {
+            let operating_system = device.operating_system.unwrap_or_default();
+            let os_version = device.os_version.unwrap_or_default();
+            let compliance_state = device.compliance_state.unwrap_or_else(|| "unknown".to_string());
+            let encrypted = device.is_encrypted.unwrap_or(false);
+            let minimum_os_version = min_os
+                .iter()
+                .find(|(os, _)| os.eq_ignore_ascii_case(&operating_system))
+                .map(|(_, version)| version.clone());
+
+            let mut issues = Vec::new();
+            issues.extend(compliance_issue(&compliance_state).map(str::to_string));
+            if !encrypted {
+                issues.push("not encrypted".to_string());
+            }
+            if let Some(minimum) = &minimum_os_version {
+                if compare_versions(&os_version, minimum).is_lt() {
+                    issues.push(format!("OS below {}", minimum));
+                }
+            }
+            DeviceReport {
+                id: device.id,
+                device_name: device.device_name.unwrap_or_default(),
+                user_principal_name: device.user_principal_name.unwrap_or_default(),
+                user_display_name: device.user_display_name.unwrap_or_default(),
+                operating_system,
+                os_version,
+                owner_type: device.owner_type.unwrap_or_default(),
+                compliance_state,
+                encrypted,
+                minimum_os_version,
+                last_sync: device.last_sync.unwrap_or_default(),
+                issues,
+            }
+        })
+        .collect();
+    reports.sort_by(|a, b| {
+        (a.user_principal_name.is_empty(), a.user_principal_name.to_lowercase(), a.device_name.to_lowercase()).cmp(&(
+            b.user_principal_name.is_empty(),
+            b.user_principal_name.to_lowercase(),
+            b.device_name.to_lowercase(),
+        ))
+    });
+    reports
+}
+
+fn owner(device: &DeviceReport) -> &str {
+    if device.user_principal_name.is_empty() {
+        "(no primary user)"
+    } else {
+        &device.user_principal_name
+    }
+}
+
+fn render_report(devices: &[&DeviceReport], total: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            let mut owners: Vec<serde_json::Value> = Vec::new();
+            for device in devices {
+                let same_owner = owners
+                    .last()
+                    .is_some_and(|last| last["userPrincipalName"] == device.user_principal_name.as_str());
+                if !same_owner {
+                    owners.push(json!({
+                        "userPrincipalName": device.user_principal_name,
+                        "userDisplayName": device.user_display_name,
+                        "devices": [],
+                    }));
+                }
+                if let Some(serde_json::Value::Array(list)) = owners.last_mut().and_then(|last| last.get_mut("devices")) {
+                    list.push(serde_json::to_value(device)?);
+                }
+            }
+            out = serde_json::to_string_pretty(&json!({
+                "owners": owners,
+                "totalDevices": total,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "userPrincipalName,userDisplayName,deviceName,operatingSystem,osVersion,ownerType,complianceState,encrypted,minimumOsVersion,lastSync,issues"
+            )?;
+            for device in devices {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&device.user_principal_name),
+                    csv::field(&device.user_display_name),
+                    csv::field(&device.device_name),
+                    csv::field(&device.operating_system),
+                    csv::field(&device.os_version),
+                    device.owner_type,
+                    device.compliance_state,
+                    device.encrypted,
+                    device.minimum_os_version.as_deref().unwrap_or_default(),
+                    device.last_sync,
+                    csv::field(&device.issues.join("; "))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<30} {:<20} {:<8} {:<18} {:<10} ISSUES",
+                "OWNER", "DEVICE", "OS", "VERSION", "LAST SYNC"
+            )?;
+            let mut previous = None;
+            for device in devices {
+                // The owner is printed once for each group of devices.
+                let name = owner(device);
+                let line = format!(
+                    "{:<30} {:<20} {:<8} {:<18} {:<10} {}",
+                    if previous == Some(name) { "" } else { name },
+                    device.device_name,
+                    device.operating_system,
+                    device.os_version,
+                    device.last_sync.get(..10).unwrap_or("-"),
+                    device.issues.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+                previous = Some(name);
+            }
+            writeln!(out)?;
+            let flagged: Vec<&&DeviceReport> = devices.iter().filter(|device| !device.issues.is_empty()).collect();
+            let mut owners: Vec<&str> = flagged
+                .iter()
+                .map(|device| device.user_principal_name.as_str())
+                .filter(|upn| !upn.is_empty())
+                .collect();
+            owners.dedup();
+            let count = |issue: &str| {
+                devices
+                    .iter()
+                    .filter(|device| device.issues.iter().any(|found| found.starts_with(issue)))
+                    .count()
+            };
+            writeln!(
+                out,
+                "{} of {} managed devices flagged, owned by {} users and {} without a primary user: {} non-compliant, {} not encrypted, {} below the minimum OS version",
+                flagged.len(),
+                total,
+                owners.len(),
+                flagged.iter().filter(|device| device.user_principal_name.is_empty()).count(),
+                count("non-compliant"),
+                count("not encrypted"),
+                count("OS below")
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_intune_devices");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["DeviceManagementManagedDevices.Read.All"])?;
+
+    let phase = client.stats().phase("list managed devices");
+    let devices = list_managed_devices(&client).await?;
+    drop(phase);
+    let reports = assess(devices, &config.min_os);
+    let listed: Vec<&DeviceReport> = reports
+        .iter()
+        .filter(|device| config.all || !device.issues.is_empty())
+        .collect();
+
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&listed, reports.len(), &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[test]
+    fn compares_os_versions() {
+        assert!(compare_versions("10.0.19045.3803", "10.0.22631").is_lt());
+        assert!(compare_versions("17.4", "17.4.0").is_eq());
+        assert!(compare_versions("14.4.1 (23E224)", "14.4").is_gt());
+        assert!(compare_versions("14", "13.9").is_gt());
+        assert!(parse_min_os("Windows=10.0.19045").is_ok());
+        assert!(parse_min_os("Windows").is_err());
+        assert!(parse_min_os("iOS=latest").is_err());
+    }
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let devices = list_managed_devices(&client).await.unwrap();
+        let min_os = [
+            ("Windows".to_string(), "10.0.22631".to_string()),
+            ("iOS".to_string(), "17.5".to_string()),
+        ];
+        let reports = assess(devices, &min_os);
+        let flagged: Vec<&DeviceReport> = reports.iter().filter(|device| !device.issues.is_empty()).collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&flagged, reports.len(), output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::stats::Summary;
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+
+// clientAppUsed values of protocols that only support basic authentication. ActiveSync and
+// Outlook clients using modern authentication are logged as "Mobile Apps and Desktop clients".
+const LEGACY_CLIENT_APPS: &[&str] = &[
+    "Authenticated SMTP",
+    "AutoDiscover",
+    "Exchange ActiveSync",
+    "Exchange Online PowerShell",
+    "Exchange Web Services",
+    "IMAP4",
+    "MAPI Over HTTP",
+    "Offline Address Book",
+    "Other clients",
+    "Outlook Anywhere (RPC over HTTP)",
+    "POP3",
+    "Reporting Web Services",
+    "Universal Outlook",
+];
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_legacy_auth")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Summarizes sign-ins that used legacy authentication protocols by user, protocol, and source IP, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(1..=30), help = "Number of days of sign-in logs to read")]
+    days: i64,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignIn {
+    #[serde(rename = "createdDateTime")]
+    created_date_time: DateTime<Utc>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "clientAppUsed")]
+    client_app_used: Option<String>,
+    #[serde(rename = "ipAddress")]
+    ip_address: Option<String>,
+    status: Option<SignInStatus>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignInStatus {
+    #[serde(rename = "errorCode", default)]
+    error_code: i64,
+}
+
+// Sign-ins by one user with one protocol from one address.
+#[derive(Serialize, Debug)]
+struct LegacyUsage {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "clientApp")]
+    client_app: String,
+    #[serde(rename = "ipAddress")]
+    ip_address: String,
+    #[serde(rename = "signIns")]
+    sign_ins: usize,
+    failed: usize,
+    #[serde(rename = "firstSeen")]
+    first_seen: DateTime<Utc>,
+    #[serde(rename = "lastSeen")]
+    last_seen: DateTime<Utc>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+async fn legacy_sign_ins(client: &GraphClient, since: DateTime<Utc>) -> Result<Vec<SignIn>> {
+    let protocols: Vec<String> = LEGACY_CLIENT_APPS
+        .iter()
+        .map(|app| format!("clientAppUsed eq {}", encode::odata_string(app)))
+        .collect();
+    client
+        .path(&["auditLogs", "signIns"])
+        .filter(&format!("createdDateTime ge {} and ({})", timestamp(since), protocols.join(" or ")))
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch sign-in logs")
+}
+
+fn summarize(sign_ins: Vec<SignIn>) -> Vec<LegacyUsage> {
+    let mut usage: BTreeMap<(String, String, String), LegacyUsage> = BTreeMap::new();
+    for sign_in in sign_ins {
+        let user = sign_in.user_principal_name.unwrap_or_default().to_lowercase();
+        let app = sign_in.client_app_used.unwrap_or_default();
+        let ip = sign_in.ip_address.unwrap_or_default();
+        let entry = usage.entry((user.clone(), app.clone(), ip.clone())).or_insert(LegacyUsage {
+            user_principal_name: user,
+            client_app: app,
+            ip_address: ip,
+            sign_ins: 0,
+            failed: 0,
+            first_seen: sign_in.created_date_time,
+            last_seen: sign_in.created_date_time,
+        });
+        entry.sign_ins += 1;
+        if sign_in.status.is_some_and(|status| status.error_code != 0) {
+            entry.failed += 1;
+        }
+        entry.first_seen = entry.first_seen.min(sign_in.created_date_time);
+        entry.last_seen = entry.last_seen.max(sign_in.created_date_time);
+    }
+    usage.into_values().collect()
+}
+
+// Sign-in counts per user or per protocol, most sign-ins first.
+fn totals<'a>(usage: &'a [LegacyUsage], key: impl Fn(&'a LegacyUsage) -> &'a str) -> Vec<(&'a str, usize)> {
+    let mut totals: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in usage {
+        *totals.entry(key(entry)).or_default() += entry.sign_ins;
+    }
+    let mut totals: Vec<(&str, usize)> = totals.into_iter().collect();
+    totals.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    totals
+}
+
+fn render_report(usage: &[LegacyUsage], output: &str, days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let by_user = totals(usage, |entry| &entry.user_principal_name);
+    let by_app = totals(usage, |entry| &entry.client_app);
+    match output {
+        "json" => {
+            let count = |totals: &[(&str, usize)], key: &str| {
+                totals
+                    .iter()
+                    .map(|(name, sign_ins)| json!({ key: name, "signIns": sign_ins }))
+                    .collect::<Vec<_>>()
+            };
+            out = serde_json::to_string_pretty(&json!({
+                "days": days,
+                "legacySignIns": usage,
+                "byUser": count(&by_user, "userPrincipalName"),
+                "byClientApp": count(&by_app, "clientApp"),
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,clientApp,ipAddress,signIns,failed,firstSeen,lastSeen")?;
+            for entry in usage {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv::field(&entry.user_principal_name),
+                    csv::field(&entry.client_app),
+                    csv::field(&entry.ip_address),
+                    entry.sign_ins,
+                    entry.failed,
+                    timestamp(entry.first_seen),
+                    timestamp(entry.last_seen)
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<32} {:<40} {:>8} {:>7}  LAST SEEN",
+                "USER", "PROTOCOL", "IP ADDRESS", "SIGN-INS", "FAILED"
+            )?;
+            for entry in usage {
+                writeln!(
+                    out,
+                    "{:<35} {:<32} {:<40} {:>8} {:>7}  {}",
+                    entry.user_principal_name,
+                    entry.client_app,
+                    entry.ip_address,
+                    entry.sign_ins,
+                    entry.failed,
+                    timestamp(entry.last_seen)
+                )?;
+            }
+            let mut addresses: Vec<&str> = usage.iter().map(|entry| entry.ip_address.as_str()).collect();
+            addresses.sort();
+            addresses.dedup();
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} legacy sign-ins by {} users from {} addresses in the last {} days",
+                usage.iter().map(|entry| entry.sign_ins).sum::<usize>(),
+                by_user.len(),
+                addresses.len(),
+                days
+            )?;
+            for (label, totals) in [("By protocol", &by_app), ("By user", &by_user)] {
+                if !totals.is_empty() {
+                    let totals: Vec<String> = totals.iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+                    writeln!(out, "{}: {}", label, totals.join(", "))?;
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_legacy_auth");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["AuditLog.Read.All", "Directory.Read.All"])?;
+
+    let phase = client.stats().phase("read sign-in logs");
+    let sign_ins = legacy_sign_ins(&client, Utc::now() - Duration::days(config.days)).await?;
+    drop(phase);
+    info!("Found {} legacy authentication sign-ins", sign_ins.len());
+
+    let usage = summarize(sign_ins);
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&usage, &config.output, config.days, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let sign_ins = legacy_sign_ins(&client, "2026-09-15T00:00:00Z".parse().unwrap())
+            .await
+            .unwrap();
+        let usage = summarize(sign_ins);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&usage, output, 30, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::reports::GroupActivity;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_orphaned_groups")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports Microsoft 365 and security groups without owners or members, with their creation date and last activity, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Recommend deleting empty groups created and inactive for this many days")]
+    inactive_days: i64,
+
+    #[arg(long, value_name = "FILE", help = "Write the groups recommended for deletion to this CSV file")]
+    deletions: Option<PathBuf>,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+    #[serde(rename = "groupTypes", default)]
+    group_types: Vec<String>,
+    #[serde(rename = "securityEnabled", default)]
+    security_enabled: bool,
+    #[serde(rename = "mailEnabled", default)]
+    mail_enabled: bool,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "onPremisesSyncEnabled")]
+    on_premises_sync_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct OrphanedGroup {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    mail: String,
+    #[serde(rename = "groupType")]
+    group_type: &'static str,
+    dynamic: bool,
+    synced: bool,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastActivity")]
+    last_activity: Option<NaiveDate>,
+    owners: usize,
+    members: usize,
+    #[serde(rename = "recommendDeletion")]
+    recommend_deletion: bool,
+}
+
+struct GroupCounts {
+    owners: usize,
+    members: usize,
+}
+
+struct Scan {
+    groups: Vec<OrphanedGroup>,
+    total: usize,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Distribution lists are left out; their owners are managed in Exchange Online, not in the
+// directory.
+fn group_type(group: &Group) -> Option<&'static str> {
+    if group.group_types.iter().any(|group_type| group_type == "Unified") {
+        Some("Microsoft 365")
+    } else if group.security_enabled && group.mail_enabled {
+        Some("mail-enabled security")
+    } else if group.security_enabled {
+        Some("security")
+    } else {
+        None
+    }
+}
+
+async fn list_groups(client: &GraphClient) -> Result<Vec<Group>> {
+    client
+        .path(&["groups"])
+        .select("id,displayName,mail,groupTypes,securityEnabled,mailEnabled,createdDateTime,onPremisesSyncEnabled")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch groups")
+}
+
+async fn count_group(client: &GraphClient, group: &Group) -> Result<GroupCounts> {
+    let owners: Vec<Value> = client
+        .path(&["groups", &group.id, "owners"])
+        .select("id")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the owners of {}", group.id))?;
+    let members: Vec<Value> = client
+        .path(&["groups", &group.id, "members"])
+        .select("id")
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the members of {}", group.id))?;
+    Ok(GroupCounts {
+        owners: owners.len(),
+        members: members.len(),
+    })
+}
+
+async fn scan(client: &GraphClient, activity: &GroupActivity, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list groups");
+    let groups: Vec<(Group, &'static str)> = list_groups(client)
+        .await?
+        .into_iter()
+        .filter_map(|group| group_type(&group).map(|group_type| (group, group_type)))
+        .collect();
+    drop(phase);
+    info!("Reading the owners and members of {} groups", groups.len());
+
+    let _phase = client.stats().phase("read groups");
+    let results: Vec<(&(Group, &'static str), Result<GroupCounts>)> = stream::iter(&groups)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|entry| async move { (entry, count_group(client, &entry.0).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut orphaned = Vec::new();
+    let mut failed = 0;
+    for ((group, group_type), result) in results {
+        let counts = match result {
+            Ok(counts) => counts,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        if counts.owners > 0 && counts.members > 0 {
+            continue;
+        }
+        orphaned.push(OrphanedGroup {
+            id: group.id.clone(),
+            display_name: group.display_name.clone().unwrap_or_default(),
+            mail: group.mail.clone().unwrap_or_default(),
+            group_type,
+            dynamic: group.group_types.iter().any(|group_type| group_type == "DynamicMembership"),
+            synced: group.on_premises_sync_enabled.unwrap_or(false),
+            created_date_time: group.created_date_time,
+            last_activity: activity.last_activity(&group.id),
+            owners: counts.owners,
+            members: counts.members,
+            recommend_deletion: false,
+        });
+    }
+    orphaned.sort_by_key(|group| group.display_name.to_lowercase());
+    Ok(Scan {
+        groups: orphaned,
+        total: groups.len(),
+        checked,
+        failed,
+        interrupted: checked < groups.len(),
+    })
+}
+
+// An empty group is safe to delete once it is older than the cutoff and had no activity since.
+// Groups synced from on-premises must be deleted there, and dynamic groups fill themselves
+// again when a user matches the rule, so neither is recommended.
+fn recommend_deletions(groups: &mut [OrphanedGroup], cutoff: DateTime<Utc>) {
+    for group in groups {
+        group.recommend_deletion = group.members == 0
+            && !group.synced
+            && !group.dynamic
+            && group.created_date_time.is_some_and(|created| created < cutoff)
+            && group.last_activity.is_none_or(|last| last < cutoff.date_naive());
+    }
+}
+
+fn reason(group: &OrphanedGroup) -> &'static str {
+    match (group.owners, group.members) {
+        (0, 0) => "no owners or members",
+        (0, _) => "no owners",
+        _ => "no members",
+    }
+}
+
+fn date(value: Option<NaiveDate>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+fn render_report(groups: &[OrphanedGroup], total: usize, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "groups": groups,
+                "totalGroups": total,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "id,displayName,mail,groupType,dynamic,synced,createdDateTime,lastActivity,owners,members,recommendDeletion")?;
+            for group in groups {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    group.id,
+                    csv::field(&group.display_name),
+                    csv::field(&group.mail),
+                    group.group_type,
+                    group.dynamic,
+                    group.synced,
+                    group
+                        .created_date_time
+                        .map(|created| created.to_rfc3339_opts(SecondsFormat::Secs, true))
+                        .unwrap_or_default(),
+                    group.last_activity.map(|last| last.to_string()).unwrap_or_default(),
+                    group.owners,
+                    group.members,
+                    group.recommend_deletion
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<30} {:<22} {:<11} {:<13} {:>6} {:>7}  NOTE",
+                "GROUP", "TYPE", "CREATED", "LAST ACTIVITY", "OWNERS", "MEMBERS"
+            )?;
+            for group in groups {
+                let mut notes = vec![reason(group)];
+                if group.dynamic {
+                    notes.push("dynamic");
+                }
+                if group.synced {
+                    notes.push("synced");
+                }
+                if group.recommend_deletion {
+                    notes.push("delete");
+                }
+                writeln!(
+                    out,
+                    "{:<30} {:<22} {:<11} {:<13} {:>6} {:>7}  {}",
+                    group.display_name,
+                    group.group_type,
+                    date(group.created_date_time.map(|created| created.date_naive())),
+                    date(group.last_activity),
+                    group.owners,
+                    group.members,
+                    notes.join(", ")
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} groups: {} without owners, {} without members, {} recommended for deletion",
+                groups.len(),
+                total,
+                groups.iter().filter(|group| group.owners == 0).count(),
+                groups.iter().filter(|group| group.members == 0).count(),
+                groups.iter().filter(|group| group.recommend_deletion).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn render_deletions(groups: &[OrphanedGroup]) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "id,displayName,mail,groupType,reason")?;
+    for group in groups.iter().filter(|group| group.recommend_deletion) {
+        writeln!(
+            out,
+            "{},{},{},{},{}",
+            group.id,
+            csv::field(&group.display_name),
+            csv::field(&group.mail),
+            group.group_type,
+            reason(group)
+        )?;
+    }
+    Ok(out)
+}
+
+fn write_deletions(path: &Path, groups: &[OrphanedGroup]) -> Result<()> {
+    std::fs::write(path, render_deletions(groups)?).with_context(|| format!("Failed to write {}", path.display()))?;
+    info!(
+        "Wrote {} recommended deletions to {}",
+        groups.iter().filter(|group| group.recommend_deletion).count(),
+        path.display()
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_orphaned_groups");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Group.Read.All", "Reports.Read.All"])?;
+
+    let phase = client.stats().phase("read activity report");
+    let activity = GroupActivity::load(&client).await?;
+    drop(phase);
+
+    let shutdown = service::shutdown_signal();
+    let mut scan = scan(&client, &activity, config.concurrency as usize, &shutdown).await?;
+    recommend_deletions(&mut scan.groups, Utc::now() - Duration::days(config.inactive_days));
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.groups, scan.total, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} groups; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if let Some(path) = &config.deletions {
+        write_deletions(path, &scan.groups)?;
+    }
+    if scan.failed > 0 {
+        warn!(
+            "The owners or members of {} of {} groups could not be read; they are missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} groups could not be read", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let activity = GroupActivity::load(&client).await.unwrap();
+        let mut scan = scan(&client, &activity, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.total, scan.checked, scan.failed, scan.interrupted), (8, 8, 0, false));
+        recommend_deletions(&mut scan.groups, "2026-07-17T00:00:00Z".parse().unwrap());
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.groups, scan.total, output, &Summary::fixture()));
+        golden::assert_matches(&golden::testdata("golden/deletions.csv"), &render_deletions(&scan.groups).unwrap());
+    }
+}
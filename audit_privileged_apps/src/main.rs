@@ -0,0 +1,396 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::apps::{self, AppRoleAssignment, Credential};
+use graph_common::audit::Severity;
+use graph_common::roles::{self, role_severity};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Write;
+
+// Application permissions that let an app make itself or anyone else a global administrator,
+// or switch off the controls protecting administrators.
+const TAKEOVER_PERMISSIONS: &[&str] = &[
+    "RoleManagement.ReadWrite.Directory",
+    "AppRoleAssignment.ReadWrite.All",
+    "Application.ReadWrite.All",
+    "Directory.ReadWrite.All",
+    "Policy.ReadWrite.ConditionalAccess",
+    "Policy.ReadWrite.AuthenticationMethod",
+    "UserAuthenticationMethod.ReadWrite.All",
+    "Domain.ReadWrite.All",
+];
+
+// Application permissions that read or change the data of every user.
+const DATA_PERMISSIONS: &[&str] = &[
+    "Mail.Read",
+    "Mail.ReadWrite",
+    "Mail.Send",
+    "full_access_as_app",
+    "Files.Read.All",
+    "Files.ReadWrite.All",
+    "Sites.ReadWrite.All",
+    "Sites.FullControl.All",
+    "User.ReadWrite.All",
+    "Group.ReadWrite.All",
+    "GroupMember.ReadWrite.All",
+];
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_privileged_apps")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports service principals holding privileged application permissions or directory roles, with their owners and credentials, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Application {
+    id: String,
+    #[serde(rename = "appId")]
+    app_id: String,
+    #[serde(rename = "passwordCredentials", default)]
+    password_credentials: Vec<Credential>,
+    #[serde(rename = "keyCredentials", default)]
+    key_credentials: Vec<Credential>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Owner {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct PrivilegedApp {
+    severity: Severity,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "appId")]
+    app_id: String,
+    #[serde(rename = "servicePrincipalType")]
+    service_principal_type: String,
+    publisher: String,
+    #[serde(rename = "appPermissions")]
+    app_permissions: Vec<String>,
+    #[serde(rename = "directoryRoles")]
+    directory_roles: Vec<String>,
+    owners: Vec<String>,
+    secrets: usize,
+    certificates: usize,
+}
+
+struct Scan {
+    apps: Vec<PrivilegedApp>,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn permission_severity(permission: &str) -> Option<Severity> {
+    if TAKEOVER_PERMISSIONS.contains(&permission) {
+        Some(Severity::High)
+    } else if DATA_PERMISSIONS.contains(&permission) {
+        Some(Severity::Medium)
+    } else {
+        None
+    }
+}
+
+// App registrations keyed by app id. Credentials added to the registration work for the
+// service principal as well, and are where most secrets live.
+async fn list_applications(client: &GraphClient) -> Result<HashMap<String, Application>> {
+    let applications: Vec<Application> = client
+        .path(&["applications"])
+        .select("id,appId,passwordCredentials,keyCredentials")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch applications")?;
+    Ok(applications
+        .into_iter()
+        .map(|application| (application.app_id.clone(), application))
+        .collect())
+}
+
+// Owners of the service principal and of the app registration; both can add credentials and
+// sign in as the app.
+async fn owners(client: &GraphClient, principal_id: &str, application_id: Option<&str>) -> Result<Vec<String>> {
+    let mut paths = vec![["servicePrincipals", principal_id, "owners"]];
+    if let Some(application_id) = application_id {
+        paths.push(["applications", application_id, "owners"]);
+    }
+    let mut names = Vec::new();
+    for path in paths {
+        let owners: Vec<Owner> = client
+            .path(&path)
+            .select("id,displayName,userPrincipalName")
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to fetch the owners of {}", principal_id))?;
+        for owner in owners {
+            let name = owner.user_principal_name.or(owner.display_name).unwrap_or(owner.id);
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+async fn scan(client: &GraphClient, tenant_id: &str, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list service principals");
+    let principals = apps::service_principals(client).await?;
+    let applications = list_applications(client).await?;
+    let directory_roles = roles::service_principal_role_assignments(client).await?;
+    drop(phase);
+
+    let mut resources: Vec<&str> = principals
+        .values()
+        .filter(|principal| !principal.app_roles.is_empty())
+        .map(|principal| principal.id.as_str())
+        .collect();
+    resources.sort();
+    info!("Reading the app role assignments of {} APIs", resources.len());
+    let phase = client.stats().phase("read app role assignments");
+    let results: Vec<Result<Vec<AppRoleAssignment>>> = stream::iter(&resources)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|resource_id| apps::assigned_to(client, resource_id))
+        .buffered(concurrency)
+        .collect()
+        .await;
+    drop(phase);
+    let mut interrupted = results.len() < resources.len();
+    let mut failed = 0;
+    let mut permissions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for result in results {
+        let assignments = match result {
+            Ok(assignments) => assignments,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        for assignment in assignments.into_iter().filter(|assignment| assignment.principal_type == "ServicePrincipal") {
+            let permission = apps::app_role_name(&principals, &assignment);
+            if permission_severity(&permission).is_some() {
+                permissions.entry(assignment.principal_id).or_default().push(permission);
+            }
+        }
+    }
+
+    let mut privileged: Vec<&String> = permissions.keys().chain(directory_roles.keys()).collect();
+    privileged.sort();
+    privileged.dedup();
+    let privileged: Vec<&apps::ServicePrincipal> = privileged.into_iter().filter_map(|id| principals.get(id)).collect();
+    info!("Reading the owners of {} privileged service principals", privileged.len());
+    let _phase = client.stats().phase("read owners");
+    let results: Vec<(&apps::ServicePrincipal, Result<Vec<String>>)> = stream::iter(&privileged)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|principal| async {
+            let application = applications.get(&principal.app_id).map(|application| application.id.as_str());
+            (*principal, owners(client, &principal.id, application).await)
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+    interrupted |= results.len() < privileged.len();
+
+    let mut privileged_apps = Vec::new();
+    for (principal, owners) in results {
+        let owners = match owners {
+            Ok(owners) => owners,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let mut app_permissions = permissions.get(&principal.id).cloned().unwrap_or_default();
+        app_permissions.sort_by_key(|permission| (std::cmp::Reverse(permission_severity(permission)), permission.clone()));
+        app_permissions.dedup();
+        let mut directory_roles = directory_roles.get(&principal.id).cloned().unwrap_or_default();
+        directory_roles.sort_by_key(|role| (std::cmp::Reverse(role_severity(role)), role.clone()));
+        let severity = app_permissions
+            .iter()
+            .filter_map(|permission| permission_severity(permission))
+            .chain(directory_roles.iter().map(|role| role_severity(role)))
+            .max()
+            .unwrap_or(Severity::Low);
+        let application = applications.get(&principal.app_id);
+        privileged_apps.push(PrivilegedApp {
+            severity,
+            display_name: principal.name(),
+            app_id: principal.app_id.clone(),
+            service_principal_type: principal.service_principal_type.clone().unwrap_or_default(),
+            publisher: principal.publisher(tenant_id),
+            app_permissions,
+            directory_roles,
+            owners,
+            secrets: principal.password_credentials.len()
+                + application.map_or(0, |application| application.password_credentials.len()),
+            certificates: principal.key_credentials.len()
+                + application.map_or(0, |application| application.key_credentials.len()),
+        });
+    }
+    privileged_apps.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.display_name.cmp(&b.display_name)));
+    Ok(Scan {
+        apps: privileged_apps,
+        failed,
+        interrupted,
+    })
+}
+
+fn list(values: &[String]) -> String {
+    if values.is_empty() {
+        "none".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn render_report(apps: &[PrivilegedApp], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "servicePrincipals": apps,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "severity,displayName,appId,servicePrincipalType,publisher,appPermissions,directoryRoles,owners,secrets,certificates")?;
+            for app in apps {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    app.severity,
+                    csv::field(&app.display_name),
+                    app.app_id,
+                    app.service_principal_type,
+                    csv::field(&app.publisher),
+                    csv::field(&app.app_permissions.join("; ")),
+                    csv::field(&app.directory_roles.join("; ")),
+                    csv::field(&app.owners.join("; ")),
+                    app.secrets,
+                    app.certificates
+                )?;
+            }
+        }
+        _ => {
+            for app in apps {
+                writeln!(
+                    out,
+                    "{:<7} {} ({}, {}) {}",
+                    app.severity.to_string(),
+                    app.display_name,
+                    app.publisher,
+                    app.service_principal_type,
+                    app.app_id
+                )?;
+                if !app.app_permissions.is_empty() {
+                    writeln!(out, "        permissions: {}", app.app_permissions.join(", "))?;
+                }
+                if !app.directory_roles.is_empty() {
+                    writeln!(out, "        roles: {}", app.directory_roles.join(", "))?;
+                }
+                writeln!(out, "        owners: {}", list(&app.owners))?;
+                writeln!(out, "        credentials: {} secrets, {} certificates", app.secrets, app.certificates)?;
+            }
+            if !apps.is_empty() {
+                writeln!(out)?;
+            }
+            writeln!(
+                out,
+                "{} service principals hold privileged access, {} of them high: {} without owners, {} with secrets or certificates",
+                apps.len(),
+                apps.iter().filter(|app| app.severity == Severity::High).count(),
+                apps.iter().filter(|app| app.owners.is_empty()).count(),
+                apps.iter().filter(|app| app.secrets + app.certificates > 0).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_privileged_apps");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Application.Read.All", "Directory.Read.All"])?;
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, &tenant_id, config.concurrency as usize, &shutdown).await?;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.apps, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted before all service principals were read; the report above is incomplete");
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!("{} requests failed; the affected service principals are missing from the report", scan.failed);
+        return Err(anyhow::anyhow!("{} requests failed", scan.failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let scan = scan(&client, "11111111-2222-4333-8444-555555555555", 2, &ShutdownSignal::never())
+            .await
+            .unwrap();
+        assert_eq!((scan.failed, scan.interrupted), (0, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.apps, output, &Summary::fixture()));
+    }
+}
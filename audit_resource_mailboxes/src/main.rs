@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::reports::MailboxUsage;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_resource_mailboxes")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists room and equipment mailboxes with their capacity, booking settings, delegates, and bookings, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u16).range(1..=365), help = "Days before and after today searched for bookings")]
+    days: u16,
+
+    #[arg(long, help = "Only list resources with an issue")]
+    flagged: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Room {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "emailAddress")]
+    email_address: Option<String>,
+    capacity: Option<u32>,
+    building: Option<String>,
+    #[serde(rename = "floorLabel")]
+    floor_label: Option<String>,
+    #[serde(rename = "floorNumber")]
+    floor_number: Option<i32>,
+    #[serde(rename = "bookingType")]
+    booking_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    value: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CalendarPermission {
+    #[serde(rename = "emailAddress")]
+    email_address: Option<EmailAddress>,
+    role: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmailAddress {
+    address: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Booking {
+    start: Option<DateTimeTimeZone>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DateTimeTimeZone {
+    #[serde(rename = "dateTime")]
+    date_time: String,
+}
+
+// One row per room or equipment mailbox.
+#[derive(Serialize, Debug)]
+struct ResourceRow {
+    #[serde(rename = "displayName")]
+    display_name: String,
+    email: String,
+    kind: &'static str,
+    building: String,
+    floor: String,
+    capacity: Option<u32>,
+    #[serde(rename = "bookingType")]
+    booking_type: String,
+    delegates: Vec<String>,
+    #[serde(rename = "lastBooked")]
+    last_booked: Option<NaiveDate>,
+    #[serde(rename = "nextBooked")]
+    next_booked: Option<NaiveDate>,
+    #[serde(rename = "lastMailboxActivity")]
+    last_mailbox_activity: Option<NaiveDate>,
+    issues: Vec<&'static str>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Rooms come from the places directory, which has their capacity and location; equipment
+// mailboxes, and rooms that were never added to it, are only known from the mailbox usage report.
+fn resources(rooms: &[Room], usage: &MailboxUsage) -> Vec<ResourceRow> {
+    let mut resources: BTreeMap<String, ResourceRow> = BTreeMap::new();
+    for room in rooms {
+        let Some(email) = room.email_address.as_deref() else { continue };
+        resources.insert(
+            email.to_lowercase(),
+            ResourceRow {
+                display_name: room.display_name.clone().unwrap_or_default(),
+                email: email.to_lowercase(),
+                kind: "room",
+                building: room.building.clone().unwrap_or_default(),
+                floor: room
+                    .floor_label
+                    .clone()
+                    .or_else(|| room.floor_number.map(|floor| floor.to_string()))
+                    .unwrap_or_default(),
+                capacity: room.capacity,
+                booking_type: room.booking_type.clone().unwrap_or_default(),
+                delegates: Vec::new(),
+                last_booked: None,
+                next_booked: None,
+                last_mailbox_activity: None,
+                issues: if room.capacity.unwrap_or(0) == 0 { vec!["no capacity"] } else { Vec::new() },
+            },
+        );
+    }
+    for mailbox in usage.mailboxes() {
+        let kind = match mailbox.recipient_type.to_lowercase().as_str() {
+            "room" => "room",
+            "equipment" => "equipment",
+            _ => continue,
+        };
+        let row = resources
+            .entry(mailbox.user_principal_name.to_lowercase())
+            .or_insert_with(|| ResourceRow {
+                display_name: mailbox.display_name.clone(),
+                email: mailbox.user_principal_name.to_lowercase(),
+                kind,
+                building: String::new(),
+                floor: String::new(),
+                capacity: None,
+                booking_type: String::new(),
+                delegates: Vec::new(),
+                last_booked: None,
+                next_booked: None,
+                last_mailbox_activity: None,
+                issues: if kind == "room" { vec!["not in room list"] } else { Vec::new() },
+            });
+        row.last_mailbox_activity = mailbox.last_activity;
+    }
+    resources.into_values().collect()
+}
+
+fn booking_date(page: Result<Page<Booking>>) -> Result<Option<NaiveDate>> {
+    Ok(page?
+        .value
+        .first()
+        .and_then(|booking| booking.start.as_ref())
+        .and_then(|start| NaiveDate::parse_from_str(start.date_time.get(..10)?, "%Y-%m-%d").ok()))
+}
+
+fn calendar_view(client: &GraphClient, email: &str, start: NaiveDate, end: NaiveDate, order: &str) -> String {
+    client
+        .user(email)
+        .resource()
+        .segment("calendarView")
+        .query("startDateTime", &format!("{}T00:00:00", start))
+        .query("endDateTime", &format!("{}T00:00:00", end))
+        .select("start")
+        .query("$orderby", order)
+        .top(1)
+        .relative_url()
+}
+
+// Delegates, the latest booking before today, and the first booking from today on are read
+// through $batch. The booking policy (auto-accept, booking window, conflicts) is only exposed
+// by Exchange Online PowerShell, so the places bookingType and the delegates are the hints here.
+async fn inspect(client: &GraphClient, rows: &mut [ResourceRow], today: NaiveDate, days: i64) -> Result<()> {
+    let phase = client.stats().phase("read delegates");
+    let urls: Vec<String> = rows
+        .iter()
+        .map(|row| client.user(&row.email).calendar_permissions().relative_url())
+        .collect();
+    let permissions: Vec<Result<Page<CalendarPermission>>> = client.batch_get(&urls).await?;
+    drop(phase);
+
+    let _phase = client.stats().phase("read bookings");
+    let (from, until) = (today - Duration::days(days), today + Duration::days(days));
+    let urls: Vec<String> = rows
+        .iter()
+        .map(|row| calendar_view(client, &row.email, from, today, "start/dateTime desc"))
+        .collect();
+    let last: Vec<Result<Page<Booking>>> = client.batch_get(&urls).await?;
+    let urls: Vec<String> = rows
+        .iter()
+        .map(|row| calendar_view(client, &row.email, today, until, "start/dateTime"))
+        .collect();
+    let next: Vec<Result<Page<Booking>>> = client.batch_get(&urls).await?;
+
+    for (((row, permissions), last), next) in rows.iter_mut().zip(permissions).zip(last).zip(next) {
+        match permissions {
+            Ok(page) => {
+                row.delegates = page
+                    .value
+                    .iter()
+                    .filter(|permission| permission.role.as_deref().is_some_and(|role| role.starts_with("delegate")))
+                    .filter_map(|permission| permission.email_address.as_ref()?.address.as_deref())
+                    .map(str::to_lowercase)
+                    .collect();
+                if row.booking_type == "reserved" && row.delegates.is_empty() {
+                    row.issues.push("reserved without delegates");
+                }
+            }
+            Err(e) => warn!("Failed to read the delegates of {}: {:#}", row.email, e),
+        }
+        match (booking_date(last), booking_date(next)) {
+            (Ok(last), Ok(next)) => {
+                row.last_booked = last;
+                row.next_booked = next;
+                if last.is_none() && next.is_none() {
+                    row.issues.push("no bookings");
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to read the bookings of {}: {:#}", row.email, e);
+                row.issues.push("calendar not readable");
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn collect(client: &GraphClient, today: NaiveDate, days: i64) -> Result<Vec<ResourceRow>> {
+    let phase = client.stats().phase("list rooms");
+    let rooms: Vec<Room> = client
+        .path(&["places", "microsoft.graph.room"])
+        .get_all()
+        .await
+        .context("Failed to list rooms")?;
+    drop(phase);
+    let phase = client.stats().phase("read usage report");
+    let usage = MailboxUsage::load(client).await?;
+    if !usage.is_empty() && usage.mailboxes().all(|mailbox| !mailbox.user_principal_name.contains('@')) {
+        warn!("The mailbox usage report conceals user names, so equipment mailboxes and their activity are missing; turn off concealed names in the Microsoft 365 admin center reports settings to see them");
+    }
+    drop(phase);
+
+    let mut rows = resources(&rooms, &usage);
+    info!("Checking {} room and equipment mailboxes", rows.len());
+    inspect(client, &mut rows, today, days).await?;
+    rows.sort_by_key(|row| (row.kind != "room", row.building.to_lowercase(), row.display_name.to_lowercase()));
+    Ok(rows)
+}
+
+fn date(value: Option<NaiveDate>) -> String {
+    value.map(|date| date.to_string()).unwrap_or_default()
+}
+
+fn render_report(rows: &[&ResourceRow], all_rows: &[ResourceRow], days: u16, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let rooms = all_rows.iter().filter(|row| row.kind == "room").count();
+    let unused = all_rows.iter().filter(|row| row.issues.contains(&"no bookings")).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "resources": rows,
+                "rooms": rooms,
+                "equipment": all_rows.len() - rooms,
+                "noBookings": unused,
+                "bookingWindowDays": days,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "displayName,email,kind,building,floor,capacity,bookingType,delegates,lastBooked,nextBooked,lastMailboxActivity,issues"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.display_name),
+                    csv::field(&row.email),
+                    row.kind,
+                    csv::field(&row.building),
+                    csv::field(&row.floor),
+                    row.capacity.map(|capacity| capacity.to_string()).unwrap_or_default(),
+                    row.booking_type,
+                    csv::field(&row.delegates.join(";")),
+                    date(row.last_booked),
+                    date(row.next_booked),
+                    date(row.last_mailbox_activity),
+                    csv::field(&row.issues.join(";"))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<24} {:<30} {:<9} {:<16} {:>5} {:<8} {:>9} {:<11} {:<11} ISSUES",
+                "NAME", "MAILBOX", "KIND", "LOCATION", "SEATS", "BOOKING", "DELEGATES", "LAST", "NEXT"
+            )?;
+            for row in rows {
+                let location = match (row.building.is_empty(), row.floor.is_empty()) {
+                    (true, _) => "-".to_string(),
+                    (false, true) => row.building.clone(),
+                    (false, false) => format!("{} / {}", row.building, row.floor),
+                };
+                let line = format!(
+                    "{:<24} {:<30} {:<9} {:<16} {:>5} {:<8} {:>9} {:<11} {:<11} {}",
+                    row.display_name,
+                    row.email,
+                    row.kind,
+                    location,
+                    row.capacity.map(|capacity| capacity.to_string()).unwrap_or_else(|| "-".to_string()),
+                    if row.booking_type.is_empty() { "-" } else { &row.booking_type },
+                    row.delegates.len(),
+                    row.last_booked.map(|date| date.to_string()).unwrap_or_else(|| "-".to_string()),
+                    row.next_booked.map(|date| date.to_string()).unwrap_or_else(|| "-".to_string()),
+                    row.issues.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} rooms and {} equipment mailboxes, {} not booked in the {} days before or after today",
+                rooms,
+                all_rows.len() - rooms,
+                unused,
+                days
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_resource_mailboxes");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Place.Read.All", "Reports.Read.All", "Calendars.Read"])?;
+
+    let rows = collect(&client, Utc::now().date_naive(), config.days as i64).await?;
+    let listed: Vec<&ResourceRow> = rows
+        .iter()
+        .filter(|row| !config.flagged || !row.issues.is_empty())
+        .collect();
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&listed, &rows, config.days, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let rows = collect(&client, today, 90).await.unwrap();
+        let listed: Vec<&ResourceRow> = rows.iter().collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&listed, &rows, 90, output, &Summary::fixture()));
+    }
+}
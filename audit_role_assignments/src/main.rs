@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::audit::Severity;
+use graph_common::roles::role_severity;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_role_assignments")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports active and PIM-eligible directory role assignments per role, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScheduleInstance {
+    #[serde(rename = "principalId")]
+    principal_id: String,
+    #[serde(rename = "directoryScopeId")]
+    directory_scope_id: Option<String>,
+    #[serde(rename = "endDateTime")]
+    end_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "assignmentType")]
+    assignment_type: Option<String>,
+    #[serde(rename = "memberType")]
+    member_type: Option<String>,
+    principal: Option<Principal>,
+    #[serde(rename = "roleDefinition")]
+    role_definition: Option<RoleDefinition>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Principal {
+    #[serde(rename = "@odata.type")]
+    odata_type: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RoleDefinition {
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Serialize, Debug)]
+struct RoleAssignment {
+    role: String,
+    severity: Severity,
+    principal: String,
+    #[serde(rename = "principalId")]
+    principal_id: String,
+    #[serde(rename = "principalType")]
+    principal_type: String,
+    // "active" for permanent or time-bound assignments, "activated" for eligible assignments
+    // that are activated right now, and "eligible".
+    assignment: &'static str,
+    #[serde(rename = "memberType")]
+    member_type: String,
+    scope: String,
+    #[serde(rename = "endDateTime")]
+    end_date_time: Option<DateTime<Utc>>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn schedule_instances(client: &GraphClient, kind: &str) -> Result<Vec<ScheduleInstance>> {
+    client
+        .path(&["roleManagement", "directory", kind])
+        .query("$expand", "principal,roleDefinition")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch {}", kind))
+}
+
+fn assignment(instance: ScheduleInstance, eligible: bool) -> RoleAssignment {
+    let role = instance
+        .role_definition
+        .map(|role| role.display_name)
+        .unwrap_or_else(|| "unknown role".to_string());
+    let principal = instance.principal.as_ref();
+    let principal_type = principal
+        .and_then(|principal| principal.odata_type.as_deref())
+        .and_then(|odata_type| odata_type.strip_prefix("#microsoft.graph."))
+        .unwrap_or("unknown")
+        .to_string();
+    let name = principal
+        .and_then(|principal| principal.user_principal_name.clone().or(principal.display_name.clone()))
+        .unwrap_or_else(|| instance.principal_id.clone());
+    RoleAssignment {
+        severity: role_severity(&role),
+        role,
+        principal: name,
+        principal_id: instance.principal_id,
+        principal_type,
+        assignment: match (eligible, instance.assignment_type.as_deref()) {
+            (true, _) => "eligible",
+            (false, Some("Activated")) => "activated",
+            (false, _) => "active",
+        },
+        member_type: instance.member_type.unwrap_or_else(|| "Direct".to_string()).to_lowercase(),
+        scope: match instance.directory_scope_id.as_deref() {
+            None | Some("/") => "tenant".to_string(),
+            Some(scope) => scope.to_string(),
+        },
+        end_date_time: instance.end_date_time,
+    }
+}
+
+async fn list_assignments(client: &GraphClient) -> Result<Vec<RoleAssignment>> {
+    let active = schedule_instances(client, "roleAssignmentScheduleInstances").await?;
+    let eligible = schedule_instances(client, "roleEligibilityScheduleInstances").await?;
+    let mut assignments: Vec<RoleAssignment> = active
+        .into_iter()
+        .map(|instance| assignment(instance, false))
+        .chain(eligible.into_iter().map(|instance| assignment(instance, true)))
+        .collect();
+    assignments.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.role.cmp(&b.role))
+            .then_with(|| a.assignment.cmp(b.assignment))
+            .then_with(|| a.principal.cmp(&b.principal))
+    });
+    Ok(assignments)
+}
+
+fn end_date(value: Option<DateTime<Utc>>) -> String {
+    value.map_or_else(|| "permanent".to_string(), |end| end.format("%Y-%m-%d").to_string())
+}
+
+fn render_report(assignments: &[RoleAssignment], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "assignments": assignments,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "role,severity,principal,principalId,principalType,assignment,memberType,scope,endDateTime")?;
+            for assignment in assignments {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv::field(&assignment.role),
+                    assignment.severity,
+                    csv::field(&assignment.principal),
+                    assignment.principal_id,
+                    assignment.principal_type,
+                    assignment.assignment,
+                    assignment.member_type,
+                    csv::field(&assignment.scope),
+                    assignment
+                        .end_date_time
+                        .map(|end| end.to_rfc3339_opts(SecondsFormat::Secs, true))
+                        .unwrap_or_default()
+                )?;
+            }
+        }
+        _ => {
+            let mut by_role: BTreeMap<(std::cmp::Reverse<Severity>, &str), Vec<&RoleAssignment>> = BTreeMap::new();
+            for assignment in assignments {
+                by_role
+                    .entry((std::cmp::Reverse(assignment.severity), &assignment.role))
+                    .or_default()
+                    .push(assignment);
+            }
+            for ((severity, role), assignments) in &by_role {
+                let count = |kind: &str| assignments.iter().filter(|assignment| assignment.assignment == kind).count();
+                writeln!(
+                    out,
+                    "{} ({}): {} active, {} activated, {} eligible",
+                    role,
+                    severity.0,
+                    count("active"),
+                    count("activated"),
+                    count("eligible")
+                )?;
+                for assignment in assignments {
+                    let scope = if assignment.scope == "tenant" { String::new() } else { format!(" scope {}", assignment.scope) };
+                    writeln!(
+                        out,
+                        "    {:<10} {:<10} {:<17} {:<7} {}{}",
+                        assignment.assignment,
+                        end_date(assignment.end_date_time),
+                        assignment.principal_type,
+                        assignment.member_type,
+                        assignment.principal,
+                        scope
+                    )?;
+                }
+            }
+            if !by_role.is_empty() {
+                writeln!(out)?;
+            }
+            writeln!(
+                out,
+                "{} assignments in {} roles: {} active ({} permanent), {} activated, {} eligible",
+                assignments.len(),
+                by_role.len(),
+                assignments.iter().filter(|assignment| assignment.assignment == "active").count(),
+                assignments
+                    .iter()
+                    .filter(|assignment| assignment.assignment == "active" && assignment.end_date_time.is_none())
+                    .count(),
+                assignments.iter().filter(|assignment| assignment.assignment == "activated").count(),
+                assignments.iter().filter(|assignment| assignment.assignment == "eligible").count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_role_assignments");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["RoleManagement.Read.Directory"])?;
+
+    let phase = client.stats().phase("list role assignments");
+    let assignments = list_assignments(&client).await?;
+    drop(phase);
+
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&assignments, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let assignments = list_assignments(&client).await.unwrap();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&assignments, output, &Summary::fixture()));
+    }
+}
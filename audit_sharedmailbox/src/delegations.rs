@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use graph_common::csv::Table;
+use graph_common::GraphClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Calendar roles below read only show free/busy times, so they are not reported.
+const CALENDAR_ACCESS_ROLES: &[&str] = &[
+    "read",
+    "write",
+    "delegateWithoutPrivateEventAccess",
+    "delegateWithPrivateEventAccess",
+    "custom",
+];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Delegation {
+    pub trustee: String,
+    pub right: String,
+    pub source: String,
+    #[serde(rename = "trusteeStatus")]
+    pub trustee_status: String,
+}
+
+impl Delegation {
+    pub fn is_orphaned(&self) -> bool {
+        self.trustee_status != "active"
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CalendarPermission {
+    #[serde(rename = "emailAddress")]
+    email_address: Option<EmailAddress>,
+    role: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmailAddress {
+    address: Option<String>,
+}
+
+// Whether each account is enabled, keyed by lowercase UPN and mail address, so a trustee can
+// be matched by either.
+pub struct Directory {
+    accounts: HashMap<String, bool>,
+}
+
+impl Directory {
+    pub fn new<'a>(accounts: impl Iterator<Item = (&'a str, Option<&'a str>, bool)>) -> Self {
+        let mut map = HashMap::new();
+        for (upn, mail, enabled) in accounts {
+            map.insert(upn.to_lowercase(), enabled);
+            if let Some(mail) = mail {
+                map.insert(mail.to_lowercase(), enabled);
+            }
+        }
+        Self { accounts: map }
+    }
+
+    // Trustees of deleted accounts show up as a SID or a bare display name, so anything not
+    // in the directory is reported rather than skipped.
+    pub fn status(&self, trustee: &str) -> &'static str {
+        match self.accounts.get(&trustee.to_lowercase()) {
+            Some(true) => "active",
+            Some(false) => "disabled",
+            None => "notFound",
+        }
+    }
+
+    pub fn delegation(&self, trustee: &str, right: &str, source: &str) -> Delegation {
+        Delegation {
+            trustee: trustee.to_string(),
+            right: right.to_string(),
+            source: source.to_string(),
+            trustee_status: self.status(trustee).to_string(),
+        }
+    }
+}
+
+// Trustees and roles of the mailbox's calendar. The "My Organization" default entry has no
+// address and is skipped. Needs Calendars.Read.
+pub async fn calendar_delegates(client: &GraphClient, upn: &str) -> Result<Vec<(String, String)>> {
+    let permissions: Vec<CalendarPermission> = client
+        .user(upn)
+        .calendar_permissions()
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch calendar permissions for {}", upn))?;
+    Ok(permissions
+        .into_iter()
+        .filter(|permission| CALENDAR_ACCESS_ROLES.contains(&permission.role.as_str()))
+        .filter_map(|permission| {
+            let address = permission.email_address?.address?;
+            Some((address.to_lowercase(), format!("Calendar:{}", permission.role)))
+        })
+        .collect())
+}
+
+// FullAccess, SendAs, and SendOnBehalf are Exchange Online permissions that Graph does not
+// expose, so they come from a CSV exported with Exchange Online PowerShell: one row per
+// mailbox, trustee, and access right.
+pub struct PermissionExport {
+    entries: HashMap<String, Vec<(String, String)>>,
+}
+
+impl PermissionExport {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let table = Table::parse(content.trim_start_matches('\u{feff}'))?;
+        let mailbox = table
+            .column(&["Mailbox", "Identity", "UserPrincipalName"])
+            .context("The permission export has no Mailbox column")?;
+        let trustee = table
+            .column(&["Trustee", "User"])
+            .context("The permission export has no Trustee column")?;
+        let rights = table
+            .column(&["AccessRights", "Right"])
+            .context("The permission export has no AccessRights column")?;
+
+        let mut entries: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for record in &table.records {
+            let trustee = record.get(trustee);
+            if trustee.is_empty() || trustee.eq_ignore_ascii_case("NT AUTHORITY\\SELF") {
+                continue;
+            }
+            for right in record.get(rights).split([',', ';']).map(str::trim).filter(|right| !right.is_empty()) {
+                entries
+                    .entry(record.get(mailbox).to_lowercase())
+                    .or_default()
+                    .push((trustee.to_string(), right.to_string()));
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn for_mailbox(&self, upn: &str) -> &[(String, String)] {
+        self.entries
+            .get(&upn.to_lowercase())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
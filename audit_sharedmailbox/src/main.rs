@@ -0,0 +1,597 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::licenses::SkuCatalog;
+use graph_common::reports::MailboxUsage;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, mail, roles, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+mod delegations;
+use delegations::{Delegation, Directory, PermissionExport};
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_sharedmailbox")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports the licenses, sign-in status, admin roles, forwarding, and last sign-in of every shared mailbox using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value = "mailbox-settings", value_parser = ["mailbox-settings", "usage-report"], help = "How shared mailboxes are found: a mailboxSettings request per user, or the mailbox usage report with per-user requests only for mailboxes missing from it")]
+    discovery: String,
+
+    #[arg(long, default_value_t = 30, value_name = "DAYS", help = "Flag shared mailboxes signed in to interactively within this many days")]
+    sign_in_days: u32,
+
+    #[arg(long, help = "Report who has delegated access to each shared mailbox and flag trustees whose account is disabled or gone")]
+    delegations: bool,
+
+    #[arg(long, requires = "delegations", value_name = "FILE", help = "CSV of FullAccess, SendAs, and SendOnBehalf permissions exported from Exchange Online")]
+    permissions_export: Option<PathBuf>,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of mailboxes read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "assignedLicenses", default)]
+    assigned_licenses: Vec<License>,
+    #[serde(rename = "signInActivity")]
+    sign_in_activity: Option<SignInActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct License {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignInActivity {
+    #[serde(rename = "lastSignInDateTime")]
+    last_sign_in_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastNonInteractiveSignInDateTime")]
+    last_non_interactive_sign_in_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MailboxSettings {
+    #[serde(rename = "userPurpose")]
+    user_purpose: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SharedMailbox {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "signInBlocked")]
+    sign_in_blocked: bool,
+    licenses: Vec<String>,
+    #[serde(rename = "adminRoles")]
+    admin_roles: Vec<String>,
+    #[serde(rename = "forwardsTo")]
+    forwards_to: Vec<String>,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<DateTime<Utc>>,
+    #[serde(rename = "lastNonInteractiveSignIn")]
+    last_non_interactive_sign_in: Option<DateTime<Utc>>,
+    #[serde(rename = "recentInteractiveSignIn")]
+    recent_interactive_sign_in: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delegations: Option<Vec<Delegation>>,
+}
+
+struct ScanOptions<'a> {
+    discovery: &'a str,
+    concurrency: usize,
+    delegations: bool,
+    permissions_export: Option<&'a PermissionExport>,
+}
+
+// What is read from a mailbox once it is known to be shared.
+struct MailboxDetails {
+    forwards_to: Vec<String>,
+    calendar_delegates: Vec<(String, String)>,
+}
+
+struct Scan {
+    mailboxes: Vec<SharedMailbox>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Every property the report needs comes from this one listing, so the tenant is enumerated
+// once. signInActivity is only returned to tenants with Microsoft Entra ID P1 or P2. Users
+// without a mailbox are kept to resolve delegation trustees.
+async fn list_users(client: &GraphClient) -> Result<Vec<User>> {
+    client
+        .users()
+        .select("id,userPrincipalName,displayName,mail,accountEnabled,assignedLicenses,signInActivity")
+        .get_all()
+        .await
+        .context("Failed to fetch users")
+}
+
+// Only enabled inbox rules forward mail. Mailbox-level forwarding (ForwardingSmtpAddress) is
+// not exposed by Graph.
+async fn forwarding_addresses(client: &GraphClient, upn: &str) -> Result<Vec<String>> {
+    let mut addresses = Vec::new();
+    for rule in mail::list_inbox_rules(client, upn).await? {
+        if rule["isEnabled"].as_bool() != Some(true) {
+            continue;
+        }
+        for address in mail::forwarding_recipients(&rule) {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+// `shared` is what the usage report says about the mailbox; mailboxes missing from it are
+// looked up in their mailbox settings. Inbox rules and calendar permissions are only read
+// once a mailbox is known to be shared.
+async fn read_mailbox(
+    client: &GraphClient,
+    upn: &str,
+    shared: Option<bool>,
+    delegations: bool,
+) -> Result<Option<MailboxDetails>> {
+    let shared = match shared {
+        Some(shared) => shared,
+        None => {
+            let settings: MailboxSettings = client
+                .user(upn)
+                .mailbox_settings()
+                .get_json()
+                .await
+                .with_context(|| format!("Failed to fetch mailbox settings for {}", upn))?;
+            settings.user_purpose.as_deref() == Some("shared")
+        }
+    };
+    if !shared {
+        return Ok(None);
+    }
+    let calendar_delegates = if delegations {
+        delegations::calendar_delegates(client, upn).await?
+    } else {
+        Vec::new()
+    };
+    Ok(Some(MailboxDetails {
+        forwards_to: forwarding_addresses(client, upn).await?,
+        calendar_delegates,
+    }))
+}
+
+async fn mailbox_usage(client: &GraphClient, users: &[&User]) -> Result<MailboxUsage> {
+    let _phase = client.stats().phase("read usage report");
+    let usage = MailboxUsage::load(client).await?;
+    let classified = users
+        .iter()
+        .filter(|user| usage.is_shared(&user.user_principal_name).is_some())
+        .count();
+    if classified == 0 && !usage.is_empty() {
+        warn!("The mailbox usage report conceals user names, so every mailbox is looked up individually; turn off concealed names in the Microsoft 365 admin center reports settings to use it");
+    }
+    info!(
+        "The mailbox usage report covers {} of {} users; looking up the mailbox settings of the other {}",
+        classified,
+        users.len(),
+        users.len() - classified
+    );
+    Ok(usage)
+}
+
+async fn scan(client: &GraphClient, options: &ScanOptions<'_>, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list users");
+    let skus = SkuCatalog::load(client).await?;
+    let all_users = list_users(client).await?;
+    drop(phase);
+    let directory = Directory::new(all_users.iter().map(|user| {
+        (user.user_principal_name.as_str(), user.mail.as_deref(), user.account_enabled != Some(false))
+    }));
+    let users: Vec<&User> = all_users.iter().filter(|user| user.mail.is_some()).collect();
+
+    let usage = match options.discovery {
+        "usage-report" => Some(mailbox_usage(client, &users).await?),
+        _ => None,
+    };
+
+    let phase = client.stats().phase("read role members");
+    let roles = roles::role_assignments(client).await?;
+    drop(phase);
+    info!("Checking the mailboxes of {} users", users.len());
+
+    let _phase = client.stats().phase("read mailboxes");
+    let results: Vec<(&User, Result<Option<MailboxDetails>>)> = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|&user| {
+            let shared = usage.as_ref().and_then(|usage| usage.is_shared(&user.user_principal_name));
+            async move {
+                let details = read_mailbox(client, &user.user_principal_name, shared, options.delegations).await;
+                (user, details)
+            }
+        })
+        .buffered(options.concurrency)
+        .collect()
+        .await;
+
+    let mut mailboxes = Vec::new();
+    let mut failed = 0;
+    for (user, result) in results.iter() {
+        match result {
+            Ok(Some(details)) => {
+                debug!("{} is a shared mailbox", user.user_principal_name);
+                let delegations = options.delegations.then(|| {
+                    let exported = options
+                        .permissions_export
+                        .map(|export| export.for_mailbox(&user.user_principal_name))
+                        .unwrap_or_default();
+                    details
+                        .calendar_delegates
+                        .iter()
+                        .map(|(trustee, right)| directory.delegation(trustee, right, "calendar"))
+                        .chain(exported.iter().map(|(trustee, right)| directory.delegation(trustee, right, "export")))
+                        .collect::<Vec<_>>()
+                });
+                for delegation in delegations.iter().flatten().filter(|delegation| delegation.is_orphaned()) {
+                    warn!(
+                        "{} grants {} to {}, whose account is {}",
+                        user.user_principal_name,
+                        delegation.right,
+                        delegation.trustee,
+                        trustee_status(delegation)
+                    );
+                }
+                mailboxes.push(SharedMailbox {
+                    user_principal_name: user.user_principal_name.clone(),
+                    display_name: user.display_name.clone().unwrap_or_default(),
+                    sign_in_blocked: user.account_enabled == Some(false),
+                    licenses: user
+                        .assigned_licenses
+                        .iter()
+                        .map(|license| skus.display_name(&license.sku_id))
+                        .collect(),
+                    admin_roles: roles.get(&user.id).cloned().unwrap_or_default(),
+                    forwards_to: details.forwards_to.clone(),
+                    last_sign_in: user
+                        .sign_in_activity
+                        .as_ref()
+                        .and_then(|activity| activity.last_sign_in_date_time),
+                    last_non_interactive_sign_in: user
+                        .sign_in_activity
+                        .as_ref()
+                        .and_then(|activity| activity.last_non_interactive_sign_in_date_time),
+                    recent_interactive_sign_in: false,
+                    delegations,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(Scan {
+        mailboxes,
+        checked: results.len(),
+        failed,
+        interrupted: results.len() < users.len(),
+    })
+}
+
+// Nobody should sign in to a shared mailbox interactively; it is opened through the
+// delegated access of its members. A recent interactive sign-in means someone holds its
+// password, which is a common attacker foothold.
+fn flag_recent_sign_ins(mailboxes: &mut [SharedMailbox], cutoff: DateTime<Utc>) {
+    for mailbox in mailboxes {
+        mailbox.recent_interactive_sign_in = mailbox.last_sign_in.is_some_and(|last| last >= cutoff);
+        if mailbox.recent_interactive_sign_in {
+            warn!(
+                "{} was signed in to interactively on {}",
+                mailbox.user_principal_name,
+                timestamp(mailbox.last_sign_in)
+            );
+        }
+    }
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value
+        .map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn trustee_status(delegation: &Delegation) -> &'static str {
+    match delegation.trustee_status.as_str() {
+        "disabled" => "disabled",
+        "notFound" => "not in the directory",
+        _ => "active",
+    }
+}
+
+fn list_or_dash(values: &[String]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(", ")
+    }
+}
+
+fn render_report(mailboxes: &[SharedMailbox], output: &str, sign_in_days: u32, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({ "sharedMailboxes": mailboxes, "runStats": run_stats }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            let delegations = mailboxes.iter().any(|mailbox| mailbox.delegations.is_some());
+            writeln!(
+                out,
+                "userPrincipalName,displayName,signInBlocked,licenses,adminRoles,forwardsTo,lastSignIn,lastNonInteractiveSignIn,recentInteractiveSignIn{}",
+                if delegations { ",delegations" } else { "" }
+            )?;
+            for mailbox in mailboxes {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}{}",
+                    csv::field(&mailbox.user_principal_name),
+                    csv::field(&mailbox.display_name),
+                    mailbox.sign_in_blocked,
+                    csv::field(&mailbox.licenses.join("; ")),
+                    csv::field(&mailbox.admin_roles.join("; ")),
+                    csv::field(&mailbox.forwards_to.join("; ")),
+                    timestamp(mailbox.last_sign_in),
+                    timestamp(mailbox.last_non_interactive_sign_in),
+                    mailbox.recent_interactive_sign_in,
+                    match &mailbox.delegations {
+                        Some(delegations) => format!(
+                            ",{}",
+                            csv::field(
+                                &delegations
+                                    .iter()
+                                    .map(|delegation| format!(
+                                        "{} {} ({})",
+                                        delegation.trustee, delegation.right, delegation.trustee_status
+                                    ))
+                                    .collect::<Vec<_>>()
+                                    .join("; ")
+                            )
+                        ),
+                        None => String::new(),
+                    }
+                )?;
+            }
+        }
+        _ => {
+            for mailbox in mailboxes {
+                let last_sign_in = match mailbox.last_sign_in {
+                    Some(_) if mailbox.recent_interactive_sign_in => {
+                        format!("{}  (within {} days)", timestamp(mailbox.last_sign_in), sign_in_days)
+                    }
+                    Some(_) => timestamp(mailbox.last_sign_in),
+                    None => "never".to_string(),
+                };
+                let last_non_interactive = match mailbox.last_non_interactive_sign_in {
+                    Some(_) => timestamp(mailbox.last_non_interactive_sign_in),
+                    None => "never".to_string(),
+                };
+                writeln!(out, "{} ({})", mailbox.user_principal_name, mailbox.display_name)?;
+                writeln!(
+                    out,
+                    "  Sign-in:               {}",
+                    if mailbox.sign_in_blocked { "blocked" } else { "enabled" }
+                )?;
+                writeln!(out, "  Last interactive:      {}", last_sign_in)?;
+                writeln!(out, "  Last non-interactive:  {}", last_non_interactive)?;
+                writeln!(out, "  Licenses:              {}", list_or_dash(&mailbox.licenses))?;
+                writeln!(out, "  Admin roles:           {}", list_or_dash(&mailbox.admin_roles))?;
+                writeln!(out, "  Forwards to:           {}", list_or_dash(&mailbox.forwards_to))?;
+                if let Some(delegations) = &mailbox.delegations {
+                    if delegations.is_empty() {
+                        writeln!(out, "  Delegates:             -")?;
+                    }
+                    for (index, delegation) in delegations.iter().enumerate() {
+                        writeln!(
+                            out,
+                            "  {:<23}{} {} ({}){}",
+                            if index == 0 { "Delegates:" } else { "" },
+                            delegation.trustee,
+                            delegation.right,
+                            delegation.source,
+                            if delegation.is_orphaned() {
+                                format!(", account {}", trustee_status(delegation))
+                            } else {
+                                String::new()
+                            }
+                        )?;
+                    }
+                }
+                writeln!(out)?;
+            }
+            let count = |check: fn(&SharedMailbox) -> bool| mailboxes.iter().filter(|mailbox| check(mailbox)).count();
+            writeln!(
+                out,
+                "{} shared mailboxes: {} can be signed in to, {} signed in to interactively in the last {} days, {} hold licenses, {} hold admin roles, {} forward mail",
+                mailboxes.len(),
+                count(|mailbox| !mailbox.sign_in_blocked),
+                count(|mailbox| mailbox.recent_interactive_sign_in),
+                sign_in_days,
+                count(|mailbox| !mailbox.licenses.is_empty()),
+                count(|mailbox| !mailbox.admin_roles.is_empty()),
+                count(|mailbox| !mailbox.forwards_to.is_empty())
+            )?;
+            if mailboxes.iter().any(|mailbox| mailbox.delegations.is_some()) {
+                let orphaned = mailboxes
+                    .iter()
+                    .flat_map(|mailbox| mailbox.delegations.iter().flatten())
+                    .filter(|delegation| delegation.is_orphaned())
+                    .count();
+                writeln!(out, "{} delegations to disabled accounts or accounts not in the directory", orphaned)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_sharedmailbox");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec!["Directory.Read.All", "MailboxSettings.Read", "AuditLog.Read.All"];
+    if config.discovery == "usage-report" {
+        required.push("Reports.Read.All");
+    }
+    if config.delegations {
+        required.push("Calendars.Read");
+    }
+    client.require_permissions(&required)?;
+
+    let permissions_export = config
+        .permissions_export
+        .as_deref()
+        .map(PermissionExport::load)
+        .transpose()?;
+    if config.delegations && permissions_export.is_none() {
+        warn!("FullAccess, SendAs, and SendOnBehalf permissions are not available through Graph, so only calendar delegates are reported; pass --permissions-export to include them");
+    }
+
+    let shutdown = service::shutdown_signal();
+    let options = ScanOptions {
+        discovery: &config.discovery,
+        concurrency: config.concurrency as usize,
+        delegations: config.delegations,
+        permissions_export: permissions_export.as_ref(),
+    };
+    let mut scan = scan(&client, &options, &shutdown).await?;
+    flag_recent_sign_ins(&mut scan.mailboxes, Utc::now() - chrono::Duration::days(config.sign_in_days.into()));
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.mailboxes, &config.output, config.sign_in_days, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} users; the report above is incomplete", scan.checked);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "{} of {} mailboxes could not be read; they may be shared mailboxes missing from the report",
+            scan.failed,
+            scan.checked
+        );
+        return Err(anyhow::anyhow!("{} of {} users could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let options = ScanOptions {
+            discovery: "mailbox-settings",
+            concurrency: 2,
+            delegations: false,
+            permissions_export: None,
+        };
+        let mut scan = scan(&client, &options, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 1, false));
+        flag_recent_sign_ins(&mut scan.mailboxes, "2026-09-15T00:00:00Z".parse().unwrap());
+
+        let run_stats = Summary::fixture();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.mailboxes, output, 30, &run_stats));
+    }
+
+    // The usage report classifies helpdesk, jane.doe, and archive without a mailboxSettings
+    // request; reception is missing from it and is looked up, so the report is the same.
+    #[tokio::test]
+    async fn usage_report_discovery_matches_mailbox_settings() {
+        let client = golden::fixture_client();
+        let options = ScanOptions {
+            discovery: "usage-report",
+            concurrency: 2,
+            delegations: false,
+            permissions_export: None,
+        };
+        let mut scan = scan(&client, &options, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 0, false));
+        flag_recent_sign_ins(&mut scan.mailboxes, "2026-09-15T00:00:00Z".parse().unwrap());
+
+        let run_stats = client.stats().summary();
+        let rendered = render_report(&scan.mailboxes, "text", 30, &run_stats).unwrap();
+        golden::assert_matches(&golden::testdata("golden/report.txt"), &rendered);
+    }
+
+    #[tokio::test]
+    async fn delegation_reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let export = PermissionExport::load(&golden::testdata("permissions.csv")).unwrap();
+        let options = ScanOptions {
+            discovery: "mailbox-settings",
+            concurrency: 2,
+            delegations: true,
+            permissions_export: Some(&export),
+        };
+        let mut scan = scan(&client, &options, &ShutdownSignal::never()).await.unwrap();
+        flag_recent_sign_ins(&mut scan.mailboxes, "2026-09-15T00:00:00Z".parse().unwrap());
+
+        let run_stats = Summary::fixture();
+        golden::assert_reports("delegations", &["text", "csv", "json"], |output| render_report(&scan.mailboxes, output, 30, &run_stats));
+    }
+}
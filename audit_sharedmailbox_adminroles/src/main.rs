@@ -2,10 +2,18 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use dotenv::dotenv;
 use env_logger::Builder;
-use log::{debug, info, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
+use graph_common::cache::ResponseCache;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, permissions, GraphClient};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
 use std::env;
+use std::fmt::Write;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(name = "api_template")]
@@ -18,6 +26,15 @@ struct AppConfig {
 
     #[arg(long, help = "Enable debug level logging")]
     debug: bool,
+
+    #[arg(long, value_name = "SECONDS", help = "Cache Graph responses on disk for this many seconds, revalidating with ETags afterwards")]
+    cache_ttl: Option<u64>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "Report every role member, not only shared mailboxes")]
+    all: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -28,22 +45,40 @@ struct DirectoryRole {
 }
 
 #[derive(Deserialize, Debug)]
-struct DirectoryRoleResponse {
-    value: Vec<DirectoryRole>,
+struct RoleMember {
+    id: String,
+    #[serde(rename = "@odata.type")]
+    odata_type: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
-struct DirectoryRoleMembersResponse {
-    value: Vec<RoleMember>,
+impl RoleMember {
+    fn member_type(&self) -> &str {
+        self.odata_type
+            .as_deref()
+            .and_then(|odata_type| odata_type.strip_prefix("#microsoft.graph."))
+            .unwrap_or("unknown")
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct RoleMember {
-    id: String,
-    #[serde(rename = "displayName")]
-    display_name: String,
+// One row per role and user, so a user holding a role both directly and through a group
+// appears once for each assignment.
+#[derive(Serialize, Debug)]
+struct ReportRow {
+    role: String,
+    #[serde(rename = "memberType")]
+    member_type: String,
+    #[serde(rename = "assignedVia")]
+    assigned_via: String,
     #[serde(rename = "userPrincipalName")]
     user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "mailboxPurpose")]
+    mailbox_purpose: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -53,97 +88,58 @@ struct MailboxSettings {
 }
 
 struct GraphApiClient {
-    client: Client,
-    access_token: String,
+    graph: GraphClient,
 }
 
 impl GraphApiClient {
-    fn new(access_token: String) -> Self {
+    fn new(access_token: String, cache: Option<ResponseCache>) -> Self {
         Self {
-            client: Client::new(),
-            access_token,
+            graph: GraphClient::new(access_token).with_cache(cache),
         }
     }
 
     async fn fetch_directory_roles(&self) -> Result<Vec<DirectoryRole>> {
-        let url = "https://graph.microsoft.com/v1.0/directoryRoles";
-        let response = self
-            .client
-            .get(url)
-            .bearer_auth(&self.access_token)
-            .send()
+        self.graph
+            .directory_roles()
+            .resource()
+            .get_all()
             .await
-            .context("Failed to send request to Graph API")?;
-
-        if response.status().is_success() {
-            let role_response: DirectoryRoleResponse = response
-                .json()
-                .await
-                .context("Failed to parse response from Graph API")?;
-            Ok(role_response.value)
-        } else {
-            Err(anyhow::anyhow!(
-                "Graph API request failed with status: {}",
-                response.status()
-            ))
-        }
+            .context("Failed to fetch directory roles")
     }
 
     async fn fetch_directory_role_members(&self, role_id: &str) -> Result<Vec<RoleMember>> {
-        let url = format!(
-            "https://graph.microsoft.com/v1.0/directoryRoles/{}/members",
-            role_id
-        );
+        self.graph
+            .directory_roles()
+            .members(role_id)
+            .get_all()
+            .await
+            .context("Failed to fetch directory role members")
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
+    // Role-assignable groups pass their role on to every member, including nested ones. The
+    // cast to microsoft.graph.user means Graph omits the @odata.type of each member.
+    async fn fetch_group_users(&self, group_id: &str) -> Result<Vec<RoleMember>> {
+        let mut users: Vec<RoleMember> = self
+            .graph
+            .path(&["groups", group_id, "transitiveMembers", "microsoft.graph.user"])
+            .select("id,displayName,userPrincipalName")
+            .top(999)
+            .get_all()
             .await
-            .context("Failed to send request to Graph API")?;
-
-        if response.status().is_success() {
-            let members_response: DirectoryRoleMembersResponse = response
-                .json()
-                .await
-                .context("Failed to parse response from Graph API")?;
-            Ok(members_response.value)
-        } else {
-            Err(anyhow::anyhow!(
-                "Graph API request failed with status: {}",
-                response.status()
-            ))
+            .with_context(|| format!("Failed to fetch members of group {}", group_id))?;
+        for user in &mut users {
+            user.odata_type.get_or_insert_with(|| "#microsoft.graph.user".to_string());
         }
+        Ok(users)
     }
 
     async fn get_mailbox_settings(&self, user_principal_name: &str) -> Result<MailboxSettings> {
-        let url = format!(
-            "https://graph.microsoft.com/v1.0/users/{}/mailboxSettings",
-            user_principal_name
-        );
-
-        let response = self
-            .client
-            .get(&url)
-            .bearer_auth(&self.access_token)
-            .send()
+        self.graph
+            .user(user_principal_name)
+            .mailbox_settings()
+            .get_json()
             .await
-            .context("Failed to send request to fetch mailbox settings")?;
-
-        if response.status().is_success() {
-            let mailbox_settings: MailboxSettings = response
-                .json()
-                .await
-                .context("Failed to parse mailbox settings response")?;
-            Ok(mailbox_settings)
-        } else {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response text")?;
-            Err(anyhow::anyhow!("HTTP error: {}", error_text))
-        }
+            .context("Failed to fetch mailbox settings")
     }
 }
 
@@ -200,49 +196,155 @@ async fn get_access_token(tenant_id: &str, client_id: &str, client_secret: &str)
     }
 }
 
-async fn process_directory_roles(api_client: &GraphApiClient) -> Result<Vec<String>> {
-    let mut shared_mailboxes = Vec::new();
+async fn mailbox_purpose(
+    api_client: &GraphApiClient,
+    purposes: &mut HashMap<String, String>,
+    user_principal_name: &str,
+) -> String {
+    if let Some(purpose) = purposes.get(user_principal_name) {
+        return purpose.clone();
+    }
+    let _phase = api_client.graph.stats().phase("read mailbox settings");
+    let purpose = match api_client.get_mailbox_settings(user_principal_name).await {
+        Ok(mailbox_settings) => {
+            debug!(
+                "Retrieved mailbox settings for {}: {:?}",
+                user_principal_name, mailbox_settings
+            );
+            mailbox_settings.user_purpose.unwrap_or_default().to_lowercase()
+        }
+        Err(e) => {
+            debug!(
+                "Failed to retrieve mailbox settings for {}: {:?}",
+                user_principal_name, e
+            );
+            String::new()
+        }
+    };
+    purposes.insert(user_principal_name.to_string(), purpose.clone());
+    purpose
+}
+
+// Returns the report rows and whether the scan was interrupted before finishing.
+async fn process_directory_roles(
+    api_client: &GraphApiClient,
+    shutdown: &ShutdownSignal,
+    all: bool,
+) -> Result<(Vec<ReportRow>, bool)> {
+    let mut rows = Vec::new();
+    let mut purposes = HashMap::new();
     let roles = api_client.fetch_directory_roles().await?;
 
     info!("Fetched {} directory roles", roles.len());
     for role in roles {
+        if shutdown.is_requested() {
+            return Ok((rows, true));
+        }
         debug!(
             "Processing role - ID: {}, DisplayName: {}",
             role.id, role.display_name
         );
-        let members = api_client.fetch_directory_role_members(&role.id).await?;
-        for member in members {
+
+        let phase = api_client.graph.stats().phase("read role members");
+        let mut assignments = Vec::new();
+        for member in api_client.fetch_directory_role_members(&role.id).await? {
+            match member.member_type() {
+                "group" => {
+                    let group_name = member.display_name.clone().unwrap_or_else(|| member.id.clone());
+                    for user in api_client.fetch_group_users(&member.id).await? {
+                        assignments.push((user, format!("group:{}", group_name)));
+                    }
+                }
+                _ => assignments.push((member, "direct".to_string())),
+            }
+        }
+        drop(phase);
+
+        for (member, assigned_via) in assignments {
+            if shutdown.is_requested() {
+                return Ok((rows, true));
+            }
             debug!(
-                "Processing member - ID: {}, DisplayName: {}, UserPrincipalName: {}",
+                "Processing member - ID: {}, DisplayName: {:?}, UserPrincipalName: {:?}",
                 member.id, member.display_name, member.user_principal_name
             );
 
-            match api_client
-                .get_mailbox_settings(&member.user_principal_name)
-                .await
-            {
-                Ok(mailbox_settings) => {
-                    debug!(
-                        "Retrieved mailbox settings for {}: {:?}",
-                        member.user_principal_name, mailbox_settings
-                    );
-                    if let Some(purpose) = mailbox_settings.user_purpose {
-                        if purpose.to_lowercase() == "shared" {
-                            info!("Found shared mailbox: {}", member.user_principal_name);
-                            shared_mailboxes.push(member.user_principal_name);
-                        }
-                    }
-                }
-                Err(e) => {
-                    debug!(
-                        "Failed to retrieve mailbox settings for {}: {:?}",
-                        member.user_principal_name, e
-                    );
-                }
+            let purpose = match &member.user_principal_name {
+                Some(user_principal_name) => mailbox_purpose(api_client, &mut purposes, user_principal_name).await,
+                None => String::new(),
+            };
+            if purpose == "shared" {
+                info!(
+                    "Found shared mailbox: {} ({})",
+                    member.user_principal_name.as_deref().unwrap_or_default(),
+                    role.display_name
+                );
+            } else if !all {
+                continue;
             }
+            rows.push(ReportRow {
+                role: role.display_name.clone(),
+                member_type: member.member_type().to_string(),
+                assigned_via,
+                user_principal_name: member.user_principal_name.clone().unwrap_or_default(),
+                display_name: member.display_name.clone().unwrap_or_default(),
+                mailbox_purpose: purpose,
+            });
         }
     }
-    Ok(shared_mailboxes)
+    Ok((rows, false))
+}
+
+fn render_rows(rows: &[ReportRow], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({ "assignments": rows, "runStats": run_stats }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "role,memberType,assignedVia,userPrincipalName,displayName,mailboxPurpose")?;
+            for row in rows {
+                let fields = [
+                    &row.role,
+                    &row.member_type,
+                    &row.assigned_via,
+                    &row.user_principal_name,
+                    &row.display_name,
+                    &row.mailbox_purpose,
+                ];
+                writeln!(
+                    out,
+                    "{}",
+                    fields.iter().map(|field| csv::field(field)).collect::<Vec<_>>().join(",")
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<40} {:<45} {:<17} {:<10} ASSIGNED VIA",
+                "ROLE", "MEMBER", "TYPE", "MAILBOX"
+            )?;
+            for row in rows {
+                let member = if row.user_principal_name.is_empty() {
+                    &row.display_name
+                } else {
+                    &row.user_principal_name
+                };
+                writeln!(
+                    out,
+                    "{:<40} {:<45} {:<17} {:<10} {}",
+                    row.role,
+                    member,
+                    row.member_type,
+                    if row.mailbox_purpose.is_empty() { "-" } else { &row.mailbox_purpose },
+                    row.assigned_via
+                )?;
+            }
+        }
+    }
+    Ok(out)
 }
 
 #[tokio::main]
@@ -261,21 +363,49 @@ async fn main() -> Result<()> {
 
     info!("Requesting access token");
     let access_token = get_access_token(&tenant_id, &client_id, &client_secret).await?;
+    permissions::check(&access_token, &["Directory.Read.All", "MailboxSettings.Read"])?;
 
-    let api_client = GraphApiClient::new(access_token);
+    let cache = config
+        .cache_ttl
+        .map(|ttl| ResponseCache::for_tool("audit_sharedmailbox_adminroles", &tenant_id, Duration::from_secs(ttl)))
+        .transpose()?;
+    let api_client = GraphApiClient::new(access_token, cache);
 
     info!("Processing directory roles");
-    let shared_mailboxes = process_directory_roles(&api_client).await?;
+    let shutdown = service::shutdown_signal();
+    let (rows, interrupted) = process_directory_roles(&api_client, &shutdown, config.all).await?;
 
     info!(
-        "Processing complete. Found {} shared mailboxes",
-        shared_mailboxes.len()
+        "Processing complete. Found {} role assignments held by shared mailboxes",
+        rows.iter().filter(|row| row.mailbox_purpose == "shared").count()
     );
-
-    println!("\nShared Mailboxes:");
-    for mailbox in shared_mailboxes {
-        println!("{} is a shared mailbox with an admin role.", mailbox);
+    let run_stats = api_client.graph.stats().summary();
+    print!("{}", render_rows(&rows, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
     }
 
+    if interrupted {
+        warn!("Interrupted before all directory roles were checked; the report above is incomplete");
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let api_client = GraphApiClient {
+            graph: golden::fixture_client(),
+        };
+        let (rows, interrupted) = process_directory_roles(&api_client, &ShutdownSignal::never(), true)
+            .await
+            .unwrap();
+        assert!(!interrupted);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_rows(&rows, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,402 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::confirm;
+use graph_common::reports::MailboxUsage;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, remediation, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_sharedmailbox_blockstatus")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports shared mailboxes whose sign-in is not blocked, and optionally blocks it, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "Block sign-in (accountEnabled=false) for every reported mailbox that is not excluded")]
+    remediate: bool,
+
+    #[arg(long, requires = "remediate", help = "List the mailboxes that would be blocked without changing them")]
+    dry_run: bool,
+
+    #[arg(short, long, requires = "remediate", help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, help = "File of shared mailbox UPNs that must keep sign-in enabled, one per line")]
+    exclude: Option<PathBuf>,
+
+    #[arg(long, default_value = "mailbox-settings", value_parser = ["mailbox-settings", "usage-report"], help = "How shared mailboxes are found: a mailboxSettings request per user, or the mailbox usage report with per-user requests only for mailboxes missing from it")]
+    discovery: String,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MailboxSettings {
+    #[serde(rename = "userPurpose")]
+    user_purpose: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SharedMailbox {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    excluded: bool,
+}
+
+struct Scan {
+    mailboxes: Vec<SharedMailbox>,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn read_exclusions(path: &PathBuf) -> Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+// Blocked accounts are already compliant, so only enabled users with a mailbox are checked.
+async fn enabled_mailbox_users(client: &GraphClient) -> Result<Vec<User>> {
+    let users: Vec<User> = client
+        .users()
+        .filter("accountEnabled eq true")
+        .select("userPrincipalName,displayName,mail")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users.into_iter().filter(|user| user.mail.is_some()).collect())
+}
+
+async fn is_shared(client: &GraphClient, upn: &str) -> Result<bool> {
+    let settings: MailboxSettings = client
+        .user(upn)
+        .mailbox_settings()
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to fetch mailbox settings for {}", upn))?;
+    Ok(settings.user_purpose.as_deref() == Some("shared"))
+}
+
+async fn mailbox_usage(client: &GraphClient, users: &[User]) -> Result<MailboxUsage> {
+    let _phase = client.stats().phase("read usage report");
+    let usage = MailboxUsage::load(client).await?;
+    let classified = users
+        .iter()
+        .filter(|user| usage.is_shared(&user.user_principal_name).is_some())
+        .count();
+    if classified == 0 && !usage.is_empty() {
+        warn!("The mailbox usage report conceals user names, so every mailbox is looked up individually; turn off concealed names in the Microsoft 365 admin center reports settings to use it");
+    }
+    info!(
+        "The mailbox usage report covers {} of {} enabled users; looking up the mailbox settings of the other {}",
+        classified,
+        users.len(),
+        users.len() - classified
+    );
+    Ok(usage)
+}
+
+async fn scan(
+    client: &GraphClient,
+    exclusions: &HashSet<String>,
+    discovery: &str,
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("list users");
+    let users = enabled_mailbox_users(client).await?;
+    drop(phase);
+
+    let usage = match discovery {
+        "usage-report" => Some(mailbox_usage(client, &users).await?),
+        _ => None,
+    };
+    info!("Checking the mailbox purpose of {} enabled users", users.len());
+
+    let _phase = client.stats().phase("read mailbox settings");
+    let results: Vec<(&User, Result<bool>)> = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|user| {
+            let known = usage.as_ref().and_then(|usage| usage.is_shared(&user.user_principal_name));
+            async move {
+                let shared = match known {
+                    Some(shared) => Ok(shared),
+                    None => is_shared(client, &user.user_principal_name).await,
+                };
+                (user, shared)
+            }
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let mut mailboxes = Vec::new();
+    let mut failed = 0;
+    for (user, shared) in &results {
+        match shared {
+            Ok(true) => {
+                debug!("{} is a shared mailbox with sign-in enabled", user.user_principal_name);
+                mailboxes.push(SharedMailbox {
+                    user_principal_name: user.user_principal_name.clone(),
+                    display_name: user.display_name.clone().unwrap_or_default(),
+                    excluded: exclusions.contains(&user.user_principal_name.to_lowercase()),
+                });
+            }
+            Ok(false) => {}
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(Scan {
+        mailboxes,
+        checked: results.len(),
+        failed,
+        interrupted: results.len() < users.len(),
+    })
+}
+
+fn render_report(mailboxes: &[SharedMailbox], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({ "sharedMailboxes": mailboxes, "runStats": run_stats }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,displayName,excluded")?;
+            for mailbox in mailboxes {
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    csv::field(&mailbox.user_principal_name),
+                    csv::field(&mailbox.display_name),
+                    mailbox.excluded
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<45} {:<35} NOTE", "SHARED MAILBOX", "DISPLAY NAME")?;
+            for mailbox in mailboxes {
+                writeln!(
+                    out,
+                    "{:<45} {:<35} {}",
+                    mailbox.user_principal_name,
+                    mailbox.display_name,
+                    if mailbox.excluded { "excluded" } else { "" }
+                )?;
+            }
+            let excluded = mailboxes.iter().filter(|mailbox| mailbox.excluded).count();
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} shared mailboxes can be signed in to ({} excluded)",
+                mailboxes.len(),
+                excluded
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn remediate(client: &GraphClient, targets: &[&SharedMailbox], config: &AppConfig, shutdown: &ShutdownSignal) -> Result<()> {
+    println!();
+    if config.dry_run {
+        for mailbox in targets {
+            println!("CHANGE  {}: enabled -> blocked", mailbox.user_principal_name);
+        }
+        println!();
+        println!("Processed {} of {} mailboxes: {} to block", targets.len(), targets.len(), targets.len());
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+    if targets.is_empty() {
+        println!("Nothing to block.");
+        return Ok(());
+    }
+    if !config.yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        println!("Block sign-in for {} shared mailboxes?", targets.len());
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    // Mailboxes already being processed finish; no new ones are started after an interrupt.
+    let _phase = client.stats().phase("block mailboxes");
+    let mut results = stream::iter(targets)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|mailbox| async move {
+            (mailbox, remediation::set_account_enabled(client, &mailbox.user_principal_name, false).await)
+        })
+        .buffer_unordered(config.concurrency as usize);
+    let mut finished = HashSet::new();
+    let mut blocked = 0;
+    let mut failed = 0;
+    while let Some((mailbox, result)) = results.next().await {
+        finished.insert(&mailbox.user_principal_name);
+        match result {
+            Ok(()) => {
+                blocked += 1;
+                println!("OK      {}", mailbox.user_principal_name);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", mailbox.user_principal_name, e);
+            }
+        }
+    }
+
+    let interrupted = targets.len() - finished.len();
+    for mailbox in targets.iter().filter(|mailbox| !finished.contains(&mailbox.user_principal_name)) {
+        println!("SKIPPED {}: not started", mailbox.user_principal_name);
+    }
+    println!();
+    println!(
+        "Processed {} of {} mailboxes: {} blocked, {} failed, {} skipped",
+        targets.len() - interrupted,
+        targets.len(),
+        blocked,
+        failed,
+        interrupted
+    );
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} mailboxes were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} mailboxes failed", failed, targets.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_sharedmailbox_blockstatus");
+    debug!("Configuration: {:?}", config);
+
+    if config.remediate && config.output != "text" {
+        return Err(anyhow::anyhow!("--remediate reports its progress as text and cannot be combined with -o {}", config.output));
+    }
+    let exclusions = match &config.exclude {
+        Some(path) => read_exclusions(path)?,
+        None => HashSet::new(),
+    };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let users_permission = if config.remediate && !config.dry_run {
+        "User.ReadWrite.All"
+    } else {
+        "User.Read.All"
+    };
+    let mut required = vec![users_permission, "MailboxSettings.Read"];
+    if config.discovery == "usage-report" {
+        required.push("Reports.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, &exclusions, &config.discovery, config.concurrency as usize, &shutdown).await?;
+    print!("{}", render_report(&scan.mailboxes, &config.output, &client.stats().summary())?);
+
+    if scan.interrupted {
+        warn!("Interrupted after checking {} users; the report above is incomplete", scan.checked);
+        client.stats().summary().print();
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!(
+            "Mailbox settings could not be read for {} of {} users; they may be shared mailboxes missing from the report",
+            scan.failed,
+            scan.checked
+        );
+    }
+
+    if config.remediate {
+        let targets: Vec<&SharedMailbox> = scan.mailboxes.iter().filter(|mailbox| !mailbox.excluded).collect();
+        let result = remediate(&client, &targets, &config, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+
+    if scan.failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users could not be checked", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let exclusions = HashSet::from(["reception@contoso.com".to_string()]);
+        let scan = scan(&client, &exclusions, "mailbox-settings", 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.checked, scan.failed, scan.interrupted), (4, 1, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.mailboxes, output, &Summary::fixture()));
+    }
+}
@@ -2,14 +2,16 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use dotenv::dotenv;
 use env_logger::Builder;
-use log::{debug, info, LevelFilter};
-use reqwest::Client;
+use log::{debug, info, warn, LevelFilter};
+use graph_common::cache::ResponseCache;
+use graph_common::licenses::SkuCatalog;
+use graph_common::service;
+use graph_common::{permissions, GraphClient};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use futures::future::join_all;
+use std::time::Duration;
+use futures::stream::{self, StreamExt};
 
 mod auth;
 use auth::get_access_token;
@@ -19,7 +21,13 @@ use auth::get_access_token;
 #[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
 #[command(version = "1.0")]
 #[command(about = "Audit shared mailbox licenses")]
-struct AppConfig;
+struct AppConfig {
+    #[arg(long, value_name = "SECONDS", help = "Cache Graph responses on disk for this many seconds, revalidating with ETags afterwards")]
+    cache_ttl: Option<u64>,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of mailbox settings requests in flight at the same time")]
+    concurrency: u16,
+}
 
 fn setup_logger() {
     let mut builder = Builder::from_default_env();
@@ -33,13 +41,6 @@ fn setup_logger() {
     builder.init();
 }
 
-#[derive(Deserialize, Debug)]
-struct UsersResponse {
-    value: Vec<User>,
-    #[serde(rename = "@odata.nextLink")]
-    next_link: Option<String>,
-}
-
 #[derive(Deserialize, Debug)]
 struct User {
     #[serde(rename = "userPrincipalName")]
@@ -60,88 +61,36 @@ struct MailboxSettings {
     user_purpose: Option<String>,
 }
 
-async fn get_users(access_token: &str) -> Result<Vec<User>> {
-    let client = Client::new();
-    let mut url = "https://graph.microsoft.com/v1.0/users?$select=userPrincipalName,assignedLicenses".to_string();
-    let mut users: Vec<User> = Vec::new();
-    let mut page_count = 0;
-
-    loop {
-        page_count += 1;
-        debug!("Fetching users from URL: {} (Page {})", url, page_count);
-        let response = client
-            .get(&url)
-            .bearer_auth(access_token)
-            .send()
-            .await
-            .context("Failed to send request to fetch users")?;
-
-        debug!("Response status: {}", response.status());
-
-        if response.status().is_success() {
-            let users_response: UsersResponse = response
-                .json()
-                .await
-                .context("Failed to parse users response")?;
-            debug!("Number of users fetched on this page: {}", users_response.value.len());
-            users.extend(users_response.value);
-
-            if let Some(next_link) = users_response.next_link {
-                url = next_link;
-                debug!("Next page URL: {}", url);
-            } else {
-                debug!("No more pages. Finished fetching users.");
-                break;
-            }
-        } else {
-            let error_text = response
-                .text()
-                .await
-                .context("Failed to read error response text")?;
-            return Err(anyhow::anyhow!("HTTP error: {}", error_text));
-        }
-    }
+async fn get_users(client: &GraphClient) -> Result<Vec<User>> {
+    let users_resource = client.users().select("userPrincipalName,assignedLicenses");
+    debug!("Fetching users from URL: {}", users_resource.url());
+
+    let users: Vec<User> = users_resource
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
 
     debug!("Total number of users fetched: {}", users.len());
     Ok(users)
 }
 
-async fn get_mailbox_settings(client: &Client, access_token: &str, user_principal_name: &str) -> Result<MailboxSettings> {
-    let url = format!(
-        "https://graph.microsoft.com/v1.0/users/{}/mailboxSettings",
-        user_principal_name
-    );
-
+async fn get_mailbox_settings(client: &GraphClient, user_principal_name: &str) -> Result<MailboxSettings> {
     debug!("Fetching mailbox settings for user: {}", user_principal_name);
 
-    let response = client
-        .get(&url)
-        .bearer_auth(access_token)
-        .send()
+    let mailbox_settings: MailboxSettings = client
+        .user(user_principal_name)
+        .mailbox_settings()
+        .get_json()
         .await
-        .context("Failed to send request to fetch mailbox settings")?;
-
-    debug!("Response status: {}", response.status());
-
-    if response.status().is_success() {
-        let mailbox_settings: MailboxSettings = response
-            .json()
-            .await
-            .context("Failed to parse mailbox settings response")?;
-        debug!("Mailbox settings fetched for user {}: {:?}", user_principal_name, mailbox_settings);
-        Ok(mailbox_settings)
-    } else {
-        let error_text = response
-            .text()
-            .await
-            .context("Failed to read error response text")?;
-        Err(anyhow::anyhow!("HTTP error: {}", error_text))
-    }
+        .context("Failed to fetch mailbox settings")?;
+    debug!("Mailbox settings fetched for user {}: {:?}", user_principal_name, mailbox_settings);
+    Ok(mailbox_settings)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
+    let config = AppConfig::parse();
     setup_logger();
 
     info!("Starting api_template");
@@ -153,53 +102,86 @@ async fn main() -> Result<()> {
     let access_token = get_access_token(&tenant_id, &client_id, &client_secret)
         .await
         .context("Failed to obtain access token")?;
+    permissions::check(&access_token, &["User.Read.All", "MailboxSettings.Read", "Organization.Read.All"])?;
 
     // Step 1: Pull all users into memory
-    let users = get_users(&access_token).await?;
+    let cache = config
+        .cache_ttl
+        .map(|ttl| ResponseCache::for_tool("audit_sharedmailbox_licenses", &tenant_id, Duration::from_secs(ttl)))
+        .transpose()?;
+    let client = GraphClient::new(access_token).with_cache(cache);
+    let phase = client.stats().phase("list users");
+    let skus = SkuCatalog::load(&client).await?;
+    let users = get_users(&client).await?;
+    drop(phase);
     debug!("Completed fetching all users.");
 
-    // Step 2: Once all users are pulled, fetch mailbox settings concurrently for those with licenses
-    let client = Arc::new(Client::new());
-    let user_purpose_map = Arc::new(Mutex::new(HashMap::new()));
+    // Step 2: Once all users are pulled, fetch mailbox settings for those with licenses, at most
+    // --concurrency at a time. Throttled requests are retried by the client after Retry-After.
+    // The audit only reads data, so an interrupt cancels the outstanding requests and the
+    // mailboxes checked so far are still reported.
+    let shutdown = service::shutdown_signal();
     let mut shared_users_with_licenses_count = 0;
 
-    let tasks: Vec<_> = users.iter()
+    let licensed: Vec<&User> = users.iter()
         .filter(|user| !user.assigned_licenses.is_empty())
+        .collect();
+    let licensed_users = licensed.len();
+    let phase = client.stats().phase("read mailbox settings");
+    let results: Vec<(&str, Option<Result<MailboxSettings>>)> = stream::iter(&licensed)
         .map(|user| {
-            let client = Arc::clone(&client);
-            let access_token = access_token.to_string();
-            let user_purpose_map = Arc::clone(&user_purpose_map);
-            let user_principal_name = user.user_principal_name.clone();
-
-            tokio::spawn(async move {
-                let mailbox_settings = match get_mailbox_settings(&client, &access_token, &user_principal_name).await {
-                    Ok(settings) => settings,
-                    Err(e) => {
-                        debug!("Error fetching mailbox settings for {}: {}", user_principal_name, e);
-                        return;
-                    }
+            let client = &client;
+            let mut shutdown = shutdown.clone();
+            async move {
+                let user_principal_name = user.user_principal_name.as_str();
+                let result = tokio::select! {
+                    result = get_mailbox_settings(client, user_principal_name) => Some(result),
+                    _ = shutdown.requested() => None,
                 };
-
-                let mut map = user_purpose_map.lock().await;
-                map.insert(user_principal_name.clone(), mailbox_settings.user_purpose);
-            })
+                (user_principal_name, result)
+            }
         })
-        .collect();
-
-    join_all(tasks).await;
+        .buffer_unordered(config.concurrency as usize)
+        .collect()
+        .await;
+    drop(phase);
+
+    let mut user_purpose_map = HashMap::new();
+    let mut failed_users = 0;
+    for (user_principal_name, result) in results {
+        match result {
+            Some(Ok(mailbox_settings)) => {
+                user_purpose_map.insert(user_principal_name.to_string(), mailbox_settings.user_purpose);
+            }
+            Some(Err(e)) => {
+                warn!("Could not read mailbox settings for {}: {:#}", user_principal_name, e);
+                failed_users += 1;
+            }
+            None => {}
+        }
+    }
 
     debug!("Finished processing all users. Outputting results...");
 
     // Output the results to the terminal
-    let user_purpose_map = user_purpose_map.lock().await;
-    for user in users {
+    let mut wasted_seats: BTreeMap<String, u32> = BTreeMap::new();
+    for user in &users {
         let user_purpose = user_purpose_map.get(&user.user_principal_name).unwrap_or(&None).clone();
         if let Some(ref up) = user_purpose {
             if up == "shared" {
+                let licenses: Vec<String> = user
+                    .assigned_licenses
+                    .iter()
+                    .map(|license| skus.display_name(&license.sku_id))
+                    .collect();
                 info!(
-                    "User with shared purpose and licenses: {}",
-                    user.user_principal_name
+                    "User with shared purpose and licenses: {} ({})",
+                    user.user_principal_name,
+                    licenses.join(", ")
                 );
+                for license in &user.assigned_licenses {
+                    *wasted_seats.entry(license.sku_id.to_lowercase()).or_default() += 1;
+                }
                 shared_users_with_licenses_count += 1;
             }
         }
@@ -209,6 +191,37 @@ async fn main() -> Result<()> {
         "Total number of users with shared purpose and licenses: {}",
         shared_users_with_licenses_count
     );
+    // Shared mailboxes do not need a license for up to 50 GB, so every seat counted here is
+    // likely wasted.
+    for (sku_id, seats) in &wasted_seats {
+        match skus.get(sku_id) {
+            Some(sku) => info!(
+                "Seats held by shared mailboxes: {}: {} of {} consumed",
+                sku.display_name(),
+                seats,
+                sku.consumed_units
+            ),
+            None => info!("Seats held by shared mailboxes: {}: {}", sku_id, seats),
+        }
+    }
+    client.stats().summary().print();
+
+    if shutdown.is_requested() {
+        warn!(
+            "Interrupted after checking {} of {} licensed users; the results above are incomplete",
+            user_purpose_map.len(),
+            licensed_users
+        );
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if failed_users > 0 {
+        warn!(
+            "Mailbox settings could not be read for {} of {} licensed users; they may be shared mailboxes missing from the results above",
+            failed_users,
+            licensed_users
+        );
+        return Err(anyhow::anyhow!("{} of {} licensed users could not be checked", failed_users, licensed_users));
+    }
 
     debug!("Program finished successfully.");
     Ok(())
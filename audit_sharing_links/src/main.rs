@@ -0,0 +1,630 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::confirm;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, remediation, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_sharing_links")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists OneDrive items shared through anonymous or company-wide links or directly with external users, and optionally revokes old anonymous links, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "Only audit the OneDrive of these users (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Anonymous links shared this many days ago or earlier are old")]
+    older_than: i64,
+
+    #[arg(long, help = "Remove every old anonymous link")]
+    revoke: bool,
+
+    #[arg(long, requires = "revoke", help = "List the links that would be removed without removing them")]
+    dry_run: bool,
+
+    #[arg(short, long, requires = "revoke", help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    value: Vec<T>,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "userType")]
+    user_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    id: String,
+    #[serde(rename = "isVerified", default)]
+    is_verified: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Drive {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DriveItem {
+    id: String,
+    name: Option<String>,
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+    #[serde(rename = "parentReference")]
+    parent_reference: Option<ItemReference>,
+    shared: Option<Shared>,
+    deleted: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ItemReference {
+    id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Shared {
+    #[serde(rename = "sharedDateTime")]
+    shared_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Permission {
+    id: String,
+    #[serde(default)]
+    roles: Vec<String>,
+    link: Option<SharingLink>,
+    #[serde(rename = "grantedToV2")]
+    granted_to: Option<IdentitySet>,
+    #[serde(rename = "grantedToIdentitiesV2", default)]
+    granted_to_identities: Vec<IdentitySet>,
+    invitation: Option<Invitation>,
+    #[serde(rename = "inheritedFrom")]
+    inherited_from: Option<serde_json::Value>,
+    #[serde(rename = "expirationDateTime")]
+    expiration_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SharingLink {
+    scope: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdentitySet {
+    user: Option<Identity>,
+    #[serde(rename = "siteUser")]
+    site_user: Option<Identity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Identity {
+    email: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Invitation {
+    email: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ShareRow {
+    owner: String,
+    path: String,
+    #[serde(rename = "webUrl")]
+    web_url: String,
+    share: &'static str,
+    access: &'static str,
+    #[serde(rename = "sharedDateTime")]
+    shared_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "expirationDateTime")]
+    expiration_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "sharedWith")]
+    shared_with: Vec<String>,
+    // Anonymous links shared before the cutoff; the ones --revoke removes.
+    old: bool,
+    #[serde(rename = "driveId")]
+    drive_id: String,
+    #[serde(rename = "itemId")]
+    item_id: String,
+    #[serde(rename = "permissionId")]
+    permission_id: String,
+}
+
+struct Scan {
+    shares: Vec<ShareRow>,
+    users: usize,
+    drives: usize,
+    without_drive: usize,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_users(client: &GraphClient) -> Result<Vec<String>> {
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,userType")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users
+        .into_iter()
+        .filter(|user| user.user_type.as_deref() != Some("Guest"))
+        .map(|user| user.user_principal_name)
+        .collect())
+}
+
+async fn internal_domains(client: &GraphClient) -> Result<Vec<String>> {
+    let domains: Vec<Domain> = client
+        .path(&["domains"])
+        .select("id,isVerified")
+        .get_all()
+        .await
+        .context("Failed to fetch the tenant's domains")?;
+    Ok(domains
+        .into_iter()
+        .filter(|domain| domain.is_verified)
+        .map(|domain| domain.id.to_lowercase())
+        .collect())
+}
+
+fn is_external(email: &str, domains: &[String]) -> bool {
+    let Some((_, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let domain = domain.to_lowercase();
+    !domains
+        .iter()
+        .any(|internal| domain == *internal || domain.ends_with(&format!(".{}", internal)))
+}
+
+// Delta only returns the parent id of each item, so paths are rebuilt from the names of the
+// folders above it.
+fn item_path(items: &HashMap<&str, &DriveItem>, item: &DriveItem) -> String {
+    let mut names = Vec::new();
+    let mut current = item;
+    while let Some(parent) = current
+        .parent_reference
+        .as_ref()
+        .and_then(|parent| parent.id.as_deref())
+        .and_then(|id| items.get(id))
+    {
+        names.push(current.name.as_deref().unwrap_or_default());
+        if names.len() > 64 {
+            break;
+        }
+        current = parent;
+    }
+    names.reverse();
+    format!("/{}", names.join("/"))
+}
+
+fn access(roles: &[String]) -> &'static str {
+    if roles.iter().any(|role| role == "write") {
+        "edit"
+    } else {
+        "view"
+    }
+}
+
+// Owner permissions and permissions inherited from a shared folder are left out; the folder
+// itself is listed instead. Direct and specific-people shares are only listed when they include
+// someone outside the tenant's verified domains.
+fn classify(permission: &Permission, domains: &[String]) -> Option<(&'static str, Vec<String>)> {
+    if permission.inherited_from.is_some() || permission.roles.iter().any(|role| role == "owner") {
+        return None;
+    }
+    match permission.link.as_ref().and_then(|link| link.scope.as_deref()) {
+        Some("anonymous") => return Some(("anonymous", Vec::new())),
+        Some("organization") => return Some(("company", Vec::new())),
+        _ => {}
+    }
+    let mut external: Vec<String> = permission
+        .granted_to
+        .iter()
+        .chain(&permission.granted_to_identities)
+        .flat_map(|identity| [&identity.user, &identity.site_user])
+        .filter_map(|identity| identity.as_ref()?.email.as_deref())
+        .chain(permission.invitation.as_ref().and_then(|invitation| invitation.email.as_deref()))
+        .map(str::to_lowercase)
+        .filter(|email| is_external(email, domains))
+        .collect();
+    external.sort();
+    external.dedup();
+    (!external.is_empty()).then_some(("external", external))
+}
+
+async fn read_drive(client: &GraphClient, owner: &str, drive_id: &str, domains: &[String], cutoff: DateTime<Utc>) -> Result<Vec<ShareRow>> {
+    let items: Vec<DriveItem> = client
+        .path(&["drives", drive_id, "root", "delta"])
+        .select("id,name,webUrl,parentReference,shared,deleted")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to read the OneDrive of {}", owner))?;
+    let by_id: HashMap<&str, &DriveItem> = items.iter().map(|item| (item.id.as_str(), item)).collect();
+    let shared: Vec<&DriveItem> = items
+        .iter()
+        .filter(|item| item.shared.is_some() && item.deleted.is_none())
+        .collect();
+    debug!("{} of {} items in the OneDrive of {} are shared", shared.len(), items.len(), owner);
+
+    let urls: Vec<String> = shared
+        .iter()
+        .map(|item| client.path(&["drives", drive_id, "items", &item.id, "permissions"]).relative_url())
+        .collect();
+    let permissions: Vec<Result<Page<Permission>>> = client.batch_get(&urls).await?;
+    let mut rows = Vec::new();
+    for (item, permissions) in shared.into_iter().zip(permissions) {
+        let permissions = permissions.with_context(|| format!("Failed to read the permissions of {} in the OneDrive of {}", item.id, owner))?;
+        let shared_date_time = item.shared.as_ref().and_then(|shared| shared.shared_date_time);
+        for permission in &permissions.value {
+            let Some((share, shared_with)) = classify(permission, domains) else {
+                continue;
+            };
+            rows.push(ShareRow {
+                owner: owner.to_string(),
+                path: item_path(&by_id, item),
+                web_url: item.web_url.clone().unwrap_or_default(),
+                share,
+                access: access(&permission.roles),
+                shared_date_time,
+                expiration_date_time: permission.expiration_date_time,
+                shared_with,
+                old: share == "anonymous" && shared_date_time.is_some_and(|shared| shared <= cutoff),
+                drive_id: drive_id.to_string(),
+                item_id: item.id.clone(),
+                permission_id: permission.id.clone(),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+// Users without a OneDrive (unlicensed, or never signed in) answer 404 for their drive and are
+// counted separately from drives that could not be read.
+async fn scan(
+    client: &GraphClient,
+    users: &[String],
+    domains: &[String],
+    cutoff: DateTime<Utc>,
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("find drives");
+    let urls: Vec<String> = users
+        .iter()
+        .map(|upn| client.user(upn).resource().segment("drive").select("id").relative_url())
+        .collect();
+    let results: Vec<Result<Drive>> = client.batch_get(&urls).await?;
+    drop(phase);
+    let mut drives = Vec::new();
+    let (mut without_drive, mut failed) = (0, 0);
+    for (upn, result) in users.iter().zip(results) {
+        match result {
+            Ok(drive) => drives.push((upn, drive.id)),
+            Err(e) if e.to_string().starts_with("HTTP error 404") => without_drive += 1,
+            Err(e) => {
+                warn!("Failed to find the OneDrive of {}: {:#}", upn, e);
+                failed += 1;
+            }
+        }
+    }
+    info!("Reading {} OneDrives", drives.len());
+
+    let _phase = client.stats().phase("read drives");
+    let results: Vec<Result<Vec<ShareRow>>> = stream::iter(&drives)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|(upn, drive_id)| read_drive(client, upn, drive_id, domains, cutoff))
+        .buffered(concurrency)
+        .collect()
+        .await;
+    let checked = results.len();
+    let mut shares = Vec::new();
+    for result in results {
+        match result {
+            Ok(rows) => shares.extend(rows),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    shares.sort_by(|a, b| (a.owner.to_lowercase(), &a.path, a.share).cmp(&(b.owner.to_lowercase(), &b.path, b.share)));
+    Ok(Scan {
+        shares,
+        users: users.len(),
+        drives: drives.len(),
+        without_drive,
+        checked,
+        failed,
+        interrupted: checked < drives.len(),
+    })
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value
+        .map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn render_report(scan: &Scan, output: &str, older_than: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let count = |share: &str| scan.shares.iter().filter(|row| row.share == share).count();
+    let old = scan.shares.iter().filter(|row| row.old).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "shares": scan.shares,
+                "users": scan.users,
+                "drives": scan.drives,
+                "withoutOneDrive": scan.without_drive,
+                "anonymousLinks": count("anonymous"),
+                "oldAnonymousLinks": old,
+                "companyLinks": count("company"),
+                "externalShares": count("external"),
+                "olderThanDays": older_than,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "owner,path,webUrl,share,access,sharedDateTime,expirationDateTime,sharedWith,old,driveId,itemId,permissionId"
+            )?;
+            for row in &scan.shares {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.owner),
+                    csv::field(&row.path),
+                    csv::field(&row.web_url),
+                    row.share,
+                    row.access,
+                    timestamp(row.shared_date_time),
+                    timestamp(row.expiration_date_time),
+                    csv::field(&row.shared_with.join(";")),
+                    row.old,
+                    row.drive_id,
+                    row.item_id,
+                    row.permission_id
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<28} {:<10} {:<6} {:<10} {:<10} {:<40} SHARED WITH",
+                "OWNER", "SHARE", "ACCESS", "SHARED", "EXPIRES", "ITEM"
+            )?;
+            let date = |value: Option<DateTime<Utc>>| value.map_or_else(|| "-".to_string(), |value| value.date_naive().to_string());
+            for row in &scan.shares {
+                let mut note = row.shared_with.join(", ");
+                if row.old {
+                    note = format!("older than {} days", older_than);
+                }
+                let line = format!(
+                    "{:<28} {:<10} {:<6} {:<10} {:<10} {:<40} {}",
+                    row.owner,
+                    row.share,
+                    row.access,
+                    date(row.shared_date_time),
+                    date(row.expiration_date_time),
+                    row.path,
+                    note
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} OneDrives of {} users ({} without a OneDrive): {} anonymous links ({} older than {} days), {} company-wide links, {} external shares",
+                scan.drives,
+                scan.users,
+                scan.without_drive,
+                count("anonymous"),
+                old,
+                older_than,
+                count("company"),
+                count("external")
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn revoke(client: &GraphClient, targets: &[&ShareRow], config: &AppConfig, shutdown: &ShutdownSignal) -> Result<()> {
+    let label = |row: &ShareRow| format!("{}: {}", row.owner, row.path);
+    println!();
+    if config.dry_run {
+        for row in targets {
+            println!("{:<8}{}", "REVOKE", label(row));
+        }
+        println!();
+        println!("Processed {} of {} links: {} to revoke", targets.len(), targets.len(), targets.len());
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+    if targets.is_empty() {
+        println!("Nothing to revoke.");
+        return Ok(());
+    }
+    if !config.yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        println!("Revoke {} anonymous links older than {} days?", targets.len(), config.older_than);
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    // Links already being removed finish; no new ones are started after an interrupt.
+    let _phase = client.stats().phase("revoke links");
+    let mut results = stream::iter(targets.iter().enumerate())
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|(index, row)| async move {
+            let result = remediation::delete_drive_permission(client, &row.drive_id, &row.item_id, &row.permission_id).await;
+            (index, row, result)
+        })
+        .buffer_unordered(config.concurrency as usize);
+    let mut finished = HashSet::new();
+    let (mut revoked, mut failed) = (0, 0);
+    while let Some((index, row, result)) = results.next().await {
+        finished.insert(index);
+        match result {
+            Ok(()) => {
+                revoked += 1;
+                println!("{:<8}{}", "OK", label(row));
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", label(row), e);
+            }
+        }
+    }
+
+    let interrupted = targets.len() - finished.len();
+    for (_, row) in targets.iter().enumerate().filter(|(index, _)| !finished.contains(index)) {
+        println!("SKIPPED {}: not started", label(row));
+    }
+    println!();
+    println!(
+        "Processed {} of {} links: {} revoked, {} failed, {} skipped",
+        targets.len() - interrupted,
+        targets.len(),
+        revoked,
+        failed,
+        interrupted
+    );
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} links were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} links could not be revoked", failed, targets.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_sharing_links");
+    debug!("Configuration: {:?}", config);
+
+    if config.revoke && config.output != "text" {
+        return Err(anyhow::anyhow!("--revoke reports its progress as text and cannot be combined with -o {}", config.output));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&[
+        "User.Read.All",
+        "Domain.Read.All|Directory.Read.All",
+        if config.revoke && !config.dry_run {
+            "Files.ReadWrite.All"
+        } else {
+            "Files.Read.All"
+        },
+    ])?;
+
+    let phase = client.stats().phase("list users");
+    let users = if config.upn.is_empty() {
+        list_users(&client).await?
+    } else {
+        config.upn.clone()
+    };
+    let domains = internal_domains(&client).await?;
+    drop(phase);
+
+    let shutdown = service::shutdown_signal();
+    let cutoff = Utc::now() - Duration::days(config.older_than);
+    let scan = scan(&client, &users, &domains, cutoff, config.concurrency as usize, &shutdown).await?;
+    print!("{}", render_report(&scan, &config.output, config.older_than, &client.stats().summary())?);
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} of {} OneDrives; the report above is incomplete", scan.checked, scan.drives);
+        return Err(anyhow::anyhow!("Audit interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!("{} OneDrives could not be read; their shares are missing from the report", scan.failed);
+    }
+    if config.revoke {
+        let targets: Vec<&ShareRow> = scan.shares.iter().filter(|row| row.old).collect();
+        let result = revoke(&client, &targets, &config, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+    if scan.failed > 0 {
+        return Err(anyhow::anyhow!("{} OneDrives could not be read", scan.failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let users = list_users(&client).await.unwrap();
+        let domains = internal_domains(&client).await.unwrap();
+        let cutoff = "2026-07-17T00:00:00Z".parse().unwrap();
+        let scan = scan(&client, &users, &domains, cutoff, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.users, scan.drives, scan.checked, scan.failed, scan.interrupted), (3, 2, 2, 1, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan, output, 90, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::licenses::{PriceList, SkuCatalog};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_stale_accounts")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports member accounts that have not signed in recently and the license cost they hold, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Flag accounts that have not signed in for this many days")]
+    inactive_days: i64,
+
+    #[arg(long, value_name = "FILE", help = "CSV of monthly license prices (SkuPartNumber, MonthlyPrice) used to estimate the cost of stale accounts")]
+    prices: Option<PathBuf>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "assignedLicenses", default)]
+    assigned_licenses: Vec<License>,
+    #[serde(rename = "signInActivity")]
+    sign_in_activity: Option<SignInActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct License {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignInActivity {
+    #[serde(rename = "lastSignInDateTime")]
+    last_sign_in_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastNonInteractiveSignInDateTime")]
+    last_non_interactive_sign_in_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+struct StaleAccount {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: bool,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<DateTime<Utc>>,
+    licenses: Vec<String>,
+    #[serde(rename = "monthlyCost")]
+    monthly_cost: Option<f64>,
+    #[serde(rename = "unpricedLicenses", skip_serializing_if = "Vec::is_empty")]
+    unpriced_licenses: Vec<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_members(client: &GraphClient) -> Result<Vec<User>> {
+    client
+        .users()
+        .filter("userType eq 'Member'")
+        .select("userPrincipalName,displayName,accountEnabled,createdDateTime,assignedLicenses,signInActivity")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")
+}
+
+// An account that never signed in is stale once it was created before the cutoff, so new
+// starters who have not signed in yet are not reported.
+fn stale_accounts(
+    users: Vec<User>,
+    cutoff: DateTime<Utc>,
+    skus: &SkuCatalog,
+    prices: Option<&PriceList>,
+) -> Vec<StaleAccount> {
+    let mut accounts = Vec::new();
+    for user in users {
+        let activity = user.sign_in_activity.as_ref();
+        let last_sign_in = activity
+            .and_then(|activity| activity.last_sign_in_date_time)
+            .max(activity.and_then(|activity| activity.last_non_interactive_sign_in_date_time));
+        if last_sign_in.or(user.created_date_time).is_none_or(|last| last >= cutoff) {
+            continue;
+        }
+
+        let mut monthly_cost = prices.map(|_| 0.0);
+        let mut unpriced_licenses = Vec::new();
+        for license in &user.assigned_licenses {
+            let part_number = skus.get(&license.sku_id).map(|sku| sku.sku_part_number.as_str());
+            let price = prices.zip(part_number).and_then(|(prices, part_number)| prices.monthly_price(part_number));
+            match (monthly_cost.as_mut(), price) {
+                (Some(total), Some(price)) => *total += price,
+                (Some(_), None) => unpriced_licenses.push(skus.display_name(&license.sku_id)),
+                (None, _) => {}
+            }
+        }
+        accounts.push(StaleAccount {
+            display_name: user.display_name.unwrap_or_default(),
+            account_enabled: user.account_enabled.unwrap_or(false),
+            created_date_time: user.created_date_time,
+            last_sign_in,
+            licenses: user
+                .assigned_licenses
+                .iter()
+                .map(|license| skus.display_name(&license.sku_id))
+                .collect(),
+            monthly_cost,
+            unpriced_licenses,
+            user_principal_name: user.user_principal_name,
+        });
+    }
+    accounts
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value
+        .map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn render_report(
+    accounts: &[StaleAccount],
+    members: usize,
+    output: &str,
+    inactive_days: i64,
+    run_stats: &Summary,
+) -> Result<String> {
+    let mut out = String::new();
+    let licensed: Vec<&StaleAccount> = accounts.iter().filter(|account| !account.licenses.is_empty()).collect();
+    let total_cost: Option<f64> = accounts.iter().map(|account| account.monthly_cost).sum();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "inactiveDays": inactive_days,
+                "members": members,
+                "staleAccounts": accounts,
+                "monthlyCost": total_cost,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,displayName,accountEnabled,createdDateTime,lastSignIn,licenses,monthlyCost,unpricedLicenses")?;
+            for account in accounts {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv::field(&account.user_principal_name),
+                    csv::field(&account.display_name),
+                    account.account_enabled,
+                    timestamp(account.created_date_time),
+                    timestamp(account.last_sign_in),
+                    csv::field(&account.licenses.join("; ")),
+                    account.monthly_cost.map(|cost| format!("{:.2}", cost)).unwrap_or_default(),
+                    csv::field(&account.unpriced_licenses.join("; "))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<8} {:<13} {:>12}  LICENSES",
+                "USER", "ENABLED", "LAST SIGN-IN", "MONTHLY COST"
+            )?;
+            for account in accounts {
+                writeln!(
+                    out,
+                    "{:<35} {:<8} {:<13} {:>12}  {}",
+                    account.user_principal_name,
+                    if account.account_enabled { "yes" } else { "no" },
+                    account
+                        .last_sign_in
+                        .map_or_else(|| "never".to_string(), |last| last.format("%Y-%m-%d").to_string()),
+                    account.monthly_cost.map_or_else(|| "-".to_string(), |cost| format!("{:.2}", cost)),
+                    if account.licenses.is_empty() { "-".to_string() } else { account.licenses.join(", ") }
+                )?;
+            }
+            writeln!(out)?;
+            write!(
+                out,
+                "{} of {} members have not signed in for {} days, {} of them licensed",
+                accounts.len(),
+                members,
+                inactive_days,
+                licensed.len()
+            )?;
+            match total_cost {
+                Some(cost) => writeln!(out, ", holding an estimated {:.2} a month in licenses", cost)?,
+                None => writeln!(out)?,
+            }
+            let unpriced: usize = accounts.iter().map(|account| account.unpriced_licenses.len()).sum();
+            if unpriced > 0 {
+                writeln!(out, "{} assigned licenses have no price in the price list and are not included", unpriced)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_stale_accounts");
+    debug!("Configuration: {:?}", config);
+
+    let prices = config.prices.as_deref().map(PriceList::load).transpose()?;
+    if prices.is_none() {
+        warn!("No price list given, so the license cost of stale accounts is not estimated; pass --prices to include it");
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "AuditLog.Read.All", "Organization.Read.All"])?;
+
+    let phase = client.stats().phase("list users");
+    let users = list_members(&client).await?;
+    let skus = SkuCatalog::load(&client).await?;
+    drop(phase);
+
+    let members = users.len();
+    let cutoff = Utc::now() - Duration::days(config.inactive_days);
+    let accounts = stale_accounts(users, cutoff, &skus, prices.as_ref());
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&accounts, members, &config.output, config.inactive_days, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let users = list_members(&client).await.unwrap();
+        let members = users.len();
+        let skus = SkuCatalog::load(&client).await.unwrap();
+        let prices = PriceList::load(&golden::testdata("prices.csv")).unwrap();
+        let accounts = stale_accounts(users, "2026-07-17T00:00:00Z".parse().unwrap(), &skus, Some(&prices));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&accounts, members, output, 90, &Summary::fixture()));
+    }
+}
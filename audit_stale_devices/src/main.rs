@@ -0,0 +1,452 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::confirm;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, remediation, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_stale_devices")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports devices that have not signed in for a number of days, grouped by operating system and join type, and optionally disables or deletes them, using the Microsoft API")]
+#[command(group(ArgGroup::new("remediation").args(["disable", "delete"])))]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Flag devices that have not signed in for this many days")]
+    inactive_days: i64,
+
+    #[arg(short, long, help = "File of device names or ids that must not be flagged, one per line")]
+    exclude: Option<PathBuf>,
+
+    #[arg(long, help = "Disable every stale device that is not excluded")]
+    disable: bool,
+
+    #[arg(long, help = "Delete every stale device that is not excluded")]
+    delete: bool,
+
+    #[arg(long, requires = "remediation", help = "List the devices that would be changed without changing them")]
+    dry_run: bool,
+
+    #[arg(short, long, requires = "remediation", help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Device {
+    id: String,
+    #[serde(rename = "deviceId")]
+    device_id: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "operatingSystem")]
+    operating_system: Option<String>,
+    #[serde(rename = "operatingSystemVersion")]
+    operating_system_version: Option<String>,
+    #[serde(rename = "trustType")]
+    trust_type: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "approximateLastSignInDateTime")]
+    approximate_last_sign_in: Option<DateTime<Utc>>,
+    #[serde(rename = "registrationDateTime")]
+    registration_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "onPremisesSyncEnabled")]
+    on_premises_sync_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct StaleDevice {
+    id: String,
+    #[serde(rename = "deviceId")]
+    device_id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "operatingSystem")]
+    operating_system: String,
+    #[serde(rename = "operatingSystemVersion")]
+    operating_system_version: String,
+    #[serde(rename = "joinType")]
+    join_type: &'static str,
+    enabled: bool,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<String>,
+    registered: Option<String>,
+    // Devices synced from on-premises Active Directory are changed there, not in Entra ID.
+    synced: bool,
+    excluded: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn read_exclusions(path: &PathBuf) -> Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+fn join_type(trust_type: Option<&str>) -> &'static str {
+    match trust_type {
+        Some("AzureAd") => "joined",
+        Some("ServerAd") => "hybrid joined",
+        Some("Workplace") => "registered",
+        _ => "unknown",
+    }
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> Option<String> {
+    value.map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+async fn list_devices(client: &GraphClient) -> Result<Vec<Device>> {
+    client
+        .path(&["devices"])
+        .select("id,deviceId,displayName,operatingSystem,operatingSystemVersion,trustType,accountEnabled,approximateLastSignInDateTime,registrationDateTime,onPremisesSyncEnabled")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch devices")
+}
+
+// A device that never signed in is stale once it was registered before the cutoff. Sorted by
+// operating system and join type so that each group is listed together, oldest sign-in first.
+fn stale_devices(devices: &[Device], cutoff: DateTime<Utc>, exclusions: &HashSet<String>) -> Vec<StaleDevice> {
+    let mut stale: Vec<StaleDevice> = devices
+        .iter()
+        .filter(|device| match device.approximate_last_sign_in {
+            Some(last_sign_in) => last_sign_in < cutoff,
+            None => device.registration_date_time.is_some_and(|registered| registered < cutoff),
+        })
+        .map(|device| {
+            let display_name = device.display_name.clone().unwrap_or_default();
+            let device_id = device.device_id.clone().unwrap_or_default();
+            let excluded = [&display_name, &device_id, &device.id]
+                .iter()
+                .any(|key| !key.is_empty() && exclusions.contains(&key.to_lowercase()));
+            StaleDevice {
+                id: device.id.clone(),
+                device_id,
+                display_name,
+                operating_system: device.operating_system.clone().unwrap_or_default(),
+                operating_system_version: device.operating_system_version.clone().unwrap_or_default(),
+                join_type: join_type(device.trust_type.as_deref()),
+                enabled: device.account_enabled.unwrap_or(true),
+                last_sign_in: timestamp(device.approximate_last_sign_in),
+                registered: timestamp(device.registration_date_time),
+                synced: device.on_premises_sync_enabled == Some(true),
+                excluded,
+            }
+        })
+        .collect();
+    stale.sort_by(|a, b| {
+        (a.operating_system.to_lowercase(), a.join_type, &a.last_sign_in, a.display_name.to_lowercase()).cmp(&(
+            b.operating_system.to_lowercase(),
+            b.join_type,
+            &b.last_sign_in,
+            b.display_name.to_lowercase(),
+        ))
+    });
+    stale
+}
+
+// Stale device counts per operating system and join type, in the order the devices are listed.
+fn groups(devices: &[StaleDevice]) -> Vec<((&str, &'static str), usize)> {
+    let mut groups: Vec<((&str, &'static str), usize)> = Vec::new();
+    for device in devices {
+        let key = (device.operating_system.as_str(), device.join_type);
+        match groups.last_mut() {
+            Some((last, count)) if *last == key => *count += 1,
+            _ => groups.push((key, 1)),
+        }
+    }
+    groups
+}
+
+fn render_report(devices: &[StaleDevice], total: usize, output: &str, inactive_days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            let by_group: Vec<_> = groups(devices)
+                .into_iter()
+                .map(|((operating_system, join_type), count)| {
+                    json!({ "operatingSystem": operating_system, "joinType": join_type, "staleDevices": count })
+                })
+                .collect();
+            out = serde_json::to_string_pretty(&json!({
+                "staleDevices": devices,
+                "byGroup": by_group,
+                "totalDevices": total,
+                "inactiveDays": inactive_days,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "displayName,deviceId,operatingSystem,operatingSystemVersion,joinType,enabled,lastSignIn,registered,synced,excluded"
+            )?;
+            for device in devices {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&device.display_name),
+                    device.device_id,
+                    csv::field(&device.operating_system),
+                    csv::field(&device.operating_system_version),
+                    device.join_type,
+                    device.enabled,
+                    device.last_sign_in.as_deref().unwrap_or_default(),
+                    device.registered.as_deref().unwrap_or_default(),
+                    device.synced,
+                    device.excluded
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<30} {:<12} {:<14} {:<8} {:<12} {:<12} NOTE",
+                "DEVICE", "OS", "JOIN TYPE", "ENABLED", "LAST SIGN-IN", "REGISTERED"
+            )?;
+            let date = |value: &Option<String>| value.as_deref().map_or("never".to_string(), |value| value[..10].to_string());
+            for device in devices {
+                let note = [(device.synced, "synced"), (device.excluded, "excluded")]
+                    .iter()
+                    .filter(|(flag, _)| *flag)
+                    .map(|(_, note)| *note)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let line = format!(
+                    "{:<30} {:<12} {:<14} {:<8} {:<12} {:<12} {}",
+                    device.display_name,
+                    device.operating_system,
+                    device.join_type,
+                    if device.enabled { "yes" } else { "no" },
+                    date(&device.last_sign_in),
+                    date(&device.registered),
+                    note
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(out, "{:<12} {:<14} STALE", "OS", "JOIN TYPE")?;
+            for ((operating_system, join_type), count) in groups(devices) {
+                writeln!(out, "{:<12} {:<14} {}", operating_system, join_type, count)?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} devices have not signed in for {} days: {} still enabled, {} synced from on-premises, {} excluded",
+                devices.len(),
+                total,
+                inactive_days,
+                devices.iter().filter(|device| device.enabled).count(),
+                devices.iter().filter(|device| device.synced).count(),
+                devices.iter().filter(|device| device.excluded).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+// Excluded and synced devices are never changed, and disabled devices are not disabled again.
+fn targets(devices: &[StaleDevice], delete: bool) -> Vec<&StaleDevice> {
+    devices
+        .iter()
+        .filter(|device| !device.excluded && !device.synced)
+        .filter(|device| delete || device.enabled)
+        .collect()
+}
+
+async fn remediate(client: &GraphClient, targets: &[&StaleDevice], config: &AppConfig, shutdown: &ShutdownSignal) -> Result<()> {
+    let (action, verb, past) = if config.delete {
+        ("DELETE", "Delete", "deleted")
+    } else {
+        ("DISABLE", "Disable", "disabled")
+    };
+    println!();
+    if config.dry_run {
+        for device in targets {
+            println!("{:<8}{}", action, device.display_name);
+        }
+        println!();
+        println!(
+            "Processed {} of {} devices: {} to {}",
+            targets.len(),
+            targets.len(),
+            targets.len(),
+            verb.to_lowercase()
+        );
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+    if targets.is_empty() {
+        println!("Nothing to {}.", verb.to_lowercase());
+        return Ok(());
+    }
+    if !config.yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        println!("{} {} stale devices?", verb, targets.len());
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    // Devices already being processed finish; no new ones are started after an interrupt.
+    let _phase = client.stats().phase(if config.delete { "delete devices" } else { "disable devices" });
+    let mut results = stream::iter(targets)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|device| async move {
+            let result = if config.delete {
+                remediation::delete_device(client, &device.id).await
+            } else {
+                remediation::set_device_enabled(client, &device.id, false).await
+            };
+            (device, result)
+        })
+        .buffer_unordered(config.concurrency as usize);
+    let mut finished = HashSet::new();
+    let mut changed = 0;
+    let mut failed = 0;
+    while let Some((device, result)) = results.next().await {
+        finished.insert(&device.id);
+        match result {
+            Ok(()) => {
+                changed += 1;
+                println!("OK      {}", device.display_name);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", device.display_name, e);
+            }
+        }
+    }
+
+    let interrupted = targets.len() - finished.len();
+    for device in targets.iter().filter(|device| !finished.contains(&device.id)) {
+        println!("SKIPPED {}: not started", device.display_name);
+    }
+    println!();
+    println!(
+        "Processed {} of {} devices: {} {}, {} failed, {} skipped",
+        targets.len() - interrupted,
+        targets.len(),
+        changed,
+        past,
+        failed,
+        interrupted
+    );
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} devices were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} devices failed", failed, targets.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_stale_devices");
+    debug!("Configuration: {:?}", config);
+
+    let remediating = config.disable || config.delete;
+    if remediating && config.output != "text" {
+        return Err(anyhow::anyhow!("--disable and --delete report their progress as text and cannot be combined with -o {}", config.output));
+    }
+    let exclusions = match &config.exclude {
+        Some(path) => read_exclusions(path)?,
+        None => HashSet::new(),
+    };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&[if remediating && !config.dry_run {
+        "Device.ReadWrite.All"
+    } else {
+        "Device.Read.All"
+    }])?;
+
+    let phase = client.stats().phase("list devices");
+    let devices = list_devices(&client).await?;
+    drop(phase);
+    let stale = stale_devices(&devices, Utc::now() - Duration::days(config.inactive_days), &exclusions);
+    info!("{} of {} devices are stale", stale.len(), devices.len());
+    print!("{}", render_report(&stale, devices.len(), &config.output, config.inactive_days, &client.stats().summary())?);
+
+    if remediating {
+        let targets = targets(&stale, config.delete);
+        let shutdown = service::shutdown_signal();
+        let result = remediate(&client, &targets, &config, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let devices = list_devices(&client).await.unwrap();
+        let exclusions = HashSet::from(["kiosk-lobby".to_string()]);
+        let stale = stale_devices(&devices, "2026-07-17T00:00:00Z".parse().unwrap(), &exclusions);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&stale, devices.len(), output, 90, &Summary::fixture()));
+
+        let names = |targets: Vec<&StaleDevice>| targets.iter().map(|device| device.display_name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(targets(&stale, false)), ["Jane's iPhone", "OLD-LAPTOP-07"]);
+        assert_eq!(names(targets(&stale, true)), ["Jane's iPhone", "RETIRED-PC-02", "OLD-LAPTOP-07"]);
+    }
+}
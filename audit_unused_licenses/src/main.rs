@@ -0,0 +1,355 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::licenses::{PriceList, SkuCatalog};
+use graph_common::reports::OfficeActivity;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// Suite licenses worth reclaiming; add-ons and free SKUs are left out unless asked for.
+const DEFAULT_SKUS: &[&str] = &[
+    "SPE_E3",
+    "SPE_E5",
+    "ENTERPRISEPACK",
+    "ENTERPRISEPREMIUM",
+    "SPB",
+    "O365_BUSINESS_PREMIUM",
+];
+
+#[derive(Parser, Debug)]
+#[command(name = "audit_unused_licenses")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports licensed users with no sign-in or Microsoft 365 activity in the last N days and the seats that could be reclaimed, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(i64).range(1..), help = "Flag users with no activity for this many days")]
+    inactive_days: i64,
+
+    #[arg(long, value_delimiter = ',', conflicts_with = "all_skus", help = "SKU part numbers to check (repeatable or comma separated); Microsoft 365 and Office 365 E3/E5 and Business Premium by default")]
+    sku: Vec<String>,
+
+    #[arg(long, help = "Check every assigned SKU")]
+    all_skus: bool,
+
+    #[arg(long, value_name = "FILE", help = "CSV of monthly license prices (SkuPartNumber, MonthlyPrice) used to estimate the savings")]
+    prices: Option<PathBuf>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "assignedLicenses", default)]
+    assigned_licenses: Vec<License>,
+    #[serde(rename = "signInActivity")]
+    sign_in_activity: Option<SignInActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct License {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignInActivity {
+    #[serde(rename = "lastSignInDateTime")]
+    last_sign_in_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastNonInteractiveSignInDateTime")]
+    last_non_interactive_sign_in_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+struct UnusedLicense {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: bool,
+    #[serde(rename = "lastSignIn")]
+    last_sign_in: Option<NaiveDate>,
+    #[serde(rename = "lastOfficeActivity")]
+    last_office_activity: Option<NaiveDate>,
+    #[serde(rename = "skuPartNumbers")]
+    sku_part_numbers: Vec<String>,
+    #[serde(rename = "monthlyCost")]
+    monthly_cost: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct SkuSummary {
+    sku: String,
+    #[serde(rename = "skuPartNumber")]
+    sku_part_number: String,
+    #[serde(rename = "consumedUnits")]
+    consumed_units: u32,
+    reclaimable: usize,
+    #[serde(rename = "monthlySavings")]
+    monthly_savings: Option<f64>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_users(client: &GraphClient) -> Result<Vec<User>> {
+    client
+        .users()
+        .select("userPrincipalName,displayName,accountEnabled,createdDateTime,assignedLicenses,signInActivity")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")
+}
+
+// A user is inactive when neither the last sign-in nor the last activity in any Microsoft 365
+// app is after the cutoff. Accounts created after the cutoff are new starters and skipped.
+fn unused_licenses(
+    users: Vec<User>,
+    activity: &OfficeActivity,
+    cutoff: DateTime<Utc>,
+    skus: &SkuCatalog,
+    checked: Option<&[String]>,
+    prices: Option<&PriceList>,
+) -> Vec<UnusedLicense> {
+    let mut unused = Vec::new();
+    for user in users {
+        let mut part_numbers: Vec<String> = user
+            .assigned_licenses
+            .iter()
+            .filter_map(|license| skus.get(&license.sku_id))
+            .map(|sku| sku.sku_part_number.clone())
+            .filter(|part_number| checked.is_none_or(|checked| checked.iter().any(|sku| sku.eq_ignore_ascii_case(part_number))))
+            .collect();
+        if part_numbers.is_empty() || user.created_date_time.is_some_and(|created| created >= cutoff) {
+            continue;
+        }
+        let activity_info = user.sign_in_activity.as_ref();
+        let last_sign_in = activity_info
+            .and_then(|activity| activity.last_sign_in_date_time)
+            .max(activity_info.and_then(|activity| activity.last_non_interactive_sign_in_date_time))
+            .map(|last| last.date_naive());
+        let last_office_activity = activity.last_activity(&user.user_principal_name);
+        if last_sign_in.max(last_office_activity).is_some_and(|last| last >= cutoff.date_naive()) {
+            continue;
+        }
+        part_numbers.sort();
+        unused.push(UnusedLicense {
+            monthly_cost: prices.and_then(|prices| {
+                let priced: Vec<f64> = part_numbers.iter().filter_map(|sku| prices.monthly_price(sku)).collect();
+                (!priced.is_empty()).then(|| priced.iter().sum())
+            }),
+            display_name: user.display_name.unwrap_or_default(),
+            account_enabled: user.account_enabled.unwrap_or(false),
+            last_sign_in,
+            last_office_activity,
+            sku_part_numbers: part_numbers,
+            user_principal_name: user.user_principal_name,
+        });
+    }
+    unused.sort_by(|a, b| a.user_principal_name.cmp(&b.user_principal_name));
+    unused
+}
+
+// Reclaimable seats per SKU, most first. SKUs missing from the price list have no savings.
+fn sku_summary(unused: &[UnusedLicense], skus: &SkuCatalog, prices: Option<&PriceList>) -> Vec<SkuSummary> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for user in unused {
+        for sku in &user.sku_part_numbers {
+            *counts.entry(sku).or_default() += 1;
+        }
+    }
+    let mut summary: Vec<SkuSummary> = counts
+        .into_iter()
+        .map(|(part_number, reclaimable)| {
+            let sku = skus.find(part_number);
+            SkuSummary {
+                sku: sku.map(|sku| sku.display_name()).unwrap_or_else(|| part_number.to_string()),
+                sku_part_number: part_number.to_string(),
+                consumed_units: sku.map_or(0, |sku| sku.consumed_units),
+                reclaimable,
+                monthly_savings: prices
+                    .and_then(|prices| prices.monthly_price(part_number))
+                    .map(|price| price * reclaimable as f64),
+            }
+        })
+        .collect();
+    summary.sort_by_key(|sku| std::cmp::Reverse(sku.reclaimable));
+    summary
+}
+
+fn date(value: Option<NaiveDate>) -> String {
+    value.map_or_else(|| "never".to_string(), |value| value.to_string())
+}
+
+fn render_report(
+    unused: &[UnusedLicense],
+    summary: &[SkuSummary],
+    output: &str,
+    inactive_days: i64,
+    run_stats: &Summary,
+) -> Result<String> {
+    let mut out = String::new();
+    let priced: Vec<f64> = summary.iter().filter_map(|sku| sku.monthly_savings).collect();
+    let total_savings: Option<f64> = (!priced.is_empty()).then(|| priced.iter().sum());
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "inactiveDays": inactive_days,
+                "unusedLicenses": unused,
+                "bySku": summary,
+                "monthlySavings": total_savings,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,displayName,accountEnabled,lastSignIn,lastOfficeActivity,skuPartNumbers,monthlyCost")?;
+            for user in unused {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv::field(&user.user_principal_name),
+                    csv::field(&user.display_name),
+                    user.account_enabled,
+                    user.last_sign_in.map(|value| value.to_string()).unwrap_or_default(),
+                    user.last_office_activity.map(|value| value.to_string()).unwrap_or_default(),
+                    csv::field(&user.sku_part_numbers.join("; ")),
+                    user.monthly_cost.map(|cost| format!("{:.2}", cost)).unwrap_or_default()
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<8} {:<12} {:<15} {:>12}  LICENSES",
+                "USER", "ENABLED", "LAST SIGN-IN", "LAST ACTIVITY", "MONTHLY COST"
+            )?;
+            for user in unused {
+                writeln!(
+                    out,
+                    "{:<35} {:<8} {:<12} {:<15} {:>12}  {}",
+                    user.user_principal_name,
+                    if user.account_enabled { "yes" } else { "no" },
+                    date(user.last_sign_in),
+                    date(user.last_office_activity),
+                    user.monthly_cost.map_or_else(|| "-".to_string(), |cost| format!("{:.2}", cost)),
+                    user.sku_part_numbers.join(", ")
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(out, "{} users have had no activity for {} days", unused.len(), inactive_days)?;
+            for sku in summary {
+                write!(out, "  {}: {} of {} seats reclaimable", sku.sku, sku.reclaimable, sku.consumed_units)?;
+                match sku.monthly_savings {
+                    Some(savings) => writeln!(out, ", {:.2} a month", savings)?,
+                    None => writeln!(out)?,
+                }
+            }
+            if let Some(savings) = total_savings {
+                writeln!(out, "Estimated savings: {:.2} a month", savings)?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting audit_unused_licenses");
+    debug!("Configuration: {:?}", config);
+
+    let prices = config.prices.as_deref().map(PriceList::load).transpose()?;
+    let checked: Option<Vec<String>> = match (config.all_skus, config.sku.is_empty()) {
+        (true, _) => None,
+        (false, true) => Some(DEFAULT_SKUS.iter().map(|sku| sku.to_string()).collect()),
+        (false, false) => Some(config.sku.clone()),
+    };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "AuditLog.Read.All", "Reports.Read.All", "Organization.Read.All"])?;
+
+    let phase = client.stats().phase("list users");
+    let users = list_users(&client).await?;
+    let skus = SkuCatalog::load(&client).await?;
+    drop(phase);
+    let phase = client.stats().phase("read activity report");
+    let activity = OfficeActivity::load(&client).await?;
+    drop(phase);
+    if !activity.is_empty() && !users.iter().any(|user| activity.contains(&user.user_principal_name)) {
+        warn!("No user in the activity report matches a user principal name; the tenant conceals user names in reports, so only sign-ins are used");
+    }
+
+    let cutoff = Utc::now() - Duration::days(config.inactive_days);
+    let unused = unused_licenses(users, &activity, cutoff, &skus, checked.as_deref(), prices.as_ref());
+    let summary = sku_summary(&unused, &skus, prices.as_ref());
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&unused, &summary, &config.output, config.inactive_days, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let users = list_users(&client).await.unwrap();
+        let skus = SkuCatalog::load(&client).await.unwrap();
+        let activity = OfficeActivity::load(&client).await.unwrap();
+        let prices = PriceList::load(&golden::testdata("prices.csv")).unwrap();
+        let checked: Vec<String> = DEFAULT_SKUS.iter().map(|sku| sku.to_string()).collect();
+        let unused = unused_licenses(
+            users,
+            &activity,
+            "2026-07-17T00:00:00Z".parse().unwrap(),
+            &skus,
+            Some(&checked),
+            Some(&prices),
+        );
+        let summary = sku_summary(&unused, &skus, Some(&prices));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&unused, &summary, output, 90, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,442 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::{csv, mail, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::env;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[command(name = "automatic_replies")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Turns automatic replies (out of office) on or off for users using the Microsoft API")]
+#[command(group(ArgGroup::new("targets").required(true).multiple(true).args(["upn", "file"])))]
+#[command(group(ArgGroup::new("mode").required(true).args(["message", "disable"])))]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "User Principal Name (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "CSV with a upn column and optional start and end columns (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(short, long, help = "Template file of the reply to senders inside the organization (.html is sent as is, anything else as plain text)")]
+    message: Option<String>,
+
+    #[arg(long, requires = "message", help = "Template file of the reply to external senders (defaults to --message)")]
+    external_message: Option<String>,
+
+    #[arg(long, default_value = "all", value_parser = ["all", "contactsOnly", "none"], help = "External senders who get a reply")]
+    external_audience: String,
+
+    #[arg(long, requires = "end", conflicts_with = "disable", help = "When replies start, as YYYY-MM-DD or YYYY-MM-DDTHH:MM (defaults to now)")]
+    start: Option<String>,
+
+    #[arg(long, conflicts_with = "disable", help = "When replies stop, as YYYY-MM-DD or YYYY-MM-DDTHH:MM; a date alone means midnight at its start")]
+    end: Option<String>,
+
+    #[arg(long, help = "Time zone of --start and --end, e.g. \"GMT Standard Time\" (defaults to each mailbox's time zone)")]
+    time_zone: Option<String>,
+
+    #[arg(long, help = "Turn automatic replies off")]
+    disable: bool,
+
+    #[arg(long, help = "Show the changes without making them")]
+    dry_run: bool,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Target {
+    upn: String,
+    schedule: Option<Schedule>,
+}
+
+// Times are local to the time zone, which is applied when the mailbox's own zone is known.
+// Without a start, replies start immediately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Schedule {
+    start: Option<NaiveDateTime>,
+    end: NaiveDateTime,
+}
+
+struct Templates {
+    internal: String,
+    external: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct UserNames {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "givenName")]
+    given_name: Option<String>,
+}
+
+enum Outcome {
+    Changed(String),
+    Unchanged(String),
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn parse_time(value: &str) -> Result<NaiveDateTime> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default());
+    }
+    NaiveDateTime::parse_from_str(&value.replacen(' ', "T", 1), "%Y-%m-%dT%H:%M")
+        .with_context(|| format!("{:?} is not a date (YYYY-MM-DD) or time (YYYY-MM-DDTHH:MM)", value))
+}
+
+fn parse_schedule(start: Option<&str>, end: Option<&str>) -> Result<Option<Schedule>> {
+    let start = start.filter(|value| !value.trim().is_empty()).map(parse_time).transpose()?;
+    let Some(end) = end.filter(|value| !value.trim().is_empty()).map(parse_time).transpose()? else {
+        if start.is_some() {
+            return Err(anyhow::anyhow!("A start time needs an end time"));
+        }
+        return Ok(None);
+    };
+    if start.is_some_and(|start| start >= end) {
+        return Err(anyhow::anyhow!("The end time must be after the start time"));
+    }
+    Ok(Some(Schedule { start, end }))
+}
+
+// A CSV row's start and end override the ones on the command line.
+fn read_targets(config: &AppConfig) -> Result<Vec<Target>> {
+    let default = parse_schedule(config.start.as_deref(), config.end.as_deref())?;
+    let mut targets: Vec<Target> = config
+        .upn
+        .iter()
+        .map(|upn| Target {
+            upn: upn.trim().to_string(),
+            schedule: default,
+        })
+        .collect();
+    if let Some(file) = &config.file {
+        let content = if file == "-" {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .context("Failed to read users from stdin")?;
+            content
+        } else {
+            std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?
+        };
+        let table = csv::Table::parse(&content)?;
+        let upn = table.upn_column()?;
+        let start = table.column(&["start"]);
+        let end = table.column(&["end"]);
+        for record in &table.records {
+            let cell = |column: Option<usize>| column.map(|column| record.get(column)).filter(|value| !value.is_empty());
+            let schedule = match (cell(start), cell(end)) {
+                (None, None) => default,
+                (start, end) => parse_schedule(start, end).with_context(|| format!("Line {}", record.line))?,
+            };
+            if config.disable && schedule.is_some() {
+                return Err(anyhow::anyhow!("Line {}: start and end cannot be used with --disable", record.line));
+            }
+            targets.push(Target {
+                upn: record.get(upn).to_string(),
+                schedule,
+            });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    targets.retain(|target| !target.upn.is_empty() && seen.insert(target.upn.to_lowercase()));
+    if targets.is_empty() {
+        return Err(anyhow::anyhow!("No users to process"));
+    }
+    Ok(targets)
+}
+
+// Graph stores replies as HTML, so plain text is escaped and its line breaks kept.
+fn to_html(text: &str) -> String {
+    text.trim_end()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>\n")
+}
+
+fn read_template(file: &str) -> Result<String> {
+    let content = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+    let html = Path::new(file)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"));
+    Ok(if html { content } else { to_html(&content) })
+}
+
+fn uses_names(template: &str) -> bool {
+    template.contains("{displayName}") || template.contains("{givenName}")
+}
+
+fn render(template: &str, names: &UserNames, schedule: Option<&Schedule>) -> String {
+    let date = |value: Option<NaiveDateTime>| value.map_or(String::new(), |value| value.format("%-d %B %Y").to_string());
+    template
+        .replace("{displayName}", names.display_name.as_deref().unwrap_or_default())
+        .replace("{givenName}", names.given_name.as_deref().unwrap_or_default())
+        .replace("{startDate}", &date(schedule.and_then(|schedule| schedule.start)))
+        .replace("{endDate}", &date(schedule.map(|schedule| schedule.end)))
+}
+
+fn date_time(value: NaiveDateTime, time_zone: &str) -> Value {
+    json!({
+        "dateTime": value.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        "timeZone": time_zone,
+    })
+}
+
+// Returns the automaticRepliesSetting to send and a description of it.
+fn build_setting(
+    templates: &Templates,
+    external_audience: &str,
+    schedule: Option<&Schedule>,
+    time_zone: &str,
+    names: &UserNames,
+    now: DateTime<Utc>,
+) -> (Value, String) {
+    let mut setting = json!({
+        "status": "alwaysEnabled",
+        "externalAudience": external_audience,
+        "internalReplyMessage": render(&templates.internal, names, schedule),
+        "externalReplyMessage": render(&templates.external, names, schedule),
+    });
+    let Some(schedule) = schedule else {
+        return (setting, "enabled".to_string());
+    };
+    setting["status"] = json!("scheduled");
+    setting["scheduledStartDateTime"] = match schedule.start {
+        Some(start) => date_time(start, time_zone),
+        None => date_time(now.naive_utc(), "UTC"),
+    };
+    setting["scheduledEndDateTime"] = date_time(schedule.end, time_zone);
+    let description = match schedule.start {
+        Some(start) => format!(
+            "scheduled from {} to {} ({})",
+            start.format("%Y-%m-%d %H:%M"),
+            schedule.end.format("%Y-%m-%d %H:%M"),
+            time_zone
+        ),
+        None => format!("enabled until {} ({})", schedule.end.format("%Y-%m-%d %H:%M"), time_zone),
+    };
+    (setting, description)
+}
+
+async fn process(client: &GraphClient, config: &AppConfig, templates: Option<&Templates>, target: &Target) -> Result<Outcome> {
+    let previous = mail::mailbox_settings(client, &target.upn).await?;
+    let setting = match templates {
+        None => {
+            if previous["automaticRepliesSetting"]["status"] == "disabled" {
+                return Ok(Outcome::Unchanged("already disabled".to_string()));
+            }
+            (json!({ "status": "disabled" }), "disabled".to_string())
+        }
+        Some(templates) => {
+            let names = if uses_names(&templates.internal) || uses_names(&templates.external) {
+                client
+                    .user(&target.upn)
+                    .resource()
+                    .select("displayName,givenName")
+                    .get_json()
+                    .await
+                    .with_context(|| format!("Failed to look up {}", target.upn))?
+            } else {
+                UserNames::default()
+            };
+            let time_zone = config
+                .time_zone
+                .as_deref()
+                .or(previous["timeZone"].as_str().filter(|zone| !zone.is_empty()))
+                .unwrap_or("UTC");
+            build_setting(
+                templates,
+                &config.external_audience,
+                target.schedule.as_ref(),
+                time_zone,
+                &names,
+                Utc::now(),
+            )
+        }
+    };
+    let (setting, description) = setting;
+    if !config.dry_run {
+        mail::update_automatic_replies(client, &target.upn, &previous, setting).await?;
+    }
+    Ok(Outcome::Changed(description))
+}
+
+async fn run(
+    client: &GraphClient,
+    config: &AppConfig,
+    templates: Option<&Templates>,
+    targets: &[Target],
+    shutdown: &ShutdownSignal,
+) -> Result<()> {
+    let _phase = client.stats().phase("set automatic replies");
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let mut results = stream::iter(targets)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|target| async move { (target, process(client, config, templates, target).await) })
+        .buffer_unordered(config.concurrency as usize);
+
+    let mut finished = HashSet::new();
+    let (mut changed, mut unchanged, mut failed) = (0, 0, 0);
+    while let Some((target, result)) = results.next().await {
+        finished.insert(target.upn.to_lowercase());
+        match result {
+            Ok(Outcome::Changed(description)) => {
+                changed += 1;
+                println!("{:<8}{}: {}", if config.dry_run { "CHANGE" } else { "OK" }, target.upn, description);
+            }
+            Ok(Outcome::Unchanged(reason)) => {
+                unchanged += 1;
+                println!("SKIPPED {}: {}", target.upn, reason);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", target.upn, e);
+            }
+        }
+    }
+
+    let total = targets.len();
+    let interrupted = total - finished.len();
+    for target in targets.iter().filter(|target| !finished.contains(&target.upn.to_lowercase())) {
+        println!("SKIPPED {}: not started", target.upn);
+    }
+    println!();
+    println!(
+        "Processed {} of {} users: {} {}, {} unchanged, {} failed, {} skipped",
+        total - interrupted,
+        total,
+        changed,
+        if config.dry_run { "to change" } else { "changed" },
+        unchanged,
+        failed,
+        interrupted
+    );
+    if config.dry_run {
+        println!("Dry run, no changes were made.");
+    }
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} users were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, total));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting automatic_replies");
+    debug!("Configuration: {:?}", config);
+
+    let templates = match &config.message {
+        Some(file) => {
+            let internal = read_template(file)?;
+            let external = match &config.external_message {
+                Some(file) => read_template(file)?,
+                None => internal.clone(),
+            };
+            Some(Templates { internal, external })
+        }
+        None => None,
+    };
+    let targets = read_targets(&config)?;
+    info!("Processing {} users", targets.len());
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec![if config.dry_run { "MailboxSettings.Read" } else { "MailboxSettings.ReadWrite" }];
+    if templates
+        .as_ref()
+        .is_some_and(|templates| uses_names(&templates.internal) || uses_names(&templates.external))
+    {
+        required.push("User.ReadBasic.All|User.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let shutdown = service::shutdown_signal();
+    let result = run(&client, &config, templates.as_ref(), &targets, &shutdown).await;
+    client.stats().summary().print();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_scheduled_replies_from_templates() {
+        let schedule = parse_schedule(Some("2026-10-16 09:00"), Some("2026-10-26")).unwrap().unwrap();
+        assert!(parse_schedule(Some("2026-10-26"), Some("2026-10-16")).is_err());
+        assert!(parse_schedule(Some("2026-10-16"), None).is_err());
+        assert_eq!(parse_schedule(None, None).unwrap(), None);
+
+        let templates = Templates {
+            internal: to_html("Hi,\n{givenName} is away until {endDate} & has no email access.\n"),
+            external: "<p>{displayName} is out of the office.</p>".to_string(),
+        };
+        let names = UserNames {
+            display_name: Some("Jane Doe".to_string()),
+            given_name: Some("Jane".to_string()),
+        };
+        let now = "2026-10-15T08:00:00Z".parse().unwrap();
+        let (setting, description) =
+            build_setting(&templates, "contactsOnly", Some(&schedule), "GMT Standard Time", &names, now);
+        assert_eq!(
+            setting,
+            json!({
+                "status": "scheduled",
+                "externalAudience": "contactsOnly",
+                "internalReplyMessage": "Hi,<br>\nJane is away until 26 October 2026 &amp; has no email access.",
+                "externalReplyMessage": "<p>Jane Doe is out of the office.</p>",
+                "scheduledStartDateTime": { "dateTime": "2026-10-16T09:00:00", "timeZone": "GMT Standard Time" },
+                "scheduledEndDateTime": { "dateTime": "2026-10-26T00:00:00", "timeZone": "GMT Standard Time" },
+            })
+        );
+        assert_eq!(description, "scheduled from 2026-10-16 09:00 to 2026-10-26 00:00 (GMT Standard Time)");
+
+        let open_ended = Schedule { start: None, end: schedule.end };
+        let (setting, description) = build_setting(&templates, "all", Some(&open_ended), "UTC", &names, now);
+        assert_eq!(setting["scheduledStartDateTime"]["dateTime"], "2026-10-15T08:00:00");
+        assert_eq!(description, "enabled until 2026-10-26 00:00 (UTC)");
+        let (setting, description) = build_setting(&templates, "all", None, "UTC", &names, now);
+        assert_eq!(setting["status"], "alwaysEnabled");
+        assert!(setting.get("scheduledEndDateTime").is_none());
+        assert_eq!(description, "enabled");
+    }
+}
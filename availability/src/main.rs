@@ -0,0 +1,501 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{client, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::fmt::Write as _;
+
+#[derive(Parser, Debug)]
+#[command(name = "availability")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Checks free/busy for users and rooms and finds and books meeting times using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Show the free/busy of users and rooms in a time window")]
+    Check {
+        #[arg(short, long, required = true, help = "User or room email address (repeatable)")]
+        schedule: Vec<String>,
+
+        #[command(flatten)]
+        window: Window,
+
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(5..=1440), help = "Length of each slot in minutes")]
+        interval: i64,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+    },
+
+    #[command(about = "Suggest meeting times for you and the attendees, optionally in one of the rooms, and book the first")]
+    Find {
+        #[arg(short, long, help = "Attendee email address (repeatable)")]
+        attendee: Vec<String>,
+
+        #[arg(short, long, help = "Room email address; suggestions need one of the rooms to be free (repeatable)")]
+        room: Vec<String>,
+
+        #[command(flatten)]
+        window: Window,
+
+        #[arg(short, long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(5..=1440), help = "Meeting length in minutes")]
+        duration: i64,
+
+        #[arg(long, help = "Also suggest times outside working hours")]
+        any_time: bool,
+
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..=20), help = "Maximum number of suggestions")]
+        max: u32,
+
+        #[arg(long, requires = "subject", help = "Book the first suggestion, with the first free room, and invite the attendees")]
+        book: bool,
+
+        #[arg(long, help = "Subject of the booked meeting")]
+        subject: Option<String>,
+
+        #[arg(long, requires = "book", help = "Add a Microsoft Teams meeting link to the booked meeting")]
+        teams: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+struct Window {
+    #[arg(long, help = "Start of the window, as YYYY-MM-DDTHH:MM in --time-zone")]
+    start: String,
+
+    #[arg(long, help = "End of the window, as YYYY-MM-DDTHH:MM in --time-zone")]
+    end: String,
+
+    #[arg(long, default_value = "UTC", help = "Time zone of the window and of the times shown, e.g. \"GMT Standard Time\"")]
+    time_zone: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScheduleList {
+    value: Vec<ScheduleInformation>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct ScheduleInformation {
+    #[serde(rename = "scheduleId")]
+    schedule_id: String,
+    #[serde(rename = "availabilityView", default)]
+    availability_view: String,
+    #[serde(rename = "scheduleItems", default)]
+    schedule_items: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MeetingTimeSuggestions {
+    #[serde(rename = "meetingTimeSuggestions", default)]
+    suggestions: Vec<Suggestion>,
+    #[serde(rename = "emptySuggestionsReason")]
+    empty_suggestions_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Suggestion {
+    confidence: Option<f64>,
+    #[serde(rename = "meetingTimeSlot")]
+    meeting_time_slot: TimeSlot,
+    #[serde(default)]
+    locations: Vec<Location>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TimeSlot {
+    start: DateTimeTimeZone,
+    end: DateTimeTimeZone,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct DateTimeTimeZone {
+    #[serde(rename = "dateTime")]
+    date_time: String,
+    #[serde(rename = "timeZone")]
+    time_zone: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Location {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "locationEmailAddress")]
+    location_email_address: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn parse_time(value: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(&value.trim().replacen(' ', "T", 1), "%Y-%m-%dT%H:%M")
+        .with_context(|| format!("{:?} is not a time (YYYY-MM-DDTHH:MM)", value))
+}
+
+fn parse_window(window: &Window) -> Result<(NaiveDateTime, NaiveDateTime)> {
+    let (start, end) = (parse_time(&window.start)?, parse_time(&window.end)?);
+    if end <= start {
+        return Err(anyhow::anyhow!("--end must be after --start"));
+    }
+    if end - start > Duration::days(62) {
+        return Err(anyhow::anyhow!("The window can be at most 62 days"));
+    }
+    Ok((start, end))
+}
+
+fn date_time(value: NaiveDateTime, time_zone: &str) -> Value {
+    json!({
+        "dateTime": value.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        "timeZone": time_zone,
+    })
+}
+
+fn short_time(value: &str) -> String {
+    value.chars().take(16).collect::<String>().replace('T', " ")
+}
+
+// getSchedule and findMeetingTimes are POSTs but change nothing, so they are sent without
+// being recorded in the action log.
+async fn post_query<T: serde::de::DeserializeOwned>(client: &GraphClient, url: &str, body: &Value) -> Result<T> {
+    let response = client.send(|http| http.post(url).json(body)).await?;
+    client::parse_json(response).await
+}
+
+async fn get_schedule(
+    client: &GraphClient,
+    schedules: &[String],
+    window: (NaiveDateTime, NaiveDateTime),
+    time_zone: &str,
+    interval: i64,
+) -> Result<Vec<ScheduleInformation>> {
+    let url = client
+        .user(&schedules[0])
+        .resource()
+        .segment("calendar")
+        .segment("getSchedule")
+        .url();
+    let body = json!({
+        "schedules": schedules,
+        "startTime": date_time(window.0, time_zone),
+        "endTime": date_time(window.1, time_zone),
+        "availabilityViewInterval": interval,
+    });
+    let list: ScheduleList = post_query(client, &url, &body)
+        .await
+        .context("Failed to read free/busy")?;
+    Ok(list.value)
+}
+
+// Ranges of slots in which every schedule is free, as (start, end) times. A schedule that
+// could not be read is not free.
+fn free_for_everyone(schedules: &[ScheduleInformation], start: NaiveDateTime, interval: i64, slots: usize) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let is_free = |slot: usize| {
+        schedules
+            .iter()
+            .all(|schedule| schedule.error.is_none() && schedule.availability_view.as_bytes().get(slot) == Some(&b'0'))
+    };
+    let mut ranges: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+    for slot in (0..slots).filter(|&slot| is_free(slot)) {
+        let slot_start = start + Duration::minutes(interval * slot as i64);
+        let slot_end = slot_start + Duration::minutes(interval);
+        match ranges.last_mut() {
+            Some(last) if last.1 == slot_start => last.1 = slot_end,
+            _ => ranges.push((slot_start, slot_end)),
+        }
+    }
+    ranges
+}
+
+fn render_check(
+    schedules: &[ScheduleInformation],
+    window: (NaiveDateTime, NaiveDateTime),
+    time_zone: &str,
+    interval: i64,
+    output: &str,
+) -> Result<String> {
+    let slots = ((window.1 - window.0).num_minutes() as f64 / interval as f64).ceil() as usize;
+    let free = free_for_everyone(schedules, window.0, interval, slots);
+    let mut out = String::new();
+    if output == "json" {
+        let free: Vec<Value> = free
+            .iter()
+            .map(|(start, end)| json!({ "start": date_time(*start, time_zone), "end": date_time(*end, time_zone) }))
+            .collect();
+        out = serde_json::to_string_pretty(&json!({
+            "schedules": schedules,
+            "freeForEveryone": free,
+            "intervalMinutes": interval,
+        }))?;
+        out.push('\n');
+        return Ok(out);
+    }
+
+    writeln!(
+        out,
+        "{} to {} ({}), {} minute slots: . free, t tentative, # busy, o out of office, w working elsewhere",
+        window.0.format("%Y-%m-%d %H:%M"),
+        window.1.format("%Y-%m-%d %H:%M"),
+        time_zone,
+        interval
+    )?;
+    writeln!(out)?;
+    for schedule in schedules {
+        let view: String = match &schedule.error {
+            Some(error) => format!("error: {}", error["message"].as_str().unwrap_or("unknown")),
+            None => schedule
+                .availability_view
+                .chars()
+                .map(|c| match c {
+                    '0' => '.',
+                    '1' => 't',
+                    '2' => '#',
+                    '3' => 'o',
+                    '4' => 'w',
+                    _ => '?',
+                })
+                .collect(),
+        };
+        writeln!(out, "{:<35} {}", schedule.schedule_id, view)?;
+    }
+    writeln!(out)?;
+    if free.is_empty() {
+        writeln!(out, "No time in the window is free for everyone")?;
+    } else {
+        let ranges: Vec<String> = free
+            .iter()
+            .map(|(start, end)| {
+                if start.date() == window.0.date() && end.date() == window.0.date() {
+                    format!("{}-{}", start.format("%H:%M"), end.format("%H:%M"))
+                } else {
+                    format!("{} to {}", start.format("%Y-%m-%d %H:%M"), end.format("%Y-%m-%d %H:%M"))
+                }
+            })
+            .collect();
+        writeln!(out, "Free for everyone: {}", ranges.join(", "))?;
+    }
+    Ok(out)
+}
+
+fn find_body(attendees: &[String], rooms: &[String], window: (NaiveDateTime, NaiveDateTime), time_zone: &str, duration: i64, any_time: bool, max: u32) -> Value {
+    let mut body = json!({
+        "attendees": attendees
+            .iter()
+            .map(|address| json!({ "type": "required", "emailAddress": { "address": address } }))
+            .collect::<Vec<_>>(),
+        "timeConstraint": {
+            "activityDomain": if any_time { "unrestricted" } else { "work" },
+            "timeSlots": [{ "start": date_time(window.0, time_zone), "end": date_time(window.1, time_zone) }],
+        },
+        "meetingDuration": format!("PT{}M", duration),
+        "maxCandidates": max,
+        "isOrganizerOptional": false,
+        "returnSuggestionReasons": true,
+    });
+    if !rooms.is_empty() {
+        body["locationConstraint"] = json!({
+            "isRequired": true,
+            "suggestLocation": false,
+            "locations": rooms
+                .iter()
+                .map(|room| json!({ "resolveAvailability": true, "displayName": room, "locationEmailAddress": room }))
+                .collect::<Vec<_>>(),
+        });
+    }
+    body
+}
+
+fn room_name(location: &Location) -> String {
+    location
+        .display_name
+        .clone()
+        .or_else(|| location.location_email_address.clone())
+        .unwrap_or_default()
+}
+
+async fn book(
+    client: &GraphClient,
+    suggestion: &Suggestion,
+    attendees: &[String],
+    subject: &str,
+    teams: bool,
+) -> Result<()> {
+    let room = suggestion.locations.iter().find(|location| location.location_email_address.is_some());
+    let mut invited: Vec<Value> = attendees
+        .iter()
+        .map(|address| json!({ "type": "required", "emailAddress": { "address": address } }))
+        .collect();
+    let mut body = json!({
+        "subject": subject,
+        "start": suggestion.meeting_time_slot.start,
+        "end": suggestion.meeting_time_slot.end,
+        "transactionId": format!(
+            "availability-{}-{}",
+            suggestion.meeting_time_slot.start.date_time,
+            subject
+        ),
+    });
+    if let Some(room) = room {
+        invited.push(json!({ "type": "resource", "emailAddress": { "address": room.location_email_address } }));
+        body["location"] = json!({ "displayName": room_name(room), "locationEmailAddress": room.location_email_address });
+    }
+    body["attendees"] = json!(invited);
+    if teams {
+        body["isOnlineMeeting"] = json!(true);
+        body["onlineMeetingProvider"] = json!("teamsForBusiness");
+    }
+    let response = client.path(&["me", "events"]).post_json(&body).await?;
+    let event: Value = client::parse_json(response).await.context("Failed to book the meeting")?;
+    println!(
+        "OK      booked \"{}\" {} to {}{}",
+        subject,
+        short_time(&suggestion.meeting_time_slot.start.date_time),
+        short_time(&suggestion.meeting_time_slot.end.date_time),
+        room.map_or(String::new(), |room| format!(" in {}", room_name(room)))
+    );
+    println!("id: {}", event["id"].as_str().unwrap_or_default());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting availability");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+
+    match &config.command {
+        Commands::Check {
+            schedule,
+            window,
+            interval,
+            output,
+        } => {
+            let range = parse_window(window)?;
+            let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+            let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+                .await
+                .context("Failed to obtain access token")?;
+            client.require_permissions(&["Calendars.Read|Calendars.ReadBasic"])?;
+            let schedules = get_schedule(&client, schedule, range, &window.time_zone, *interval).await?;
+            print!("{}", render_check(&schedules, range, &window.time_zone, *interval, output)?);
+            if output != "json" {
+                client.stats().summary().print();
+            }
+        }
+        Commands::Find {
+            attendee,
+            room,
+            window,
+            duration,
+            any_time,
+            max,
+            book: book_first,
+            subject,
+            teams,
+        } => {
+            let range = parse_window(window)?;
+            // findMeetingTimes only accepts delegated tokens: it suggests times for the
+            // signed-in user, who also organizes the booked meeting.
+            let client = GraphClient::from_device_code(&tenant_id, &client_id)
+                .await
+                .context("Failed to obtain access token")?
+                .with_header("Prefer", &format!("outlook.timezone=\"{}\"", window.time_zone));
+            client.require_permissions(&[if *book_first { "Calendars.ReadWrite" } else { "Calendars.Read.Shared|Calendars.Read" }])?;
+
+            let url = client.path(&["me", "findMeetingTimes"]).url();
+            let body = find_body(attendee, room, range, &window.time_zone, *duration, *any_time, *max);
+            let found: MeetingTimeSuggestions = post_query(&client, &url, &body)
+                .await
+                .context("Failed to find meeting times")?;
+            if found.suggestions.is_empty() {
+                println!(
+                    "No meeting times found{}",
+                    found
+                        .empty_suggestions_reason
+                        .filter(|reason| !reason.is_empty())
+                        .map_or(String::new(), |reason| format!(": {}", reason))
+                );
+            }
+            for suggestion in &found.suggestions {
+                let rooms: Vec<String> = suggestion.locations.iter().map(room_name).filter(|name| !name.is_empty()).collect();
+                let line = format!(
+                    "{} to {} ({})  {:>3.0}%  {}",
+                    short_time(&suggestion.meeting_time_slot.start.date_time),
+                    short_time(&suggestion.meeting_time_slot.end.date_time),
+                    suggestion.meeting_time_slot.start.time_zone,
+                    suggestion.confidence.unwrap_or_default(),
+                    rooms.join(", ")
+                );
+                println!("{}", line.trim_end());
+            }
+            if *book_first {
+                let first = found.suggestions.first().context("Nothing to book")?;
+                book(&client, first, attendee, subject.as_deref().unwrap_or_default(), *teams).await?;
+            }
+            client.stats().summary().print();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_time_free_for_everyone() {
+        let schedules: Vec<ScheduleInformation> = serde_json::from_value(json!([
+            { "scheduleId": "jane.doe@contoso.com", "availabilityView": "0020000011" },
+            { "scheduleId": "room-1@contoso.com", "availabilityView": "0000220000" },
+            { "scheduleId": "john.smith@contoso.com", "availabilityView": "0000003000" },
+        ]))
+        .unwrap();
+        let start = parse_time("2026-10-20T09:00").unwrap();
+        let end = parse_time("2026-10-20T14:00").unwrap();
+        let rendered = render_check(&schedules, (start, end), "GMT Standard Time", 30, "text").unwrap();
+        assert_eq!(
+            rendered,
+            "2026-10-20 09:00 to 2026-10-20 14:00 (GMT Standard Time), 30 minute slots: . free, t tentative, # busy, o out of office, w working elsewhere\n\
+             \n\
+             jane.doe@contoso.com                ..#.....tt\n\
+             room-1@contoso.com                  ....##....\n\
+             john.smith@contoso.com              ......o...\n\
+             \n\
+             Free for everyone: 09:00-10:00, 10:30-11:00, 12:30-13:00\n"
+        );
+
+        let unreadable: Vec<ScheduleInformation> = serde_json::from_value(json!([
+            { "scheduleId": "jane.doe@contoso.com", "availabilityView": "0000" },
+            { "scheduleId": "partner@fabrikam.example", "error": { "message": "Mailbox not found" } },
+        ]))
+        .unwrap();
+        assert!(free_for_everyone(&unreadable, start, 30, 4).is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::{confirm, is_object_id, read_upns};
+use graph_common::{encode, remediation, service, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::io::IsTerminal;
+
+const USER_SELECT: &str = "id,userPrincipalName,displayName,department,accountEnabled";
+
+#[derive(Parser, Debug)]
+#[command(name = "block_signin")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Blocks or unblocks sign-in for users using the Microsoft API")]
+#[command(group(ArgGroup::new("mode").required(true).args(["block", "unblock"])))]
+#[command(group(ArgGroup::new("targets").required(true).multiple(true).args(["upn", "file", "filter", "group"])))]
+struct AppConfig {
+    #[arg(long, help = "Block sign-in (accountEnabled=false)")]
+    block: bool,
+
+    #[arg(long, help = "Unblock sign-in (accountEnabled=true)")]
+    unblock: bool,
+
+    #[arg(short, long, value_delimiter = ',', help = "User Principal Name (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "File of UPNs, one per line or a CSV with a upn column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(long, help = "OData $filter selecting users, e.g. \"department eq 'Sales'\"")]
+    filter: Option<String>,
+
+    #[arg(short, long, help = "Group display name or object id; all members, including nested groups, are targeted")]
+    group: Option<String>,
+
+    #[arg(long, help = "List the accounts that would change without changing them")]
+    dry_run: bool,
+
+    #[arg(short, long, help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct User {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    department: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn resolve_group(client: &GraphClient, group: &str) -> Result<Group> {
+    if is_object_id(group) {
+        return client
+            .path(&["groups", group])
+            .select("id,displayName")
+            .get_json::<Group>()
+            .await
+            .with_context(|| format!("Failed to look up group {}", group));
+    }
+
+    let mut groups = client
+        .path(&["groups"])
+        .filter(&format!("displayName eq {}", encode::odata_string(group)))
+        .select("id,displayName")
+        .get_all::<Group>()
+        .await
+        .with_context(|| format!("Failed to look up group {}", group))?;
+    match groups.len() {
+        0 => Err(anyhow::anyhow!("No group named {}", group)),
+        1 => Ok(groups.remove(0)),
+        count => Err(anyhow::anyhow!(
+            "{} groups are named {}; pass the group's object id instead",
+            count,
+            group
+        )),
+    }
+}
+
+// Returns the matched users plus the UPNs that could not be looked up.
+async fn resolve_users(client: &GraphClient, config: &AppConfig) -> Result<(Vec<User>, Vec<(String, anyhow::Error)>)> {
+    let mut upns: Vec<String> = config.upn.iter().map(|upn| upn.trim().to_string()).collect();
+    if let Some(file) = &config.file {
+        upns.extend(read_upns(file)?);
+    }
+    upns.retain(|upn| !upn.is_empty());
+
+    let mut users = Vec::new();
+    let mut unresolved = Vec::new();
+    let lookups: Vec<_> = stream::iter(&upns)
+        .map(|upn| async move {
+            let user = client
+                .user(upn)
+                .resource()
+                .select(USER_SELECT)
+                .get_json::<User>()
+                .await
+                .with_context(|| format!("Failed to look up {}", upn));
+            (upn.clone(), user)
+        })
+        .buffered(config.concurrency as usize)
+        .collect()
+        .await;
+    for (upn, user) in lookups {
+        match user {
+            Ok(user) => users.push(user),
+            Err(e) => unresolved.push((upn, e)),
+        }
+    }
+
+    if let Some(filter) = &config.filter {
+        let matched = client
+            .users()
+            .filter(filter)
+            .select(USER_SELECT)
+            .top(999)
+            .get_all::<User>()
+            .await
+            .with_context(|| format!("Failed to list users matching {}", filter))?;
+        info!("{} users match the filter {}", matched.len(), filter);
+        users.extend(matched);
+    }
+
+    if let Some(group) = &config.group {
+        let group = resolve_group(client, group).await?;
+        let members = client
+            .path(&["groups", &group.id, "transitiveMembers", "microsoft.graph.user"])
+            .select(USER_SELECT)
+            .top(999)
+            .get_all::<User>()
+            .await
+            .with_context(|| format!("Failed to list members of group {}", group.id))?;
+        info!(
+            "{} users are members of {}",
+            members.len(),
+            group.display_name.as_deref().unwrap_or(&group.id)
+        );
+        users.extend(members);
+    }
+
+    let mut seen = HashSet::new();
+    users.retain(|user| seen.insert(user.id.clone()));
+    Ok((users, unresolved))
+}
+
+fn print_targets(users: &[User], enable: bool) {
+    for user in users {
+        let state = match user.account_enabled {
+            Some(enabled) if enabled == enable => "no change",
+            Some(true) => "enabled -> blocked",
+            Some(false) => "blocked -> enabled",
+            None => "unknown",
+        };
+        println!(
+            "  {}  {} | {} | {}",
+            user.user_principal_name,
+            user.display_name.as_deref().unwrap_or("-"),
+            user.department.as_deref().unwrap_or("-"),
+            state
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting block_signin");
+    debug!("Configuration: {:?}", config);
+
+    let enable = config.unblock;
+    let verb = if enable { "unblock" } else { "block" };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec!["User.ReadWrite.All"];
+    if config.group.is_some() {
+        required.push("GroupMember.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let phase = client.stats().phase("resolve users");
+    let (users, unresolved) = resolve_users(&client, &config).await?;
+    drop(phase);
+    for (upn, e) in &unresolved {
+        warn!("{}: {:#}", upn, e);
+    }
+    if users.is_empty() {
+        return Err(anyhow::anyhow!("No users matched the given targets"));
+    }
+
+    let pending = users
+        .iter()
+        .filter(|user| user.account_enabled != Some(enable))
+        .count();
+    println!("{} users matched, {} to {}:", users.len(), pending, verb);
+    print_targets(&users, enable);
+
+    if config.dry_run {
+        println!();
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+
+    if users.len() > 1 && !config.yes {
+        // The prompt reads its answer from stdin, which is unavailable once UPNs are piped in.
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+    println!();
+
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let shutdown = service::shutdown_signal();
+    let mut succeeded = 0;
+    let mut unchanged = 0;
+    let mut failed = unresolved.len();
+    for (upn, e) in &unresolved {
+        println!("FAILED  {}: {:#}", upn, e);
+    }
+
+    let mut results = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|user| {
+            let client = &client;
+            async move {
+                if user.account_enabled == Some(enable) {
+                    return (user, None);
+                }
+                let result = remediation::set_account_enabled(client, &user.user_principal_name, enable).await;
+                (user, Some(result))
+            }
+        })
+        .buffer_unordered(config.concurrency as usize);
+    let phase = client.stats().phase(&format!("{} users", verb));
+    let mut finished = HashSet::new();
+    while let Some((user, result)) = results.next().await {
+        finished.insert(&user.id);
+        match result {
+            None => {
+                unchanged += 1;
+                println!("SKIPPED {}: already {}ed", user.user_principal_name, verb);
+            }
+            Some(Ok(_)) => {
+                succeeded += 1;
+                println!("OK      {}", user.user_principal_name);
+            }
+            Some(Err(e)) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", user.user_principal_name, e);
+            }
+        }
+    }
+
+    let total = users.len() + unresolved.len();
+    let interrupted = users.len() - finished.len();
+    if interrupted > 0 {
+        warn!("Interrupted before {} users were processed", interrupted);
+        for user in users.iter().filter(|user| !finished.contains(&user.id)) {
+            println!("SKIPPED {}: not started", user.user_principal_name);
+        }
+    }
+
+    println!();
+    println!(
+        "Processed {} of {} users: {} {}ed, {} already {}ed, {} failed, {} skipped",
+        total - interrupted,
+        total,
+        succeeded,
+        verb,
+        unchanged,
+        verb,
+        failed,
+        interrupted
+    );
+    drop(phase);
+    client.stats().summary().print();
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!(
+            "Interrupted before {} users were processed",
+            interrupted
+        ));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, total));
+    }
+
+    info!("Operation completed successfully.");
+    Ok(())
+}
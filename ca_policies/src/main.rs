@@ -0,0 +1,232 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::GraphClient;
+use log::{debug, info, LevelFilter};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+mod policies;
+use policies::PolicyDiff;
+
+#[derive(Parser, Debug)]
+#[command(name = "ca_policies")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports conditional access policies to JSON files and diffs them against a baseline using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Export all conditional access policies to one normalized JSON file per policy")]
+    Export {
+        #[arg(short, long, help = "Directory to write the policies to; files of deleted policies are removed")]
+        dir: PathBuf,
+    },
+
+    #[command(about = "Diff two exports, or a saved baseline against the live tenant")]
+    Diff {
+        #[arg(help = "Baseline export directory")]
+        baseline: PathBuf,
+
+        #[arg(help = "Export directory to compare with the baseline; the live tenant by default")]
+        current: Option<PathBuf>,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+
+        #[arg(long, help = "Exit with an error when the policies differ from the baseline")]
+        exit_code: bool,
+    },
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn connect() -> Result<GraphClient> {
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Policy.Read.All"])?;
+    Ok(client)
+}
+
+// Normalized policies keyed by id.
+async fn live_policies(client: &GraphClient) -> Result<BTreeMap<String, Value>> {
+    let policies: Vec<Value> = client
+        .path(&["identity", "conditionalAccess", "policies"])
+        .get_all()
+        .await
+        .context("Failed to fetch conditional access policies")?;
+    Ok(policies
+        .into_iter()
+        .map(|policy| {
+            let policy = policies::normalize(policy);
+            (policy["id"].as_str().unwrap_or_default().to_string(), policy)
+        })
+        .collect())
+}
+
+fn value_text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+        None => String::new(),
+    }
+}
+
+fn render_diff(diffs: &[PolicyDiff], output: &str) -> Result<String> {
+    let mut out = String::new();
+    let disabled = diffs.iter().filter(|diff| diff.disabled()).count();
+    let exclusions = diffs
+        .iter()
+        .flat_map(|diff| &diff.changes)
+        .filter(|change| change.new_exclusion())
+        .count();
+    let count = |status: &str| diffs.iter().filter(|diff| diff.status == status).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "policies": diffs,
+                "added": count("added"),
+                "removed": count("removed"),
+                "changed": count("changed"),
+                "disabled": disabled,
+                "newExclusions": exclusions,
+            }))?;
+            out.push('\n');
+        }
+        _ => {
+            for diff in diffs {
+                write!(out, "{:<8} {} ({})", diff.status.to_uppercase(), diff.display_name, diff.state)?;
+                if diff.disabled() {
+                    write!(out, "  <- DISABLED")?;
+                }
+                writeln!(out)?;
+                for change in &diff.changes {
+                    match change.kind {
+                        "changed" => writeln!(
+                            out,
+                            "    ~ {}: {} -> {}",
+                            change.path,
+                            value_text(change.before.as_ref()),
+                            value_text(change.after.as_ref())
+                        )?,
+                        "added" => {
+                            write!(out, "    + {}: {}", change.path, value_text(change.after.as_ref()))?;
+                            if change.new_exclusion() {
+                                write!(out, "  <- NEW EXCLUSION")?;
+                            }
+                            writeln!(out)?;
+                        }
+                        _ => writeln!(out, "    - {}: {}", change.path, value_text(change.before.as_ref()))?,
+                    }
+                }
+            }
+            if !diffs.is_empty() {
+                writeln!(out)?;
+            }
+            writeln!(
+                out,
+                "{} added, {} removed, {} changed; {} disabled, {} new exclusions",
+                count("added"),
+                count("removed"),
+                count("changed"),
+                disabled,
+                exclusions
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting ca_policies");
+    debug!("Configuration: {:?}", config);
+
+    match config.command {
+        Commands::Export { dir } => {
+            let client = connect().await?;
+            let policies = live_policies(&client).await?;
+            let written = policies::write(&dir, &policies)?;
+            info!("Exported {} policies to {}", written.len(), dir.display());
+        }
+        Commands::Diff {
+            baseline,
+            current,
+            output,
+            exit_code,
+        } => {
+            let before = policies::load(&baseline)?;
+            let after = match current {
+                Some(dir) => policies::load(&dir)?,
+                None => live_policies(&connect().await?).await?,
+            };
+            let diffs = policies::diff(&before, &after);
+            print!("{}", render_diff(&diffs, &output)?);
+            if exit_code && !diffs.is_empty() {
+                return Err(anyhow::anyhow!("{} policies differ from the baseline", diffs.len()));
+            }
+        }
+    }
+
+    info!("Operation completed successfully.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn diffs_match_golden_files() {
+        let client = golden::fixture_client();
+        let live = live_policies(&client).await.unwrap();
+
+        let dir = env::temp_dir().join(format!("ca_policies-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Deleted_policy.json"), "{\"id\": \"stale\"}").unwrap();
+        let written = policies::write(&dir, &live).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), written.len());
+        golden::assert_matches(&golden::testdata("golden/export.txt"), &format!("{}\n", names.join("\n")));
+        let exported = policies::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(exported, live);
+        assert!(policies::diff(&exported, &live).is_empty());
+
+        let baseline = policies::load(&golden::testdata("baseline")).unwrap();
+        let diffs = policies::diff(&baseline, &live);
+        golden::assert_reports("diff", &["text", "json"], |output| render_diff(&diffs, output));
+    }
+}
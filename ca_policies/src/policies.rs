@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Fields Graph updates on its own, which would show up as changes in every diff.
+const VOLATILE_FIELDS: &[&str] = &["createdDateTime", "modifiedDateTime"];
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PolicyDiff {
+    pub id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    pub status: &'static str,
+    pub state: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<Change>,
+}
+
+impl PolicyDiff {
+    pub fn disabled(&self) -> bool {
+        self.status == "changed" && self.changes.iter().any(|change| change.path == "state") && self.state == "disabled"
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+impl Change {
+    // A user, group, role, application, location, or platform added to an exclude list.
+    pub fn new_exclusion(&self) -> bool {
+        self.kind == "added" && self.path.rsplit('.').next().is_some_and(|field| field.starts_with("exclude"))
+    }
+}
+
+// Drops read-only and null fields and sorts lists of values, so exports of an unchanged
+// policy are byte for byte identical. Object keys are already sorted by serde_json.
+pub fn normalize(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(key, value)| {
+                    !value.is_null()
+                        && !VOLATILE_FIELDS.contains(&key.as_str())
+                        && (!key.starts_with("@odata.") || key == "@odata.type")
+                })
+                .map(|(key, value)| (key, normalize(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(items) => {
+            let mut items: Vec<Value> = items.into_iter().map(normalize).collect();
+            if items.iter().all(|item| !item.is_object() && !item.is_array()) {
+                items.sort_by_key(|item| item.to_string());
+            }
+            Value::Array(items)
+        }
+        value => value,
+    }
+}
+
+fn field<'a>(policy: &'a Value, name: &str) -> &'a str {
+    policy.get(name).and_then(Value::as_str).unwrap_or_default()
+}
+
+fn file_stem(display_name: &str) -> String {
+    let mut stem = String::new();
+    for c in display_name.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+            stem.push(c);
+        } else if !stem.ends_with('_') {
+            stem.push('_');
+        }
+    }
+    stem.trim_matches(|c| c == '_' || c == '.').to_string()
+}
+
+// Policies keyed by id.
+pub fn load(dir: &Path) -> Result<BTreeMap<String, Value>> {
+    let mut policies = BTreeMap::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("Failed to read export directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let policy: Value = serde_json::from_str(&text).with_context(|| format!("{} is not valid JSON", path.display()))?;
+        let id = field(&policy, "id").to_string();
+        if id.is_empty() {
+            anyhow::bail!("{} has no policy id", path.display());
+        }
+        if policies.insert(id.clone(), normalize(policy)).is_some() {
+            anyhow::bail!("Policy {} is exported twice in {}", id, dir.display());
+        }
+    }
+    Ok(policies)
+}
+
+// Writes one file per policy, named after the policy, and removes files of policies that
+// no longer exist so the directory mirrors the tenant.
+pub fn write(dir: &Path, policies: &BTreeMap<String, Value>) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut ordered: Vec<(&String, &Value)> = policies.iter().collect();
+    ordered.sort_by_key(|(id, policy)| (field(policy, "displayName"), *id));
+
+    let mut written = Vec::new();
+    let mut names = HashSet::new();
+    for (id, policy) in ordered {
+        let mut stem = file_stem(field(policy, "displayName"));
+        if stem.is_empty() || !names.insert(stem.to_lowercase()) {
+            stem = if stem.is_empty() { id.clone() } else { format!("{}-{}", stem, id) };
+            names.insert(stem.to_lowercase());
+        }
+        let path = dir.join(format!("{}.json", stem));
+        fs::write(&path, format!("{}\n", serde_json::to_string_pretty(policy)?))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "json") && !written.contains(&path) {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            log::info!("Removed {}, its policy no longer exists", path.display());
+        }
+    }
+    Ok(written)
+}
+
+fn compare(path: &str, before: Option<&Value>, after: Option<&Value>, changes: &mut Vec<Change>) {
+    let change = |kind, before: Option<&Value>, after: Option<&Value>| Change {
+        path: path.to_string(),
+        kind,
+        before: before.cloned(),
+        after: after.cloned(),
+    };
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                compare(&child, before.get(key), after.get(key), changes);
+            }
+        }
+        (Some(Value::Array(before)), Some(Value::Array(after)))
+            if before.iter().chain(after).all(|item| !item.is_object() && !item.is_array()) =>
+        {
+            for item in before.iter().filter(|item| !after.contains(item)) {
+                changes.push(change("removed", Some(item), None));
+            }
+            for item in after.iter().filter(|item| !before.contains(item)) {
+                changes.push(change("added", None, Some(item)));
+            }
+        }
+        (Some(before), Some(after)) if before != after => changes.push(change("changed", Some(before), Some(after))),
+        (Some(before), None) => changes.push(change("removed", Some(before), None)),
+        (None, Some(after)) => changes.push(change("added", None, Some(after))),
+        _ => {}
+    }
+}
+
+// Added, removed, and changed policies, matched by id and sorted by name.
+pub fn diff(before: &BTreeMap<String, Value>, after: &BTreeMap<String, Value>) -> Vec<PolicyDiff> {
+    let mut diffs = Vec::new();
+    for (id, policy) in before.iter().filter(|(id, _)| !after.contains_key(*id)) {
+        diffs.push(PolicyDiff {
+            id: id.clone(),
+            display_name: field(policy, "displayName").to_string(),
+            status: "removed",
+            state: field(policy, "state").to_string(),
+            changes: Vec::new(),
+        });
+    }
+    for (id, policy) in after {
+        let (status, changes) = match before.get(id) {
+            None => ("added", Vec::new()),
+            Some(old) => {
+                let mut changes = Vec::new();
+                compare("", Some(old), Some(policy), &mut changes);
+                if changes.is_empty() {
+                    continue;
+                }
+                ("changed", changes)
+            }
+        };
+        diffs.push(PolicyDiff {
+            id: id.clone(),
+            display_name: field(policy, "displayName").to_string(),
+            status,
+            state: field(policy, "state").to_string(),
+            changes,
+        });
+    }
+    diffs.sort_by(|a, b| a.display_name.cmp(&b.display_name).then_with(|| a.id.cmp(&b.id)));
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn normalizes_policies() {
+        let policy = json!({
+            "@odata.context": "https://graph.microsoft.com/v1.0/$metadata",
+            "id": "1",
+            "modifiedDateTime": "2026-09-01T10:00:00Z",
+            "sessionControls": null,
+            "conditions": {
+                "users": { "includeUsers": ["b", "a"], "excludeGroups": [] },
+                "authenticationFlows": null
+            },
+            "grantControls": { "authenticationStrength": { "@odata.type": "x", "createdDateTime": "2026-01-01T00:00:00Z" } }
+        });
+        assert_eq!(
+            normalize(policy),
+            json!({
+                "id": "1",
+                "conditions": { "users": { "includeUsers": ["a", "b"], "excludeGroups": [] } },
+                "grantControls": { "authenticationStrength": { "@odata.type": "x" } }
+            })
+        );
+    }
+
+    #[test]
+    fn names_files_after_policies() {
+        assert_eq!(file_stem("CA001: Require MFA for admins"), "CA001_Require_MFA_for_admins");
+        assert_eq!(file_stem("  Block legacy auth (all users)  "), "Block_legacy_auth_all_users");
+        assert_eq!(file_stem("???"), "");
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{remediation, rules, GraphClient};
+use log::{debug, error, info, warn, LevelFilter};
+use serde::Serialize;
+use std::env;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "compromise_response")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Runs the account-compromise playbook against a user and writes a JSON incident report")]
+struct AppConfig {
+    #[arg(short, long, help = "User Principal Name of the compromised account")]
+    upn: String,
+
+    #[arg(long, conflicts_with = "enable", help = "Disable the account before any other action")]
+    disable: bool,
+
+    #[arg(long, help = "Re-enable the account as the last step, once the password reset and session revocation succeeded")]
+    enable: bool,
+
+    #[arg(long, help = "Do not reset the password")]
+    skip_password: bool,
+
+    #[arg(long, help = "Do not revoke sign-in sessions")]
+    skip_sessions: bool,
+
+    #[arg(long, help = "Do not remove MFA methods")]
+    skip_mfa: bool,
+
+    #[arg(short, long, help = "Write the incident report to this file instead of stdout")]
+    report: Option<PathBuf>,
+
+    #[arg(long, help = "Record the actions that would be taken without changing anything")]
+    dry_run: bool,
+
+    #[arg(long, help = "Enable info level logging")]
+    info: bool,
+
+    #[arg(long, help = "Enable debug level logging")]
+    debug: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Succeeded,
+    Failed,
+    Skipped,
+    Planned,
+}
+
+#[derive(Serialize, Debug)]
+struct Action {
+    action: String,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct IncidentReport {
+    upn: String,
+    operator: String,
+    #[serde(rename = "dryRun")]
+    dry_run: bool,
+    #[serde(rename = "startedAt")]
+    started_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    finished_at: Option<DateTime<Utc>>,
+    actions: Vec<Action>,
+    #[serde(rename = "inboxRules")]
+    inbox_rules: Vec<rules::RuleAssessment>,
+    forwarding: Vec<String>,
+}
+
+impl IncidentReport {
+    fn record(&mut self, action: &str, status: Status, detail: Option<String>) {
+        match status {
+            Status::Failed => error!("{}: failed: {}", action, detail.as_deref().unwrap_or("")),
+            _ => info!("{}: {:?} {}", action, status, detail.as_deref().unwrap_or("")),
+        }
+        self.actions.push(Action {
+            action: action.to_string(),
+            status,
+            detail,
+            at: Utc::now(),
+        });
+    }
+
+    fn record_result(&mut self, action: &str, result: Result<String>) {
+        match result {
+            Ok(detail) => self.record(action, Status::Succeeded, Some(detail)),
+            Err(e) => self.record(action, Status::Failed, Some(format!("{:#}", e))),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.actions
+            .iter()
+            .filter(|action| action.status == Status::Failed)
+            .count()
+    }
+}
+
+fn setup_logger(config: &AppConfig) {
+    let mut builder = Builder::from_default_env();
+    builder.filter(
+        None,
+        if config.debug {
+            LevelFilter::Debug
+        } else if config.info {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        },
+    );
+    builder.init();
+}
+
+async fn disable_account(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    if !config.disable {
+        return;
+    }
+    let action = "disable_account";
+    if config.dry_run {
+        report.record(action, Status::Planned, None);
+        return;
+    }
+    let result = remediation::set_account_enabled(client, &config.upn, false).await;
+    report.record_result(action, result.map(|_| "accountEnabled=false".to_string()));
+}
+
+// Runs last, and only once the attacker's password and sessions are gone: re-enabling an
+// account whose credentials are still valid would let the attacker straight back in.
+async fn enable_account(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    if !config.enable {
+        return;
+    }
+    let action = "enable_account";
+    let failed: Vec<&str> = report
+        .actions
+        .iter()
+        .filter(|action| {
+            action.status == Status::Failed
+                && (action.action == "reset_password" || action.action == "revoke_sign_in_sessions")
+        })
+        .map(|action| action.action.as_str())
+        .collect();
+    if !failed.is_empty() {
+        let detail = format!("account not re-enabled because {} failed", failed.join(" and "));
+        report.record(action, Status::Failed, Some(detail));
+        return;
+    }
+    if config.dry_run {
+        report.record(action, Status::Planned, None);
+        return;
+    }
+    let result = remediation::set_account_enabled(client, &config.upn, true).await;
+    report.record_result(action, result.map(|_| "accountEnabled=true".to_string()));
+}
+
+async fn reset_password(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    let action = "reset_password";
+    if config.skip_password {
+        report.record(action, Status::Skipped, None);
+        return;
+    }
+    if config.dry_run {
+        report.record(action, Status::Planned, Some("random password, change required at next sign-in".to_string()));
+        return;
+    }
+
+    let password = match remediation::generate_password(&remediation::PasswordPolicy::default()) {
+        Ok(password) => password,
+        Err(e) => {
+            report.record(action, Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+    let result = remediation::reset_password(client, &config.upn, &password, true).await;
+    if result.is_ok() {
+        // Printed once on stderr so it never ends up in the incident report on stdout.
+        eprintln!(
+            "Temporary password for {} (shown once, not stored in the report): {}",
+            config.upn, password
+        );
+    }
+    report.record_result(
+        action,
+        result.map(|_| "random password set, change required at next sign-in".to_string()),
+    );
+}
+
+async fn revoke_sessions(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    let action = "revoke_sign_in_sessions";
+    if config.skip_sessions {
+        report.record(action, Status::Skipped, None);
+    } else if config.dry_run {
+        report.record(action, Status::Planned, None);
+    } else {
+        let result = remediation::revoke_sign_in_sessions(client, &config.upn).await;
+        report.record_result(action, result.map(|_| "all refresh tokens invalidated".to_string()));
+    }
+}
+
+async fn remove_mfa_methods(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    if config.skip_mfa {
+        report.record("delete_mfa_method", Status::Skipped, None);
+        return;
+    }
+
+    let methods = match remediation::list_authentication_methods(client, &config.upn).await {
+        Ok(methods) => methods,
+        Err(e) => {
+            report.record("list_mfa_methods", Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+    let removable: Vec<_> = methods
+        .iter()
+        .filter(|method| method.short_name() != Some("password"))
+        .collect();
+    report.record(
+        "list_mfa_methods",
+        Status::Succeeded,
+        Some(format!("{} methods besides the password", removable.len())),
+    );
+
+    for method in removable {
+        let detail = format!(
+            "{} {}",
+            method.short_name().unwrap_or(&method.odata_type),
+            method.id
+        );
+        if config.dry_run {
+            report.record("delete_mfa_method", Status::Planned, Some(detail));
+            continue;
+        }
+        let result = remediation::delete_authentication_method(client, &config.upn, method).await;
+        report.record_result("delete_mfa_method", result.map(|_| detail));
+    }
+}
+
+async fn review_inbox_rules(client: &GraphClient, config: &AppConfig, report: &mut IncidentReport) {
+    let inbox_rules = match rules::review(client, &config.upn).await {
+        Ok(inbox_rules) => inbox_rules,
+        Err(e) => {
+            report.record("review_inbox_rules", Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+
+    for assessment in inbox_rules {
+        if assessment.suspicious {
+            warn!(
+                "Suspicious inbox rule \"{}\": {}",
+                assessment.display_name,
+                assessment.reasons.join("; ")
+            );
+        }
+        for address in &assessment.forwards_to {
+            if !report.forwarding.contains(address) {
+                report.forwarding.push(address.clone());
+            }
+        }
+        report.inbox_rules.push(assessment);
+    }
+
+    let suspicious = report.inbox_rules.iter().filter(|rule| rule.suspicious).count();
+    report.record(
+        "review_inbox_rules",
+        Status::Succeeded,
+        Some(format!("{} rules, {} suspicious", report.inbox_rules.len(), suspicious)),
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(&config);
+
+    info!("Starting compromise response for {}", config.upn);
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec!["User.ReadWrite.All", "MailboxSettings.Read", "Mail.ReadBasic.All"];
+    if !config.skip_mfa {
+        required.push("UserAuthenticationMethod.ReadWrite.All");
+    }
+    client.require_permissions(&required)?;
+
+    let mut report = IncidentReport {
+        upn: config.upn.clone(),
+        operator: graph_common::current_operator(),
+        dry_run: config.dry_run,
+        started_at: Utc::now(),
+        finished_at: None,
+        actions: Vec::new(),
+        inbox_rules: Vec::new(),
+        forwarding: Vec::new(),
+    };
+
+    // Lock the attacker out first, then invalidate the tokens they already hold.
+    disable_account(&client, &config, &mut report).await;
+    reset_password(&client, &config, &mut report).await;
+    revoke_sessions(&client, &config, &mut report).await;
+    remove_mfa_methods(&client, &config, &mut report).await;
+    review_inbox_rules(&client, &config, &mut report).await;
+    enable_account(&client, &config, &mut report).await;
+    report.finished_at = Some(Utc::now());
+
+    let json = serde_json::to_string_pretty(&report)?;
+    match &config.report {
+        Some(path) => {
+            std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+            info!("Incident report written to {}", path.display());
+        }
+        None => println!("{}", json),
+    }
+
+    let failures = report.failures();
+    if failures > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} actions failed; see the incident report",
+            failures,
+            report.actions.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,517 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{client, csv, encode, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "drive_search")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Searches a user's OneDrive or a SharePoint site for files by name or content and downloads the matches with their folder structure, using the Microsoft API")]
+#[command(group(ArgGroup::new("location").required(true).args(["upn", "site"])))]
+struct AppConfig {
+    #[arg(short, long, help = "User Principal Name whose OneDrive is searched")]
+    upn: Option<String>,
+
+    #[arg(short, long, help = "URL of the SharePoint site whose document libraries are searched")]
+    site: Option<String>,
+
+    #[arg(short, long, help = "Text to search for in file names and content")]
+    query: String,
+
+    #[arg(long, help = "Only keep files whose name contains the query")]
+    name_only: bool,
+
+    #[arg(short, long, value_delimiter = ',', help = "Only keep files with this extension (repeatable or comma separated)")]
+    extension: Vec<String>,
+
+    #[arg(short, long, value_name = "DIR", help = "Download the matches into this directory, with a manifest.csv")]
+    download: Option<PathBuf>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format of the list of matches")]
+    output: String,
+
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=16), help = "Number of downloads in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Site {
+    id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Drive {
+    id: String,
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DriveItem {
+    id: String,
+    name: Option<String>,
+    size: Option<u64>,
+    file: Option<File>,
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+    #[serde(rename = "lastModifiedDateTime")]
+    last_modified_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastModifiedBy")]
+    last_modified_by: Option<IdentitySet>,
+}
+
+#[derive(Deserialize, Debug)]
+struct File {
+    hashes: Option<Hashes>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Hashes {
+    #[serde(rename = "quickXorHash")]
+    quick_xor_hash: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdentitySet {
+    user: Option<Identity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Identity {
+    email: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ItemLocation {
+    #[serde(rename = "parentReference")]
+    parent_reference: Option<ParentReference>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ParentReference {
+    path: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Match {
+    drive: String,
+    folder: String,
+    name: String,
+    size: u64,
+    #[serde(rename = "lastModifiedDateTime")]
+    last_modified_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastModifiedBy")]
+    last_modified_by: String,
+    #[serde(rename = "webUrl")]
+    web_url: String,
+    #[serde(rename = "quickXorHash")]
+    quick_xor_hash: String,
+    #[serde(rename = "driveId")]
+    drive_id: String,
+    #[serde(rename = "itemId")]
+    item_id: String,
+}
+
+struct Download {
+    local_path: PathBuf,
+    sha256: String,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// "https://contoso.sharepoint.com/sites/legal" is addressed as
+// /sites/contoso.sharepoint.com:/sites/legal, which cannot be built segment by segment.
+async fn site_drives(client: &GraphClient, site_url: &str) -> Result<Vec<Drive>> {
+    let rest = site_url
+        .trim_end_matches('/')
+        .strip_prefix("https://")
+        .with_context(|| format!("{} is not an https:// site URL", site_url))?;
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let path: Vec<String> = path.split('/').map(encode::path_segment).collect();
+    let url = format!("{}/sites/{}:/{}?$select=id", client.base_url(), encode::path_segment(host), path.join("/"));
+    let site: Site = client
+        .get_json(&url)
+        .await
+        .with_context(|| format!("Failed to find the site {}", site_url))?;
+    client
+        .path(&["sites", &site.id, "drives"])
+        .select("id,name")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the document libraries of {}", site_url))
+}
+
+async fn user_drive(client: &GraphClient, upn: &str) -> Result<Drive> {
+    client
+        .user(upn)
+        .resource()
+        .segment("drive")
+        .select("id,name")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to find the OneDrive of {}", upn))
+}
+
+fn search_url(client: &GraphClient, drive_id: &str, query: &str) -> String {
+    format!(
+        "{}/drives/{}/root/search(q={})?$select={}",
+        client.base_url(),
+        encode::path_segment(drive_id),
+        encode::query_value(&encode::odata_string(query)),
+        encode::query_value("id,name,size,file,webUrl,lastModifiedDateTime,lastModifiedBy")
+    )
+}
+
+fn keep(item: &DriveItem, config: &AppConfig) -> bool {
+    let name = item.name.as_deref().unwrap_or_default().to_lowercase();
+    item.file.is_some()
+        && (!config.name_only || name.contains(&config.query.to_lowercase()))
+        && (config.extension.is_empty()
+            || config.extension.iter().any(|extension| {
+                name.ends_with(&format!(".{}", extension.trim_start_matches('.').to_lowercase()))
+            }))
+}
+
+// Search results have no folder path, so each match's parent path is read through $batch.
+// Graph returns it as "/drives/<id>/root:/Folder/Subfolder".
+async fn search(client: &GraphClient, drives: &[Drive], config: &AppConfig) -> Result<Vec<Match>> {
+    let mut matches = Vec::new();
+    for drive in drives {
+        let drive_name = drive.name.clone().unwrap_or_else(|| drive.id.clone());
+        let items: Vec<DriveItem> = client
+            .get_all(&search_url(client, &drive.id, &config.query))
+            .await
+            .with_context(|| format!("Failed to search {}", drive_name))?;
+        let items: Vec<DriveItem> = items.into_iter().filter(|item| keep(item, config)).collect();
+        debug!("{} files in {} match", items.len(), drive_name);
+
+        let urls: Vec<String> = items
+            .iter()
+            .map(|item| {
+                client
+                    .path(&["drives", &drive.id, "items", &item.id])
+                    .select("parentReference")
+                    .relative_url()
+            })
+            .collect();
+        let locations: Vec<Result<ItemLocation>> = client.batch_get(&urls).await?;
+        for (item, location) in items.into_iter().zip(locations) {
+            let folder = match location {
+                Ok(location) => location
+                    .parent_reference
+                    .and_then(|parent| parent.path)
+                    .and_then(|path| path.split_once("root:").map(|(_, folder)| folder.trim_start_matches('/').to_string()))
+                    .unwrap_or_default(),
+                Err(e) => {
+                    warn!("Failed to read the folder of {}: {:#}", item.name.as_deref().unwrap_or(&item.id), e);
+                    String::new()
+                }
+            };
+            let modified_by = item.last_modified_by.and_then(|by| by.user);
+            matches.push(Match {
+                drive: drive_name.clone(),
+                folder,
+                name: item.name.unwrap_or_default(),
+                size: item.size.unwrap_or_default(),
+                last_modified_date_time: item.last_modified_date_time,
+                last_modified_by: modified_by
+                    .and_then(|user| user.email.or(user.display_name))
+                    .unwrap_or_default(),
+                web_url: item.web_url.unwrap_or_default(),
+                quick_xor_hash: item
+                    .file
+                    .and_then(|file| file.hashes)
+                    .and_then(|hashes| hashes.quick_xor_hash)
+                    .unwrap_or_default(),
+                drive_id: drive.id.clone(),
+                item_id: item.id,
+            });
+        }
+    }
+    matches.sort_by(|a, b| (&a.drive, &a.folder, &a.name).cmp(&(&b.drive, &b.folder, &b.name)));
+    Ok(matches)
+}
+
+fn display_path(item: &Match) -> String {
+    if item.folder.is_empty() {
+        format!("{}/{}", item.drive, item.name)
+    } else {
+        format!("{}/{}/{}", item.drive, item.folder, item.name)
+    }
+}
+
+fn timestamp(value: Option<DateTime<Utc>>) -> String {
+    value
+        .map(|value| value.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn render_report(matches: &[Match], query: &str, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let total: u64 = matches.iter().map(|item| item.size).sum();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "query": query,
+                "matches": matches,
+                "totalBytes": total,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "drive,folder,name,size,lastModifiedDateTime,lastModifiedBy,webUrl,quickXorHash,driveId,itemId")?;
+            for item in matches {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&item.drive),
+                    csv::field(&item.folder),
+                    csv::field(&item.name),
+                    item.size,
+                    timestamp(item.last_modified_date_time),
+                    csv::field(&item.last_modified_by),
+                    csv::field(&item.web_url),
+                    item.quick_xor_hash,
+                    item.drive_id,
+                    item.item_id
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<10} {:>12} {:<28} PATH", "MODIFIED", "BYTES", "MODIFIED BY")?;
+            for item in matches {
+                writeln!(
+                    out,
+                    "{:<10} {:>12} {:<28} {}",
+                    item.last_modified_date_time
+                        .map_or_else(|| "-".to_string(), |modified| modified.date_naive().to_string()),
+                    item.size,
+                    item.last_modified_by,
+                    display_path(item)
+                )?;
+            }
+            writeln!(out)?;
+            writeln!(out, "{} files match \"{}\", {} bytes in total", matches.len(), query, total)?;
+        }
+    }
+    Ok(out)
+}
+
+// Each part of the Graph path becomes a directory; characters Windows does not allow in file
+// names are replaced, and "." and ".." cannot climb out of the download directory.
+fn local_path(dir: &Path, item: &Match) -> PathBuf {
+    let clean = |part: &str| {
+        let part: String = part
+            .chars()
+            .map(|c| if c.is_control() || "<>:\"/\\|?*".contains(c) { '_' } else { c })
+            .collect();
+        match part.trim_end_matches(['.', ' ']) {
+            "" => "_".to_string(),
+            part => part.to_string(),
+        }
+    };
+    let mut path = dir.join(clean(&item.drive));
+    for part in item.folder.split('/').filter(|part| !part.is_empty()) {
+        path.push(clean(part));
+    }
+    path.push(clean(&item.name));
+    path
+}
+
+// Files are written with create_new, so an existing file is never overwritten, and hashed while
+// they are written.
+async fn download(client: &GraphClient, item: &Match, dir: &Path) -> Result<Download> {
+    let local_path = local_path(dir, item);
+    if let Some(parent) = local_path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let url = client.path(&["drives", &item.drive_id, "items", &item.item_id, "content"]).url();
+    let mut response = client.get(&url).await?;
+    if !response.status().is_success() {
+        return Err(client::error_from_response(response).await);
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&local_path)
+        .with_context(|| format!("Failed to create {}", local_path.display()))?;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = response.chunk().await.context("Failed to read the file from Graph API")? {
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .with_context(|| format!("Failed to write {}", local_path.display()))?;
+    }
+    Ok(Download {
+        local_path,
+        sha256: hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect(),
+    })
+}
+
+fn write_manifest(dir: &Path, rows: &[(&Match, Download)]) -> Result<PathBuf> {
+    let mut out = String::new();
+    writeln!(out, "localPath,drive,folder,name,size,lastModifiedDateTime,lastModifiedBy,webUrl,quickXorHash,sha256,itemId")?;
+    for (item, download) in rows {
+        let local_path = download.local_path.strip_prefix(dir).unwrap_or(&download.local_path);
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            csv::field(&local_path.to_string_lossy()),
+            csv::field(&item.drive),
+            csv::field(&item.folder),
+            csv::field(&item.name),
+            item.size,
+            timestamp(item.last_modified_date_time),
+            csv::field(&item.last_modified_by),
+            csv::field(&item.web_url),
+            item.quick_xor_hash,
+            download.sha256,
+            item.item_id
+        )?;
+    }
+    let path = dir.join("manifest.csv");
+    std::fs::write(&path, out).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+async fn download_all(client: &GraphClient, matches: &[Match], dir: &Path, concurrency: usize, shutdown: &ShutdownSignal) -> Result<()> {
+    let _phase = client.stats().phase("download files");
+    let results: Vec<(&Match, Result<Download>)> = stream::iter(matches)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|item| async move { (item, download(client, item, dir).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let started = results.len();
+    let mut downloaded = Vec::new();
+    let mut failed = 0;
+    for (item, result) in results {
+        match result {
+            Ok(download) => {
+                println!("{:<8}{}", "OK", display_path(item));
+                downloaded.push((item, download));
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", display_path(item), e);
+            }
+        }
+    }
+    for item in &matches[started..] {
+        println!("SKIPPED {}: not started", display_path(item));
+    }
+    let manifest = write_manifest(dir, &downloaded)?;
+    println!();
+    println!(
+        "Downloaded {} of {} files to {}, {} failed, {} skipped; manifest: {}",
+        downloaded.len(),
+        matches.len(),
+        dir.display(),
+        failed,
+        matches.len() - started,
+        manifest.display()
+    );
+    if started < matches.len() {
+        return Err(anyhow::anyhow!("Interrupted before {} files were downloaded", matches.len() - started));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} files could not be downloaded", failed, matches.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting drive_search");
+    debug!("Configuration: {:?}", config);
+
+    if config.download.is_some() && config.output != "text" {
+        return Err(anyhow::anyhow!("--download reports its progress as text and cannot be combined with -o {}", config.output));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&[if config.site.is_some() {
+        "Sites.Read.All|Files.Read.All"
+    } else {
+        "Files.Read.All"
+    }])?;
+
+    let phase = client.stats().phase("search");
+    let drives = match (&config.upn, &config.site) {
+        (Some(upn), _) => vec![user_drive(&client, upn).await?],
+        (None, Some(site)) => site_drives(&client, site).await?,
+        (None, None) => return Err(anyhow::anyhow!("--upn or --site is required")),
+    };
+    let matches = search(&client, &drives, &config).await?;
+    drop(phase);
+    print!("{}", render_report(&matches, &config.query, &config.output, &client.stats().summary())?);
+
+    if let Some(dir) = &config.download {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        println!();
+        let shutdown = service::shutdown_signal();
+        let result = download_all(&client, &matches, dir, config.concurrency as usize, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let config = AppConfig::parse_from(["drive_search", "-s", "https://contoso.sharepoint.com/sites/legal", "-q", "Fabrikam contract", "-e", "docx,pdf"]);
+        let drives = site_drives(&client, config.site.as_deref().unwrap()).await.unwrap();
+        let matches = search(&client, &drives, &config).await.unwrap();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&matches, &config.query, output, &Summary::fixture()));
+
+        let path = local_path(Path::new("out"), &matches[0]);
+        assert!(path.starts_with("out"));
+        let unsafe_item = Match { folder: "../../etc".to_string(), name: "a:b?.docx".to_string(), ..matches.into_iter().next().unwrap() };
+        assert_eq!(local_path(Path::new("out"), &unsafe_item), Path::new("out/Case Files/_/_/etc/a_b_.docx"));
+    }
+}
@@ -0,0 +1,441 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fmt::Write;
+
+mod rule;
+
+#[derive(Parser, Debug)]
+#[command(name = "dynamic_groups")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports and validates the membership rules of dynamic groups, or shows which dynamic groups a user matches, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_name = "UPN", help = "Show which dynamic user groups this user matches instead of the rule report")]
+    test_user: Option<String>,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "membershipRule")]
+    membership_rule: Option<String>,
+    #[serde(rename = "membershipRuleProcessingState")]
+    membership_rule_processing_state: Option<String>,
+}
+
+impl Group {
+    fn name(&self) -> String {
+        self.display_name.clone().unwrap_or_else(|| self.id.clone())
+    }
+
+    fn rule(&self) -> &str {
+        self.membership_rule.as_deref().unwrap_or_default()
+    }
+
+    fn paused(&self) -> bool {
+        self.membership_rule_processing_state.as_deref() == Some("Paused")
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DynamicGroup {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "membershipRule")]
+    membership_rule: String,
+    #[serde(rename = "processingState")]
+    processing_state: String,
+    // "user", "device", "mixed", or "unknown" when the rule does not parse.
+    subject: &'static str,
+    members: usize,
+    issues: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct GroupMatch {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "membershipRule")]
+    membership_rule: String,
+    // None when the rule cannot be evaluated locally; the reason is in note.
+    matches: Option<bool>,
+    member: bool,
+    note: String,
+}
+
+struct Scan {
+    groups: Vec<DynamicGroup>,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_dynamic_groups(client: &GraphClient) -> Result<Vec<Group>> {
+    let mut groups: Vec<Group> = client
+        .path(&["groups"])
+        .filter("groupTypes/any(c:c eq 'DynamicMembership')")
+        .select("id,displayName,membershipRule,membershipRuleProcessingState")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch dynamic groups")?;
+    groups.sort_by_key(|group| group.name().to_lowercase());
+    Ok(groups)
+}
+
+async fn count_members(client: &GraphClient, group: &Group) -> Result<usize> {
+    let members: Vec<Value> = client
+        .path(&["groups", &group.id, "members"])
+        .select("id")
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the members of {}", group.name()))?;
+    Ok(members.len())
+}
+
+fn validate(group: &Group, members: usize) -> DynamicGroup {
+    let (subject, mut issues) = match rule::parse(group.rule()) {
+        Ok(parsed) => (parsed.subject(), parsed.issues()),
+        Err(e) => ("unknown", vec![format!("rule does not parse: {:#}", e)]),
+    };
+    if group.paused() {
+        issues.push("rule processing is paused".to_string());
+    }
+    DynamicGroup {
+        id: group.id.clone(),
+        display_name: group.name(),
+        membership_rule: group.rule().to_string(),
+        processing_state: group.membership_rule_processing_state.clone().unwrap_or_default(),
+        subject,
+        members,
+        issues,
+    }
+}
+
+async fn scan(client: &GraphClient, concurrency: usize, shutdown: &ShutdownSignal) -> Result<Scan> {
+    let phase = client.stats().phase("list dynamic groups");
+    let groups = list_dynamic_groups(client).await?;
+    drop(phase);
+    info!("Counting the members of {} dynamic groups", groups.len());
+
+    let _phase = client.stats().phase("count members");
+    let results: Vec<(&Group, Result<usize>)> = stream::iter(&groups)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|group| async move { (group, count_members(client, group).await) })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let interrupted = results.len() < groups.len();
+    let mut validated = Vec::new();
+    let mut failed = 0;
+    for (group, result) in results {
+        match result {
+            Ok(members) => validated.push(validate(group, members)),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    Ok(Scan {
+        groups: validated,
+        failed,
+        interrupted,
+    })
+}
+
+// Which dynamic user groups the user matches by rule, next to the groups the user is a member
+// of now. A difference on a group whose rule is processed means an update is still pending.
+async fn test_user(client: &GraphClient, upn: &str) -> Result<Vec<GroupMatch>> {
+    let phase = client.stats().phase("list dynamic groups");
+    let groups = list_dynamic_groups(client).await?;
+    drop(phase);
+
+    let _phase = client.stats().phase("read user");
+    let parsed: Vec<(&Group, Result<rule::Rule>)> = groups
+        .iter()
+        .map(|group| (group, rule::parse(group.rule())))
+        .filter(|(_, parsed)| parsed.as_ref().map_or(true, |parsed| parsed.subject() != "device"))
+        .collect();
+    let extensions: BTreeSet<String> = parsed
+        .iter()
+        .filter_map(|(_, parsed)| parsed.as_ref().ok())
+        .flat_map(rule::directory_extensions)
+        .collect();
+    let mut select = rule::USER_SELECT.to_string();
+    for extension in &extensions {
+        select.push(',');
+        select.push_str(extension);
+    }
+    let user: Value = client
+        .user(upn)
+        .resource()
+        .select(&select)
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to fetch {}", upn))?;
+    let member_of: Vec<Value> = client
+        .user(upn)
+        .member_of()
+        .select("id")
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the groups of {}", upn))?;
+    let member_of: Vec<Value> = member_of.into_iter().filter_map(|group| group.get("id").cloned()).collect();
+    let current: HashSet<&str> = member_of.iter().filter_map(Value::as_str).collect();
+
+    let mut matches = Vec::new();
+    for (group, parsed) in &parsed {
+        let member = current.contains(group.id.as_str());
+        let (result, mut note) = match parsed {
+            Ok(parsed) => {
+                let mut user = user.clone();
+                user["memberOf"] = Value::Array(member_of.clone());
+                match rule::evaluate(parsed, &user) {
+                    Ok(result) => (Some(result), String::new()),
+                    Err(e) => (None, format!("{:#}", e)),
+                }
+            }
+            Err(e) => (None, format!("rule does not parse: {:#}", e)),
+        };
+        if result.is_some_and(|result| result != member) {
+            note = if group.paused() {
+                "rule processing is paused".to_string()
+            } else {
+                "membership update pending".to_string()
+            };
+        }
+        matches.push(GroupMatch {
+            id: group.id.clone(),
+            display_name: group.name(),
+            membership_rule: group.rule().to_string(),
+            matches: result,
+            member,
+            note,
+        });
+    }
+    Ok(matches)
+}
+
+fn yes_no(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "unknown",
+    }
+}
+
+fn render_report(groups: &[DynamicGroup], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "groups": groups,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "id,displayName,subject,processingState,members,membershipRule,issues")?;
+            for group in groups {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    group.id,
+                    csv::field(&group.display_name),
+                    group.subject,
+                    group.processing_state,
+                    group.members,
+                    csv::field(&group.membership_rule),
+                    csv::field(&group.issues.join("; "))
+                )?;
+            }
+        }
+        _ => {
+            for group in groups {
+                writeln!(
+                    out,
+                    "{} ({}, {}, {} members)",
+                    group.display_name,
+                    group.subject,
+                    if group.processing_state.is_empty() { "unknown" } else { &group.processing_state },
+                    group.members
+                )?;
+                writeln!(out, "    {}", group.membership_rule)?;
+                for issue in &group.issues {
+                    writeln!(out, "    ! {}", issue)?;
+                }
+            }
+            if !groups.is_empty() {
+                writeln!(out)?;
+            }
+            writeln!(
+                out,
+                "{} dynamic groups: {} user, {} device, {} with issues, {} without members",
+                groups.len(),
+                groups.iter().filter(|group| group.subject == "user").count(),
+                groups.iter().filter(|group| group.subject == "device").count(),
+                groups.iter().filter(|group| !group.issues.is_empty()).count(),
+                groups.iter().filter(|group| group.members == 0).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn render_matches(upn: &str, matches: &[GroupMatch], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "userPrincipalName": upn,
+                "groups": matches,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "id,displayName,matches,member,note,membershipRule")?;
+            for group in matches {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    group.id,
+                    csv::field(&group.display_name),
+                    group.matches.map(|matches| matches.to_string()).unwrap_or_default(),
+                    group.member,
+                    csv::field(&group.note),
+                    csv::field(&group.membership_rule)
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<30} {:<8} {:<7} NOTE", "GROUP", "MATCHES", "MEMBER")?;
+            for group in matches {
+                let line = format!(
+                    "{:<30} {:<8} {:<7} {}",
+                    group.display_name,
+                    yes_no(group.matches),
+                    yes_no(Some(group.member)),
+                    group.note
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} matches {} of {} dynamic user groups and is a member of {}; {} could not be evaluated",
+                upn,
+                matches.iter().filter(|group| group.matches == Some(true)).count(),
+                matches.len(),
+                matches.iter().filter(|group| group.member).count(),
+                matches.iter().filter(|group| group.matches.is_none()).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting dynamic_groups");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+
+    if let Some(upn) = &config.test_user {
+        client.require_permissions(&["Group.Read.All", "User.Read.All"])?;
+        let matches = test_user(&client, upn).await?;
+        let run_stats = client.stats().summary();
+        print!("{}", render_matches(upn, &matches, &config.output, &run_stats)?);
+        if config.output != "json" {
+            run_stats.print();
+        }
+        return Ok(());
+    }
+
+    client.require_permissions(&["Group.Read.All"])?;
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, config.concurrency as usize, &shutdown).await?;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&scan.groups, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} groups; the report above is incomplete", scan.groups.len() + scan.failed);
+        return Err(anyhow::anyhow!("Export interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!("The members of {} groups could not be counted; they are missing from the report", scan.failed);
+        return Err(anyhow::anyhow!("{} groups could not be read", scan.failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let scan = scan(&client, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.failed, scan.interrupted), (0, false));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&scan.groups, output, &Summary::fixture()));
+    }
+
+    #[tokio::test]
+    async fn test_user_matches_golden_files() {
+        let client = golden::fixture_client();
+        let matches = test_user(&client, "jane.doe@contoso.com").await.unwrap();
+        golden::assert_reports("test_user", &["text", "csv", "json"], |output| render_matches("jane.doe@contoso.com", &matches, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,646 @@
+use anyhow::{anyhow, Result};
+use regex::RegexBuilder;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+// Attributes dynamic membership rules accept, without the user. or device. prefix.
+// extensionAttribute1-15 and extension_<appId>_<name> directory extensions are accepted too.
+const USER_ATTRIBUTES: &[&str] = &[
+    "accountEnabled",
+    "assignedPlans",
+    "city",
+    "companyName",
+    "country",
+    "department",
+    "dirSyncEnabled",
+    "displayName",
+    "employeeHireDate",
+    "employeeId",
+    "employeeType",
+    "facsimileTelephoneNumber",
+    "givenName",
+    "jobTitle",
+    "mail",
+    "mailNickName",
+    "memberOf",
+    "mobile",
+    "objectId",
+    "onPremisesDistinguishedName",
+    "onPremisesSamAccountName",
+    "onPremisesSecurityIdentifier",
+    "otherMails",
+    "passwordPolicies",
+    "physicalDeliveryOfficeName",
+    "postalCode",
+    "preferredLanguage",
+    "proxyAddresses",
+    "sipProxyAddress",
+    "state",
+    "streetAddress",
+    "surname",
+    "telephoneNumber",
+    "usageLocation",
+    "userPrincipalName",
+    "userType",
+];
+
+const DEVICE_ATTRIBUTES: &[&str] = &[
+    "accountEnabled",
+    "deviceCategory",
+    "deviceId",
+    "deviceManagementAppId",
+    "deviceManufacturer",
+    "deviceModel",
+    "deviceOSType",
+    "deviceOSVersion",
+    "deviceOwnership",
+    "devicePhysicalIds",
+    "deviceTrustType",
+    "displayName",
+    "enrollmentProfileName",
+    "isRooted",
+    "managementType",
+    "memberOf",
+    "objectId",
+    "organizationalUnit",
+    "profileType",
+    "systemLabels",
+];
+
+// Attributes rules still accept but that are no longer kept up to date, so groups built on
+// them silently stop changing.
+const DEPRECATED_ATTRIBUTES: &[(&str, &str)] = &[(
+    "user.sipProxyAddress",
+    "only maintained by Skype for Business, which is retired; use user.proxyAddresses",
+)];
+
+// The Graph user property behind a rule attribute, where the names differ.
+const GRAPH_PROPERTIES: &[(&str, &str)] = &[
+    ("dirSyncEnabled", "onPremisesSyncEnabled"),
+    ("facsimileTelephoneNumber", "faxNumber"),
+    ("mailNickName", "mailNickname"),
+    ("mobile", "mobilePhone"),
+    ("objectId", "id"),
+    ("physicalDeliveryOfficeName", "officeLocation"),
+    ("telephoneNumber", "businessPhones"),
+];
+
+// The user properties read to evaluate rules; directory extensions a rule references are
+// added to it.
+pub const USER_SELECT: &str = "id,accountEnabled,assignedPlans,businessPhones,city,companyName,country,department,displayName,employeeHireDate,employeeId,employeeType,faxNumber,givenName,jobTitle,mail,mailNickname,mobilePhone,officeLocation,onPremisesDistinguishedName,onPremisesExtensionAttributes,onPremisesSamAccountName,onPremisesSecurityIdentifier,onPremisesSyncEnabled,otherMails,passwordPolicies,postalCode,preferredLanguage,proxyAddresses,state,streetAddress,surname,usageLocation,userPrincipalName,userType";
+
+const OPERATORS: &[&str] = &[
+    "-eq",
+    "-ne",
+    "-startswith",
+    "-notstartswith",
+    "-contains",
+    "-notcontains",
+    "-match",
+    "-notmatch",
+    "-in",
+    "-notin",
+    "-le",
+    "-ge",
+    "-lt",
+    "-gt",
+    "-any",
+    "-all",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Bool(bool),
+    Text(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rule {
+    And(Box<Rule>, Box<Rule>),
+    Or(Box<Rule>, Box<Rule>),
+    Not(Box<Rule>),
+    Compare {
+        property: String,
+        operator: String,
+        value: Literal,
+    },
+    // -any or -all over a multi-valued property, with the condition each value is tested
+    // against, such as (assignedPlan.capabilityStatus -eq "Enabled") or (_ -startsWith "smtp:").
+    Each {
+        property: String,
+        all: bool,
+        condition: Box<Rule>,
+    },
+    DirectReports(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+    Operator(String),
+    Word(String),
+    Text(String),
+}
+
+fn tokenize(rule: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | '[' | ']' | ',' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    '[' => Token::OpenList,
+                    ']' => Token::CloseList,
+                    _ => Token::Comma,
+                });
+            }
+            // Strings are quoted with " or ', and a backtick escapes the quote character.
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('`') => text.extend(chars.next()),
+                        Some(next) if next == c => break,
+                        Some(next) => text.push(next),
+                        None => return Err(anyhow!("Unterminated string {}{}", c, text)),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()[],\"'".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                let is_operator = word.len() > 1 && word.starts_with('-') && !word[1..].starts_with(|c: char| c.is_ascii_digit());
+                // The portal also accepts and, or, and not without the leading dash.
+                let is_keyword = ["and", "or", "not"].iter().any(|keyword| word.eq_ignore_ascii_case(keyword));
+                tokens.push(if is_operator {
+                    Token::Operator(word.to_lowercase())
+                } else if is_keyword {
+                    Token::Operator(format!("-{}", word.to_lowercase()))
+                } else {
+                    Token::Word(word)
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "the end of the rule".to_string(),
+        Some(Token::Open) => "(".to_string(),
+        Some(Token::Close) => ")".to_string(),
+        Some(Token::OpenList) => "[".to_string(),
+        Some(Token::CloseList) => "]".to_string(),
+        Some(Token::Comma) => ",".to_string(),
+        Some(Token::Operator(word)) | Some(Token::Word(word)) => word.clone(),
+        Some(Token::Text(text)) => format!("\"{}\"", text),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(anyhow!("Expected {}, found {}", describe(Some(token)), describe(self.peek())))
+        }
+    }
+
+    fn or(&mut self) -> Result<Rule> {
+        let mut rule = self.and()?;
+        while self.eat(&Token::Operator("-or".to_string())) {
+            rule = Rule::Or(Box::new(rule), Box::new(self.and()?));
+        }
+        Ok(rule)
+    }
+
+    fn and(&mut self) -> Result<Rule> {
+        let mut rule = self.unary()?;
+        while self.eat(&Token::Operator("-and".to_string())) {
+            rule = Rule::And(Box::new(rule), Box::new(self.unary()?));
+        }
+        Ok(rule)
+    }
+
+    fn unary(&mut self) -> Result<Rule> {
+        if self.eat(&Token::Operator("-not".to_string())) {
+            return Ok(Rule::Not(Box::new(self.unary()?)));
+        }
+        if self.eat(&Token::Open) {
+            let rule = self.or()?;
+            self.expect(&Token::Close)?;
+            return Ok(rule);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Rule> {
+        let property = match self.next() {
+            Some(Token::Word(word)) => word,
+            other => return Err(anyhow!("Expected an attribute, found {}", describe(other.as_ref()))),
+        };
+        let operator = match self.next() {
+            Some(Token::Operator(operator)) if OPERATORS.contains(&operator.as_str()) => operator,
+            other => return Err(anyhow!("Expected an operator after {}, found {}", property, describe(other.as_ref()))),
+        };
+        if operator == "-any" || operator == "-all" {
+            self.expect(&Token::Open)?;
+            let condition = self.or()?;
+            self.expect(&Token::Close)?;
+            return Ok(Rule::Each {
+                property,
+                all: operator == "-all",
+                condition: Box::new(condition),
+            });
+        }
+        Ok(Rule::Compare {
+            property,
+            operator,
+            value: self.literal()?,
+        })
+    }
+
+    fn literal(&mut self) -> Result<Literal> {
+        match self.next() {
+            Some(Token::Text(text)) => Ok(Literal::Text(text)),
+            // system.now -plus p30d is kept as one value.
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("system.now") => {
+                let mut value = word;
+                while let Some(Token::Operator(operator)) = self.peek().cloned() {
+                    if operator != "-plus" && operator != "-minus" {
+                        break;
+                    }
+                    self.position += 1;
+                    match self.next() {
+                        Some(Token::Word(duration)) => value = format!("{} {} {}", value, operator, duration),
+                        other => return Err(anyhow!("Expected a duration, found {}", describe(other.as_ref()))),
+                    }
+                }
+                Ok(Literal::Text(value))
+            }
+            Some(Token::Word(word)) => Ok(match word.to_lowercase().as_str() {
+                "null" => Literal::Null,
+                "true" => Literal::Bool(true),
+                "false" => Literal::Bool(false),
+                _ => Literal::Text(word),
+            }),
+            Some(Token::OpenList) => {
+                let mut items = Vec::new();
+                loop {
+                    match self.next() {
+                        Some(Token::Text(item)) | Some(Token::Word(item)) => items.push(item),
+                        Some(Token::CloseList) if items.is_empty() => break,
+                        other => return Err(anyhow!("Expected a list value, found {}", describe(other.as_ref()))),
+                    }
+                    if self.eat(&Token::CloseList) {
+                        break;
+                    }
+                    self.expect(&Token::Comma)?;
+                }
+                Ok(Literal::List(items))
+            }
+            other => Err(anyhow!("Expected a value, found {}", describe(other.as_ref()))),
+        }
+    }
+}
+
+pub fn parse(rule: &str) -> Result<Rule> {
+    let rule = rule.trim();
+    if rule.get(..18).is_some_and(|prefix| prefix.eq_ignore_ascii_case("Direct Reports for")) {
+        return Ok(Rule::DirectReports(rule[18..].trim().trim_matches(['"', '\'']).to_string()));
+    }
+    let mut parser = Parser {
+        tokens: tokenize(rule)?,
+        position: 0,
+    };
+    let parsed = parser.or()?;
+    if parser.peek().is_some() {
+        return Err(anyhow!("Unexpected {}", describe(parser.peek())));
+    }
+    Ok(parsed)
+}
+
+impl Rule {
+    // The user. and device. attributes the rule tests, without the inner attributes of -any
+    // and -all conditions.
+    pub fn attributes(&self) -> BTreeSet<String> {
+        let mut attributes = BTreeSet::new();
+        self.collect_attributes(&mut attributes);
+        attributes
+    }
+
+    fn collect_attributes(&self, attributes: &mut BTreeSet<String>) {
+        match self {
+            Rule::And(left, right) | Rule::Or(left, right) => {
+                left.collect_attributes(attributes);
+                right.collect_attributes(attributes);
+            }
+            Rule::Not(rule) => rule.collect_attributes(attributes),
+            Rule::Compare { property, .. } | Rule::Each { property, .. } => {
+                attributes.insert(property.clone());
+            }
+            Rule::DirectReports(_) => {}
+        }
+    }
+
+    // "user" or "device" for the objects the group holds, from the attributes it tests.
+    pub fn subject(&self) -> &'static str {
+        if matches!(self, Rule::DirectReports(_)) {
+            return "user";
+        }
+        let attributes = self.attributes();
+        let users = attributes.iter().any(|attribute| attribute.to_lowercase().starts_with("user."));
+        let devices = attributes.iter().any(|attribute| attribute.to_lowercase().starts_with("device."));
+        match (users, devices) {
+            (true, false) => "user",
+            (false, true) => "device",
+            (true, true) => "mixed",
+            (false, false) => "unknown",
+        }
+    }
+
+    pub fn issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        for attribute in self.attributes() {
+            if let Some((_, reason)) = DEPRECATED_ATTRIBUTES.iter().find(|(name, _)| name.eq_ignore_ascii_case(&attribute)) {
+                issues.push(format!("deprecated attribute {}: {}", attribute, reason));
+                continue;
+            }
+            let (prefix, name) = attribute.split_once('.').unwrap_or(("", &attribute));
+            let known = match prefix.to_lowercase().as_str() {
+                "user" => USER_ATTRIBUTES,
+                "device" => DEVICE_ATTRIBUTES,
+                _ => &[],
+            };
+            let supported = (!known.is_empty() && is_extension(name))
+                || known.iter().any(|known| known.eq_ignore_ascii_case(name));
+            if !supported {
+                issues.push(format!("unsupported attribute {}", attribute));
+            }
+        }
+        if self.subject() == "mixed" {
+            issues.push("mixes user and device attributes".to_string());
+        }
+        issues
+    }
+}
+
+fn is_extension(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower.starts_with("extension_")
+        || lower
+            .strip_prefix("extensionattribute")
+            .and_then(|number| number.parse::<u8>().ok())
+            .is_some_and(|number| (1..=15).contains(&number))
+}
+
+// The directory extensions a rule references, which have to be selected explicitly.
+pub fn directory_extensions(rule: &Rule) -> Vec<String> {
+    rule.attributes()
+        .iter()
+        .filter_map(|attribute| attribute.split_once('.'))
+        .filter(|(prefix, name)| prefix.eq_ignore_ascii_case("user") && name.to_lowercase().starts_with("extension_"))
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn field<'a>(object: &'a Value, name: &str) -> Option<&'a Value> {
+    object
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+// The value of a user. attribute on a user read with USER_SELECT, plus a memberOf array of
+// group ids.
+fn user_attribute<'a>(user: &'a Value, attribute: &str) -> Result<Option<&'a Value>> {
+    let name = match attribute.split_once('.') {
+        Some((prefix, name)) if prefix.eq_ignore_ascii_case("user") => name,
+        _ => return Err(anyhow!("{} cannot be evaluated for a user", attribute)),
+    };
+    if name.eq_ignore_ascii_case("sipProxyAddress") {
+        return Err(anyhow!("Graph does not return user.sipProxyAddress"));
+    }
+    if !is_extension(name) && !USER_ATTRIBUTES.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+        return Err(anyhow!("{} is not a supported attribute", attribute));
+    }
+    if is_extension(name) && !name.to_lowercase().starts_with("extension_") {
+        return Ok(field(user, "onPremisesExtensionAttributes").and_then(|attributes| field(attributes, name)));
+    }
+    let property = GRAPH_PROPERTIES
+        .iter()
+        .find(|(rule_name, _)| rule_name.eq_ignore_ascii_case(name))
+        .map_or(name, |(_, property)| property);
+    let value = field(user, property);
+    // businessPhones is a list in Graph but a single number in rules.
+    if property == "businessPhones" {
+        return Ok(value.and_then(|phones| phones.get(0)));
+    }
+    Ok(value)
+}
+
+fn text(value: Option<&Value>) -> Result<Option<String>> {
+    Ok(match value {
+        None | Some(Value::Null) => None,
+        Some(Value::String(text)) if text.is_empty() => None,
+        Some(Value::String(text)) => Some(text.clone()),
+        Some(Value::Bool(value)) => Some(value.to_string()),
+        Some(Value::Number(number)) => Some(number.to_string()),
+        Some(_) => return Err(anyhow!("A multi-valued attribute can only be tested with -any or -all")),
+    })
+}
+
+fn compare(actual: Option<&Value>, operator: &str, expected: &Literal) -> Result<bool> {
+    let actual = text(actual)?;
+    let lower = actual.as_deref().map(str::to_lowercase);
+    let negated = matches!(operator, "-ne" | "-notstartswith" | "-notcontains" | "-notmatch" | "-notin");
+    let matched = match (operator, expected) {
+        ("-eq" | "-ne", Literal::Null) => actual.is_none(),
+        ("-eq" | "-ne", Literal::Bool(value)) => lower.as_deref() == Some(if *value { "true" } else { "false" }),
+        ("-eq" | "-ne", Literal::Text(value)) => lower == Some(value.to_lowercase()),
+        ("-startswith" | "-notstartswith", Literal::Text(value)) => lower.is_some_and(|actual| actual.starts_with(&value.to_lowercase())),
+        ("-contains" | "-notcontains", Literal::Text(value)) => lower.is_some_and(|actual| actual.contains(&value.to_lowercase())),
+        ("-match" | "-notmatch", Literal::Text(pattern)) => {
+            let regex = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+            actual.is_some_and(|actual| regex.is_match(&actual))
+        }
+        ("-in" | "-notin", Literal::List(values)) => lower.is_some_and(|actual| values.iter().any(|value| value.to_lowercase() == actual)),
+        ("-le" | "-ge" | "-lt" | "-gt", Literal::Text(value)) => {
+            if value.to_lowercase().starts_with("system.now") {
+                return Err(anyhow!("Dates relative to system.now cannot be evaluated locally"));
+            }
+            let Some(actual) = actual else {
+                return Ok(false);
+            };
+            // Numbers compare as numbers and ISO 8601 dates as text.
+            let ordering = match (actual.parse::<f64>(), value.parse::<f64>()) {
+                (Ok(actual), Ok(value)) => actual.total_cmp(&value),
+                _ => actual.as_str().cmp(value.as_str()),
+            };
+            match operator {
+                "-le" => ordering.is_le(),
+                "-ge" => ordering.is_ge(),
+                "-lt" => ordering.is_lt(),
+                _ => ordering.is_gt(),
+            }
+        }
+        _ => return Err(anyhow!("{} cannot be used with {:?}", operator, expected)),
+    };
+    Ok(matched != negated)
+}
+
+// In a -any or -all condition, _ and any attribute of a string value is the value itself, and
+// attributes of an object value are its fields, such as assignedPlan.servicePlanId.
+fn element_attribute<'a>(element: &'a Value, attribute: &str) -> Option<&'a Value> {
+    match (element, attribute.split_once('.')) {
+        (Value::String(_), _) => Some(element),
+        (_, Some((_, name))) => field(element, name),
+        _ => Some(element),
+    }
+}
+
+fn evaluate_with(rule: &Rule, resolve: &dyn Fn(&str) -> Result<Option<Value>>) -> Result<bool> {
+    match rule {
+        Rule::And(left, right) => Ok(evaluate_with(left, resolve)? && evaluate_with(right, resolve)?),
+        Rule::Or(left, right) => Ok(evaluate_with(left, resolve)? || evaluate_with(right, resolve)?),
+        Rule::Not(rule) => Ok(!evaluate_with(rule, resolve)?),
+        Rule::Compare { property, operator, value } => compare(resolve(property)?.as_ref(), operator, value),
+        Rule::Each { property, all, condition } => {
+            let values = match resolve(property)? {
+                Some(Value::Array(values)) => values,
+                None | Some(Value::Null) => Vec::new(),
+                Some(_) => return Err(anyhow!("{} is not a multi-valued attribute", property)),
+            };
+            let mut results = Vec::new();
+            for element in &values {
+                results.push(evaluate_with(condition, &|attribute| Ok(element_attribute(element, attribute).cloned()))?);
+            }
+            Ok(if *all { results.iter().all(|result| *result) } else { results.iter().any(|result| *result) })
+        }
+        Rule::DirectReports(_) => Err(anyhow!("Direct Reports rules cannot be evaluated locally")),
+    }
+}
+
+// Whether a user would match the rule. Errors name what cannot be evaluated locally, such as
+// Direct Reports rules, device attributes, or dates relative to system.now.
+pub fn evaluate(rule: &Rule, user: &Value) -> Result<bool> {
+    evaluate_with(rule, &|attribute| Ok(user_attribute(user, attribute)?.cloned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_rules() {
+        let rule = parse(r#"(user.department -eq "Sales") -and -not (user.jobTitle -contains 'Intern') -or user.country -in ["US","CA"]"#).unwrap();
+        assert!(matches!(rule, Rule::Or(_, _)));
+        assert_eq!(rule.subject(), "user");
+        assert_eq!(
+            rule.attributes().into_iter().collect::<Vec<_>>(),
+            ["user.country", "user.department", "user.jobTitle"]
+        );
+        assert_eq!(parse(r#"(user.city -eq "Oslo") and (user.userType -eq "Member")"#).unwrap().attributes().len(), 2);
+        assert_eq!(parse("Direct Reports for \"62e19b97-8b3d-4d4a-a106-4ce66896a863\"").unwrap(), Rule::DirectReports("62e19b97-8b3d-4d4a-a106-4ce66896a863".to_string()));
+        assert_eq!(
+            parse(r#"user.displayName -eq "O`"Brien""#).unwrap(),
+            Rule::Compare {
+                property: "user.displayName".to_string(),
+                operator: "-eq".to_string(),
+                value: Literal::Text("O\"Brien".to_string()),
+            }
+        );
+        assert!(parse("(user.department -eq \"Sales\"").is_err());
+        assert!(parse("user.department -like \"Sales\"").is_err());
+        assert!(parse("user.department -eq \"Sales\" user.city").is_err());
+    }
+
+    #[test]
+    fn flags_deprecated_and_unsupported_attributes() {
+        let rule = parse(r#"(user.sipProxyAddress -ne null) -and (user.costCenter -eq "42") -and (device.deviceOSType -eq "Windows") -and (user.extensionAttribute7 -eq "x") -and (user.extension_b7d8e5_costCenter -eq "y")"#).unwrap();
+        assert_eq!(
+            rule.issues(),
+            [
+                "unsupported attribute user.costCenter",
+                "deprecated attribute user.sipProxyAddress: only maintained by Skype for Business, which is retired; use user.proxyAddresses",
+                "mixes user and device attributes",
+            ]
+        );
+        assert_eq!(directory_extensions(&rule), ["extension_b7d8e5_costCenter"]);
+    }
+
+    #[test]
+    fn evaluates_rules_against_users() {
+        let user = json!({
+            "id": "u1",
+            "accountEnabled": true,
+            "department": "Sales",
+            "country": "CA",
+            "jobTitle": null,
+            "businessPhones": ["+1 555 0100"],
+            "proxyAddresses": ["SMTP:jane@contoso.com", "smtp:jane@contoso.onmicrosoft.com"],
+            "assignedPlans": [{ "servicePlanId": "efb87545-963c-4e0d-99df-69c6916d9eb0", "capabilityStatus": "Enabled" }],
+            "onPremisesExtensionAttributes": { "extensionAttribute7": "EMEA" },
+            "memberOf": ["g1", "g2"],
+        });
+        let matches = |rule: &str| evaluate(&parse(rule).unwrap(), &user);
+        assert!(matches(r#"(user.department -eq "sales") -and (user.accountEnabled -eq true)"#).unwrap());
+        assert!(matches(r#"user.country -in ["US", "CA"] -and user.jobTitle -eq null"#).unwrap());
+        assert!(!matches(r#"user.department -ne "Sales" -or user.telephoneNumber -notStartsWith "+1""#).unwrap());
+        assert!(matches(r#"user.extensionAttribute7 -match "^em""#).unwrap());
+        assert!(matches(r#"user.proxyAddresses -any (_ -startsWith "smtp:jane@contoso.onmicrosoft")"#).unwrap());
+        assert!(matches(r#"user.assignedPlans -any (assignedPlan.servicePlanId -eq "efb87545-963c-4e0d-99df-69c6916d9eb0" -and assignedPlan.capabilityStatus -eq "Enabled")"#).unwrap());
+        assert!(matches(r#"user.memberOf -any (group.objectId -in ["g2", "g3"])"#).unwrap());
+        assert!(!matches(r#"user.proxyAddresses -all (_ -contains "@contoso.com")"#).unwrap());
+        assert!(matches(r#"user.employeeHireDate -ge system.now -plus p30d"#).is_err());
+        assert!(matches(r#"user.proxyAddresses -contains "jane""#).is_err());
+        assert!(matches("Direct Reports for \"62e19b97-8b3d-4d4a-a106-4ce66896a863\"").is_err());
+    }
+}
@@ -0,0 +1,638 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::actionlog::{Change, Undo};
+use graph_common::{client, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::env;
+
+#[derive(Parser, Debug)]
+#[command(name = "events")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Creates, updates, cancels, and lists calendar events in user and room mailboxes using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Create an event and send invitations to its attendees")]
+    Create {
+        #[arg(short = 'u', long, help = "Mailbox the event is created in, as its organizer")]
+        mailbox: String,
+
+        #[command(flatten)]
+        event: EventArgs,
+
+        #[arg(long, help = "Client-chosen id that keeps a retried run from creating the event twice")]
+        transaction_id: Option<String>,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+
+        #[arg(long, help = "Show the request without sending it")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Change an event; attendees get an updated invitation")]
+    Update {
+        #[arg(short = 'u', long, help = "Mailbox that organizes the event")]
+        mailbox: String,
+
+        #[arg(long, help = "Event id, as printed by create or list")]
+        id: String,
+
+        #[command(flatten)]
+        event: EventArgs,
+
+        #[arg(long, help = "Attendee email address to remove (repeatable)")]
+        remove_attendee: Vec<String>,
+
+        #[arg(long, help = "Show the request without sending it")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Cancel an event; attendees get a cancellation")]
+    Cancel {
+        #[arg(short = 'u', long, help = "Mailbox that organizes the event")]
+        mailbox: String,
+
+        #[arg(long, help = "Event id, as printed by create or list")]
+        id: String,
+
+        #[arg(short, long, help = "Message sent to the attendees with the cancellation")]
+        comment: Option<String>,
+
+        #[arg(long, help = "Show the request without sending it")]
+        dry_run: bool,
+    },
+
+    #[command(about = "List the events in a mailbox's calendar")]
+    List {
+        #[arg(short = 'u', long, help = "Mailbox whose calendar is listed")]
+        mailbox: String,
+
+        #[arg(long, help = "First day listed, as YYYY-MM-DD (defaults to today)")]
+        start: Option<String>,
+
+        #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(i64).range(1..=366), help = "Number of days listed")]
+        days: i64,
+
+        #[arg(long, default_value = "UTC", help = "Time zone the times are shown in, e.g. \"GMT Standard Time\"")]
+        time_zone: String,
+    },
+}
+
+#[derive(Args, Debug, Default)]
+struct EventArgs {
+    #[arg(short, long, help = "Subject")]
+    subject: Option<String>,
+
+    #[arg(long, help = "Start, as YYYY-MM-DDTHH:MM in --time-zone")]
+    start: Option<String>,
+
+    #[arg(long, help = "End, as YYYY-MM-DDTHH:MM in --time-zone")]
+    end: Option<String>,
+
+    #[arg(long, default_value = "UTC", help = "Time zone of --start and --end, e.g. \"GMT Standard Time\"")]
+    time_zone: String,
+
+    #[arg(short, long, help = "Required attendee email address (repeatable); update adds them")]
+    attendee: Vec<String>,
+
+    #[arg(long, help = "Optional attendee email address (repeatable); update adds them")]
+    optional_attendee: Vec<String>,
+
+    #[arg(long, help = "Room mailbox to book (repeatable); update adds them")]
+    room: Vec<String>,
+
+    #[arg(short, long, help = "Location shown on the event (defaults to the first room)")]
+    location: Option<String>,
+
+    #[arg(short, long, conflicts_with = "body_file", help = "Event description, as plain text")]
+    body: Option<String>,
+
+    #[arg(long, help = "File with the event description (.html is sent as is, anything else as plain text)")]
+    body_file: Option<String>,
+
+    #[arg(long, help = "Add a Microsoft Teams meeting link")]
+    teams: bool,
+
+    #[arg(long, value_parser = ["daily", "weekly", "monthly"], help = "Repeat the event, starting on the day of --start")]
+    recurrence: Option<String>,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=99), requires = "recurrence", help = "Repeat every this many days, weeks, or months")]
+    interval: u32,
+
+    #[arg(long, requires = "recurrence", conflicts_with = "count", help = "Last day the event repeats, as YYYY-MM-DD")]
+    until: Option<String>,
+
+    #[arg(long, requires = "recurrence", help = "Number of occurrences")]
+    count: Option<u32>,
+
+    #[arg(long, help = "Reminder this many minutes before the start")]
+    reminder: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    id: String,
+    subject: Option<String>,
+    start: Option<DateTimeTimeZone>,
+    end: Option<DateTimeTimeZone>,
+    #[serde(rename = "isOrganizer", default)]
+    is_organizer: bool,
+    #[serde(rename = "isCancelled", default)]
+    is_cancelled: bool,
+    #[serde(default)]
+    attendees: Vec<Value>,
+    organizer: Option<Value>,
+    #[serde(rename = "webLink")]
+    web_link: Option<String>,
+    #[serde(rename = "onlineMeeting")]
+    online_meeting: Option<OnlineMeeting>,
+    #[serde(rename = "iCalUId")]
+    ical_uid: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DateTimeTimeZone {
+    #[serde(rename = "dateTime")]
+    date_time: String,
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OnlineMeeting {
+    #[serde(rename = "joinUrl")]
+    join_url: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn parse_time(value: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(&value.trim().replacen(' ', "T", 1), "%Y-%m-%dT%H:%M")
+        .with_context(|| format!("{:?} is not a time (YYYY-MM-DDTHH:MM)", value))
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").with_context(|| format!("{:?} is not a date (YYYY-MM-DD)", value))
+}
+
+fn date_time(value: NaiveDateTime, time_zone: &str) -> Value {
+    json!({
+        "dateTime": value.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        "timeZone": time_zone,
+    })
+}
+
+// Graph stores descriptions as HTML, so plain text is escaped and its line breaks kept.
+fn to_html(text: &str) -> String {
+    text.trim_end()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>\n")
+}
+
+fn recurrence(args: &EventArgs, kind: &str, start: NaiveDateTime) -> Result<Value> {
+    let pattern = match kind {
+        "daily" => json!({ "type": "daily", "interval": args.interval }),
+        "weekly" => json!({
+            "type": "weekly",
+            "interval": args.interval,
+            "daysOfWeek": [start.format("%A").to_string().to_lowercase()],
+        }),
+        _ => json!({ "type": "absoluteMonthly", "interval": args.interval, "dayOfMonth": start.day() }),
+    };
+    let start_date = start.format("%Y-%m-%d").to_string();
+    let range = match (&args.until, args.count) {
+        (Some(until), _) => {
+            let until = parse_date(until)?;
+            if until < start.date() {
+                return Err(anyhow::anyhow!("--until is before --start"));
+            }
+            json!({ "type": "endDate", "startDate": start_date, "endDate": until.format("%Y-%m-%d").to_string() })
+        }
+        (None, Some(count)) => json!({ "type": "numbered", "startDate": start_date, "numberOfOccurrences": count }),
+        (None, None) => json!({ "type": "noEnd", "startDate": start_date }),
+    };
+    Ok(json!({ "pattern": pattern, "range": range }))
+}
+
+// The fields given on the command line, apart from attendees, as they are sent to Graph.
+fn event_fields(args: &EventArgs) -> Result<Map<String, Value>> {
+    let mut fields = Map::new();
+    if let Some(subject) = &args.subject {
+        fields.insert("subject".to_string(), json!(subject));
+    }
+    let start = args.start.as_deref().map(parse_time).transpose()?;
+    let end = args.end.as_deref().map(parse_time).transpose()?;
+    if let (Some(start), Some(end)) = (start, end) {
+        if end <= start {
+            return Err(anyhow::anyhow!("--end must be after --start"));
+        }
+    }
+    if let Some(start) = start {
+        fields.insert("start".to_string(), date_time(start, &args.time_zone));
+    }
+    if let Some(end) = end {
+        fields.insert("end".to_string(), date_time(end, &args.time_zone));
+    }
+    if let Some(kind) = &args.recurrence {
+        let start = start.context("--recurrence needs --start")?;
+        fields.insert("recurrence".to_string(), recurrence(args, kind, start)?);
+    }
+
+    let body = match (&args.body, &args.body_file) {
+        (Some(text), _) => Some(to_html(text)),
+        (None, Some(file)) => {
+            let content = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+            Some(if file.to_lowercase().ends_with(".html") || file.to_lowercase().ends_with(".htm") {
+                content
+            } else {
+                to_html(&content)
+            })
+        }
+        (None, None) => None,
+    };
+    if let Some(body) = body {
+        fields.insert("body".to_string(), json!({ "contentType": "html", "content": body }));
+    }
+    if let Some(location) = args.location.as_ref().or(args.room.first()) {
+        fields.insert("location".to_string(), json!({ "displayName": location }));
+    }
+    if args.teams {
+        fields.insert("isOnlineMeeting".to_string(), json!(true));
+        fields.insert("onlineMeetingProvider".to_string(), json!("teamsForBusiness"));
+    }
+    if let Some(minutes) = args.reminder {
+        fields.insert("isReminderOn".to_string(), json!(true));
+        fields.insert("reminderMinutesBeforeStart".to_string(), json!(minutes));
+    }
+    Ok(fields)
+}
+
+fn attendee_address(attendee: &Value) -> String {
+    attendee["emailAddress"]["address"].as_str().unwrap_or_default().to_lowercase()
+}
+
+// Adds the attendees given on the command line to `current` and drops the removed ones.
+// Returns None when the list does not change.
+fn merge_attendees(current: &[Value], args: &EventArgs, remove: &[String]) -> Option<Vec<Value>> {
+    let mut attendees: Vec<Value> = current
+        .iter()
+        .filter(|attendee| !remove.iter().any(|address| address.eq_ignore_ascii_case(&attendee_address(attendee))))
+        .cloned()
+        .collect();
+    let added = [
+        (&args.attendee, "required"),
+        (&args.optional_attendee, "optional"),
+        (&args.room, "resource"),
+    ];
+    for (addresses, kind) in added {
+        for address in addresses {
+            let address = address.trim().to_lowercase();
+            if !attendees.iter().any(|attendee| attendee_address(attendee) == address) {
+                attendees.push(json!({ "emailAddress": { "address": address }, "type": kind }));
+            }
+        }
+    }
+    (attendees.len() != current.len() || attendees.iter().zip(current).any(|(a, b)| a != b)).then_some(attendees)
+}
+
+fn describe(event: &Event) -> String {
+    let time = |value: &Option<DateTimeTimeZone>| {
+        value.as_ref().map_or("-".to_string(), |value| value.date_time.chars().take(16).collect::<String>().replace('T', " "))
+    };
+    let time_zone = event
+        .start
+        .as_ref()
+        .and_then(|start| start.time_zone.as_deref())
+        .unwrap_or("UTC");
+    format!(
+        "\"{}\" {} to {} ({})",
+        event.subject.as_deref().unwrap_or_default(),
+        time(&event.start),
+        time(&event.end),
+        time_zone
+    )
+}
+
+async fn read_event(client: &GraphClient, mailbox: &str, id: &str, fields: &str) -> Result<Value> {
+    client
+        .user(mailbox)
+        .resource()
+        .segment("events")
+        .segment(id)
+        .select(fields)
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read event {} in {}", id, mailbox))
+}
+
+async fn create(
+    client: &GraphClient,
+    mailbox: &str,
+    args: &EventArgs,
+    transaction_id: Option<&str>,
+    output: &str,
+    dry_run: bool,
+) -> Result<()> {
+    if args.subject.is_none() || args.start.is_none() || args.end.is_none() {
+        return Err(anyhow::anyhow!("create needs --subject, --start, and --end"));
+    }
+    let mut body = event_fields(args)?;
+    body.insert(
+        "attendees".to_string(),
+        json!(merge_attendees(&[], args, &[]).unwrap_or_default()),
+    );
+    if let Some(transaction_id) = transaction_id {
+        body.insert("transactionId".to_string(), json!(transaction_id));
+    }
+    let body = Value::Object(body);
+    if dry_run {
+        println!("POST {}", client.user(mailbox).resource().segment("events").url());
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        return Ok(());
+    }
+
+    let response = client.user(mailbox).resource().segment("events").post_json(&body).await?;
+    let event: Event = client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to create the event in {}", mailbox))?;
+    let join_url = event.online_meeting.as_ref().and_then(|meeting| meeting.join_url.as_deref());
+    if output == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "id": event.id,
+                "iCalUId": event.ical_uid,
+                "webLink": event.web_link,
+                "joinUrl": join_url,
+            }))?
+        );
+    } else {
+        println!("OK      created {} in {}", describe(&event), mailbox);
+        println!("id: {}", event.id);
+        if let Some(join_url) = join_url {
+            println!("Teams link: {}", join_url);
+        }
+    }
+    Ok(())
+}
+
+// The undo PATCHes back the previous value of every field that was changed.
+async fn update(client: &GraphClient, mailbox: &str, id: &str, args: &EventArgs, remove: &[String], dry_run: bool) -> Result<()> {
+    let mut body = event_fields(args)?;
+    let mut select: Vec<&str> = body.keys().map(String::as_str).collect();
+    select.extend(["id", "subject", "start", "end", "isOrganizer", "attendees"]);
+    select.sort();
+    select.dedup();
+    let previous = read_event(client, mailbox, id, &select.join(",")).await?;
+    let event: Event = serde_json::from_value(previous.clone()).context("Failed to parse the event")?;
+    if !event.is_organizer {
+        return Err(anyhow::anyhow!("{} is not the organizer of {}; only the organizer can change it", mailbox, describe(&event)));
+    }
+    if let Some(attendees) = merge_attendees(&event.attendees, args, remove) {
+        body.insert("attendees".to_string(), json!(attendees));
+    }
+    if body.is_empty() {
+        println!("SKIPPED {}: nothing to change", describe(&event));
+        return Ok(());
+    }
+    let undo: Map<String, Value> = body
+        .keys()
+        .map(|key| (key.clone(), previous.get(key).cloned().unwrap_or(Value::Null)))
+        .collect();
+    let changed: Vec<String> = body.keys().cloned().collect();
+    let body = Value::Object(body);
+    let url = client.user(mailbox).resource().segment("events").segment(id).url();
+    if dry_run {
+        println!("PATCH {}", url);
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        return Ok(());
+    }
+
+    let change = Change::new("PATCH", &url, Some(&body)).with_undo(Undo {
+        method: "PATCH".to_string(),
+        url: url.clone(),
+        body: Some(Value::Object(undo)),
+    });
+    let response = client.send_change(change).await?;
+    let updated: Event = client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to update {}", describe(&event)))?;
+    println!("OK      updated {}: {}", describe(&updated), changed.join(", "));
+    Ok(())
+}
+
+// Meetings are cancelled so attendees are told; an appointment without attendees is deleted.
+async fn cancel(client: &GraphClient, mailbox: &str, id: &str, comment: Option<&str>, dry_run: bool) -> Result<()> {
+    let previous = read_event(client, mailbox, id, "id,subject,start,end,isOrganizer,isCancelled,attendees").await?;
+    let event: Event = serde_json::from_value(previous).context("Failed to parse the event")?;
+    if event.is_cancelled {
+        println!("SKIPPED {}: already cancelled", describe(&event));
+        return Ok(());
+    }
+    if !event.is_organizer {
+        return Err(anyhow::anyhow!("{} is not the organizer of {}; only the organizer can cancel it", mailbox, describe(&event)));
+    }
+    let resource = client.user(mailbox).resource().segment("events").segment(id);
+    let (method, url, body) = if event.attendees.is_empty() {
+        ("DELETE", resource.url(), None)
+    } else {
+        ("POST", resource.segment("cancel").url(), Some(json!({ "comment": comment.unwrap_or_default() })))
+    };
+    if dry_run {
+        println!("{} {}", method, url);
+        if let Some(body) = &body {
+            println!("{}", serde_json::to_string_pretty(body)?);
+        }
+        return Ok(());
+    }
+    let response = client.send_change(Change::new(method, &url, body.as_ref())).await?;
+    client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to cancel {}", describe(&event)))?;
+    println!(
+        "OK      {} {}",
+        if event.attendees.is_empty() { "deleted" } else { "cancelled" },
+        describe(&event)
+    );
+    Ok(())
+}
+
+async fn list(client: &GraphClient, mailbox: &str, start: NaiveDate, days: i64) -> Result<Vec<Event>> {
+    let start = start.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let end = start + Duration::days(days);
+    client
+        .user(mailbox)
+        .resource()
+        .segment("calendarView")
+        .query("startDateTime", &start.format("%Y-%m-%dT%H:%M:%S").to_string())
+        .query("endDateTime", &end.format("%Y-%m-%dT%H:%M:%S").to_string())
+        .select("id,subject,start,end,organizer,isCancelled")
+        .query("$orderby", "start/dateTime")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the events in {}", mailbox))
+}
+
+fn render_list(events: &[Event]) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("{:<17} {:<17} {:<40} {:<30} ID\n", "START", "END", "SUBJECT", "ORGANIZER"));
+    let time = |value: &Option<DateTimeTimeZone>| {
+        value.as_ref().map_or("-".to_string(), |value| value.date_time.chars().take(16).collect::<String>().replace('T', " "))
+    };
+    for event in events {
+        let subject = format!(
+            "{}{}",
+            if event.is_cancelled { "Canceled: " } else { "" },
+            event.subject.as_deref().unwrap_or_default()
+        );
+        let organizer = event
+            .organizer
+            .as_ref()
+            .and_then(|organizer| organizer["emailAddress"]["address"].as_str())
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "{:<17} {:<17} {:<40} {:<30} {}\n",
+            time(&event.start),
+            time(&event.end),
+            subject.chars().take(40).collect::<String>(),
+            organizer,
+            event.id
+        ));
+    }
+    out.push_str(&format!("\n{} events\n", events.len()));
+    out
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting events");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let mut client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    if let Commands::List { time_zone, .. } = &config.command {
+        // Graph returns event times in UTC unless asked for another zone.
+        client = client.with_header("Prefer", &format!("outlook.timezone=\"{}\"", time_zone));
+        client.require_permissions(&["Calendars.Read"])?;
+    } else {
+        client.require_permissions(&["Calendars.ReadWrite"])?;
+    }
+
+    let result = match &config.command {
+        Commands::Create {
+            mailbox,
+            event,
+            transaction_id,
+            output,
+            dry_run,
+        } => create(&client, mailbox, event, transaction_id.as_deref(), output, *dry_run).await,
+        Commands::Update {
+            mailbox,
+            id,
+            event,
+            remove_attendee,
+            dry_run,
+        } => update(&client, mailbox, id, event, remove_attendee, *dry_run).await,
+        Commands::Cancel {
+            mailbox,
+            id,
+            comment,
+            dry_run,
+        } => cancel(&client, mailbox, id, comment.as_deref(), *dry_run).await,
+        Commands::List {
+            mailbox, start, days, ..
+        } => {
+            let start = match start {
+                Some(start) => parse_date(start)?,
+                None => Utc::now().date_naive(),
+            };
+            let events = list(&client, mailbox, start, *days).await?;
+            print!("{}", render_list(&events));
+            Ok(())
+        }
+    };
+    client.stats().summary().print();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &[&str]) -> EventArgs {
+        let config = AppConfig::parse_from([&["events", "create", "-u", "it@contoso.com"], command].concat());
+        match config.command {
+            Commands::Create { event, .. } => event,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn builds_event_requests() {
+        let event = args(&[
+            "-s", "Patching window", "--start", "2026-10-20T22:00", "--end", "2026-10-20T23:30",
+            "--time-zone", "GMT Standard Time", "-a", "Ops@contoso.com", "--room", "room-1@contoso.com",
+            "-b", "Servers <prod> restart\nExpect downtime", "--teams", "--recurrence", "weekly", "--count", "4",
+        ]);
+        let fields = event_fields(&event).unwrap();
+        assert_eq!(
+            Value::Object(fields),
+            json!({
+                "subject": "Patching window",
+                "start": { "dateTime": "2026-10-20T22:00:00", "timeZone": "GMT Standard Time" },
+                "end": { "dateTime": "2026-10-20T23:30:00", "timeZone": "GMT Standard Time" },
+                "recurrence": {
+                    "pattern": { "type": "weekly", "interval": 1, "daysOfWeek": ["tuesday"] },
+                    "range": { "type": "numbered", "startDate": "2026-10-20", "numberOfOccurrences": 4 },
+                },
+                "body": { "contentType": "html", "content": "Servers &lt;prod&gt; restart<br>\nExpect downtime" },
+                "location": { "displayName": "room-1@contoso.com" },
+                "isOnlineMeeting": true,
+                "onlineMeetingProvider": "teamsForBusiness",
+            })
+        );
+        assert!(event_fields(&args(&["--start", "2026-10-20T22:00", "--end", "2026-10-20T21:00"])).is_err());
+        assert!(event_fields(&args(&["--recurrence", "daily"])).is_err());
+
+        let current = vec![
+            json!({ "emailAddress": { "address": "ops@contoso.com" }, "type": "required" }),
+            json!({ "emailAddress": { "address": "jane.doe@contoso.com" }, "type": "optional" }),
+        ];
+        assert_eq!(merge_attendees(&current, &event, &[]).unwrap().len(), 3);
+        assert_eq!(merge_attendees(&current, &args(&["-a", "OPS@contoso.com"]), &[]), None);
+        let removed = merge_attendees(&current, &args(&[]), &["Jane.Doe@contoso.com".to_string()]).unwrap();
+        assert_eq!(removed, current[..1].to_vec());
+    }
+}
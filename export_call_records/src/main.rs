@@ -0,0 +1,518 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::io::{BufWriter, Write};
+
+const CSV_HEADER: &str = "callId,callStart,callType,modalities,segmentStart,segmentEnd,durationSeconds,caller,callerPlatform,callerConnection,callee,calleePlatform,calleeConnection,streams,avgJitterMs,maxJitterMs,avgPacketLossPct,maxPacketLossPct,avgRoundTripMs,maxRoundTripMs,avgAudioDegradation,poorQuality,failure";
+
+#[derive(Parser, Debug)]
+#[command(name = "export_call_records")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports Microsoft Teams call records with their participants, duration, and media quality to CSV or NDJSON, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "csv", value_parser = ["csv", "ndjson"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, value_name = "UPN", help = "Export only calls this user took part in (can be repeated)")]
+    user: Vec<String>,
+
+    #[arg(long, conflicts_with = "days", help = "Export calls that started from this date (YYYY-MM-DD) or time (RFC 3339) on")]
+    start: Option<String>,
+
+    #[arg(long, help = "Export calls that started up to and including this date (YYYY-MM-DD), or before this time (RFC 3339)")]
+    end: Option<String>,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i64).range(1..=30), help = "Export calls from this many days back, when --start is not given")]
+    days: i64,
+
+    #[arg(long, help = "Only export segments with poor media quality")]
+    poor_only: bool,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=20), help = "Number of call records read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CallRecord {
+    id: String,
+    #[serde(rename = "startDateTime")]
+    start_date_time: String,
+    #[serde(rename = "type")]
+    call_type: Option<String>,
+    modalities: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Session {
+    segments: Vec<Segment>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Segment {
+    #[serde(rename = "startDateTime")]
+    start_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "endDateTime")]
+    end_date_time: Option<DateTime<Utc>>,
+    caller: Option<Endpoint>,
+    callee: Option<Endpoint>,
+    media: Vec<Media>,
+    #[serde(rename = "failureInfo")]
+    failure_info: Option<FailureInfo>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Endpoint {
+    #[serde(rename = "associatedIdentity")]
+    associated_identity: Option<Identity>,
+    identity: Option<IdentitySet>,
+    #[serde(rename = "userAgent")]
+    user_agent: Option<UserAgent>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct IdentitySet {
+    user: Option<Identity>,
+    phone: Option<Identity>,
+    guest: Option<Identity>,
+    application: Option<Identity>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Identity {
+    id: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct UserAgent {
+    platform: Option<String>,
+    #[serde(rename = "productFamily")]
+    product_family: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Media {
+    #[serde(rename = "callerNetwork")]
+    caller_network: Option<Network>,
+    #[serde(rename = "calleeNetwork")]
+    callee_network: Option<Network>,
+    streams: Vec<MediaStream>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Network {
+    #[serde(rename = "connectionType")]
+    connection_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct MediaStream {
+    #[serde(rename = "averageJitter")]
+    average_jitter: Option<String>,
+    #[serde(rename = "maxJitter")]
+    max_jitter: Option<String>,
+    #[serde(rename = "averagePacketLossRate")]
+    average_packet_loss_rate: Option<f64>,
+    #[serde(rename = "maxPacketLossRate")]
+    max_packet_loss_rate: Option<f64>,
+    #[serde(rename = "averageRoundTripTime")]
+    average_round_trip_time: Option<String>,
+    #[serde(rename = "maxRoundTripTime")]
+    max_round_trip_time: Option<String>,
+    #[serde(rename = "averageAudioDegradation")]
+    average_audio_degradation: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct FailureInfo {
+    stage: Option<String>,
+    reason: Option<String>,
+}
+
+// The worst value of each metric across the streams of a segment.
+#[derive(Debug, Default)]
+struct Quality {
+    streams: usize,
+    avg_jitter_ms: Option<f64>,
+    max_jitter_ms: Option<f64>,
+    avg_packet_loss: Option<f64>,
+    max_packet_loss: Option<f64>,
+    avg_round_trip_ms: Option<f64>,
+    max_round_trip_ms: Option<f64>,
+    avg_audio_degradation: Option<f64>,
+}
+
+impl Quality {
+    fn of(segment: &Segment) -> Self {
+        let mut quality = Quality::default();
+        let worst = |current: &mut Option<f64>, value: Option<f64>| {
+            if let Some(value) = value {
+                *current = Some(current.map_or(value, |current| current.max(value)));
+            }
+        };
+        for stream in segment.media.iter().flat_map(|media| &media.streams) {
+            quality.streams += 1;
+            let ms = |value: &Option<String>| value.as_deref().and_then(duration_ms);
+            worst(&mut quality.avg_jitter_ms, ms(&stream.average_jitter));
+            worst(&mut quality.max_jitter_ms, ms(&stream.max_jitter));
+            worst(&mut quality.avg_packet_loss, stream.average_packet_loss_rate);
+            worst(&mut quality.max_packet_loss, stream.max_packet_loss_rate);
+            worst(&mut quality.avg_round_trip_ms, ms(&stream.average_round_trip_time));
+            worst(&mut quality.max_round_trip_ms, ms(&stream.max_round_trip_time));
+            worst(&mut quality.avg_audio_degradation, stream.average_audio_degradation);
+        }
+        quality
+    }
+
+    // The thresholds the Teams call quality dashboard uses to classify an audio stream as poor.
+    fn is_poor(&self) -> bool {
+        self.avg_jitter_ms.is_some_and(|jitter| jitter > 30.0)
+            || self.avg_packet_loss.is_some_and(|loss| loss > 0.1)
+            || self.avg_round_trip_ms.is_some_and(|round_trip| round_trip > 500.0)
+            || self.avg_audio_degradation.is_some_and(|degradation| degradation > 1.0)
+    }
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// A date alone means midnight UTC at its start, or with end_of_day, the midnight after it, so
+// that "--end 2026-10-03" includes the whole day.
+fn parse_time(value: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        return Ok(if end_of_day { start + Duration::days(1) } else { start });
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| anyhow!("{} is not a date (YYYY-MM-DD) or an RFC 3339 time", value))
+}
+
+// Media metrics are ISO 8601 durations such as "PT0.031S" or "PT1M2.5S".
+fn duration_ms(value: &str) -> Option<f64> {
+    let mut rest = value.strip_prefix("PT")?;
+    let mut seconds = 0.0;
+    for (unit, factor) in [('H', 3600.0), ('M', 60.0), ('S', 1.0)] {
+        if let Some((number, tail)) = rest.split_once(unit) {
+            seconds += number.parse::<f64>().ok()? * factor;
+            rest = tail;
+        }
+    }
+    rest.is_empty().then_some(seconds * 1000.0)
+}
+
+fn build_filter(start: DateTime<Utc>, end: Option<DateTime<Utc>>, user_ids: &[String]) -> String {
+    let mut filters = vec![format!("startDateTime ge {}", timestamp(start))];
+    if let Some(end) = end {
+        filters.push(format!("startDateTime lt {}", timestamp(end)));
+    }
+    let users: Vec<String> = user_ids
+        .iter()
+        .map(|id| format!("participants_v2/any(p:p/id eq {})", encode::odata_string(id)))
+        .collect();
+    match users.len() {
+        0 => {}
+        1 => filters.extend(users),
+        _ => filters.push(format!("({})", users.join(" or "))),
+    }
+    filters.join(" and ")
+}
+
+fn endpoint_name(endpoint: Option<&Endpoint>) -> String {
+    let Some(endpoint) = endpoint else { return String::new() };
+    if let Some(identity) = &endpoint.associated_identity {
+        if let Some(name) = identity.user_principal_name.as_ref().or(identity.display_name.as_ref()) {
+            return name.clone();
+        }
+    }
+    let identities = endpoint.identity.as_ref();
+    [
+        identities.and_then(|set| set.user.as_ref()),
+        identities.and_then(|set| set.guest.as_ref()),
+        identities.and_then(|set| set.phone.as_ref()),
+        identities.and_then(|set| set.application.as_ref()),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|identity| identity.display_name.clone().or_else(|| identity.id.clone()))
+    .unwrap_or_default()
+}
+
+fn platform(endpoint: Option<&Endpoint>) -> String {
+    let agent = endpoint.and_then(|endpoint| endpoint.user_agent.as_ref());
+    match (
+        agent.and_then(|agent| agent.product_family.as_deref()),
+        agent.and_then(|agent| agent.platform.as_deref()),
+    ) {
+        (Some(product), Some(platform)) => format!("{}/{}", product, platform),
+        (product, platform) => product.or(platform).unwrap_or_default().to_string(),
+    }
+}
+
+fn connection(segment: &Segment, network: fn(&Media) -> &Option<Network>) -> String {
+    segment
+        .media
+        .iter()
+        .find_map(|media| network(media).as_ref()?.connection_type.clone())
+        .unwrap_or_default()
+}
+
+fn number(value: Option<f64>, scale: f64) -> String {
+    value.map(|value| format!("{:.1}", value * scale)).unwrap_or_default()
+}
+
+fn csv_rows(record: &CallRecord, sessions: &[Session], poor_only: bool) -> Vec<String> {
+    let mut rows = Vec::new();
+    for segment in sessions.iter().flat_map(|session| &session.segments) {
+        let quality = Quality::of(segment);
+        if poor_only && !quality.is_poor() {
+            continue;
+        }
+        let duration = match (segment.start_date_time, segment.end_date_time) {
+            (Some(start), Some(end)) => (end - start).num_seconds().to_string(),
+            _ => String::new(),
+        };
+        let failure = segment
+            .failure_info
+            .as_ref()
+            .map(|failure| {
+                [failure.stage.as_deref(), failure.reason.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(": ")
+            })
+            .unwrap_or_default();
+        rows.push(
+            [
+                csv::field(&record.id),
+                record.start_date_time.clone(),
+                record.call_type.clone().unwrap_or_default(),
+                csv::field(&record.modalities.join(";")),
+                segment.start_date_time.map(timestamp).unwrap_or_default(),
+                segment.end_date_time.map(timestamp).unwrap_or_default(),
+                duration,
+                csv::field(&endpoint_name(segment.caller.as_ref())),
+                csv::field(&platform(segment.caller.as_ref())),
+                connection(segment, |media| &media.caller_network),
+                csv::field(&endpoint_name(segment.callee.as_ref())),
+                csv::field(&platform(segment.callee.as_ref())),
+                connection(segment, |media| &media.callee_network),
+                quality.streams.to_string(),
+                number(quality.avg_jitter_ms, 1.0),
+                number(quality.max_jitter_ms, 1.0),
+                number(quality.avg_packet_loss, 100.0),
+                number(quality.max_packet_loss, 100.0),
+                number(quality.avg_round_trip_ms, 1.0),
+                number(quality.max_round_trip_ms, 1.0),
+                quality.avg_audio_degradation.map(|degradation| format!("{:.2}", degradation)).unwrap_or_default(),
+                quality.is_poor().to_string(),
+                csv::field(&failure),
+            ]
+            .join(","),
+        );
+    }
+    rows
+}
+
+async fn user_ids(client: &GraphClient, upns: &[String]) -> Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for upn in upns {
+        let user: User = client
+            .user(upn)
+            .resource()
+            .select("id")
+            .get_json()
+            .await
+            .with_context(|| format!("Failed to look up {}", upn))?;
+        ids.push(user.id);
+    }
+    Ok(ids)
+}
+
+async fn read_sessions(client: &GraphClient, id: &str) -> Result<Vec<Value>> {
+    client
+        .path(&["communications", "callRecords", id, "sessions"])
+        .query("$expand", "segments")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the sessions of call {}", id))
+}
+
+// The sessions of each call are read separately, since a call with many segments has its
+// sessions paged. NDJSON writes each call record with all its sessions on one line; CSV writes
+// one row per segment, the leg between two endpoints, with the worst quality of its streams.
+async fn export(client: &GraphClient, filter: &str, config: &AppConfig, out: &mut dyn Write) -> Result<(usize, usize)> {
+    let records: Vec<Value> = client
+        .path(&["communications", "callRecords"])
+        .filter(filter)
+        .get_all()
+        .await
+        .context("Failed to fetch call records")?;
+    info!("Reading the sessions of {} calls", records.len());
+
+    if config.output == "csv" {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+    let mut results = stream::iter(&records)
+        .map(|record| async move {
+            let id = record.get("id").and_then(Value::as_str).unwrap_or_default();
+            (record, read_sessions(client, id).await)
+        })
+        .buffered(config.concurrency as usize);
+    let (mut exported, mut failed) = (0, 0);
+    while let Some((record, sessions)) = results.next().await {
+        let sessions = match sessions {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        if config.output == "ndjson" {
+            let mut record = record.clone();
+            record["sessions"] = json!(sessions);
+            writeln!(out, "{}", serde_json::to_string(&record)?)?;
+        } else {
+            let call: CallRecord = serde_json::from_value(record.clone()).context("Failed to parse a call record")?;
+            let sessions: Vec<Session> = serde_json::from_value(json!(sessions)).context("Failed to parse call sessions")?;
+            for row in csv_rows(&call, &sessions, config.poor_only) {
+                writeln!(out, "{}", row)?;
+            }
+        }
+        exported += 1;
+        out.flush().context("Failed to write the export")?;
+    }
+    Ok((exported, failed))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting export_call_records");
+    debug!("Configuration: {:?}", config);
+
+    let start = match &config.start {
+        Some(start) => parse_time(start, false)?,
+        None => Utc::now() - Duration::days(config.days),
+    };
+    let end = config.end.as_deref().map(|end| parse_time(end, true)).transpose()?;
+    if end.is_some_and(|end| end <= start) {
+        return Err(anyhow!("--end must be after the start of the export"));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["CallRecords.Read.All"])?;
+    if !config.user.is_empty() {
+        client.require_permissions(&["User.Read.All|User.ReadBasic.All|Directory.Read.All"])?;
+    }
+
+    let ids = user_ids(&client, &config.user).await?;
+    let filter = build_filter(start, end, &ids);
+    debug!("Filter: {}", filter);
+
+    let phase = client.stats().phase("export call records");
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let (exported, failed) = export(&client, &filter, &config, &mut out).await?;
+    out.flush().context("Failed to write the export")?;
+    drop(phase);
+    info!("Exported {} calls", exported);
+
+    client.stats().summary().print();
+    if failed > 0 {
+        return Err(anyhow!("The sessions of {} calls could not be read; they are missing from the export", failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    fn config(args: &[&str]) -> AppConfig {
+        AppConfig::parse_from(["export_call_records"].iter().chain(args))
+    }
+
+    #[test]
+    fn builds_filters_and_reads_durations() {
+        let start = parse_time("2026-10-13", false).unwrap();
+        let end = parse_time("2026-10-14", true).unwrap();
+        assert_eq!(build_filter(start, None, &[]), "startDateTime ge 2026-10-13T00:00:00Z");
+        assert_eq!(
+            build_filter(start, Some(end), &["id-1".to_string(), "id-2".to_string()]),
+            "startDateTime ge 2026-10-13T00:00:00Z and startDateTime lt 2026-10-15T00:00:00Z and (participants_v2/any(p:p/id eq 'id-1') or participants_v2/any(p:p/id eq 'id-2'))"
+        );
+        assert_eq!(duration_ms("PT0.031S"), Some(31.0));
+        assert_eq!(duration_ms("PT1M2.5S"), Some(62500.0));
+        assert_eq!(duration_ms("P1D"), None);
+    }
+
+    #[tokio::test]
+    async fn exports_match_golden_files() {
+        let client = golden::fixture_client();
+        let filter = build_filter(parse_time("2026-10-13", false).unwrap(), None, &[]);
+        for (args, file) in [
+            (&["-o", "csv"][..], "export.csv"),
+            (&["-o", "csv", "--poor-only"][..], "poor.csv"),
+            (&["-o", "ndjson"][..], "export.ndjson"),
+        ] {
+            let mut out = Vec::new();
+            let (exported, failed) = export(&client, &filter, &config(args), &mut out).await.unwrap();
+            assert_eq!((exported, failed), (2, 1));
+            golden::assert_matches(&golden::testdata(&format!("golden/{}", file)), &String::from_utf8(out).unwrap());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+use std::io::{BufWriter, Write};
+
+const CSV_HEADER: &str = "activityDateTime,category,activityDisplayName,operationType,result,resultReason,initiatedBy,initiatorIpAddress,targets,modifiedProperties,loggedByService,correlationId,id";
+
+#[derive(Parser, Debug)]
+#[command(name = "export_directory_audits")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports Microsoft Entra ID directory audit logs, filtered by activity, category, initiator, target, date, or result, to CSV or NDJSON, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "csv", value_parser = ["csv", "ndjson"], help = "Output format")]
+    output: String,
+
+    #[arg(long, value_name = "NAME", help = "Export only this activity, such as \"Add member to role\" (can be repeated)")]
+    activity: Vec<String>,
+
+    #[arg(long, value_name = "NAME", help = "Export only this category, such as RoleManagement (can be repeated)")]
+    category: Vec<String>,
+
+    #[arg(short, long, value_name = "UPN_OR_APP", help = "Export only changes made by this user or application (can be repeated)")]
+    initiator: Vec<String>,
+
+    #[arg(short, long, value_name = "UPN_OR_NAME", help = "Export only changes to this user, group, application, or other object (can be repeated)")]
+    target: Vec<String>,
+
+    #[arg(long, conflicts_with = "days", help = "Export changes from this date (YYYY-MM-DD) or time (RFC 3339) on")]
+    start: Option<String>,
+
+    #[arg(long, help = "Export changes up to and including this date (YYYY-MM-DD), or before this time (RFC 3339)")]
+    end: Option<String>,
+
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(i64).range(1..=30), help = "Export changes from this many days back, when --start is not given")]
+    days: i64,
+
+    #[arg(long, value_parser = ["success", "failure"], help = "Export only successful or only failed changes")]
+    result: Option<String>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct DirectoryAudit {
+    id: String,
+    #[serde(rename = "activityDateTime")]
+    activity_date_time: String,
+    category: Option<String>,
+    #[serde(rename = "activityDisplayName")]
+    activity_display_name: Option<String>,
+    #[serde(rename = "operationType")]
+    operation_type: Option<String>,
+    result: Option<String>,
+    #[serde(rename = "resultReason")]
+    result_reason: Option<String>,
+    #[serde(rename = "initiatedBy")]
+    initiated_by: Option<InitiatedBy>,
+    #[serde(rename = "targetResources")]
+    target_resources: Vec<TargetResource>,
+    #[serde(rename = "loggedByService")]
+    logged_by_service: Option<String>,
+    #[serde(rename = "correlationId")]
+    correlation_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct InitiatedBy {
+    user: Option<Identity>,
+    app: Option<Identity>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Identity {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "ipAddress")]
+    ip_address: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct TargetResource {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "type")]
+    resource_type: Option<String>,
+    #[serde(rename = "modifiedProperties")]
+    modified_properties: Vec<ModifiedProperty>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ModifiedProperty {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "oldValue")]
+    old_value: Option<String>,
+    #[serde(rename = "newValue")]
+    new_value: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// A date alone means midnight UTC at its start, or with end_of_day, the midnight after it, so
+// that "--end 2026-10-03" includes the whole day.
+fn parse_time(value: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let start = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        return Ok(if end_of_day { start + Duration::days(1) } else { start });
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| anyhow!("{} is not a date (YYYY-MM-DD) or an RFC 3339 time", value))
+}
+
+// Values of one filter are alternatives, so they are joined with "or"; different filters must
+// all match.
+fn any_of(clauses: Vec<String>) -> Option<String> {
+    match clauses.len() {
+        0 => None,
+        1 => clauses.into_iter().next(),
+        _ => Some(format!("({})", clauses.join(" or "))),
+    }
+}
+
+// Initiators and targets with an @ are matched by user principal name, anything else by display
+// name, which is how applications and groups show up in the log.
+fn build_filter(config: &AppConfig, start: DateTime<Utc>, end: Option<DateTime<Utc>>) -> String {
+    let mut filters = vec![format!("activityDateTime ge {}", timestamp(start))];
+    if let Some(end) = end {
+        filters.push(format!("activityDateTime lt {}", timestamp(end)));
+    }
+    let equals = |field: &str, values: &[String]| {
+        values
+            .iter()
+            .map(|value| format!("{} eq {}", field, encode::odata_string(value)))
+            .collect::<Vec<_>>()
+    };
+    filters.extend(any_of(equals("activityDisplayName", &config.activity)));
+    filters.extend(any_of(equals("category", &config.category)));
+    filters.extend(any_of(
+        config
+            .initiator
+            .iter()
+            .map(|initiator| {
+                if initiator.contains('@') {
+                    format!("initiatedBy/user/userPrincipalName eq {}", encode::odata_string(initiator))
+                } else {
+                    format!("initiatedBy/app/displayName eq {}", encode::odata_string(initiator))
+                }
+            })
+            .collect(),
+    ));
+    filters.extend(any_of(
+        config
+            .target
+            .iter()
+            .map(|target| {
+                let field = if target.contains('@') { "userPrincipalName" } else { "displayName" };
+                format!("targetResources/any(t: t/{} eq {})", field, encode::odata_string(target))
+            })
+            .collect(),
+    ));
+    if let Some(result) = &config.result {
+        filters.push(format!("result eq {}", encode::odata_string(result)));
+    }
+    filters.join(" and ")
+}
+
+fn initiator(audit: &DirectoryAudit) -> (String, String) {
+    let Some(initiated_by) = &audit.initiated_by else {
+        return Default::default();
+    };
+    let identity = initiated_by.user.as_ref().or(initiated_by.app.as_ref());
+    let name = identity
+        .and_then(|identity| identity.user_principal_name.as_ref().or(identity.display_name.as_ref()))
+        .filter(|name| !name.is_empty());
+    let ip_address = identity.and_then(|identity| identity.ip_address.as_ref());
+    (name.cloned().unwrap_or_default(), ip_address.cloned().unwrap_or_default())
+}
+
+// Values are logged as JSON text, such as "[\"Global Administrator\"]"; single values are shown
+// without the brackets and quotes, and missing ones as "-".
+fn property_value(value: Option<&str>) -> String {
+    let value = value.unwrap_or_default().trim();
+    let value = match serde_json::from_str::<Value>(value) {
+        Ok(Value::Array(items)) if items.len() == 1 => match &items[0] {
+            Value::String(item) => item.clone(),
+            item => item.to_string(),
+        },
+        Ok(Value::String(item)) => item,
+        _ => value.to_string(),
+    };
+    let value = value.trim().trim_end_matches(';').trim_end();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(audit: &DirectoryAudit) -> String {
+    let text = |value: &Option<String>| csv::field(value.as_deref().unwrap_or_default());
+    let (initiated_by, ip_address) = initiator(audit);
+    let targets: Vec<String> = audit
+        .target_resources
+        .iter()
+        .filter_map(|target| {
+            let name = target
+                .user_principal_name
+                .as_ref()
+                .or(target.display_name.as_ref())
+                .filter(|name| !name.is_empty())?;
+            Some(match &target.resource_type {
+                Some(resource_type) => format!("{} ({})", name, resource_type),
+                None => name.clone(),
+            })
+        })
+        .collect();
+    let changes: Vec<String> = audit
+        .target_resources
+        .iter()
+        .flat_map(|target| &target.modified_properties)
+        .map(|property| {
+            format!(
+                "{}: {} -> {}",
+                property.display_name.as_deref().unwrap_or_default(),
+                property_value(property.old_value.as_deref()),
+                property_value(property.new_value.as_deref())
+            )
+        })
+        .collect();
+    [
+        audit.activity_date_time.clone(),
+        text(&audit.category),
+        text(&audit.activity_display_name),
+        text(&audit.operation_type),
+        text(&audit.result),
+        text(&audit.result_reason),
+        csv::field(&initiated_by),
+        ip_address,
+        csv::field(&targets.join("; ")),
+        csv::field(&changes.join("; ")),
+        text(&audit.logged_by_service),
+        text(&audit.correlation_id),
+        csv::field(&audit.id),
+    ]
+    .join(",")
+}
+
+// Writes each page as it arrives. NDJSON keeps every field Graph returns, one change per line;
+// CSV flattens the initiator, targets, and modified properties into one row.
+async fn export(client: &GraphClient, filter: &str, output: &str, out: &mut dyn Write) -> Result<usize> {
+    if output == "csv" {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+    let count = client
+        .path(&["auditLogs", "directoryAudits"])
+        .filter(filter)
+        .top(999)
+        .for_each_page(|page: Vec<Value>| {
+            for audit in page {
+                if output == "ndjson" {
+                    writeln!(out, "{}", serde_json::to_string(&audit)?)?;
+                } else {
+                    let audit: DirectoryAudit = serde_json::from_value(audit).context("Failed to parse a directory audit")?;
+                    writeln!(out, "{}", csv_row(&audit))?;
+                }
+            }
+            out.flush().context("Failed to write the export")
+        })
+        .await
+        .context("Failed to fetch directory audit logs")?;
+    Ok(count)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting export_directory_audits");
+    debug!("Configuration: {:?}", config);
+
+    let start = match &config.start {
+        Some(start) => parse_time(start, false)?,
+        None => Utc::now() - Duration::days(config.days),
+    };
+    let end = config.end.as_deref().map(|end| parse_time(end, true)).transpose()?;
+    if end.is_some_and(|end| end <= start) {
+        return Err(anyhow!("--end must be after the start of the export"));
+    }
+    let filter = build_filter(&config, start, end);
+    debug!("Filter: {}", filter);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["AuditLog.Read.All"])?;
+
+    let phase = client.stats().phase("export directory audits");
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let count = export(&client, &filter, &config.output, &mut out).await?;
+    out.flush().context("Failed to write the export")?;
+    drop(phase);
+    info!("Exported {} directory audit entries", count);
+
+    client.stats().summary().print();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    fn config(args: &[&str]) -> AppConfig {
+        AppConfig::parse_from(["export_directory_audits"].iter().chain(args))
+    }
+
+    #[test]
+    fn builds_filters() {
+        let start = parse_time("2026-10-01", false).unwrap();
+        let end = parse_time("2026-10-03", true).unwrap();
+        assert_eq!(
+            build_filter(&config(&[]), start, None),
+            "activityDateTime ge 2026-10-01T00:00:00Z"
+        );
+        assert_eq!(
+            build_filter(
+                &config(&[
+                    "--activity", "Add member to role",
+                    "--activity", "Consent to application",
+                    "-i", "it.admin@contoso.com",
+                    "-i", "Microsoft Graph PowerShell",
+                    "-t", "O'Brien Team",
+                    "--result", "success",
+                ]),
+                start,
+                Some(end)
+            ),
+            "activityDateTime ge 2026-10-01T00:00:00Z and activityDateTime lt 2026-10-04T00:00:00Z and (activityDisplayName eq 'Add member to role' or activityDisplayName eq 'Consent to application') and (initiatedBy/user/userPrincipalName eq 'it.admin@contoso.com' or initiatedBy/app/displayName eq 'Microsoft Graph PowerShell') and targetResources/any(t: t/displayName eq 'O''Brien Team') and result eq 'success'"
+        );
+        assert_eq!(
+            build_filter(&config(&["--category", "RoleManagement", "-t", "jane.doe@contoso.com"]), start, None),
+            "activityDateTime ge 2026-10-01T00:00:00Z and category eq 'RoleManagement' and targetResources/any(t: t/userPrincipalName eq 'jane.doe@contoso.com')"
+        );
+    }
+
+    #[test]
+    fn unwraps_single_property_values() {
+        assert_eq!(property_value(Some("[\"Global Administrator\"]")), "Global Administrator");
+        assert_eq!(property_value(Some("[]")), "[]");
+        assert_eq!(property_value(Some("[true]")), "true");
+        assert_eq!(property_value(Some("[\"a\",\"b\"]")), "[\"a\",\"b\"]");
+        assert_eq!(property_value(Some("\"[] => [[Scope: Mail.Read]]; \"")), "[] => [[Scope: Mail.Read]]");
+        assert_eq!(property_value(None), "-");
+    }
+
+    #[tokio::test]
+    async fn exports_match_golden_files() {
+        let client = golden::fixture_client();
+        let filter = build_filter(&config(&[]), parse_time("2026-10-01", false).unwrap(), None);
+        for (output, file) in [("csv", "export.csv"), ("ndjson", "export.ndjson")] {
+            let mut out = Vec::new();
+            let count = export(&client, &filter, output, &mut out).await.unwrap();
+            assert_eq!(count, 3);
+            golden::assert_matches(&golden::testdata(&format!("golden/{}", file)), &String::from_utf8(out).unwrap());
+        }
+    }
+}
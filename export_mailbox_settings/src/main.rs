@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::collect_upns;
+use graph_common::stats::Summary;
+use graph_common::{csv, mail, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+use std::fmt::Write as _;
+
+#[derive(Parser, Debug)]
+#[command(name = "export_mailbox_settings")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports the mailbox settings of all or selected users to CSV or JSON, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "csv", value_parser = ["csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, value_delimiter = ',', help = "Export only this user (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "Export only the users in this CSV with a upn column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    mail: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct MailboxSettings {
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+    language: Option<Language>,
+    #[serde(rename = "dateFormat")]
+    date_format: Option<String>,
+    #[serde(rename = "timeFormat")]
+    time_format: Option<String>,
+    #[serde(rename = "workingHours")]
+    working_hours: Option<WorkingHours>,
+    #[serde(rename = "userPurpose")]
+    user_purpose: Option<String>,
+    #[serde(rename = "automaticRepliesSetting")]
+    automatic_replies: Option<AutomaticReplies>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Language {
+    locale: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct WorkingHours {
+    #[serde(rename = "daysOfWeek")]
+    days_of_week: Vec<String>,
+    #[serde(rename = "startTime")]
+    start_time: Option<String>,
+    #[serde(rename = "endTime")]
+    end_time: Option<String>,
+    #[serde(rename = "timeZone")]
+    time_zone: Option<NamedTimeZone>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct NamedTimeZone {
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct AutomaticReplies {
+    status: Option<String>,
+    #[serde(rename = "externalAudience")]
+    external_audience: Option<String>,
+    #[serde(rename = "scheduledStartDateTime")]
+    scheduled_start: Option<DateTimeTimeZone>,
+    #[serde(rename = "scheduledEndDateTime")]
+    scheduled_end: Option<DateTimeTimeZone>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct DateTimeTimeZone {
+    #[serde(rename = "dateTime")]
+    date_time: Option<String>,
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct MessageRules {
+    value: Vec<Value>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct MailboxRow {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "timeZone")]
+    time_zone: String,
+    locale: String,
+    #[serde(rename = "dateFormat")]
+    date_format: String,
+    #[serde(rename = "timeFormat")]
+    time_format: String,
+    #[serde(rename = "workingDays")]
+    working_days: Vec<String>,
+    #[serde(rename = "workingHoursStart")]
+    working_hours_start: String,
+    #[serde(rename = "workingHoursEnd")]
+    working_hours_end: String,
+    #[serde(rename = "workingHoursTimeZone")]
+    working_hours_time_zone: String,
+    #[serde(rename = "userPurpose")]
+    user_purpose: String,
+    #[serde(rename = "automaticReplies")]
+    automatic_replies: String,
+    #[serde(rename = "automaticRepliesAudience")]
+    automatic_replies_audience: String,
+    #[serde(rename = "automaticRepliesStart")]
+    automatic_replies_start: String,
+    #[serde(rename = "automaticRepliesEnd")]
+    automatic_replies_end: String,
+    #[serde(rename = "forwardingRules")]
+    forwarding_rules: Option<usize>,
+    #[serde(rename = "forwardingTo")]
+    forwarding_to: Vec<String>,
+    error: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Users without a mail address have no mailbox, so they are left out of a full export.
+async fn list_mailbox_users(client: &GraphClient) -> Result<Vec<String>> {
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,mail")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    let mut upns: Vec<String> = users
+        .into_iter()
+        .filter(|user| user.mail.is_some())
+        .map(|user| user.user_principal_name)
+        .collect();
+    upns.sort_by_key(|upn| upn.to_lowercase());
+    Ok(upns)
+}
+
+// "08:00:00.0000000" becomes "08:00".
+fn short_time(value: Option<&str>) -> String {
+    value.map_or(String::new(), |value| value.chars().take(5).collect())
+}
+
+fn scheduled(value: Option<&DateTimeTimeZone>) -> String {
+    let Some(date_time) = value.and_then(|value| value.date_time.as_deref()) else {
+        return String::new();
+    };
+    let date_time: String = date_time.chars().take(16).collect();
+    match value.and_then(|value| value.time_zone.as_deref()) {
+        Some(time_zone) => format!("{} ({})", date_time, time_zone),
+        None => date_time,
+    }
+}
+
+fn to_row(upn: &str, settings: MailboxSettings, rules: Result<MessageRules>) -> MailboxRow {
+    let working_hours = settings.working_hours.unwrap_or_default();
+    let replies = settings.automatic_replies.unwrap_or_default();
+    // Scheduled times are kept by Graph after replies are turned off, so they are only shown
+    // while they apply.
+    let is_scheduled = replies.status.as_deref() == Some("scheduled");
+    let (forwarding_rules, forwarding_to) = match &rules {
+        Ok(rules) => {
+            let forwarding: Vec<&Value> = rules
+                .value
+                .iter()
+                .filter(|rule| !mail::forwarding_recipients(rule).is_empty())
+                .collect();
+            let mut recipients: Vec<String> =
+                forwarding.iter().flat_map(|rule| mail::forwarding_recipients(rule)).collect();
+            recipients.sort();
+            recipients.dedup();
+            (Some(forwarding.len()), recipients)
+        }
+        Err(_) => (None, Vec::new()),
+    };
+    MailboxRow {
+        user_principal_name: upn.to_string(),
+        time_zone: settings.time_zone.unwrap_or_default(),
+        locale: settings.language.and_then(|language| language.locale).unwrap_or_default(),
+        date_format: settings.date_format.unwrap_or_default(),
+        time_format: settings.time_format.unwrap_or_default(),
+        working_days: working_hours.days_of_week,
+        working_hours_start: short_time(working_hours.start_time.as_deref()),
+        working_hours_end: short_time(working_hours.end_time.as_deref()),
+        working_hours_time_zone: working_hours.time_zone.and_then(|zone| zone.name).unwrap_or_default(),
+        user_purpose: settings.user_purpose.unwrap_or_default(),
+        automatic_replies: replies.status.unwrap_or_default(),
+        automatic_replies_audience: replies.external_audience.unwrap_or_default(),
+        automatic_replies_start: if is_scheduled { scheduled(replies.scheduled_start.as_ref()) } else { String::new() },
+        automatic_replies_end: if is_scheduled { scheduled(replies.scheduled_end.as_ref()) } else { String::new() },
+        forwarding_rules,
+        forwarding_to,
+        error: rules.err().map(|e| format!("inbox rules: {:#}", e)),
+    }
+}
+
+// Settings and inbox rules are read through $batch, 20 mailboxes per request. A mailbox that
+// cannot be read is exported with the error instead of failing the export.
+async fn collect(client: &GraphClient, upns: &[String]) -> Result<Vec<MailboxRow>> {
+    let phase = client.stats().phase("read mailbox settings");
+    let urls: Vec<String> = upns
+        .iter()
+        .map(|upn| client.user(upn).mailbox_settings().relative_url())
+        .collect();
+    let settings: Vec<Result<MailboxSettings>> = client.batch_get(&urls).await?;
+    drop(phase);
+    let _phase = client.stats().phase("read inbox rules");
+    let urls: Vec<String> = upns.iter().map(|upn| client.user(upn).inbox_rules().relative_url()).collect();
+    let rules: Vec<Result<MessageRules>> = client.batch_get(&urls).await?;
+
+    Ok(upns
+        .iter()
+        .zip(settings)
+        .zip(rules)
+        .map(|((upn, settings), rules)| match settings {
+            Ok(settings) => to_row(upn, settings, rules),
+            Err(e) => MailboxRow {
+                user_principal_name: upn.clone(),
+                error: Some(format!("{:#}", e)),
+                ..MailboxRow::default()
+            },
+        })
+        .collect())
+}
+
+fn render_report(rows: &[MailboxRow], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let failed = rows.iter().filter(|row| row.error.is_some()).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "mailboxes": rows,
+                "failed": failed,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        _ => {
+            writeln!(
+                out,
+                "userPrincipalName,timeZone,locale,dateFormat,timeFormat,workingDays,workingHoursStart,workingHoursEnd,workingHoursTimeZone,userPurpose,automaticReplies,automaticRepliesAudience,automaticRepliesStart,automaticRepliesEnd,forwardingRules,forwardingTo,error"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.user_principal_name),
+                    csv::field(&row.time_zone),
+                    row.locale,
+                    csv::field(&row.date_format),
+                    csv::field(&row.time_format),
+                    row.working_days.join(";"),
+                    row.working_hours_start,
+                    row.working_hours_end,
+                    csv::field(&row.working_hours_time_zone),
+                    row.user_purpose,
+                    row.automatic_replies,
+                    row.automatic_replies_audience,
+                    csv::field(&row.automatic_replies_start),
+                    csv::field(&row.automatic_replies_end),
+                    row.forwarding_rules.map_or(String::new(), |count| count.to_string()),
+                    csv::field(&row.forwarding_to.join(";")),
+                    csv::field(row.error.as_deref().unwrap_or_default())
+                )?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting export_mailbox_settings");
+    debug!("Configuration: {:?}", config);
+
+    let selected = collect_upns(&config.upn, config.file.as_ref())?;
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec!["MailboxSettings.Read"];
+    if selected.is_empty() {
+        required.push("User.ReadBasic.All|User.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let upns = if selected.is_empty() {
+        let _phase = client.stats().phase("list users");
+        list_mailbox_users(&client).await?
+    } else {
+        selected
+    };
+    info!("Exporting the mailbox settings of {} users", upns.len());
+    let rows = collect(&client, &upns).await?;
+    let failed = rows.iter().filter(|row| row.error.is_some()).count();
+    if failed > 0 {
+        info!("{} of {} mailboxes could not be read completely; see the error column", failed, rows.len());
+    }
+    print!("{}", render_report(&rows, &config.output, &client.stats().summary())?);
+    if config.output != "json" {
+        client.stats().summary().print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let upns = list_mailbox_users(&client).await.unwrap();
+        let rows = collect(&client, &upns).await.unwrap();
+        golden::assert_reports("mailboxes", &["csv", "json"], |output| render_report(&rows, output, &Summary::fixture()));
+    }
+}
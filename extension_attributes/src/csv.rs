@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use graph_common::csv::Table;
+
+pub struct Row {
+    pub line: usize,
+    pub upn: String,
+    pub value: String,
+}
+
+// The file needs a upn (or userPrincipalName) column and a value column, named either
+// "value" or after the attribute, so an HR export can be used without renaming columns.
+pub fn read_rows(content: &str, attribute_names: &[&str]) -> Result<Vec<Row>> {
+    let table = Table::parse(content)?;
+    let upn_column = table.upn_column()?;
+    let mut value_names = vec!["value"];
+    value_names.extend_from_slice(attribute_names);
+    let value_column = table
+        .column(&value_names)
+        .with_context(|| format!("The file has no value or {} column", attribute_names.join(" or ")))?;
+
+    let mut rows = Vec::new();
+    for record in &table.records {
+        let upn = record.get(upn_column);
+        if upn.is_empty() {
+            return Err(anyhow::anyhow!("Line {} has no UPN", record.line));
+        }
+        rows.push(Row {
+            line: record.line,
+            upn: upn.to_string(),
+            value: record.get(value_column).to_string(),
+        });
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_named_columns_with_quoted_values() {
+        let content = "employeeId,UserPrincipalName,costCenter\n\
+                       # exported from HR\n\
+                       100,jane@contoso.com,\"Sales, EMEA\"\n\
+                       101,john@contoso.com,\n\
+                       102,o'brien@contoso.com,\"R&D \"\"Labs\"\"\"\n";
+        let rows = read_rows(content, &["costCenter", "extension_abc_costCenter"]).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].upn, "jane@contoso.com");
+        assert_eq!(rows[0].value, "Sales, EMEA");
+        assert_eq!(rows[0].line, 3);
+        assert_eq!(rows[1].value, "");
+        assert_eq!(rows[2].value, "R&D \"Labs\"");
+
+        assert!(read_rows("upn,department\njane@contoso.com,Sales\n", &["costCenter"]).is_err());
+    }
+}
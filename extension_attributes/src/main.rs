@@ -0,0 +1,442 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::actionlog::{Change, Undo};
+use graph_common::{client, encode, service, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Read;
+
+mod csv;
+
+const DATA_TYPES: [&str; 6] = ["String", "Integer", "LargeInteger", "Boolean", "DateTime", "Binary"];
+
+#[derive(Parser, Debug)]
+#[command(name = "extension_attributes")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Creates, lists, and populates directory extension attributes on users using the Microsoft API")]
+struct AppConfig {
+    #[arg(long, global = true, help = "Application (client) id of the app registration that owns the attributes (defaults to CLIENT_ID)")]
+    app_id: Option<String>,
+
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "List the extension attributes defined on the app registration")]
+    List {
+        #[arg(long, help = "List every extension attribute available in the tenant, from any app")]
+        all: bool,
+    },
+
+    #[command(about = "Define a new extension attribute for users")]
+    Create {
+        #[arg(short, long, help = "Attribute name, e.g. costCenter")]
+        name: String,
+
+        #[arg(short = 't', long = "type", default_value = "String", value_parser = DATA_TYPES, help = "Data type of the attribute")]
+        data_type: String,
+
+        #[arg(long, help = "Allow several values per user")]
+        multi_valued: bool,
+    },
+
+    #[command(about = "Set the attribute on users from a CSV file")]
+    Populate {
+        #[arg(short, long, help = "CSV with a upn column and a value column named value or after the attribute (- for stdin)")]
+        file: String,
+
+        #[arg(short, long, help = "Attribute name, either the short name or the full extension_<appId>_<name>")]
+        name: String,
+
+        #[arg(long, help = "Show the changes without making them")]
+        dry_run: bool,
+
+        #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+        concurrency: u16,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct Application {
+    id: String,
+    #[serde(rename = "appId")]
+    app_id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExtensionProperty {
+    name: String,
+    #[serde(rename = "dataType")]
+    data_type: String,
+    #[serde(rename = "isMultiValued", default)]
+    is_multi_valued: bool,
+    #[serde(rename = "targetObjects", default)]
+    target_objects: Vec<String>,
+    #[serde(rename = "appDisplayName")]
+    app_display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExtensionPropertyList {
+    value: Vec<ExtensionProperty>,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(flatten)]
+    properties: HashMap<String, Value>,
+}
+
+enum Outcome {
+    Updated,
+    Unchanged,
+    Planned(Value, Value),
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Graph names directory extensions extension_<appId without dashes>_<name>.
+fn full_name(app_id: &str, name: &str) -> String {
+    if name.starts_with("extension_") {
+        name.to_string()
+    } else {
+        format!("extension_{}_{}", app_id.replace('-', ""), name)
+    }
+}
+
+fn short_name(full_name: &str) -> &str {
+    full_name
+        .strip_prefix("extension_")
+        .and_then(|rest| rest.split_once('_'))
+        .map(|(_, name)| name)
+        .unwrap_or(full_name)
+}
+
+async fn resolve_application(client: &GraphClient, app_id: &str) -> Result<Application> {
+    let mut applications = client
+        .path(&["applications"])
+        .filter(&format!("appId eq {}", encode::odata_string(app_id)))
+        .select("id,appId,displayName")
+        .get_all::<Application>()
+        .await
+        .with_context(|| format!("Failed to look up the app registration {}", app_id))?;
+    if applications.is_empty() {
+        return Err(anyhow::anyhow!("No app registration with application id {}", app_id));
+    }
+    Ok(applications.remove(0))
+}
+
+async fn list_extension_properties(client: &GraphClient, application: &Application) -> Result<Vec<ExtensionProperty>> {
+    client
+        .path(&["applications", &application.id, "extensionProperties"])
+        .get_all()
+        .await
+        .context("Failed to list extension attributes")
+}
+
+// getAvailableExtensionProperties is a POST but changes nothing, so it is sent without
+// being recorded in the action log.
+async fn list_available_extension_properties(client: &GraphClient) -> Result<Vec<ExtensionProperty>> {
+    let url = client
+        .path(&["directoryObjects", "getAvailableExtensionProperties"])
+        .url();
+    let body = json!({ "isSyncedFromOnPremises": false });
+    let response = client
+        .send(|http| http.post(&url).json(&body))
+        .await?;
+    let list: ExtensionPropertyList = client::parse_json(response)
+        .await
+        .context("Failed to list extension attributes available in the tenant")?;
+    Ok(list.value)
+}
+
+fn print_extension_properties(properties: &[ExtensionProperty], show_app: bool) {
+    if properties.is_empty() {
+        println!("No extension attributes found");
+        return;
+    }
+    print!("{:<60} {:<12} {:<6} {:<20}", "NAME", "TYPE", "MULTI", "TARGETS");
+    println!("{}", if show_app { " APP" } else { "" });
+    for property in properties {
+        print!(
+            "{:<60} {:<12} {:<6} {:<20}",
+            property.name,
+            property.data_type,
+            if property.is_multi_valued { "yes" } else { "no" },
+            property.target_objects.join(",")
+        );
+        match property.app_display_name.as_deref().filter(|_| show_app) {
+            Some(app) => println!(" {}", app),
+            None => println!(),
+        }
+    }
+}
+
+fn scalar_value(data_type: &str, raw: &str) -> Result<Value> {
+    match data_type {
+        "Integer" | "LargeInteger" => raw
+            .parse::<i64>()
+            .map(Value::from)
+            .with_context(|| format!("{:?} is not an integer", raw)),
+        "Boolean" => match raw.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Ok(Value::Bool(true)),
+            "false" | "no" | "0" => Ok(Value::Bool(false)),
+            _ => Err(anyhow::anyhow!("{:?} is not a boolean", raw)),
+        },
+        _ => Ok(Value::String(raw.to_string())),
+    }
+}
+
+// An empty cell clears the attribute; multi-valued attributes take values separated by ';'.
+fn attribute_value(property: &ExtensionProperty, raw: &str) -> Result<Value> {
+    if raw.is_empty() {
+        return Ok(Value::Null);
+    }
+    if property.is_multi_valued {
+        return raw
+            .split(';')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| scalar_value(&property.data_type, value))
+            .collect::<Result<Vec<Value>>>()
+            .map(Value::Array);
+    }
+    scalar_value(&property.data_type, raw)
+}
+
+fn read_file(source: &str) -> Result<String> {
+    if source == "-" {
+        debug!("Reading rows from stdin");
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read rows from stdin")?;
+        Ok(content)
+    } else {
+        debug!("Reading rows from {}", source);
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))
+    }
+}
+
+// The current value is read first so unchanged users are skipped and the previous value is
+// recorded in the action log for `mstools undo`.
+async fn populate_user(client: &GraphClient, upn: &str, attribute: &str, value: &Value, dry_run: bool) -> Result<Outcome> {
+    let resource = client.user(upn).resource();
+    let user: User = resource
+        .clone()
+        .select(&format!("id,{}", attribute))
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to look up {}", upn))?;
+    let current = user.properties.get(attribute).cloned().unwrap_or(Value::Null);
+    if &current == value {
+        return Ok(Outcome::Unchanged);
+    }
+    if dry_run {
+        return Ok(Outcome::Planned(current, value.clone()));
+    }
+
+    let url = resource.url();
+    let body = json!({ attribute: value });
+    let change = Change::new("PATCH", &url, Some(&body)).with_undo(Undo {
+        method: "PATCH".to_string(),
+        url: url.clone(),
+        body: Some(json!({ attribute: current })),
+    });
+    let response = client.send_change(change).await?;
+    client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to set {} for {}", attribute, upn))?;
+    Ok(Outcome::Updated)
+}
+
+async fn populate(client: &GraphClient, application: &Application, name: &str, file: &str, dry_run: bool, concurrency: usize) -> Result<()> {
+    let attribute = full_name(&application.app_id, name);
+    let properties = list_extension_properties(client, application).await?;
+    let property = properties
+        .iter()
+        .find(|property| property.name.eq_ignore_ascii_case(&attribute))
+        .with_context(|| {
+            format!(
+                "{} is not defined on {}; create it first or pass the owning app with --app-id",
+                attribute,
+                application.display_name.as_deref().unwrap_or(&application.app_id)
+            )
+        })?;
+    if !property.target_objects.iter().any(|target| target == "User") {
+        return Err(anyhow::anyhow!("{} does not apply to users", property.name));
+    }
+    let attribute = property.name.as_str();
+
+    let rows = csv::read_rows(&read_file(file)?, &[short_name(attribute), attribute])?;
+    let mut seen = HashSet::new();
+    if let Some(duplicate) = rows.iter().find(|row| !seen.insert(row.upn.to_lowercase())) {
+        return Err(anyhow::anyhow!("{} appears more than once (line {})", duplicate.upn, duplicate.line));
+    }
+    info!("Setting {} for {} users", attribute, rows.len());
+
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let shutdown = service::shutdown_signal();
+    let mut updated = 0;
+    let mut unchanged = 0;
+    let mut failed = 0;
+    let mut results = stream::iter(&rows)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|row| async move {
+            let result = match attribute_value(property, &row.value) {
+                Ok(value) => populate_user(client, &row.upn, attribute, &value, dry_run).await,
+                Err(e) => Err(e.context(format!("Line {}", row.line))),
+            };
+            (row, result)
+        })
+        .buffer_unordered(concurrency);
+    let phase = client.stats().phase("update users");
+    let mut finished = HashSet::new();
+    while let Some((row, result)) = results.next().await {
+        finished.insert(row.line);
+        match result {
+            Ok(Outcome::Updated) => {
+                updated += 1;
+                println!("OK      {}", row.upn);
+            }
+            Ok(Outcome::Planned(current, value)) => {
+                updated += 1;
+                println!("CHANGE  {}: {} -> {}", row.upn, current, value);
+            }
+            Ok(Outcome::Unchanged) => {
+                unchanged += 1;
+                println!("SKIPPED {}: unchanged", row.upn);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", row.upn, e);
+            }
+        }
+    }
+
+    let interrupted = rows.len() - finished.len();
+    if interrupted > 0 {
+        warn!("Interrupted before {} users were processed", interrupted);
+        for row in rows.iter().filter(|row| !finished.contains(&row.line)) {
+            println!("SKIPPED {}: not started", row.upn);
+        }
+    }
+
+    println!();
+    println!(
+        "Processed {} of {} users: {} {}, {} unchanged, {} failed, {} skipped",
+        rows.len() - interrupted,
+        rows.len(),
+        updated,
+        if dry_run { "to update" } else { "updated" },
+        unchanged,
+        failed,
+        interrupted
+    );
+    if dry_run {
+        println!("Dry run, no changes were made.");
+    }
+    drop(phase);
+    client.stats().summary().print();
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} users were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, rows.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting extension_attributes");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&match config.command {
+        Commands::Create { .. } => vec!["Application.ReadWrite.OwnedBy|Application.ReadWrite.All"],
+        Commands::List { .. } => vec!["Application.Read.All|Application.ReadWrite.OwnedBy"],
+        Commands::Populate { .. } => vec!["Application.Read.All|Application.ReadWrite.OwnedBy", "User.ReadWrite.All"],
+    })?;
+
+    let app_id = config.app_id.clone().unwrap_or(client_id);
+    match config.command {
+        Commands::List { all: true } => {
+            print_extension_properties(&list_available_extension_properties(&client).await?, true);
+        }
+        Commands::List { all: false } => {
+            let application = resolve_application(&client, &app_id).await?;
+            print_extension_properties(&list_extension_properties(&client, &application).await?, false);
+        }
+        Commands::Create {
+            name,
+            data_type,
+            multi_valued,
+        } => {
+            let application = resolve_application(&client, &app_id).await?;
+            let body = json!({
+                "name": short_name(&name),
+                "dataType": data_type,
+                "isMultiValued": multi_valued,
+                "targetObjects": ["User"],
+            });
+            let response = client
+                .path(&["applications", &application.id, "extensionProperties"])
+                .post_json(&body)
+                .await?;
+            let created: ExtensionProperty = client::parse_json(response)
+                .await
+                .with_context(|| format!("Failed to create {}", name))?;
+            println!(
+                "Created {} ({}{}) on {}",
+                created.name,
+                created.data_type,
+                if created.is_multi_valued { ", multi-valued" } else { "" },
+                application.display_name.as_deref().unwrap_or(&application.app_id)
+            );
+        }
+        Commands::Populate {
+            file,
+            name,
+            dry_run,
+            concurrency,
+        } => {
+            let application = resolve_application(&client, &app_id).await?;
+            populate(&client, &application, &name, &file, dry_run, concurrency as usize).await?;
+        }
+    }
+
+    info!("Operation completed successfully.");
+    Ok(())
+}
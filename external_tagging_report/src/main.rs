@@ -0,0 +1,365 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::stats::Summary;
+use graph_common::{service, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+mod report;
+use report::{DomainReport, Message, Thresholds};
+
+#[derive(Parser, Debug)]
+#[command(name = "external_tagging_report")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports external-sender tagging coverage and frequent external correspondent domains")]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "Mailboxes to sample (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(long, default_value_t = 50, help = "Number of mailboxes to sample when --upn is not given")]
+    sample: usize,
+
+    #[arg(short, long, default_value_t = 30, help = "Number of days of received mail to read")]
+    days: i64,
+
+    #[arg(short, long, default_value = "[EXTERNAL]", help = "Subject prefix or banner text that marks a message as external")]
+    tag: String,
+
+    #[arg(long, help = "File of domains already on the external tagging allow list, one per line")]
+    allow_list: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 20, help = "Messages a domain needs before it is suggested for the allow list")]
+    min_messages: usize,
+
+    #[arg(long, default_value_t = 3, help = "Sampled mailboxes a domain must have written to before it is suggested")]
+    min_mailboxes: usize,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=20), help = "Number of mailboxes read at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "Enable info level logging")]
+    info: bool,
+
+    #[arg(long, help = "Enable debug level logging")]
+    debug: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    id: String,
+    #[serde(rename = "isVerified")]
+    is_verified: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    mail: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct TaggingReport {
+    #[serde(rename = "generatedAt")]
+    generated_at: String,
+    days: i64,
+    tag: String,
+    #[serde(rename = "internalDomains")]
+    internal_domains: Vec<String>,
+    #[serde(rename = "mailboxesSampled")]
+    mailboxes_sampled: usize,
+    #[serde(rename = "mailboxesFailed")]
+    mailboxes_failed: Vec<String>,
+    #[serde(rename = "externalMessages")]
+    external_messages: usize,
+    #[serde(rename = "taggedMessages")]
+    tagged_messages: usize,
+    domains: Vec<DomainReport>,
+    #[serde(rename = "runStats")]
+    run_stats: Summary,
+}
+
+fn setup_logger(config: &AppConfig) {
+    let mut builder = Builder::from_default_env();
+    builder.filter(
+        None,
+        if config.debug {
+            LevelFilter::Debug
+        } else if config.info {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        },
+    );
+    builder.init();
+}
+
+fn read_allow_list(path: &PathBuf) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim().trim_start_matches("*.").to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+async fn internal_domains(client: &GraphClient) -> Result<Vec<String>> {
+    let domains: Vec<Domain> = client
+        .path(&["domains"])
+        .select("id,isVerified")
+        .get_all()
+        .await
+        .context("Failed to fetch the tenant's domains")?;
+    Ok(domains
+        .into_iter()
+        .filter(|domain| domain.is_verified)
+        .map(|domain| domain.id.to_lowercase())
+        .collect())
+}
+
+async fn sample_mailboxes(client: &GraphClient, config: &AppConfig) -> Result<Vec<String>> {
+    if !config.upn.is_empty() {
+        return Ok(config.upn.clone());
+    }
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,mail,accountEnabled")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+    Ok(users
+        .into_iter()
+        .filter(|user| user.mail.is_some() && user.account_enabled == Some(true))
+        .map(|user| user.user_principal_name)
+        .take(config.sample)
+        .collect())
+}
+
+async fn received_messages(client: &GraphClient, upn: &str, since: &str) -> Result<Vec<Message>> {
+    client
+        .user(upn)
+        .mail_folders()
+        .segment("inbox")
+        .segment("messages")
+        .filter(&format!("receivedDateTime ge {}", since))
+        .select("from,subject,bodyPreview,receivedDateTime")
+        .top(100)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to read the inbox of {}", upn))
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+fn render_report(report: &TaggingReport) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "Sampled {} mailboxes over the last {} days ({} could not be read)",
+        report.mailboxes_sampled,
+        report.days,
+        report.mailboxes_failed.len()
+    )?;
+    writeln!(out, "Internal domains: {}", report.internal_domains.join(", "))?;
+    writeln!(
+        out,
+        "External messages: {}, tagged with \"{}\": {} ({:.1}%)",
+        report.external_messages,
+        report.tag,
+        report.tagged_messages,
+        percent(report.tagged_messages, report.external_messages)
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "{:<40} {:>8} {:>9} {:>8}  NOTE",
+        "DOMAIN", "MESSAGES", "MAILBOXES", "TAGGED"
+    )?;
+    for domain in &report.domains {
+        let note = if domain.candidate {
+            "allow-list candidate"
+        } else if domain.allow_listed {
+            "allow-listed"
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{:<40} {:>8} {:>9} {:>7.0}%  {}",
+            domain.domain,
+            domain.messages,
+            domain.mailboxes,
+            percent(domain.tagged, domain.messages),
+            note
+        )?;
+    }
+
+    let candidates = report.domains.iter().filter(|domain| domain.candidate).count();
+    writeln!(out)?;
+    writeln!(out, "{} domains suggested for the allow list", candidates)?;
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(&config);
+    debug!("Configuration: {:?}", config);
+
+    let allow_list = match &config.allow_list {
+        Some(path) => read_allow_list(path)?,
+        None => Vec::new(),
+    };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec!["Mail.Read", "Domain.Read.All"];
+    if config.upn.is_empty() {
+        required.push("User.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let phase = client.stats().phase("list domains and mailboxes");
+    let internal_domains = internal_domains(&client).await?;
+    let mailboxes = sample_mailboxes(&client, &config).await?;
+    drop(phase);
+    if mailboxes.is_empty() {
+        return Err(anyhow::anyhow!("No mailboxes to sample"));
+    }
+    let since = (Utc::now() - Duration::days(config.days)).to_rfc3339_opts(SecondsFormat::Secs, true);
+    info!("Reading mail received since {} in {} mailboxes", since, mailboxes.len());
+
+    // Mailboxes already being read finish; the report covers what was read before an interrupt.
+    let shutdown = service::shutdown_signal();
+    let phase = client.stats().phase("read mailboxes");
+    let results: Vec<(String, Result<Vec<Message>>)> = stream::iter(&mailboxes)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|upn| {
+            let client = &client;
+            let since = &since;
+            async move { (upn.clone(), received_messages(client, upn, since).await) }
+        })
+        .buffer_unordered(config.concurrency as usize)
+        .collect()
+        .await;
+    drop(phase);
+
+    let mut messages = Vec::new();
+    let mut mailboxes_failed = Vec::new();
+    let mailboxes_read = results.len();
+    for (upn, result) in results {
+        match result {
+            Ok(received) => {
+                debug!("{} messages in {}", received.len(), upn);
+                messages.extend(received.into_iter().map(|message| (upn.clone(), message)));
+            }
+            Err(e) => {
+                warn!("{:#}", e);
+                mailboxes_failed.push(upn);
+            }
+        }
+    }
+    if mailboxes_read < mailboxes.len() {
+        warn!(
+            "Interrupted, the report covers {} of {} mailboxes",
+            mailboxes_read,
+            mailboxes.len()
+        );
+    }
+
+    let thresholds = Thresholds {
+        min_messages: config.min_messages,
+        min_mailboxes: config.min_mailboxes,
+        allow_list: &allow_list,
+    };
+    let phase = client.stats().phase("aggregate");
+    let (domains, totals) = report::aggregate(&messages, &internal_domains, &config.tag, &thresholds);
+    drop(phase);
+    let report = TaggingReport {
+        generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        days: config.days,
+        tag: config.tag.clone(),
+        internal_domains,
+        mailboxes_sampled: mailboxes_read,
+        mailboxes_failed,
+        external_messages: totals.external_messages,
+        tagged_messages: totals.tagged_messages,
+        domains,
+        run_stats: client.stats().summary(),
+    };
+
+    if config.output == "json" {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", render_report(&report)?);
+        report.run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let internal_domains = internal_domains(&client).await.unwrap();
+        let mut messages = Vec::new();
+        for upn in ["jane.doe@contoso.com", "john.doe@contoso.com"] {
+            let received = received_messages(&client, upn, "2024-10-01T00:00:00Z").await.unwrap();
+            messages.extend(received.into_iter().map(|message| (upn.to_string(), message)));
+        }
+
+        let allow_list = vec!["tailspintoys.com".to_string()];
+        let thresholds = Thresholds {
+            min_messages: 2,
+            min_mailboxes: 2,
+            allow_list: &allow_list,
+        };
+        let (domains, totals) = report::aggregate(&messages, &internal_domains, "[EXTERNAL]", &thresholds);
+        let report = TaggingReport {
+            generated_at: "2024-10-15T09:00:00Z".to_string(),
+            days: 14,
+            tag: "[EXTERNAL]".to_string(),
+            internal_domains,
+            mailboxes_sampled: 3,
+            mailboxes_failed: vec!["shared@contoso.com".to_string()],
+            external_messages: totals.external_messages,
+            tagged_messages: totals.tagged_messages,
+            domains,
+            run_stats: Summary::fixture(),
+        };
+
+        golden::assert_matches(&golden::testdata("golden/report.txt"), &render_report(&report).unwrap());
+        let json = serde_json::to_string_pretty(&report).unwrap() + "\n";
+        golden::assert_matches(&golden::testdata("golden/report.json"), &json);
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Deserialize, Debug)]
+pub struct Message {
+    pub from: Option<Recipient>,
+    pub subject: Option<String>,
+    #[serde(rename = "bodyPreview")]
+    pub body_preview: Option<String>,
+    #[serde(rename = "receivedDateTime")]
+    pub received_date_time: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Recipient {
+    #[serde(rename = "emailAddress")]
+    pub email_address: EmailAddress,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmailAddress {
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DomainReport {
+    pub domain: String,
+    pub messages: usize,
+    pub mailboxes: usize,
+    pub tagged: usize,
+    #[serde(rename = "firstSeen")]
+    pub first_seen: String,
+    #[serde(rename = "lastSeen")]
+    pub last_seen: String,
+    #[serde(rename = "allowListed")]
+    pub allow_listed: bool,
+    pub candidate: bool,
+}
+
+pub struct Thresholds<'a> {
+    pub min_messages: usize,
+    pub min_mailboxes: usize,
+    pub allow_list: &'a [String],
+}
+
+#[derive(Default)]
+struct DomainStats {
+    messages: usize,
+    mailboxes: BTreeSet<String>,
+    tagged: usize,
+    first_seen: String,
+    last_seen: String,
+}
+
+fn sender_domain(message: &Message) -> Option<String> {
+    let address = message.from.as_ref()?.email_address.address.as_deref()?;
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+// Subdomains count as the parent domain, so mail.contoso.com is internal when contoso.com is.
+pub fn matches_domain(domain: &str, list: &[String]) -> bool {
+    list.iter().any(|entry| {
+        let entry = entry.trim().to_lowercase();
+        domain == entry || domain.ends_with(&format!(".{}", entry))
+    })
+}
+
+// The tag is found either as a subject prefix added by a transport rule or at the start of
+// a banner inserted into the body.
+pub fn is_tagged(message: &Message, tag: &str) -> bool {
+    let tag = tag.to_lowercase();
+    let subject = message.subject.as_deref().unwrap_or_default().to_lowercase();
+    let preview = message.body_preview.as_deref().unwrap_or_default().to_lowercase();
+    subject.contains(&tag) || preview.trim_start().starts_with(&tag)
+}
+
+pub struct Totals {
+    pub external_messages: usize,
+    pub tagged_messages: usize,
+}
+
+pub fn aggregate(
+    messages: &[(String, Message)],
+    internal_domains: &[String],
+    tag: &str,
+    thresholds: &Thresholds,
+) -> (Vec<DomainReport>, Totals) {
+    let mut stats: BTreeMap<String, DomainStats> = BTreeMap::new();
+    let mut totals = Totals {
+        external_messages: 0,
+        tagged_messages: 0,
+    };
+
+    for (mailbox, message) in messages {
+        let Some(domain) = sender_domain(message) else {
+            continue;
+        };
+        if matches_domain(&domain, internal_domains) {
+            continue;
+        }
+        let tagged = is_tagged(message, tag);
+        totals.external_messages += 1;
+        totals.tagged_messages += tagged as usize;
+
+        let entry = stats.entry(domain).or_default();
+        entry.messages += 1;
+        entry.tagged += tagged as usize;
+        entry.mailboxes.insert(mailbox.to_lowercase());
+        let received = message.received_date_time.clone().unwrap_or_default();
+        if entry.first_seen.is_empty() || received < entry.first_seen {
+            entry.first_seen = received.clone();
+        }
+        if received > entry.last_seen {
+            entry.last_seen = received;
+        }
+    }
+
+    let mut reports: Vec<DomainReport> = stats
+        .into_iter()
+        .map(|(domain, stats)| {
+            let allow_listed = matches_domain(&domain, thresholds.allow_list);
+            let candidate = !allow_listed
+                && stats.messages >= thresholds.min_messages
+                && stats.mailboxes.len() >= thresholds.min_mailboxes;
+            DomainReport {
+                domain,
+                messages: stats.messages,
+                mailboxes: stats.mailboxes.len(),
+                tagged: stats.tagged,
+                first_seen: stats.first_seen,
+                last_seen: stats.last_seen,
+                allow_listed,
+                candidate,
+            }
+        })
+        .collect();
+    reports.sort_by(|a, b| b.messages.cmp(&a.messages).then_with(|| a.domain.cmp(&b.domain)));
+    (reports, totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, subject: &str, received: &str) -> Message {
+        Message {
+            from: Some(Recipient {
+                email_address: EmailAddress {
+                    address: Some(from.to_string()),
+                },
+            }),
+            subject: Some(subject.to_string()),
+            body_preview: None,
+            received_date_time: Some(received.to_string()),
+        }
+    }
+
+    #[test]
+    fn counts_external_domains_and_flags_candidates() {
+        let messages = vec![
+            ("a@contoso.com".to_string(), message("x@fabrikam.com", "[EXTERNAL] Invoice", "2024-10-02T00:00:00Z")),
+            ("b@contoso.com".to_string(), message("y@Fabrikam.com", "Order", "2024-10-01T00:00:00Z")),
+            ("b@contoso.com".to_string(), message("z@mail.contoso.com", "Internal", "2024-10-01T00:00:00Z")),
+            ("a@contoso.com".to_string(), message("n@news.tailspin.com", "[EXTERNAL] News", "2024-10-03T00:00:00Z")),
+        ];
+        let allow_list = vec!["tailspin.com".to_string()];
+        let thresholds = Thresholds {
+            min_messages: 2,
+            min_mailboxes: 2,
+            allow_list: &allow_list,
+        };
+        let (reports, totals) = aggregate(&messages, &["contoso.com".to_string()], "[external]", &thresholds);
+
+        assert_eq!(totals.external_messages, 3);
+        assert_eq!(totals.tagged_messages, 2);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].domain, "fabrikam.com");
+        assert_eq!(reports[0].mailboxes, 2);
+        assert_eq!(reports[0].first_seen, "2024-10-01T00:00:00Z");
+        assert!(reports[0].candidate);
+        assert!(reports[1].allow_listed);
+        assert!(!reports[1].candidate);
+    }
+}
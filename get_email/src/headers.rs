@@ -0,0 +1,248 @@
+use crate::risk::{self, AuthenticationResults, MessageFacts};
+use chrono::DateTime;
+use colored::*;
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct HeaderAnalysis {
+    pub subject: String,
+    pub from: String,
+    pub sender: String,
+    #[serde(rename = "replyTo")]
+    pub reply_to: Vec<String>,
+    #[serde(rename = "returnPath")]
+    pub return_path: Option<String>,
+    #[serde(rename = "messageId")]
+    pub message_id: Option<String>,
+    pub authentication: AuthenticationResults,
+    pub hops: Vec<Hop>,
+    pub indicators: Vec<String>,
+    pub score: u32,
+    #[serde(rename = "riskLevel")]
+    pub risk_level: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Hop {
+    pub hop: usize,
+    pub from: Option<String>,
+    pub by: Option<String>,
+    pub with: Option<String>,
+    pub timestamp: Option<String>,
+    #[serde(rename = "delaySeconds")]
+    pub delay_seconds: Option<i64>,
+}
+
+pub fn parse_headers(raw: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in raw.lines() {
+        if line.trim().is_empty() {
+            if headers.is_empty() {
+                continue;
+            }
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn extract_address(value: &str) -> String {
+    match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => value[start + 1..end].trim().to_string(),
+        _ => value.trim().trim_matches('"').to_string(),
+    }
+}
+
+fn extract_addresses(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(extract_address)
+        .filter(|address| address.contains('@'))
+        .collect()
+}
+
+pub fn received_hops(headers: &[(String, String)]) -> Vec<Hop> {
+    // Each relay prepends its Received header, so the last one is the first hop.
+    let mut hops: Vec<Hop> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Received"))
+        .rev()
+        .enumerate()
+        .map(|(index, (_, value))| parse_received(index + 1, value))
+        .collect();
+
+    let mut previous: Option<i64> = None;
+    for hop in hops.iter_mut() {
+        let current = hop.timestamp.as_deref().and_then(parse_timestamp);
+        if let (Some(previous), Some(current)) = (previous, current) {
+            hop.delay_seconds = Some(current - previous);
+        }
+        if current.is_some() {
+            previous = current;
+        }
+    }
+    hops
+}
+
+fn parse_received(hop: usize, value: &str) -> Hop {
+    let (route, timestamp) = match value.rsplit_once(';') {
+        Some((route, timestamp)) => (route, Some(timestamp.trim().to_string())),
+        None => (value, None),
+    };
+    let words: Vec<&str> = route.split_whitespace().collect();
+    let clause = |keyword: &str| {
+        words
+            .iter()
+            .position(|word| word.eq_ignore_ascii_case(keyword))
+            .and_then(|position| words.get(position + 1))
+            .map(|word| word.to_string())
+    };
+
+    Hop {
+        hop,
+        from: clause("from"),
+        by: clause("by"),
+        with: clause("with"),
+        timestamp,
+        delay_seconds: None,
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = match value.find(" (") {
+        Some(comment) => &value[..comment],
+        None => value,
+    };
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|timestamp| timestamp.timestamp())
+}
+
+pub fn analyze(raw: &str, internal_domain: Option<&str>) -> HeaderAnalysis {
+    let headers = parse_headers(raw);
+    let from = header(&headers, "From").map(extract_address).unwrap_or_default();
+    let facts = MessageFacts {
+        sender: header(&headers, "Sender")
+            .map(extract_address)
+            .unwrap_or_else(|| from.clone()),
+        reply_to: header(&headers, "Reply-To")
+            .map(extract_addresses)
+            .unwrap_or_default(),
+        return_path: header(&headers, "Return-Path").map(extract_address),
+        authentication: header(&headers, "Authentication-Results")
+            .map(risk::parse_authentication_results)
+            .unwrap_or_default(),
+        from,
+    };
+
+    let internal_domain = internal_domain
+        .map(str::to_string)
+        .or_else(|| {
+            header(&headers, "To")
+                .and_then(|to| extract_addresses(to).into_iter().next())
+                .and_then(|address| risk::domain_of(&address))
+        })
+        .unwrap_or_default();
+    let (indicators, score) = risk::assess(&facts, &internal_domain);
+
+    HeaderAnalysis {
+        subject: header(&headers, "Subject").unwrap_or("Unknown").to_string(),
+        from: facts.from,
+        sender: facts.sender,
+        reply_to: facts.reply_to,
+        return_path: facts.return_path,
+        message_id: header(&headers, "Message-ID").map(str::to_string),
+        authentication: facts.authentication,
+        hops: received_hops(&headers),
+        indicators,
+        score,
+        risk_level: risk::risk_level(score).to_string(),
+    }
+}
+
+pub fn print_analysis(analysis: &HeaderAnalysis) {
+    println!("Subject: {}", analysis.subject);
+    if analysis.sender.eq_ignore_ascii_case(&analysis.from) {
+        println!("Sender: {}", analysis.sender.green());
+        println!("From: {}", analysis.from.green());
+    } else {
+        println!("Sender: {}", analysis.sender.red());
+        println!("From: {}", analysis.from.red());
+    }
+    if analysis.reply_to.is_empty() {
+        println!("ReplyTo: None");
+    }
+    for reply_to in &analysis.reply_to {
+        if reply_to.eq_ignore_ascii_case(&analysis.from) {
+            println!("ReplyTo: {}", reply_to.green());
+        } else {
+            println!("ReplyTo: {}", reply_to.red());
+        }
+    }
+    println!(
+        "Return-Path: {}",
+        analysis.return_path.as_deref().unwrap_or("None")
+    );
+
+    println!();
+    print_verdict("SPF", &analysis.authentication.spf);
+    print_verdict("DKIM", &analysis.authentication.dkim);
+    print_verdict("DMARC", &analysis.authentication.dmarc);
+
+    println!();
+    println!("Received hops (oldest first):");
+    for hop in &analysis.hops {
+        let delay = hop
+            .delay_seconds
+            .map(|delay| format!(" (+{}s)", delay))
+            .unwrap_or_default();
+        println!(
+            "  {}. from {} by {} with {}{}",
+            hop.hop,
+            hop.from.as_deref().unwrap_or("?"),
+            hop.by.as_deref().unwrap_or("?"),
+            hop.with.as_deref().unwrap_or("?"),
+            delay
+        );
+        if let Some(timestamp) = &hop.timestamp {
+            println!("     {}", timestamp);
+        }
+    }
+
+    println!();
+    let score = format!("{} ({})", analysis.score, analysis.risk_level);
+    println!(
+        "Risk score: {}",
+        match analysis.risk_level.as_str() {
+            "low" => score.green(),
+            "medium" => score.yellow(),
+            _ => score.red(),
+        }
+    );
+    if !analysis.indicators.is_empty() {
+        println!("Indicators: {}", analysis.indicators.join(", "));
+    }
+}
+
+fn print_verdict(name: &str, verdict: &Option<String>) {
+    match verdict.as_deref() {
+        Some("pass") => println!("{}: {}", name, "pass".green()),
+        Some(verdict) => println!("{}: {}", name, verdict.red()),
+        None => println!("{}: none", name),
+    }
+}
@@ -0,0 +1,264 @@
+use futures::stream::{self, StreamExt};
+use graph_common::encode;
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+const CONCURRENCY: usize = 10;
+
+#[derive(Serialize, Debug)]
+pub struct ImpactReport {
+    #[serde(rename = "internetMessageId")]
+    pub internet_message_id: String,
+    pub subject: String,
+    #[serde(rename = "mailboxesSearched")]
+    pub mailboxes_searched: usize,
+    #[serde(rename = "mailboxesFailed")]
+    pub mailboxes_failed: Vec<String>,
+    pub recipients: Vec<Recipient>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Recipient {
+    #[serde(rename = "userPrincipalName")]
+    pub user_principal_name: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    pub folder: String,
+    #[serde(rename = "receivedDateTime")]
+    pub received_date_time: String,
+    #[serde(rename = "isRead")]
+    pub is_read: bool,
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+}
+
+struct Mailbox {
+    id: String,
+    upn: String,
+    display_name: String,
+}
+
+// The search can return several copies of one message (for example in Inbox and Deleted
+// Items), so each internetMessageId is only searched for once.
+fn unique_message_ids(emails: &Value) -> Vec<(String, String)> {
+    let mut ids: Vec<(String, String)> = Vec::new();
+    for email in emails["value"].as_array().into_iter().flatten() {
+        let Some(id) = email["internetMessageId"].as_str() else {
+            continue;
+        };
+        if !ids.iter().any(|(seen, _)| seen == id) {
+            let subject = email["subject"].as_str().unwrap_or("Unknown").to_string();
+            ids.push((id.to_string(), subject));
+        }
+    }
+    ids
+}
+
+async fn get_json(client: &Client, access_token: &str, url: &str) -> Result<Value, Box<dyn Error>> {
+    debug!("Requesting {}", url);
+    let response = client
+        .get(url)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+async fn list_mailboxes(client: &Client, access_token: &str) -> Result<Vec<Mailbox>, Box<dyn Error>> {
+    let mut url = "https://graph.microsoft.com/v1.0/users?$select=id,userPrincipalName,displayName,mail&$top=999".to_string();
+    let mut mailboxes = Vec::new();
+
+    loop {
+        let json = get_json(client, access_token, &url).await?;
+        for user in json["value"].as_array().into_iter().flatten() {
+            // Users without a mail address have no mailbox to search.
+            if user["mail"].as_str().is_none() {
+                continue;
+            }
+            mailboxes.push(Mailbox {
+                id: user["id"].as_str().unwrap_or_default().to_string(),
+                upn: user["userPrincipalName"].as_str().unwrap_or_default().to_string(),
+                display_name: user["displayName"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+        match json["@odata.nextLink"].as_str() {
+            Some(next_link) => url = next_link.to_string(),
+            None => break,
+        }
+    }
+    Ok(mailboxes)
+}
+
+async fn search_mailbox(
+    client: &Client,
+    access_token: &str,
+    mailbox: &Mailbox,
+    internet_message_id: &str,
+) -> Result<Vec<Recipient>, Box<dyn Error>> {
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages?$filter={}&$select=id,parentFolderId,receivedDateTime,isRead",
+        encode::path_segment(&mailbox.id),
+        encode::query_value(&format!(
+            "internetMessageId eq {}",
+            encode::odata_string(internet_message_id)
+        ))
+    );
+    let json = get_json(client, access_token, &url).await?;
+
+    let mut recipients = Vec::new();
+    let mut folders: HashMap<String, String> = HashMap::new();
+    for message in json["value"].as_array().into_iter().flatten() {
+        let folder_id = message["parentFolderId"].as_str().unwrap_or_default();
+        if !folders.contains_key(folder_id) {
+            let url = format!(
+                "https://graph.microsoft.com/v1.0/users/{}/mailFolders/{}?$select=displayName",
+                encode::path_segment(&mailbox.id),
+                encode::path_segment(folder_id)
+            );
+            let name = match get_json(client, access_token, &url).await {
+                Ok(folder) => folder["displayName"].as_str().unwrap_or("Unknown").to_string(),
+                Err(e) => {
+                    debug!("Could not resolve folder {} for {}: {}", folder_id, mailbox.upn, e);
+                    "Unknown".to_string()
+                }
+            };
+            folders.insert(folder_id.to_string(), name);
+        }
+
+        recipients.push(Recipient {
+            user_principal_name: mailbox.upn.clone(),
+            display_name: mailbox.display_name.clone(),
+            folder: folders[folder_id].clone(),
+            received_date_time: message["receivedDateTime"].as_str().unwrap_or_default().to_string(),
+            is_read: message["isRead"].as_bool().unwrap_or(false),
+            message_id: message["id"].as_str().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(recipients)
+}
+
+pub async fn find_recipients(
+    client: &Client,
+    access_token: &str,
+    emails: &Value,
+) -> Result<Vec<ImpactReport>, Box<dyn Error>> {
+    let message_ids = unique_message_ids(emails);
+    if message_ids.is_empty() {
+        info!("No matched messages with an internetMessageId");
+        return Ok(Vec::new());
+    }
+
+    let mailboxes = list_mailboxes(client, access_token).await?;
+    info!("Searching {} mailboxes", mailboxes.len());
+
+    let mut reports = Vec::new();
+    for (internet_message_id, subject) in message_ids {
+        info!("Searching for {}", internet_message_id);
+        let results: Vec<_> = stream::iter(&mailboxes)
+            .map(|mailbox| {
+                let internet_message_id = &internet_message_id;
+                async move {
+                    let result = search_mailbox(client, access_token, mailbox, internet_message_id).await;
+                    (mailbox, result.map_err(|e| e.to_string()))
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut recipients = Vec::new();
+        let mut mailboxes_failed = Vec::new();
+        for (mailbox, result) in results {
+            match result {
+                Ok(found) => recipients.extend(found),
+                Err(e) => {
+                    // Mailboxes that are not provisioned or are on-premises return 404 here.
+                    debug!("Could not search {}: {}", mailbox.upn, e);
+                    mailboxes_failed.push(mailbox.upn.clone());
+                }
+            }
+        }
+        if !mailboxes_failed.is_empty() {
+            warn!(
+                "{} mailboxes could not be searched for {}",
+                mailboxes_failed.len(),
+                internet_message_id
+            );
+        }
+        recipients.sort_by_key(|recipient| recipient.user_principal_name.to_lowercase());
+        mailboxes_failed.sort();
+
+        reports.push(ImpactReport {
+            internet_message_id,
+            subject,
+            mailboxes_searched: mailboxes.len(),
+            mailboxes_failed,
+            recipients,
+        });
+    }
+    Ok(reports)
+}
+
+pub fn print_reports(reports: &[ImpactReport]) {
+    for report in reports {
+        let mailboxes: HashSet<&str> = report
+            .recipients
+            .iter()
+            .map(|recipient| recipient.user_principal_name.as_str())
+            .collect();
+        println!("Message: {}", report.internet_message_id);
+        println!("Subject: {}", report.subject);
+        println!(
+            "Found in {} of {} mailboxes searched",
+            mailboxes.len(),
+            report.mailboxes_searched
+        );
+        for recipient in &report.recipients {
+            println!(
+                "  {}  {}  {}  {}",
+                recipient.user_principal_name,
+                recipient.folder,
+                recipient.received_date_time,
+                if recipient.is_read { "read" } else { "unread" }
+            );
+        }
+        if !report.mailboxes_failed.is_empty() {
+            println!(
+                "Could not search {} mailboxes: {}",
+                report.mailboxes_failed.len(),
+                report.mailboxes_failed.join(", ")
+            );
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deduplicates_internet_message_ids() {
+        let emails = json!({
+            "value": [
+                { "internetMessageId": "<a@fabrikam.com>", "subject": "Invoice" },
+                { "internetMessageId": "<a@fabrikam.com>", "subject": "Invoice (copy)" },
+                { "subject": "No id" },
+                { "internetMessageId": "<b@fabrikam.com>", "subject": "Payment" }
+            ]
+        });
+        assert_eq!(
+            unique_message_ids(&emails),
+            vec![
+                ("<a@fabrikam.com>".to_string(), "Invoice".to_string()),
+                ("<b@fabrikam.com>".to_string(), "Payment".to_string()),
+            ]
+        );
+    }
+}
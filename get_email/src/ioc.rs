@@ -0,0 +1,178 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use graph_common::encode;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ioc {
+    #[serde(rename = "type")]
+    pub ioc_type: String,
+    pub value: String,
+    pub sha256: Option<String>,
+    #[serde(rename = "messageCount")]
+    pub message_count: usize,
+}
+
+#[derive(Default)]
+pub struct IocCollector {
+    iocs: BTreeMap<(String, String, Option<String>), usize>,
+}
+
+impl IocCollector {
+    fn add(&mut self, ioc_type: &str, value: &str, sha256: Option<String>) {
+        *self
+            .iocs
+            .entry((ioc_type.to_string(), value.to_string(), sha256))
+            .or_insert(0) += 1;
+    }
+
+    pub fn into_iocs(self) -> Vec<Ioc> {
+        self.iocs
+            .into_iter()
+            .map(|((ioc_type, value, sha256), message_count)| Ioc {
+                ioc_type,
+                value,
+                sha256,
+                message_count,
+            })
+            .collect()
+    }
+}
+
+pub async fn extract_iocs(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    emails: &Value,
+) -> Result<Vec<Ioc>, Box<dyn Error>> {
+    let url_regex = Regex::new(r#"https?://[^\s"'<>()\[\]]+"#)?;
+    let mut collector = IocCollector::default();
+
+    for email in emails["value"].as_array().into_iter().flatten() {
+        let body = email["body"]["content"].as_str().unwrap_or_default();
+        let mut urls: Vec<String> = url_regex
+            .find_iter(body)
+            .map(|m| unwrap_safe_link(&m.as_str().replace("&amp;", "&")))
+            .collect();
+        urls.sort();
+        urls.dedup();
+
+        let mut domains: Vec<String> = urls
+            .iter()
+            .filter_map(|url| Url::parse(url).ok())
+            .filter_map(|url| url.host_str().map(str::to_lowercase))
+            .collect();
+        domains.sort();
+        domains.dedup();
+
+        for url in &urls {
+            collector.add("url", url, None);
+        }
+        for domain in &domains {
+            collector.add("domain", domain, None);
+        }
+
+        if email["hasAttachments"].as_bool().unwrap_or(false) {
+            if let Some(message_id) = email["id"].as_str() {
+                for (name, sha256) in get_attachment_hashes(client, access_token, upn, message_id).await? {
+                    collector.add("attachment", &name, sha256);
+                }
+            }
+        }
+    }
+
+    Ok(collector.into_iocs())
+}
+
+fn unwrap_safe_link(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(parsed)
+            if parsed
+                .host_str()
+                .is_some_and(|host| host.ends_with("safelinks.protection.outlook.com")) =>
+        {
+            parsed
+                .query_pairs()
+                .find(|(key, _)| key == "url")
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_else(|| url.to_string())
+        }
+        _ => url.to_string(),
+    }
+}
+
+async fn get_attachment_hashes(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    message_id: &str,
+) -> Result<Vec<(String, Option<String>)>, Box<dyn Error>> {
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages/{}/attachments",
+        encode::path_segment(upn),
+        encode::path_segment(message_id)
+    );
+    debug!("Fetching attachments with URL: {}", url);
+
+    let response = client
+        .get(&url)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?;
+    let json: Value = response.json().await?;
+
+    let mut attachments = Vec::new();
+    for attachment in json["value"].as_array().into_iter().flatten() {
+        let name = attachment["name"].as_str().unwrap_or("Unknown").to_string();
+        let sha256 = match attachment["contentBytes"].as_str() {
+            Some(content) => match BASE64.decode(content) {
+                Ok(bytes) => Some(
+                    Sha256::digest(&bytes)
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect(),
+                ),
+                Err(e) => {
+                    warn!("Failed to decode attachment {}: {}", name, e);
+                    None
+                }
+            },
+            None => {
+                debug!("Attachment {} has no content bytes (item or reference attachment)", name);
+                None
+            }
+        };
+        attachments.push((name, sha256));
+    }
+    Ok(attachments)
+}
+
+pub fn to_csv(iocs: &[Ioc]) -> String {
+    let mut csv = String::from("type,value,sha256,messageCount\n");
+    for ioc in iocs {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            csv_field(&ioc.ioc_type),
+            csv_field(&ioc.value),
+            csv_field(ioc.sha256.as_deref().unwrap_or_default()),
+            ioc.message_count
+        ));
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -1,20 +1,48 @@
 use clap::{Arg, ArgAction, Command};
 use colored::*;
 use env_logger::Builder;
+use graph_common::{actionlog, encode, permissions};
 use log::{debug, error, info, warn, LevelFilter};
 use reqwest::Client;
 use serde_json::Value;
+use std::env;
 use std::error::Error;
+use std::io::Read;
 use std::path::PathBuf;
-use std::process::Command as ProcessCommand;
+
+use query::{FilterBuilder, MessageQuery, SearchBuilder};
+
+mod auth;
+mod headers;
+mod impact;
+mod ioc;
+mod query;
+mod risk;
+mod thread;
+mod watch;
+
+const RISK_SELECT: &str = "id,conversationId,subject,receivedDateTime,internetMessageId,from,sender,replyTo,internetMessageHeaders";
 
 #[derive(Debug)]
 struct AppConfig {
     upn: String,
     subject: String,
+    filters: Vec<String>,
+    raw_filter: Option<String>,
     info: bool,
     debug: bool,
     spoofed: bool,
+    output: String,
+    extract_iocs: bool,
+    thread: bool,
+    who_else_received: bool,
+    ioc_format: String,
+    move_to: Option<String>,
+    mark_read: bool,
+    analyze_headers: Option<String>,
+    watch: bool,
+    interval: std::time::Duration,
+    webhook: Option<String>,
 }
 
 #[tokio::main]
@@ -22,14 +50,82 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let config = parse_args();
     setup_logger(&config);
 
-    info!("Starting email search");
     debug!("Configuration: {:?}", config);
 
-    let access_token = get_access_token().await?;
+    if let Some(source) = &config.analyze_headers {
+        info!("Analyzing raw message headers");
+        let raw = read_header_source(source)?;
+        let internal_domain = risk::domain_of(&config.upn);
+        let analysis = headers::analyze(&raw, internal_domain.as_deref());
+        if config.output == "json" {
+            println!("{}", serde_json::to_string_pretty(&analysis)?);
+        } else {
+            headers::print_analysis(&analysis);
+        }
+        return Ok(());
+    }
+
+    info!("Starting email search");
+
+    let query = build_query(&config)?;
     let client = Client::new();
-    let emails = search_email_messages(&client, &access_token, &config.upn, &config.subject).await?;
+    let select = match (config.output == "json", config.extract_iocs) {
+        (true, true) => Some(format!("{},body,hasAttachments", RISK_SELECT)),
+        (true, false) => Some(RISK_SELECT.to_string()),
+        _ => None,
+    };
+
+    let access_token = get_access_token(config.debug).await?;
+    permissions::check(&access_token, &required_permissions(&config))?;
+
+    if config.watch {
+        let options = watch::WatchOptions {
+            upn: &config.upn,
+            query: &query,
+            select: select.as_deref(),
+            interval: config.interval,
+            output: &config.output,
+            webhook: config.webhook.as_deref(),
+            debug: config.debug,
+        };
+        return watch::run(&client, options).await;
+    }
 
-    process_emails(&emails, config.spoofed);
+    let emails = search_email_messages(&client, &access_token, &config.upn, &query, select.as_deref()).await?;
+
+    if config.who_else_received {
+        let reports = impact::find_recipients(&client, &access_token, &emails).await?;
+        if config.output == "json" {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+        } else {
+            impact::print_reports(&reports);
+        }
+    } else if config.thread {
+        let threads = thread::fetch_threads(&client, &access_token, &config.upn, &emails).await?;
+        info!("Retrieved {} conversation threads", threads.len());
+        if config.output == "json" {
+            println!("{}", serde_json::to_string_pretty(&threads)?);
+        } else {
+            thread::print_threads(&threads);
+        }
+    } else if config.extract_iocs {
+        let iocs = ioc::extract_iocs(&client, &access_token, &config.upn, &emails).await?;
+        info!("Extracted {} unique IOCs", iocs.len());
+        if config.ioc_format == "json" {
+            println!("{}", serde_json::to_string_pretty(&iocs)?);
+        } else {
+            print!("{}", ioc::to_csv(&iocs));
+        }
+    } else if config.output == "json" {
+        let report = risk::score_messages(&emails, &config.upn);
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        process_emails(&emails, config.spoofed);
+    }
+
+    if config.mark_read || config.move_to.is_some() {
+        apply_actions(&client, &access_token, &config, &emails).await?;
+    }
 
     info!("Operation completed successfully.");
     Ok(())
@@ -40,19 +136,83 @@ fn parse_args() -> AppConfig {
         .version("1.0")
         .author("Bryan Abbott <bryan.abbott01@pm.me>")
         .about("Obtains Basic Email Information")
-        .arg(Arg::new("upn").short('u').long("upn").value_name("UPN").help("User Principal Name").required(true))
-        .arg(Arg::new("subject").short('s').long("subject").value_name("SUBJECT").help("Email subject to search for").required(true))
+        .arg(Arg::new("upn").short('u').long("upn").value_name("UPN").help("User Principal Name").required_unless_present("analyze-headers"))
+        .arg(Arg::new("subject").short('s').long("subject").value_name("SUBJECT").help("Email subject to search for").required_unless_present_any(["analyze-headers", "filter", "raw-filter"]))
+        .arg(Arg::new("filter").short('f').long("filter").value_name("EXPRESSION").help("Filter clause as '<property> <operator> <value>', e.g. 'receivedDateTime ge 2024-10-01T00:00:00Z' (repeatable)").action(ArgAction::Append))
+        .arg(Arg::new("raw-filter").long("raw-filter").value_name("ODATA").help("OData $filter expression passed to Graph as-is"))
         .arg(Arg::new("info").long("info").help("Enable informational logging").action(ArgAction::SetTrue))
         .arg(Arg::new("debug").long("debug").help("Enable debug logging").action(ArgAction::SetTrue))
         .arg(Arg::new("spoofed").short('p').long("spoofed").help("Output spoofed email addresses").action(ArgAction::SetTrue))
+        .arg(Arg::new("output").short('o').long("output").value_name("FORMAT").help("Output format: text, or json with a phishing risk score per message").value_parser(["text", "json"]).default_value("text"))
+        .arg(Arg::new("extract-iocs").long("extract-iocs").help("Output a deduplicated list of URLs, domains, and attachment hashes found in matched messages").action(ArgAction::SetTrue))
+        .arg(Arg::new("ioc-format").long("ioc-format").value_name("FORMAT").help("IOC output format: csv or json").value_parser(["csv", "json"]).default_value("csv"))
+        .arg(Arg::new("thread").long("thread").help("Output every message in the conversation of each match").action(ArgAction::SetTrue).conflicts_with("extract-iocs"))
+        .arg(Arg::new("who-else-received").long("who-else-received").help("List every tenant mailbox containing the matched messages, by internetMessageId").action(ArgAction::SetTrue).conflicts_with_all(["thread", "extract-iocs"]))
+        .arg(Arg::new("move-to").long("move-to").value_name("FOLDER").help("Move matched messages to the given mail folder"))
+        .arg(Arg::new("mark-read").long("mark-read").help("Mark matched messages as read").action(ArgAction::SetTrue))
+        .arg(Arg::new("analyze-headers").long("analyze-headers").value_name("FILE").help("Analyze raw RFC822 headers from a file (or - for stdin) without calling Graph"))
+        .arg(Arg::new("watch").long("watch").help("Re-run the search periodically and output only messages not seen before").action(ArgAction::SetTrue).conflicts_with_all(["thread", "extract-iocs", "who-else-received", "move-to", "mark-read", "analyze-headers"]))
+        .arg(Arg::new("interval").long("interval").value_name("DURATION").help("Polling interval for --watch, e.g. 30s, 5m, or 1h").value_parser(watch::parse_interval).default_value("60s"))
+        .arg(Arg::new("webhook").long("webhook").value_name("URL").help("POST new matches found by --watch as JSON to this URL").requires("watch"))
         .get_matches();
 
     AppConfig {
-        upn: matches.get_one::<String>("upn").expect("UPN is required").clone(),
-        subject: matches.get_one::<String>("subject").expect("Subject is required").clone(),
+        upn: matches.get_one::<String>("upn").cloned().unwrap_or_default(),
+        subject: matches.get_one::<String>("subject").cloned().unwrap_or_default(),
+        filters: matches.get_many::<String>("filter").into_iter().flatten().cloned().collect(),
+        raw_filter: matches.get_one::<String>("raw-filter").cloned(),
         info: *matches.get_one::<bool>("info").unwrap_or(&false),
         debug: *matches.get_one::<bool>("debug").unwrap_or(&false),
         spoofed: *matches.get_one::<bool>("spoofed").unwrap_or(&false),
+        output: matches.get_one::<String>("output").expect("Output has a default").clone(),
+        extract_iocs: *matches.get_one::<bool>("extract-iocs").unwrap_or(&false),
+        thread: *matches.get_one::<bool>("thread").unwrap_or(&false),
+        who_else_received: *matches.get_one::<bool>("who-else-received").unwrap_or(&false),
+        ioc_format: matches.get_one::<String>("ioc-format").expect("IOC format has a default").clone(),
+        move_to: matches.get_one::<String>("move-to").cloned(),
+        mark_read: *matches.get_one::<bool>("mark-read").unwrap_or(&false),
+        analyze_headers: matches.get_one::<String>("analyze-headers").cloned(),
+        watch: *matches.get_one::<bool>("watch").unwrap_or(&false),
+        interval: *matches.get_one::<std::time::Duration>("interval").expect("Interval has a default"),
+        webhook: matches.get_one::<String>("webhook").cloned(),
+    }
+}
+
+fn build_query(config: &AppConfig) -> Result<MessageQuery, Box<dyn Error>> {
+    // Graph does not support $search together with $filter on messages, so any
+    // filter switches the subject match to a contains() clause.
+    if config.filters.is_empty() && config.raw_filter.is_none() {
+        return SearchBuilder::default()
+            .term("subject", &config.subject)?
+            .build()
+            .ok_or_else(|| "A subject or filter is required".into());
+    }
+
+    let mut builder = FilterBuilder::default();
+    if !config.subject.is_empty() {
+        builder = builder.contains("subject", &config.subject)?;
+    }
+    for filter in &config.filters {
+        builder = builder.clause(filter)?;
+    }
+    if let Some(raw_filter) = &config.raw_filter {
+        warn!("Using raw filter without validation: {}", raw_filter);
+        builder = builder.raw(raw_filter);
+    }
+    builder
+        .build()
+        .ok_or_else(|| "A subject or filter is required".into())
+}
+
+fn read_header_source(source: &str) -> Result<String, Box<dyn Error>> {
+    if source == "-" {
+        debug!("Reading headers from stdin");
+        let mut raw = String::new();
+        std::io::stdin().read_to_string(&mut raw)?;
+        Ok(raw)
+    } else {
+        debug!("Reading headers from {}", source);
+        Ok(std::fs::read_to_string(source)?)
     }
 }
 
@@ -75,55 +235,83 @@ fn setup_logger(config: &AppConfig) {
     }
 }
 
-async fn get_access_token() -> Result<String, Box<dyn Error>> {
+fn required_permissions(config: &AppConfig) -> Vec<&'static str> {
+    let mut required = vec!["Mail.Read"];
+    if config.who_else_received {
+        required.push("User.Read.All");
+    }
+    if config.mark_read || config.move_to.is_some() {
+        required.push("Mail.ReadWrite");
+    }
+    required
+}
+
+async fn get_access_token(verbose: bool) -> Result<String, Box<dyn Error>> {
     debug!("Getting access token");
-    let auth_service_path = get_auth_service_path()?;
+    let env_path = get_env_file_path()?;
+    match dotenv::from_path(&env_path) {
+        Ok(_) => debug!(".env file loaded from {:?}", env_path),
+        Err(_) => {
+            debug!("No .env file next to the executable, falling back to the current directory");
+            dotenv::dotenv().ok();
+        }
+    }
 
-    let output = ProcessCommand::new(&auth_service_path).output()?;
+    let tenant_id = env::var("TENANT_ID").map_err(|_| "TENANT_ID environment variable not found")?;
+    let client_id = env::var("CLIENT_ID").map_err(|_| "CLIENT_ID environment variable not found")?;
+    let client_secret = env::var("CLIENT_SECRET").map_err(|_| "CLIENT_SECRET environment variable not found")?;
 
-    if output.status.success() {
-        let token = String::from_utf8(output.stdout)?.trim().to_string();
-        debug!("Access token received successfully");
-        Ok(token)
-    } else {
-        let error_msg = format!("Auth service failed: {}", String::from_utf8_lossy(&output.stderr));
-        error!("{}", error_msg);
-        Err(error_msg.into())
-    }
+    let token = auth::get_access_token(&tenant_id, &client_id, &client_secret, verbose).await?;
+    debug!("Access token received successfully");
+    Ok(token)
 }
 
-fn get_auth_service_path() -> Result<PathBuf, Box<dyn Error>> {
+fn get_env_file_path() -> Result<PathBuf, Box<dyn Error>> {
     let current_exe = std::env::current_exe()?;
-    let mut auth_service_path = current_exe.parent().ok_or("Unable to get parent directory")?.to_path_buf();
-    auth_service_path.push("ms_auth_service");
-    debug!("Auth service path: {:?}", auth_service_path);
-    Ok(auth_service_path)
+    let mut env_path = current_exe.parent().ok_or("Unable to get parent directory")?.to_path_buf();
+    env_path.push(".env");
+    debug!("Env file path: {:?}", env_path);
+    Ok(env_path)
 }
 
 async fn search_email_messages(
     client: &Client,
     access_token: &str,
     upn: &str,
-    subject: &str,
+    query: &MessageQuery,
+    select: Option<&str>,
 ) -> Result<Value, Box<dyn Error>> {
-    let url = format!(
-        "https://graph.microsoft.com/v1.0/users/{}/messages?$search=\"subject:{}\"",
-        upn, subject
+    let mut url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages?{}",
+        encode::path_segment(upn),
+        query.to_query_string()
     );
+    if let Some(select) = select {
+        url.push_str(&format!("&$select={}", select));
+    }
     debug!("Searching email messages with URL: {}", url);
 
-    let response = client
-        .get(&url)
-        .bearer_auth(access_token)
-        .header("ConsistencyLevel", "eventual")
-        .send()
-        .await?
-        .error_for_status()?;
-
-    debug!("Received response from Microsoft Graph API");
-    let json: Value = response.json().await?;
-    debug!("Successfully parsed JSON response");
-    Ok(json)
+    // Every page is read so --move-to and --mark-read act on all matches, not just the first page.
+    let mut messages = Vec::new();
+    loop {
+        let response = client
+            .get(&url)
+            .bearer_auth(access_token)
+            .header("ConsistencyLevel", "eventual")
+            .send()
+            .await?
+            .error_for_status()?;
+        let mut json: Value = response.json().await?;
+        if let Some(page) = json["value"].as_array_mut() {
+            messages.append(page);
+        }
+        match json["@odata.nextLink"].as_str() {
+            Some(next_link) => url = next_link.to_string(),
+            None => break,
+        }
+    }
+    debug!("Search returned {} messages", messages.len());
+    Ok(serde_json::json!({ "value": messages }))
 }
 
 fn process_emails(emails: &Value, spoofed: bool) {
@@ -183,4 +371,229 @@ fn process_sender_and_from(email: &Value) {
         println!("From: {}", from_address.red());
         warn!("Sender and From addresses do not match. Sender: {}, From: {}", sender_address, from_address);
     }
-}
\ No newline at end of file
+}
+
+async fn apply_actions(
+    client: &Client,
+    access_token: &str,
+    config: &AppConfig,
+    emails: &Value,
+) -> Result<(), Box<dyn Error>> {
+    let email_array = match emails["value"].as_array() {
+        Some(email_array) if !email_array.is_empty() => email_array,
+        _ => {
+            info!("No matching messages to act on");
+            return Ok(());
+        }
+    };
+
+    let destination_id = match &config.move_to {
+        Some(folder) => Some(resolve_folder_id(client, access_token, &config.upn, folder).await?),
+        None => None,
+    };
+
+    for email in email_array {
+        let message_id = match email["id"].as_str() {
+            Some(id) => id,
+            None => {
+                warn!("Skipping message without an id");
+                continue;
+            }
+        };
+        let subject = email["subject"].as_str().unwrap_or("Unknown");
+
+        // Mark as read first: moving a message assigns it a new id.
+        if config.mark_read {
+            mark_message_read(client, access_token, &config.upn, message_id).await?;
+            println!("Marked as read: {}", subject);
+        }
+
+        if let (Some(destination_id), Some(folder)) = (&destination_id, &config.move_to) {
+            move_message(client, access_token, &config.upn, message_id, destination_id).await?;
+            println!("Moved to {}: {}", folder, subject);
+        }
+    }
+
+    Ok(())
+}
+
+// Accepts a display name, a well-known name such as "junkemail", or a path of display names
+// such as "Inbox/Phishing Review". A plain name that is not a top-level folder is looked up in
+// the child folders of every folder.
+async fn resolve_folder_id(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    folder: &str,
+) -> Result<String, Box<dyn Error>> {
+    let not_found = || -> Box<dyn Error> {
+        let error_msg = format!("Mail folder '{}' not found in mailbox {}", folder, upn);
+        error!("{}", error_msg);
+        error_msg.into()
+    };
+    let base = format!("https://graph.microsoft.com/v1.0/users/{}/mailFolders", encode::path_segment(upn));
+    let names: Vec<&str> = folder.split('/').map(str::trim).filter(|name| !name.is_empty()).collect();
+    let (first, rest) = names.split_first().ok_or_else(not_found)?;
+
+    let mut id = match find_child_folder(client, access_token, &base, first).await? {
+        Some(id) => Some(id),
+        None => well_known_folder(client, access_token, &base, first).await?,
+    };
+    if id.is_none() && rest.is_empty() {
+        id = search_child_folders(client, access_token, &base, first).await?;
+    }
+    let mut id = id.ok_or_else(not_found)?;
+    for name in rest {
+        let children = format!("{}/{}/childFolders", base, encode::path_segment(&id));
+        id = find_child_folder(client, access_token, &children, name)
+            .await?
+            .ok_or_else(not_found)?;
+    }
+    debug!("Resolved folder {} to id {}", folder, id);
+    Ok(id)
+}
+
+async fn find_child_folder(
+    client: &Client,
+    access_token: &str,
+    folders_url: &str,
+    name: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let url = format!(
+        "{}?$filter={}&includeHiddenFolders=true",
+        folders_url,
+        encode::query_value(&format!("displayName eq {}", encode::odata_string(name)))
+    );
+    debug!("Resolving mail folder with URL: {}", url);
+    let json: Value = client
+        .get(&url)
+        .bearer_auth(access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(json["value"]
+        .as_array()
+        .and_then(|folders| folders.first())
+        .and_then(|folder| folder["id"].as_str())
+        .map(str::to_string))
+}
+
+async fn well_known_folder(
+    client: &Client,
+    access_token: &str,
+    base: &str,
+    name: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let url = format!("{}/{}", base, encode::path_segment(name));
+    debug!("Trying well-known mail folder with URL: {}", url);
+    let response = client.get(&url).bearer_auth(access_token).send().await?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    let json: Value = response.json().await?;
+    Ok(json["id"].as_str().map(str::to_string))
+}
+
+// Breadth-first through the folder tree, so the match closest to the top wins.
+async fn search_child_folders(
+    client: &Client,
+    access_token: &str,
+    base: &str,
+    name: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut queue = vec![format!("{}?$select=id,displayName,childFolderCount&$top=100&includeHiddenFolders=true", base)];
+    while !queue.is_empty() {
+        let mut next = Vec::new();
+        for first_page in queue {
+            let mut url = first_page;
+            loop {
+                let json: Value = client
+                    .get(&url)
+                    .bearer_auth(access_token)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                for folder in json["value"].as_array().into_iter().flatten() {
+                    let Some(id) = folder["id"].as_str() else {
+                        continue;
+                    };
+                    if folder["displayName"].as_str().is_some_and(|display_name| display_name.eq_ignore_ascii_case(name)) {
+                        return Ok(Some(id.to_string()));
+                    }
+                    if folder["childFolderCount"].as_u64().unwrap_or(0) > 0 {
+                        next.push(format!(
+                            "{}/{}/childFolders?$select=id,displayName,childFolderCount&$top=100&includeHiddenFolders=true",
+                            base,
+                            encode::path_segment(id)
+                        ));
+                    }
+                }
+                match json["@odata.nextLink"].as_str() {
+                    Some(next_link) => url = next_link.to_string(),
+                    None => break,
+                }
+            }
+        }
+        queue = next;
+    }
+    Ok(None)
+}
+
+async fn mark_message_read(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    message_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages/{}",
+        encode::path_segment(upn),
+        encode::path_segment(message_id)
+    );
+    debug!("Marking message as read with URL: {}", url);
+
+    let body = serde_json::json!({ "isRead": true });
+    let response = client
+        .patch(&url)
+        .bearer_auth(access_token)
+        .json(&body)
+        .send()
+        .await?;
+    actionlog::record("PATCH", &url, Some(&body), &response)?;
+    response.error_for_status()?;
+
+    info!("Marked message {} as read", message_id);
+    Ok(())
+}
+
+async fn move_message(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    message_id: &str,
+    destination_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages/{}/move",
+        encode::path_segment(upn),
+        encode::path_segment(message_id)
+    );
+    debug!("Moving message with URL: {}", url);
+
+    let body = serde_json::json!({ "destinationId": destination_id });
+    let response = client
+        .post(&url)
+        .bearer_auth(access_token)
+        .json(&body)
+        .send()
+        .await?;
+    actionlog::record("POST", &url, Some(&body), &response)?;
+    response.error_for_status()?;
+
+    info!("Moved message {} to folder {}", message_id, destination_id);
+    Ok(())
+}
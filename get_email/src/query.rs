@@ -0,0 +1,242 @@
+use graph_common::encode;
+use std::error::Error;
+
+const SEARCH_PROPERTIES: &[&str] = &[
+    "subject",
+    "from",
+    "to",
+    "cc",
+    "participants",
+    "body",
+    "attachment",
+];
+const FILTER_OPERATORS: &[&str] = &["eq", "ne", "gt", "ge", "lt", "le"];
+const FILTER_FUNCTIONS: &[&str] = &["contains", "startswith", "endswith"];
+
+#[derive(Debug, PartialEq)]
+pub enum MessageQuery {
+    Search(String),
+    Filter(String),
+}
+
+impl MessageQuery {
+    pub fn to_query_string(&self) -> String {
+        match self {
+            MessageQuery::Search(expression) => format!("$search={}", encode::query_value(expression)),
+            MessageQuery::Filter(expression) => format!("$filter={}", encode::query_value(expression)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SearchBuilder {
+    terms: Vec<String>,
+}
+
+impl SearchBuilder {
+    pub fn term(mut self, property: &str, value: &str) -> Result<Self, Box<dyn Error>> {
+        let property = property.to_lowercase();
+        if !SEARCH_PROPERTIES.contains(&property.as_str()) {
+            return Err(format!(
+                "Unsupported search property '{}'. Supported properties: {}",
+                property,
+                SEARCH_PROPERTIES.join(", ")
+            )
+            .into());
+        }
+        self.terms.push(format!("{}:{}", property, sanitize_kql(value)));
+        Ok(self)
+    }
+
+    pub fn build(self) -> Option<MessageQuery> {
+        if self.terms.is_empty() {
+            None
+        } else {
+            Some(MessageQuery::Search(format!("\"{}\"", self.terms.join(" AND "))))
+        }
+    }
+}
+
+fn sanitize_kql(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '\\' | '"'))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct FilterBuilder {
+    clauses: Vec<String>,
+}
+
+impl FilterBuilder {
+    pub fn contains(mut self, property: &str, value: &str) -> Result<Self, Box<dyn Error>> {
+        validate_property(property)?;
+        self.clauses.push(format!(
+            "contains({}, {})",
+            property,
+            encode::odata_string(value)
+        ));
+        Ok(self)
+    }
+
+    pub fn clause(mut self, expression: &str) -> Result<Self, Box<dyn Error>> {
+        self.clauses.push(parse_clause(expression)?);
+        Ok(self)
+    }
+
+    pub fn raw(mut self, expression: &str) -> Self {
+        self.clauses.push(format!("({})", expression));
+        self
+    }
+
+    pub fn build(self) -> Option<MessageQuery> {
+        if self.clauses.is_empty() {
+            None
+        } else {
+            Some(MessageQuery::Filter(self.clauses.join(" and ")))
+        }
+    }
+}
+
+fn validate_property(property: &str) -> Result<(), Box<dyn Error>> {
+    let valid = !property.is_empty()
+        && property
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '/')
+        && property.starts_with(|c: char| c.is_ascii_alphabetic());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid filter property '{}'", property).into())
+    }
+}
+
+fn parse_clause(expression: &str) -> Result<String, Box<dyn Error>> {
+    let mut parts = expression.trim().splitn(3, char::is_whitespace);
+    let (property, operator, value) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(property), Some(operator), Some(value)) => (property, operator.to_lowercase(), value.trim()),
+        _ => {
+            return Err(format!(
+                "Invalid filter '{}'. Expected '<property> <operator> <value>'",
+                expression
+            )
+            .into())
+        }
+    };
+    validate_property(property)?;
+
+    if FILTER_FUNCTIONS.contains(&operator.as_str()) {
+        return Ok(format!(
+            "{}({}, {})",
+            operator,
+            property,
+            encode::odata_string(unquote(value))
+        ));
+    }
+    if !FILTER_OPERATORS.contains(&operator.as_str()) {
+        return Err(format!(
+            "Unsupported filter operator '{}'. Supported operators: {}, {}",
+            operator,
+            FILTER_OPERATORS.join(", "),
+            FILTER_FUNCTIONS.join(", ")
+        )
+        .into());
+    }
+    Ok(format!("{} {} {}", property, operator, literal(value)))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+        .unwrap_or(value)
+}
+
+fn literal(value: &str) -> String {
+    let is_keyword = matches!(value, "true" | "false" | "null");
+    let is_number = value.parse::<f64>().is_ok();
+    let is_datetime = chrono::DateTime::parse_from_rfc3339(value).is_ok();
+    if is_keyword || is_number || is_datetime {
+        value.to_string()
+    } else {
+        encode::odata_string(unquote(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_quotes_from_search_terms() {
+        let query = SearchBuilder::default()
+            .term("subject", "Invoice \"Q3\" overdue")
+            .unwrap()
+            .build();
+        assert_eq!(
+            query,
+            Some(MessageQuery::Search(
+                "\"subject:Invoice Q3 overdue\"".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_search_properties() {
+        assert!(SearchBuilder::default().term("subject) OR (x", "a").is_err());
+    }
+
+    #[test]
+    fn quotes_string_filter_values() {
+        let query = FilterBuilder::default()
+            .clause("from/emailAddress/address eq o'brien@contoso.com")
+            .unwrap()
+            .build();
+        assert_eq!(
+            query,
+            Some(MessageQuery::Filter(
+                "from/emailAddress/address eq 'o''brien@contoso.com'".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn keeps_datetimes_and_booleans_unquoted() {
+        let query = FilterBuilder::default()
+            .clause("receivedDateTime ge 2024-10-01T00:00:00Z")
+            .unwrap()
+            .clause("isRead eq false")
+            .unwrap()
+            .build();
+        assert_eq!(
+            query,
+            Some(MessageQuery::Filter(
+                "receivedDateTime ge 2024-10-01T00:00:00Z and isRead eq false".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_operators_and_injected_properties() {
+        assert!(FilterBuilder::default().clause("subject or true").is_err());
+        assert!(FilterBuilder::default().clause("subject eq").is_err());
+        assert!(FilterBuilder::default()
+            .clause("subject',1) or (x eq 'y")
+            .is_err());
+    }
+
+    #[test]
+    fn supports_string_functions() {
+        let query = FilterBuilder::default()
+            .clause("subject contains 'wire transfer'")
+            .unwrap()
+            .build();
+        assert_eq!(
+            query,
+            Some(MessageQuery::Filter(
+                "contains(subject, 'wire transfer')".to_string()
+            ))
+        );
+    }
+}
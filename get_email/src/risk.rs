@@ -0,0 +1,258 @@
+use serde::Serialize;
+use serde_json::Value;
+
+const REPLY_TO_MISMATCH_WEIGHT: u32 = 25;
+const SENDER_FROM_MISMATCH_WEIGHT: u32 = 20;
+const SPF_FAIL_WEIGHT: u32 = 20;
+const DKIM_FAIL_WEIGHT: u32 = 15;
+const DMARC_FAIL_WEIGHT: u32 = 15;
+const EXTERNAL_RETURN_PATH_WEIGHT: u32 = 10;
+const LOOKALIKE_DOMAIN_WEIGHT: u32 = 30;
+
+#[derive(Serialize, Debug)]
+pub struct RiskReport {
+    pub mailbox: String,
+    pub messages: Vec<MessageRisk>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageRisk {
+    pub id: String,
+    pub subject: String,
+    #[serde(rename = "receivedDateTime")]
+    pub received_date_time: String,
+    #[serde(rename = "internetMessageId")]
+    pub internet_message_id: String,
+    pub from: String,
+    pub sender: String,
+    #[serde(rename = "replyTo")]
+    pub reply_to: Vec<String>,
+    #[serde(rename = "returnPath")]
+    pub return_path: Option<String>,
+    pub authentication: AuthenticationResults,
+    pub indicators: Vec<String>,
+    pub score: u32,
+    #[serde(rename = "riskLevel")]
+    pub risk_level: String,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct AuthenticationResults {
+    pub spf: Option<String>,
+    pub dkim: Option<String>,
+    pub dmarc: Option<String>,
+}
+
+pub fn score_messages(emails: &Value, mailbox: &str) -> RiskReport {
+    let internal_domain = domain_of(mailbox).unwrap_or_default();
+    let messages = emails["value"]
+        .as_array()
+        .map(|emails| {
+            emails
+                .iter()
+                .map(|email| score_message(email, &internal_domain))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    RiskReport {
+        mailbox: mailbox.to_string(),
+        messages,
+    }
+}
+
+pub struct MessageFacts {
+    pub from: String,
+    pub sender: String,
+    pub reply_to: Vec<String>,
+    pub return_path: Option<String>,
+    pub authentication: AuthenticationResults,
+}
+
+fn score_message(email: &Value, internal_domain: &str) -> MessageRisk {
+    let headers = email["internetMessageHeaders"].as_array();
+    let facts = MessageFacts {
+        from: email["from"]["emailAddress"]["address"]
+            .as_str()
+            .unwrap_or("Unknown")
+            .to_string(),
+        sender: email["sender"]["emailAddress"]["address"]
+            .as_str()
+            .unwrap_or("Unknown")
+            .to_string(),
+        reply_to: email["replyTo"]
+            .as_array()
+            .map(|reply_to| {
+                reply_to
+                    .iter()
+                    .filter_map(|reply| reply["emailAddress"]["address"].as_str())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        return_path: header_value(headers, "Return-Path")
+            .map(|value| value.trim_matches(|c| c == '<' || c == '>').to_string()),
+        authentication: header_value(headers, "Authentication-Results")
+            .map(|value| parse_authentication_results(&value))
+            .unwrap_or_default(),
+    };
+
+    let (indicators, score) = assess(&facts, internal_domain);
+    MessageRisk {
+        id: email["id"].as_str().unwrap_or_default().to_string(),
+        subject: email["subject"].as_str().unwrap_or("Unknown").to_string(),
+        received_date_time: email["receivedDateTime"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        internet_message_id: email["internetMessageId"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        from: facts.from,
+        sender: facts.sender,
+        reply_to: facts.reply_to,
+        return_path: facts.return_path,
+        authentication: facts.authentication,
+        indicators,
+        score,
+        risk_level: risk_level(score).to_string(),
+    }
+}
+
+pub fn assess(facts: &MessageFacts, internal_domain: &str) -> (Vec<String>, u32) {
+    let mut indicators = Vec::new();
+    let mut score = 0;
+
+    if facts
+        .reply_to
+        .iter()
+        .any(|address| !address.eq_ignore_ascii_case(&facts.from))
+    {
+        indicators.push("replyToMismatch".to_string());
+        score += REPLY_TO_MISMATCH_WEIGHT;
+    }
+    if !facts.sender.eq_ignore_ascii_case(&facts.from) {
+        indicators.push("senderFromMismatch".to_string());
+        score += SENDER_FROM_MISMATCH_WEIGHT;
+    }
+    if is_failure(&facts.authentication.spf) {
+        indicators.push("spfFail".to_string());
+        score += SPF_FAIL_WEIGHT;
+    }
+    if is_failure(&facts.authentication.dkim) {
+        indicators.push("dkimFail".to_string());
+        score += DKIM_FAIL_WEIGHT;
+    }
+    if is_failure(&facts.authentication.dmarc) {
+        indicators.push("dmarcFail".to_string());
+        score += DMARC_FAIL_WEIGHT;
+    }
+
+    let from_domain = domain_of(&facts.from);
+    if let (Some(return_domain), Some(from_domain)) = (
+        facts.return_path.as_deref().and_then(domain_of),
+        from_domain.as_deref(),
+    ) {
+        if !same_organization(&return_domain, from_domain) {
+            indicators.push("externalReturnPath".to_string());
+            score += EXTERNAL_RETURN_PATH_WEIGHT;
+        }
+    }
+    if let Some(from_domain) = from_domain {
+        if is_lookalike(&from_domain, internal_domain) {
+            indicators.push("lookalikeDomain".to_string());
+            score += LOOKALIKE_DOMAIN_WEIGHT;
+        }
+    }
+
+    (indicators, score.min(100))
+}
+
+pub fn risk_level(score: u32) -> &'static str {
+    match score {
+        0..=29 => "low",
+        30..=59 => "medium",
+        _ => "high",
+    }
+}
+
+fn header_value(headers: Option<&Vec<Value>>, name: &str) -> Option<String> {
+    headers?
+        .iter()
+        .find(|header| {
+            header["name"]
+                .as_str()
+                .is_some_and(|header_name| header_name.eq_ignore_ascii_case(name))
+        })
+        .and_then(|header| header["value"].as_str())
+        .map(str::to_string)
+}
+
+pub fn parse_authentication_results(value: &str) -> AuthenticationResults {
+    let mut results = AuthenticationResults::default();
+    for part in value.split([';', ' ', '\n', '\t']) {
+        let part = part.trim().to_lowercase();
+        if let Some(verdict) = part.strip_prefix("spf=") {
+            results.spf.get_or_insert_with(|| verdict.to_string());
+        } else if let Some(verdict) = part.strip_prefix("dkim=") {
+            results.dkim.get_or_insert_with(|| verdict.to_string());
+        } else if let Some(verdict) = part.strip_prefix("dmarc=") {
+            results.dmarc.get_or_insert_with(|| verdict.to_string());
+        }
+    }
+    results
+}
+
+fn is_failure(verdict: &Option<String>) -> bool {
+    matches!(
+        verdict.as_deref(),
+        Some("fail") | Some("softfail") | Some("permerror")
+    )
+}
+
+pub fn domain_of(address: &str) -> Option<String> {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim_end_matches('>').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+fn same_organization(a: &str, b: &str) -> bool {
+    a == b || a.ends_with(&format!(".{}", b)) || b.ends_with(&format!(".{}", a))
+}
+
+fn is_lookalike(domain: &str, internal_domain: &str) -> bool {
+    if internal_domain.is_empty() || same_organization(domain, internal_domain) {
+        return false;
+    }
+    let candidate = normalize_homoglyphs(domain);
+    let internal = normalize_homoglyphs(internal_domain);
+    candidate == internal || edit_distance(&candidate, &internal) <= 2
+}
+
+fn normalize_homoglyphs(domain: &str) -> String {
+    domain
+        .to_lowercase()
+        .replace("rn", "m")
+        .replace("vv", "w")
+        .replace('0', "o")
+        .replace('1', "l")
+        .replace('3', "e")
+        .replace('5', "s")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,271 @@
+use graph_common::encode;
+use log::debug;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+
+const THREAD_SELECT: &str =
+    "id,conversationId,subject,from,toRecipients,ccRecipients,receivedDateTime,bodyPreview,isRead";
+
+#[derive(Serialize, Debug)]
+pub struct Thread {
+    #[serde(rename = "conversationId")]
+    pub conversation_id: String,
+    pub messages: Vec<ThreadMessage>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ThreadMessage {
+    pub id: String,
+    pub subject: String,
+    pub from: String,
+    #[serde(rename = "toRecipients")]
+    pub to_recipients: Vec<String>,
+    #[serde(rename = "ccRecipients")]
+    pub cc_recipients: Vec<String>,
+    #[serde(rename = "receivedDateTime")]
+    pub received_date_time: String,
+    #[serde(rename = "bodyPreview")]
+    pub body_preview: String,
+    #[serde(rename = "isRead")]
+    pub is_read: bool,
+    #[serde(skip_serializing_if = "ThreadDelta::is_empty")]
+    pub delta: ThreadDelta,
+}
+
+// What changed compared with the previous message in the thread. In a BEC thread this is
+// where a lookalike sender or a quietly dropped recipient shows up.
+#[derive(Serialize, Debug, Default)]
+pub struct ThreadDelta {
+    #[serde(rename = "previousSender", skip_serializing_if = "Option::is_none")]
+    pub previous_sender: Option<String>,
+    #[serde(rename = "newSenderDomain", skip_serializing_if = "Option::is_none")]
+    pub new_sender_domain: Option<String>,
+    #[serde(rename = "addedRecipients", skip_serializing_if = "Vec::is_empty")]
+    pub added_recipients: Vec<String>,
+    #[serde(rename = "removedRecipients", skip_serializing_if = "Vec::is_empty")]
+    pub removed_recipients: Vec<String>,
+    #[serde(rename = "subjectChanged", skip_serializing_if = "std::ops::Not::not")]
+    pub subject_changed: bool,
+}
+
+impl ThreadDelta {
+    pub fn is_empty(&self) -> bool {
+        self.previous_sender.is_none()
+            && self.new_sender_domain.is_none()
+            && self.added_recipients.is_empty()
+            && self.removed_recipients.is_empty()
+            && !self.subject_changed
+    }
+}
+
+pub async fn fetch_threads(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    emails: &Value,
+) -> Result<Vec<Thread>, Box<dyn Error>> {
+    let conversation_ids = emails["value"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|email| email["conversationId"].as_str());
+    let mut seen = Vec::new();
+
+    let mut threads = Vec::new();
+    for conversation_id in conversation_ids {
+        if seen.contains(&conversation_id) {
+            continue;
+        }
+        seen.push(conversation_id);
+        let messages = fetch_conversation(client, access_token, upn, conversation_id).await?;
+        threads.push(Thread {
+            conversation_id: conversation_id.to_string(),
+            messages,
+        });
+    }
+    Ok(threads)
+}
+
+async fn fetch_conversation(
+    client: &Client,
+    access_token: &str,
+    upn: &str,
+    conversation_id: &str,
+) -> Result<Vec<ThreadMessage>, Box<dyn Error>> {
+    let mut url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages?$filter=conversationId%20eq%20{}&$select={}&$top=100",
+        encode::path_segment(upn),
+        encode::query_value(&encode::odata_string(conversation_id)),
+        THREAD_SELECT
+    );
+    let mut messages = Vec::new();
+
+    loop {
+        debug!("Fetching conversation messages with URL: {}", url);
+        let response = client
+            .get(&url)
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        let json: Value = response.json().await?;
+
+        for message in json["value"].as_array().into_iter().flatten() {
+            messages.push(ThreadMessage {
+                id: message["id"].as_str().unwrap_or_default().to_string(),
+                subject: message["subject"].as_str().unwrap_or("Unknown").to_string(),
+                from: message["from"]["emailAddress"]["address"]
+                    .as_str()
+                    .unwrap_or("Unknown")
+                    .to_string(),
+                to_recipients: addresses(&message["toRecipients"]),
+                cc_recipients: addresses(&message["ccRecipients"]),
+                received_date_time: message["receivedDateTime"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                body_preview: message["bodyPreview"].as_str().unwrap_or_default().to_string(),
+                is_read: message["isRead"].as_bool().unwrap_or(false),
+                delta: ThreadDelta::default(),
+            });
+        }
+
+        match json["@odata.nextLink"].as_str() {
+            Some(next_link) => url = next_link.to_string(),
+            None => break,
+        }
+    }
+
+    // Graph rejects $orderby combined with a conversationId filter, so sort locally.
+    messages.sort_by(|a, b| a.received_date_time.cmp(&b.received_date_time));
+    compute_deltas(&mut messages);
+    debug!(
+        "Conversation {} contains {} messages",
+        conversation_id,
+        messages.len()
+    );
+    Ok(messages)
+}
+
+fn domain(address: &str) -> String {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default()
+}
+
+fn normalized_subject(subject: &str) -> String {
+    let mut subject = subject.trim().to_lowercase();
+    while let Some(rest) = ["re:", "fw:", "fwd:"]
+        .iter()
+        .find_map(|prefix| subject.strip_prefix(prefix))
+    {
+        subject = rest.trim_start().to_string();
+    }
+    subject
+}
+
+fn participants(message: &ThreadMessage) -> Vec<String> {
+    let mut participants: Vec<String> = Vec::new();
+    for address in std::iter::once(&message.from)
+        .chain(&message.to_recipients)
+        .chain(&message.cc_recipients)
+    {
+        let address = address.to_lowercase();
+        if !participants.contains(&address) {
+            participants.push(address);
+        }
+    }
+    participants
+}
+
+fn compute_deltas(messages: &mut [ThreadMessage]) {
+    let mut sender_domains: Vec<String> = Vec::new();
+    for index in 0..messages.len() {
+        let sender_domain = domain(&messages[index].from);
+        let mut delta = ThreadDelta::default();
+
+        if index > 0 {
+            let previous = &messages[index - 1];
+            let current = &messages[index];
+            if !previous.from.eq_ignore_ascii_case(&current.from) {
+                delta.previous_sender = Some(previous.from.clone());
+            }
+            if !sender_domain.is_empty() && !sender_domains.contains(&sender_domain) {
+                delta.new_sender_domain = Some(sender_domain.clone());
+            }
+
+            let before = participants(previous);
+            let after = participants(current);
+            delta.added_recipients = after
+                .iter()
+                .filter(|address| !before.contains(address))
+                .cloned()
+                .collect();
+            delta.removed_recipients = before
+                .iter()
+                .filter(|address| !after.contains(address))
+                .cloned()
+                .collect();
+            delta.subject_changed =
+                normalized_subject(&previous.subject) != normalized_subject(&current.subject);
+        }
+
+        if !sender_domains.contains(&sender_domain) {
+            sender_domains.push(sender_domain);
+        }
+        messages[index].delta = delta;
+    }
+}
+
+fn print_delta(delta: &ThreadDelta) {
+    if let Some(previous_sender) = &delta.previous_sender {
+        println!("  ! Sender changed from {}", previous_sender);
+    }
+    if let Some(new_sender_domain) = &delta.new_sender_domain {
+        println!("  ! First message from domain {}", new_sender_domain);
+    }
+    if !delta.added_recipients.is_empty() {
+        println!("  ! Added: {}", delta.added_recipients.join(", "));
+    }
+    if !delta.removed_recipients.is_empty() {
+        println!("  ! Dropped: {}", delta.removed_recipients.join(", "));
+    }
+    if delta.subject_changed {
+        println!("  ! Subject changed");
+    }
+}
+
+fn addresses(recipients: &Value) -> Vec<String> {
+    recipients
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|recipient| recipient["emailAddress"]["address"].as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn print_threads(threads: &[Thread]) {
+    for thread in threads {
+        println!(
+            "Conversation {} ({} messages)",
+            thread.conversation_id,
+            thread.messages.len()
+        );
+        for message in &thread.messages {
+            println!("  Received: {}", message.received_date_time);
+            println!("  From: {}", message.from);
+            println!("  To: {}", message.to_recipients.join(", "));
+            if !message.cc_recipients.is_empty() {
+                println!("  Cc: {}", message.cc_recipients.join(", "));
+            }
+            println!("  Subject: {}", message.subject);
+            print_delta(&message.delta);
+            println!("  Preview: {}", message.body_preview.replace(['\r', '\n'], " "));
+            println!();
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::query::MessageQuery;
+use crate::risk;
+use graph_common::service::{self, ServiceEvent, ServiceEvents};
+use log::{debug, info, warn};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+pub struct WatchOptions<'a> {
+    pub upn: &'a str,
+    pub query: &'a MessageQuery,
+    pub select: Option<&'a str>,
+    pub interval: Duration,
+    pub output: &'a str,
+    pub webhook: Option<&'a str>,
+    pub debug: bool,
+}
+
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid interval '{}'. Use e.g. 30s, 5m, or 1h", value))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        _ => return Err(format!("Invalid interval unit '{}'. Use s, m, or h", unit)),
+    };
+    if seconds == 0 {
+        return Err("Interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+pub async fn run(client: &Client, options: WatchOptions<'_>) -> Result<(), Box<dyn Error>> {
+    let mut access_token = crate::get_access_token(options.debug).await?;
+    let mut events = service::signals();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut token_refreshed = false;
+    let mut ready = false;
+    info!(
+        "Watching mailbox {} every {} seconds",
+        options.upn,
+        options.interval.as_secs()
+    );
+    service::spawn_watchdog();
+
+    loop {
+        match crate::search_email_messages(
+            client,
+            &access_token,
+            options.upn,
+            options.query,
+            options.select,
+        )
+        .await
+        {
+            Ok(emails) => {
+                token_refreshed = false;
+                let new_messages: Vec<Value> = emails["value"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter(|email| {
+                        email["id"]
+                            .as_str()
+                            .is_some_and(|id| seen.insert(id.to_string()))
+                    })
+                    .cloned()
+                    .collect();
+                debug!("{} new matching messages", new_messages.len());
+
+                if !new_messages.is_empty() {
+                    report(client, &options, new_messages).await;
+                }
+                if !ready {
+                    service::notify_ready(&format!("Watching mailbox {}", options.upn));
+                    ready = true;
+                }
+                service::notify_status(&format!("{} matching messages reported", seen.len()));
+            }
+            Err(e) if is_unauthorized(e.as_ref()) && !token_refreshed => {
+                info!("Access token expired, requesting a new one");
+                access_token = crate::get_access_token(options.debug).await?;
+                token_refreshed = true;
+                continue;
+            }
+            Err(e) if is_unauthorized(e.as_ref()) => return Err(e),
+            Err(e) => warn!("Search failed, retrying at the next interval: {}", e),
+        }
+
+        match wait(&mut events, options.interval).await {
+            Some(ServiceEvent::Shutdown) => break,
+            Some(ServiceEvent::Reload) => {
+                service::notify_reloading();
+                match reload_credentials(options.debug).await {
+                    Ok(token) => {
+                        access_token = token;
+                        service::notify_ready("Reloaded credentials");
+                    }
+                    Err(e) => {
+                        warn!("Reload failed, keeping the previous credentials: {}", e);
+                        service::notify_ready("Reload failed, using the previous credentials");
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    service::notify_stopping();
+    info!(
+        "Stopped watching mailbox {} after reporting {} messages",
+        options.upn,
+        seen.len()
+    );
+    Ok(())
+}
+
+// Sleeps for the interval, returning early when a shutdown or reload is requested.
+async fn wait(events: &mut ServiceEvents, interval: Duration) -> Option<ServiceEvent> {
+    tokio::select! {
+        _ = tokio::time::sleep(interval) => None,
+        event = events.recv() => match event {
+            Some(event) => Some(event),
+            None => {
+                tokio::time::sleep(interval).await;
+                None
+            }
+        },
+    }
+}
+
+const CREDENTIAL_VARIABLES: [&str; 3] = ["TENANT_ID", "CLIENT_ID", "CLIENT_SECRET"];
+
+// dotenv never overrides variables that are already set, so clear them first to pick up
+// rotated secrets from the .env file. Values that only came from the environment are restored.
+async fn reload_credentials(debug: bool) -> Result<String, Box<dyn Error>> {
+    let previous: Vec<(&str, Option<String>)> = CREDENTIAL_VARIABLES
+        .iter()
+        .map(|name| (*name, env::var(name).ok()))
+        .collect();
+    for name in CREDENTIAL_VARIABLES {
+        env::remove_var(name);
+    }
+
+    let result = crate::get_access_token(debug).await;
+    for (name, value) in previous {
+        if let Some(value) = value {
+            if result.is_err() || env::var(name).is_err() {
+                env::set_var(name, value);
+            }
+        }
+    }
+    result
+}
+
+fn is_unauthorized(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        == Some(StatusCode::UNAUTHORIZED)
+}
+
+async fn report(client: &Client, options: &WatchOptions<'_>, new_messages: Vec<Value>) {
+    let emails = json!({ "value": new_messages });
+    let payload = if options.output == "json" {
+        let report = json!(risk::score_messages(&emails, options.upn));
+        println!("{}", report);
+        report
+    } else {
+        for email in emails["value"].as_array().into_iter().flatten() {
+            println!(
+                "{} | {} | {}",
+                email["receivedDateTime"].as_str().unwrap_or("Unknown"),
+                email["from"]["emailAddress"]["address"]
+                    .as_str()
+                    .unwrap_or("Unknown"),
+                email["subject"].as_str().unwrap_or("Unknown")
+            );
+        }
+        emails
+    };
+
+    if let Some(webhook) = options.webhook {
+        let body = json!({ "upn": options.upn, "matches": payload });
+        match client.post(webhook).json(&body).send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("Posted new matches to webhook")
+            }
+            Ok(response) => warn!("Webhook returned HTTP {}", response.status()),
+            Err(e) => warn!("Failed to post to webhook: {}", e),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use fs2::FileExt;
+use log::debug;
+use percent_encoding::percent_decode_str;
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+// Every line is the JSON of an entry followed by `,"hash":"<hex>"}`, where the hash is the
+// SHA-256 of the previous line's hash and the entry JSON. Editing, removing, or reordering
+// a line breaks the chain from that point on.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ActionEntry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub operator: String,
+    pub tool: String,
+    pub method: String,
+    pub target: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    pub status: u16,
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undo: Option<Undo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<u64>,
+    #[serde(rename = "prevHash")]
+    pub prev_hash: String,
+}
+
+impl ActionEntry {
+    pub fn succeeded(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+// The request that restores the state from before a reversible change, captured when the
+// change was made. It is stored unredacted because it must be replayable.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Undo {
+    pub method: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+pub struct Change<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    pub body: Option<&'a Value>,
+    pub undo: Option<Undo>,
+    pub undoes: Option<u64>,
+}
+
+impl<'a> Change<'a> {
+    pub fn new(method: &'a str, url: &'a str, body: Option<&'a Value>) -> Self {
+        Self {
+            method,
+            url,
+            body,
+            undo: None,
+            undoes: None,
+        }
+    }
+
+    pub fn with_undo(mut self, undo: Undo) -> Self {
+        self.undo = Some(undo);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ActionLog {
+    path: PathBuf,
+    tool: String,
+}
+
+impl ActionLog {
+    pub fn new(path: PathBuf, tool: &str) -> Self {
+        Self {
+            path,
+            tool: tool.to_string(),
+        }
+    }
+
+    // MSTOOLS_ACTION_LOG overrides the location; setting it to "off" disables the log.
+    pub fn from_env() -> Option<Self> {
+        let path = match env::var("MSTOOLS_ACTION_LOG") {
+            Ok(value) if value.eq_ignore_ascii_case("off") => return None,
+            Ok(value) if !value.is_empty() => PathBuf::from(value),
+            _ => default_path(),
+        };
+        let tool = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "unknown".to_string());
+        Some(Self::new(path, &tool))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, change: &Change, status: u16, request_id: Option<String>) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open action log {}", self.path.display()))?;
+        file.lock_exclusive()
+            .with_context(|| format!("Failed to lock action log {}", self.path.display()))?;
+
+        let result = (|| {
+            let (seq, prev_hash) = match last_line(&mut file)? {
+                Some(line) => {
+                    let (entry, hash) = split_line(&line)
+                        .with_context(|| format!("Action log {} is corrupt", self.path.display()))?;
+                    (entry.seq + 1, hash.to_string())
+                }
+                None => (1, GENESIS_HASH.to_string()),
+            };
+            let entry = ActionEntry {
+                seq,
+                timestamp: Utc::now().to_rfc3339(),
+                operator: crate::current_operator(),
+                tool: self.tool.clone(),
+                method: change.method.to_string(),
+                target: target_of(change.url),
+                url: change.url.to_string(),
+                request: change.body.map(redact),
+                status,
+                request_id,
+                undo: change.undo.clone(),
+                undoes: change.undoes,
+                prev_hash,
+            };
+            let line = encode_line(&entry)?;
+            file.write_all(line.as_bytes())?;
+            file.write_all(b"\n")?;
+            file.flush()?;
+            debug!("Recorded {} {} in {}", change.method, change.url, self.path.display());
+            Ok(())
+        })();
+
+        let _ = file.unlock();
+        result
+    }
+
+    // The change has already been sent, so a failure here means it happened without a trace
+    // and the caller must not report success.
+    pub fn record(&self, change: &Change, response: &Response) -> Result<()> {
+        let request_id = response
+            .headers()
+            .get("request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        self.append(change, response.status().as_u16(), request_id)
+            .with_context(|| {
+                format!(
+                    "{} {} was sent but could not be recorded in the action log",
+                    change.method, change.url
+                )
+            })
+    }
+}
+
+// For tools that call Graph with their own reqwest client instead of GraphClient.
+pub fn record(method: &str, url: &str, request: Option<&Value>, response: &Response) -> Result<()> {
+    match ActionLog::from_env() {
+        Some(log) => log.record(&Change::new(method, url, request), response),
+        None => Ok(()),
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    let base = env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+        .or_else(|_| env::var("LOCALAPPDATA").map(PathBuf::from))
+        .unwrap_or_else(|_| PathBuf::from("."));
+    base.join("microsoft_services_tools").join("actions.jsonl")
+}
+
+fn hash(prev_hash: &str, entry_json: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(entry_json.as_bytes());
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn encode_line(entry: &ActionEntry) -> Result<String> {
+    let json = serde_json::to_string(entry)?;
+    let hash = hash(&entry.prev_hash, &json);
+    Ok(format!("{},\"hash\":\"{}\"}}", &json[..json.len() - 1], hash))
+}
+
+// Splits a line back into the entry and its stored hash without re-serializing, so the
+// hash is checked against the exact bytes that were written.
+fn split_line(line: &str) -> Result<(ActionEntry, &str)> {
+    let marker = line
+        .rfind(",\"hash\":\"")
+        .context("Entry has no hash")?;
+    let hash = line[marker + 9..]
+        .strip_suffix("\"}")
+        .context("Entry has a malformed hash")?;
+    let json = format!("{}}}", &line[..marker]);
+    let entry: ActionEntry = serde_json::from_str(&json).context("Entry is not valid JSON")?;
+    if hash != self::hash(&entry.prev_hash, &json) {
+        return Err(anyhow::anyhow!("Entry hash does not match its contents"));
+    }
+    Ok((entry, hash))
+}
+
+// Reads the tail as raw bytes: the seek can land inside a multi-byte character, so only the
+// final line is decoded.
+fn last_line(file: &mut File) -> Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut chunk = TAIL_CHUNK;
+    loop {
+        let start = len.saturating_sub(chunk);
+        file.seek(SeekFrom::Start(start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let end = tail.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |index| index + 1);
+        let trimmed = &tail[..end];
+        let line = match trimmed.iter().rposition(|&b| b == b'\n') {
+            Some(index) => &trimmed[index + 1..],
+            None if start == 0 => trimmed,
+            None => {
+                chunk *= 2;
+                continue;
+            }
+        };
+        if line.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(String::from_utf8(line.to_vec()).context("Last entry is not valid UTF-8")?));
+    }
+}
+
+fn target_of(url: &str) -> String {
+    let path = url
+        .split_once("/v1.0/")
+        .or_else(|| url.split_once("/beta/"))
+        .map(|(_, path)| path)
+        .unwrap_or(url);
+    let path = path.split('?').next().unwrap_or(path);
+    percent_decode_str(path.trim_end_matches('/'))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let lower = key.to_lowercase();
+                    if lower.contains("password") || lower.contains("secret") {
+                        (key.clone(), Value::String("[redacted]".to_string()))
+                    } else {
+                        (key.clone(), redact(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        other => other.clone(),
+    }
+}
+
+// Returns the number of entries and the hash of the last one. Keep that hash somewhere the
+// operators cannot write to: a log rewritten from scratch still forms a valid chain.
+pub fn verify(path: &Path) -> Result<(u64, String)> {
+    let (entries, head) = read_verified(path)?;
+    Ok((entries.len() as u64, head))
+}
+
+pub fn read_verified(path: &Path) -> Result<(Vec<ActionEntry>, String)> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read action log {}", path.display()))?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut entries: Vec<ActionEntry> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let count = entries.len() as u64;
+        let line_number = index + 1;
+        let (entry, hash) =
+            split_line(line).with_context(|| format!("Line {} failed verification", line_number))?;
+        if entry.prev_hash != prev_hash {
+            return Err(anyhow::anyhow!(
+                "Line {} does not follow the previous entry; entries were removed, inserted, or reordered",
+                line_number
+            ));
+        }
+        if entry.seq != count + 1 {
+            return Err(anyhow::anyhow!(
+                "Line {} has sequence number {}, expected {}",
+                line_number,
+                entry.seq,
+                count + 1
+            ));
+        }
+        prev_hash = hash.to_string();
+        entries.push(entry);
+    }
+    Ok((entries, prev_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_log(name: &str) -> ActionLog {
+        let path = env::temp_dir().join(format!("actionlog-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        ActionLog::new(path, "test")
+    }
+
+    #[test]
+    fn appended_entries_form_a_verifiable_chain() {
+        let log = temp_log("chain");
+        let url = "https://graph.microsoft.com/v1.0/users/jane%40contoso.com";
+        let body = json!({ "passwordProfile": { "password": "hunter2" } });
+        log.append(&Change::new("PATCH", url, Some(&body)), 204, None).unwrap();
+        let disable = json!({ "accountEnabled": false });
+        let undo = Undo {
+            method: "PATCH".to_string(),
+            url: url.to_string(),
+            body: Some(json!({ "accountEnabled": true })),
+        };
+        log.append(&Change::new("PATCH", url, Some(&disable)).with_undo(undo), 204, None)
+            .unwrap();
+        let revoke_url = format!("{}/revokeSignInSessions", url);
+        log.append(&Change::new("POST", &revoke_url, None), 200, Some("abc".to_string()))
+            .unwrap();
+
+        let contents = std::fs::read_to_string(log.path()).unwrap();
+        assert!(!contents.contains("hunter2"));
+        assert!(contents.contains("\"target\":\"users/jane@contoso.com\""));
+        let (entries, _) = read_verified(log.path()).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].undo.as_ref().unwrap().body, Some(json!({ "accountEnabled": true })));
+        assert!(entries[2].undo.is_none());
+        std::fs::remove_file(log.path()).unwrap();
+    }
+
+    #[test]
+    fn targets_keep_the_full_resource_path() {
+        assert_eq!(
+            target_of("https://graph.microsoft.com/v1.0/groups/g1/members/m1/$ref"),
+            "groups/g1/members/m1/$ref"
+        );
+        assert_eq!(
+            target_of("https://graph.microsoft.com/beta/users/jane%40contoso.com/authentication/methods/abc?x=1"),
+            "users/jane@contoso.com/authentication/methods/abc"
+        );
+    }
+
+    #[test]
+    fn detects_edited_and_removed_entries() {
+        let log = temp_log("tamper");
+        for status in [204, 204, 404] {
+            let change = Change::new("DELETE", "https://graph.microsoft.com/v1.0/users/a/authentication/methods/1", None);
+            log.append(&change, status, None).unwrap();
+        }
+        let contents = std::fs::read_to_string(log.path()).unwrap();
+
+        std::fs::write(log.path(), contents.replace("\"status\":404", "\"status\":204")).unwrap();
+        assert!(verify(log.path()).is_err());
+
+        let without_first: Vec<&str> = contents.lines().skip(1).collect();
+        std::fs::write(log.path(), without_first.join("\n")).unwrap();
+        assert!(verify(log.path()).is_err());
+        std::fs::remove_file(log.path()).unwrap();
+    }
+
+    #[test]
+    fn reads_last_line_when_the_tail_starts_inside_a_character() {
+        let path = env::temp_dir().join(format!("actionlog-tail-{}.jsonl", std::process::id()));
+        let contents = format!("x{}\nlast entry é\n", "é".repeat(40_000));
+        assert!(!contents.is_char_boundary(contents.len() - TAIL_CHUNK as usize));
+        std::fs::write(&path, contents).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(last_line(&mut file).unwrap().as_deref(), Some("last entry é"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
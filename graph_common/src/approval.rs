@@ -0,0 +1,378 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{debug, info};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Plan {
+    pub tool: String,
+    #[serde(rename = "createdBy")]
+    pub created_by: String,
+    #[serde(rename = "authorKey")]
+    pub author_key: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    pub description: String,
+    pub actions: Vec<PlanAction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanAction {
+    pub operation: String,
+    pub target: String,
+    #[serde(default)]
+    pub details: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlanSignature {
+    pub approver: String,
+    #[serde(rename = "publicKey")]
+    pub public_key: String,
+    #[serde(rename = "planSha256")]
+    pub plan_sha256: String,
+    pub signature: String,
+    #[serde(rename = "approvedAt")]
+    pub approved_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SecretKeyFile {
+    name: String,
+    #[serde(rename = "secretKey")]
+    secret_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PublicKeyFile {
+    name: String,
+    #[serde(rename = "publicKey")]
+    public_key: String,
+}
+
+impl Plan {
+    // The author and their key are filled in when the plan is written.
+    pub fn new(tool: &str, description: &str, actions: Vec<PlanAction>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            created_by: String::new(),
+            author_key: String::new(),
+            created_at: Utc::now().to_rfc3339(),
+            description: description.to_string(),
+            actions,
+        }
+    }
+
+    // Signs the plan with the author's key, so approvals can be checked against the author's
+    // key rather than a name.
+    pub fn write(&mut self, path: &Path, key_path: &Path) -> Result<PathBuf> {
+        let (name, signing_key) = load_secret_key(key_path)?;
+        self.created_by = name;
+        self.author_key = BASE64.encode(signing_key.verifying_key().to_bytes());
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize plan")?;
+        fs::write(path, &json)
+            .with_context(|| format!("Failed to write plan file {}", path.display()))?;
+
+        let signature = sign(json.as_bytes(), &self.created_by, &signing_key);
+        let sig_path = author_signature_path(path);
+        fs::write(&sig_path, serde_json::to_string_pretty(&signature)?)
+            .with_context(|| format!("Failed to write signature {}", sig_path.display()))?;
+        info!("Plan with {} actions written to {}", self.actions.len(), path.display());
+        Ok(sig_path)
+    }
+}
+
+pub fn signature_path(plan_path: &Path) -> PathBuf {
+    let mut path = plan_path.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+pub fn author_signature_path(plan_path: &Path) -> PathBuf {
+    let mut path = plan_path.as_os_str().to_owned();
+    path.push(".author.sig");
+    PathBuf::from(path)
+}
+
+pub fn approvers_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("MSTOOLS_APPROVERS_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let mut exe_path = env::current_exe().context("Failed to get current executable path")?;
+    exe_path.pop();
+    exe_path.push("approvers");
+    Ok(exe_path)
+}
+
+pub fn generate_keypair(name: &str, out_dir: &Path) -> Result<(PathBuf, PathBuf)> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    let secret = SecretKeyFile {
+        name: name.to_string(),
+        secret_key: BASE64.encode(signing_key.to_bytes()),
+    };
+    let public = PublicKeyFile {
+        name: name.to_string(),
+        public_key: BASE64.encode(signing_key.verifying_key().to_bytes()),
+    };
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create directory {}", out_dir.display()))?;
+    let secret_path = out_dir.join(format!("{}.key", name));
+    let public_path = out_dir.join(format!("{}.pub", name));
+
+    fs::write(&secret_path, serde_json::to_string_pretty(&secret)?)
+        .with_context(|| format!("Failed to write {}", secret_path.display()))?;
+    restrict_permissions(&secret_path)?;
+    fs::write(&public_path, serde_json::to_string_pretty(&public)?)
+        .with_context(|| format!("Failed to write {}", public_path.display()))?;
+
+    Ok((secret_path, public_path))
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn load_plan_bytes(plan_path: &Path) -> Result<(Vec<u8>, Plan)> {
+    let bytes = fs::read(plan_path)
+        .with_context(|| format!("Failed to read plan file {}", plan_path.display()))?;
+    let plan: Plan = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse plan file {}", plan_path.display()))?;
+    Ok((bytes, plan))
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn load_secret_key(key_path: &Path) -> Result<(String, SigningKey)> {
+    let key_json = fs::read_to_string(key_path)
+        .with_context(|| format!("Failed to read key file {}", key_path.display()))?;
+    let key_file: SecretKeyFile =
+        serde_json::from_str(&key_json).context("Failed to parse key file")?;
+    let secret: [u8; 32] = BASE64
+        .decode(&key_file.secret_key)
+        .context("Failed to decode secret key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Secret key has an invalid length"))?;
+    Ok((key_file.name, SigningKey::from_bytes(&secret)))
+}
+
+fn sign(bytes: &[u8], name: &str, signing_key: &SigningKey) -> PlanSignature {
+    PlanSignature {
+        approver: name.to_string(),
+        public_key: BASE64.encode(signing_key.verifying_key().to_bytes()),
+        plan_sha256: sha256_hex(bytes),
+        signature: BASE64.encode(signing_key.sign(bytes).to_bytes()),
+        approved_at: Utc::now().to_rfc3339(),
+    }
+}
+
+fn read_signature(sig_path: &Path, missing: &str) -> Result<PlanSignature> {
+    let sig_json = fs::read_to_string(sig_path)
+        .with_context(|| format!("{} (missing {})", missing, sig_path.display()))?;
+    serde_json::from_str(&sig_json)
+        .with_context(|| format!("Failed to parse signature {}", sig_path.display()))
+}
+
+fn decode_public_key(public_key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = BASE64
+        .decode(public_key)
+        .context("Failed to decode public key")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Public key has an invalid length"))?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid public key")
+}
+
+fn check_signature(bytes: &[u8], signature: &PlanSignature, key: &VerifyingKey) -> Result<()> {
+    if signature.plan_sha256 != sha256_hex(bytes) {
+        return Err(anyhow::anyhow!(
+            "Plan was modified after it was signed by {}",
+            signature.approver
+        ));
+    }
+    let sig_bytes: [u8; 64] = BASE64
+        .decode(&signature.signature)
+        .context("Failed to decode signature")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signature has an invalid length"))?;
+    key.verify(bytes, &Signature::from_bytes(&sig_bytes))
+        .with_context(|| format!("Plan signature of {} is invalid", signature.approver))
+}
+
+pub fn approve_plan(plan_path: &Path, key_path: &Path) -> Result<PathBuf> {
+    let (bytes, plan) = load_plan_bytes(plan_path)?;
+    let author_signature = read_signature(
+        &author_signature_path(plan_path),
+        &format!("Plan {} was not signed by its author", plan_path.display()),
+    )?;
+    if author_signature.public_key != plan.author_key {
+        return Err(anyhow::anyhow!("Plan was not signed with its author's key"));
+    }
+    check_signature(&bytes, &author_signature, &decode_public_key(&plan.author_key)?)?;
+
+    let (name, signing_key) = load_secret_key(key_path)?;
+    if BASE64.encode(signing_key.verifying_key().to_bytes()) == plan.author_key {
+        return Err(anyhow::anyhow!(
+            "Plan was created with this key by {}; it must be approved by a different person",
+            plan.created_by
+        ));
+    }
+
+    let signature = sign(&bytes, &name, &signing_key);
+    let sig_path = signature_path(plan_path);
+    fs::write(&sig_path, serde_json::to_string_pretty(&signature)?)
+        .with_context(|| format!("Failed to write signature {}", sig_path.display()))?;
+    info!(
+        "Plan {} approved by {}",
+        plan_path.display(),
+        signature.approver
+    );
+    Ok(sig_path)
+}
+
+// Keys are matched by their public key, never by a name taken from a signature, so a
+// signature cannot point the lookup at a file outside the directory.
+fn trusted_key(approvers_dir: &Path, public_key: &str) -> Result<(String, VerifyingKey)> {
+    let entries = fs::read_dir(approvers_dir).with_context(|| {
+        format!("Failed to read approvers directory {}", approvers_dir.display())
+    })?;
+    for entry in entries {
+        let path = entry.context("Failed to read approvers directory")?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("pub") {
+            continue;
+        }
+        debug!("Checking trusted key {}", path.display());
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let public: PublicKeyFile = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse public key file {}", path.display()))?;
+        if public.public_key == public_key {
+            return Ok((public.name, decode_public_key(&public.public_key)?));
+        }
+    }
+    Err(anyhow::anyhow!(
+        "The signing key is not a trusted key in {}",
+        approvers_dir.display()
+    ))
+}
+
+pub fn verify_plan(plan_path: &Path, approvers_dir: &Path) -> Result<(Plan, PlanSignature)> {
+    let (bytes, mut plan) = load_plan_bytes(plan_path)?;
+
+    let author_signature = read_signature(
+        &author_signature_path(plan_path),
+        &format!("Plan {} was not signed by its author", plan_path.display()),
+    )?;
+    if author_signature.public_key != plan.author_key {
+        return Err(anyhow::anyhow!("Plan was not signed with its author's key"));
+    }
+    let (author, key) = trusted_key(approvers_dir, &author_signature.public_key)
+        .with_context(|| format!("Plan author {} is not trusted", plan.created_by))?;
+    check_signature(&bytes, &author_signature, &key)?;
+    plan.created_by = author;
+
+    let mut signature = read_signature(
+        &signature_path(plan_path),
+        &format!("Plan {} has not been approved", plan_path.display()),
+    )?;
+    if signature.public_key == plan.author_key {
+        return Err(anyhow::anyhow!(
+            "Plan was approved with the key of its own author {}",
+            plan.created_by
+        ));
+    }
+    let (approver, key) = trusted_key(approvers_dir, &signature.public_key)
+        .with_context(|| format!("Approver {} is not trusted", signature.approver))?;
+    check_signature(&bytes, &signature, &key)?;
+    signature.approver = approver;
+
+    info!(
+        "Plan {} verified: created by {}, approved by {} at {}",
+        plan_path.display(),
+        plan.created_by,
+        signature.approver,
+        signature.approved_at
+    );
+    Ok((plan, signature))
+}
+
+pub fn load_approved_plan(plan_path: &Path, tool: &str) -> Result<Plan> {
+    let (plan, _) = verify_plan(plan_path, &approvers_dir()?)?;
+    if plan.tool != tool {
+        return Err(anyhow::anyhow!(
+            "Plan was generated by {}, not {}",
+            plan.tool,
+            tool
+        ));
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_an_approval_from_another_trusted_key() {
+        let dir = env::temp_dir().join(format!("mstools-approval-{}", std::process::id()));
+        let keys = dir.join("keys");
+        let approvers = dir.join("approvers");
+        fs::create_dir_all(&approvers).unwrap();
+        for name in ["author", "approver"] {
+            generate_keypair(name, &keys).unwrap();
+            fs::copy(keys.join(format!("{}.pub", name)), approvers.join(format!("{}.pub", name))).unwrap();
+        }
+        let plan_path = dir.join("plan.json");
+        let action = PlanAction {
+            operation: "revokeSignInSessions".to_string(),
+            target: "jane.doe@contoso.com".to_string(),
+            details: Value::Null,
+        };
+        Plan::new("revoke_sessions", "test", vec![action])
+            .write(&plan_path, &keys.join("author.key"))
+            .unwrap();
+        assert!(verify_plan(&plan_path, &approvers).is_err());
+
+        // A key renamed to look like someone else is still the author's key.
+        let mut renamed: SecretKeyFile = serde_json::from_str(&fs::read_to_string(keys.join("author.key")).unwrap()).unwrap();
+        renamed.name = "approver".to_string();
+        fs::write(keys.join("renamed.key"), serde_json::to_string(&renamed).unwrap()).unwrap();
+        assert!(approve_plan(&plan_path, &keys.join("renamed.key")).is_err());
+
+        approve_plan(&plan_path, &keys.join("approver.key")).unwrap();
+        let (plan, signature) = verify_plan(&plan_path, &approvers).unwrap();
+        assert_eq!((plan.created_by.as_str(), signature.approver.as_str()), ("author", "approver"));
+
+        // A signature naming a path outside the directory is matched by its key, not its name.
+        let sig_path = signature_path(&plan_path);
+        let mut forged: PlanSignature = serde_json::from_str(&fs::read_to_string(&sig_path).unwrap()).unwrap();
+        forged.approver = "../keys/approver".to_string();
+        fs::write(&sig_path, serde_json::to_string(&forged).unwrap()).unwrap();
+        assert_eq!(verify_plan(&plan_path, &approvers).unwrap().1.approver, "approver");
+
+        fs::remove_file(approvers.join("approver.pub")).unwrap();
+        assert!(verify_plan(&plan_path, &approvers).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const MICROSOFT_TENANT_ID: &str = "f8cdef31-a31e-4b4a-93e4-5f571e91255a";
+
+#[derive(Deserialize, Debug)]
+pub struct ServicePrincipal {
+    pub id: String,
+    #[serde(rename = "appId")]
+    pub app_id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    #[serde(rename = "servicePrincipalType")]
+    pub service_principal_type: Option<String>,
+    #[serde(rename = "appOwnerOrganizationId")]
+    pub app_owner_organization_id: Option<String>,
+    #[serde(rename = "verifiedPublisher")]
+    pub verified_publisher: Option<VerifiedPublisher>,
+    #[serde(rename = "appRoles", default)]
+    pub app_roles: Vec<AppRole>,
+    #[serde(rename = "passwordCredentials", default)]
+    pub password_credentials: Vec<Credential>,
+    #[serde(rename = "keyCredentials", default)]
+    pub key_credentials: Vec<Credential>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifiedPublisher {
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppRole {
+    pub id: String,
+    pub value: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Credential {
+    #[serde(rename = "keyId")]
+    pub key_id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppRoleAssignment {
+    #[serde(rename = "principalId")]
+    pub principal_id: String,
+    #[serde(rename = "principalType")]
+    pub principal_type: String,
+    #[serde(rename = "resourceId")]
+    pub resource_id: String,
+    #[serde(rename = "appRoleId")]
+    pub app_role_id: String,
+}
+
+impl ServicePrincipal {
+    pub fn name(&self) -> String {
+        self.display_name.clone().unwrap_or_else(|| self.app_id.clone())
+    }
+
+    // The verified publisher, "Microsoft" for first-party apps, "internal" for apps registered
+    // in this tenant, or "unverified".
+    pub fn publisher(&self, tenant_id: &str) -> String {
+        let owner = self.app_owner_organization_id.as_deref();
+        if let Some(name) = self.verified_publisher.as_ref().and_then(|publisher| publisher.display_name.clone()) {
+            name
+        } else if owner == Some(MICROSOFT_TENANT_ID) {
+            "Microsoft".to_string()
+        } else if owner.is_some_and(|owner| owner.eq_ignore_ascii_case(tenant_id)) {
+            "internal".to_string()
+        } else {
+            "unverified".to_string()
+        }
+    }
+}
+
+// Every service principal in the tenant keyed by id.
+pub async fn service_principals(client: &GraphClient) -> Result<HashMap<String, ServicePrincipal>> {
+    let principals: Vec<ServicePrincipal> = client
+        .path(&["servicePrincipals"])
+        .select("id,appId,displayName,servicePrincipalType,appOwnerOrganizationId,verifiedPublisher,appRoles,passwordCredentials,keyCredentials")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch service principals")?;
+    Ok(principals
+        .into_iter()
+        .map(|principal| (principal.id.clone(), principal))
+        .collect())
+}
+
+// The app roles of an API assigned to users, groups, and service principals. Application
+// permissions are the assignments to service principals.
+pub async fn assigned_to(client: &GraphClient, resource_id: &str) -> Result<Vec<AppRoleAssignment>> {
+    client
+        .path(&["servicePrincipals", resource_id, "appRoleAssignedTo"])
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the app role assignments of service principal {}", resource_id))
+}
+
+// The permission name of an app role, such as Mail.Read, or its id when the API does not
+// define it.
+pub fn app_role_name(principals: &HashMap<String, ServicePrincipal>, assignment: &AppRoleAssignment) -> String {
+    principals
+        .get(&assignment.resource_id)
+        .and_then(|resource| resource.app_roles.iter().find(|role| role.id == assignment.app_role_id))
+        .and_then(|role| role.value.clone())
+        .unwrap_or_else(|| assignment.app_role_id.clone())
+}
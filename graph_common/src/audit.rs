@@ -0,0 +1,305 @@
+use crate::timezones::Expectations;
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+pub const CONTRACT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub target: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+// Written to a plugin's stdin as a single JSON document.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginInput {
+    #[serde(rename = "contractVersion")]
+    pub contract_version: u32,
+    #[serde(rename = "tenantId")]
+    pub tenant_id: String,
+    #[serde(rename = "accessToken")]
+    pub access_token: String,
+}
+
+// Expected on a plugin's stdout when it exits successfully.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginOutput {
+    pub name: Option<String>,
+    pub findings: Vec<Finding>,
+}
+
+pub const BUILTIN_AUDITS: &[(&str, &str)] = &[
+    (
+        "shared-mailbox-licenses",
+        "Shared mailboxes that are assigned licenses",
+    ),
+    (
+        "shared-mailbox-admin-roles",
+        "Shared mailboxes that are members of a directory role",
+    ),
+    (
+        "mailbox-timezone-drift",
+        "Mailboxes whose time zone or language does not match the user's usage or office location",
+    ),
+];
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "assignedLicenses", default)]
+    assigned_licenses: Vec<License>,
+}
+
+#[derive(Deserialize, Debug)]
+struct License {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryRole {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RoleMember {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LocatedUser {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    mail: Option<String>,
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+    #[serde(rename = "usageLocation")]
+    usage_location: Option<String>,
+    #[serde(rename = "officeLocation")]
+    office_location: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MailboxSettings {
+    #[serde(rename = "userPurpose")]
+    user_purpose: Option<String>,
+    #[serde(rename = "timeZone")]
+    time_zone: Option<String>,
+    language: Option<LocaleInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LocaleInfo {
+    locale: Option<String>,
+}
+
+pub fn required_permissions(name: &str) -> &'static [&'static str] {
+    match name {
+        "shared-mailbox-licenses" => &["User.Read.All", "MailboxSettings.Read"],
+        "shared-mailbox-admin-roles" => &["RoleManagement.Read.Directory|Directory.Read.All", "MailboxSettings.Read"],
+        "mailbox-timezone-drift" => &["User.Read.All", "MailboxSettings.Read"],
+        _ => &[],
+    }
+}
+
+// A missing permission fails the audit up front rather than with a 403 part way through.
+pub async fn run_builtin(name: &str, client: &GraphClient) -> Result<Vec<Finding>> {
+    client.require_permissions(required_permissions(name))?;
+    match name {
+        "shared-mailbox-licenses" => shared_mailbox_licenses(client).await,
+        "shared-mailbox-admin-roles" => shared_mailbox_admin_roles(client).await,
+        "mailbox-timezone-drift" => {
+            mailbox_timezone_drift(client, &Expectations::from_env()?).await
+        }
+        _ => Err(anyhow::anyhow!("Unknown built-in audit: {}", name)),
+    }
+}
+
+async fn is_shared_mailbox(client: &GraphClient, user_principal_name: &str) -> bool {
+    match client
+        .user(user_principal_name)
+        .mailbox_settings()
+        .get_json::<MailboxSettings>()
+        .await
+    {
+        Ok(settings) => settings
+            .user_purpose
+            .is_some_and(|purpose| purpose.eq_ignore_ascii_case("shared")),
+        Err(e) => {
+            debug!("Failed to fetch mailbox settings for {}: {}", user_principal_name, e);
+            false
+        }
+    }
+}
+
+pub async fn shared_mailbox_licenses(client: &GraphClient) -> Result<Vec<Finding>> {
+    let users: Vec<User> = client
+        .users()
+        .select("userPrincipalName,assignedLicenses")
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+
+    let mut findings = Vec::new();
+    for user in users.iter().filter(|user| !user.assigned_licenses.is_empty()) {
+        if is_shared_mailbox(client, &user.user_principal_name).await {
+            let sku_ids: Vec<&str> = user
+                .assigned_licenses
+                .iter()
+                .map(|license| license.sku_id.as_str())
+                .collect();
+            findings.push(Finding {
+                severity: Severity::Low,
+                target: user.user_principal_name.clone(),
+                message: format!("Shared mailbox has {} assigned licenses", sku_ids.len()),
+                details: json!({ "skuIds": sku_ids }),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+pub async fn shared_mailbox_admin_roles(client: &GraphClient) -> Result<Vec<Finding>> {
+    let roles: Vec<DirectoryRole> = client
+        .directory_roles()
+        .resource()
+        .get_all()
+        .await
+        .context("Failed to fetch directory roles")?;
+
+    let mut findings = Vec::new();
+    for role in roles {
+        let members: Vec<RoleMember> = client
+            .directory_roles()
+            .members(&role.id)
+            .get_all()
+            .await
+            .context("Failed to fetch directory role members")?;
+        for user_principal_name in members.into_iter().filter_map(|member| member.user_principal_name) {
+            if is_shared_mailbox(client, &user_principal_name).await {
+                findings.push(Finding {
+                    severity: Severity::High,
+                    target: user_principal_name,
+                    message: format!("Shared mailbox holds the {} role", role.display_name),
+                    details: json!({ "roleId": role.id, "role": role.display_name }),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
+
+pub async fn mailbox_timezone_drift(
+    client: &GraphClient,
+    expectations: &Expectations,
+) -> Result<Vec<Finding>> {
+    let users: Vec<LocatedUser> = client
+        .users()
+        .select("userPrincipalName,mail,accountEnabled,usageLocation,officeLocation")
+        .get_all()
+        .await
+        .context("Failed to fetch users")?;
+
+    let mut findings = Vec::new();
+    for user in users
+        .iter()
+        .filter(|user| user.mail.is_some() && user.account_enabled != Some(false))
+    {
+        let Some((expected, source)) =
+            expectations.lookup(user.usage_location.as_deref(), user.office_location.as_deref())
+        else {
+            debug!("No expected time zone for {}", user.user_principal_name);
+            continue;
+        };
+        let settings = match client
+            .user(&user.user_principal_name)
+            .mailbox_settings()
+            .get_json::<MailboxSettings>()
+            .await
+        {
+            Ok(settings) => settings,
+            Err(e) => {
+                debug!("Failed to fetch mailbox settings for {}: {}", user.user_principal_name, e);
+                continue;
+            }
+        };
+        let basis = match source {
+            "officeLocation" => user.office_location.as_deref(),
+            _ => user.usage_location.as_deref(),
+        }
+        .unwrap_or_default();
+        let details = |actual: &Option<String>| {
+            json!({
+                "usageLocation": user.usage_location,
+                "officeLocation": user.office_location,
+                "expectedFrom": source,
+                "actual": actual,
+                "expectedTimeZones": expected.time_zones,
+                "expectedLocales": expected.locales,
+            })
+        };
+
+        // Mailboxes that were never opened have no time zone yet; Outlook asks on first use.
+        if let Some(time_zone) = settings.time_zone.as_deref().filter(|zone| !zone.is_empty()) {
+            if !expected.time_zone_matches(time_zone) {
+                findings.push(Finding {
+                    severity: Severity::Low,
+                    target: user.user_principal_name.clone(),
+                    message: format!(
+                        "Mailbox time zone {} is not expected for {} {}",
+                        time_zone, source, basis
+                    ),
+                    details: details(&settings.time_zone),
+                });
+            }
+        }
+
+        let locale = settings.language.and_then(|language| language.locale);
+        if let Some(actual) = locale.as_deref().filter(|locale| !locale.is_empty()) {
+            if !expected.locale_matches(actual) {
+                findings.push(Finding {
+                    severity: Severity::Info,
+                    target: user.user_principal_name.clone(),
+                    message: format!(
+                        "Mailbox language {} is not expected for {} {}",
+                        actual, source, basis
+                    ),
+                    details: details(&locale),
+                });
+            }
+        }
+    }
+    Ok(findings)
+}
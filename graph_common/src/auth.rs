@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use log::debug;
+use reqwest::Client;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct AccessTokenResponse {
+    access_token: String,
+}
+
+pub async fn client_credentials_token(
+    tenant_id: &str,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<String> {
+    let url = format!(
+        "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+        crate::encode::path_segment(tenant_id)
+    );
+    let params = [
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("scope", "https://graph.microsoft.com/.default"),
+        ("grant_type", "client_credentials"),
+    ];
+
+    debug!("Requesting access token from: {}", url);
+    let response = Client::new()
+        .post(&url)
+        .form(&params)
+        .send()
+        .await
+        .context("Failed to send request to obtain access token")?;
+
+    if !response.status().is_success() {
+        return Err(crate::client::error_from_response(response).await);
+    }
+    let token_response: AccessTokenResponse = response
+        .json()
+        .await
+        .context("Failed to parse access token response")?;
+    debug!("Access token obtained successfully");
+    Ok(token_response.access_token)
+}
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    message: String,
+    interval: u64,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+// Signs a user in with the device code flow, for the Graph APIs that only accept delegated
+// tokens. The app registration needs "Allow public client flows" enabled; the sign-in
+// instructions are written to stderr so that report output on stdout stays clean.
+pub async fn device_code_token(tenant_id: &str, client_id: &str) -> Result<String> {
+    let base = format!(
+        "https://login.microsoftonline.com/{}/oauth2/v2.0",
+        crate::encode::path_segment(tenant_id)
+    );
+    let client = Client::new();
+
+    debug!("Requesting device code from: {}/devicecode", base);
+    let response = client
+        .post(format!("{}/devicecode", base))
+        .form(&[
+            ("client_id", client_id),
+            ("scope", "https://graph.microsoft.com/.default"),
+        ])
+        .send()
+        .await
+        .context("Failed to send request to obtain device code")?;
+    if !response.status().is_success() {
+        return Err(crate::client::error_from_response(response).await);
+    }
+    let device_code: DeviceCodeResponse = response
+        .json()
+        .await
+        .context("Failed to parse device code response")?;
+    eprintln!("{}", device_code.message);
+
+    let mut interval = device_code.interval.max(1);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(device_code.expires_in);
+    loop {
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        let response = client
+            .post(format!("{}/token", base))
+            .form(&[
+                ("client_id", client_id),
+                ("device_code", device_code.device_code.as_str()),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ])
+            .send()
+            .await
+            .context("Failed to send request to obtain access token")?;
+        if response.status().is_success() {
+            let token_response: AccessTokenResponse = response
+                .json()
+                .await
+                .context("Failed to parse access token response")?;
+            debug!("Access token obtained successfully");
+            return Ok(token_response.access_token);
+        }
+
+        let error: TokenErrorResponse = response
+            .json()
+            .await
+            .context("Failed to parse access token error response")?;
+        match error.error.as_str() {
+            "authorization_pending" if std::time::Instant::now() < deadline => {}
+            "slow_down" => interval += 5,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Sign-in failed: {}",
+                    error.error_description.unwrap_or(error.error)
+                ))
+            }
+        }
+    }
+}
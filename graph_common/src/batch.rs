@@ -0,0 +1,141 @@
+use crate::client::{retry_after_value, MAX_RETRIES};
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Graph rejects a $batch with more than 20 requests.
+pub const MAX_BATCH_SIZE: usize = 20;
+
+#[derive(Deserialize, Debug)]
+struct BatchResponse {
+    responses: Vec<BatchItem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchItem {
+    id: String,
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Value,
+}
+
+impl GraphClient {
+    // GETs the given relative URLs (see Resource::relative_url) through $batch, 20 per request.
+    // Results are in the order of the URLs; a failed item fails only its own result. Items
+    // Graph throttles inside a batch are sent again in the next batch after their Retry-After.
+    pub async fn batch_get<T: DeserializeOwned>(&self, relative_urls: &[String]) -> Result<Vec<Result<T>>> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(fixtures) = self.fixtures() {
+            return Ok(relative_urls
+                .iter()
+                .map(|url| {
+                    let text = fixtures.response(url)?;
+                    serde_json::from_str(&text).context("Failed to parse response from Graph API")
+                })
+                .collect());
+        }
+        let mut results: Vec<Option<Result<T>>> = relative_urls.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..relative_urls.len()).collect();
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            let mut throttled = Vec::new();
+            let mut wait = Duration::ZERO;
+            for chunk in pending.chunks(MAX_BATCH_SIZE) {
+                for item in self.send_batch(chunk, relative_urls).await? {
+                    let Some(index) = item.id.parse::<usize>().ok().filter(|index| chunk.contains(index)) else {
+                        warn!("Ignoring $batch response with unknown id {}", item.id);
+                        continue;
+                    };
+                    if (item.status == 429 || item.status == 503) && attempt < MAX_RETRIES {
+                        self.stats().throttled();
+                        let retry_after = item
+                            .headers
+                            .iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case("Retry-After"))
+                            .map(|(_, value)| value.as_str());
+                        wait = wait.max(retry_after_value(retry_after, attempt + 1));
+                        throttled.push(index);
+                        continue;
+                    }
+                    results[index] = Some(item_result(item));
+                }
+            }
+
+            // Anything Graph left out of a response is reported rather than retried.
+            for &index in &pending {
+                if results[index].is_none() && !throttled.contains(&index) {
+                    results[index] = Some(Err(anyhow::anyhow!("No response for {} in $batch", relative_urls[index])));
+                }
+            }
+            if !throttled.is_empty() {
+                attempt += 1;
+                warn!(
+                    "Graph API throttled {} requests in a $batch, retrying in {:?} (attempt {}/{})",
+                    throttled.len(),
+                    wait,
+                    attempt,
+                    MAX_RETRIES
+                );
+                tokio::time::sleep(wait).await;
+            }
+            pending = throttled;
+        }
+        Ok(results.into_iter().map(|result| result.expect("every batch item has a result")).collect())
+    }
+
+    async fn send_batch(&self, chunk: &[usize], relative_urls: &[String]) -> Result<Vec<BatchItem>> {
+        let requests: Vec<Value> = chunk
+            .iter()
+            .map(|&index| json!({ "id": index.to_string(), "method": "GET", "url": relative_urls[index] }))
+            .collect();
+        let url = format!("{}/$batch", self.base_url());
+        debug!("POST {} ({} requests)", url, requests.len());
+        let body = json!({ "requests": requests });
+        let response = self.send(|client| client.post(&url).json(&body)).await?;
+        if !response.status().is_success() {
+            return Err(crate::client::error_from_response(response).await);
+        }
+        let text = response
+            .text()
+            .await
+            .context("Failed to read $batch response from Graph API")?;
+        self.stats().received(text.len() as u64);
+        let batch: BatchResponse =
+            serde_json::from_str(&text).context("Failed to parse $batch response from Graph API")?;
+        Ok(batch.responses)
+    }
+}
+
+fn item_result<T: DeserializeOwned>(item: BatchItem) -> Result<T> {
+    if !(200..300).contains(&item.status) {
+        return Err(anyhow::anyhow!("HTTP error {}: {}", item.status, item.body));
+    }
+    serde_json::from_value(item.body).context("Failed to parse response from Graph API")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_batch_items_to_results() {
+        let batch: BatchResponse = serde_json::from_value(json!({ "responses": [
+            { "id": "1", "status": 404, "body": { "error": { "code": "ErrorItemNotFound" } } },
+            { "id": "0", "status": 200, "headers": { "Content-Type": "application/json" }, "body": { "userPurpose": "shared" } },
+        ]}))
+        .unwrap();
+        let mut items = batch.responses.into_iter();
+
+        let missing = item_result::<Value>(items.next().unwrap()).unwrap_err();
+        assert!(missing.to_string().starts_with("HTTP error 404"));
+        let found: Value = item_result(items.next().unwrap()).unwrap();
+        assert_eq!(found["userPurpose"], "shared");
+    }
+}
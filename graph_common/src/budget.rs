@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_REQUESTS_PER_SECOND: u32 = 10;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct BudgetState {
+    #[serde(rename = "windowStartMs")]
+    window_start_ms: u64,
+    count: u32,
+    #[serde(rename = "throttledUntilMs")]
+    throttled_until_ms: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestBudget {
+    path: PathBuf,
+    requests_per_second: u32,
+}
+
+impl RequestBudget {
+    pub fn new(path: PathBuf, requests_per_second: u32) -> Self {
+        Self {
+            path,
+            requests_per_second: requests_per_second.max(1),
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("GRAPH_BUDGET_FILE").ok()?;
+        let requests_per_second = env::var("GRAPH_BUDGET_RPS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_REQUESTS_PER_SECOND);
+        debug!(
+            "Sharing a budget of {} requests/second through {}",
+            requests_per_second, path
+        );
+        Some(Self::new(PathBuf::from(path), requests_per_second))
+    }
+
+    pub async fn acquire(&self) -> Result<()> {
+        loop {
+            match self.try_acquire().await? {
+                None => return Ok(()),
+                Some(wait) => {
+                    debug!("Request budget exhausted, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+
+    pub async fn throttled(&self, retry_after: Duration) -> Result<()> {
+        let until = now_ms() + retry_after.as_millis() as u64;
+        self.with_locked_state(move |state| {
+            state.throttled_until_ms = state.throttled_until_ms.max(until);
+            None
+        })
+        .await
+        .map(|_| ())
+    }
+
+    async fn try_acquire(&self) -> Result<Option<Duration>> {
+        let requests_per_second = self.requests_per_second;
+        self.with_locked_state(move |state| {
+            let now = now_ms();
+            if state.throttled_until_ms > now {
+                return Some(Duration::from_millis(state.throttled_until_ms - now));
+            }
+            if now.saturating_sub(state.window_start_ms) >= 1000 {
+                state.window_start_ms = now;
+                state.count = 0;
+            }
+            if state.count < requests_per_second {
+                state.count += 1;
+                None
+            } else {
+                Some(Duration::from_millis(
+                    (state.window_start_ms + 1000).saturating_sub(now).max(1),
+                ))
+            }
+        })
+        .await
+    }
+
+    // Waiting for the lock blocks, so it runs off the async worker threads.
+    async fn with_locked_state<F>(&self, f: F) -> Result<Option<Duration>>
+    where
+        F: FnOnce(&mut BudgetState) -> Option<Duration> + Send + 'static,
+    {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || update_locked_state(&path, f))
+            .await
+            .context("Budget file task failed")?
+    }
+}
+
+fn update_locked_state<F>(path: &Path, f: F) -> Result<Option<Duration>>
+where
+    F: FnOnce(&mut BudgetState) -> Option<Duration>,
+{
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open budget file {}", path.display()))?;
+    file.lock_exclusive()
+        .with_context(|| format!("Failed to lock budget file {}", path.display()))?;
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .context("Failed to read budget file")?;
+    let mut state: BudgetState = if contents.trim().is_empty() {
+        BudgetState::default()
+    } else {
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Resetting unreadable budget file: {}", e);
+            BudgetState::default()
+        })
+    };
+
+    let result = f(&mut state);
+
+    file.set_len(0).context("Failed to truncate budget file")?;
+    file.seek(SeekFrom::Start(0))
+        .context("Failed to rewind budget file")?;
+    file.write_all(serde_json::to_string(&state)?.as_bytes())
+        .context("Failed to write budget file")?;
+    FileExt::unlock(&file).context("Failed to unlock budget file")?;
+
+    Ok(result)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub url: String,
+    pub etag: Option<String>,
+    #[serde(rename = "storedAt")]
+    pub stored_at: u64,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Result<Self> {
+        create_private_dir(&dir)?;
+        Ok(Self { dir, ttl })
+    }
+
+    // Entries are kept per tenant: the URLs of two tenants are the same, their responses are not.
+    pub fn for_tool(tool: &str, tenant_id: &str, ttl: Duration) -> Result<Self> {
+        let base = match env::var("GRAPH_CACHE_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => {
+                let home = env::var("XDG_CACHE_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|_| env::var("HOME").map(|home| Path::new(&home).join(".cache")))
+                    .unwrap_or_else(|_| env::temp_dir());
+                home.join("microsoft_services_tools")
+            }
+        };
+        let tenant: String = tenant_id
+            .trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        if tenant.is_empty() || tenant.chars().all(|c| c == '.') {
+            return Err(anyhow::anyhow!("Cannot cache responses without a tenant id"));
+        }
+        let dir = base.join(tool).join(tenant);
+        debug!("Caching Graph responses in {} for {:?}", dir.display(), ttl);
+        Self::new(dir, ttl)
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        let key: String = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn lookup(&self, url: &str) -> Option<CacheEntry> {
+        let contents = fs::read_to_string(self.entry_path(url)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+        (entry.url == url).then_some(entry)
+    }
+
+    pub fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        now_secs().saturating_sub(entry.stored_at) < self.ttl.as_secs()
+    }
+
+    pub fn store(&self, url: &str, etag: Option<String>, body: &str) {
+        let entry = CacheEntry {
+            url: url.to_string(),
+            etag,
+            stored_at: now_secs(),
+            body: body.to_string(),
+        };
+        let result = serde_json::to_string(&entry)
+            .context("Failed to serialize cache entry")
+            .and_then(|json| {
+                fs::write(self.entry_path(url), json).context("Failed to write cache entry")
+            });
+        if let Err(e) = result {
+            warn!("Failed to cache response for {}: {}", url, e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create cache directory {}", dir.display()))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create cache directory {}", dir.display()))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_tenants_apart() {
+        let base = env::temp_dir().join(format!("graph-cache-{}", std::process::id()));
+        env::set_var("GRAPH_CACHE_DIR", &base);
+        let url = "https://graph.microsoft.com/v1.0/users?$select=id";
+        let a = ResponseCache::for_tool("tool", "11111111-aaaa-4aaa-8aaa-000000000001", Duration::from_secs(60)).unwrap();
+        let b = ResponseCache::for_tool("tool", "22222222-bbbb-4bbb-8bbb-000000000002", Duration::from_secs(60)).unwrap();
+        a.store(url, None, "{\"value\":[]}");
+        assert!(a.lookup(url).is_some());
+        assert!(b.lookup(url).is_none());
+        assert!(ResponseCache::for_tool("tool", "../..", Duration::from_secs(60)).is_ok_and(|cache| cache.dir.starts_with(base.join("tool"))));
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
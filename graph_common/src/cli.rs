@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub fn confirm() -> Result<bool> {
+    print!("Proceed? [y/N] ");
+    std::io::stdout().flush().context("Failed to write prompt")?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read confirmation")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub fn is_object_id(value: &str) -> bool {
+    value.len() == 36
+        && value
+            .chars()
+            .enumerate()
+            .all(|(index, c)| match index {
+                8 | 13 | 18 | 23 => c == '-',
+                _ => c.is_ascii_hexdigit(),
+            })
+}
+
+// Reads UPNs from a file, or from stdin when the source is "-".
+pub fn read_upns(source: impl AsRef<Path>) -> Result<Vec<String>> {
+    let source = source.as_ref();
+    let content = if source == Path::new("-") {
+        debug!("Reading UPNs from stdin");
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read UPNs from stdin")?;
+        content
+    } else {
+        debug!("Reading UPNs from {}", source.display());
+        std::fs::read_to_string(source)
+            .with_context(|| format!("Failed to read {}", source.display()))?
+    };
+    Ok(parse_upns(&content))
+}
+
+// The UPNs given on the command line followed by those read from the file, without duplicates.
+pub fn collect_upns(upns: &[String], file: Option<impl AsRef<Path>>) -> Result<Vec<String>> {
+    let mut all: Vec<String> = upns.iter().map(|upn| upn.trim().to_string()).collect();
+    if let Some(file) = file {
+        all.extend(read_upns(file)?);
+    }
+    let mut seen = std::collections::HashSet::new();
+    all.retain(|upn| !upn.is_empty() && seen.insert(upn.to_lowercase()));
+    Ok(all)
+}
+
+fn parse_upns(content: &str) -> Vec<String> {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    // Accept either a plain list of UPNs or a CSV with a upn/userPrincipalName column.
+    let mut column = 0;
+    if let Some(header) = lines.peek() {
+        let header_column = header.split(',').position(|field| {
+            let field = field.trim().trim_matches('"');
+            field.eq_ignore_ascii_case("upn") || field.eq_ignore_ascii_case("userPrincipalName")
+        });
+        if let Some(header_column) = header_column {
+            column = header_column;
+            lines.next();
+        }
+    }
+
+    let mut upns: Vec<String> = Vec::new();
+    for line in lines {
+        let upn = line
+            .split(',')
+            .nth(column)
+            .map(|field| field.trim().trim_matches('"'))
+            .unwrap_or_default();
+        if upn.is_empty() {
+            debug!("Skipping line without a UPN: {}", line);
+        } else if !upns.iter().any(|existing| existing.eq_ignore_ascii_case(upn)) {
+            upns.push(upn.to_string());
+        }
+    }
+    upns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_lists_and_csv_files() {
+        assert_eq!(
+            parse_upns("# users\nadele.vance@contoso.com\n\nAdele.Vance@contoso.com\nlee.gu@contoso.com\n"),
+            ["adele.vance@contoso.com", "lee.gu@contoso.com"]
+        );
+        assert_eq!(
+            parse_upns("displayName,\"userPrincipalName\"\nAdele Vance,\"adele.vance@contoso.com\"\nNo UPN,\n"),
+            ["adele.vance@contoso.com"]
+        );
+    }
+
+    #[test]
+    fn recognizes_object_ids() {
+        assert!(is_object_id("6e7b768e-07e2-4810-8459-485f84f8f204"));
+        assert!(!is_object_id("adele.vance@contoso.com"));
+        assert!(!is_object_id("6e7b768e007e2-4810-8459-485f84f8f204"));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::actionlog::{ActionLog, Change};
+use crate::budget::RequestBudget;
+use crate::cache::ResponseCache;
+#[cfg(any(test, feature = "testing"))]
+use crate::fixtures::Fixtures;
+use crate::stats::RunStats;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+#[cfg(any(test, feature = "testing"))]
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const GRAPH_BASE_URL: &str = "https://graph.microsoft.com/v1.0";
+pub(crate) const MAX_RETRIES: u32 = 4;
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    value: Vec<T>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct GraphClient {
+    client: Client,
+    base_url: String,
+    access_token: String,
+    budget: Option<RequestBudget>,
+    cache: Option<ResponseCache>,
+    action_log: Option<ActionLog>,
+    stats: RunStats,
+    #[cfg(any(test, feature = "testing"))]
+    fixtures: Option<Arc<Fixtures>>,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl GraphClient {
+    pub fn new(access_token: String) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: GRAPH_BASE_URL.to_string(),
+            access_token,
+            budget: RequestBudget::from_env(),
+            cache: None,
+            action_log: ActionLog::from_env(),
+            stats: RunStats::new(),
+            #[cfg(any(test, feature = "testing"))]
+            fixtures: None,
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn with_budget(mut self, budget: Option<RequestBudget>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: Option<ResponseCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_action_log(mut self, action_log: Option<ActionLog>) -> Self {
+        self.action_log = action_log;
+        self
+    }
+
+    // Points resource paths at another endpoint, such as a mock server in benchmarks.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    // Answers GETs from recorded responses instead of Graph, for report tests.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(Arc::new(fixtures));
+        self
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn fixtures(&self) -> Option<&Fixtures> {
+        self.fixtures.as_deref()
+    }
+
+    // Sent with every request, for APIs that require extra headers such as ocp-client-name.
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn stats(&self) -> &RunStats {
+        &self.stats
+    }
+
+    pub async fn send<F>(&self, build: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            if let Some(budget) = &self.budget {
+                budget.acquire().await?;
+            }
+
+            let request = self
+                .headers
+                .iter()
+                .fold(build(&self.client), |request, (name, value)| request.header(*name, value))
+                .bearer_auth(&self.access_token)
+                .build()
+                .context("Failed to build request to Graph API")?;
+            self.stats.request(
+                request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .map_or(0, |body| body.len() as u64),
+            );
+            let response = self
+                .client
+                .execute(request)
+                .await
+                .context("Failed to send request to Graph API")?;
+
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+                self.stats.throttled();
+            }
+            if !(status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::SERVICE_UNAVAILABLE)
+                || attempt >= MAX_RETRIES
+            {
+                return Ok(response);
+            }
+
+            attempt += 1;
+            let retry_after = retry_after(&response, attempt);
+            warn!(
+                "Graph API throttled the request ({}), retrying in {:?} (attempt {}/{})",
+                status, retry_after, attempt, MAX_RETRIES
+            );
+            if let Some(budget) = &self.budget {
+                budget.throttled(retry_after).await?;
+            }
+            tokio::time::sleep(retry_after).await;
+        }
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        debug!("GET {}", url);
+        let response = self.send(|client| client.get(url)).await?;
+        self.stats.received(response.content_length().unwrap_or(0));
+        Ok(response)
+    }
+
+    pub async fn get_text(&self, url: &str) -> Result<String> {
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(fixtures) = &self.fixtures {
+            return fixtures.response(url.strip_prefix(self.base_url.as_str()).unwrap_or(url));
+        }
+        let cached = self.cache.as_ref().and_then(|cache| cache.lookup(url));
+        if let (Some(cache), Some(entry)) = (&self.cache, &cached) {
+            if cache.is_fresh(entry) {
+                debug!("Serving {} from cache", url);
+                self.stats.cache_hit();
+                return Ok(entry.body.clone());
+            }
+        }
+
+        let etag = cached.as_ref().and_then(|entry| entry.etag.clone());
+        debug!("GET {}", url);
+        let response = self
+            .send(|client| match &etag {
+                Some(etag) => client.get(url).header(IF_NONE_MATCH, etag),
+                None => client.get(url),
+            })
+            .await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let (Some(cache), Some(entry)) = (&self.cache, cached) {
+                debug!("{} not modified, revalidated cached response", url);
+                self.stats.cache_hit();
+                cache.store(url, entry.etag, &entry.body);
+                return Ok(entry.body);
+            }
+        }
+        if !response.status().is_success() {
+            return Err(error_from_response(response).await);
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response
+            .text()
+            .await
+            .context("Failed to read response from Graph API")?;
+        self.stats.received(body.len() as u64);
+        if let Some(cache) = &self.cache {
+            cache.store(url, etag, &body);
+        }
+        Ok(body)
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let body = self.get_text(url).await?;
+        serde_json::from_str(&body).context("Failed to parse response from Graph API")
+    }
+
+    pub async fn get_all<T: DeserializeOwned>(&self, url: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        self.for_each_page(url, |page| {
+            items.extend(page);
+            Ok(())
+        })
+        .await?;
+        Ok(items)
+    }
+
+    // Hands each page to on_page as it arrives instead of collecting them, so exports of large
+    // logs do not hold every item in memory. Returns the number of items read.
+    pub async fn for_each_page<T, F>(&self, url: &str, mut on_page: F) -> Result<usize>
+    where
+        T: DeserializeOwned,
+        F: FnMut(Vec<T>) -> Result<()>,
+    {
+        let mut count = 0;
+        let mut next = Some(url.to_string());
+        while let Some(url) = next {
+            let page: Page<T> = self.get_json(&url).await?;
+            debug!("Fetched {} items from {}", page.value.len(), url);
+            count += page.value.len();
+            on_page(page.value)?;
+            next = page.next_link;
+        }
+        Ok(count)
+    }
+
+    pub async fn post_json(&self, url: &str, body: &Value) -> Result<Response> {
+        self.send_change(Change::new("POST", url, Some(body))).await
+    }
+
+    pub async fn patch_json(&self, url: &str, body: &Value) -> Result<Response> {
+        self.send_change(Change::new("PATCH", url, Some(body))).await
+    }
+
+    pub async fn delete(&self, url: &str) -> Result<Response> {
+        self.send_change(Change::new("DELETE", url, None)).await
+    }
+
+    // Every request that changes data goes through here so it is recorded in the action log.
+    pub async fn send_change(&self, change: Change<'_>) -> Result<Response> {
+        debug!("{} {}", change.method, change.url);
+        let method = Method::from_bytes(change.method.as_bytes())
+            .with_context(|| format!("Invalid HTTP method {}", change.method))?;
+        let response = self
+            .send(|client| {
+                let request = client.request(method.clone(), change.url);
+                match change.body {
+                    Some(body) => request.json(body),
+                    None => request,
+                }
+            })
+            .await?;
+        self.stats.received(response.content_length().unwrap_or(0));
+        if let Some(action_log) = &self.action_log {
+            action_log.record(&change, &response)?;
+        }
+        Ok(response)
+    }
+}
+
+pub async fn parse_json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    if response.status().is_success() {
+        response
+            .json()
+            .await
+            .context("Failed to parse response from Graph API")
+    } else {
+        Err(error_from_response(response).await)
+    }
+}
+
+pub async fn ensure_success(response: Response) -> Result<()> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(error_from_response(response).await)
+    }
+}
+
+pub async fn error_from_response(response: Response) -> anyhow::Error {
+    let status = response.status();
+    let error_text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "<unreadable response body>".to_string());
+    anyhow::anyhow!("HTTP error {}: {}", status, error_text)
+}
+
+fn retry_after(response: &Response, attempt: u32) -> Duration {
+    retry_after_value(
+        response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok()),
+        attempt,
+    )
+}
+
+pub(crate) fn retry_after_value(value: Option<&str>, attempt: u32) -> Duration {
+    value
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| Duration::from_secs(2u64.pow(attempt)))
+}
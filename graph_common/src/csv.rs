@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+// A CSV file with a header row. Blank lines and lines starting with '#' are skipped, and
+// line numbers refer to the original file so errors can point at the right row.
+pub struct Table {
+    pub header: Vec<String>,
+    pub records: Vec<Record>,
+}
+
+// Splits one CSV line, honouring double-quoted fields with embedded commas and "" escapes.
+pub fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|field| field.trim().to_string()).collect()
+}
+
+// Quotes a field for CSV output when it contains a separator, quote, or line break.
+pub fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl Table {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+        let (_, header) = lines.next().context("The file is empty")?;
+        Ok(Self {
+            header: split_fields(header),
+            records: lines
+                .map(|(index, line)| Record {
+                    line: index + 1,
+                    fields: split_fields(line),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn column(&self, names: &[&str]) -> Option<usize> {
+        self.header
+            .iter()
+            .position(|field| names.iter().any(|name| field.eq_ignore_ascii_case(name)))
+    }
+
+    pub fn upn_column(&self) -> Result<usize> {
+        self.column(&["upn", "userPrincipalName"])
+            .context("The file has no upn or userPrincipalName column")
+    }
+}
+
+impl Record {
+    pub fn get(&self, column: usize) -> &str {
+        self.fields.get(column).map(String::as_str).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_fields_and_keeps_line_numbers() {
+        let table = Table::parse("UserPrincipalName,costCenter\n# comment\n\njane@contoso.com,\"Sales, EMEA\"\njohn@contoso.com,\"R&D \"\"Labs\"\"\"\n").unwrap();
+        assert_eq!(table.upn_column().unwrap(), 0);
+        assert_eq!(table.column(&["COSTCENTER"]), Some(1));
+        assert_eq!(table.records.len(), 2);
+        assert_eq!(table.records[0].line, 4);
+        assert_eq!(table.records[0].get(1), "Sales, EMEA");
+        assert_eq!(table.records[1].get(1), "R&D \"Labs\"");
+        assert_eq!(table.records[1].get(5), "");
+        assert_eq!(split_fields(&field("R&D \"Labs\", EMEA")), vec!["R&D \"Labs\", EMEA"]);
+    }
+}
@@ -0,0 +1,92 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'@');
+
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'@')
+    .remove(b',')
+    .remove(b':')
+    .remove(b'/')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'$');
+
+pub fn path_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, PATH_SEGMENT).to_string()
+}
+
+pub fn query_value(value: &str) -> String {
+    utf8_percent_encode(value, QUERY_VALUE).to_string()
+}
+
+pub fn odata_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_guest_upn_hashes() {
+        assert_eq!(
+            path_segment("jane_fabrikam.com#EXT#@contoso.onmicrosoft.com"),
+            "jane_fabrikam.com%23EXT%23@contoso.onmicrosoft.com"
+        );
+    }
+
+    #[test]
+    fn encodes_apostrophes_in_upns() {
+        assert_eq!(path_segment("o'brien@contoso.com"), "o%27brien@contoso.com");
+    }
+
+    #[test]
+    fn encodes_path_separators_and_query_characters() {
+        assert_eq!(path_segment("a/b?c d"), "a%2Fb%3Fc%20d");
+    }
+
+    #[test]
+    fn leaves_plain_upns_untouched() {
+        assert_eq!(path_segment("john.doe@contoso.com"), "john.doe@contoso.com");
+    }
+
+    #[test]
+    fn escapes_odata_string_literals() {
+        assert_eq!(odata_string("O'Brien's mail"), "'O''Brien''s mail'");
+        assert_eq!(odata_string("plain"), "'plain'");
+    }
+
+    #[test]
+    fn encodes_filter_expressions_as_query_values() {
+        let filter = format!("displayName eq {}", odata_string("Phishing & O'Brien #1"));
+        assert_eq!(
+            query_value(&filter),
+            "displayName%20eq%20%27Phishing%20%26%20O%27%27Brien%20%231%27"
+        );
+    }
+
+    #[test]
+    fn encodes_quoted_search_subjects() {
+        assert_eq!(
+            query_value("\"subject:Invoice \\\"Q3\\\" + fees\""),
+            "%22subject:Invoice%20%5C%22Q3%5C%22%20%2B%20fees%22"
+        );
+    }
+
+    #[test]
+    fn keeps_select_lists_readable() {
+        assert_eq!(
+            query_value("userPrincipalName,assignedLicenses"),
+            "userPrincipalName,assignedLicenses"
+        );
+    }
+}
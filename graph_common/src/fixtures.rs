@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Recorded Graph responses keyed by the URL relative to the client's base URL, for example
+// "/directoryRoles" or "/users/jane@contoso.com/mailboxSettings". A client with fixtures
+// answers every GET from them, including GETs sent through $batch, and never sends a request,
+// so report tests run offline. A string value is returned as is, for endpoints such as the
+// usage reports that return CSV. A URL without a recorded response is an error.
+#[derive(Debug, Default)]
+pub struct Fixtures {
+    responses: HashMap<String, String>,
+}
+
+impl Fixtures {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read fixtures {}", path.display()))?;
+        let responses: Map<String, Value> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse fixtures {}", path.display()))?;
+        Ok(Self {
+            responses: responses
+                .into_iter()
+                .map(|(url, body)| match body {
+                    Value::String(text) => (url, text),
+                    body => (url, body.to_string()),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn response(&self, relative_url: &str) -> Result<String> {
+        self.responses
+            .get(relative_url)
+            .cloned()
+            .with_context(|| format!("No recorded response for GET {}", relative_url))
+    }
+}
@@ -0,0 +1,63 @@
+use crate::fixtures::Fixtures;
+use crate::GraphClient;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The testdata directory of the crate under test; cargo sets CARGO_MANIFEST_DIR when it runs tests.
+pub fn testdata_dir() -> PathBuf {
+    PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is not set; run the tests with cargo"))
+        .join("testdata")
+}
+
+pub fn testdata(name: &str) -> PathBuf {
+    testdata_dir().join(name)
+}
+
+// A client answering from the recorded responses in testdata/graph.json.
+pub fn fixture_client() -> GraphClient {
+    let fixtures = Fixtures::load(&testdata("graph.json")).expect("failed to load testdata/graph.json");
+    GraphClient::new(String::new()).with_fixtures(fixtures)
+}
+
+// Renders the report once per output format and compares each with golden/<name>.<extension>,
+// where text output is kept as .txt.
+pub fn assert_reports<F>(name: &str, outputs: &[&str], render: F)
+where
+    F: Fn(&str) -> Result<String>,
+{
+    for output in outputs {
+        let rendered = render(output).unwrap_or_else(|e| panic!("Failed to render the {} report: {:#}", output, e));
+        let extension = if *output == "text" { "txt" } else { output };
+        assert_matches(&testdata(&format!("golden/{}.{}", name, extension)), &rendered);
+    }
+}
+
+// Compares report output with a checked-in golden file. Run the tests with UPDATE_GOLDEN=1
+// to rewrite the golden files after an intentional format change, then review the diff.
+pub fn assert_matches(path: &Path, actual: &str) {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("failed to create the golden file directory");
+        }
+        fs::write(path, actual).expect("failed to write the golden file");
+        return;
+    }
+    let expected = fs::read_to_string(path).unwrap_or_else(|e| {
+        panic!("Failed to read golden file {} ({}); run with UPDATE_GOLDEN=1 to create it", path.display(), e)
+    });
+    if expected != actual {
+        let line = expected
+            .lines()
+            .zip(actual.lines())
+            .position(|(expected, actual)| expected != actual)
+            .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+        panic!(
+            "Output differs from golden file {} at line {}:\n  expected: {:?}\n  actual:   {:?}\nRun with UPDATE_GOLDEN=1 if the change is intentional.",
+            path.display(),
+            line + 1,
+            expected.lines().nth(line).unwrap_or("<end of file>"),
+            actual.lines().nth(line).unwrap_or("<end of file>")
+        );
+    }
+}
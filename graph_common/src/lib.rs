@@ -0,0 +1,57 @@
+pub mod actionlog;
+pub mod approval;
+pub mod apps;
+pub mod audit;
+pub mod auth;
+pub mod batch;
+pub mod budget;
+pub mod cache;
+pub mod cli;
+pub mod client;
+pub mod csv;
+pub mod encode;
+#[cfg(any(test, feature = "testing"))]
+pub mod fixtures;
+#[cfg(any(test, feature = "testing"))]
+pub mod golden;
+pub mod licenses;
+pub mod mail;
+pub mod paths;
+pub mod permissions;
+pub mod remediation;
+pub mod reports;
+pub mod roles;
+pub mod rules;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod stats;
+pub mod timezones;
+
+pub use client::GraphClient;
+
+use anyhow::Result;
+
+use std::env;
+
+pub fn current_operator() -> String {
+    env::var("MSTOOLS_OPERATOR")
+        .or_else(|_| env::var("USER"))
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+impl GraphClient {
+    pub async fn from_client_credentials(
+        tenant_id: &str,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<Self> {
+        let access_token = auth::client_credentials_token(tenant_id, client_id, client_secret).await?;
+        Ok(Self::new(access_token))
+    }
+
+    pub async fn from_device_code(tenant_id: &str, client_id: &str) -> Result<Self> {
+        let access_token = auth::device_code_token(tenant_id, client_id).await?;
+        Ok(Self::new(access_token))
+    }
+}
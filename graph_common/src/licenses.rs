@@ -0,0 +1,235 @@
+use crate::csv::Table;
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Product names for common SKUs. Graph only returns the part number; anything not listed
+// here is shown by its part number.
+const PRODUCT_NAMES: &[(&str, &str)] = &[
+    ("AAD_PREMIUM", "Microsoft Entra ID P1"),
+    ("AAD_PREMIUM_P2", "Microsoft Entra ID P2"),
+    ("ATP_ENTERPRISE", "Microsoft Defender for Office 365 (Plan 1)"),
+    ("DESKLESSPACK", "Office 365 F3"),
+    ("EMS", "Enterprise Mobility + Security E3"),
+    ("EMSPREMIUM", "Enterprise Mobility + Security E5"),
+    ("ENTERPRISEPACK", "Office 365 E3"),
+    ("ENTERPRISEPREMIUM", "Office 365 E5"),
+    ("EXCHANGEARCHIVE_ADDON", "Exchange Online Archiving for Exchange Online"),
+    ("EXCHANGEDESKLESS", "Exchange Online Kiosk"),
+    ("EXCHANGEENTERPRISE", "Exchange Online (Plan 2)"),
+    ("EXCHANGESTANDARD", "Exchange Online (Plan 1)"),
+    ("FLOW_FREE", "Microsoft Power Automate Free"),
+    ("O365_BUSINESS_ESSENTIALS", "Microsoft 365 Business Basic"),
+    ("O365_BUSINESS_PREMIUM", "Microsoft 365 Business Standard"),
+    ("POWER_BI_PRO", "Power BI Pro"),
+    ("POWER_BI_STANDARD", "Power BI (free)"),
+    ("PROJECTPREMIUM", "Project Plan 5"),
+    ("PROJECTPROFESSIONAL", "Project Plan 3"),
+    ("SPB", "Microsoft 365 Business Premium"),
+    ("SPE_E3", "Microsoft 365 E3"),
+    ("SPE_E5", "Microsoft 365 E5"),
+    ("SPE_F1", "Microsoft 365 F3"),
+    ("STANDARDPACK", "Office 365 E1"),
+    ("VISIOCLIENT", "Visio Plan 2"),
+    ("WIN_DEF_ATP", "Microsoft Defender for Endpoint"),
+];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubscribedSku {
+    #[serde(rename = "skuId")]
+    pub sku_id: String,
+    #[serde(rename = "skuPartNumber")]
+    pub sku_part_number: String,
+    #[serde(rename = "consumedUnits", default)]
+    pub consumed_units: u32,
+    #[serde(rename = "prepaidUnits", default)]
+    pub prepaid_units: PrepaidUnits,
+    #[serde(rename = "capabilityStatus")]
+    pub capability_status: Option<String>,
+    #[serde(rename = "servicePlans", default)]
+    pub service_plans: Vec<ServicePlan>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ServicePlan {
+    #[serde(rename = "servicePlanId")]
+    pub service_plan_id: String,
+    #[serde(rename = "servicePlanName")]
+    pub service_plan_name: String,
+}
+
+// Purchased seats by state. Seats in warning are in the grace period after a subscription
+// expired; suspended seats can no longer be assigned.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PrepaidUnits {
+    #[serde(default)]
+    pub enabled: u32,
+    #[serde(default)]
+    pub suspended: u32,
+    #[serde(default)]
+    pub warning: u32,
+    #[serde(rename = "lockedOut", default)]
+    pub locked_out: u32,
+}
+
+impl SubscribedSku {
+    // Service plans are named like TEAMS1 or EXCHANGE_S_ENTERPRISE, as in servicePlanName.
+    pub fn service_plan(&self, name: &str) -> Option<&ServicePlan> {
+        self.service_plans
+            .iter()
+            .find(|plan| plan.service_plan_name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn display_name(&self) -> String {
+        match product_name(&self.sku_part_number) {
+            Some(name) => format!("{} ({})", name, self.sku_part_number),
+            None => self.sku_part_number.clone(),
+        }
+    }
+}
+
+pub fn product_name(sku_part_number: &str) -> Option<&'static str> {
+    PRODUCT_NAMES
+        .iter()
+        .find(|(part_number, _)| part_number.eq_ignore_ascii_case(sku_part_number))
+        .map(|(_, name)| *name)
+}
+
+pub async fn subscribed_skus(client: &GraphClient) -> Result<Vec<SubscribedSku>> {
+    client
+        .path(&["subscribedSkus"])
+        .get_all()
+        .await
+        .context("Failed to fetch subscribed SKUs")
+}
+
+// The tenant's subscriptions keyed by lowercase skuId, read once per run. Needs
+// Organization.Read.All (or Directory.Read.All).
+pub struct SkuCatalog {
+    skus: HashMap<String, SubscribedSku>,
+}
+
+impl SkuCatalog {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        Ok(Self::from_skus(subscribed_skus(client).await?))
+    }
+
+    pub fn from_skus(skus: Vec<SubscribedSku>) -> Self {
+        Self {
+            skus: skus
+                .into_iter()
+                .map(|sku| (sku.sku_id.to_lowercase(), sku))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, sku_id: &str) -> Option<&SubscribedSku> {
+        self.skus.get(&sku_id.to_lowercase())
+    }
+
+    pub fn find(&self, sku_part_number: &str) -> Option<&SubscribedSku> {
+        self.skus
+            .values()
+            .find(|sku| sku.sku_part_number.eq_ignore_ascii_case(sku_part_number))
+    }
+
+    // Matches the part number (ENTERPRISEPACK) or the product name (Office 365 E3).
+    pub fn find_by_name(&self, name: &str) -> Option<&SubscribedSku> {
+        self.find(name).or_else(|| {
+            self.skus.values().find(|sku| {
+                product_name(&sku.sku_part_number).is_some_and(|product| product.eq_ignore_ascii_case(name))
+            })
+        })
+    }
+
+    // SKUs the tenant no longer subscribes to are shown by their GUID.
+    pub fn display_name(&self, sku_id: &str) -> String {
+        self.get(sku_id)
+            .map(SubscribedSku::display_name)
+            .unwrap_or_else(|| sku_id.to_string())
+    }
+}
+
+// Monthly prices per SKU part number, from a CSV kept by the operator. Graph has no pricing,
+// and list prices differ by agreement, region, and currency.
+pub struct PriceList {
+    prices: HashMap<String, f64>,
+}
+
+impl PriceList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        let table = Table::parse(content.trim_start_matches('\u{feff}'))?;
+        let sku = table
+            .column(&["SkuPartNumber", "sku"])
+            .context("The price list has no SkuPartNumber column")?;
+        let price = table
+            .column(&["MonthlyPrice", "price"])
+            .context("The price list has no MonthlyPrice column")?;
+        let mut prices = HashMap::new();
+        for record in &table.records {
+            let value = record.get(price);
+            let value: f64 = value
+                .parse()
+                .with_context(|| format!("Line {}: {:?} is not a price", record.line, value))?;
+            prices.insert(record.get(sku).to_uppercase(), value);
+        }
+        Ok(Self { prices })
+    }
+
+    pub fn monthly_price(&self, sku_part_number: &str) -> Option<f64> {
+        self.prices.get(&sku_part_number.to_uppercase()).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_known_and_unknown_skus() {
+        let catalog = SkuCatalog::from_skus(vec![
+            SubscribedSku {
+                sku_id: "6FD2C87F-B296-42F0-B197-1E91E994B900".to_string(),
+                sku_part_number: "ENTERPRISEPACK".to_string(),
+                consumed_units: 120,
+                prepaid_units: PrepaidUnits::default(),
+                capability_status: None,
+                service_plans: Vec::new(),
+            },
+            SubscribedSku {
+                sku_id: "0c266dff-15dd-4b49-8397-2bb16070ed52".to_string(),
+                sku_part_number: "MCOMEETADV".to_string(),
+                consumed_units: 4,
+                prepaid_units: PrepaidUnits::default(),
+                capability_status: None,
+                service_plans: Vec::new(),
+            },
+        ]);
+        assert_eq!(
+            catalog.display_name("6fd2c87f-b296-42f0-b197-1e91e994b900"),
+            "Office 365 E3 (ENTERPRISEPACK)"
+        );
+        assert_eq!(catalog.display_name("0c266dff-15dd-4b49-8397-2bb16070ed52"), "MCOMEETADV");
+        assert_eq!(
+            catalog.display_name("11111111-1111-1111-1111-111111111111"),
+            "11111111-1111-1111-1111-111111111111"
+        );
+    }
+
+    #[test]
+    fn parses_price_lists() {
+        let prices = PriceList::parse("\u{feff}SkuPartNumber,MonthlyPrice\n# list prices\nSPE_E3,36.00\nenterprisepack,23\n").unwrap();
+        assert_eq!(prices.monthly_price("spe_e3"), Some(36.0));
+        assert_eq!(prices.monthly_price("ENTERPRISEPACK"), Some(23.0));
+        assert_eq!(prices.monthly_price("SPE_E5"), None);
+        assert!(PriceList::parse("SkuPartNumber,MonthlyPrice\nSPE_E3,free\n").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::actionlog::{Change, Undo};
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+pub async fn search_messages(
+    client: &GraphClient,
+    upn: &str,
+    search: &str,
+    select: Option<&str>,
+) -> Result<Vec<Value>> {
+    let mut messages = client.user(upn).messages().search(search);
+    if let Some(select) = select {
+        messages = messages.select(select);
+    }
+    messages
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to search messages for {}", upn))
+}
+
+pub async fn mark_read(client: &GraphClient, upn: &str, message_id: &str) -> Result<()> {
+    let response = client
+        .user(upn)
+        .message(message_id)
+        .patch_json(&json!({ "isRead": true }))
+        .await?;
+    crate::client::ensure_success(response).await
+}
+
+pub async fn move_message(
+    client: &GraphClient,
+    upn: &str,
+    message_id: &str,
+    destination_id: &str,
+) -> Result<Value> {
+    let response = client
+        .user(upn)
+        .message(message_id)
+        .segment("move")
+        .post_json(&json!({ "destinationId": destination_id }))
+        .await?;
+    crate::client::parse_json(response).await
+}
+
+pub async fn list_inbox_rules(client: &GraphClient, upn: &str) -> Result<Vec<Value>> {
+    client
+        .user(upn)
+        .inbox_rules()
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list inbox rules for {}", upn))
+}
+
+// Turns on an automatic reply to every sender, internal and external.
+pub async fn set_automatic_replies(client: &GraphClient, upn: &str, message: &str) -> Result<()> {
+    let previous = mailbox_settings(client, upn).await?;
+    let setting = json!({
+        "status": "alwaysEnabled",
+        "externalAudience": "all",
+        "internalReplyMessage": message,
+        "externalReplyMessage": message,
+    });
+    update_automatic_replies(client, upn, &previous, setting).await
+}
+
+pub async fn mailbox_settings(client: &GraphClient, upn: &str) -> Result<Value> {
+    client
+        .user(upn)
+        .mailbox_settings()
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read the mailbox settings of {}", upn))
+}
+
+// PATCHes automaticRepliesSetting. `previous` is the mailbox settings read just before, so
+// that `mstools undo` can put the previous setting back.
+pub async fn update_automatic_replies(client: &GraphClient, upn: &str, previous: &Value, setting: Value) -> Result<()> {
+    let url = client.user(upn).mailbox_settings().url();
+    let body = json!({ "automaticRepliesSetting": setting });
+    let mut change = Change::new("PATCH", &url, Some(&body));
+    if let Some(setting) = previous.get("automaticRepliesSetting") {
+        let mut setting = setting.clone();
+        // Scheduled times are only accepted back when the status is "scheduled".
+        if setting["status"] != "scheduled" {
+            if let Some(setting) = setting.as_object_mut() {
+                setting.remove("scheduledStartDateTime");
+                setting.remove("scheduledEndDateTime");
+            }
+        }
+        change = change.with_undo(Undo {
+            method: "PATCH".to_string(),
+            url: url.clone(),
+            body: Some(json!({ "automaticRepliesSetting": setting })),
+        });
+    }
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to set the automatic reply of {}", upn))
+}
+
+// Adds an inbox rule that forwards every incoming message, and returns the rule id.
+pub async fn create_forwarding_rule(
+    client: &GraphClient,
+    upn: &str,
+    display_name: &str,
+    recipient: &str,
+) -> Result<String> {
+    let response = client
+        .user(upn)
+        .inbox_rules()
+        .post_json(&json!({
+            "displayName": display_name,
+            "sequence": 1,
+            "isEnabled": true,
+            "actions": {
+                "forwardTo": [{ "emailAddress": { "address": recipient } }],
+                "stopProcessingRules": false,
+            },
+        }))
+        .await?;
+    let rule: Value = crate::client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to create a forwarding rule for {}", upn))?;
+    rule["id"]
+        .as_str()
+        .map(str::to_string)
+        .context("The created inbox rule has no id")
+}
+
+// The forwarding actions of an inbox rule with their lowercase recipient addresses, for
+// example ("redirectTo", "drop@evil.example").
+pub fn forwarding_actions(rule: &Value) -> Vec<(&'static str, String)> {
+    ["forwardTo", "forwardAsAttachmentTo", "redirectTo"]
+        .iter()
+        .flat_map(|action| {
+            rule["actions"][*action]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|recipient| recipient["emailAddress"]["address"].as_str())
+                .map(|address| (*action, address.to_lowercase()))
+        })
+        .collect()
+}
+
+// Lowercase addresses an inbox rule forwards, forwards as attachment, or redirects to.
+pub fn forwarding_recipients(rule: &Value) -> Vec<String> {
+    forwarding_actions(rule).into_iter().map(|(_, address)| address).collect()
+}
+
+pub async fn mail_folder_name(client: &GraphClient, upn: &str, folder_id: &str) -> Result<String> {
+    let folder: Value = client
+        .user(upn)
+        .mail_folders()
+        .segment(folder_id)
+        .select("displayName")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to fetch mail folder {} for {}", folder_id, upn))?;
+    Ok(folder["displayName"].as_str().unwrap_or(folder_id).to_string())
+}
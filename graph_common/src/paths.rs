@@ -0,0 +1,224 @@
+use crate::client::GraphClient;
+use crate::encode;
+use anyhow::Result;
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+#[derive(Clone)]
+pub struct Resource<'a> {
+    client: &'a GraphClient,
+    path: String,
+    query: Vec<(String, String)>,
+}
+
+impl<'a> Resource<'a> {
+    pub(crate) fn new(client: &'a GraphClient) -> Self {
+        Self {
+            client,
+            path: String::new(),
+            query: Vec::new(),
+        }
+    }
+
+    pub fn segment(mut self, segment: &str) -> Self {
+        self.path.push('/');
+        self.path.push_str(&encode::path_segment(segment));
+        self
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn select(self, fields: &str) -> Self {
+        self.query("$select", fields)
+    }
+
+    pub fn filter(self, expression: &str) -> Self {
+        self.query("$filter", expression)
+    }
+
+    pub fn top(self, count: usize) -> Self {
+        self.query("$top", &count.to_string())
+    }
+
+    pub fn search(self, expression: &str) -> Self {
+        self.query("$search", &format!("\"{}\"", expression.replace('"', "\\\"")))
+    }
+
+    pub fn url(&self) -> String {
+        format!("{}{}", self.client.base_url(), self.relative_url())
+    }
+
+    // The path and query without the base URL, as used by requests inside a $batch.
+    pub fn relative_url(&self) -> String {
+        let mut url = self.path.clone();
+        for (index, (key, value)) in self.query.iter().enumerate() {
+            url.push(if index == 0 { '?' } else { '&' });
+            url.push_str(key);
+            url.push('=');
+            url.push_str(&encode::query_value(value));
+        }
+        url
+    }
+
+    pub async fn get(&self) -> Result<Response> {
+        self.client.get(&self.url()).await
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self) -> Result<T> {
+        self.client.get_json(&self.url()).await
+    }
+
+    pub async fn get_all<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.client.get_all(&self.url()).await
+    }
+
+    pub async fn for_each_page<T, F>(&self, on_page: F) -> Result<usize>
+    where
+        T: DeserializeOwned,
+        F: FnMut(Vec<T>) -> Result<()>,
+    {
+        self.client.for_each_page(&self.url(), on_page).await
+    }
+
+    pub async fn post_json(&self, body: &Value) -> Result<Response> {
+        self.client.post_json(&self.url(), body).await
+    }
+
+    pub async fn patch_json(&self, body: &Value) -> Result<Response> {
+        self.client.patch_json(&self.url(), body).await
+    }
+
+    pub async fn delete(&self) -> Result<Response> {
+        self.client.delete(&self.url()).await
+    }
+}
+
+pub struct UserResource<'a> {
+    resource: Resource<'a>,
+}
+
+impl<'a> UserResource<'a> {
+    pub fn resource(self) -> Resource<'a> {
+        self.resource
+    }
+
+    pub fn messages(self) -> Resource<'a> {
+        self.resource.segment("messages")
+    }
+
+    pub fn message(self, message_id: &str) -> Resource<'a> {
+        self.messages().segment(message_id)
+    }
+
+    pub fn mail_folders(self) -> Resource<'a> {
+        self.resource.segment("mailFolders")
+    }
+
+    pub fn inbox_rules(self) -> Resource<'a> {
+        self.mail_folders().segment("inbox").segment("messageRules")
+    }
+
+    pub fn mailbox_settings(self) -> Resource<'a> {
+        self.resource.segment("mailboxSettings")
+    }
+
+    pub fn calendar_permissions(self) -> Resource<'a> {
+        self.resource.segment("calendar").segment("calendarPermissions")
+    }
+
+    pub fn authentication_methods(self) -> Resource<'a> {
+        self.resource.segment("authentication").segment("methods")
+    }
+
+    pub fn member_of(self) -> Resource<'a> {
+        self.resource.segment("memberOf")
+    }
+
+    pub fn sponsors(self) -> Resource<'a> {
+        self.resource.segment("sponsors")
+    }
+
+    pub fn revoke_sign_in_sessions(self) -> Resource<'a> {
+        self.resource.segment("revokeSignInSessions")
+    }
+
+    pub fn send_mail(self) -> Resource<'a> {
+        self.resource.segment("sendMail")
+    }
+}
+
+pub struct DirectoryRolesResource<'a> {
+    resource: Resource<'a>,
+}
+
+impl<'a> DirectoryRolesResource<'a> {
+    pub fn resource(self) -> Resource<'a> {
+        self.resource
+    }
+
+    pub fn role(self, role_id: &str) -> Resource<'a> {
+        self.resource.segment(role_id)
+    }
+
+    pub fn members(self, role_id: &str) -> Resource<'a> {
+        self.role(role_id).segment("members")
+    }
+}
+
+impl GraphClient {
+    pub fn path(&self, segments: &[&str]) -> Resource<'_> {
+        segments
+            .iter()
+            .fold(Resource::new(self), |resource, segment| {
+                resource.segment(segment)
+            })
+    }
+
+    pub fn users(&self) -> Resource<'_> {
+        self.path(&["users"])
+    }
+
+    pub fn user(&self, id_or_upn: &str) -> UserResource<'_> {
+        UserResource {
+            resource: self.users().segment(id_or_upn),
+        }
+    }
+
+    pub fn directory_roles(&self) -> DirectoryRolesResource<'_> {
+        DirectoryRolesResource {
+            resource: self.path(&["directoryRoles"]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_encoded_user_message_search_url() {
+        let client = GraphClient::new(String::new());
+        let url = client
+            .user("jane_fabrikam.com#EXT#@contoso.onmicrosoft.com")
+            .messages()
+            .search("subject:O'Brien")
+            .url();
+        assert_eq!(
+            url,
+            "https://graph.microsoft.com/v1.0/users/jane_fabrikam.com%23EXT%23@contoso.onmicrosoft.com/messages?$search=%22subject:O%27Brien%22"
+        );
+    }
+
+    #[test]
+    fn builds_directory_role_members_url() {
+        let client = GraphClient::new(String::new());
+        assert_eq!(
+            client.directory_roles().members("role-id").url(),
+            "https://graph.microsoft.com/v1.0/directoryRoles/role-id/members"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::debug;
+use serde::Deserialize;
+use std::env;
+
+// Broader permissions that include narrower ones, beyond the general rule that
+// X.ReadWrite.Y includes X.Read.Y.
+const IMPLIED: &[(&str, &[&str])] = &[
+    (
+        "Directory.Read.All",
+        &["User.Read.All", "User.ReadBasic.All", "Group.Read.All", "GroupMember.Read.All", "Domain.Read.All", "Application.Read.All", "RoleManagement.Read.Directory", "Organization.Read.All"],
+    ),
+    (
+        "Directory.ReadWrite.All",
+        &["User.ReadWrite.All", "Group.ReadWrite.All", "GroupMember.ReadWrite.All", "Directory.Read.All"],
+    ),
+    ("User.Read.All", &["User.ReadBasic.All"]),
+    ("Group.Read.All", &["GroupMember.Read.All"]),
+    ("Group.ReadWrite.All", &["GroupMember.ReadWrite.All"]),
+    ("Mail.Read", &["Mail.ReadBasic.All", "Mail.ReadBasic"]),
+    ("Mail.ReadWrite", &["Mail.Read"]),
+    ("Application.ReadWrite.All", &["Application.ReadWrite.OwnedBy"]),
+    ("BitlockerKey.Read.All", &["BitlockerKey.ReadBasic.All"]),
+];
+
+#[derive(Deserialize, Debug)]
+struct Claims {
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    scp: Option<String>,
+}
+
+// Application tokens list their permissions in `roles`; delegated tokens use the
+// space-separated `scp` claim instead.
+fn claims(access_token: &str) -> Result<Claims> {
+    let payload = access_token
+        .split('.')
+        .nth(1)
+        .context("The access token is not a JWT")?;
+    let decoded = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("Failed to decode the access token")?;
+    serde_json::from_slice(&decoded).context("Failed to parse the access token claims")
+}
+
+pub fn granted(access_token: &str) -> Result<Vec<String>> {
+    let claims = claims(access_token)?;
+    let mut granted = claims.roles;
+    if let Some(scp) = claims.scp {
+        granted.extend(scp.split_whitespace().map(str::to_string));
+    }
+    Ok(granted)
+}
+
+fn includes(granted: &str, required: &str) -> bool {
+    granted.eq_ignore_ascii_case(required)
+        || granted.eq_ignore_ascii_case(&required.replacen(".Read.", ".ReadWrite.", 1))
+        || (required.ends_with(".Read") && granted.eq_ignore_ascii_case(&format!("{}Write", required)))
+        || IMPLIED.iter().any(|(broader, narrower)| {
+            granted.eq_ignore_ascii_case(broader)
+                && narrower.iter().any(|narrower| includes(narrower, required))
+        })
+}
+
+// A requirement may list alternatives separated by '|', any one of which is enough.
+pub fn missing<'a>(granted: &[String], required: &[&'a str]) -> Vec<&'a str> {
+    required
+        .iter()
+        .filter(|requirement| {
+            !requirement
+                .split('|')
+                .any(|alternative| granted.iter().any(|granted| includes(granted, alternative)))
+        })
+        .copied()
+        .collect()
+}
+
+// Fails before any request is made when the token lacks a permission the tool needs, instead
+// of a 403 part way through a run. MSTOOLS_SKIP_PERMISSION_CHECK=1 skips the check for
+// tenants that grant access some other way, such as Exchange RBAC for applications.
+pub fn check(access_token: &str, required: &[&str]) -> Result<()> {
+    if env::var("MSTOOLS_SKIP_PERMISSION_CHECK").is_ok_and(|value| value == "1") {
+        debug!("Skipping the permission check");
+        return Ok(());
+    }
+    let granted = granted(access_token)?;
+    debug!("Token permissions: {}", granted.join(", "));
+    let missing = missing(&granted, required);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let kind = if claims(access_token)?.scp.is_some() {
+        "delegated"
+    } else {
+        "application"
+    };
+    Err(anyhow::anyhow!(
+        "The app registration is missing Microsoft Graph permissions: {}. Add them as {} permissions and grant admin consent, then run again",
+        missing
+            .iter()
+            .map(|requirement| requirement.replace('|', " or "))
+            .collect::<Vec<_>>()
+            .join(", "),
+        kind
+    ))
+}
+
+impl GraphClient {
+    pub fn require_permissions(&self, required: &[&str]) -> Result<()> {
+        check(self.access_token(), required)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_roles_and_reports_missing_permissions() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"roles":["User.ReadWrite.All","Directory.Read.All","Mail.ReadWrite"]}"#);
+        let token = format!("eyJhbGciOiJub25lIn0.{}.signature", claims);
+        let granted = granted(&token).unwrap();
+        assert_eq!(granted.len(), 3);
+
+        let required = [
+            "User.Read.All",
+            "GroupMember.Read.All",
+            "Mail.Read",
+            "MailboxSettings.Read",
+            "Application.ReadWrite.OwnedBy|Application.ReadWrite.All",
+        ];
+        assert_eq!(
+            missing(&granted, &required),
+            vec!["MailboxSettings.Read", "Application.ReadWrite.OwnedBy|Application.ReadWrite.All"]
+        );
+        assert!(check("not-a-token", &required).is_err());
+    }
+
+    #[test]
+    fn reads_delegated_scopes() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"scp":"BitlockerKey.Read.All Device.Read.All"}"#);
+        let token = format!("eyJhbGciOiJub25lIn0.{}.signature", claims);
+        assert!(missing(&granted(&token).unwrap(), &["BitlockerKey.ReadBasic.All", "Device.Read.All"]).is_empty());
+
+        let error = check(&token, &["DeviceManagementManagedDevices.Read.All"]).unwrap_err();
+        assert!(error.to_string().contains("as delegated permissions"));
+    }
+}
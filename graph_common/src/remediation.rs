@@ -0,0 +1,427 @@
+use crate::actionlog::{Change, Undo};
+use crate::client::GRAPH_BASE_URL;
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// (short name, @odata.type, endpoint used to delete the method)
+pub const AUTHENTICATION_METHOD_TYPES: &[(&str, &str, &str)] = &[
+    ("password", "#microsoft.graph.passwordAuthenticationMethod", "passwordMethods"),
+    ("softwareoath", "#microsoft.graph.softwareOathAuthenticationMethod", "softwareOathMethods"),
+    ("authenticator", "#microsoft.graph.microsoftAuthenticatorAuthenticationMethod", "microsoftAuthenticatorMethods"),
+    ("phone", "#microsoft.graph.phoneAuthenticationMethod", "phoneMethods"),
+    ("fido2", "#microsoft.graph.fido2AuthenticationMethod", "fido2Methods"),
+    ("email", "#microsoft.graph.emailAuthenticationMethod", "emailMethods"),
+    ("windowshello", "#microsoft.graph.windowsHelloForBusinessAuthenticationMethod", "windowsHelloForBusinessMethods"),
+    ("tap", "#microsoft.graph.temporaryAccessPassAuthenticationMethod", "temporaryAccessPassMethods"),
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthenticationMethod {
+    pub id: String,
+    #[serde(rename = "@odata.type")]
+    pub odata_type: String,
+    #[serde(flatten)]
+    pub properties: serde_json::Map<String, Value>,
+}
+
+impl AuthenticationMethod {
+    pub fn short_name(&self) -> Option<&'static str> {
+        AUTHENTICATION_METHOD_TYPES
+            .iter()
+            .find(|(_, odata_type, _)| *odata_type == self.odata_type)
+            .map(|(name, _, _)| *name)
+    }
+
+    fn endpoint(&self) -> Option<&'static str> {
+        AUTHENTICATION_METHOD_TYPES
+            .iter()
+            .find(|(_, odata_type, _)| *odata_type == self.odata_type)
+            .map(|(_, _, endpoint)| *endpoint)
+    }
+}
+
+pub async fn revoke_sign_in_sessions(client: &GraphClient, upn: &str) -> Result<()> {
+    let response = client
+        .user(upn)
+        .revoke_sign_in_sessions()
+        .post_json(&json!({}))
+        .await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to revoke sign-in sessions for {}", upn))
+}
+
+pub async fn list_authentication_methods(
+    client: &GraphClient,
+    upn: &str,
+) -> Result<Vec<AuthenticationMethod>> {
+    client
+        .user(upn)
+        .authentication_methods()
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list authentication methods for {}", upn))
+}
+
+pub async fn delete_authentication_method(
+    client: &GraphClient,
+    upn: &str,
+    method: &AuthenticationMethod,
+) -> Result<()> {
+    let endpoint = match method.endpoint() {
+        Some("passwordMethods") | None => {
+            return Err(anyhow::anyhow!(
+                "Authentication method type {} cannot be deleted",
+                method.odata_type
+            ))
+        }
+        Some(endpoint) => endpoint,
+    };
+    let response = client
+        .user(upn)
+        .resource()
+        .segment("authentication")
+        .segment(endpoint)
+        .segment(&method.id)
+        .delete()
+        .await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to delete authentication method {} for {}", method.id, upn))
+}
+
+#[derive(Deserialize, Debug)]
+struct AccountState {
+    #[serde(rename = "accountEnabled")]
+    account_enabled: Option<bool>,
+}
+
+// The previous value is read first so that `mstools undo` can put it back.
+pub async fn set_account_enabled(client: &GraphClient, upn: &str, enabled: bool) -> Result<()> {
+    let resource = client.user(upn).resource();
+    let url = resource.url();
+    let previous: AccountState = resource
+        .clone()
+        .select("accountEnabled")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read accountEnabled for {}", upn))?;
+    let body = json!({ "accountEnabled": enabled });
+    let mut change = Change::new("PATCH", &url, Some(&body));
+    if let Some(previous) = previous.account_enabled {
+        change = change.with_undo(Undo {
+            method: "PATCH".to_string(),
+            url: url.clone(),
+            body: Some(json!({ "accountEnabled": previous })),
+        });
+    }
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to set accountEnabled={} for {}", enabled, upn))
+}
+
+#[derive(Deserialize, Debug)]
+struct AssignedLicenses {
+    #[serde(rename = "assignedLicenses", default)]
+    assigned_licenses: Vec<AssignedLicense>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AssignedLicense {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+    #[serde(rename = "disabledPlans", default)]
+    disabled_plans: Vec<String>,
+}
+
+// Only licenses the user actually holds are removed, and their disabled plans are kept in
+// the undo so a restore does not enable service plans that were switched off.
+pub async fn remove_licenses(client: &GraphClient, upn: &str, sku_ids: &[String]) -> Result<()> {
+    let current: AssignedLicenses = client
+        .user(upn)
+        .resource()
+        .select("assignedLicenses")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read the licenses of {}", upn))?;
+    let removed: Vec<AssignedLicense> = current
+        .assigned_licenses
+        .into_iter()
+        .filter(|license| sku_ids.iter().any(|sku| sku.eq_ignore_ascii_case(&license.sku_id)))
+        .collect();
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    let url = format!("{}/assignLicense", client.user(upn).resource().url());
+    let body = json!({
+        "addLicenses": [],
+        "removeLicenses": removed.iter().map(|license| &license.sku_id).collect::<Vec<_>>(),
+    });
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "POST".to_string(),
+        url: url.clone(),
+        body: Some(json!({ "addLicenses": removed, "removeLicenses": [] })),
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to remove licenses from {}", upn))
+}
+
+pub async fn assign_licenses(client: &GraphClient, upn: &str, sku_ids: &[String]) -> Result<()> {
+    if sku_ids.is_empty() {
+        return Ok(());
+    }
+    let url = format!("{}/assignLicense", client.user(upn).resource().url());
+    let body = json!({
+        "addLicenses": sku_ids
+            .iter()
+            .map(|sku_id| json!({ "skuId": sku_id, "disabledPlans": [] }))
+            .collect::<Vec<_>>(),
+        "removeLicenses": [],
+    });
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "POST".to_string(),
+        url: url.clone(),
+        body: Some(json!({ "addLicenses": [], "removeLicenses": sku_ids })),
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to assign licenses to {}", upn))
+}
+
+pub async fn add_group_member(client: &GraphClient, group_id: &str, member_id: &str) -> Result<()> {
+    let members = client.path(&["groups", group_id, "members"]).url();
+    let body = json!({
+        "@odata.id": format!("{}/directoryObjects/{}", GRAPH_BASE_URL, member_id),
+    });
+    let url = format!("{}/$ref", members);
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "DELETE".to_string(),
+        url: format!("{}/{}/$ref", members, crate::encode::path_segment(member_id)),
+        body: None,
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to add {} to group {}", member_id, group_id))
+}
+
+// `$ref` is appended after encoding because path segments escape the dollar sign.
+pub async fn remove_group_member(client: &GraphClient, group_id: &str, member_id: &str) -> Result<()> {
+    let members = client.path(&["groups", group_id, "members"]).url();
+    let url = format!("{}/{}/$ref", members, crate::encode::path_segment(member_id));
+    let change = Change::new("DELETE", &url, None).with_undo(Undo {
+        method: "POST".to_string(),
+        url: format!("{}/$ref", members),
+        body: Some(json!({
+            "@odata.id": format!("{}/directoryObjects/{}", GRAPH_BASE_URL, member_id),
+        })),
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to remove {} from group {}", member_id, group_id))
+}
+
+pub async fn add_group_owner(client: &GraphClient, group_id: &str, owner_id: &str) -> Result<()> {
+    let owners = client.path(&["groups", group_id, "owners"]).url();
+    let body = json!({
+        "@odata.id": format!("{}/directoryObjects/{}", GRAPH_BASE_URL, owner_id),
+    });
+    let url = format!("{}/$ref", owners);
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "DELETE".to_string(),
+        url: format!("{}/{}/$ref", owners, crate::encode::path_segment(owner_id)),
+        body: None,
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to add {} as an owner of group {}", owner_id, group_id))
+}
+
+pub async fn remove_group_owner(client: &GraphClient, group_id: &str, owner_id: &str) -> Result<()> {
+    let owners = client.path(&["groups", group_id, "owners"]).url();
+    let url = format!("{}/{}/$ref", owners, crate::encode::path_segment(owner_id));
+    let change = Change::new("DELETE", &url, None).with_undo(Undo {
+        method: "POST".to_string(),
+        url: format!("{}/$ref", owners),
+        body: Some(json!({
+            "@odata.id": format!("{}/directoryObjects/{}", GRAPH_BASE_URL, owner_id),
+        })),
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to remove {} as an owner of group {}", owner_id, group_id))
+}
+
+// `id` is the directory object id of the device, not its deviceId. The previous value is read
+// first so that `mstools undo` can put it back.
+pub async fn set_device_enabled(client: &GraphClient, id: &str, enabled: bool) -> Result<()> {
+    let resource = client.path(&["devices", id]);
+    let url = resource.url();
+    let previous: AccountState = resource
+        .clone()
+        .select("accountEnabled")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read accountEnabled for device {}", id))?;
+    let body = json!({ "accountEnabled": enabled });
+    let mut change = Change::new("PATCH", &url, Some(&body));
+    if let Some(previous) = previous.account_enabled {
+        change = change.with_undo(Undo {
+            method: "PATCH".to_string(),
+            url: url.clone(),
+            body: Some(json!({ "accountEnabled": previous })),
+        });
+    }
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to set accountEnabled={} for device {}", enabled, id))
+}
+
+// Deleted devices are not kept in the directory's deleted items, so a deletion has no undo.
+pub async fn delete_device(client: &GraphClient, id: &str) -> Result<()> {
+    let url = client.path(&["devices", id]).url();
+    let response = client.send_change(Change::new("DELETE", &url, None)).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to delete device {}", id))
+}
+
+// A removed sharing link cannot be restored; a new link gets a different URL, so a deletion has
+// no undo.
+pub async fn delete_drive_permission(client: &GraphClient, drive_id: &str, item_id: &str, permission_id: &str) -> Result<()> {
+    let url = client
+        .path(&["drives", drive_id, "items", item_id, "permissions", permission_id])
+        .url();
+    let response = client.send_change(Change::new("DELETE", &url, None)).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to delete permission {} of item {}", permission_id, item_id))
+}
+
+// Identity Protection cannot put a dismissed risk back, so a dismissal has no undo.
+pub async fn dismiss_user_risk(client: &GraphClient, user_id: &str) -> Result<()> {
+    let url = client.path(&["identityProtection", "riskyUsers", "dismiss"]).url();
+    let body = json!({ "userIds": [user_id] });
+    let response = client.send_change(Change::new("POST", &url, Some(&body))).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to dismiss the risk of user {}", user_id))
+}
+
+// Confirming a compromise sets the user risk to high; dismissing the risk reverses it.
+pub async fn confirm_user_compromised(client: &GraphClient, user_id: &str) -> Result<()> {
+    let url = client.path(&["identityProtection", "riskyUsers", "confirmCompromised"]).url();
+    let body = json!({ "userIds": [user_id] });
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "POST".to_string(),
+        url: client.path(&["identityProtection", "riskyUsers", "dismiss"]).url(),
+        body: Some(body.clone()),
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to confirm user {} as compromised", user_id))
+}
+
+// Deleted users stay in the directory's deleted items for 30 days, so the undo restores them.
+pub async fn delete_user(client: &GraphClient, user_id: &str) -> Result<()> {
+    let url = client.user(user_id).resource().url();
+    let change = Change::new("DELETE", &url, None).with_undo(Undo {
+        method: "POST".to_string(),
+        url: client.path(&["directory", "deletedItems", user_id, "restore"]).url(),
+        body: None,
+    });
+    let response = client.send_change(change).await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to delete user {}", user_id))
+}
+
+pub async fn reset_password(
+    client: &GraphClient,
+    upn: &str,
+    password: &str,
+    force_change: bool,
+) -> Result<()> {
+    let response = client
+        .user(upn)
+        .resource()
+        .patch_json(&json!({
+            "passwordProfile": {
+                "password": password,
+                "forceChangePasswordNextSignIn": force_change,
+            }
+        }))
+        .await?;
+    crate::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to reset the password for {}", upn))
+}
+
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub uppercase: bool,
+    pub lowercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 24,
+            uppercase: true,
+            lowercase: true,
+            digits: true,
+            symbols: true,
+        }
+    }
+}
+
+// Entra ID accepts 8 to 256 characters drawn from at least three of the four classes.
+pub fn generate_password(policy: &PasswordPolicy) -> Result<String> {
+    let classes: Vec<&[u8]> = [
+        (policy.uppercase, &b"ABCDEFGHJKLMNPQRSTUVWXYZ"[..]),
+        (policy.lowercase, &b"abcdefghijkmnopqrstuvwxyz"[..]),
+        (policy.digits, &b"23456789"[..]),
+        (policy.symbols, &b"!@#$%^&*-_=+?"[..]),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, class)| class)
+    .collect();
+    if classes.len() < 3 {
+        return Err(anyhow::anyhow!(
+            "Passwords must use at least three of uppercase, lowercase, digits, and symbols"
+        ));
+    }
+    if !(8..=256).contains(&policy.length) {
+        return Err(anyhow::anyhow!("Password length must be between 8 and 256"));
+    }
+
+    // One character from every enabled class, then the rest from all of them, shuffled.
+    let mut rng = rand::thread_rng();
+    let mut password: Vec<u8> = classes
+        .iter()
+        .map(|class| class[rng.gen_range(0..class.len())])
+        .collect();
+    let all: Vec<u8> = classes.concat();
+    while password.len() < policy.length {
+        password.push(all[rng.gen_range(0..all.len())]);
+    }
+    password.shuffle(&mut rng);
+    Ok(String::from_utf8(password).expect("password characters are ASCII"))
+}
@@ -0,0 +1,447 @@
+use crate::csv::Table;
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+
+// Every mailbox in the mailbox usage detail report, keyed by lowercase UPN, with its recipient
+// type, size, and quotas. One report request replaces a mailboxSettings request per user when
+// finding shared mailboxes. Needs Reports.Read.All. The report lags behind the directory by a
+// day or two, and tenants that conceal user names in reports get hashes instead of UPNs.
+pub struct MailboxUsage {
+    mailboxes: HashMap<String, Mailbox>,
+}
+
+// Sizes and quotas are in bytes. Columns missing from the report are None.
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    pub user_principal_name: String,
+    pub display_name: String,
+    pub recipient_type: String,
+    pub last_activity: Option<NaiveDate>,
+    pub item_count: Option<u64>,
+    pub storage_used: Option<u64>,
+    pub issue_warning_quota: Option<u64>,
+    pub prohibit_send_quota: Option<u64>,
+    pub prohibit_send_receive_quota: Option<u64>,
+    pub has_archive: Option<bool>,
+}
+
+impl MailboxUsage {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        // The function call syntax of the report path is not a plain segment, so it is built
+        // by hand rather than with client.path().
+        let url = format!("{}/reports/getMailboxUsageDetail(period='D7')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the mailbox usage detail report")?;
+        Self::parse(&report)
+    }
+
+    pub fn parse(report: &str) -> Result<Self> {
+        let table = Table::parse(report.trim_start_matches('\u{feff}'))?;
+        let upn = table
+            .column(&["User Principal Name"])
+            .context("The mailbox usage report has no User Principal Name column")?;
+        let recipient_type = table
+            .column(&["Recipient Type"])
+            .context("The mailbox usage report has no Recipient Type column")?;
+        let deleted = table.column(&["Is Deleted"]);
+        let display_name = table.column(&["Display Name"]);
+        let last_activity = table.column(&["Last Activity Date"]);
+        let item_count = table.column(&["Item Count"]);
+        let storage_used = table.column(&["Storage Used (Byte)"]);
+        let issue_warning_quota = table.column(&["Issue Warning Quota (Byte)"]);
+        let prohibit_send_quota = table.column(&["Prohibit Send Quota (Byte)"]);
+        let prohibit_send_receive_quota = table.column(&["Prohibit Send/Receive Quota (Byte)"]);
+        let has_archive = table.column(&["Has Archive"]);
+        Ok(Self {
+            mailboxes: table
+                .records
+                .iter()
+                .filter(|record| deleted.is_none_or(|column| !record.get(column).eq_ignore_ascii_case("true")))
+                .filter(|record| !record.get(upn).is_empty() && !record.get(recipient_type).is_empty())
+                .map(|record| {
+                    let number = |column: Option<usize>| column.and_then(|column| record.get(column).parse().ok());
+                    let mailbox = Mailbox {
+                        user_principal_name: record.get(upn).to_string(),
+                        display_name: display_name.map(|column| record.get(column).to_string()).unwrap_or_default(),
+                        recipient_type: record.get(recipient_type).to_string(),
+                        last_activity: last_activity
+                            .and_then(|column| NaiveDate::parse_from_str(record.get(column), "%Y-%m-%d").ok()),
+                        item_count: number(item_count),
+                        storage_used: number(storage_used),
+                        issue_warning_quota: number(issue_warning_quota),
+                        prohibit_send_quota: number(prohibit_send_quota),
+                        prohibit_send_receive_quota: number(prohibit_send_receive_quota),
+                        has_archive: has_archive.and_then(|column| match record.get(column).to_lowercase().as_str() {
+                            "true" => Some(true),
+                            "false" => Some(false),
+                            _ => None,
+                        }),
+                    };
+                    (record.get(upn).to_lowercase(), mailbox)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.mailboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mailboxes.is_empty()
+    }
+
+    pub fn mailboxes(&self) -> impl Iterator<Item = &Mailbox> {
+        self.mailboxes.values()
+    }
+
+    // None when the mailbox is not in the report, so only those need a mailboxSettings lookup.
+    pub fn is_shared(&self, upn: &str) -> Option<bool> {
+        self.mailboxes
+            .get(&upn.to_lowercase())
+            .map(|mailbox| mailbox.recipient_type.eq_ignore_ascii_case("shared"))
+    }
+}
+
+// The latest date each user was active in any Microsoft 365 app (Exchange, OneDrive,
+// SharePoint, Teams, Yammer, Skype for Business) from the active user detail report, keyed by
+// lowercase UPN. Needs Reports.Read.All, with the same lag and concealed names as MailboxUsage.
+pub struct OfficeActivity {
+    last_activity: HashMap<String, Option<NaiveDate>>,
+}
+
+impl OfficeActivity {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        let url = format!("{}/reports/getOffice365ActiveUserDetail(period='D180')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the Microsoft 365 active user detail report")?;
+        Self::parse(&report)
+    }
+
+    pub fn parse(report: &str) -> Result<Self> {
+        let table = Table::parse(report.trim_start_matches('\u{feff}'))?;
+        let upn = table
+            .column(&["User Principal Name"])
+            .context("The active user report has no User Principal Name column")?;
+        let deleted = table.column(&["Is Deleted"]);
+        let activity_columns: Vec<usize> = (0..table.header.len())
+            .filter(|column| table.header[*column].ends_with("Last Activity Date"))
+            .collect();
+        Ok(Self {
+            last_activity: table
+                .records
+                .iter()
+                .filter(|record| deleted.is_none_or(|column| !record.get(column).eq_ignore_ascii_case("true")))
+                .filter(|record| !record.get(upn).is_empty())
+                .map(|record| {
+                    let last = activity_columns
+                        .iter()
+                        .filter_map(|column| NaiveDate::parse_from_str(record.get(*column), "%Y-%m-%d").ok())
+                        .max();
+                    (record.get(upn).to_lowercase(), last)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_activity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_activity.is_empty()
+    }
+
+    pub fn contains(&self, upn: &str) -> bool {
+        self.last_activity.contains_key(&upn.to_lowercase())
+    }
+
+    // None when the user is not in the report or was never active.
+    pub fn last_activity(&self, upn: &str) -> Option<NaiveDate> {
+        self.last_activity.get(&upn.to_lowercase()).copied().flatten()
+    }
+}
+
+// The last activity date of every Microsoft 365 group (mail received, files, Teams, Yammer)
+// from the groups activity detail report, keyed by lowercase group id. Needs Reports.Read.All;
+// security groups and distribution lists are not in the report.
+pub struct GroupActivity {
+    last_activity: HashMap<String, Option<NaiveDate>>,
+}
+
+impl GroupActivity {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        let url = format!("{}/reports/getOffice365GroupsActivityDetail(period='D180')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the Microsoft 365 groups activity detail report")?;
+        Self::parse(&report)
+    }
+
+    pub fn parse(report: &str) -> Result<Self> {
+        Ok(Self {
+            last_activity: last_activity_by_id(report, "Group Id", "groups activity report")?,
+        })
+    }
+
+    pub fn contains(&self, group_id: &str) -> bool {
+        self.last_activity.contains_key(&group_id.to_lowercase())
+    }
+
+    // None when the group is not in the report or had no activity in the report period.
+    pub fn last_activity(&self, group_id: &str) -> Option<NaiveDate> {
+        self.last_activity.get(&group_id.to_lowercase()).copied().flatten()
+    }
+}
+
+// The team activity detail report, like the groups one, keyed by lowercase team (group) id.
+// Teams with no chat, channel, or meeting activity in the period have no Last Activity Date.
+pub struct TeamActivity {
+    last_activity: HashMap<String, Option<NaiveDate>>,
+}
+
+impl TeamActivity {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        let url = format!("{}/reports/getTeamsTeamActivityDetail(period='D180')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the Teams team activity detail report")?;
+        Self::parse(&report)
+    }
+
+    pub fn parse(report: &str) -> Result<Self> {
+        Ok(Self {
+            last_activity: last_activity_by_id(report, "Team Id", "team activity report")?,
+        })
+    }
+
+    pub fn contains(&self, team_id: &str) -> bool {
+        self.last_activity.contains_key(&team_id.to_lowercase())
+    }
+
+    // None when the team is not in the report or had no activity in the report period.
+    pub fn last_activity(&self, team_id: &str) -> Option<NaiveDate> {
+        self.last_activity.get(&team_id.to_lowercase()).copied().flatten()
+    }
+}
+
+// Every SharePoint site in the site usage detail report, or every OneDrive in the OneDrive usage
+// account detail report, with its storage, file counts, and last activity. The two reports share
+// their columns. Needs Reports.Read.All. The reports have no site names, and tenants that
+// conceal names in reports get an empty Site URL and owner.
+pub struct SiteUsage {
+    sites: Vec<Site>,
+}
+
+// Storage is in bytes. Columns missing from the report are None.
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub site_id: String,
+    pub url: String,
+    pub owner_display_name: String,
+    pub owner_principal_name: String,
+    pub template: String,
+    pub last_activity: Option<NaiveDate>,
+    pub file_count: Option<u64>,
+    pub active_file_count: Option<u64>,
+    pub page_view_count: Option<u64>,
+    pub storage_used: Option<u64>,
+    pub storage_allocated: Option<u64>,
+}
+
+impl SiteUsage {
+    pub async fn load(client: &GraphClient) -> Result<Self> {
+        let url = format!("{}/reports/getSharePointSiteUsageDetail(period='D7')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the SharePoint site usage detail report")?;
+        Self::parse(&report)
+    }
+
+    pub async fn load_onedrive(client: &GraphClient) -> Result<Self> {
+        let url = format!("{}/reports/getOneDriveUsageAccountDetail(period='D7')", client.base_url());
+        let report = client
+            .get_text(&url)
+            .await
+            .context("Failed to fetch the OneDrive usage account detail report")?;
+        Self::parse(&report)
+    }
+
+    pub fn parse(report: &str) -> Result<Self> {
+        let table = Table::parse(report.trim_start_matches('\u{feff}'))?;
+        let site_id = table
+            .column(&["Site Id"])
+            .context("The site usage report has no Site Id column")?;
+        let deleted = table.column(&["Is Deleted"]);
+        let url = table.column(&["Site URL"]);
+        let owner_display_name = table.column(&["Owner Display Name"]);
+        let owner_principal_name = table.column(&["Owner Principal Name"]);
+        let template = table.column(&["Root Web Template"]);
+        let last_activity = table.column(&["Last Activity Date"]);
+        let file_count = table.column(&["File Count"]);
+        let active_file_count = table.column(&["Active File Count"]);
+        let page_view_count = table.column(&["Page View Count"]);
+        let storage_used = table.column(&["Storage Used (Byte)"]);
+        let storage_allocated = table.column(&["Storage Allocated (Byte)"]);
+        Ok(Self {
+            sites: table
+                .records
+                .iter()
+                .filter(|record| deleted.is_none_or(|column| !record.get(column).eq_ignore_ascii_case("true")))
+                .filter(|record| !record.get(site_id).is_empty())
+                .map(|record| {
+                    let text = |column: Option<usize>| column.map(|column| record.get(column).to_string()).unwrap_or_default();
+                    let number = |column: Option<usize>| column.and_then(|column| record.get(column).parse().ok());
+                    Site {
+                        site_id: record.get(site_id).to_lowercase(),
+                        url: text(url),
+                        owner_display_name: text(owner_display_name),
+                        owner_principal_name: text(owner_principal_name),
+                        template: text(template),
+                        last_activity: last_activity
+                            .and_then(|column| NaiveDate::parse_from_str(record.get(column), "%Y-%m-%d").ok()),
+                        file_count: number(file_count),
+                        active_file_count: number(active_file_count),
+                        page_view_count: number(page_view_count),
+                        storage_used: number(storage_used),
+                        storage_allocated: number(storage_allocated),
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = &Site> {
+        self.sites.iter()
+    }
+}
+
+fn last_activity_by_id(report: &str, id_column: &str, name: &str) -> Result<HashMap<String, Option<NaiveDate>>> {
+    let table = Table::parse(report.trim_start_matches('\u{feff}'))?;
+    let id = table
+        .column(&[id_column])
+        .with_context(|| format!("The {} has no {} column", name, id_column))?;
+    let last = table
+        .column(&["Last Activity Date"])
+        .with_context(|| format!("The {} has no Last Activity Date column", name))?;
+    let deleted = table.column(&["Is Deleted"]);
+    Ok(table
+        .records
+        .iter()
+        .filter(|record| deleted.is_none_or(|column| !record.get(column).eq_ignore_ascii_case("true")))
+        .filter(|record| !record.get(id).is_empty())
+        .map(|record| {
+            (
+                record.get(id).to_lowercase(),
+                NaiveDate::parse_from_str(record.get(last), "%Y-%m-%d").ok(),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_mailboxes_by_recipient_type() {
+        let usage = MailboxUsage::parse(concat!(
+            "\u{feff}Report Refresh Date,User Principal Name,Display Name,Is Deleted,Recipient Type,Report Period\n",
+            "2026-10-13,Helpdesk@contoso.com,Helpdesk,False,Shared,7\n",
+            "2026-10-13,jane.doe@contoso.com,Jane Doe,False,User,7\n",
+            "2026-10-13,old-shared@contoso.com,Old Shared,True,Shared,7\n",
+            "2026-10-13,room1@contoso.com,\"Room 1, Level 2\",False,,7\n",
+        ))
+        .unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage.is_shared("helpdesk@contoso.com"), Some(true));
+        assert_eq!(usage.is_shared("jane.doe@contoso.com"), Some(false));
+        assert_eq!(usage.is_shared("old-shared@contoso.com"), None);
+        assert_eq!(usage.is_shared("room1@contoso.com"), None);
+    }
+
+    #[test]
+    fn reads_mailbox_sizes_and_quotas() {
+        let usage = MailboxUsage::parse(concat!(
+            "\u{feff}Report Refresh Date,User Principal Name,Display Name,Is Deleted,Last Activity Date,Item Count,Storage Used (Byte),Issue Warning Quota (Byte),Prohibit Send Quota (Byte),Prohibit Send/Receive Quota (Byte),Has Archive,Recipient Type,Report Period\n",
+            "2026-10-13,jane.doe@contoso.com,Jane Doe,False,2026-10-12,48211,49392123904,105226698752,106300440576,107374182400,True,User,7\n",
+            "2026-10-13,new.hire@contoso.com,New Hire,False,,0,,,,,False,User,7\n",
+        ))
+        .unwrap();
+        let mut mailboxes: Vec<&Mailbox> = usage.mailboxes().collect();
+        mailboxes.sort_by_key(|mailbox| mailbox.user_principal_name.clone());
+        assert_eq!(mailboxes[0].storage_used, Some(49392123904));
+        assert_eq!(mailboxes[0].prohibit_send_quota, Some(106300440576));
+        assert_eq!(mailboxes[0].has_archive, Some(true));
+        assert_eq!(mailboxes[0].last_activity, NaiveDate::from_ymd_opt(2026, 10, 12));
+        assert_eq!((mailboxes[1].item_count, mailboxes[1].storage_used), (Some(0), None));
+    }
+
+    #[test]
+    fn reads_latest_activity_across_apps() {
+        let activity = OfficeActivity::parse(concat!(
+            "\u{feff}Report Refresh Date,User Principal Name,Display Name,Is Deleted,Exchange Last Activity Date,OneDrive Last Activity Date,Teams Last Activity Date,Assigned Products\n",
+            "2026-10-13,Jane.Doe@contoso.com,Jane Doe,False,2026-10-01,,2026-10-12,OFFICE 365 E3\n",
+            "2026-10-13,idle@contoso.com,Idle,False,,,,OFFICE 365 E3\n",
+            "2026-10-13,gone@contoso.com,Gone,True,2026-10-10,,,\n",
+        ))
+        .unwrap();
+        assert_eq!(activity.len(), 2);
+        assert_eq!(activity.last_activity("jane.doe@contoso.com"), NaiveDate::from_ymd_opt(2026, 10, 12));
+        assert!(activity.contains("idle@contoso.com"));
+        assert_eq!(activity.last_activity("idle@contoso.com"), None);
+        assert!(!activity.contains("gone@contoso.com"));
+    }
+
+    #[test]
+    fn reads_group_activity_by_id() {
+        let activity = GroupActivity::parse(concat!(
+            "\u{feff}Report Refresh Date,Group Display Name,Is Deleted,Owner Principal Name,Last Activity Date,Group Type,Member Count,Group Id\n",
+            "2026-10-13,Project X,False,jane.doe@contoso.com,2026-09-30,Private,12,A1B2C3D4-0000-4000-8000-000000000001\n",
+            "2026-10-13,Quiet Team,False,,,Public,3,a1b2c3d4-0000-4000-8000-000000000002\n",
+            "2026-10-13,Old Team,True,,2026-01-02,Private,0,a1b2c3d4-0000-4000-8000-000000000003\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            activity.last_activity("a1b2c3d4-0000-4000-8000-000000000001"),
+            NaiveDate::from_ymd_opt(2026, 9, 30)
+        );
+        assert!(activity.contains("a1b2c3d4-0000-4000-8000-000000000002"));
+        assert_eq!(activity.last_activity("a1b2c3d4-0000-4000-8000-000000000002"), None);
+        assert!(!activity.contains("a1b2c3d4-0000-4000-8000-000000000003"));
+    }
+
+    #[test]
+    fn reads_site_storage_and_activity() {
+        let usage = SiteUsage::parse(concat!(
+            "\u{feff}Report Refresh Date,Site Id,Site URL,Owner Display Name,Is Deleted,Last Activity Date,File Count,Active File Count,Page View Count,Visited Page Count,Storage Used (Byte),Storage Allocated (Byte),Root Web Template,Owner Principal Name,Report Period\n",
+            "2026-10-13,6F1C2A3B-0000-4000-8000-000000000001,https://contoso.sharepoint.com/sites/finance,Finance Owners,False,2026-10-10,5120,312,940,51,21474836480,27487790694400,Group,finance@contoso.com,7\n",
+            "2026-10-13,6f1c2a3b-0000-4000-8000-000000000002,,,False,,0,0,0,0,1048576,27487790694400,Team Site,,7\n",
+            "2026-10-13,6f1c2a3b-0000-4000-8000-000000000003,https://contoso.sharepoint.com/sites/old,,True,2025-01-02,1,0,0,0,1,1,Team Site,,7\n",
+        ))
+        .unwrap();
+        assert_eq!(usage.len(), 2);
+        let sites: Vec<&Site> = usage.sites().collect();
+        assert_eq!(sites[0].site_id, "6f1c2a3b-0000-4000-8000-000000000001");
+        assert_eq!(sites[0].storage_used, Some(21474836480));
+        assert_eq!(sites[0].last_activity, NaiveDate::from_ymd_opt(2026, 10, 10));
+        assert_eq!((sites[1].url.as_str(), sites[1].last_activity, sites[1].file_count), ("", None, Some(0)));
+    }
+}
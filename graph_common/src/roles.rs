@@ -0,0 +1,101 @@
+use crate::audit::Severity;
+use crate::GraphClient;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Roles that can take over the tenant, directly or by granting themselves more access.
+pub const CRITICAL_ROLES: &[&str] = &[
+    "Global Administrator",
+    "Privileged Role Administrator",
+    "Privileged Authentication Administrator",
+    "Security Administrator",
+    "Conditional Access Administrator",
+    "Hybrid Identity Administrator",
+    "Application Administrator",
+    "Cloud Application Administrator",
+    "Exchange Administrator",
+    "SharePoint Administrator",
+    "User Administrator",
+    "Intune Administrator",
+];
+
+#[derive(Deserialize, Debug)]
+struct DirectoryRole {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RoleMember {
+    id: String,
+    #[serde(rename = "@odata.type")]
+    odata_type: Option<String>,
+}
+
+pub fn role_severity(role: &str) -> Severity {
+    if CRITICAL_ROLES.iter().any(|critical| critical.eq_ignore_ascii_case(role)) {
+        Severity::High
+    } else if role.ends_with("Reader") {
+        Severity::Low
+    } else {
+        Severity::Medium
+    }
+}
+
+// Active directory role names keyed by user id. Role-assignable groups pass their role on to
+// every member, including nested ones; service principals holding a role are left out.
+pub async fn role_assignments(client: &GraphClient) -> Result<HashMap<String, Vec<String>>> {
+    assignments_of(client, "user").await
+}
+
+// Active directory role names keyed by service principal id, directly or through
+// role-assignable groups.
+pub async fn service_principal_role_assignments(client: &GraphClient) -> Result<HashMap<String, Vec<String>>> {
+    assignments_of(client, "servicePrincipal").await
+}
+
+async fn assignments_of(client: &GraphClient, member_type: &str) -> Result<HashMap<String, Vec<String>>> {
+    let odata_type = format!("#microsoft.graph.{}", member_type);
+    let cast = format!("microsoft.graph.{}", member_type);
+    let roles: Vec<DirectoryRole> = client
+        .directory_roles()
+        .resource()
+        .get_all()
+        .await
+        .context("Failed to fetch directory roles")?;
+
+    let mut assignments: HashMap<String, Vec<String>> = HashMap::new();
+    for role in roles {
+        let members: Vec<RoleMember> = client
+            .directory_roles()
+            .members(&role.id)
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to fetch members of {}", role.display_name))?;
+        for member in members {
+            let member_ids = match member.odata_type.as_deref() {
+                Some("#microsoft.graph.group") => {
+                    let members: Vec<RoleMember> = client
+                        .path(&["groups", &member.id, "transitiveMembers", &cast])
+                        .select("id")
+                        .top(999)
+                        .get_all()
+                        .await
+                        .with_context(|| format!("Failed to fetch members of group {}", member.id))?;
+                    members.into_iter().map(|member| member.id).collect()
+                }
+                Some(member_type) if member_type == odata_type => vec![member.id],
+                _ => continue,
+            };
+            for member_id in member_ids {
+                let roles = assignments.entry(member_id).or_default();
+                if !roles.contains(&role.display_name) {
+                    roles.push(role.display_name.clone());
+                }
+            }
+        }
+    }
+    Ok(assignments)
+}
@@ -0,0 +1,192 @@
+use crate::{mail, GraphClient};
+use anyhow::Result;
+use log::debug;
+use serde::Serialize;
+use serde_json::Value;
+
+// Folders attackers commonly use to hide replies from the mailbox owner.
+const HIDING_FOLDERS: &[&str] = &[
+    "rss feeds",
+    "rss subscriptions",
+    "conversation history",
+    "archive",
+    "junk email",
+    "deleted items",
+    "notes",
+];
+
+const SENSITIVE_KEYWORDS: &[&str] = &[
+    "invoice", "payment", "wire", "bank", "remittance", "payroll", "password", "mfa", "security",
+    "phish", "hack", "compromise", "suspicious", "helpdesk",
+];
+
+#[derive(Serialize, Debug)]
+pub struct RuleAssessment {
+    pub id: String,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    #[serde(rename = "isEnabled")]
+    pub is_enabled: bool,
+    pub suspicious: bool,
+    pub reasons: Vec<String>,
+    #[serde(rename = "forwardsTo")]
+    pub forwards_to: Vec<String>,
+    #[serde(rename = "moveToFolder", skip_serializing_if = "Option::is_none")]
+    pub move_to_folder: Option<String>,
+}
+
+fn keywords(conditions: &Value) -> Vec<String> {
+    ["subjectContains", "bodyContains", "bodyOrSubjectContains"]
+        .iter()
+        .flat_map(|condition| conditions[*condition].as_array().into_iter().flatten())
+        .filter_map(Value::as_str)
+        .filter(|value| {
+            let value = value.to_lowercase();
+            SENSITIVE_KEYWORDS.iter().any(|keyword| value.contains(keyword))
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn user_domain(upn: &str) -> String {
+    upn.rsplit_once('@')
+        .map(|(_, domain)| domain.to_lowercase())
+        .unwrap_or_default()
+}
+
+// `move_folder` is the display name of the rule's moveToFolder target, when it has one.
+pub fn assess(rule: &Value, user_domain: &str, move_folder: Option<&str>) -> RuleAssessment {
+    let actions = &rule["actions"];
+    let display_name = rule["displayName"].as_str().unwrap_or_default().to_string();
+    let mut reasons = Vec::new();
+
+    let forwards_to = mail::forwarding_recipients(rule);
+    for address in &forwards_to {
+        if !address.ends_with(&format!("@{}", user_domain)) {
+            reasons.push(format!("forwards to external address {}", address));
+        }
+    }
+
+    let deletes = actions["delete"].as_bool() == Some(true)
+        || actions["permanentDelete"].as_bool() == Some(true);
+    if deletes {
+        reasons.push("deletes matching messages".to_string());
+    }
+
+    let hidden_folder = move_folder
+        .filter(|folder| HIDING_FOLDERS.contains(&folder.to_lowercase().as_str()));
+    if let Some(folder) = hidden_folder {
+        reasons.push(format!("moves matching messages to {}", folder));
+    }
+
+    if deletes && !forwards_to.is_empty() {
+        reasons.push("forwards and then deletes matching messages".to_string());
+    }
+
+    let hides = deletes || hidden_folder.is_some();
+    if hides && actions["markAsRead"].as_bool() == Some(true) {
+        reasons.push("marks matching messages as read".to_string());
+    }
+    if hides || !forwards_to.is_empty() {
+        for keyword in keywords(&rule["conditions"]) {
+            reasons.push(format!("matches sensitive keyword \"{}\"", keyword));
+        }
+    }
+
+    if !display_name.chars().any(char::is_alphanumeric) {
+        reasons.push("has a blank or punctuation-only name".to_string());
+    }
+
+    RuleAssessment {
+        id: rule["id"].as_str().unwrap_or_default().to_string(),
+        display_name,
+        is_enabled: rule["isEnabled"].as_bool().unwrap_or(false),
+        suspicious: !reasons.is_empty(),
+        reasons,
+        forwards_to,
+        move_to_folder: move_folder.map(str::to_string),
+    }
+}
+
+// Lists the user's inbox rules and assesses each one, resolving moveToFolder targets to their
+// display names. A folder that cannot be resolved is treated as not hiding mail.
+pub async fn review(client: &GraphClient, upn: &str) -> Result<Vec<RuleAssessment>> {
+    let domain = user_domain(upn);
+    let mut assessments = Vec::new();
+    for rule in mail::list_inbox_rules(client, upn).await? {
+        let move_folder = match rule["actions"]["moveToFolder"].as_str() {
+            Some(folder_id) => match mail::mail_folder_name(client, upn, folder_id).await {
+                Ok(name) => Some(name),
+                Err(e) => {
+                    debug!("Could not resolve folder {}: {:#}", folder_id, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        assessments.push(assess(&rule, &domain, move_folder.as_deref()));
+    }
+    Ok(assessments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn flags_external_forwarding_and_hidden_deletes() {
+        let rule = json!({
+            "id": "1",
+            "displayName": ".",
+            "isEnabled": true,
+            "conditions": { "subjectContains": ["Invoice"] },
+            "actions": {
+                "forwardTo": [{ "emailAddress": { "address": "Drop@Evil.example" } }],
+                "delete": true,
+                "markAsRead": true
+            }
+        });
+        let assessment = assess(&rule, "contoso.com", None);
+        assert!(assessment.suspicious);
+        assert_eq!(assessment.forwards_to, vec!["drop@evil.example"]);
+        assert_eq!(assessment.reasons.len(), 6);
+    }
+
+    #[test]
+    fn ignores_ordinary_filing_rules() {
+        let rule = json!({
+            "id": "2",
+            "displayName": "Newsletters",
+            "isEnabled": true,
+            "conditions": { "senderContains": ["news@contoso.com"] },
+            "actions": {
+                "moveToFolder": "AAMk",
+                "forwardTo": [{ "emailAddress": { "address": "team@contoso.com" } }]
+            }
+        });
+        let assessment = assess(&rule, "contoso.com", Some("Newsletters"));
+        assert!(!assessment.suspicious);
+        assert_eq!(assessment.forwards_to, vec!["team@contoso.com"]);
+    }
+
+    #[test]
+    fn flags_keywords_on_forwarding_rules_and_hidden_folders() {
+        let rule = json!({
+            "id": "3",
+            "displayName": "Sort",
+            "isEnabled": true,
+            "conditions": { "bodyOrSubjectContains": ["Suspicious sign-in"] },
+            "actions": { "moveToFolder": "AAMk" }
+        });
+        let assessment = assess(&rule, "contoso.com", Some("RSS Feeds"));
+        assert_eq!(
+            assessment.reasons,
+            vec![
+                "moves matching messages to RSS Feeds",
+                "matches sensitive keyword \"Suspicious sign-in\"",
+            ]
+        );
+        assert_eq!(assessment.move_to_folder.as_deref(), Some("RSS Feeds"));
+    }
+}
@@ -0,0 +1,159 @@
+use log::{debug, info, warn};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceEvent {
+    Reload,
+    Shutdown,
+}
+
+pub type ServiceEvents = UnboundedReceiver<ServiceEvent>;
+
+pub fn channel() -> (UnboundedSender<ServiceEvent>, ServiceEvents) {
+    mpsc::unbounded_channel()
+}
+
+// SIGINT and SIGTERM request a graceful shutdown; SIGHUP requests a config reload.
+pub fn signals() -> ServiceEvents {
+    let (sender, events) = channel();
+    tokio::spawn(forward_signals(sender));
+    events
+}
+
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    // A signal that is never raised, for callers that run without signal handling.
+    pub fn never() -> Self {
+        ShutdownSignal(watch::channel(false).1)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn requested(&mut self) {
+        if self.0.wait_for(|requested| *requested).await.is_err() {
+            // The signal listener is gone, so a shutdown can no longer be requested.
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// For bulk and audit modes: the first SIGINT or SIGTERM asks the caller to stop starting new
+// work so it can wind down and report what it finished; a second one exits immediately.
+pub fn shutdown_signal() -> ShutdownSignal {
+    let (sender, receiver) = watch::channel(false);
+    let mut events = signals();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if event != ServiceEvent::Shutdown {
+                continue;
+            }
+            if sender.send_replace(true) {
+                warn!("Interrupted again, exiting immediately");
+                std::process::exit(130);
+            }
+            warn!("Shutdown requested, finishing in-flight work. Interrupt again to exit immediately");
+        }
+    });
+    ShutdownSignal(receiver)
+}
+
+#[cfg(unix)]
+async fn forward_signals(sender: UnboundedSender<ServiceEvent>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut interrupt, mut terminate, mut hangup) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) {
+        (Ok(interrupt), Ok(terminate), Ok(hangup)) => (interrupt, terminate, hangup),
+        _ => {
+            warn!("Failed to install signal handlers");
+            return;
+        }
+    };
+
+    loop {
+        let event = tokio::select! {
+            _ = interrupt.recv() => ServiceEvent::Shutdown,
+            _ = terminate.recv() => ServiceEvent::Shutdown,
+            _ = hangup.recv() => ServiceEvent::Reload,
+        };
+        debug!("Received {:?} signal", event);
+        if sender.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn forward_signals(sender: UnboundedSender<ServiceEvent>) {
+    while tokio::signal::ctrl_c().await.is_ok() {
+        if sender.send(ServiceEvent::Shutdown).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn notify(states: &[sd_notify::NotifyState]) {
+    // Does nothing unless the process was started by systemd with NOTIFY_SOCKET set.
+    if let Err(e) = sd_notify::notify(false, states) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+pub fn notify_ready(status: &str) {
+    info!("{}", status);
+    #[cfg(target_os = "linux")]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(status),
+    ]);
+}
+
+pub fn notify_status(status: &str) {
+    debug!("Service status: {}", status);
+    #[cfg(target_os = "linux")]
+    notify(&[sd_notify::NotifyState::Status(status)]);
+}
+
+pub fn notify_reloading() {
+    info!("Reloading configuration");
+    #[cfg(target_os = "linux")]
+    match sd_notify::NotifyState::monotonic_usec_now() {
+        Ok(now) => notify(&[sd_notify::NotifyState::Reloading, now]),
+        Err(_) => notify(&[sd_notify::NotifyState::Reloading]),
+    }
+}
+
+pub fn notify_stopping() {
+    info!("Shutting down");
+    #[cfg(target_os = "linux")]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+// Pings the systemd watchdog at half the configured WatchdogSec while the process is alive.
+pub fn spawn_watchdog() {
+    #[cfg(target_os = "linux")]
+    {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return;
+        }
+        let period = std::time::Duration::from_micros(usec / 2);
+        debug!("Pinging the systemd watchdog every {:?}", period);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                notify(&[sd_notify::NotifyState::Watchdog]);
+            }
+        });
+    }
+}
@@ -0,0 +1,197 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Counters shared by a GraphClient and its clones, so a run's totals include requests made
+// from every task. Requests made with a tool's own reqwest client are not counted.
+#[derive(Clone)]
+pub struct RunStats {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    started: Instant,
+    requests: AtomicU64,
+    throttled: AtomicU64,
+    cache_hits: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    phases: Mutex<Vec<PhaseTime>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PhaseTime {
+    pub name: String,
+    #[serde(rename = "seconds")]
+    pub seconds: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+    pub requests: u64,
+    pub throttled: u64,
+    #[serde(rename = "cacheHits")]
+    pub cache_hits: u64,
+    #[serde(rename = "bytesSent")]
+    pub bytes_sent: u64,
+    #[serde(rename = "bytesReceived")]
+    pub bytes_received: u64,
+    #[serde(rename = "wallSeconds")]
+    pub wall_seconds: f64,
+    pub phases: Vec<PhaseTime>,
+}
+
+// Records the time from creation until it is dropped under the phase name.
+pub struct Phase {
+    stats: RunStats,
+    name: String,
+    started: Instant,
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+        self.stats.record_phase(&self.name, elapsed);
+    }
+}
+
+impl Default for RunStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                started: Instant::now(),
+                requests: AtomicU64::new(0),
+                throttled: AtomicU64::new(0),
+                cache_hits: AtomicU64::new(0),
+                bytes_sent: AtomicU64::new(0),
+                bytes_received: AtomicU64::new(0),
+                phases: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    pub fn phase(&self, name: &str) -> Phase {
+        Phase {
+            stats: self.clone(),
+            name: name.to_string(),
+            started: Instant::now(),
+        }
+    }
+
+    // A phase entered more than once, e.g. per mailbox, accumulates its time.
+    fn record_phase(&self, name: &str, elapsed: Duration) {
+        let mut phases = self.inner.phases.lock().unwrap_or_else(|e| e.into_inner());
+        match phases.iter_mut().find(|phase| phase.name == name) {
+            Some(phase) => phase.seconds += elapsed.as_secs_f64(),
+            None => phases.push(PhaseTime {
+                name: name.to_string(),
+                seconds: elapsed.as_secs_f64(),
+            }),
+        }
+    }
+
+    pub(crate) fn request(&self, bytes_sent: u64) {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes_sent.fetch_add(bytes_sent, Ordering::Relaxed);
+    }
+
+    pub(crate) fn throttled(&self) {
+        self.inner.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn cache_hit(&self) {
+        self.inner.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, bytes: u64) {
+        self.inner.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            requests: self.inner.requests.load(Ordering::Relaxed),
+            throttled: self.inner.throttled.load(Ordering::Relaxed),
+            cache_hits: self.inner.cache_hits.load(Ordering::Relaxed),
+            bytes_sent: self.inner.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.inner.bytes_received.load(Ordering::Relaxed),
+            wall_seconds: self.inner.started.elapsed().as_secs_f64(),
+            phases: self.inner.phases.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1048576.0),
+    }
+}
+
+impl Summary {
+    // Fixed counters for the report format tests, so the golden files do not depend on a run.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn fixture() -> Self {
+        Summary {
+            requests: 10,
+            throttled: 0,
+            cache_hits: 0,
+            bytes_sent: 0,
+            bytes_received: 4096,
+            wall_seconds: 1.0,
+            phases: Vec::new(),
+        }
+    }
+
+    // Written to stderr so that CSV or JSON on stdout stays machine-readable.
+    pub fn print(&self) {
+        eprintln!();
+        eprintln!(
+            "Run summary: {} Graph requests ({} throttled, {} served from cache), {} received, {} sent, {:.1}s",
+            self.requests,
+            self.throttled,
+            self.cache_hits,
+            format_bytes(self.bytes_received),
+            format_bytes(self.bytes_sent),
+            self.wall_seconds
+        );
+        for phase in &self.phases {
+            eprintln!("  {:<30} {:>8.1}s", phase.name, phase.seconds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_counters_and_repeated_phases() {
+        let stats = RunStats::new();
+        let clone = stats.clone();
+        clone.request(120);
+        clone.received(4096);
+        stats.request(0);
+        stats.throttled();
+        for _ in 0..2 {
+            let _phase = stats.phase("read mailboxes");
+        }
+        drop(stats.phase("report"));
+
+        let summary = stats.summary();
+        assert_eq!(summary.requests, 2);
+        assert_eq!(summary.throttled, 1);
+        assert_eq!(summary.bytes_sent, 120);
+        assert_eq!(summary.bytes_received, 4096);
+        assert_eq!(summary.phases.len(), 2);
+        assert_eq!(summary.phases[0].name, "read mailboxes");
+        assert_eq!(format_bytes(4096), "4.0 KB");
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Expectation {
+    #[serde(rename = "timeZones", default)]
+    pub time_zones: Vec<String>,
+    #[serde(default)]
+    pub locales: Vec<String>,
+}
+
+// Expected mailbox settings per usageLocation country code, with optional overrides per
+// officeLocation. An office entry wins over the country because countries such as the US or
+// Australia span several time zones.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Expectations {
+    #[serde(rename = "usageLocation", default)]
+    pub usage_location: HashMap<String, Expectation>,
+    #[serde(rename = "officeLocation", default)]
+    pub office_location: HashMap<String, Expectation>,
+}
+
+// Windows time zone ids as stored by Outlook, plus the IANA names some clients write instead.
+const DEFAULTS: &[(&str, &[&str], &[&str])] = &[
+    (
+        "US",
+        &[
+            "Eastern Standard Time", "Central Standard Time", "Mountain Standard Time",
+            "US Mountain Standard Time", "Pacific Standard Time", "Alaskan Standard Time",
+            "Hawaiian Standard Time", "America/New_York", "America/Chicago", "America/Denver",
+            "America/Phoenix", "America/Los_Angeles", "America/Anchorage", "Pacific/Honolulu",
+        ],
+        &["en-US", "es-US"],
+    ),
+    (
+        "CA",
+        &[
+            "Eastern Standard Time", "Central Standard Time", "Canada Central Standard Time",
+            "Mountain Standard Time", "Pacific Standard Time", "Atlantic Standard Time",
+            "Newfoundland Standard Time", "America/Toronto", "America/Winnipeg", "America/Regina",
+            "America/Edmonton", "America/Vancouver", "America/Halifax", "America/St_Johns",
+        ],
+        &["en-CA", "fr-CA"],
+    ),
+    ("GB", &["GMT Standard Time", "Europe/London"], &["en-GB"]),
+    ("IE", &["GMT Standard Time", "Europe/Dublin"], &["en-IE", "ga-IE"]),
+    (
+        "AU",
+        &[
+            "AUS Eastern Standard Time", "E. Australia Standard Time", "Cen. Australia Standard Time",
+            "AUS Central Standard Time", "W. Australia Standard Time", "Tasmania Standard Time",
+            "Australia/Sydney", "Australia/Melbourne", "Australia/Brisbane", "Australia/Adelaide",
+            "Australia/Darwin", "Australia/Perth", "Australia/Hobart",
+        ],
+        &["en-AU"],
+    ),
+    ("NZ", &["New Zealand Standard Time", "Pacific/Auckland"], &["en-NZ", "mi-NZ"]),
+    ("DE", &["W. Europe Standard Time", "Europe/Berlin"], &["de-DE"]),
+    ("AT", &["W. Europe Standard Time", "Europe/Vienna"], &["de-AT"]),
+    ("CH", &["W. Europe Standard Time", "Europe/Zurich"], &["de-CH", "fr-CH", "it-CH"]),
+    ("NL", &["W. Europe Standard Time", "Europe/Amsterdam"], &["nl-NL"]),
+    ("IT", &["W. Europe Standard Time", "Europe/Rome"], &["it-IT"]),
+    ("SE", &["W. Europe Standard Time", "Europe/Stockholm"], &["sv-SE"]),
+    ("NO", &["W. Europe Standard Time", "Europe/Oslo"], &["nb-NO", "nn-NO"]),
+    ("FR", &["Romance Standard Time", "Europe/Paris"], &["fr-FR"]),
+    ("BE", &["Romance Standard Time", "Europe/Brussels"], &["nl-BE", "fr-BE"]),
+    ("ES", &["Romance Standard Time", "Europe/Madrid"], &["es-ES", "ca-ES"]),
+    ("DK", &["Romance Standard Time", "Europe/Copenhagen"], &["da-DK"]),
+    ("PL", &["Central European Standard Time", "Europe/Warsaw"], &["pl-PL"]),
+    ("FI", &["FLE Standard Time", "Europe/Helsinki"], &["fi-FI", "sv-FI"]),
+    ("ZA", &["South Africa Standard Time", "Africa/Johannesburg"], &["en-ZA", "af-ZA"]),
+    ("IN", &["India Standard Time", "Asia/Kolkata", "Asia/Calcutta"], &["en-IN", "hi-IN"]),
+    ("SG", &["Singapore Standard Time", "Asia/Singapore"], &["en-SG"]),
+    ("JP", &["Tokyo Standard Time", "Asia/Tokyo"], &["ja-JP"]),
+];
+
+impl Expectations {
+    pub fn builtin() -> Self {
+        let usage_location = DEFAULTS
+            .iter()
+            .map(|(country, time_zones, locales)| {
+                let expectation = Expectation {
+                    time_zones: time_zones.iter().map(|zone| zone.to_string()).collect(),
+                    locales: locales.iter().map(|locale| locale.to_string()).collect(),
+                };
+                (country.to_string(), expectation)
+            })
+            .collect();
+        Self {
+            usage_location,
+            office_location: HashMap::new(),
+        }
+    }
+
+    // Entries in the file replace the built-in entry for the same country or office.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let overrides: Expectations = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let mut expectations = Self::builtin();
+        expectations.usage_location.extend(
+            overrides
+                .usage_location
+                .into_iter()
+                .map(|(country, expectation)| (country.to_uppercase(), expectation)),
+        );
+        expectations.office_location.extend(overrides.office_location);
+        Ok(expectations)
+    }
+
+    pub fn from_env() -> Result<Self> {
+        match env::var("MSTOOLS_TIMEZONE_MAP") {
+            Ok(path) if !path.is_empty() => Self::load(Path::new(&path)),
+            _ => Ok(Self::builtin()),
+        }
+    }
+
+    // Returns the expectation and the attribute it was derived from.
+    pub fn lookup(
+        &self,
+        usage_location: Option<&str>,
+        office_location: Option<&str>,
+    ) -> Option<(&Expectation, &'static str)> {
+        let office = office_location.and_then(|office| {
+            self.office_location
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(office.trim()))
+                .map(|(_, expectation)| (expectation, "officeLocation"))
+        });
+        office.or_else(|| {
+            usage_location
+                .and_then(|country| self.usage_location.get(&country.to_uppercase()))
+                .map(|expectation| (expectation, "usageLocation"))
+        })
+    }
+}
+
+impl Expectation {
+    pub fn time_zone_matches(&self, time_zone: &str) -> bool {
+        self.time_zones.is_empty()
+            || self
+                .time_zones
+                .iter()
+                .any(|expected| expected.eq_ignore_ascii_case(time_zone))
+    }
+
+    pub fn locale_matches(&self, locale: &str) -> bool {
+        self.locales.is_empty()
+            || self
+                .locales
+                .iter()
+                .any(|expected| expected.eq_ignore_ascii_case(locale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn office_location_overrides_usage_location() {
+        let mut expectations = Expectations::builtin();
+        expectations.office_location.insert(
+            "Perth".to_string(),
+            Expectation {
+                time_zones: vec!["W. Australia Standard Time".to_string()],
+                locales: Vec::new(),
+            },
+        );
+
+        let (sydney, source) = expectations.lookup(Some("au"), Some("Sydney")).unwrap();
+        assert_eq!(source, "usageLocation");
+        assert!(sydney.time_zone_matches("W. Australia Standard Time"));
+        assert!(!sydney.locale_matches("en-US"));
+
+        let (perth, source) = expectations.lookup(Some("AU"), Some(" perth ")).unwrap();
+        assert_eq!(source, "officeLocation");
+        assert!(!perth.time_zone_matches("AUS Eastern Standard Time"));
+        assert!(perth.locale_matches("en-US"));
+
+        assert!(expectations.lookup(Some("XX"), None).is_none());
+    }
+}
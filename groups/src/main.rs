@@ -0,0 +1,630 @@
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::{confirm, is_object_id};
+use graph_common::{csv, encode, remediation, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Read};
+
+#[derive(Parser, Debug)]
+#[command(name = "groups")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists, adds, and removes the members and owners of groups using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "List the members or owners of a group")]
+    List {
+        #[arg(short, long, help = "Group display name or object id")]
+        group: String,
+
+        #[arg(long, help = "List the owners instead of the members")]
+        owners: bool,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+        output: String,
+    },
+
+    #[command(about = "Add members or owners to groups")]
+    Add {
+        #[command(flatten)]
+        change: ChangeArgs,
+
+        #[arg(long, help = "Also remove everyone not in the list, so the group matches it exactly")]
+        mirror: bool,
+
+        #[arg(short, long, requires = "mirror", help = "Skip the confirmation prompt for removals (for automation)")]
+        yes: bool,
+    },
+
+    #[command(about = "Remove members or owners from groups")]
+    Remove {
+        #[command(flatten)]
+        change: ChangeArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ChangeArgs {
+    #[arg(short, long, help = "Group display name or object id (default for file rows without a group)")]
+    group: Option<String>,
+
+    #[arg(short, long, help = "User Principal Name or object id (repeatable)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "CSV with a upn column and an optional group column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(long, help = "Change the owners instead of the members")]
+    owners: bool,
+
+    #[arg(long, help = "Show the adds and removes without making them")]
+    dry_run: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "groupTypes", default)]
+    group_types: Vec<String>,
+    #[serde(rename = "mailEnabled")]
+    mail_enabled: Option<bool>,
+    #[serde(rename = "onPremisesSyncEnabled")]
+    on_premises_sync_enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Member {
+    id: String,
+    #[serde(rename = "@odata.type", default)]
+    odata_type: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operation {
+    Add,
+    Remove,
+}
+
+impl Member {
+    fn kind(&self) -> &str {
+        self.odata_type.trim_start_matches("#microsoft.graph.")
+    }
+
+    fn name(&self) -> &str {
+        self.user_principal_name
+            .as_deref()
+            .or(self.display_name.as_deref())
+            .unwrap_or(&self.id)
+    }
+}
+
+impl Group {
+    fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.id)
+    }
+
+    // Dynamic and synced groups cannot be changed through Graph, nor can distribution lists and
+    // mail-enabled security groups, which are managed in Exchange.
+    fn read_only_reason(&self) -> Option<&'static str> {
+        if self.group_types.iter().any(|group_type| group_type == "DynamicMembership") {
+            Some("its membership is dynamic")
+        } else if self.on_premises_sync_enabled == Some(true) {
+            Some("it is synced from on-premises")
+        } else if self.mail_enabled == Some(true) && !self.group_types.iter().any(|group_type| group_type == "Unified") {
+            Some("it is managed in Exchange")
+        } else {
+            None
+        }
+    }
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn resolve_group(client: &GraphClient, group: &str) -> Result<Group> {
+    let select = "id,displayName,groupTypes,mailEnabled,onPremisesSyncEnabled";
+    if is_object_id(group) {
+        return client
+            .path(&["groups", group])
+            .select(select)
+            .get_json::<Group>()
+            .await
+            .with_context(|| format!("Failed to look up group {}", group));
+    }
+
+    let mut groups = client
+        .path(&["groups"])
+        .filter(&format!("displayName eq {}", encode::odata_string(group)))
+        .select(select)
+        .get_all::<Group>()
+        .await
+        .with_context(|| format!("Failed to look up group {}", group))?;
+    match groups.len() {
+        0 => Err(anyhow::anyhow!("No group named {}", group)),
+        1 => Ok(groups.remove(0)),
+        count => Err(anyhow::anyhow!(
+            "{} groups are named {}; pass the group's object id instead",
+            count,
+            group
+        )),
+    }
+}
+
+// Object ids are used as they are, so groups, devices, and service principals can be members too.
+async fn resolve_member(client: &GraphClient, member: &str) -> Result<Member> {
+    if is_object_id(member) {
+        return client
+            .path(&["directoryObjects", member])
+            .get_json::<Member>()
+            .await
+            .with_context(|| format!("Failed to look up {}", member));
+    }
+    client
+        .user(member)
+        .resource()
+        .select("id,displayName,userPrincipalName,mail")
+        .get_json::<Member>()
+        .await
+        .with_context(|| format!("Failed to look up {}", member))
+}
+
+async fn list(client: &GraphClient, group: &Group, owners: bool) -> Result<Vec<Member>> {
+    let mut members: Vec<Member> = client
+        .path(&["groups", &group.id, if owners { "owners" } else { "members" }])
+        .select("id,displayName,userPrincipalName,mail")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the {} of {}", if owners { "owners" } else { "members" }, group.name()))?;
+    members.sort_by_key(|member| member.display_name.as_deref().unwrap_or(member.name()).to_lowercase());
+    Ok(members)
+}
+
+fn render_list(group: &Group, members: &[Member], owners: bool, output: &str) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "group": { "id": group.id, "displayName": group.display_name },
+                if owners { "owners" } else { "members" }: members,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "type,displayName,userPrincipalName,mail,id")?;
+            for member in members {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    member.kind(),
+                    csv::field(member.display_name.as_deref().unwrap_or_default()),
+                    member.user_principal_name.as_deref().unwrap_or_default(),
+                    member.mail.as_deref().unwrap_or_default(),
+                    member.id
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<18} {:<30} UPN OR MAIL", "TYPE", "DISPLAY NAME")?;
+            for member in members {
+                let line = format!(
+                    "{:<18} {:<30} {}",
+                    member.kind(),
+                    member.display_name.as_deref().unwrap_or_default(),
+                    member
+                        .user_principal_name
+                        .as_deref()
+                        .or(member.mail.as_deref())
+                        .unwrap_or_default()
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} has {} {}",
+                group.name(),
+                members.len(),
+                if owners { "owners" } else { "members" }
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn read_file(source: &str) -> Result<String> {
+    if source == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read members from stdin")?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))
+    }
+}
+
+// (group, member) pairs in input order. Rows without a group use --group.
+fn read_entries(change: &ChangeArgs) -> Result<Vec<(String, String)>> {
+    let mut entries = Vec::new();
+    let default_group = || {
+        change
+            .group
+            .clone()
+            .context("Pass --group, or a file with a group column")
+    };
+    for upn in &change.upn {
+        entries.push((default_group()?, upn.trim().to_string()));
+    }
+    if let Some(file) = &change.file {
+        let table = csv::Table::parse(&read_file(file)?)?;
+        let upn = table
+            .column(&["upn", "userPrincipalName", "id"])
+            .context("The file has no upn, userPrincipalName, or id column")?;
+        let group = table.column(&["group"]);
+        for record in &table.records {
+            let member = record.get(upn);
+            if member.is_empty() {
+                continue;
+            }
+            let group = match group.map(|group| record.get(group)).filter(|group| !group.is_empty()) {
+                Some(group) => group.to_string(),
+                None => default_group().with_context(|| format!("Line {} has no group", record.line))?,
+            };
+            entries.push((group, member.to_string()));
+        }
+    }
+    if entries.is_empty() {
+        return Err(anyhow::anyhow!("Pass --upn or --file with at least one member"));
+    }
+    Ok(entries)
+}
+
+// The adds and removes that bring `current` to the requested state, plus the requested members
+// that need no change.
+fn plan<'a>(
+    current: &'a [Member],
+    requested: &'a [Member],
+    operation: &Operation,
+    mirror: bool,
+) -> (Vec<(Operation, &'a Member)>, Vec<&'a Member>) {
+    let current_ids: HashSet<&str> = current.iter().map(|member| member.id.as_str()).collect();
+    let requested_ids: HashSet<&str> = requested.iter().map(|member| member.id.as_str()).collect();
+    let mut changes = Vec::new();
+    let mut unchanged = Vec::new();
+    for member in requested {
+        let present = current_ids.contains(member.id.as_str());
+        match operation {
+            Operation::Add if !present => changes.push((Operation::Add, member)),
+            Operation::Remove if present => changes.push((Operation::Remove, member)),
+            _ => unchanged.push(member),
+        }
+    }
+    if mirror {
+        for member in current.iter().filter(|member| !requested_ids.contains(member.id.as_str())) {
+            changes.push((Operation::Remove, member));
+        }
+    }
+    (changes, unchanged)
+}
+
+struct Request<'a> {
+    change: &'a ChangeArgs,
+    operation: Operation,
+    mirror: bool,
+    yes: bool,
+}
+
+struct Totals {
+    added: usize,
+    removed: usize,
+    unchanged: usize,
+    failed: usize,
+}
+
+async fn apply(
+    client: &GraphClient,
+    request: &Request<'_>,
+    group_name: &str,
+    members: &[String],
+    resolved: &mut HashMap<String, Member>,
+    totals: &mut Totals,
+) -> Result<()> {
+    let Request { change, operation, mirror, yes } = request;
+    let group = resolve_group(client, group_name).await?;
+    if !change.owners {
+        if let Some(reason) = group.read_only_reason() {
+            return Err(anyhow::anyhow!("The members of {} cannot be changed: {}", group.name(), reason));
+        }
+    }
+
+    let mut requested: Vec<Member> = Vec::new();
+    let mut unresolved = Vec::new();
+    for member in members {
+        let key = member.to_lowercase();
+        if let Entry::Vacant(entry) = resolved.entry(key.clone()) {
+            match resolve_member(client, member).await {
+                Ok(found) => {
+                    entry.insert(found);
+                }
+                Err(e) => {
+                    unresolved.push((member, e));
+                    continue;
+                }
+            }
+        }
+        let found = &resolved[&key];
+        if !requested.iter().any(|existing| existing.id == found.id) {
+            requested.push(found.clone());
+        }
+    }
+    // Without every listed member, a mirror would remove people who are meant to stay.
+    if *mirror && !unresolved.is_empty() {
+        for (member, e) in &unresolved {
+            println!("FAILED  {}: {:#}", member, e);
+        }
+        totals.failed += unresolved.len();
+        return Err(anyhow::anyhow!(
+            "{} members of {} could not be looked up; nothing was mirrored",
+            unresolved.len(),
+            group.name()
+        ));
+    }
+
+    let current = list(client, &group, change.owners).await?;
+    let (changes, unchanged) = plan(&current, &requested, operation, *mirror);
+    println!("{} ({})", group.name(), if change.owners { "owners" } else { "members" });
+    for (member, e) in &unresolved {
+        println!("FAILED  {}: {:#}", member, e);
+    }
+    totals.failed += unresolved.len();
+    for member in &unchanged {
+        println!(
+            "SKIPPED {}: {}",
+            member.name(),
+            if *operation == Operation::Add { "already present" } else { "not present" }
+        );
+    }
+    totals.unchanged += unchanged.len();
+    for (operation, member) in &changes {
+        println!("{:<8}{}", if *operation == Operation::Add { "ADD" } else { "REMOVE" }, member.name());
+    }
+    if change.dry_run || changes.is_empty() {
+        println!();
+        return Ok(());
+    }
+
+    let removals = changes.iter().filter(|(operation, _)| *operation == Operation::Remove).count();
+    if *mirror && removals > 0 && !yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        println!("Remove {} {} from {}?", removals, if change.owners { "owners" } else { "members" }, group.name());
+        if !confirm()? {
+            println!("Aborted, no changes were made to {}.", group.name());
+            println!();
+            return Ok(());
+        }
+    }
+
+    for (operation, member) in changes {
+        let result = match (operation == Operation::Add, change.owners) {
+            (true, false) => remediation::add_group_member(client, &group.id, &member.id).await,
+            (false, false) => remediation::remove_group_member(client, &group.id, &member.id).await,
+            (true, true) => remediation::add_group_owner(client, &group.id, &member.id).await,
+            (false, true) => remediation::remove_group_owner(client, &group.id, &member.id).await,
+        };
+        let verb = if operation == Operation::Add { "added" } else { "removed" };
+        match result {
+            Ok(()) => {
+                if operation == Operation::Add {
+                    totals.added += 1;
+                } else {
+                    totals.removed += 1;
+                }
+                println!("OK      {} {}", verb, member.name());
+            }
+            Err(e) => {
+                totals.failed += 1;
+                println!("FAILED  {}: {:#}", member.name(), e);
+            }
+        }
+    }
+    println!();
+    Ok(())
+}
+
+async fn change_members(client: &GraphClient, request: Request<'_>) -> Result<()> {
+    let change = request.change;
+    let entries = read_entries(change)?;
+    let mut by_group: Vec<(String, Vec<String>)> = Vec::new();
+    for (group, member) in entries {
+        match by_group.iter_mut().find(|(existing, _)| existing.eq_ignore_ascii_case(&group)) {
+            Some((_, members)) => members.push(member),
+            None => by_group.push((group, vec![member])),
+        }
+    }
+
+    let mut totals = Totals {
+        added: 0,
+        removed: 0,
+        unchanged: 0,
+        failed: 0,
+    };
+    let mut failed_groups = 0;
+    let mut resolved = HashMap::new();
+    for (group, members) in &by_group {
+        if let Err(e) = apply(client, &request, group, members, &mut resolved, &mut totals).await {
+            failed_groups += 1;
+            println!("FAILED  {}: {:#}", group, e);
+            println!();
+        }
+    }
+
+    if change.dry_run {
+        println!("Dry run, no changes were made.");
+    } else {
+        println!(
+            "Processed {} groups: {} added, {} removed, {} unchanged, {} failed",
+            by_group.len(),
+            totals.added,
+            totals.removed,
+            totals.unchanged,
+            totals.failed
+        );
+    }
+    client.stats().summary().print();
+    if failed_groups > 0 {
+        return Err(anyhow::anyhow!("{} of {} groups failed", failed_groups, by_group.len()));
+    }
+    if totals.failed > 0 {
+        return Err(anyhow::anyhow!("{} changes failed", totals.failed));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting groups");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&match &config.command {
+        Commands::List { .. } => vec!["Group.Read.All", "User.ReadBasic.All"],
+        Commands::Add { change, .. } | Commands::Remove { change } => match (change.dry_run, change.owners) {
+            (true, _) => vec!["Group.Read.All", "User.ReadBasic.All"],
+            (false, false) => vec!["Group.Read.All", "GroupMember.ReadWrite.All", "User.ReadBasic.All"],
+            (false, true) => vec!["Group.ReadWrite.All", "User.ReadBasic.All"],
+        },
+    })?;
+
+    match &config.command {
+        Commands::List { group, owners, output } => {
+            let group = resolve_group(&client, group).await?;
+            let members = list(&client, &group, *owners).await?;
+            print!("{}", render_list(&group, &members, *owners, output)?);
+            if output != "json" {
+                client.stats().summary().print();
+            }
+        }
+        Commands::Add { change, mirror, yes } => {
+            let request = Request {
+                change,
+                operation: Operation::Add,
+                mirror: *mirror,
+                yes: *yes,
+            };
+            change_members(&client, request).await?
+        }
+        Commands::Remove { change } => {
+            let request = Request {
+                change,
+                operation: Operation::Remove,
+                mirror: false,
+                yes: false,
+            };
+            change_members(&client, request).await?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn member_lists_match_golden_files() {
+        let client = golden::fixture_client();
+        let group = resolve_group(&client, "Sales").await.unwrap();
+        assert_eq!(group.read_only_reason(), None);
+        let members = list(&client, &group, false).await.unwrap();
+        golden::assert_reports("members", &["text", "csv", "json"], |output| render_list(&group, &members, false, output));
+    }
+
+    fn member(id: &str, upn: &str) -> Member {
+        Member {
+            id: id.to_string(),
+            odata_type: "#microsoft.graph.user".to_string(),
+            display_name: None,
+            user_principal_name: Some(upn.to_string()),
+            mail: None,
+        }
+    }
+
+    #[test]
+    fn plans_adds_removes_and_mirrors() {
+        let current = [member("1", "jane@contoso.com"), member("2", "john@contoso.com"), member("3", "kim@contoso.com")];
+        let requested = [member("2", "john@contoso.com"), member("4", "lee@contoso.com")];
+        let names = |(changes, unchanged): (Vec<(Operation, &Member)>, Vec<&Member>)| {
+            (
+                changes
+                    .iter()
+                    .map(|(operation, member)| format!("{:?} {}", operation, member.name()))
+                    .collect::<Vec<_>>(),
+                unchanged.iter().map(|member| member.name().to_string()).collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(
+            names(plan(&current, &requested, &Operation::Add, false)),
+            (vec!["Add lee@contoso.com".to_string()], vec!["john@contoso.com".to_string()])
+        );
+        assert_eq!(
+            names(plan(&current, &requested, &Operation::Remove, false)),
+            (vec!["Remove john@contoso.com".to_string()], vec!["lee@contoso.com".to_string()])
+        );
+        assert_eq!(
+            names(plan(&current, &requested, &Operation::Add, true)),
+            (
+                vec![
+                    "Add lee@contoso.com".to_string(),
+                    "Remove jane@contoso.com".to_string(),
+                    "Remove kim@contoso.com".to_string()
+                ],
+                vec!["john@contoso.com".to_string()]
+            )
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::licenses::{self, SubscribedSku};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::fmt::{self, Write};
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(name = "license_consumption")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports purchased, consumed, and available license seats per SKU and exits with a monitoring status code, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 5, help = "Warn when this many seats or fewer are available")]
+    warn_remaining: i64,
+
+    #[arg(long, default_value_t = 0, help = "Critical when this many seats or fewer are available")]
+    critical_remaining: i64,
+
+    #[arg(long, value_delimiter = ',', help = "SKU part numbers to check (repeatable or comma separated); every subscribed SKU by default")]
+    sku: Vec<String>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+// Ordered so the worst status of all SKUs is the maximum. The exit codes follow the
+// Nagios plugin convention, which most monitoring systems understand.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl Status {
+    fn exit_code(self) -> u8 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+        })
+    }
+}
+
+const UNKNOWN_EXIT_CODE: u8 = 3;
+
+#[derive(Serialize, Debug)]
+struct SkuConsumption {
+    sku: String,
+    #[serde(rename = "skuPartNumber")]
+    sku_part_number: String,
+    status: Status,
+    enabled: u32,
+    consumed: u32,
+    available: i64,
+    suspended: u32,
+    warning: u32,
+    #[serde(rename = "capabilityStatus")]
+    capability_status: String,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Seats in warning still work until the grace period ends, so they count as available but
+// raise at least a warning. Available is negative when more seats are assigned than purchased.
+// Subscriptions without usable or consumed seats have lapsed and are left out.
+fn consumption(skus: Vec<SubscribedSku>, checked: &[String], warn_remaining: i64, critical_remaining: i64) -> Vec<SkuConsumption> {
+    let mut rows: Vec<SkuConsumption> = skus
+        .into_iter()
+        .filter(|sku| checked.is_empty() || checked.iter().any(|part_number| part_number.eq_ignore_ascii_case(&sku.sku_part_number)))
+        .filter(|sku| sku.prepaid_units.enabled + sku.prepaid_units.warning > 0 || sku.consumed_units > 0)
+        .map(|sku| {
+            let units = &sku.prepaid_units;
+            let available = i64::from(units.enabled) + i64::from(units.warning) - i64::from(sku.consumed_units);
+            SkuConsumption {
+                sku: sku.display_name(),
+                status: if available <= critical_remaining {
+                    Status::Critical
+                } else if available <= warn_remaining || units.warning > 0 {
+                    Status::Warning
+                } else {
+                    Status::Ok
+                },
+                enabled: units.enabled,
+                consumed: sku.consumed_units,
+                available,
+                suspended: units.suspended,
+                warning: units.warning,
+                capability_status: sku.capability_status.unwrap_or_else(|| "Enabled".to_string()),
+                sku_part_number: sku.sku_part_number,
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| b.status.cmp(&a.status).then_with(|| a.sku.cmp(&b.sku)));
+    rows
+}
+
+fn overall_status(rows: &[SkuConsumption]) -> Status {
+    rows.iter().map(|row| row.status).max().unwrap_or(Status::Ok)
+}
+
+fn render_report(rows: &[SkuConsumption], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let status = overall_status(rows);
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "status": status,
+                "skus": rows,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "sku,skuPartNumber,status,enabled,consumed,available,suspended,warning,capabilityStatus")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.sku),
+                    row.sku_part_number,
+                    row.status,
+                    row.enabled,
+                    row.consumed,
+                    row.available,
+                    row.suspended,
+                    row.warning,
+                    row.capability_status
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<45} {:<8} {:>8} {:>8} {:>9} {:>9} {:>7}",
+                "SKU", "STATUS", "ENABLED", "CONSUMED", "AVAILABLE", "SUSPENDED", "WARNING"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{:<45} {:<8} {:>8} {:>8} {:>9} {:>9} {:>7}",
+                    row.sku,
+                    row.status.to_string(),
+                    row.enabled,
+                    row.consumed,
+                    row.available,
+                    row.suspended,
+                    row.warning
+                )?;
+            }
+            writeln!(out)?;
+            let short: Vec<String> = rows
+                .iter()
+                .filter(|row| row.status != Status::Ok)
+                .map(|row| match row.warning {
+                    0 => format!("{} {} available", row.sku_part_number, row.available),
+                    warning => format!("{} {} available, {} in grace period", row.sku_part_number, row.available, warning),
+                })
+                .collect();
+            if short.is_empty() {
+                writeln!(out, "{} - {} SKUs have enough seats available", status, rows.len())?;
+            } else {
+                writeln!(out, "{} - {} of {} SKUs are running out of seats: {}", status, short.len(), rows.len(), short.join("; "))?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+async fn run(config: &AppConfig) -> Result<Status> {
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Organization.Read.All"])?;
+
+    let phase = client.stats().phase("list subscribed SKUs");
+    let skus = licenses::subscribed_skus(&client).await?;
+    drop(phase);
+
+    let rows = consumption(skus, &config.sku, config.warn_remaining, config.critical_remaining);
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&rows, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(overall_status(&rows))
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting license_consumption");
+    debug!("Configuration: {:?}", config);
+
+    // Failures exit with the UNKNOWN status so a monitor does not read them as a warning.
+    match run(&config).await {
+        Ok(status) => ExitCode::from(status.exit_code()),
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(UNKNOWN_EXIT_CODE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let skus = licenses::subscribed_skus(&client).await.unwrap();
+        let rows = consumption(skus, &[], 5, 0);
+        assert_eq!(overall_status(&rows).exit_code(), 2);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&rows, output, &Summary::fixture()));
+    }
+
+    #[test]
+    fn applies_thresholds_to_checked_skus() {
+        let sku = |part_number: &str, enabled: u32, consumed: u32| SubscribedSku {
+            sku_id: part_number.to_string(),
+            sku_part_number: part_number.to_string(),
+            consumed_units: consumed,
+            prepaid_units: licenses::PrepaidUnits {
+                enabled,
+                ..Default::default()
+            },
+            capability_status: None,
+            service_plans: Vec::new(),
+        };
+        let skus = || vec![sku("SPE_E3", 100, 90), sku("POWER_BI_PRO", 10, 6), sku("VISIOCLIENT", 0, 0)];
+
+        let rows = consumption(skus(), &[], 5, 0);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(overall_status(&rows), Status::Warning);
+
+        let rows = consumption(skus(), &["spe_e3".to_string()], 10, 0);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].status, Status::Warning);
+        assert_eq!(overall_status(&consumption(skus(), &["SPE_E3".to_string()], 5, 0)), Status::Ok);
+    }
+}
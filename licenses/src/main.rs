@@ -0,0 +1,731 @@
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::cli::is_object_id;
+use graph_common::actionlog::{Change, Undo};
+use graph_common::licenses::{SkuCatalog, SubscribedSku};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::{client, csv, encode, remediation, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::env;
+use std::io::Read;
+
+#[derive(Parser, Debug)]
+#[command(name = "licenses")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Assigns and removes licenses for users in bulk using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Assign licenses, or change their disabled service plans")]
+    Assign {
+        #[command(flatten)]
+        targets: Targets,
+
+        #[arg(short, long, required = true, help = "License by SKU part number or product name, e.g. SPE_E3 or \"Microsoft 365 E3\" (repeatable)")]
+        license: Vec<String>,
+
+        #[arg(long, help = "Service plan to turn off, e.g. YAMMER_ENTERPRISE (repeatable)")]
+        disable_plan: Vec<String>,
+
+        #[arg(long, help = "Service plan to turn back on for users who already have the license (repeatable)")]
+        enable_plan: Vec<String>,
+
+        #[arg(long, help = "Two-letter country code set on users without a usage location")]
+        usage_location: Option<String>,
+
+        #[command(flatten)]
+        run: RunArgs,
+    },
+
+    #[command(about = "Remove directly assigned licenses")]
+    Remove {
+        #[command(flatten)]
+        targets: Targets,
+
+        #[arg(short, long, required = true, help = "License by SKU part number or product name (repeatable)")]
+        license: Vec<String>,
+
+        #[command(flatten)]
+        run: RunArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+#[group(id = "targets", required = true, multiple = true)]
+struct Targets {
+    #[arg(short, long, help = "User Principal Name (repeatable)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "CSV with a upn column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(short, long, help = "Every user in this group, including nested groups, by display name or object id")]
+    group: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    #[arg(long, help = "Show the changes without making them")]
+    dry_run: bool,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+    concurrency: u16,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "usageLocation")]
+    usage_location: Option<String>,
+    #[serde(rename = "licenseAssignmentStates", default)]
+    license_assignment_states: Vec<LicenseAssignmentState>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LicenseAssignmentState {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+    #[serde(rename = "assignedByGroup")]
+    assigned_by_group: Option<String>,
+    #[serde(rename = "disabledPlans", default)]
+    disabled_plans: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DirectoryObject {
+    #[serde(rename = "@odata.type", default)]
+    odata_type: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+}
+
+// A license to assign, with the service plan ids to turn off and on.
+struct Requested<'a> {
+    sku: &'a SubscribedSku,
+    disable: Vec<String>,
+    enable: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Assignment {
+    // (skuId, disabledPlans) to send, and the previous disabledPlans of licenses already held.
+    add: Vec<(String, Vec<String>)>,
+    previous: Vec<(String, Vec<String>)>,
+    set_usage_location: Option<String>,
+    description: String,
+}
+
+type UserPlan = (String, Result<(User, Option<Assignment>)>);
+
+enum Outcome {
+    Changed(String),
+    Unchanged(String),
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn group_users(client: &GraphClient, group: &str) -> Result<Vec<String>> {
+    let group_id = if is_object_id(group) {
+        group.to_string()
+    } else {
+        let mut groups: Vec<Group> = client
+            .path(&["groups"])
+            .filter(&format!("displayName eq {}", encode::odata_string(group)))
+            .select("id")
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to look up group {}", group))?;
+        match groups.len() {
+            0 => return Err(anyhow::anyhow!("No group named {}", group)),
+            1 => groups.remove(0).id,
+            count => {
+                return Err(anyhow::anyhow!(
+                    "{} groups are named {}; pass the group's object id instead",
+                    count,
+                    group
+                ))
+            }
+        }
+    };
+    let members: Vec<DirectoryObject> = client
+        .path(&["groups", &group_id, "transitiveMembers"])
+        .select("id,userPrincipalName")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the members of {}", group))?;
+    Ok(members
+        .into_iter()
+        .filter(|member| member.odata_type == "#microsoft.graph.user")
+        .filter_map(|member| member.user_principal_name)
+        .collect())
+}
+
+async fn read_targets(client: &GraphClient, targets: &Targets) -> Result<Vec<String>> {
+    let mut upns: Vec<String> = targets.upn.iter().map(|upn| upn.trim().to_string()).collect();
+    if let Some(file) = &targets.file {
+        let content = if file == "-" {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .context("Failed to read users from stdin")?;
+            content
+        } else {
+            std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?
+        };
+        let table = csv::Table::parse(&content)?;
+        let column = table.upn_column()?;
+        upns.extend(table.records.iter().map(|record| record.get(column).to_string()));
+    }
+    if let Some(group) = &targets.group {
+        upns.extend(group_users(client, group).await?);
+    }
+
+    let mut seen = HashSet::new();
+    upns.retain(|upn| !upn.is_empty() && seen.insert(upn.to_lowercase()));
+    if upns.is_empty() {
+        return Err(anyhow::anyhow!("No users to process"));
+    }
+    Ok(upns)
+}
+
+fn resolve_skus<'a>(catalog: &'a SkuCatalog, names: &[String]) -> Result<Vec<&'a SubscribedSku>> {
+    names
+        .iter()
+        .map(|name| {
+            catalog
+                .find_by_name(name)
+                .with_context(|| format!("The tenant has no subscription named {}", name))
+        })
+        .collect()
+}
+
+// Each plan name must belong to at least one of the requested licenses, so a typo fails the
+// run instead of being silently ignored.
+fn resolve_plans<'a>(skus: &[&'a SubscribedSku], disable: &[String], enable: &[String]) -> Result<Vec<Requested<'a>>> {
+    for name in disable.iter().chain(enable) {
+        if !skus.iter().any(|sku| sku.service_plan(name).is_some()) {
+            return Err(anyhow::anyhow!("None of the licenses includes a service plan named {}", name));
+        }
+    }
+    let plan_ids = |sku: &SubscribedSku, names: &[String]| {
+        names
+            .iter()
+            .filter_map(|name| sku.service_plan(name))
+            .map(|plan| plan.service_plan_id.to_lowercase())
+            .collect::<Vec<_>>()
+    };
+    Ok(skus
+        .iter()
+        .map(|sku| Requested {
+            sku,
+            disable: plan_ids(sku, disable),
+            enable: plan_ids(sku, enable),
+        })
+        .collect())
+}
+
+fn plan_names(sku: &SubscribedSku, plan_ids: &[String]) -> String {
+    if plan_ids.is_empty() {
+        return "none".to_string();
+    }
+    plan_ids
+        .iter()
+        .map(|id| {
+            sku.service_plans
+                .iter()
+                .find(|plan| plan.service_plan_id.eq_ignore_ascii_case(id))
+                .map_or(id.as_str(), |plan| plan.service_plan_name.as_str())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Licenses inherited from a group are left alone; a direct assignment is added or updated
+// next to them. Returns None when the user already has every license as requested.
+fn plan_assignment(user: &User, requested: &[Requested], usage_location: Option<&str>) -> Result<Option<Assignment>> {
+    let mut add = Vec::new();
+    let mut previous = Vec::new();
+    let mut parts = Vec::new();
+    for request in requested {
+        let sku_id = request.sku.sku_id.to_lowercase();
+        let direct = user
+            .license_assignment_states
+            .iter()
+            .find(|state| state.assigned_by_group.is_none() && state.sku_id.eq_ignore_ascii_case(&sku_id));
+        match direct {
+            Some(state) => {
+                let current: Vec<String> = state.disabled_plans.iter().map(|id| id.to_lowercase()).collect();
+                let mut disabled: Vec<String> = current
+                    .iter()
+                    .chain(&request.disable)
+                    .filter(|id| !request.enable.contains(id))
+                    .cloned()
+                    .collect();
+                disabled.sort();
+                disabled.dedup();
+                let mut sorted_current = current.clone();
+                sorted_current.sort();
+                if disabled == sorted_current {
+                    continue;
+                }
+                parts.push(format!(
+                    "update {}, disabled plans: {}",
+                    request.sku.display_name(),
+                    plan_names(request.sku, &disabled)
+                ));
+                previous.push((sku_id.clone(), current));
+                add.push((sku_id, disabled));
+            }
+            None => {
+                parts.push(if request.disable.is_empty() {
+                    format!("assign {}", request.sku.display_name())
+                } else {
+                    format!(
+                        "assign {} without {}",
+                        request.sku.display_name(),
+                        plan_names(request.sku, &request.disable)
+                    )
+                });
+                add.push((sku_id, request.disable.clone()));
+            }
+        }
+    }
+    if add.is_empty() {
+        return Ok(None);
+    }
+
+    // Only new assignments need a usage location; a license already held is updated as is.
+    let set_usage_location = match (&user.usage_location, usage_location) {
+        (Some(_), _) => None,
+        (None, _) if add.len() == previous.len() => None,
+        (None, Some(location)) => {
+            parts.push(format!("set usage location {}", location));
+            Some(location.to_string())
+        }
+        (None, None) => {
+            return Err(anyhow::anyhow!(
+                "No usage location, which licenses require; pass --usage-location"
+            ))
+        }
+    };
+    Ok(Some(Assignment {
+        add,
+        previous,
+        set_usage_location,
+        description: parts.join("; "),
+    }))
+}
+
+async fn read_user(client: &GraphClient, upn: &str) -> Result<User> {
+    client
+        .user(upn)
+        .resource()
+        .select("id,userPrincipalName,usageLocation,licenseAssignmentStates")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to look up {}", upn))
+}
+
+// The undo removes the licenses this added and puts back the disabled plans of the ones it
+// updated.
+async fn apply_assignment(client: &GraphClient, user: &User, assignment: &Assignment) -> Result<()> {
+    let resource = client.user(&user.id).resource();
+    if let Some(location) = &assignment.set_usage_location {
+        let url = resource.url();
+        let body = json!({ "usageLocation": location.to_uppercase() });
+        let change = Change::new("PATCH", &url, Some(&body)).with_undo(Undo {
+            method: "PATCH".to_string(),
+            url: url.clone(),
+            body: Some(json!({ "usageLocation": null })),
+        });
+        let response = client.send_change(change).await?;
+        client::ensure_success(response)
+            .await
+            .with_context(|| format!("Failed to set the usage location of {}", user.user_principal_name))?;
+    }
+
+    let url = format!("{}/assignLicense", resource.url());
+    let body = json!({
+        "addLicenses": assignment
+            .add
+            .iter()
+            .map(|(sku_id, disabled)| json!({ "skuId": sku_id, "disabledPlans": disabled }))
+            .collect::<Vec<_>>(),
+        "removeLicenses": [],
+    });
+    let added: Vec<&String> = assignment
+        .add
+        .iter()
+        .map(|(sku_id, _)| sku_id)
+        .filter(|sku_id| !assignment.previous.iter().any(|(previous, _)| previous == *sku_id))
+        .collect();
+    let undo = json!({
+        "addLicenses": assignment
+            .previous
+            .iter()
+            .map(|(sku_id, disabled)| json!({ "skuId": sku_id, "disabledPlans": disabled }))
+            .collect::<Vec<_>>(),
+        "removeLicenses": added,
+    });
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "POST".to_string(),
+        url: url.clone(),
+        body: Some(undo),
+    });
+    let response = client.send_change(change).await?;
+    client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to assign licenses to {}", user.user_principal_name))
+}
+
+async fn assign_user(client: &GraphClient, user: &User, assignment: Option<&Assignment>, dry_run: bool) -> Result<Outcome> {
+    let Some(assignment) = assignment else {
+        return Ok(Outcome::Unchanged("already assigned as requested".to_string()));
+    };
+    if !dry_run {
+        apply_assignment(client, user, assignment).await?;
+    }
+    Ok(Outcome::Changed(assignment.description.clone()))
+}
+
+// Only direct assignments can be removed; licenses inherited from a group stay until the user
+// leaves the group.
+async fn remove_user(client: &GraphClient, user: &User, skus: &[&SubscribedSku], dry_run: bool) -> Result<Outcome> {
+    let held = |direct: bool| -> Vec<&SubscribedSku> {
+        skus.iter()
+            .filter(|sku| {
+                user.license_assignment_states.iter().any(|state| {
+                    state.sku_id.eq_ignore_ascii_case(&sku.sku_id) && state.assigned_by_group.is_none() == direct
+                })
+            })
+            .copied()
+            .collect()
+    };
+    let direct = held(true);
+    if direct.is_empty() {
+        let inherited = held(false);
+        return Ok(Outcome::Unchanged(if inherited.is_empty() {
+            "does not have the licenses".to_string()
+        } else {
+            format!(
+                "{} assigned through a group",
+                inherited.iter().map(|sku| sku.display_name()).collect::<Vec<_>>().join(", ")
+            )
+        }));
+    }
+    let description = format!(
+        "remove {}",
+        direct.iter().map(|sku| sku.display_name()).collect::<Vec<_>>().join(", ")
+    );
+    if !dry_run {
+        let sku_ids: Vec<String> = direct.iter().map(|sku| sku.sku_id.clone()).collect();
+        remediation::remove_licenses(client, &user.id, &sku_ids).await?;
+    }
+    Ok(Outcome::Changed(description))
+}
+
+async fn read_users(client: &GraphClient, upns: &[String], concurrency: usize) -> Vec<(String, Result<User>)> {
+    let _phase = client.stats().phase("read users");
+    stream::iter(upns)
+        .map(|upn| async move { (upn.clone(), read_user(client, upn).await) })
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+// Seats are counted before the first change, so a shortage fails the run instead of leaving
+// part of the batch unlicensed.
+fn check_seats(skus: &[&SubscribedSku], plans: &[UserPlan]) -> Result<()> {
+    for sku in skus {
+        let needed = plans
+            .iter()
+            .filter_map(|(_, plan)| plan.as_ref().ok())
+            .filter_map(|(_, assignment)| assignment.as_ref())
+            .filter(|assignment| {
+                assignment
+                    .add
+                    .iter()
+                    .any(|(sku_id, _)| sku_id.eq_ignore_ascii_case(&sku.sku_id))
+                    && !assignment
+                        .previous
+                        .iter()
+                        .any(|(sku_id, _)| sku_id.eq_ignore_ascii_case(&sku.sku_id))
+            })
+            .count() as u32;
+        let available = sku.prepaid_units.enabled.saturating_sub(sku.consumed_units);
+        if needed > available {
+            return Err(anyhow::anyhow!(
+                "{} is needed for {} users but only {} seats are free",
+                sku.display_name(),
+                needed,
+                available
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn run(
+    client: &GraphClient,
+    plans: Vec<UserPlan>,
+    remove: Option<&[&SubscribedSku]>,
+    run: &RunArgs,
+    shutdown: &ShutdownSignal,
+) -> Result<()> {
+    let total = plans.len();
+    let _phase = client.stats().phase(if remove.is_some() { "remove licenses" } else { "assign licenses" });
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let mut results = stream::iter(&plans)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|(upn, plan)| async move {
+            let result = match (plan, remove) {
+                (Err(e), _) => Err(anyhow::anyhow!("{:#}", e)),
+                (Ok((user, _)), Some(skus)) => remove_user(client, user, skus, run.dry_run).await,
+                (Ok((user, assignment)), None) => assign_user(client, user, assignment.as_ref(), run.dry_run).await,
+            };
+            (upn, result)
+        })
+        .buffer_unordered(run.concurrency as usize);
+
+    let mut finished = HashSet::new();
+    let (mut changed, mut unchanged, mut failed) = (0, 0, 0);
+    while let Some((upn, result)) = results.next().await {
+        finished.insert(upn.to_lowercase());
+        match result {
+            Ok(Outcome::Changed(description)) => {
+                changed += 1;
+                println!("{:<8}{}: {}", if run.dry_run { "CHANGE" } else { "OK" }, upn, description);
+            }
+            Ok(Outcome::Unchanged(reason)) => {
+                unchanged += 1;
+                println!("SKIPPED {}: {}", upn, reason);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", upn, e);
+            }
+        }
+    }
+
+    let interrupted = total - finished.len();
+    for (upn, _) in plans.iter().filter(|(upn, _)| !finished.contains(&upn.to_lowercase())) {
+        println!("SKIPPED {}: not started", upn);
+    }
+    println!();
+    println!(
+        "Processed {} of {} users: {} {}, {} unchanged, {} failed, {} skipped",
+        total - interrupted,
+        total,
+        changed,
+        if run.dry_run { "to change" } else { "changed" },
+        unchanged,
+        failed,
+        interrupted
+    );
+    if run.dry_run {
+        println!("Dry run, no changes were made.");
+    }
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} users were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, total));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting licenses");
+    debug!("Configuration: {:?}", config);
+
+    let (targets, license, run_args) = match &config.command {
+        Commands::Assign { targets, license, run, .. } | Commands::Remove { targets, license, run } => {
+            (targets, license, run)
+        }
+    };
+    if let Commands::Assign { usage_location: Some(location), .. } = &config.command {
+        if location.len() != 2 || !location.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(anyhow::anyhow!("Usage location {:?} is not a two-letter country code", location));
+        }
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec![
+        if run_args.dry_run { "User.Read.All" } else { "User.ReadWrite.All|LicenseAssignment.ReadWrite.All" },
+        "Organization.Read.All|Directory.Read.All",
+    ];
+    if targets.group.is_some() {
+        required.push("GroupMember.Read.All");
+    }
+    client.require_permissions(&required)?;
+
+    let catalog = SkuCatalog::load(&client).await?;
+    let skus = resolve_skus(&catalog, license)?;
+    let upns = read_targets(&client, targets).await?;
+    info!("Processing {} users", upns.len());
+    let users = read_users(&client, &upns, run_args.concurrency as usize).await;
+
+    let shutdown = service::shutdown_signal();
+    let result = match &config.command {
+        Commands::Assign {
+            disable_plan,
+            enable_plan,
+            usage_location,
+            ..
+        } => {
+            let requested = resolve_plans(&skus, disable_plan, enable_plan)?;
+            let plans: Vec<_> = users
+                .into_iter()
+                .map(|(upn, user)| {
+                    let plan = user.and_then(|user| {
+                        let assignment = plan_assignment(&user, &requested, usage_location.as_deref())?;
+                        Ok((user, assignment))
+                    });
+                    (upn, plan)
+                })
+                .collect();
+            check_seats(&skus, &plans)?;
+            run(&client, plans, None, run_args, &shutdown).await
+        }
+        Commands::Remove { .. } => {
+            let plans = users
+                .into_iter()
+                .map(|(upn, user)| (upn, user.map(|user| (user, None))))
+                .collect();
+            run(&client, plans, Some(&skus), run_args, &shutdown).await
+        }
+    };
+    client.stats().summary().print();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::licenses::{PrepaidUnits, ServicePlan};
+
+    const E3: &str = "05e9a617-0261-4cee-bb44-138d3ef5d965";
+    const VISIO: &str = "c5928f49-12ba-48f7-ada3-0d743a3601d5";
+
+    fn sku(sku_id: &str, part_number: &str, plans: &[(&str, &str)]) -> SubscribedSku {
+        SubscribedSku {
+            sku_id: sku_id.to_string(),
+            sku_part_number: part_number.to_string(),
+            consumed_units: 0,
+            prepaid_units: PrepaidUnits::default(),
+            capability_status: None,
+            service_plans: plans
+                .iter()
+                .map(|(id, name)| ServicePlan {
+                    service_plan_id: id.to_string(),
+                    service_plan_name: name.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn user(usage_location: Option<&str>, states: &[(&str, Option<&str>, &[&str])]) -> User {
+        User {
+            id: "1".to_string(),
+            user_principal_name: "jane.doe@contoso.com".to_string(),
+            usage_location: usage_location.map(str::to_string),
+            license_assignment_states: states
+                .iter()
+                .map(|(sku_id, group, disabled)| LicenseAssignmentState {
+                    sku_id: sku_id.to_string(),
+                    assigned_by_group: group.map(str::to_string),
+                    disabled_plans: disabled.iter().map(|id| id.to_string()).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn plans_assignments_and_disabled_plans() {
+        let e3 = sku(E3, "SPE_E3", &[("7547a3fe-08ee-4ccb-b430-5077c5041653", "YAMMER_ENTERPRISE"), ("57ff2da0-773e-42df-b2af-ffb7a2317929", "TEAMS1")]);
+        let visio = sku(VISIO, "VISIOCLIENT", &[]);
+        let skus = [&e3, &visio];
+        let requested = resolve_plans(&skus, &["yammer_enterprise".to_string()], &[]).unwrap();
+        assert!(resolve_plans(&skus, &["NOT_A_PLAN".to_string()], &[]).is_err());
+
+        let assignment = plan_assignment(&user(Some("GB"), &[]), &requested, None).unwrap().unwrap();
+        assert_eq!(
+            assignment.add,
+            vec![
+                (E3.to_string(), vec!["7547a3fe-08ee-4ccb-b430-5077c5041653".to_string()]),
+                (VISIO.to_string(), Vec::new())
+            ]
+        );
+        assert_eq!(
+            assignment.description,
+            "assign Microsoft 365 E3 (SPE_E3) without YAMMER_ENTERPRISE; assign Visio Plan 2 (VISIOCLIENT)"
+        );
+
+        // Visio through a group still gets a direct assignment; E3 already held only gains a
+        // disabled plan, keeping the one already disabled.
+        let held = user(
+            None,
+            &[(E3, None, &["57ff2da0-773e-42df-b2af-ffb7a2317929"]), (VISIO, Some("group-id"), &[])],
+        );
+        assert!(plan_assignment(&held, &requested, None).is_err());
+        let assignment = plan_assignment(&held, &requested, Some("GB")).unwrap().unwrap();
+        assert_eq!(
+            assignment.add[0],
+            (
+                E3.to_string(),
+                vec![
+                    "57ff2da0-773e-42df-b2af-ffb7a2317929".to_string(),
+                    "7547a3fe-08ee-4ccb-b430-5077c5041653".to_string()
+                ]
+            )
+        );
+        assert_eq!(assignment.previous, vec![(E3.to_string(), vec!["57ff2da0-773e-42df-b2af-ffb7a2317929".to_string()])]);
+        assert_eq!(assignment.set_usage_location.as_deref(), Some("GB"));
+
+        let requested = resolve_plans(&[&e3], &[], &[]).unwrap();
+        assert_eq!(plan_assignment(&held, &requested, None).unwrap(), None);
+        let requested = resolve_plans(&[&e3], &[], &["TEAMS1".to_string()]).unwrap();
+        let assignment = plan_assignment(&held, &requested, None).unwrap().unwrap();
+        assert_eq!(assignment.add, vec![(E3.to_string(), Vec::new())]);
+        assert_eq!(assignment.description, "update Microsoft 365 E3 (SPE_E3), disabled plans: none");
+    }
+}
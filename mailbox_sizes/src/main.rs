@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::reports::{Mailbox, MailboxUsage};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "mailbox_sizes")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists mailbox sizes, item counts, and quota status, flagging mailboxes close to their quota, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100), help = "Flag mailboxes that use at least this percentage of their prohibit send quota")]
+    threshold: u8,
+
+    #[arg(long, help = "List only flagged mailboxes")]
+    flagged: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct MailboxSize {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "recipientType")]
+    recipient_type: String,
+    #[serde(rename = "itemCount")]
+    item_count: Option<u64>,
+    #[serde(rename = "storageUsedBytes")]
+    storage_used: Option<u64>,
+    #[serde(rename = "prohibitSendQuotaBytes")]
+    prohibit_send_quota: Option<u64>,
+    #[serde(rename = "percentUsed")]
+    percent_used: Option<f64>,
+    // "ok", "near quota", "warning quota", "send prohibited", "send and receive prohibited",
+    // or "unknown" when the report has no size or quota for the mailbox.
+    #[serde(rename = "quotaStatus")]
+    quota_status: &'static str,
+    #[serde(rename = "hasArchive")]
+    has_archive: Option<bool>,
+    #[serde(rename = "lastActivity")]
+    last_activity: Option<String>,
+    flagged: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn quota_status(mailbox: &Mailbox, percent_used: Option<f64>, threshold: u8) -> &'static str {
+    let Some(used) = mailbox.storage_used else {
+        return "unknown";
+    };
+    let reached = |quota: Option<u64>| quota.is_some_and(|quota| used >= quota);
+    if reached(mailbox.prohibit_send_receive_quota) {
+        "send and receive prohibited"
+    } else if reached(mailbox.prohibit_send_quota) {
+        "send prohibited"
+    } else if reached(mailbox.issue_warning_quota) {
+        "warning quota"
+    } else if percent_used.is_some_and(|percent| percent >= f64::from(threshold)) {
+        "near quota"
+    } else if percent_used.is_none() {
+        "unknown"
+    } else {
+        "ok"
+    }
+}
+
+// Sorted with the fullest mailboxes first. Quotas are compared with the prohibit send quota,
+// the point where users notice.
+fn mailbox_sizes(usage: &MailboxUsage, threshold: u8) -> Vec<MailboxSize> {
+    let mut rows: Vec<MailboxSize> = usage
+        .mailboxes()
+        .map(|mailbox| {
+            let percent_used = match (mailbox.storage_used, mailbox.prohibit_send_quota) {
+                (Some(used), Some(quota)) if quota > 0 => Some((used as f64 * 1000.0 / quota as f64).round() / 10.0),
+                _ => None,
+            };
+            let quota_status = quota_status(mailbox, percent_used, threshold);
+            MailboxSize {
+                user_principal_name: mailbox.user_principal_name.clone(),
+                display_name: mailbox.display_name.clone(),
+                recipient_type: mailbox.recipient_type.clone(),
+                item_count: mailbox.item_count,
+                storage_used: mailbox.storage_used,
+                prohibit_send_quota: mailbox.prohibit_send_quota,
+                percent_used,
+                quota_status,
+                has_archive: mailbox.has_archive,
+                last_activity: mailbox.last_activity.map(|date| date.to_string()),
+                flagged: quota_status != "ok" && quota_status != "unknown",
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        b.percent_used
+            .unwrap_or(-1.0)
+            .total_cmp(&a.percent_used.unwrap_or(-1.0))
+            .then_with(|| a.user_principal_name.to_lowercase().cmp(&b.user_principal_name.to_lowercase()))
+    });
+    rows
+}
+
+fn gigabytes(bytes: Option<u64>) -> String {
+    bytes.map_or_else(|| "-".to_string(), |bytes| format!("{:.1}", bytes as f64 / 1073741824.0))
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn render_report(rows: &[MailboxSize], total: usize, threshold: u8, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "mailboxes": rows,
+                "totalMailboxes": total,
+                "threshold": threshold,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "userPrincipalName,displayName,recipientType,itemCount,storageUsedBytes,prohibitSendQuotaBytes,percentUsed,quotaStatus,hasArchive,lastActivity,flagged"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.user_principal_name),
+                    csv::field(&row.display_name),
+                    row.recipient_type,
+                    optional(row.item_count),
+                    optional(row.storage_used),
+                    optional(row.prohibit_send_quota),
+                    optional(row.percent_used),
+                    row.quota_status,
+                    optional(row.has_archive),
+                    optional(row.last_activity.as_deref()),
+                    row.flagged
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<8} {:>8} {:>8} {:>6} {:>8} {:<7} STATUS",
+                "MAILBOX", "TYPE", "SIZE GB", "QUOTA GB", "USED", "ITEMS", "ARCHIVE"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{:<35} {:<8} {:>8} {:>8} {:>6} {:>8} {:<7} {}",
+                    row.user_principal_name,
+                    row.recipient_type,
+                    gigabytes(row.storage_used),
+                    gigabytes(row.prohibit_send_quota),
+                    row.percent_used.map_or_else(|| "-".to_string(), |percent| format!("{:.1}%", percent)),
+                    row.item_count.map_or_else(|| "-".to_string(), |count| count.to_string()),
+                    match row.has_archive {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "-",
+                    },
+                    row.quota_status
+                )?;
+            }
+            writeln!(out)?;
+            let flagged: Vec<&MailboxSize> = rows.iter().filter(|row| row.flagged).collect();
+            writeln!(
+                out,
+                "{} of {} mailboxes listed, {} GB in total; {} at or above {}% of their quota or past a quota, {} of them without an archive",
+                rows.len(),
+                total,
+                gigabytes(Some(rows.iter().filter_map(|row| row.storage_used).sum())),
+                flagged.len(),
+                threshold,
+                flagged.iter().filter(|row| row.has_archive == Some(false)).count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn load_usage(client: &GraphClient) -> Result<MailboxUsage> {
+    let _phase = client.stats().phase("read usage report");
+    let usage = MailboxUsage::load(client).await?;
+    if !usage.is_empty() && usage.mailboxes().all(|mailbox| !mailbox.user_principal_name.contains('@')) {
+        warn!("The mailbox usage report conceals user names, so mailboxes are listed by hash; turn off concealed names in the Microsoft 365 admin center reports settings to see them");
+    }
+    info!("The mailbox usage report lists {} mailboxes", usage.len());
+    Ok(usage)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting mailbox_sizes");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Reports.Read.All"])?;
+
+    let usage = load_usage(&client).await?;
+    let mut rows = mailbox_sizes(&usage, config.threshold);
+    let total = rows.len();
+    if config.flagged {
+        rows.retain(|row| row.flagged);
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&rows, total, config.threshold, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let usage = load_usage(&client).await.unwrap();
+        let rows = mailbox_sizes(&usage, 90);
+        assert_eq!(rows.iter().filter(|row| row.flagged).count(), 3);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&rows, rows.len(), 90, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::stats::Summary;
+use graph_common::{csv, encode, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "meeting_attendance")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Exports the attendance reports of a Microsoft Teams meeting, with join and leave times per participant, using the Microsoft API")]
+#[command(group(ArgGroup::new("meeting").required(true).args(["join_url", "start"])))]
+struct AppConfig {
+    #[arg(short = 'u', long, help = "User Principal Name of the meeting organizer")]
+    organizer: String,
+
+    #[arg(short, long, help = "Join URL of the meeting")]
+    join_url: Option<String>,
+
+    #[arg(short, long, help = "Start of the meeting in the organizer's calendar, as YYYY-MM-DDTHH:MM in UTC or an RFC 3339 time")]
+    start: Option<String>,
+
+    #[arg(long, value_name = "MINUTES", help = "Mark participants who attended for less than this many minutes")]
+    required_minutes: Option<u32>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Event {
+    subject: Option<String>,
+    start: EventTime,
+    #[serde(rename = "onlineMeeting")]
+    online_meeting: Option<OnlineMeetingInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventTime {
+    #[serde(rename = "dateTime")]
+    date_time: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OnlineMeetingInfo {
+    #[serde(rename = "joinUrl")]
+    join_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OnlineMeeting {
+    id: String,
+    subject: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttendanceReport {
+    id: String,
+    #[serde(rename = "meetingStartDateTime")]
+    meeting_start_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "meetingEndDateTime")]
+    meeting_end_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "totalParticipantCount")]
+    total_participant_count: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AttendanceRecord {
+    #[serde(rename = "emailAddress")]
+    email_address: Option<String>,
+    identity: Option<Identity>,
+    role: Option<String>,
+    #[serde(rename = "totalAttendanceInSeconds", default)]
+    total_attendance_in_seconds: u64,
+    #[serde(rename = "attendanceIntervals", default)]
+    attendance_intervals: Vec<Interval>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Identity {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct Interval {
+    #[serde(rename = "joinDateTime")]
+    join_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "leaveDateTime")]
+    leave_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "durationInSeconds", default)]
+    duration_in_seconds: u64,
+}
+
+// One occurrence of the meeting; a recurring meeting has a report per occurrence.
+#[derive(Serialize, Debug)]
+struct Occurrence {
+    #[serde(rename = "reportId")]
+    report_id: String,
+    #[serde(rename = "meetingStart")]
+    meeting_start: Option<DateTime<Utc>>,
+    #[serde(rename = "meetingEnd")]
+    meeting_end: Option<DateTime<Utc>>,
+    #[serde(rename = "totalParticipantCount")]
+    total_participant_count: Option<u32>,
+    participants: Vec<Participant>,
+}
+
+#[derive(Serialize, Debug)]
+struct Participant {
+    name: String,
+    email: String,
+    role: String,
+    #[serde(rename = "totalSeconds")]
+    total_seconds: u64,
+    #[serde(rename = "metRequirement", skip_serializing_if = "Option::is_none")]
+    met_requirement: Option<bool>,
+    intervals: Vec<Interval>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_start(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(&value.trim().replacen(' ', "T", 1), "%Y-%m-%dT%H:%M") {
+        return Ok(time.and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| anyhow!("{:?} is not a time (YYYY-MM-DDTHH:MM in UTC, or RFC 3339)", value))
+}
+
+// The online meeting of a calendar event is only linked through its join URL, so the event
+// that starts closest to the given time is looked up in the organizer's calendar first.
+async fn join_url_at(client: &GraphClient, organizer: &str, start: DateTime<Utc>) -> Result<String> {
+    let events: Vec<Event> = client
+        .user(organizer)
+        .resource()
+        .segment("calendarView")
+        .query("startDateTime", &timestamp(start - Duration::hours(1)))
+        .query("endDateTime", &timestamp(start + Duration::hours(1)))
+        .select("subject,start,onlineMeeting")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to read the calendar of {}", organizer))?;
+    let distance = |event: &Event| {
+        NaiveDateTime::parse_from_str(event.start.date_time.get(..19).unwrap_or_default(), "%Y-%m-%dT%H:%M:%S")
+            .map(|time| (time.and_utc() - start).num_seconds().abs())
+            .unwrap_or(i64::MAX)
+    };
+    let event = events
+        .iter()
+        .filter(|event| event.online_meeting.as_ref().is_some_and(|meeting| meeting.join_url.is_some()))
+        .min_by_key(|event| distance(event))
+        .ok_or_else(|| anyhow!("{} has no online meeting around {}", organizer, timestamp(start)))?;
+    info!(
+        "Found \"{}\" at {} in the calendar of {}",
+        event.subject.as_deref().unwrap_or_default(),
+        event.start.date_time,
+        organizer
+    );
+    Ok(event
+        .online_meeting
+        .as_ref()
+        .and_then(|meeting| meeting.join_url.clone())
+        .unwrap_or_default())
+}
+
+async fn find_meeting(client: &GraphClient, organizer_id: &str, join_url: &str) -> Result<OnlineMeeting> {
+    let meetings: Vec<OnlineMeeting> = client
+        .path(&["users", organizer_id, "onlineMeetings"])
+        .filter(&format!("JoinWebUrl eq {}", encode::odata_string(join_url)))
+        .get_all()
+        .await
+        .context("Failed to look up the online meeting; the app needs a Teams application access policy for the organizer")?;
+    meetings
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No online meeting organized by this user has that join URL"))
+}
+
+fn to_participant(record: AttendanceRecord, required_minutes: Option<u32>) -> Participant {
+    Participant {
+        name: record
+            .identity
+            .and_then(|identity| identity.display_name)
+            .unwrap_or_default(),
+        email: record.email_address.unwrap_or_default().to_lowercase(),
+        role: record.role.unwrap_or_default(),
+        total_seconds: record.total_attendance_in_seconds,
+        met_requirement: required_minutes.map(|minutes| record.total_attendance_in_seconds >= minutes as u64 * 60),
+        intervals: record.attendance_intervals,
+    }
+}
+
+// Occurrences are kept when no start was given, or when they started within 12 hours of it,
+// so that the other occurrences of a recurring meeting are left out.
+async fn attendance(
+    client: &GraphClient,
+    organizer_id: &str,
+    meeting_id: &str,
+    start: Option<DateTime<Utc>>,
+    required_minutes: Option<u32>,
+) -> Result<Vec<Occurrence>> {
+    let meeting = client.path(&["users", organizer_id, "onlineMeetings", meeting_id]);
+    let reports: Vec<AttendanceReport> = meeting
+        .segment("attendanceReports")
+        .get_all()
+        .await
+        .context("Failed to list the attendance reports")?;
+    let mut occurrences = Vec::new();
+    for report in reports {
+        let near = |meeting_start: DateTime<Utc>| start.is_none_or(|start| (meeting_start - start).num_hours().abs() < 12);
+        if !report.meeting_start_date_time.is_some_and(near) {
+            continue;
+        }
+        let records: Vec<AttendanceRecord> = client
+            .path(&["users", organizer_id, "onlineMeetings", meeting_id, "attendanceReports", &report.id, "attendanceRecords"])
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to read attendance report {}", report.id))?;
+        let mut participants: Vec<Participant> = records
+            .into_iter()
+            .map(|record| to_participant(record, required_minutes))
+            .collect();
+        participants.sort_by_key(|participant| participant.name.to_lowercase());
+        occurrences.push(Occurrence {
+            report_id: report.id,
+            meeting_start: report.meeting_start_date_time,
+            meeting_end: report.meeting_end_date_time,
+            total_participant_count: report.total_participant_count,
+            participants,
+        });
+    }
+    occurrences.sort_by_key(|occurrence| occurrence.meeting_start);
+    Ok(occurrences)
+}
+
+fn minutes(seconds: u64) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn render_report(subject: &str, occurrences: &[Occurrence], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let time = |value: Option<DateTime<Utc>>| value.map(timestamp).unwrap_or_default();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "subject": subject,
+                "occurrences": occurrences,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "subject,meetingStart,meetingEnd,name,email,role,totalSeconds,metRequirement,joinDateTime,leaveDateTime,durationSeconds"
+            )?;
+            for occurrence in occurrences {
+                for participant in &occurrence.participants {
+                    for interval in &participant.intervals {
+                        writeln!(
+                            out,
+                            "{},{},{},{},{},{},{},{},{},{},{}",
+                            csv::field(subject),
+                            time(occurrence.meeting_start),
+                            time(occurrence.meeting_end),
+                            csv::field(&participant.name),
+                            csv::field(&participant.email),
+                            participant.role,
+                            participant.total_seconds,
+                            participant.met_requirement.map(|met| met.to_string()).unwrap_or_default(),
+                            time(interval.join_date_time),
+                            time(interval.leave_date_time),
+                            interval.duration_in_seconds
+                        )?;
+                    }
+                }
+            }
+        }
+        _ => {
+            for occurrence in occurrences {
+                writeln!(
+                    out,
+                    "{} from {} to {}, {} participants",
+                    subject,
+                    time(occurrence.meeting_start),
+                    time(occurrence.meeting_end),
+                    occurrence.participants.len()
+                )?;
+                writeln!(out)?;
+                writeln!(
+                    out,
+                    "{:<24} {:<30} {:<10} {:<20} {:<20} {:>8}  NOTE",
+                    "NAME", "EMAIL", "ROLE", "FIRST JOIN", "LAST LEAVE", "ATTENDED"
+                )?;
+                for participant in &occurrence.participants {
+                    let first_join = participant.intervals.iter().filter_map(|interval| interval.join_date_time).min();
+                    let last_leave = participant.intervals.iter().filter_map(|interval| interval.leave_date_time).max();
+                    let note = match participant.met_requirement {
+                        Some(false) => "below required time".to_string(),
+                        _ if participant.intervals.len() > 1 => format!("joined {} times", participant.intervals.len()),
+                        _ => String::new(),
+                    };
+                    let line = format!(
+                        "{:<24} {:<30} {:<10} {:<20} {:<20} {:>8}  {}",
+                        participant.name,
+                        participant.email,
+                        participant.role,
+                        time(first_join),
+                        time(last_leave),
+                        minutes(participant.total_seconds),
+                        note
+                    );
+                    writeln!(out, "{}", line.trim_end())?;
+                }
+                writeln!(out)?;
+            }
+            match occurrences.len() {
+                1 => writeln!(out, "1 attendance report")?,
+                count => writeln!(out, "{} attendance reports", count)?,
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting meeting_attendance");
+    debug!("Configuration: {:?}", config);
+
+    let start = config.start.as_deref().map(parse_start).transpose()?;
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["OnlineMeetings.Read.All", "OnlineMeetingArtifact.Read.All", "User.Read.All"])?;
+
+    let organizer: User = client
+        .user(&config.organizer)
+        .resource()
+        .select("id")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to look up {}", config.organizer))?;
+    let join_url = match (&config.join_url, start) {
+        (Some(join_url), _) => join_url.clone(),
+        (None, Some(start)) => {
+            client.require_permissions(&["Calendars.Read"])?;
+            join_url_at(&client, &config.organizer, start).await?
+        }
+        (None, None) => return Err(anyhow!("--join-url or --start is required")),
+    };
+    let meeting = find_meeting(&client, &organizer.id, &join_url).await?;
+    let occurrences = attendance(&client, &organizer.id, &meeting.id, start, config.required_minutes).await?;
+    if occurrences.is_empty() {
+        info!("The meeting has no attendance reports; they are only created once someone has joined");
+    }
+    let run_stats = client.stats().summary();
+    let subject = meeting.subject.unwrap_or_default();
+    print!("{}", render_report(&subject, &occurrences, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let start = parse_start("2026-10-14T09:00").unwrap();
+        let join_url = join_url_at(&client, "trainer@contoso.com", start).await.unwrap();
+        let meeting = find_meeting(&client, "u-trainer", &join_url).await.unwrap();
+        let occurrences = attendance(&client, "u-trainer", &meeting.id, Some(start), Some(45)).await.unwrap();
+        assert_eq!(occurrences.len(), 1);
+        let subject = meeting.subject.unwrap_or_default();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&subject, &occurrences, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "mfa_registration")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Reports the MFA and self-service password reset registration of every user, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "List only users who are not registered for MFA")]
+    only_unregistered: bool,
+
+    #[arg(long, help = "List only users who hold an admin role")]
+    only_admins: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct RegistrationDetails {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "userDisplayName")]
+    user_display_name: Option<String>,
+    #[serde(rename = "userType")]
+    user_type: Option<String>,
+    #[serde(rename = "isAdmin", default)]
+    is_admin: bool,
+    #[serde(rename = "isMfaRegistered", default)]
+    is_mfa_registered: bool,
+    #[serde(rename = "isMfaCapable", default)]
+    is_mfa_capable: bool,
+    #[serde(rename = "isSsprRegistered", default)]
+    is_sspr_registered: bool,
+    #[serde(rename = "isSsprCapable", default)]
+    is_sspr_capable: bool,
+    #[serde(rename = "isPasswordlessCapable", default)]
+    is_passwordless_capable: bool,
+    #[serde(rename = "methodsRegistered", default)]
+    methods_registered: Vec<String>,
+    #[serde(rename = "isSystemPreferredAuthenticationMethodEnabled", default)]
+    is_system_preferred_enabled: bool,
+    #[serde(rename = "systemPreferredAuthenticationMethods", default)]
+    system_preferred_methods: Vec<String>,
+    #[serde(rename = "userPreferredMethodForSecondaryAuthentication")]
+    user_preferred_method: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Registration {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    #[serde(rename = "userType")]
+    user_type: String,
+    #[serde(rename = "isAdmin")]
+    is_admin: bool,
+    #[serde(rename = "mfaRegistered")]
+    mfa_registered: bool,
+    #[serde(rename = "mfaCapable")]
+    mfa_capable: bool,
+    #[serde(rename = "passwordlessCapable")]
+    passwordless_capable: bool,
+    #[serde(rename = "ssprRegistered")]
+    sspr_registered: bool,
+    #[serde(rename = "ssprCapable")]
+    sspr_capable: bool,
+    #[serde(rename = "defaultMethod")]
+    default_method: String,
+    #[serde(rename = "methodsRegistered")]
+    methods_registered: Vec<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// The method users are prompted for first: the system-preferred method when that is enabled,
+// otherwise the one the user picked. "none" from Graph means no default is set.
+fn default_method(details: &RegistrationDetails) -> String {
+    let method = if details.is_system_preferred_enabled && !details.system_preferred_methods.is_empty() {
+        details.system_preferred_methods.first()
+    } else {
+        details.user_preferred_method.as_ref()
+    };
+    method
+        .filter(|method| !method.is_empty() && !method.eq_ignore_ascii_case("none"))
+        .cloned()
+        .unwrap_or_default()
+}
+
+// The filters run in Graph, so large tenants only page through the users that are listed.
+async fn list_registrations(client: &GraphClient, only_unregistered: bool, only_admins: bool) -> Result<Vec<Registration>> {
+    let mut filters = Vec::new();
+    if only_unregistered {
+        filters.push("isMfaRegistered eq false");
+    }
+    if only_admins {
+        filters.push("isAdmin eq true");
+    }
+    let mut request = client.path(&["reports", "authenticationMethods", "userRegistrationDetails"]);
+    if !filters.is_empty() {
+        request = request.filter(&filters.join(" and "));
+    }
+    let details: Vec<RegistrationDetails> = request
+        .get_all()
+        .await
+        .context("Failed to fetch the user registration details")?;
+
+    let mut registrations: Vec<Registration> = details
+        .into_iter()
+        .map(|details| Registration {
+            default_method: default_method(&details),
+            display_name: details.user_display_name.unwrap_or_default(),
+            user_type: details.user_type.unwrap_or_default(),
+            is_admin: details.is_admin,
+            mfa_registered: details.is_mfa_registered,
+            mfa_capable: details.is_mfa_capable,
+            passwordless_capable: details.is_passwordless_capable,
+            sspr_registered: details.is_sspr_registered,
+            sspr_capable: details.is_sspr_capable,
+            methods_registered: details.methods_registered,
+            user_principal_name: details.user_principal_name,
+        })
+        .collect();
+    registrations.sort_by_key(|registration| registration.user_principal_name.to_lowercase());
+    Ok(registrations)
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn render_report(registrations: &[Registration], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "users": registrations,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "userPrincipalName,displayName,userType,isAdmin,mfaRegistered,mfaCapable,passwordlessCapable,ssprRegistered,ssprCapable,defaultMethod,methodsRegistered"
+            )?;
+            for registration in registrations {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&registration.user_principal_name),
+                    csv::field(&registration.display_name),
+                    registration.user_type,
+                    registration.is_admin,
+                    registration.mfa_registered,
+                    registration.mfa_capable,
+                    registration.passwordless_capable,
+                    registration.sspr_registered,
+                    registration.sspr_capable,
+                    registration.default_method,
+                    csv::field(&registration.methods_registered.join(";"))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:<5} {:<10} {:<4} {:<5} {:<20} METHODS",
+                "USER", "ADMIN", "MFA", "SSPR", "PWDLS", "DEFAULT METHOD"
+            )?;
+            for registration in registrations {
+                let mfa = if registration.mfa_capable {
+                    "capable"
+                } else if registration.mfa_registered {
+                    "registered"
+                } else {
+                    "no"
+                };
+                let line = format!(
+                    "{:<35} {:<5} {:<10} {:<4} {:<5} {:<20} {}",
+                    registration.user_principal_name,
+                    yes_no(registration.is_admin),
+                    mfa,
+                    yes_no(registration.sspr_capable),
+                    yes_no(registration.passwordless_capable),
+                    if registration.default_method.is_empty() { "-" } else { &registration.default_method },
+                    registration.methods_registered.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            let count = |predicate: fn(&Registration) -> bool| registrations.iter().filter(|registration| predicate(registration)).count();
+            writeln!(
+                out,
+                "{} users: {} registered for MFA, {} MFA capable, {} SSPR capable, {} passwordless capable; {} admins not MFA capable",
+                registrations.len(),
+                count(|registration| registration.mfa_registered),
+                count(|registration| registration.mfa_capable),
+                count(|registration| registration.sspr_capable),
+                count(|registration| registration.passwordless_capable),
+                count(|registration| registration.is_admin && !registration.mfa_capable)
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting mfa_registration");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["AuditLog.Read.All"])?;
+
+    let phase = client.stats().phase("read registrations");
+    let registrations = list_registrations(&client, config.only_unregistered, config.only_admins).await?;
+    drop(phase);
+
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&registrations, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let registrations = list_registrations(&client, false, false).await.unwrap();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&registrations, output, &Summary::fixture()));
+
+        let admins = list_registrations(&client, true, true).await.unwrap();
+        let upns: Vec<&str> = admins.iter().map(|registration| registration.user_principal_name.as_str()).collect();
+        assert_eq!(upns, ["it.admin@contoso.com"]);
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::stream::{self, StreamExt};
+use graph_common::batch::MAX_BATCH_SIZE;
+use graph_common::GraphClient;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const STRATEGIES: [&str; 3] = ["serial", "concurrent", "batch"];
+
+pub struct Workload {
+    pub users: usize,
+    pub latency: Duration,
+    pub throttle_rate: f64,
+    pub retry_after: u64,
+    pub concurrency: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchResult {
+    pub strategy: String,
+    pub users: usize,
+    pub failed: usize,
+    pub requests: u64,
+    pub throttled: u64,
+    pub seconds: f64,
+    #[serde(rename = "usersPerSecond")]
+    pub users_per_second: f64,
+}
+
+// A stand-in for the Graph endpoints the workload calls. Every response is delayed by the
+// configured latency; a $batch costs one latency because Graph runs its requests in parallel.
+struct MockGraph {
+    latency: Duration,
+    throttle_rate: f64,
+    retry_after: u64,
+}
+
+impl MockGraph {
+    fn throttle(&self) -> bool {
+        self.throttle_rate > 0.0 && rand::random::<f64>() < self.throttle_rate
+    }
+}
+
+async fn mailbox_settings(State(mock): State<Arc<MockGraph>>, Path(_upn): Path<String>) -> Response {
+    tokio::time::sleep(mock.latency).await;
+    if mock.throttle() {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [("Retry-After", mock.retry_after.to_string())],
+            Json(json!({ "error": { "code": "TooManyRequests" } })),
+        )
+            .into_response();
+    }
+    Json(json!({ "userPurpose": "user" })).into_response()
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    requests: Vec<Value>,
+}
+
+async fn batch(State(mock): State<Arc<MockGraph>>, Json(batch): Json<BatchRequest>) -> Response {
+    if batch.requests.len() > MAX_BATCH_SIZE {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": { "code": "BadRequest" } }))).into_response();
+    }
+    tokio::time::sleep(mock.latency).await;
+    let responses: Vec<Value> = batch
+        .requests
+        .iter()
+        .map(|request| {
+            if mock.throttle() {
+                json!({
+                    "id": request["id"],
+                    "status": 429,
+                    "headers": { "Retry-After": mock.retry_after.to_string() },
+                    "body": { "error": { "code": "TooManyRequests" } },
+                })
+            } else {
+                json!({ "id": request["id"], "status": 200, "body": { "userPurpose": "user" } })
+            }
+        })
+        .collect();
+    Json(json!({ "responses": responses })).into_response()
+}
+
+// Starts the mock on a random local port and returns its base URL.
+async fn start_mock(workload: &Workload) -> Result<String> {
+    let mock = Arc::new(MockGraph {
+        latency: workload.latency,
+        throttle_rate: workload.throttle_rate,
+        retry_after: workload.retry_after,
+    });
+    let app = Router::new()
+        .route("/v1.0/users/:upn/mailboxSettings", get(mailbox_settings))
+        .route("/v1.0/$batch", post(batch))
+        .with_state(mock);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to start the mock Graph server")?;
+    let address = listener.local_addr()?;
+    tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    Ok(format!("http://{}/v1.0", address))
+}
+
+async fn run_strategy(client: &GraphClient, strategy: &str, upns: &[String], concurrency: usize) -> Result<usize> {
+    let failed = match strategy {
+        "serial" => {
+            let mut failed = 0;
+            for upn in upns {
+                if client.user(upn).mailbox_settings().get_json::<Value>().await.is_err() {
+                    failed += 1;
+                }
+            }
+            failed
+        }
+        "concurrent" => {
+            stream::iter(upns)
+                .map(|upn| async move { client.user(upn).mailbox_settings().get_json::<Value>().await })
+                .buffer_unordered(concurrency)
+                .filter(|result| std::future::ready(result.is_err()))
+                .count()
+                .await
+        }
+        "batch" => {
+            let urls: Vec<String> = upns
+                .iter()
+                .map(|upn| client.user(upn).mailbox_settings().relative_url())
+                .collect();
+            stream::iter(urls.chunks(MAX_BATCH_SIZE))
+                .map(|chunk| async move {
+                    match client.batch_get::<Value>(chunk).await {
+                        Ok(items) => items.iter().filter(|item| item.is_err()).count(),
+                        Err(_) => chunk.len(),
+                    }
+                })
+                .buffer_unordered(concurrency)
+                .fold(0, |failed, chunk_failed| std::future::ready(failed + chunk_failed))
+                .await
+        }
+        _ => return Err(anyhow::anyhow!("Unknown strategy {}", strategy)),
+    };
+    Ok(failed)
+}
+
+// Each strategy gets its own client, so the request and throttle counts are its own.
+pub async fn run(workload: &Workload, strategies: &[String]) -> Result<Vec<BenchResult>> {
+    let base_url = start_mock(workload).await?;
+    let upns: Vec<String> = (0..workload.users)
+        .map(|index| format!("user{}@bench.invalid", index))
+        .collect();
+
+    let mut results = Vec::new();
+    for strategy in strategies {
+        info!("Running the {} strategy against {}", strategy, base_url);
+        let client = GraphClient::new("bench".to_string())
+            .with_base_url(&base_url)
+            .with_budget(None)
+            .with_action_log(None);
+        let started = Instant::now();
+        let failed = run_strategy(&client, strategy, &upns, workload.concurrency).await?;
+        let seconds = started.elapsed().as_secs_f64();
+        let summary = client.stats().summary();
+        results.push(BenchResult {
+            strategy: strategy.clone(),
+            users: upns.len(),
+            failed,
+            requests: summary.requests,
+            throttled: summary.throttled,
+            seconds,
+            users_per_second: (upns.len() - failed) as f64 / seconds.max(f64::EPSILON),
+        });
+    }
+    Ok(results)
+}
+
+pub fn print_results(results: &[BenchResult]) {
+    println!(
+        "{:<12} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "STRATEGY", "USERS", "FAILED", "REQUESTS", "THROTTLED", "SECONDS", "USERS/S"
+    );
+    for result in results {
+        println!(
+            "{:<12} {:>7} {:>7} {:>9} {:>9} {:>9.2} {:>9.1}",
+            result.strategy,
+            result.users,
+            result.failed,
+            result.requests,
+            result.throttled,
+            result.seconds,
+            result.users_per_second
+        );
+    }
+}
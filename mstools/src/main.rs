@@ -0,0 +1,571 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{actionlog, approval};
+use graph_common::audit::{self, Finding, PluginInput, Severity, CONTRACT_VERSION};
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::GraphClient;
+use log::{debug, info, warn, LevelFilter};
+use serde::Serialize;
+use std::env;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod bench;
+mod plugins;
+mod rbac;
+mod script;
+mod serve;
+#[cfg(windows)]
+mod winservice;
+
+#[derive(Parser, Debug)]
+#[command(name = "mstools")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Administrative commands shared across the Microsoft services tools")]
+struct AppConfig {
+    #[arg(long, global = true, help = "Enable info level logging")]
+    info: bool,
+
+    #[arg(long, global = true, help = "Enable debug level logging")]
+    debug: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "Generate an approver signing keypair")]
+    Keygen {
+        #[arg(short, long, help = "Approver name recorded in signatures")]
+        name: String,
+
+        #[arg(short, long, default_value = ".", help = "Directory to write the keypair to")]
+        out: PathBuf,
+    },
+
+    #[command(about = "Approve a plan file by writing a detached signature next to it")]
+    Approve {
+        #[arg(help = "Plan file generated by a tool")]
+        plan: PathBuf,
+
+        #[arg(short, long, help = "Approver secret key file")]
+        key: PathBuf,
+    },
+
+    #[command(about = "Verify that a plan file carries a valid approval")]
+    Verify {
+        #[arg(help = "Plan file generated by a tool")]
+        plan: PathBuf,
+
+        #[arg(long, help = "Directory of trusted approver public keys")]
+        approvers: Option<PathBuf>,
+    },
+
+    #[command(about = "Verify the hash chain of the local action log written by tools that change data")]
+    VerifyLog {
+        #[arg(help = "Action log file (defaults to MSTOOLS_ACTION_LOG or the per-user data directory)")]
+        path: Option<PathBuf>,
+    },
+
+    #[command(about = "Restore the state from before a reversible change recorded in the action log")]
+    Undo {
+        #[arg(help = "Sequence number of the action log entry to undo")]
+        id: u64,
+
+        #[arg(long, help = "Action log file (defaults to MSTOOLS_ACTION_LOG or the per-user data directory)")]
+        log: Option<PathBuf>,
+
+        #[arg(short, long, help = "Skip the confirmation prompt")]
+        yes: bool,
+    },
+
+    #[command(about = "Run built-in audits and audit plugins and aggregate their findings")]
+    Audit {
+        #[arg(long, help = "Directory containing mstools-audit-* plugin executables")]
+        plugins_dir: Option<PathBuf>,
+
+        #[arg(long, value_delimiter = ',', help = "Run only the named audits (comma separated)")]
+        only: Vec<String>,
+
+        #[arg(long, help = "List available audits without running them")]
+        list: bool,
+
+        #[arg(long, default_value_t = 300, help = "Seconds to wait for each plugin before stopping it")]
+        timeout: u64,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+    },
+
+    #[command(about = "Serve search, revocation, and audit operations over an authenticated HTTP API")]
+    ServeApi {
+        #[arg(short, long, help = "Service configuration file with the listen address and API keys")]
+        config: PathBuf,
+
+        #[arg(long, help = "Run under the Windows service control manager (Windows only)")]
+        windows_service: bool,
+    },
+
+    #[command(about = "Generate an API key for serve-api")]
+    ApiKey {
+        #[arg(short, long, help = "Name recorded in the service logs for requests made with this key")]
+        name: String,
+
+        #[arg(long, value_delimiter = ',', help = "Roles from the service configuration granted to the key (comma separated)")]
+        roles: Vec<String>,
+
+        #[arg(long, value_delimiter = ',', help = "Operations granted to the key in addition to its roles (comma separated, * for all)")]
+        operations: Vec<String>,
+    },
+
+    #[command(about = "Run a Rhai script against Microsoft Graph")]
+    Run {
+        #[arg(help = "Script file")]
+        script: PathBuf,
+
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = script::parse_arg, help = "Value exposed to the script as args.NAME (repeatable)")]
+        args: Vec<(String, String)>,
+
+        #[arg(long, help = "Print post, patch, and delete calls instead of sending them")]
+        dry_run: bool,
+    },
+
+    #[command(about = "Benchmark request strategies of the shared Graph client against a local mock server")]
+    Bench {
+        #[arg(short, long, default_value_t = 200, help = "Number of users whose mailbox settings are fetched")]
+        users: usize,
+
+        #[arg(long, default_value_t = 50, help = "Milliseconds the mock server takes to answer each request")]
+        latency_ms: u64,
+
+        #[arg(long, default_value_t = 0.0, help = "Share of requests the mock server throttles (0.0-1.0)")]
+        throttle_rate: f64,
+
+        #[arg(long, default_value_t = 1, help = "Retry-After seconds sent with throttled responses")]
+        retry_after: u64,
+
+        #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=100), help = "Requests (or batches) in flight for the concurrent and batch strategies")]
+        concurrency: u16,
+
+        #[arg(short, long, value_delimiter = ',', default_values_t = bench::STRATEGIES.map(String::from), value_parser = bench::STRATEGIES, help = "Strategies to run (comma separated)")]
+        strategies: Vec<String>,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+struct AuditResult {
+    name: String,
+    source: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    findings: Vec<Finding>,
+}
+
+fn setup_logger(config: &AppConfig) {
+    let mut builder = Builder::from_default_env();
+    builder.filter(
+        None,
+        if config.debug {
+            LevelFilter::Debug
+        } else if config.info {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        },
+    );
+    builder.init();
+}
+
+async fn run_audits(
+    plugins_dir: Option<PathBuf>,
+    only: Vec<String>,
+    list: bool,
+    timeout: u64,
+    output: &str,
+) -> Result<()> {
+    let plugins_dir = match plugins_dir {
+        Some(dir) => dir,
+        None => plugins::plugins_dir()?,
+    };
+    let selected = |name: &str| only.is_empty() || only.iter().any(|only| only == name);
+    let builtins: Vec<&(&str, &str)> = audit::BUILTIN_AUDITS
+        .iter()
+        .filter(|(name, _)| selected(name))
+        .collect();
+    let plugins: Vec<plugins::Plugin> = plugins::discover(&plugins_dir)?
+        .into_iter()
+        .filter(|plugin| selected(&plugin.name))
+        .collect();
+
+    if list {
+        for (name, description) in &builtins {
+            println!("{} (built-in): {}", name, description);
+        }
+        for plugin in &plugins {
+            println!("{} (plugin): {}", plugin.name, plugin.path.display());
+        }
+        return Ok(());
+    }
+
+    let (tenant_id, access_token) = get_access_token_from_env().await?;
+    let client = GraphClient::new(access_token.clone());
+
+    let mut shutdown = service::shutdown_signal();
+    let mut results = Vec::new();
+    for (name, _) in builtins {
+        info!("Running built-in audit {}", name);
+        let _phase = client.stats().phase(name);
+        let result = until_shutdown(&mut shutdown, audit::run_builtin(name, &client)).await;
+        results.push(audit_result(name, "built-in", result));
+    }
+
+    let input = PluginInput {
+        contract_version: CONTRACT_VERSION,
+        tenant_id,
+        access_token,
+    };
+    for plugin in &plugins {
+        info!("Running audit plugin {}", plugin.name);
+        let result = until_shutdown(&mut shutdown, plugins::run(plugin, &input, Duration::from_secs(timeout)))
+            .await
+            .map(|output| output.findings);
+        results.push(audit_result(&plugin.name, "plugin", result));
+    }
+
+    print!("{}", render_audit_results(&results, output)?);
+    // Plugins use their own connections, so only the built-in audits' requests are counted.
+    client.stats().summary().print();
+
+    if shutdown.is_requested() {
+        return Err(anyhow::anyhow!("Interrupted; the audit results above are incomplete"));
+    }
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} audits failed", failed, results.len()));
+    }
+    Ok(())
+}
+
+// Audits only read data, so the running one is cancelled rather than finished. Dropping its
+// future aborts the Graph request in flight and kills a running plugin.
+async fn until_shutdown<T>(shutdown: &mut ShutdownSignal, work: impl Future<Output = Result<T>>) -> Result<T> {
+    if shutdown.is_requested() {
+        return Err(anyhow::anyhow!("Not run, shutdown was requested"));
+    }
+    tokio::select! {
+        result = work => result,
+        _ = shutdown.requested() => Err(anyhow::anyhow!("Cancelled by shutdown")),
+    }
+}
+
+fn credentials_from_env() -> Result<serve::Credentials> {
+    dotenv().ok();
+    Ok(serve::Credentials {
+        tenant_id: env::var("TENANT_ID").context("TENANT_ID not set in .env file")?,
+        client_id: env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?,
+        client_secret: env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?,
+    })
+}
+
+#[cfg(windows)]
+fn run_windows_service(config: PathBuf, credentials: serve::Credentials) -> Result<()> {
+    winservice::run(config, credentials)
+}
+
+#[cfg(not(windows))]
+fn run_windows_service(_config: PathBuf, _credentials: serve::Credentials) -> Result<()> {
+    Err(anyhow::anyhow!("--windows-service is only supported on Windows"))
+}
+
+async fn get_access_token_from_env() -> Result<(String, String)> {
+    let credentials = credentials_from_env()?;
+    let access_token = graph_common::auth::client_credentials_token(
+        &credentials.tenant_id,
+        &credentials.client_id,
+        &credentials.client_secret,
+    )
+    .await
+    .context("Failed to obtain access token")?;
+    Ok((credentials.tenant_id, access_token))
+}
+
+fn action_log_path(path: Option<PathBuf>) -> PathBuf {
+    path.or_else(|| actionlog::ActionLog::from_env().map(|log| log.path().to_path_buf()))
+        .unwrap_or_else(actionlog::default_path)
+}
+
+// The undo request is replayed as recorded, so the chain is verified first and only requests
+// back to Graph are sent. The undo itself is appended to the same log, pointing at the entry.
+async fn undo_action(id: u64, log: Option<PathBuf>, yes: bool) -> Result<()> {
+    dotenv().ok();
+    let path = action_log_path(log);
+    let (entries, _) = actionlog::read_verified(&path)?;
+    let entry = entries
+        .iter()
+        .find(|entry| entry.seq == id)
+        .with_context(|| format!("No entry {} in {}", id, path.display()))?;
+    let undo = entry
+        .undo
+        .as_ref()
+        .with_context(|| format!("Entry {} ({} {}) is not reversible", id, entry.method, entry.target))?;
+    if !entry.succeeded() {
+        return Err(anyhow::anyhow!("Entry {} failed with status {}, nothing to undo", id, entry.status));
+    }
+    if let Some(done) = entries.iter().find(|other| other.undoes == Some(id) && other.succeeded()) {
+        return Err(anyhow::anyhow!("Entry {} was already undone by entry {}", id, done.seq));
+    }
+    if !undo.url.starts_with(&format!("{}/", graph_common::client::GRAPH_BASE_URL)) {
+        return Err(anyhow::anyhow!("Entry {} has an undo request outside Microsoft Graph: {}", id, undo.url));
+    }
+
+    println!("Entry {}: {} {} by {} with {} at {}", id, entry.method, entry.target, entry.operator, entry.tool, entry.timestamp);
+    println!("Undo:     {} {}", undo.method, undo.url);
+    if let Some(body) = &undo.body {
+        println!("          {}", body);
+    }
+    if !yes && !graph_common::cli::confirm()? {
+        println!("Aborted, no changes were made.");
+        return Ok(());
+    }
+
+    let (_, access_token) = get_access_token_from_env().await?;
+    let client = GraphClient::new(access_token)
+        .with_action_log(Some(actionlog::ActionLog::new(path, "mstools")));
+    let change = actionlog::Change {
+        undoes: Some(id),
+        ..actionlog::Change::new(&undo.method, &undo.url, undo.body.as_ref())
+    };
+    let response = client.send_change(change).await?;
+    graph_common::client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to undo entry {}", id))?;
+    println!("Entry {} undone", id);
+    Ok(())
+}
+
+async fn run_script(script: PathBuf, args: Vec<(String, String)>, dry_run: bool) -> Result<()> {
+    let (_, access_token) = get_access_token_from_env().await?;
+    let client = Arc::new(GraphClient::new(access_token));
+    let handle = tokio::runtime::Handle::current();
+    let options = script::ScriptOptions { dry_run, args };
+
+    // Rhai calls are synchronous, so the script runs on a blocking thread that
+    // drives each Graph request on the runtime.
+    tokio::task::spawn_blocking(move || script::run(&script, client, handle, options))
+        .await
+        .context("Script task panicked")?
+}
+
+fn audit_result(name: &str, source: &str, result: Result<Vec<Finding>>) -> AuditResult {
+    let (status, error, findings) = match result {
+        Ok(findings) => ("ok", None, findings),
+        Err(e) => {
+            warn!("Audit {} failed: {:#}", name, e);
+            ("failed", Some(format!("{:#}", e)), Vec::new())
+        }
+    };
+    AuditResult {
+        name: name.to_string(),
+        source: source.to_string(),
+        status: status.to_string(),
+        error,
+        findings,
+    }
+}
+
+fn render_audit_results(results: &[AuditResult], output: &str) -> Result<String> {
+    let mut out = String::new();
+    if output == "json" {
+        writeln!(out, "{}", serde_json::to_string_pretty(results)?)?;
+        return Ok(out);
+    }
+    for result in results {
+        match &result.error {
+            Some(error) => writeln!(out, "{} ({}): failed: {}", result.name, result.source, error)?,
+            None => writeln!(
+                out,
+                "{} ({}): {} findings",
+                result.name,
+                result.source,
+                result.findings.len()
+            )?,
+        }
+        for finding in &result.findings {
+            writeln!(out, "  [{}] {}: {}", finding.severity, finding.target, finding.message)?;
+        }
+    }
+
+    let findings: Vec<&Finding> = results.iter().flat_map(|result| &result.findings).collect();
+    let count = |severity: Severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Total: {} findings ({} high, {} medium, {} low, {} info)",
+        findings.len(),
+        count(Severity::High),
+        count(Severity::Medium),
+        count(Severity::Low),
+        count(Severity::Info)
+    )?;
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = AppConfig::parse();
+    setup_logger(&config);
+    debug!("Configuration: {:?}", config);
+
+    match config.command {
+        Commands::Keygen { name, out } => {
+            let (secret_path, public_path) = approval::generate_keypair(&name, &out)?;
+            println!("Secret key: {}", secret_path.display());
+            println!("Public key: {}", public_path.display());
+            println!("Copy the public key into the approvers directory of every host that applies plans.");
+        }
+        Commands::Approve { plan, key } => {
+            let sig_path = approval::approve_plan(&plan, &key)?;
+            println!("Plan approved. Signature written to {}", sig_path.display());
+        }
+        Commands::Verify { plan, approvers } => {
+            let approvers = match approvers {
+                Some(dir) => dir,
+                None => approval::approvers_dir()?,
+            };
+            let (plan_data, signature) = approval::verify_plan(&plan, &approvers)?;
+            println!(
+                "Plan from {} ({} actions) created by {} was approved by {} at {}",
+                plan_data.tool,
+                plan_data.actions.len(),
+                plan_data.created_by,
+                signature.approver,
+                signature.approved_at
+            );
+        }
+        Commands::VerifyLog { path } => {
+            dotenv().ok();
+            let path = action_log_path(path);
+            let (entries, head) = actionlog::verify(&path)?;
+            println!("{}: {} entries verified, last hash {}", path.display(), entries, head);
+        }
+        Commands::Undo { id, log, yes } => {
+            undo_action(id, log, yes).await?;
+        }
+        Commands::Audit {
+            plugins_dir,
+            only,
+            list,
+            timeout,
+            output,
+        } => {
+            run_audits(plugins_dir, only, list, timeout, &output).await?;
+        }
+        Commands::ServeApi {
+            config,
+            windows_service,
+        } => {
+            let credentials = credentials_from_env()?;
+            if windows_service {
+                run_windows_service(config, credentials)?;
+            } else {
+                serve::serve(config, credentials, graph_common::service::signals()).await?;
+            }
+        }
+        Commands::ApiKey {
+            name,
+            roles,
+            operations,
+        } => {
+            rbac::validate_operations(&operations)?;
+            let key = serve::generate_api_key();
+            println!("API key (shown once): {}", key);
+            println!("Add this entry to the apiKeys list of the service configuration:");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "name": name,
+                    "keySha256": rbac::sha256_hex(&key),
+                    "roles": roles,
+                    "operations": operations,
+                }))?
+            );
+        }
+        Commands::Bench {
+            users,
+            latency_ms,
+            throttle_rate,
+            retry_after,
+            concurrency,
+            strategies,
+            output,
+        } => {
+            if !(0.0..=1.0).contains(&throttle_rate) {
+                return Err(anyhow::anyhow!("--throttle-rate must be between 0.0 and 1.0"));
+            }
+            let workload = bench::Workload {
+                users,
+                latency: Duration::from_millis(latency_ms),
+                throttle_rate,
+                retry_after,
+                concurrency: concurrency as usize,
+            };
+            let results = bench::run(&workload, &strategies).await?;
+            if output == "json" {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+            } else {
+                bench::print_results(&results);
+            }
+        }
+        Commands::Run {
+            script,
+            args,
+            dry_run,
+        } => {
+            run_script(script, args, dry_run).await?;
+        }
+    }
+
+    info!("mstools has finished execution.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn audit_reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let results = vec![
+            audit_result(
+                "shared-mailbox-licenses",
+                "built-in",
+                audit::shared_mailbox_licenses(&client).await,
+            ),
+            audit_result(
+                "stale-guests",
+                "plugin",
+                Err(anyhow::anyhow!("Plugin exited with status 1")),
+            ),
+        ];
+
+        golden::assert_reports("audit", &["text", "json"], |output| render_audit_results(&results, output));
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use graph_common::audit::{PluginInput, PluginOutput};
+use log::debug;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+const PLUGIN_PREFIX: &str = "mstools-audit-";
+
+#[derive(Debug)]
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+pub fn plugins_dir() -> Result<PathBuf> {
+    if let Ok(dir) = env::var("MSTOOLS_PLUGINS_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let current_exe = env::current_exe().context("Failed to locate the current executable")?;
+    let exe_dir = current_exe
+        .parent()
+        .context("Unable to get parent directory of the executable")?;
+    Ok(exe_dir.join("plugins"))
+}
+
+pub fn discover(dir: &Path) -> Result<Vec<Plugin>> {
+    if !dir.is_dir() {
+        debug!("Plugin directory {} does not exist", dir.display());
+        return Ok(Vec::new());
+    }
+
+    let mut plugins = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) => match stem.strip_prefix(PLUGIN_PREFIX) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => continue,
+            },
+            None => continue,
+        };
+        if !is_executable(&path) {
+            debug!("Skipping {}: not an executable file", path.display());
+            continue;
+        }
+        debug!("Discovered plugin {} at {}", name, path.display());
+        plugins.push(Plugin { name, path });
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(plugins)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+}
+
+pub async fn run(plugin: &Plugin, input: &PluginInput, timeout: Duration) -> Result<PluginOutput> {
+    debug!("Running plugin {}", plugin.path.display());
+    let mut child = Command::new(&plugin.path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start plugin {}", plugin.path.display()))?;
+
+    let mut stdin = child.stdin.take().context("Failed to open plugin stdin")?;
+    stdin
+        .write_all(&serde_json::to_vec(input)?)
+        .await
+        .context("Failed to write plugin input")?;
+    drop(stdin);
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| anyhow::anyhow!("Plugin timed out after {} seconds", timeout.as_secs()))?
+        .context("Failed to wait for plugin")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        debug!("Plugin {} stderr: {}", plugin.name, stderr.trim());
+    }
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Plugin exited with {}: {}",
+            output.status,
+            stderr.trim()
+        ));
+    }
+
+    serde_json::from_slice(&output.stdout).context("Plugin output does not match the audit contract")
+}
@@ -0,0 +1,290 @@
+use anyhow::{Context, Result};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use log::{debug, info};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Tokens with an unknown key id trigger at most one JWKS fetch per interval, and a key id the
+// issuer did not publish is not looked up again until the interval has passed.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(300);
+const MAX_UNKNOWN_KEYS: usize = 1024;
+
+pub const OPERATIONS: &[&str] = &["audit.list", "audit.run", "mail.search", "sessions.revoke"];
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AuthorizationConfig {
+    #[serde(default)]
+    pub roles: HashMap<String, Vec<String>>,
+    #[serde(rename = "apiKeys", default)]
+    pub api_keys: Vec<ApiKey>,
+    pub oidc: Option<OidcConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiKey {
+    pub name: String,
+    #[serde(rename = "keySha256")]
+    pub key_sha256: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub operations: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    #[serde(rename = "jwksUri")]
+    pub jwks_uri: Option<String>,
+    #[serde(rename = "groupsClaim", default = "default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(rename = "groupRoles", default)]
+    pub group_roles: HashMap<String, Vec<String>>,
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+#[derive(Debug)]
+pub struct Caller {
+    pub name: String,
+    pub operations: BTreeSet<String>,
+}
+
+impl Caller {
+    pub fn may(&self, operation: &str) -> bool {
+        self.operations.contains("*") || self.operations.contains(operation)
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Missing,
+    Invalid(String),
+}
+
+pub struct Authorizer {
+    config: AuthorizationConfig,
+    keys: Mutex<SigningKeys>,
+}
+
+#[derive(Default)]
+struct SigningKeys {
+    jwks: Option<JwkSet>,
+    fetched_at: Option<Instant>,
+    unknown: HashMap<String, Instant>,
+}
+
+pub fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn validate_operations(operations: &[String]) -> Result<()> {
+    match operations
+        .iter()
+        .find(|operation| *operation != "*" && !OPERATIONS.contains(&operation.as_str()))
+    {
+        Some(unknown) => Err(anyhow::anyhow!(
+            "Unknown operation {}. Available operations: {}",
+            unknown,
+            OPERATIONS.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+impl AuthorizationConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (role, operations) in &self.roles {
+            validate_operations(operations).with_context(|| format!("Invalid role {}", role))?;
+        }
+        let role_lists = self
+            .api_keys
+            .iter()
+            .map(|key| (&key.name, &key.roles))
+            .chain(self.oidc.iter().flat_map(|oidc| &oidc.group_roles));
+        for (owner, roles) in role_lists {
+            if let Some(unknown) = roles.iter().find(|role| !self.roles.contains_key(*role)) {
+                return Err(anyhow::anyhow!("{} refers to undefined role {}", owner, unknown));
+            }
+        }
+        for key in &self.api_keys {
+            validate_operations(&key.operations)
+                .with_context(|| format!("Invalid API key {}", key.name))?;
+        }
+        Ok(())
+    }
+
+    fn operations_for<'a>(&self, roles: impl IntoIterator<Item = &'a String>) -> BTreeSet<String> {
+        roles
+            .into_iter()
+            .filter_map(|role| self.roles.get(role))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Authorizer {
+    pub fn new(config: AuthorizationConfig) -> Self {
+        Self {
+            config,
+            keys: Mutex::new(SigningKeys::default()),
+        }
+    }
+
+    pub async fn authenticate(&self, credential: Option<&str>) -> Result<Caller, AuthError> {
+        let credential = credential.ok_or(AuthError::Missing)?;
+        // JWTs have three dot-separated parts; API keys are plain hex.
+        if credential.split('.').count() == 3 && self.config.oidc.is_some() {
+            self.authenticate_token(credential).await
+        } else {
+            self.authenticate_key(credential)
+        }
+    }
+
+    fn authenticate_key(&self, key: &str) -> Result<Caller, AuthError> {
+        let key_sha256 = sha256_hex(key);
+        let api_key = self
+            .config
+            .api_keys
+            .iter()
+            .find(|api_key| api_key.key_sha256.eq_ignore_ascii_case(&key_sha256))
+            .ok_or_else(|| AuthError::Invalid("Invalid API key".to_string()))?;
+
+        let mut operations = self.config.operations_for(&api_key.roles);
+        operations.extend(api_key.operations.iter().cloned());
+        Ok(Caller {
+            name: format!("key:{}", api_key.name),
+            operations,
+        })
+    }
+
+    async fn authenticate_token(&self, token: &str) -> Result<Caller, AuthError> {
+        let oidc = self.config.oidc.as_ref().expect("OIDC is configured");
+        let invalid = |message: String| AuthError::Invalid(message);
+
+        let header = decode_header(token).map_err(|e| invalid(format!("Invalid token: {}", e)))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| invalid("Token has no key id".to_string()))?;
+        let key = self
+            .decoding_key(oidc, &kid)
+            .await
+            .map_err(|e| invalid(format!("{:#}", e)))?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[&oidc.issuer]);
+        validation.set_audience(&[&oidc.audience]);
+        let claims = decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|e| invalid(format!("Invalid token: {}", e)))?
+            .claims;
+
+        let groups: Vec<String> = claims[oidc.groups_claim.as_str()]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|group| group.as_str().map(str::to_string))
+            .collect();
+        let roles = groups
+            .iter()
+            .filter_map(|group| oidc.group_roles.get(group))
+            .flatten();
+        let name = ["preferred_username", "upn", "email", "sub"]
+            .iter()
+            .find_map(|claim| claims[*claim].as_str())
+            .unwrap_or("unknown");
+        debug!("Token for {} carries groups {:?}", name, groups);
+
+        Ok(Caller {
+            name: format!("user:{}", name),
+            operations: self.config.operations_for(roles),
+        })
+    }
+
+    async fn decoding_key(&self, oidc: &OidcConfig, kid: &str) -> Result<DecodingKey> {
+        let mut keys = self.keys.lock().await;
+        if let Some(jwk) = keys.jwks.as_ref().and_then(|jwks| jwks.find(kid)) {
+            return DecodingKey::from_jwk(jwk).context("Invalid signing key");
+        }
+
+        // Unknown key id: the issuer may have rotated its keys, so refresh, but not more often
+        // than the minimum interval and not for a key id that was just found missing.
+        let now = Instant::now();
+        let unknown = || anyhow::anyhow!("Token signed with unknown key {}", kid);
+        if keys.unknown.get(kid).is_some_and(|seen| now.duration_since(*seen) < JWKS_MIN_REFRESH) {
+            return Err(unknown());
+        }
+        if keys.fetched_at.is_some_and(|fetched| now.duration_since(fetched) < JWKS_MIN_REFRESH) {
+            keys.remember_unknown(kid, now);
+            return Err(unknown());
+        }
+        keys.fetched_at = Some(now);
+        keys.jwks = Some(fetch_jwks(oidc).await?);
+        match keys.jwks.as_ref().and_then(|jwks| jwks.find(kid)) {
+            Some(jwk) => DecodingKey::from_jwk(jwk).context("Invalid signing key"),
+            None => {
+                keys.remember_unknown(kid, now);
+                Err(unknown())
+            }
+        }
+    }
+}
+
+impl SigningKeys {
+    fn remember_unknown(&mut self, kid: &str, now: Instant) {
+        if self.unknown.len() >= MAX_UNKNOWN_KEYS {
+            self.unknown.retain(|_, seen| now.duration_since(*seen) < JWKS_MIN_REFRESH);
+            if self.unknown.len() >= MAX_UNKNOWN_KEYS {
+                self.unknown.clear();
+            }
+        }
+        self.unknown.insert(kid.to_string(), now);
+    }
+}
+
+async fn fetch_jwks(oidc: &OidcConfig) -> Result<JwkSet> {
+    let client = reqwest::Client::new();
+    let jwks_uri = match &oidc.jwks_uri {
+        Some(jwks_uri) => jwks_uri.clone(),
+        None => {
+            let discovery_url = format!(
+                "{}/.well-known/openid-configuration",
+                oidc.issuer.trim_end_matches('/')
+            );
+            let discovery: serde_json::Value = client
+                .get(&discovery_url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Failed to fetch {}", discovery_url))?
+                .json()
+                .await
+                .context("Failed to parse OpenID configuration")?;
+            discovery["jwks_uri"]
+                .as_str()
+                .context("OpenID configuration has no jwks_uri")?
+                .to_string()
+        }
+    };
+
+    info!("Fetching signing keys from {}", jwks_uri);
+    client
+        .get(&jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to fetch {}", jwks_uri))?
+        .json()
+        .await
+        .context("Failed to parse signing keys")
+}
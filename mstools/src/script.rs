@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use graph_common::client::GRAPH_BASE_URL;
+use graph_common::{encode, GraphClient};
+use log::{debug, info};
+use reqwest::Response;
+use rhai::{Dynamic, Engine, EvalAltResult, Map, Scope};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+pub struct ScriptOptions {
+    pub dry_run: bool,
+    pub args: Vec<(String, String)>,
+}
+
+pub fn parse_arg(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("Invalid argument '{}'. Use NAME=VALUE", value))
+}
+
+// Scripts only ever talk to Graph, so the access token is never sent to another host.
+fn graph_url(path: &str) -> ScriptResult<String> {
+    if path.starts_with("https://graph.microsoft.com/") {
+        Ok(path.to_string())
+    } else if path.contains("://") {
+        Err(format!("Only Microsoft Graph URLs are allowed: {}", path).into())
+    } else {
+        Ok(format!("{}/{}", GRAPH_BASE_URL, path.trim_start_matches('/')))
+    }
+}
+
+fn to_dynamic(value: Value) -> ScriptResult<Dynamic> {
+    rhai::serde::to_dynamic(value)
+}
+
+fn to_value(body: &Dynamic) -> ScriptResult<Value> {
+    rhai::serde::from_dynamic(body)
+}
+
+fn script_error(error: anyhow::Error) -> Box<EvalAltResult> {
+    format!("{:#}", error).into()
+}
+
+async fn response_value(response: Response) -> anyhow::Result<Value> {
+    if !response.status().is_success() {
+        return Err(graph_common::client::error_from_response(response).await);
+    }
+    let text = response.text().await.context("Failed to read response from Graph API")?;
+    if text.trim().is_empty() {
+        Ok(Value::Null)
+    } else {
+        serde_json::from_str(&text).context("Failed to parse response from Graph API")
+    }
+}
+
+fn register_graph(engine: &mut Engine, client: Arc<GraphClient>, handle: Handle, dry_run: bool) {
+    let (c, h) = (client.clone(), handle.clone());
+    engine.register_fn("get", move |path: &str| -> ScriptResult<Dynamic> {
+        let url = graph_url(path)?;
+        let value: Value = h.block_on(c.get_json(&url)).map_err(script_error)?;
+        to_dynamic(value)
+    });
+
+    let (c, h) = (client.clone(), handle.clone());
+    engine.register_fn("list", move |path: &str| -> ScriptResult<Dynamic> {
+        let url = graph_url(path)?;
+        let items: Vec<Value> = h.block_on(c.get_all(&url)).map_err(script_error)?;
+        to_dynamic(Value::Array(items))
+    });
+
+    for method in ["post", "patch"] {
+        let (c, h) = (client.clone(), handle.clone());
+        engine.register_fn(method, move |path: &str, body: Dynamic| -> ScriptResult<Dynamic> {
+            let url = graph_url(path)?;
+            let body = to_value(&body)?;
+            if dry_run {
+                println!("[dry-run] {} {} {}", method.to_uppercase(), url, body);
+                return Ok(Dynamic::UNIT);
+            }
+            let value = h
+                .block_on(async {
+                    let response = if method == "post" {
+                        c.post_json(&url, &body).await?
+                    } else {
+                        c.patch_json(&url, &body).await?
+                    };
+                    response_value(response).await
+                })
+                .map_err(script_error)?;
+            to_dynamic(value)
+        });
+    }
+
+    let (c, h) = (client, handle);
+    engine.register_fn("delete", move |path: &str| -> ScriptResult<()> {
+        let url = graph_url(path)?;
+        if dry_run {
+            println!("[dry-run] DELETE {}", url);
+            return Ok(());
+        }
+        h.block_on(async { response_value(c.delete(&url).await?).await })
+            .map_err(script_error)?;
+        Ok(())
+    });
+
+    engine.register_fn("encode", |value: &str| encode::path_segment(value));
+    engine.register_fn("odata", |value: &str| encode::odata_string(value));
+    engine.register_fn("to_json", |value: Dynamic| -> ScriptResult<String> {
+        serde_json::to_string_pretty(&to_value(&value)?).map_err(|e| e.to_string().into())
+    });
+}
+
+pub fn run(path: &Path, client: Arc<GraphClient>, handle: Handle, options: ScriptOptions) -> Result<()> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))?;
+
+    let mut engine = Engine::new();
+    register_graph(&mut engine, client, handle, options.dry_run);
+    engine.on_debug(|text, _, position| debug!("{} {}", position, text));
+
+    let mut args = Map::new();
+    for (name, value) in options.args {
+        args.insert(name.into(), value.into());
+    }
+    let mut scope = Scope::new();
+    scope.push_constant("args", args);
+    scope.push_constant("dry_run", options.dry_run);
+
+    info!("Running script {}", path.display());
+    engine
+        .run_with_scope(&mut scope, &source)
+        .map_err(|e| anyhow::anyhow!("Script {} failed: {}", path.display(), e))
+}
@@ -0,0 +1,289 @@
+use anyhow::{bail, Context, Result};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use crate::rbac::{AuthError, AuthorizationConfig, Authorizer};
+use graph_common::service::{self, ServiceEvent, ServiceEvents};
+use graph_common::{audit, auth, mail, remediation, GraphClient};
+use log::{error, info, warn};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Mutex};
+
+// Client credential tokens are valid for at least an hour; renew well before that.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(45 * 60);
+
+#[derive(Deserialize, Debug)]
+pub struct ServeConfig {
+    pub listen: String,
+    #[serde(flatten)]
+    pub authorization: AuthorizationConfig,
+}
+
+impl ServeConfig {
+    pub fn load(path: &FsPath) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: ServeConfig = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        config.authorization.validate()?;
+        Ok(config)
+    }
+}
+
+pub struct Credentials {
+    pub tenant_id: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+struct ServeState {
+    authorizer: RwLock<Arc<Authorizer>>,
+    credentials: Credentials,
+    token: Mutex<Option<(String, Instant)>>,
+    started: Instant,
+    reloads: AtomicU64,
+}
+
+impl ServeState {
+    async fn client(&self) -> Result<GraphClient, ApiError> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, acquired)) = token.as_ref() {
+            if acquired.elapsed() < TOKEN_LIFETIME {
+                return Ok(GraphClient::new(access_token.clone()));
+            }
+        }
+        info!("Requesting a new Graph access token");
+        let access_token = auth::client_credentials_token(
+            &self.credentials.tenant_id,
+            &self.credentials.client_id,
+            &self.credentials.client_secret,
+        )
+        .await
+        .map_err(ApiError::graph)?;
+        *token = Some((access_token.clone(), Instant::now()));
+        Ok(GraphClient::new(access_token))
+    }
+
+    async fn authorize(&self, headers: &HeaderMap, operation: &str) -> Result<String, ApiError> {
+        let credential = headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| {
+                headers
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+            });
+
+        let authorizer = self.authorizer.read().expect("authorizer lock poisoned").clone();
+        let caller = match authorizer.authenticate(credential).await {
+            Ok(caller) => caller,
+            Err(AuthError::Missing) => {
+                return Err(ApiError::new(StatusCode::UNAUTHORIZED, "Missing credentials"))
+            }
+            Err(AuthError::Invalid(message)) => {
+                warn!("Rejected credentials: {}", message);
+                return Err(ApiError::new(StatusCode::UNAUTHORIZED, &message));
+            }
+        };
+
+        if caller.may(operation) {
+            Ok(caller.name)
+        } else {
+            warn!("{} denied operation {}", caller.name, operation);
+            Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                &format!("Operation {} is not allowed for {}", operation, caller.name),
+            ))
+        }
+    }
+
+    // Only the authorization settings are reloaded; the listen address needs a restart.
+    fn reload(&self, config_path: &FsPath, listen: &str) {
+        service::notify_reloading();
+        match ServeConfig::load(config_path) {
+            Ok(config) => {
+                if config.listen != listen {
+                    warn!(
+                        "Listen address changed to {}; restart the service to apply it",
+                        config.listen
+                    );
+                }
+                *self.authorizer.write().expect("authorizer lock poisoned") =
+                    Arc::new(Authorizer::new(config.authorization));
+                self.reloads.fetch_add(1, Ordering::Relaxed);
+                service::notify_ready(&format!("Reloaded {}", config_path.display()));
+            }
+            Err(e) => {
+                error!("Keeping the previous configuration: {:#}", e);
+                service::notify_ready("Reload failed, serving the previous configuration");
+            }
+        }
+    }
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: &str) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    fn graph(error: anyhow::Error) -> Self {
+        warn!("Graph request failed: {:#}", error);
+        Self::new(StatusCode::BAD_GATEWAY, &format!("{:#}", error))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+type SharedState = Arc<ServeState>;
+
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn health(State(state): State<SharedState>) -> Json<Value> {
+    Json(json!({
+        "status": "ok",
+        "uptimeSeconds": state.started.elapsed().as_secs(),
+        "configReloads": state.reloads.load(Ordering::Relaxed),
+    }))
+}
+
+async fn list_audits(State(state): State<SharedState>, headers: HeaderMap) -> ApiResult {
+    state.authorize(&headers, "audit.list").await?;
+    let audits: Vec<Value> = audit::BUILTIN_AUDITS
+        .iter()
+        .map(|(name, description)| json!({ "name": name, "description": description }))
+        .collect();
+    Ok(Json(json!({ "audits": audits })))
+}
+
+async fn run_audit(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> ApiResult {
+    let caller = state.authorize(&headers, "audit.run").await?;
+    if !audit::BUILTIN_AUDITS.iter().any(|(builtin, _)| *builtin == name) {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, &format!("Unknown audit: {}", name)));
+    }
+    info!("{} is running audit {}", caller, name);
+    let client = state.client().await?;
+    let findings = audit::run_builtin(&name, &client).await.map_err(ApiError::graph)?;
+    Ok(Json(json!({ "audit": name, "findings": findings })))
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    upn: String,
+    search: String,
+    select: Option<String>,
+}
+
+async fn search_mail(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(request): Json<SearchRequest>,
+) -> ApiResult {
+    let caller = state.authorize(&headers, "mail.search").await?;
+    info!("{} is searching mailbox {} for {}", caller, request.upn, request.search);
+    let client = state.client().await?;
+    let messages = mail::search_messages(&client, &request.upn, &request.search, request.select.as_deref())
+        .await
+        .map_err(ApiError::graph)?;
+    Ok(Json(json!({ "upn": request.upn, "messages": messages })))
+}
+
+async fn revoke_sessions(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(upn): Path<String>,
+) -> ApiResult {
+    let caller = state.authorize(&headers, "sessions.revoke").await?;
+    info!("{} is revoking sign-in sessions for {}", caller, upn);
+    let client = state.client().await?;
+    remediation::revoke_sign_in_sessions(&client, &upn)
+        .await
+        .map_err(ApiError::graph)?;
+    Ok(Json(json!({ "upn": upn, "revoked": true })))
+}
+
+pub async fn serve(config_path: PathBuf, credentials: Credentials, mut events: ServiceEvents) -> Result<()> {
+    let config = ServeConfig::load(&config_path)?;
+    let listen = config.listen.clone();
+    let state = Arc::new(ServeState {
+        authorizer: RwLock::new(Arc::new(Authorizer::new(config.authorization))),
+        credentials,
+        token: Mutex::new(None),
+        started: Instant::now(),
+        reloads: AtomicU64::new(0),
+    });
+
+    let app = Router::new()
+        .route("/v1/health", get(health))
+        .route("/v1/audits", get(list_audits))
+        .route("/v1/audits/:name", post(run_audit))
+        .route("/v1/mail/search", post(search_mail))
+        .route("/v1/users/:upn/revoke-sessions", post(revoke_sessions))
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind(&listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", listen))?;
+    // API keys and Graph results travel in plain HTTP, so they must not leave the host.
+    let address = listener.local_addr().context("Failed to read the listen address")?;
+    if !address.ip().is_loopback() {
+        bail!(
+            "Refusing to serve plain HTTP on {}; listen on a loopback address and put a TLS reverse proxy in front",
+            address
+        );
+    }
+
+    let (shutdown, shutdown_requested) = oneshot::channel();
+    let reload_listen = listen.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            match event {
+                ServiceEvent::Reload => state.reload(&config_path, &reload_listen),
+                ServiceEvent::Shutdown => break,
+            }
+        }
+        // Also reached when the event source goes away, so the server never outlives it.
+        service::notify_stopping();
+        let _ = shutdown.send(());
+    });
+
+    service::spawn_watchdog();
+    service::notify_ready(&format!("Serving API on {}", listen));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = shutdown_requested.await;
+        })
+        .await
+        .context("API server failed")?;
+    info!("API server stopped after finishing in-flight requests");
+    Ok(())
+}
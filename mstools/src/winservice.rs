@@ -0,0 +1,88 @@
+use crate::serve::{self, Credentials};
+use anyhow::{Context, Result};
+use graph_common::service::{self, ServiceEvent};
+use log::error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::{define_windows_service, service_dispatcher};
+
+pub const SERVICE_NAME: &str = "mstools";
+
+// The service entry point takes no context, so the settings parsed in main are parked here.
+static SERVICE_SETTINGS: Mutex<Option<(PathBuf, Credentials)>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+pub fn run(config_path: PathBuf, credentials: Credentials) -> Result<()> {
+    *SERVICE_SETTINGS.lock().expect("service settings lock poisoned") = Some((config_path, credentials));
+    tokio::task::block_in_place(|| service_dispatcher::start(SERVICE_NAME, ffi_service_main))
+        .context("Failed to connect to the service control manager. Is mstools installed as a service?")
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Windows service failed: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let (config_path, credentials) = SERVICE_SETTINGS
+        .lock()
+        .expect("service settings lock poisoned")
+        .take()
+        .context("Service settings are missing")?;
+
+    // Stop and shutdown map to SIGTERM and a parameter change maps to SIGHUP on Unix.
+    let (sender, events) = service::channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown | ServiceControl::Preshutdown => {
+            let _ = sender.send(ServiceEvent::Shutdown);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::ParamChange => {
+            let _ = sender.send(ServiceEvent::Reload);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let status_handle = service_control_handler::register(SERVICE_NAME, handler)
+        .context("Failed to register the service control handler")?;
+
+    let set_status = |current_state, controls_accepted, exit_code| {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    set_status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN | ServiceControlAccept::PARAM_CHANGE,
+        0,
+    )
+    .context("Failed to report the running state")?;
+
+    let result = tokio::runtime::Runtime::new()
+        .context("Failed to start the async runtime")
+        .and_then(|runtime| runtime.block_on(serve::serve(config_path, credentials, events)));
+
+    set_status(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        if result.is_ok() { 0 } else { 1 },
+    )
+    .context("Failed to report the stopped state")?;
+    result
+}
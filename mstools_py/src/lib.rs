@@ -0,0 +1,118 @@
+use graph_common::{audit, mail, remediation, GraphClient};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde::Serialize;
+use std::future::Future;
+use tokio::runtime::Runtime;
+
+fn runtime_error(error: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", error))
+}
+
+fn to_python<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    pythonize::pythonize(py, value)
+        .map(|value| value.unbind())
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))
+}
+
+#[pyclass(module = "mstools")]
+struct GraphSession {
+    client: GraphClient,
+    runtime: Runtime,
+}
+
+impl GraphSession {
+    // Releases the GIL while the request runs so other Python threads keep going.
+    fn block_on<F, T>(&self, py: Python<'_>, future: F) -> PyResult<T>
+    where
+        F: Future<Output = anyhow::Result<T>> + Send,
+        T: Send,
+    {
+        py.allow_threads(|| self.runtime.block_on(future))
+            .map_err(runtime_error)
+    }
+}
+
+fn new_runtime() -> PyResult<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))
+}
+
+#[pymethods]
+impl GraphSession {
+    #[new]
+    fn new(py: Python<'_>, tenant_id: &str, client_id: &str, client_secret: &str) -> PyResult<Self> {
+        let runtime = new_runtime()?;
+        let client = py
+            .allow_threads(|| {
+                runtime.block_on(GraphClient::from_client_credentials(
+                    tenant_id,
+                    client_id,
+                    client_secret,
+                ))
+            })
+            .map_err(runtime_error)?;
+        Ok(Self { client, runtime })
+    }
+
+    #[staticmethod]
+    fn from_token(access_token: String) -> PyResult<Self> {
+        Ok(Self {
+            client: GraphClient::new(access_token),
+            runtime: new_runtime()?,
+        })
+    }
+
+    #[pyo3(signature = (upn, search, select=None))]
+    fn search_messages(
+        &self,
+        py: Python<'_>,
+        upn: &str,
+        search: &str,
+        select: Option<&str>,
+    ) -> PyResult<PyObject> {
+        let messages = self.block_on(py, mail::search_messages(&self.client, upn, search, select))?;
+        to_python(py, &messages)
+    }
+
+    #[staticmethod]
+    fn audits() -> Vec<&'static str> {
+        audit::BUILTIN_AUDITS.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn run_audit(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        if !audit::BUILTIN_AUDITS.iter().any(|(builtin, _)| *builtin == name) {
+            return Err(PyValueError::new_err(format!("Unknown audit: {}", name)));
+        }
+        let findings = self.block_on(py, audit::run_builtin(name, &self.client))?;
+        to_python(py, &findings)
+    }
+
+    fn revoke_sign_in_sessions(&self, py: Python<'_>, upn: &str) -> PyResult<()> {
+        self.block_on(py, remediation::revoke_sign_in_sessions(&self.client, upn))
+    }
+
+    fn list_authentication_methods(&self, py: Python<'_>, upn: &str) -> PyResult<PyObject> {
+        let methods = self.block_on(py, remediation::list_authentication_methods(&self.client, upn))?;
+        to_python(py, &methods)
+    }
+
+    fn delete_authentication_method(&self, py: Python<'_>, upn: &str, method_id: &str) -> PyResult<()> {
+        self.block_on(py, async {
+            let methods = remediation::list_authentication_methods(&self.client, upn).await?;
+            let method = methods
+                .iter()
+                .find(|method| method.id == method_id)
+                .ok_or_else(|| anyhow::anyhow!("Authentication method {} not found for {}", method_id, upn))?;
+            remediation::delete_authentication_method(&self.client, upn, method).await
+        })
+    }
+}
+
+#[pymodule]
+fn mstools(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<GraphSession>()?;
+    Ok(())
+}
@@ -0,0 +1,515 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{mail, remediation, GraphClient};
+use log::{debug, error, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(name = "offboard_user")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Runs the leaver sequence against a user and writes a JSON record of every step")]
+struct AppConfig {
+    #[arg(short, long, help = "User Principal Name of the leaver")]
+    upn: String,
+
+    #[arg(short, long, help = "HR ticket reference, included in the record")]
+    ticket: Option<String>,
+
+    #[arg(long, help = "Automatic reply sent to every sender, internal and external")]
+    out_of_office: Option<String>,
+
+    #[arg(long, conflicts_with = "forward_to", help = "Forward incoming mail to the leaver's manager")]
+    forward_to_manager: bool,
+
+    #[arg(long, help = "Forward incoming mail to this address")]
+    forward_to: Option<String>,
+
+    #[arg(short, long, help = "File of group names or ids the leaver stays a member of, one per line")]
+    exclude: Option<PathBuf>,
+
+    #[arg(long, help = "Do not remove MFA methods")]
+    skip_mfa: bool,
+
+    #[arg(long, help = "Do not remove group memberships")]
+    skip_groups: bool,
+
+    #[arg(long, help = "Do not remove directly assigned licenses")]
+    skip_licenses: bool,
+
+    #[arg(short, long, help = "Write the record to this file instead of stdout")]
+    report: Option<PathBuf>,
+
+    #[arg(long, help = "Record the steps that would be taken without changing anything")]
+    dry_run: bool,
+
+    #[arg(long, help = "Enable info level logging")]
+    info: bool,
+
+    #[arg(long, help = "Enable debug level logging")]
+    debug: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Succeeded,
+    Failed,
+    Skipped,
+    Planned,
+}
+
+#[derive(Serialize, Debug)]
+struct Step {
+    step: String,
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+struct OffboardingRecord {
+    upn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ticket: Option<String>,
+    operator: String,
+    #[serde(rename = "dryRun")]
+    dry_run: bool,
+    #[serde(rename = "startedAt")]
+    started_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    finished_at: Option<DateTime<Utc>>,
+    steps: Vec<Step>,
+}
+
+impl OffboardingRecord {
+    fn record(&mut self, step: &str, status: Status, detail: Option<String>) {
+        match status {
+            Status::Failed => error!("{}: failed: {}", step, detail.as_deref().unwrap_or("")),
+            _ => info!("{}: {:?} {}", step, status, detail.as_deref().unwrap_or("")),
+        }
+        self.steps.push(Step {
+            step: step.to_string(),
+            status,
+            detail,
+            at: Utc::now(),
+        });
+    }
+
+    fn record_result(&mut self, step: &str, result: Result<String>) {
+        match result {
+            Ok(detail) => self.record(step, Status::Succeeded, Some(detail)),
+            Err(e) => self.record(step, Status::Failed, Some(format!("{:#}", e))),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| step.status == Status::Failed)
+            .count()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+    #[serde(rename = "licenseAssignmentStates", default)]
+    license_assignment_states: Vec<LicenseAssignmentState>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LicenseAssignmentState {
+    #[serde(rename = "skuId")]
+    sku_id: String,
+    #[serde(rename = "assignedByGroup")]
+    assigned_by_group: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Manager {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    mail: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Membership {
+    #[serde(rename = "@odata.type")]
+    odata_type: String,
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "groupTypes", default)]
+    group_types: Vec<String>,
+    #[serde(rename = "mailEnabled")]
+    mail_enabled: Option<bool>,
+    #[serde(rename = "onPremisesSyncEnabled")]
+    on_premises_sync_enabled: Option<bool>,
+}
+
+fn setup_logger(config: &AppConfig) {
+    let mut builder = Builder::from_default_env();
+    builder.filter(
+        None,
+        if config.debug {
+            LevelFilter::Debug
+        } else if config.info {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        },
+    );
+    builder.init();
+}
+
+fn read_exclusions(path: &PathBuf) -> Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+async fn read_user(client: &GraphClient, upn: &str) -> Result<User> {
+    client
+        .user(upn)
+        .resource()
+        .select("id,licenseAssignmentStates")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read {}", upn))
+}
+
+async fn disable_account(client: &GraphClient, config: &AppConfig, record: &mut OffboardingRecord) {
+    let step = "disable_account";
+    if config.dry_run {
+        record.record(step, Status::Planned, None);
+        return;
+    }
+    let result = remediation::set_account_enabled(client, &config.upn, false).await;
+    record.record_result(step, result.map(|_| "accountEnabled=false".to_string()));
+}
+
+async fn revoke_sessions(client: &GraphClient, config: &AppConfig, record: &mut OffboardingRecord) {
+    let step = "revoke_sign_in_sessions";
+    if config.dry_run {
+        record.record(step, Status::Planned, None);
+        return;
+    }
+    let result = remediation::revoke_sign_in_sessions(client, &config.upn).await;
+    record.record_result(step, result.map(|_| "all refresh tokens invalidated".to_string()));
+}
+
+async fn remove_mfa_methods(client: &GraphClient, config: &AppConfig, record: &mut OffboardingRecord) {
+    if config.skip_mfa {
+        record.record("delete_mfa_method", Status::Skipped, None);
+        return;
+    }
+
+    let methods = match remediation::list_authentication_methods(client, &config.upn).await {
+        Ok(methods) => methods,
+        Err(e) => {
+            record.record("list_mfa_methods", Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+    let removable: Vec<_> = methods
+        .iter()
+        .filter(|method| method.short_name() != Some("password"))
+        .collect();
+    record.record(
+        "list_mfa_methods",
+        Status::Succeeded,
+        Some(format!("{} methods besides the password", removable.len())),
+    );
+
+    for method in removable {
+        let detail = format!(
+            "{} {}",
+            method.short_name().unwrap_or(&method.odata_type),
+            method.id
+        );
+        if config.dry_run {
+            record.record("delete_mfa_method", Status::Planned, Some(detail));
+            continue;
+        }
+        let result = remediation::delete_authentication_method(client, &config.upn, method).await;
+        record.record_result("delete_mfa_method", result.map(|_| detail));
+    }
+}
+
+async fn set_out_of_office(client: &GraphClient, config: &AppConfig, record: &mut OffboardingRecord) {
+    let step = "set_out_of_office";
+    let Some(message) = &config.out_of_office else {
+        record.record(step, Status::Skipped, None);
+        return;
+    };
+    if config.dry_run {
+        record.record(step, Status::Planned, Some(message.clone()));
+        return;
+    }
+    let result = mail::set_automatic_replies(client, &config.upn, message).await;
+    record.record_result(step, result.map(|_| message.clone()));
+}
+
+async fn forwarding_address(client: &GraphClient, config: &AppConfig) -> Result<Option<String>> {
+    if let Some(address) = &config.forward_to {
+        return Ok(Some(address.clone()));
+    }
+    if !config.forward_to_manager {
+        return Ok(None);
+    }
+    let manager: Manager = client
+        .user(&config.upn)
+        .resource()
+        .segment("manager")
+        .select("displayName,mail")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to read the manager of {}", config.upn))?;
+    let address = manager.mail.with_context(|| {
+        format!(
+            "The manager of {}, {}, has no mailbox",
+            config.upn,
+            manager.display_name.as_deref().unwrap_or("unnamed")
+        )
+    })?;
+    Ok(Some(address))
+}
+
+async fn forward_mail(client: &GraphClient, config: &AppConfig, record: &mut OffboardingRecord) {
+    let step = "forward_mail";
+    let address = match forwarding_address(client, config).await {
+        Ok(Some(address)) => address,
+        Ok(None) => {
+            record.record(step, Status::Skipped, None);
+            return;
+        }
+        Err(e) => {
+            record.record(step, Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+    if config.dry_run {
+        record.record(step, Status::Planned, Some(format!("to {}", address)));
+        return;
+    }
+    let result = mail::create_forwarding_rule(client, &config.upn, "Offboarding: forward to successor", &address).await;
+    record.record_result(step, result.map(|rule_id| format!("to {}, inbox rule {}", address, rule_id)));
+}
+
+// Dynamic and synced groups cannot be changed through Graph, nor can distribution lists and
+// mail-enabled security groups, which are managed in Exchange.
+fn skip_reason(group: &Membership, exclusions: &HashSet<String>) -> Option<&'static str> {
+    let display_name = group.display_name.as_deref().unwrap_or_default().to_lowercase();
+    if exclusions.contains(&group.id.to_lowercase()) || exclusions.contains(&display_name) {
+        Some("excluded")
+    } else if group.group_types.iter().any(|group_type| group_type == "DynamicMembership") {
+        Some("dynamic membership")
+    } else if group.on_premises_sync_enabled == Some(true) {
+        Some("synced from on-premises")
+    } else if group.mail_enabled == Some(true) && !group.group_types.iter().any(|group_type| group_type == "Unified") {
+        Some("managed in Exchange")
+    } else {
+        None
+    }
+}
+
+async fn remove_group_memberships(
+    client: &GraphClient,
+    config: &AppConfig,
+    user_id: &str,
+    exclusions: &HashSet<String>,
+    record: &mut OffboardingRecord,
+) {
+    if config.skip_groups {
+        record.record("remove_group_member", Status::Skipped, None);
+        return;
+    }
+
+    let memberships: Vec<Membership> = match client
+        .user(&config.upn)
+        .member_of()
+        .select("id,displayName,groupTypes,mailEnabled,onPremisesSyncEnabled")
+        .get_all()
+        .await
+    {
+        Ok(memberships) => memberships,
+        Err(e) => {
+            record.record("list_group_memberships", Status::Failed, Some(format!("{:#}", e)));
+            return;
+        }
+    };
+    let groups: Vec<&Membership> = memberships
+        .iter()
+        .filter(|membership| membership.odata_type == "#microsoft.graph.group")
+        .collect();
+    record.record(
+        "list_group_memberships",
+        Status::Succeeded,
+        Some(format!("{} direct group memberships", groups.len())),
+    );
+
+    for group in groups {
+        let name = group.display_name.as_deref().unwrap_or(&group.id);
+        if let Some(reason) = skip_reason(group, exclusions) {
+            record.record("remove_group_member", Status::Skipped, Some(format!("{}: {}", name, reason)));
+            continue;
+        }
+        if config.dry_run {
+            record.record("remove_group_member", Status::Planned, Some(name.to_string()));
+            continue;
+        }
+        let result = remediation::remove_group_member(client, &group.id, user_id).await;
+        record.record_result("remove_group_member", result.map(|_| name.to_string()));
+    }
+}
+
+// Group-based licenses go with the group memberships; only direct assignments are removed here.
+async fn remove_licenses(client: &GraphClient, config: &AppConfig, user: &User, record: &mut OffboardingRecord) {
+    let step = "remove_licenses";
+    if config.skip_licenses {
+        record.record(step, Status::Skipped, None);
+        return;
+    }
+    let mut sku_ids: Vec<String> = user
+        .license_assignment_states
+        .iter()
+        .filter(|state| state.assigned_by_group.is_none())
+        .map(|state| state.sku_id.clone())
+        .collect();
+    sku_ids.sort();
+    sku_ids.dedup();
+    if sku_ids.is_empty() {
+        record.record(step, Status::Skipped, Some("no directly assigned licenses".to_string()));
+        return;
+    }
+    let detail = sku_ids.join(", ");
+    if config.dry_run {
+        record.record(step, Status::Planned, Some(detail));
+        return;
+    }
+    let result = remediation::remove_licenses(client, &config.upn, &sku_ids).await;
+    record.record_result(step, result.map(|_| detail));
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(&config);
+
+    info!("Starting offboarding of {}", config.upn);
+    debug!("Configuration: {:?}", config);
+
+    let exclusions = match &config.exclude {
+        Some(path) => read_exclusions(path)?,
+        None => HashSet::new(),
+    };
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut required = vec![if config.dry_run { "User.Read.All" } else { "User.ReadWrite.All" }];
+    if !config.skip_mfa {
+        required.push(if config.dry_run {
+            "UserAuthenticationMethod.Read.All"
+        } else {
+            "UserAuthenticationMethod.ReadWrite.All"
+        });
+    }
+    if config.out_of_office.is_some() || config.forward_to_manager || config.forward_to.is_some() {
+        required.push(if config.dry_run { "MailboxSettings.Read" } else { "MailboxSettings.ReadWrite" });
+    }
+    if !config.skip_groups {
+        required.push(if config.dry_run { "GroupMember.Read.All" } else { "GroupMember.ReadWrite.All" });
+    }
+    client.require_permissions(&required)?;
+
+    let user = read_user(&client, &config.upn).await?;
+    let mut record = OffboardingRecord {
+        upn: config.upn.clone(),
+        ticket: config.ticket.clone(),
+        operator: graph_common::current_operator(),
+        dry_run: config.dry_run,
+        started_at: Utc::now(),
+        finished_at: None,
+        steps: Vec::new(),
+    };
+
+    // Access is cut first. The reply and the forwarding are set while the mailbox is still
+    // licensed, and group memberships go before direct licenses so group-based ones go with them.
+    disable_account(&client, &config, &mut record).await;
+    revoke_sessions(&client, &config, &mut record).await;
+    remove_mfa_methods(&client, &config, &mut record).await;
+    set_out_of_office(&client, &config, &mut record).await;
+    forward_mail(&client, &config, &mut record).await;
+    remove_group_memberships(&client, &config, &user.id, &exclusions, &mut record).await;
+    remove_licenses(&client, &config, &user, &mut record).await;
+    record.finished_at = Some(Utc::now());
+
+    let json = serde_json::to_string_pretty(&record)?;
+    match &config.report {
+        Some(path) => {
+            std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+            info!("Offboarding record written to {}", path.display());
+        }
+        None => println!("{}", json),
+    }
+
+    let failures = record.failures();
+    if failures > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} steps failed; see the offboarding record",
+            failures,
+            record.steps.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(display_name: &str, group_types: &[&str], mail_enabled: bool, synced: bool) -> Membership {
+        Membership {
+            odata_type: "#microsoft.graph.group".to_string(),
+            id: format!("{}-id", display_name.to_lowercase()),
+            display_name: Some(display_name.to_string()),
+            group_types: group_types.iter().map(|group_type| group_type.to_string()).collect(),
+            mail_enabled: Some(mail_enabled),
+            on_premises_sync_enabled: synced.then_some(true),
+        }
+    }
+
+    #[test]
+    fn skips_groups_that_cannot_be_changed() {
+        let exclusions = HashSet::from(["all staff".to_string(), "payroll-id".to_string()]);
+        let cases = [
+            (group("Sales", &["Unified"], true, false), None),
+            (group("VPN Users", &[], false, false), None),
+            (group("All Staff", &["Unified"], true, false), Some("excluded")),
+            (group("Payroll", &[], false, false), Some("excluded")),
+            (group("Engineering", &["Unified", "DynamicMembership"], true, false), Some("dynamic membership")),
+            (group("File Share Access", &[], false, true), Some("synced from on-premises")),
+            (group("sales-dl", &[], true, false), Some("managed in Exchange")),
+        ];
+        for (group, expected) in cases {
+            assert_eq!(skip_reason(&group, &exclusions), expected, "{:?}", group.display_name);
+        }
+    }
+}
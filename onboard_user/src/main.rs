@@ -0,0 +1,529 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::is_object_id;
+use graph_common::actionlog::{Change, Undo};
+use graph_common::licenses::SkuCatalog;
+use graph_common::remediation::{self, PasswordPolicy};
+use graph_common::{client, csv, encode, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "onboard_user")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Creates users with licenses and group memberships using the Microsoft API")]
+#[command(group(ArgGroup::new("target").required(true).args(["upn", "file"])))]
+struct AppConfig {
+    #[arg(short, long, requires = "display_name", help = "User Principal Name of the new user")]
+    upn: Option<String>,
+
+    #[arg(short = 'n', long, help = "Display name of the new user")]
+    display_name: Option<String>,
+
+    #[arg(short, long, help = "CSV of joiners with upn, displayName, and optional usageLocation, licenses, and groups columns (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(long, help = "Two-letter country code, required for license assignment (default for rows without one)")]
+    usage_location: Option<String>,
+
+    #[arg(short, long, help = "License to assign by SKU part number or product name, e.g. SPE_E3 or \"Microsoft 365 E3\" (repeatable, added to every user)")]
+    license: Vec<String>,
+
+    #[arg(short, long, help = "Group to add the user to, by name or object id (repeatable, added to every user)")]
+    group: Vec<String>,
+
+    #[arg(short, long, help = "Write the initial passwords to this new file (owner-only permissions) instead of the terminal")]
+    output: Option<PathBuf>,
+
+    #[arg(long, help = "Validate the input and show the users that would be created without creating them")]
+    dry_run: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Joiner {
+    line: usize,
+    upn: String,
+    display_name: String,
+    usage_location: Option<String>,
+    licenses: Vec<String>,
+    groups: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CreatedUser {
+    id: String,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn read_file(source: &str) -> Result<String> {
+    if source == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read joiners from stdin")?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))
+    }
+}
+
+// Adds the values given on the command line to a row's own, without duplicates.
+fn merge(row: &str, defaults: &[String]) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for value in row.split(';').map(str::trim).chain(defaults.iter().map(String::as_str)) {
+        if !value.is_empty() && !values.iter().any(|existing| existing.eq_ignore_ascii_case(value)) {
+            values.push(value.to_string());
+        }
+    }
+    values
+}
+
+fn read_joiners(content: &str, config: &AppConfig) -> Result<Vec<Joiner>> {
+    let table = csv::Table::parse(content)?;
+    let upn = table.upn_column()?;
+    let display_name = table
+        .column(&["displayName", "name"])
+        .context("The file has no displayName column")?;
+    let usage_location = table.column(&["usageLocation"]);
+    let licenses = table.column(&["licenses", "license"]);
+    let groups = table.column(&["groups", "group"]);
+    let optional = |record: &csv::Record, column: Option<usize>| column.map_or("", |column| record.get(column)).to_string();
+
+    Ok(table
+        .records
+        .iter()
+        .map(|record| Joiner {
+            line: record.line,
+            upn: record.get(upn).to_string(),
+            display_name: record.get(display_name).to_string(),
+            usage_location: Some(optional(record, usage_location))
+                .filter(|location| !location.is_empty())
+                .or_else(|| config.usage_location.clone()),
+            licenses: merge(&optional(record, licenses), &config.license),
+            groups: merge(&optional(record, groups), &config.group),
+        })
+        .collect())
+}
+
+fn validate(joiner: &Joiner) -> Result<()> {
+    let valid_upn = joiner
+        .upn
+        .split_once('@')
+        .is_some_and(|(name, domain)| !name.is_empty() && domain.contains('.') && !domain.contains('@'));
+    if !valid_upn {
+        return Err(anyhow::anyhow!("{:?} is not a valid user principal name", joiner.upn));
+    }
+    if joiner.display_name.is_empty() {
+        return Err(anyhow::anyhow!("{} has no display name", joiner.upn));
+    }
+    match &joiner.usage_location {
+        Some(location) if location.len() != 2 || !location.chars().all(|c| c.is_ascii_alphabetic()) => Err(
+            anyhow::anyhow!("{}: usage location {:?} is not a two-letter country code", joiner.upn, location),
+        ),
+        None if !joiner.licenses.is_empty() => Err(anyhow::anyhow!(
+            "{} needs a usage location to be assigned licenses",
+            joiner.upn
+        )),
+        _ => Ok(()),
+    }
+}
+
+async fn resolve_group(client: &GraphClient, group: &str) -> Result<Group> {
+    if is_object_id(group) {
+        return client
+            .path(&["groups", group])
+            .select("id,displayName")
+            .get_json::<Group>()
+            .await
+            .with_context(|| format!("Failed to look up group {}", group));
+    }
+
+    let mut groups = client
+        .path(&["groups"])
+        .filter(&format!("displayName eq {}", encode::odata_string(group)))
+        .select("id,displayName")
+        .get_all::<Group>()
+        .await
+        .with_context(|| format!("Failed to look up group {}", group))?;
+    match groups.len() {
+        0 => Err(anyhow::anyhow!("No group named {}", group)),
+        1 => Ok(groups.remove(0)),
+        count => Err(anyhow::anyhow!(
+            "{} groups are named {}; pass the group's object id instead",
+            count,
+            group
+        )),
+    }
+}
+
+// Every license is resolved and counted against the free seats before anyone is created, so a
+// typo or a shortage fails the whole run instead of leaving some joiners without licenses.
+fn resolve_licenses(catalog: &SkuCatalog, joiners: &[Joiner]) -> Result<HashMap<String, (String, String)>> {
+    let mut resolved = HashMap::new();
+    let mut needed: HashMap<String, u32> = HashMap::new();
+    for name in joiners.iter().flat_map(|joiner| &joiner.licenses) {
+        let sku = catalog
+            .find_by_name(name)
+            .with_context(|| format!("The tenant has no subscription named {}", name))?;
+        *needed.entry(sku.sku_id.clone()).or_default() += 1;
+        resolved.insert(name.to_lowercase(), (sku.sku_id.clone(), sku.display_name()));
+    }
+    for (sku_id, count) in needed {
+        let sku = catalog.get(&sku_id).context("Resolved SKU is missing from the catalog")?;
+        let available = sku.prepaid_units.enabled.saturating_sub(sku.consumed_units);
+        if count > available {
+            return Err(anyhow::anyhow!(
+                "{} is needed for {} users but only {} seats are free",
+                sku.display_name(),
+                count,
+                available
+            ));
+        }
+    }
+    Ok(resolved)
+}
+
+fn mail_nickname(upn: &str) -> String {
+    upn.split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .collect()
+}
+
+// The undo deletes the user by UPN, since the object id is only known once it exists.
+async fn create_user(client: &GraphClient, joiner: &Joiner, password: &str) -> Result<String> {
+    let url = client.users().url();
+    let mut body = json!({
+        "accountEnabled": true,
+        "displayName": joiner.display_name,
+        "mailNickname": mail_nickname(&joiner.upn),
+        "userPrincipalName": joiner.upn,
+        "passwordProfile": {
+            "password": password,
+            "forceChangePasswordNextSignIn": true,
+        },
+    });
+    if let Some(location) = &joiner.usage_location {
+        body["usageLocation"] = json!(location.to_uppercase());
+    }
+    let change = Change::new("POST", &url, Some(&body)).with_undo(Undo {
+        method: "DELETE".to_string(),
+        url: client.user(&joiner.upn).resource().url(),
+        body: None,
+    });
+    let response = client.send_change(change).await?;
+    let user: CreatedUser = client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to create {}", joiner.upn))?;
+    Ok(user.id)
+}
+
+// Returns the steps that failed after the user was created.
+async fn onboard(
+    client: &GraphClient,
+    joiner: &Joiner,
+    password: &str,
+    licenses: &HashMap<String, (String, String)>,
+    groups: &HashMap<String, Group>,
+) -> Result<Vec<String>> {
+    let user_id = create_user(client, joiner, password).await?;
+    let mut failures = Vec::new();
+
+    let sku_ids: Vec<String> = joiner
+        .licenses
+        .iter()
+        .filter_map(|name| licenses.get(&name.to_lowercase()))
+        .map(|(sku_id, _)| sku_id.clone())
+        .collect();
+    if let Err(e) = remediation::assign_licenses(client, &user_id, &sku_ids).await {
+        failures.push(format!("{:#}", e));
+    }
+    for name in &joiner.groups {
+        let Some(group) = groups.get(&name.to_lowercase()) else {
+            continue;
+        };
+        if let Err(e) = remediation::add_group_member(client, &group.id, &user_id).await {
+            failures.push(format!("{}: {:#}", group.display_name.as_deref().unwrap_or(name), e));
+        }
+    }
+    Ok(failures)
+}
+
+fn describe(joiner: &Joiner, licenses: &HashMap<String, (String, String)>, groups: &HashMap<String, Group>) -> String {
+    let mut parts = Vec::new();
+    if !joiner.licenses.is_empty() {
+        let names: Vec<&str> = joiner
+            .licenses
+            .iter()
+            .filter_map(|name| licenses.get(&name.to_lowercase()))
+            .map(|(_, display_name)| display_name.as_str())
+            .collect();
+        parts.push(format!("licenses {}", names.join(", ")));
+    }
+    if !joiner.groups.is_empty() {
+        let names: Vec<&str> = joiner
+            .groups
+            .iter()
+            .filter_map(|name| groups.get(&name.to_lowercase()))
+            .map(|group| group.display_name.as_deref().unwrap_or(&group.id))
+            .collect();
+        parts.push(format!("groups {}", names.join(", ")));
+    }
+    if parts.is_empty() {
+        "no licenses or groups".to_string()
+    } else {
+        parts.join("; ")
+    }
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+// Initial passwords are only ever shown here, once, and are never logged.
+fn deliver_passwords(passwords: &[(String, String)], output: Option<&Path>) -> Result<()> {
+    if passwords.is_empty() {
+        return Ok(());
+    }
+    match output {
+        Some(path) => {
+            let mut file = create_private_file(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            writeln!(file, "upn,password")?;
+            for (upn, password) in passwords {
+                writeln!(file, "{},{}", upn, password)?;
+            }
+            println!("{} initial passwords written to {}", passwords.len(), path.display());
+        }
+        None => {
+            println!();
+            println!("Initial passwords (shown once, store them securely now):");
+            for (upn, password) in passwords {
+                println!("{}  {}", upn, password);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting onboard_user");
+    debug!("Configuration: {:?}", config);
+
+    if let Some(output) = &config.output {
+        if output.exists() {
+            return Err(anyhow::anyhow!("{} already exists", output.display()));
+        }
+    }
+    let joiners = match (&config.upn, &config.file) {
+        (Some(upn), _) => vec![Joiner {
+            line: 0,
+            upn: upn.trim().to_string(),
+            display_name: config.display_name.clone().unwrap_or_default().trim().to_string(),
+            usage_location: config.usage_location.clone(),
+            licenses: merge("", &config.license),
+            groups: merge("", &config.group),
+        }],
+        (None, Some(file)) => read_joiners(&read_file(file)?, &config)?,
+        (None, None) => unreachable!("clap requires --upn or --file"),
+    };
+    if joiners.is_empty() {
+        return Err(anyhow::anyhow!("No joiners found in the input"));
+    }
+    let mut seen = HashSet::new();
+    for joiner in &joiners {
+        validate(joiner).with_context(|| match joiner.line {
+            0 => "Invalid user".to_string(),
+            line => format!("Line {}", line),
+        })?;
+        if !seen.insert(joiner.upn.to_lowercase()) {
+            return Err(anyhow::anyhow!("{} appears more than once (line {})", joiner.upn, joiner.line));
+        }
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let with_licenses = joiners.iter().any(|joiner| !joiner.licenses.is_empty());
+    let with_groups = joiners.iter().any(|joiner| !joiner.groups.is_empty());
+    let mut required = vec![if config.dry_run { "User.Read.All" } else { "User.ReadWrite.All" }];
+    if with_licenses {
+        required.push("Organization.Read.All|Directory.Read.All");
+    }
+    if with_groups {
+        required.push(if config.dry_run { "GroupMember.Read.All" } else { "GroupMember.ReadWrite.All" });
+    }
+    client.require_permissions(&required)?;
+
+    let phase = client.stats().phase("resolve licenses and groups");
+    let licenses = if with_licenses {
+        resolve_licenses(&SkuCatalog::load(&client).await?, &joiners)?
+    } else {
+        HashMap::new()
+    };
+    let mut groups = HashMap::new();
+    for name in joiners.iter().flat_map(|joiner| &joiner.groups) {
+        if let Entry::Vacant(entry) = groups.entry(name.to_lowercase()) {
+            entry.insert(resolve_group(&client, name).await?);
+        }
+    }
+    drop(phase);
+
+    let phase = client.stats().phase("create users");
+    let policy = PasswordPolicy::default();
+    let mut passwords = Vec::new();
+    let mut failed = 0;
+    for joiner in &joiners {
+        let description = describe(joiner, &licenses, &groups);
+        if config.dry_run {
+            println!("CREATE  {}: {}", joiner.upn, description);
+            continue;
+        }
+        let password = remediation::generate_password(&policy)?;
+        match onboard(&client, joiner, &password, &licenses, &groups).await {
+            Ok(failures) => {
+                passwords.push((joiner.upn.clone(), password));
+                if failures.is_empty() {
+                    println!("OK      {}: {}", joiner.upn, description);
+                } else {
+                    failed += 1;
+                    println!("FAILED  {}: created, but {}", joiner.upn, failures.join("; "));
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", joiner.upn, e);
+            }
+        }
+    }
+    drop(phase);
+
+    deliver_passwords(&passwords, config.output.as_deref())?;
+    println!();
+    if config.dry_run {
+        println!("Processed {} users: {} to create", joiners.len(), joiners.len());
+        println!("Dry run, no changes were made.");
+    } else {
+        println!(
+            "Processed {} users: {} created, {} failed",
+            joiners.len(),
+            joiners.len() - failed,
+            failed
+        );
+    }
+    client.stats().summary().print();
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, joiners.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_validates_joiners() {
+        let config = AppConfig::parse_from([
+            "onboard_user",
+            "-f",
+            "joiners.csv",
+            "--usage-location",
+            "GB",
+            "-g",
+            "All Staff",
+        ]);
+        let content = "upn,displayName,usageLocation,licenses,groups\n\
+            jane.doe@contoso.com,Jane Doe,US,SPE_E3; Visio Plan 2,Sales;all staff\n\
+            # contractors get no license\n\
+            john.smith@contoso.com,\"Smith, John\",,,\n";
+        let joiners = read_joiners(content, &config).unwrap();
+        assert_eq!(
+            joiners,
+            vec![
+                Joiner {
+                    line: 2,
+                    upn: "jane.doe@contoso.com".to_string(),
+                    display_name: "Jane Doe".to_string(),
+                    usage_location: Some("US".to_string()),
+                    licenses: vec!["SPE_E3".to_string(), "Visio Plan 2".to_string()],
+                    groups: vec!["Sales".to_string(), "all staff".to_string()],
+                },
+                Joiner {
+                    line: 4,
+                    upn: "john.smith@contoso.com".to_string(),
+                    display_name: "Smith, John".to_string(),
+                    usage_location: Some("GB".to_string()),
+                    licenses: Vec::new(),
+                    groups: vec!["All Staff".to_string()],
+                },
+            ]
+        );
+        assert!(joiners.iter().all(|joiner| validate(joiner).is_ok()));
+
+        let invalid = |upn: &str, usage_location: Option<&str>, licenses: &[&str]| Joiner {
+            line: 1,
+            upn: upn.to_string(),
+            display_name: "Test".to_string(),
+            usage_location: usage_location.map(str::to_string),
+            licenses: licenses.iter().map(|license| license.to_string()).collect(),
+            groups: Vec::new(),
+        };
+        assert!(validate(&invalid("jane.doe", Some("US"), &[])).is_err());
+        assert!(validate(&invalid("jane.doe@contoso.com", Some("USA"), &[])).is_err());
+        assert!(validate(&invalid("jane.doe@contoso.com", None, &["SPE_E3"])).is_err());
+        assert_eq!(mail_nickname("o'brien.j+new@contoso.com"), "obrien.jnew");
+    }
+}
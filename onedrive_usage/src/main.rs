@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::reports::SiteUsage;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::Serialize;
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "onedrive_usage")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists OneDrive storage used and allocated per user, flagging accounts close to their quota, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100), help = "Flag accounts that use at least this percentage of their allocated storage")]
+    threshold: u8,
+
+    #[arg(long, help = "List only flagged accounts")]
+    flagged: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct OneDriveUsage {
+    owner: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    url: String,
+    #[serde(rename = "fileCount")]
+    file_count: Option<u64>,
+    #[serde(rename = "activeFileCount")]
+    active_file_count: Option<u64>,
+    #[serde(rename = "storageUsedBytes")]
+    storage_used: Option<u64>,
+    #[serde(rename = "storageAllocatedBytes")]
+    storage_allocated: Option<u64>,
+    #[serde(rename = "percentUsed")]
+    percent_used: Option<f64>,
+    #[serde(rename = "lastActivity")]
+    last_activity: Option<String>,
+    flagged: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Sorted with the fullest accounts first.
+fn onedrive_usage(usage: &SiteUsage, threshold: u8) -> Vec<OneDriveUsage> {
+    let mut rows: Vec<OneDriveUsage> = usage
+        .sites()
+        .map(|site| {
+            let percent_used = match (site.storage_used, site.storage_allocated) {
+                (Some(used), Some(allocated)) if allocated > 0 => Some((used as f64 * 1000.0 / allocated as f64).round() / 10.0),
+                _ => None,
+            };
+            OneDriveUsage {
+                owner: site.owner_principal_name.clone(),
+                display_name: site.owner_display_name.clone(),
+                url: site.url.clone(),
+                file_count: site.file_count,
+                active_file_count: site.active_file_count,
+                storage_used: site.storage_used,
+                storage_allocated: site.storage_allocated,
+                percent_used,
+                last_activity: site.last_activity.map(|date| date.to_string()),
+                flagged: percent_used.is_some_and(|percent| percent >= f64::from(threshold)),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        b.percent_used
+            .unwrap_or(-1.0)
+            .total_cmp(&a.percent_used.unwrap_or(-1.0))
+            .then_with(|| b.storage_used.cmp(&a.storage_used))
+            .then_with(|| a.owner.to_lowercase().cmp(&b.owner.to_lowercase()))
+    });
+    rows
+}
+
+fn gigabytes(bytes: Option<u64>) -> String {
+    bytes.map_or_else(|| "-".to_string(), |bytes| format!("{:.1}", bytes as f64 / 1073741824.0))
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn render_report(rows: &[OneDriveUsage], total: usize, threshold: u8, output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "accounts": rows,
+                "totalAccounts": total,
+                "threshold": threshold,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "owner,displayName,url,fileCount,activeFileCount,storageUsedBytes,storageAllocatedBytes,percentUsed,lastActivity,flagged"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    csv::field(&row.owner),
+                    csv::field(&row.display_name),
+                    csv::field(&row.url),
+                    optional(row.file_count),
+                    optional(row.active_file_count),
+                    optional(row.storage_used),
+                    optional(row.storage_allocated),
+                    optional(row.percent_used),
+                    optional(row.last_activity.as_deref()),
+                    row.flagged
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<35} {:>8} {:>9} {:>6} {:>8} {:<13} STATUS",
+                "OWNER", "USED GB", "QUOTA GB", "USED", "FILES", "LAST ACTIVITY"
+            )?;
+            for row in rows {
+                let line = format!(
+                    "{:<35} {:>8} {:>9} {:>6} {:>8} {:<13} {}",
+                    row.owner,
+                    gigabytes(row.storage_used),
+                    gigabytes(row.storage_allocated),
+                    row.percent_used.map_or_else(|| "-".to_string(), |percent| format!("{:.1}%", percent)),
+                    row.file_count.map_or_else(|| "-".to_string(), |count| count.to_string()),
+                    row.last_activity.as_deref().unwrap_or("never"),
+                    if row.flagged { "near quota" } else { "" }
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} of {} accounts listed, {} GB used of {} GB allocated; {} at or above {}% of their storage",
+                rows.len(),
+                total,
+                gigabytes(Some(rows.iter().filter_map(|row| row.storage_used).sum())),
+                gigabytes(Some(rows.iter().filter_map(|row| row.storage_allocated).sum())),
+                rows.iter().filter(|row| row.flagged).count(),
+                threshold
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+async fn load_usage(client: &GraphClient) -> Result<SiteUsage> {
+    let _phase = client.stats().phase("read usage report");
+    let usage = SiteUsage::load_onedrive(client).await?;
+    if !usage.is_empty() && usage.sites().all(|site| !site.owner_principal_name.contains('@')) {
+        warn!("The OneDrive usage report conceals user names, so accounts are listed by hash; turn off concealed names in the Microsoft 365 admin center reports settings to see them");
+    }
+    info!("The OneDrive usage report lists {} accounts", usage.len());
+    Ok(usage)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting onedrive_usage");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Reports.Read.All"])?;
+
+    let usage = load_usage(&client).await?;
+    let mut rows = onedrive_usage(&usage, config.threshold);
+    let total = rows.len();
+    if config.flagged {
+        rows.retain(|row| row.flagged);
+    }
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&rows, total, config.threshold, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let usage = load_usage(&client).await.unwrap();
+        let rows = onedrive_usage(&usage, 90);
+        assert_eq!(rows.iter().filter(|row| row.flagged).count(), 2);
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&rows, rows.len(), 90, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::collect_upns;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(name = "provision_onedrive")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Creates the OneDrive of new users before their first sign-in and reports when each one is ready, using the Microsoft API")]
+#[command(group(ArgGroup::new("users").required(true).multiple(true).args(["upn", "file"])))]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "User principal names to provision (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "File of user principal names to provision, one per line")]
+    file: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=100), help = "Number of times to request each OneDrive before giving up")]
+    attempts: u32,
+
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(5..), help = "Seconds to wait between attempts")]
+    interval: u64,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Drive {
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ProvisionRow {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    // ready: the OneDrive already existed; provisioned: it was created during this run;
+    // pending: it was still being created after the last attempt; failed: it could not be requested.
+    status: &'static str,
+    attempts: u32,
+    #[serde(rename = "webUrl")]
+    web_url: String,
+    note: String,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.to_string().starts_with("HTTP error 404")
+}
+
+fn row(upn: &str, status: &'static str, attempts: u32, note: String) -> ProvisionRow {
+    ProvisionRow {
+        user_principal_name: upn.to_string(),
+        status,
+        attempts,
+        web_url: String::new(),
+        note,
+    }
+}
+
+// Requesting the drive of a licensed user without a OneDrive answers 404 and starts creating
+// it; later requests answer 404 until it is ready. All users still waiting are requested again
+// through $batch after each interval.
+async fn provision(client: &GraphClient, upns: &[String], attempts: u32, interval: Duration, shutdown: &mut ShutdownSignal) -> Result<Vec<ProvisionRow>> {
+    let phase = client.stats().phase("check users");
+    let urls: Vec<String> = upns
+        .iter()
+        .map(|upn| client.user(upn).resource().select("id").relative_url())
+        .collect();
+    let users: Vec<Result<User>> = client.batch_get(&urls).await?;
+    drop(phase);
+    let mut rows = Vec::new();
+    let mut pending = Vec::new();
+    for (upn, user) in upns.iter().zip(users) {
+        match user {
+            Ok(user) => {
+                debug!("{} has id {}", upn, user.id);
+                pending.push(rows.len());
+                rows.push(row(upn, "pending", 0, String::new()));
+            }
+            Err(e) if is_not_found(&e) => rows.push(row(upn, "failed", 0, "user not found".to_string())),
+            Err(e) => rows.push(row(upn, "failed", 0, format!("{:#}", e))),
+        }
+    }
+
+    let _phase = client.stats().phase("request drives");
+    for attempt in 1..=attempts {
+        let urls: Vec<String> = pending
+            .iter()
+            .map(|&index| client.user(&rows[index].user_principal_name).resource().segment("drive").select("webUrl").relative_url())
+            .collect();
+        let drives: Vec<Result<Drive>> = client.batch_get(&urls).await?;
+        let mut waiting = Vec::new();
+        for (index, drive) in pending.into_iter().zip(drives) {
+            let row = &mut rows[index];
+            row.attempts = attempt;
+            match drive {
+                Ok(drive) => {
+                    row.status = if attempt == 1 { "ready" } else { "provisioned" };
+                    row.web_url = drive.web_url.unwrap_or_default();
+                }
+                Err(e) if is_not_found(&e) => waiting.push(index),
+                Err(e) => {
+                    row.status = "failed";
+                    row.note = format!("{:#}", e);
+                }
+            }
+        }
+        pending = waiting;
+        if pending.is_empty() || attempt == attempts {
+            break;
+        }
+        info!(
+            "{} OneDrives are not ready after attempt {} of {}, requesting them again in {} seconds",
+            pending.len(),
+            attempt,
+            attempts,
+            interval.as_secs()
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.requested() => {
+                for &index in &pending {
+                    rows[index].note = "interrupted".to_string();
+                }
+                break;
+            }
+        }
+    }
+    for &index in &pending {
+        if rows[index].note.is_empty() {
+            rows[index].note = "still being created".to_string();
+        }
+    }
+    Ok(rows)
+}
+
+fn render_report(rows: &[ProvisionRow], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let count = |status: &str| rows.iter().filter(|row| row.status == status).count();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "users": rows,
+                "ready": count("ready"),
+                "provisioned": count("provisioned"),
+                "pending": count("pending"),
+                "failed": count("failed"),
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,status,attempts,webUrl,note")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv::field(&row.user_principal_name),
+                    row.status,
+                    row.attempts,
+                    csv::field(&row.web_url),
+                    csv::field(&row.note)
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<35} {:<12} {:>8} ONEDRIVE", "USER", "STATUS", "ATTEMPTS")?;
+            for row in rows {
+                let line = format!(
+                    "{:<35} {:<12} {:>8} {}",
+                    row.user_principal_name,
+                    row.status,
+                    row.attempts,
+                    if row.web_url.is_empty() { &row.note } else { &row.web_url }
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} users: {} ready, {} provisioned, {} pending, {} failed",
+                rows.len(),
+                count("ready"),
+                count("provisioned"),
+                count("pending"),
+                count("failed")
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting provision_onedrive");
+    debug!("Configuration: {:?}", config);
+
+    let upns = collect_upns(&config.upn, config.file.as_ref())?;
+    if upns.is_empty() {
+        return Err(anyhow::anyhow!("No users to provision"));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.Read.All", "Files.Read.All"])?;
+
+    info!("Provisioning the OneDrive of {} users", upns.len());
+    let mut shutdown = service::shutdown_signal();
+    let rows = provision(&client, &upns, config.attempts, Duration::from_secs(config.interval), &mut shutdown).await?;
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&rows, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if shutdown.is_requested() {
+        return Err(anyhow::anyhow!("Interrupted before every OneDrive was ready"));
+    }
+    let not_ready = rows
+        .iter()
+        .filter(|row| row.status == "pending" || row.status == "failed")
+        .count();
+    if not_ready > 0 {
+        warn!("Users whose OneDrive stays pending may not have a SharePoint license; run again later to check them");
+        return Err(anyhow::anyhow!("{} of {} OneDrives are not ready", not_ready, rows.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let upns = collect_upns(
+            &["Adele.Vance@contoso.com".to_string(), "lee.gu@contoso.com".to_string(), "adele.vance@contoso.com".to_string()],
+            None::<&str>,
+        )
+        .unwrap();
+        let mut rows = provision(&client, &upns, 3, Duration::from_secs(5), &mut ShutdownSignal::never())
+            .await
+            .unwrap();
+        assert!(rows.iter().all(|row| row.status == "ready"));
+        // Fixtures cannot answer 404, so the other statuses are added by hand.
+        rows.push(row("new.hire@contoso.com", "pending", 10, "still being created".to_string()));
+        rows.push(row("no.one@contoso.com", "failed", 0, "user not found".to_string()));
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&rows, output, &Summary::fixture()));
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::remediation::{self, PasswordPolicy};
+use graph_common::GraphClient;
+use log::{debug, info, warn, LevelFilter};
+use std::env;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(name = "reset_password")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Sets new passwords on users using the Microsoft API")]
+#[command(group(ArgGroup::new("target").required(true).args(["upn", "file"])))]
+struct AppConfig {
+    #[arg(short, long, help = "User Principal Name")]
+    upn: Option<String>,
+
+    #[arg(short, long, help = "CSV file with a upn column and an optional password column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(long, help = "Generate a random password for every user without one")]
+    random: bool,
+
+    #[arg(long, default_value_t = 16, requires = "random", help = "Length of generated passwords (8-256)")]
+    length: usize,
+
+    #[arg(long, requires = "random", help = "Leave uppercase letters out of generated passwords")]
+    no_uppercase: bool,
+
+    #[arg(long, requires = "random", help = "Leave lowercase letters out of generated passwords")]
+    no_lowercase: bool,
+
+    #[arg(long, requires = "random", help = "Leave digits out of generated passwords")]
+    no_digits: bool,
+
+    #[arg(long, requires = "random", help = "Leave symbols out of generated passwords")]
+    no_symbols: bool,
+
+    #[arg(long, help = "Require the user to change the password at the next sign-in")]
+    force_change_at_next_signin: bool,
+
+    #[arg(short, long, requires = "random", help = "Write generated passwords to this new file (owner-only permissions) instead of the terminal")]
+    output: Option<PathBuf>,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+impl AppConfig {
+    fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy {
+            length: self.length,
+            uppercase: !self.no_uppercase,
+            lowercase: !self.no_lowercase,
+            digits: !self.no_digits,
+            symbols: !self.no_symbols,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Target {
+    upn: String,
+    password: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn read_targets(source: &str) -> Result<Vec<Target>> {
+    let content = if source == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read users from stdin")?;
+        content
+    } else {
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))?
+    };
+
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    // Accept either a plain list of UPNs or a CSV with upn and optional password columns.
+    let (mut upn_column, mut password_column) = (0, None);
+    if let Some(header) = lines.peek() {
+        let fields: Vec<String> = header
+            .split(',')
+            .map(|field| field.trim().trim_matches('"').to_lowercase())
+            .collect();
+        if let Some(column) = fields
+            .iter()
+            .position(|field| field == "upn" || field == "userprincipalname")
+        {
+            upn_column = column;
+            password_column = fields.iter().position(|field| field == "password");
+            lines.next();
+        }
+    }
+
+    Ok(lines
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim().trim_matches('"')).collect();
+            let upn = fields.get(upn_column).filter(|upn| !upn.is_empty())?;
+            let password = password_column
+                .and_then(|column| fields.get(column))
+                .filter(|password| !password.is_empty());
+            Some(Target {
+                upn: upn.to_string(),
+                password: password.map(|password| password.to_string()),
+            })
+        })
+        .collect())
+}
+
+fn prompt_password(upn: &str) -> Result<String> {
+    let password = rpassword::prompt_password(format!("New password for {}: ", upn))
+        .context("Failed to read password")?;
+    let confirmation =
+        rpassword::prompt_password("Confirm password: ").context("Failed to read password")?;
+    if password != confirmation {
+        return Err(anyhow::anyhow!("Passwords do not match"));
+    }
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("Password cannot be empty"));
+    }
+    Ok(password)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+// Generated passwords are only ever shown here, once, and are never logged.
+fn deliver_generated(generated: &[(String, String)], output: Option<&Path>) -> Result<()> {
+    if generated.is_empty() {
+        return Ok(());
+    }
+    match output {
+        Some(path) => {
+            let mut file = create_private_file(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            writeln!(file, "upn,password")?;
+            for (upn, password) in generated {
+                writeln!(file, "{},{}", upn, password)?;
+            }
+            println!("{} generated passwords written to {}", generated.len(), path.display());
+        }
+        None => {
+            println!();
+            println!("Generated passwords (shown once, store them securely now):");
+            for (upn, password) in generated {
+                println!("{}  {}", upn, password);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting reset_password");
+    debug!(
+        "Targets: upn={:?} file={:?} random={} force_change={}",
+        config.upn, config.file, config.random, config.force_change_at_next_signin
+    );
+
+    let policy = config.password_policy();
+    if config.random {
+        // Fail on an invalid policy before any password is changed.
+        remediation::generate_password(&policy)?;
+    }
+    if let Some(output) = &config.output {
+        if output.exists() {
+            return Err(anyhow::anyhow!("{} already exists", output.display()));
+        }
+    }
+
+    let mut targets = match (&config.upn, &config.file) {
+        (Some(upn), _) => vec![Target {
+            upn: upn.clone(),
+            password: None,
+        }],
+        (None, Some(file)) => read_targets(file)?,
+        (None, None) => unreachable!("clap requires --upn or --file"),
+    };
+    if targets.is_empty() {
+        return Err(anyhow::anyhow!("No users found in the input"));
+    }
+    if targets.iter().any(|target| target.password.is_some()) {
+        warn!("The input file contains plaintext passwords; delete it once the run is complete");
+    }
+    if config.upn.is_some() && !config.random {
+        targets[0].password = Some(prompt_password(&targets[0].upn)?);
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["User.ReadWrite.All"])?;
+
+    let mut generated = Vec::new();
+    let mut failed = 0;
+    for target in &targets {
+        let (password, is_generated) = match &target.password {
+            Some(password) => (password.clone(), false),
+            None if config.random => (remediation::generate_password(&policy)?, true),
+            None => {
+                failed += 1;
+                println!("FAILED  {}: no password in the file and --random not given", target.upn);
+                continue;
+            }
+        };
+
+        match remediation::reset_password(&client, &target.upn, &password, config.force_change_at_next_signin).await {
+            Ok(_) => {
+                println!("OK      {}", target.upn);
+                if is_generated {
+                    generated.push((target.upn.clone(), password));
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", target.upn, e);
+            }
+        }
+    }
+
+    deliver_generated(&generated, config.output.as_deref())?;
+
+    if targets.len() > 1 {
+        println!();
+        println!(
+            "Processed {} users: {} succeeded, {} failed",
+            targets.len(),
+            targets.len() - failed,
+            failed
+        );
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, targets.len()));
+    }
+    Ok(())
+}
@@ -1,8 +1,12 @@
 use anyhow::{Context, Result};
-use clap::{Arg, Command};
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgAction, ArgGroup, Command};
 use dotenv::dotenv;
 use env_logger::Builder;
-use log::{debug, info, error, LevelFilter};
+use graph_common::cli::read_upns;
+use graph_common::remediation::AUTHENTICATION_METHOD_TYPES;
+use graph_common::{actionlog, encode, permissions, service};
+use log::{debug, info, error, warn, LevelFilter};
 use reqwest::Client;
 use serde_json::Value;
 use std::env;
@@ -11,6 +15,26 @@ use std::path::PathBuf;
 
 mod auth;
 
+// Every method type except the password, which cannot be removed.
+fn removable_method_types() -> impl Iterator<Item = &'static (&'static str, &'static str, &'static str)> {
+    AUTHENTICATION_METHOD_TYPES
+        .iter()
+        .filter(|(name, _, _)| *name != "password")
+}
+
+fn method_endpoint(method_type: &str) -> Option<&'static str> {
+    removable_method_types()
+        .find(|(_, odata_type, _)| *odata_type == method_type)
+        .map(|(_, _, endpoint)| *endpoint)
+}
+
+fn selected_method_types(methods: &[String]) -> Vec<&'static str> {
+    removable_method_types()
+        .filter(|(name, _, _)| methods.iter().any(|method| method == "all" || method == name))
+        .map(|(_, odata_type, _)| *odata_type)
+        .collect()
+}
+
 fn setup_logger() {
     let mut builder = Builder::from_default_env();
     builder.filter_level(if cfg!(debug_assertions) {
@@ -47,13 +71,148 @@ async fn get_access_token_from_env() -> Result<String> {
     auth::get_access_token(&tenant_id, &client_id, &client_secret).await
 }
 
+struct Options {
+    method_types: Vec<&'static str>,
+    list: bool,
+    dry_run: bool,
+    tap: Option<TapOptions>,
+}
+
+impl Options {
+    // Listing and dry runs only read methods; deleting them or issuing a pass needs write access.
+    fn required_permissions(&self) -> &'static [&'static str] {
+        if self.list || self.dry_run {
+            &["UserAuthenticationMethod.Read.All"]
+        } else {
+            &["UserAuthenticationMethod.ReadWrite.All"]
+        }
+    }
+}
+
+struct TapOptions {
+    lifetime_minutes: u32,
+    one_time: bool,
+}
+
+fn parse_lifetime(value: &str) -> Result<u32, String> {
+    let value = value.trim();
+    let (number, multiplier) = if let Some(hours) = value.strip_suffix('h') {
+        (hours, 60)
+    } else if let Some(days) = value.strip_suffix('d') {
+        (days, 1440)
+    } else {
+        (value.strip_suffix('m').unwrap_or(value), 1)
+    };
+    let minutes = number
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid lifetime '{}'. Use e.g. 60m, 8h, or 1d", value))?
+        * multiplier;
+    // Graph accepts 10 minutes to 30 days; the tenant policy may narrow this further.
+    if !(10..=43200).contains(&minutes) {
+        return Err("Lifetime must be between 10m and 30d".to_string());
+    }
+    Ok(minutes)
+}
+
+struct AuthenticationMethod {
+    method_type: String,
+    id: String,
+    display_name: Option<String>,
+    added: Option<String>,
+    phone_hint: Option<String>,
+}
+
+fn method_name(method_type: &str) -> &str {
+    AUTHENTICATION_METHOD_TYPES
+        .iter()
+        .find(|(_, odata_type, _)| *odata_type == method_type)
+        .map(|(name, _, _)| *name)
+        .unwrap_or_else(|| {
+            method_type
+                .trim_start_matches("#microsoft.graph.")
+                .trim_end_matches("AuthenticationMethod")
+        })
+}
+
+fn phone_hint(method: &Value) -> Option<String> {
+    let phone_number = method["phoneNumber"].as_str()?;
+    let digits: Vec<char> = phone_number.chars().filter(char::is_ascii_digit).collect();
+    let suffix: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+    Some(match method["phoneType"].as_str() {
+        Some(phone_type) => format!("ending {} ({})", suffix, phone_type),
+        None => format!("ending {}", suffix),
+    })
+}
+
+fn display_name(method: &Value) -> Option<String> {
+    let names: Vec<&str> = ["displayName", "deviceTag", "model", "emailAddress"]
+        .iter()
+        .filter_map(|field| method[field].as_str())
+        .filter(|value| !value.is_empty())
+        .collect();
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+fn describe_method(method: &AuthenticationMethod) -> String {
+    let mut details = Vec::new();
+    if let Some(phone_hint) = &method.phone_hint {
+        details.push(format!("phone {}", phone_hint));
+    }
+    if let Some(display_name) = &method.display_name {
+        details.push(display_name.clone());
+    }
+    if let Some(added) = &method.added {
+        details.push(format!("added {}", added));
+    }
+    details.join(", ")
+}
+
+fn print_methods_table(upn: &str, methods: &[AuthenticationMethod]) {
+    let rows: Vec<[String; 4]> = methods
+        .iter()
+        .map(|method| {
+            [
+                method_name(&method.method_type).to_string(),
+                method.display_name.clone().unwrap_or_default(),
+                method.added.clone().unwrap_or_default(),
+                method.phone_hint.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let headers = ["TYPE", "DISPLAY NAME", "ADDED", "PHONE"];
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .chain(std::iter::once(headers[column].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    println!("{} ({} methods)", upn, methods.len());
+    let print_row = |cells: [&str; 4]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("  {}", line.join("  ").trim_end());
+    };
+    print_row(headers);
+    for row in &rows {
+        print_row([&row[0], &row[1], &row[2], &row[3]]);
+    }
+    println!();
+}
+
 async fn get_authentication_methods(
     access_token: &str,
     user_id: &str,
-) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+) -> Result<Vec<AuthenticationMethod>, Box<dyn Error>> {
     let url = format!(
         "https://graph.microsoft.com/v1.0/users/{}/authentication/methods",
-        user_id
+        encode::path_segment(user_id)
     );
     info!("Retrieving authentication methods for user: {}", user_id);
 
@@ -63,15 +222,21 @@ async fn get_authentication_methods(
     if response.status().is_success() {
         debug!("Received successful response for authentication methods.");
         let methods: Value = response.json().await?;
-        let method_ids: Vec<(String, String)> = methods["value"]
+        let method_ids: Vec<AuthenticationMethod> = methods["value"]
             .as_array()
-            .unwrap()
+            .context("Response has no value array")?
             .iter()
             .filter_map(|method| {
                 let method_type = method["@odata.type"].as_str()?;
                 let method_id = method["id"].as_str()?;
                 debug!("Found method type: {}, method ID: {}", method_type, method_id);
-                Some((method_type.to_string(), method_id.to_string()))
+                Some(AuthenticationMethod {
+                    method_type: method_type.to_string(),
+                    id: method_id.to_string(),
+                    display_name: display_name(method),
+                    added: method["createdDateTime"].as_str().map(str::to_string),
+                    phone_hint: phone_hint(method),
+                })
             })
             .collect();
         Ok(method_ids)
@@ -96,12 +261,14 @@ async fn delete_authentication_method(
     method_type: &str,
     method_id: &str,
 ) -> Result<(), Box<dyn Error>> {
-    let url = match method_type {
-        "#microsoft.graph.softwareOathAuthenticationMethod" => format!(
-            "https://graph.microsoft.com/v1.0/users/{}/authentication/softwareOathMethods/{}",
-            user_id, method_id
+    let url = match method_endpoint(method_type) {
+        Some(endpoint) => format!(
+            "https://graph.microsoft.com/v1.0/users/{}/authentication/{}/{}",
+            encode::path_segment(user_id),
+            endpoint,
+            encode::path_segment(method_id)
         ),
-        _ => {
+        None => {
             debug!("Ignoring unsupported method type: {}", method_type);
             return Ok(()); // Ignore other method types
         }
@@ -114,6 +281,7 @@ async fn delete_authentication_method(
 
     let client = Client::new();
     let response = client.delete(&url).bearer_auth(access_token).send().await?;
+    actionlog::record("DELETE", &url, None, &response)?;
 
     if response.status().is_success() {
         info!(
@@ -136,13 +304,108 @@ async fn delete_authentication_method(
     }
 }
 
-async fn require_mfa_reregistration(access_token: &str, upn: &str) -> Result<(), Box<dyn Error>> {
+async fn issue_temporary_access_pass(
+    access_token: &str,
+    user_id: &str,
+    tap: &TapOptions,
+) -> Result<String, Box<dyn Error>> {
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/authentication/temporaryAccessPassMethods",
+        encode::path_segment(user_id)
+    );
+    info!("Issuing Temporary Access Pass for user {}", user_id);
+
+    let body = serde_json::json!({
+        "lifetimeInMinutes": tap.lifetime_minutes,
+        "isUsableOnce": tap.one_time,
+    });
+    let client = Client::new();
+    let response = client
+        .post(&url)
+        .bearer_auth(access_token)
+        .json(&body)
+        .send()
+        .await?;
+    actionlog::record("POST", &url, Some(&body), &response)?;
+
+    if response.status().is_success() {
+        let method: Value = response.json().await?;
+        let pass = method["temporaryAccessPass"]
+            .as_str()
+            .ok_or("Temporary Access Pass missing from response")?;
+        info!("Temporary Access Pass issued for user {}", user_id);
+        Ok(pass.to_string())
+    } else {
+        let status = response.status();
+        let text = response.text().await?;
+        error!(
+            "Failed to issue Temporary Access Pass for user {}: {} - {}",
+            user_id, status, text
+        );
+        Err(format!(
+            "Failed to issue Temporary Access Pass for user {}: {} - {}",
+            user_id, status, text
+        )
+        .into())
+    }
+}
+
+async fn require_mfa_reregistration(
+    access_token: &str,
+    upn: &str,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    let method_types = &options.method_types;
+    if options.list {
+        let methods = get_authentication_methods(access_token, upn).await?;
+        print_methods_table(upn, &methods);
+        return Ok(());
+    }
+
     info!("Requiring MFA re-registration for user {}", upn);
-    let method_ids = get_authentication_methods(access_token, upn).await?;
-    for (method_type, method_id) in method_ids {
-        if method_type == "#microsoft.graph.softwareOathAuthenticationMethod" {
-            delete_authentication_method(access_token, upn, &method_type, &method_id).await?;
+    let methods: Vec<AuthenticationMethod> = get_authentication_methods(access_token, upn)
+        .await?
+        .into_iter()
+        .filter(|method| method_types.contains(&method.method_type.as_str()))
+        .collect();
+
+    if options.dry_run {
+        if methods.is_empty() {
+            println!("{}: no matching methods", upn);
         }
+        for method in &methods {
+            println!(
+                "{}: would delete {} {} ({})",
+                upn,
+                method_name(&method.method_type),
+                method.id,
+                describe_method(method)
+            );
+        }
+        if let Some(tap) = &options.tap {
+            println!(
+                "{}: would issue a Temporary Access Pass valid for {} minutes{}",
+                upn,
+                tap.lifetime_minutes,
+                if tap.one_time { " (one-time use)" } else { "" }
+            );
+        }
+        return Ok(());
+    }
+
+    for method in methods {
+        delete_authentication_method(access_token, upn, &method.method_type, &method.id).await?;
+    }
+
+    if let Some(tap) = &options.tap {
+        let pass = issue_temporary_access_pass(access_token, upn, tap).await?;
+        println!(
+            "{}: Temporary Access Pass {} (valid for {} minutes{})",
+            upn,
+            pass,
+            tap.lifetime_minutes,
+            if tap.one_time { ", one-time use" } else { "" }
+        );
     }
     info!(
         "MFA re-registration required successfully for user {}.",
@@ -151,6 +414,54 @@ async fn require_mfa_reregistration(access_token: &str, upn: &str) -> Result<(),
     Ok(())
 }
 
+async fn process_bulk(access_token: &str, upns: &[String], options: &Options) -> Result<()> {
+    // The current user is always finished so no user is left with only some methods removed.
+    let shutdown = service::shutdown_signal();
+    let mut processed = 0;
+    let mut failed = 0;
+    for upn in upns {
+        if shutdown.is_requested() {
+            break;
+        }
+        processed += 1;
+        match require_mfa_reregistration(access_token, upn, options).await {
+            Ok(_) if options.dry_run || options.list => {}
+            Ok(_) => println!("OK      {}", upn),
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {}", upn, e);
+            }
+        }
+    }
+
+    if processed < upns.len() {
+        warn!("Interrupted after processing {} of {} users", processed, upns.len());
+        for upn in &upns[processed..] {
+            println!("SKIPPED {}", upn);
+        }
+    }
+
+    println!();
+    println!(
+        "Processed {} of {} users: {} succeeded, {} failed, {} skipped",
+        processed,
+        upns.len(),
+        processed - failed,
+        failed,
+        upns.len() - processed
+    );
+    if processed < upns.len() {
+        return Err(anyhow::anyhow!(
+            "Interrupted before {} users were processed",
+            upns.len() - processed
+        ));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, upns.len()));
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
@@ -165,22 +476,104 @@ async fn main() -> Result<()> {
                 .short('u')
                 .long("upn")
                 .value_name("UPN")
-                .help("User Principal Name")
-                .required(true),
+                .help("User Principal Name"),
+        )
+        .arg(
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .value_name("FILE")
+                .help("File of UPNs, one per line or a CSV with a upn column (- for stdin)"),
+        )
+        .arg(
+            Arg::new("list")
+                .short('l')
+                .long("list")
+                .help("List the user's registered authentication methods without changing anything")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["methods", "dry-run", "issue-tap"]),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("List the methods that would be deleted without deleting them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("issue-tap")
+                .long("issue-tap")
+                .help("Issue a Temporary Access Pass after removing methods and print it once")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lifetime")
+                .long("lifetime")
+                .value_name("DURATION")
+                .help("Temporary Access Pass lifetime, e.g. 60m, 8h, or 1d")
+                .value_parser(parse_lifetime)
+                .default_value("60m")
+                .requires("issue-tap"),
+        )
+        .arg(
+            Arg::new("one-time")
+                .long("one-time")
+                .help("Make the Temporary Access Pass usable only once")
+                .action(ArgAction::SetTrue)
+                .requires("issue-tap"),
+        )
+        .group(ArgGroup::new("target").args(["upn", "file"]).required(true))
+        .arg(
+            Arg::new("methods")
+                .short('m')
+                .long("methods")
+                .value_name("METHODS")
+                .help("Comma separated authentication method types to remove")
+                .value_parser(PossibleValuesParser::new(
+                    removable_method_types().map(|(name, _, _)| *name).chain(["all"]),
+                ))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .default_value("softwareoath"),
         )
         .get_matches();
 
-    let upn = matches.get_one::<String>("upn").expect("UPN is required");
-
-    info!("Starting MFA re-registration process for user: {}", upn);
+    let methods: Vec<String> = matches
+        .get_many::<String>("methods")
+        .expect("Methods has a default")
+        .cloned()
+        .collect();
+    let method_types = selected_method_types(&methods);
+    debug!("Removing method types: {:?}", method_types);
+    let options = Options {
+        method_types,
+        list: matches.get_flag("list"),
+        dry_run: matches.get_flag("dry-run"),
+        tap: matches.get_flag("issue-tap").then(|| TapOptions {
+            lifetime_minutes: *matches.get_one::<u32>("lifetime").expect("Lifetime has a default"),
+            one_time: matches.get_flag("one-time"),
+        }),
+    };
 
-    match get_access_token_from_env().await {
-        Ok(access_token) => match require_mfa_reregistration(&access_token, upn).await {
-            Ok(_) => info!("Operation completed successfully."),
-            Err(e) => error!("Error during MFA re-registration: {}", e),
-        },
-        Err(e) => error!("Failed to obtain access token: {}", e),
+    if let Some(file) = matches.get_one::<String>("file") {
+        let upns = read_upns(file)?;
+        info!("Starting MFA re-registration process for {} users", upns.len());
+        let access_token = get_access_token_from_env()
+            .await
+            .context("Failed to obtain access token")?;
+        permissions::check(&access_token, options.required_permissions())?;
+        return process_bulk(&access_token, &upns, &options).await;
     }
 
+    let upn = matches.get_one::<String>("upn").expect("UPN or file is required");
+    info!("Starting MFA re-registration process for user: {}", upn);
+
+    let access_token = get_access_token_from_env()
+        .await
+        .context("Failed to obtain access token")?;
+    permissions::check(&access_token, options.required_permissions())?;
+    require_mfa_reregistration(&access_token, upn, &options)
+        .await
+        .map_err(|e| anyhow::anyhow!("Error during MFA re-registration: {}", e))?;
+    info!("Operation completed successfully.");
     Ok(())
 }
@@ -2,9 +2,18 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use dotenv::dotenv;
 use env_logger::Builder;
-use log::{debug, info, LevelFilter};
+use futures::stream::{self, StreamExt};
+use graph_common::cli;
+use graph_common::approval::{self, Plan, PlanAction};
+use graph_common::{actionlog, encode, permissions, remediation, service, GraphClient};
+use log::{debug, info, warn, LevelFilter};
 use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashSet;
 use std::env;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
 mod auth;
 use auth::get_access_token;
@@ -15,25 +24,60 @@ use auth::get_access_token;
 #[command(version = "1.0")]
 #[command(about = "Revokes sign-in sessions using the Microsoft API")]
 struct AppConfig {
-    #[arg(short, long, help = "User Principal Name")]
-    upn: String,
+    #[arg(short, long, value_delimiter = ',', conflicts_with = "apply", help = "User Principal Name (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, conflicts_with = "apply", help = "File of UPNs, one per line or a CSV with a upn column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(long, requires = "key", conflicts_with = "apply", help = "Write a plan for the users to this file for a second person to approve, instead of changing anything")]
+    plan: Option<PathBuf>,
+
+    #[arg(long, requires = "plan", help = "Secret key file the plan author signs the plan with")]
+    key: Option<PathBuf>,
+
+    #[arg(long, help = "Apply a plan file that a second person has approved with mstools approve")]
+    apply: Option<PathBuf>,
+
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+    concurrency: u16,
+
+    #[arg(long, conflicts_with = "apply", help = "Also disable the account (accountEnabled=false) before revoking sessions")]
+    disable_account: bool,
+
+    #[arg(short, long, help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct UserDetails {
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    department: Option<String>,
+    #[serde(rename = "jobTitle")]
+    job_title: Option<String>,
 }
 
 async fn revoke_sign_in_sessions(access_token: &str, upn: &str) -> Result<()> {
     let url = format!(
         "https://graph.microsoft.com/v1.0/users/{}/revokeSignInSessions",
-        upn
+        encode::path_segment(upn)
     );
     debug!("Revoking sign-in sessions at URL: {}", url);
 
+    let body = serde_json::json!({});
     let client = Client::new();
     let response = client
         .post(&url)
         .bearer_auth(access_token)
-        .json(&serde_json::json!({}))
+        .json(&body)
         .send()
         .await
         .context("Failed to send request")?;
+    actionlog::record("POST", &url, Some(&body), &response)?;
 
     let status = response.status();
     let response_text = response
@@ -57,9 +101,77 @@ async fn revoke_sign_in_sessions(access_token: &str, upn: &str) -> Result<()> {
     }
 }
 
-fn setup_logger() {
+// Goes through the shared remediation so the previous state is recorded for `mstools undo`.
+async fn disable_account(access_token: &str, upn: &str) -> Result<()> {
+    debug!("Disabling account for user {}", upn);
+    let client = GraphClient::new(access_token.to_string());
+    remediation::set_account_enabled(&client, upn, false).await?;
+    info!("Account disabled successfully for user {}.", upn);
+    Ok(())
+}
+
+// The account is disabled first so the user cannot sign in again between the two calls.
+async fn contain_user(access_token: &str, upn: &str, disable: bool) -> Result<()> {
+    if disable {
+        disable_account(access_token, upn).await?;
+    }
+    revoke_sign_in_sessions(access_token, upn).await
+}
+
+// UPNs piped on stdin are used when neither --upn nor --file is given.
+fn requested_upns(config: &AppConfig) -> Result<Vec<String>> {
+    let file = match &config.file {
+        Some(file) => Some(file.as_str()),
+        None if config.upn.is_empty() && !std::io::stdin().is_terminal() => Some("-"),
+        None => None,
+    };
+    let upns = cli::collect_upns(&config.upn, file)?;
+    if upns.is_empty() {
+        return Err(anyhow::anyhow!("No UPNs given. Use --upn, --file, or pipe UPNs on stdin"));
+    }
+    Ok(upns)
+}
+
+const OPERATION: &str = "revokeSignInSessions";
+
+fn write_plan(config: &AppConfig, path: &Path, key: &Path) -> Result<()> {
+    let upns = requested_upns(config)?;
+    let actions = upns
+        .iter()
+        .map(|upn| PlanAction {
+            operation: OPERATION.to_string(),
+            target: upn.clone(),
+            details: json!({"disableAccount": config.disable_account}),
+        })
+        .collect();
+    let description = if config.disable_account {
+        "Disable the accounts and revoke all sign-in sessions"
+    } else {
+        "Revoke all sign-in sessions"
+    };
+    let mut plan = Plan::new("revoke_sessions", description, actions);
+    plan.write(path, key)?;
+    println!("Plan for {} users written to {}", upns.len(), path.display());
+    println!("Have a second person approve it with `mstools approve {} --key <their key>`, then apply it with --apply.", path.display());
+    Ok(())
+}
+
+fn planned_users(plan: &Plan) -> Result<Vec<(String, bool)>> {
+    plan.actions
+        .iter()
+        .map(|action| {
+            if action.operation != OPERATION {
+                return Err(anyhow::anyhow!("Unsupported plan operation {}", action.operation));
+            }
+            let disable = action.details["disableAccount"].as_bool().unwrap_or(false);
+            Ok((action.target.clone(), disable))
+        })
+        .collect()
+}
+
+fn setup_logger(verbose: bool) {
     let mut builder = Builder::from_default_env();
-    builder.filter_level(if cfg!(debug_assertions) {
+    builder.filter_level(if verbose {
         LevelFilter::Debug
     } else {
         LevelFilter::Info
@@ -67,15 +179,97 @@ fn setup_logger() {
     builder.init();
 }
 
+// Prefer a .env file next to the executable so the tool works from any directory.
+fn load_env() {
+    let env_path = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(".env")))
+        .unwrap_or_else(|| PathBuf::from(".env"));
+    match dotenv::from_path(&env_path) {
+        Ok(_) => debug!(".env file loaded from {:?}", env_path),
+        Err(_) => {
+            debug!("No .env file next to the executable, falling back to the current directory");
+            dotenv().ok();
+        }
+    }
+}
+
+async fn lookup_users(client: &GraphClient, upns: &[String], concurrency: usize) -> Vec<(String, Result<UserDetails>)> {
+    stream::iter(upns)
+        .map(|upn| async move {
+            let details = client
+                .user(upn)
+                .resource()
+                .select("displayName,department,jobTitle")
+                .get_json::<UserDetails>()
+                .await;
+            (upn.clone(), details)
+        })
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+fn confirm(action: &str, users: &[(String, Result<UserDetails>)]) -> Result<bool> {
+    println!("About to {} for {} users:", action, users.len());
+    for (upn, details) in users {
+        match details {
+            Ok(details) => println!(
+                "  {}  {} | {} | {}",
+                upn,
+                details.display_name.as_deref().unwrap_or("-"),
+                details.department.as_deref().unwrap_or("-"),
+                details.job_title.as_deref().unwrap_or("-")
+            ),
+            Err(e) => println!("  {}  (lookup failed: {:#})", upn, e),
+        }
+    }
+    cli::confirm()
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    dotenv().ok();
     let config = AppConfig::parse();
-    setup_logger();
+    setup_logger(config.verbose);
+    load_env();
 
     info!("Starting RevokeSessionService");
     debug!("Configuration: {:?}", config);
 
+    if let (Some(path), Some(key)) = (&config.plan, &config.key) {
+        return write_plan(&config, path, key);
+    }
+    // Each user is paired with whether their account is disabled first.
+    let (users, action) = match &config.apply {
+        Some(plan_path) => {
+            let plan = approval::load_approved_plan(plan_path, "revoke_sessions")?;
+            let users = planned_users(&plan)?;
+            if users.is_empty() {
+                return Err(anyhow::anyhow!("Plan {} has no users", plan_path.display()));
+            }
+            info!("Applying plan {} for {} users", plan_path.display(), users.len());
+            (users, format!("{} (plan by {})", plan.description.to_lowercase(), plan.created_by))
+        }
+        None => {
+            let users = requested_upns(&config)?
+                .into_iter()
+                .map(|upn| (upn, config.disable_account))
+                .collect();
+            let action = if config.disable_account {
+                "disable the account and revoke all sign-in sessions"
+            } else {
+                "revoke all sign-in sessions"
+            };
+            (users, action.to_string())
+        }
+    };
+    let upns: Vec<String> = users.iter().map(|(upn, _)| upn.clone()).collect();
+    // The prompt reads its answer from stdin, which is unavailable once UPNs are piped in.
+    if !config.yes && !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Confirmation requires an interactive terminal. Pass --yes to run without it"
+        ));
+    }
     let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
     let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
     let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
@@ -85,8 +279,74 @@ async fn main() -> Result<()> {
         .context("Failed to obtain access token")?;
 
     debug!("Access token obtained successfully");
+    permissions::check(&access_token, &["User.ReadWrite.All"])?;
+
+    if !config.yes {
+        let client = GraphClient::new(access_token.clone());
+        let details = lookup_users(&client, &upns, config.concurrency as usize).await;
+        if !confirm(&action, &details)? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    info!("Starting to {} for {} users", action, upns.len());
+
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let shutdown = service::shutdown_signal();
+    let mut succeeded = 0;
+    let mut failed = 0;
+    let mut results = stream::iter(&users)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|(upn, disable)| {
+            let access_token = &access_token;
+            async move {
+                let result = contain_user(access_token, upn, *disable).await;
+                (upn, result)
+            }
+        })
+        .buffer_unordered(config.concurrency as usize);
+    let mut finished = HashSet::new();
+    while let Some((upn, result)) = results.next().await {
+        finished.insert(upn);
+        match result {
+            Ok(_) => {
+                succeeded += 1;
+                println!("OK      {}", upn);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", upn, e);
+            }
+        }
+    }
 
-    revoke_sign_in_sessions(&access_token, &config.upn).await?;
+    let processed = succeeded + failed;
+    if processed < upns.len() {
+        warn!("Interrupted after processing {} of {} users", processed, upns.len());
+        for upn in upns.iter().filter(|upn| !finished.contains(upn)) {
+            println!("SKIPPED {}", upn);
+        }
+    }
+
+    println!();
+    println!(
+        "Processed {} of {} users: {} succeeded, {} failed, {} skipped",
+        processed,
+        upns.len(),
+        succeeded,
+        failed,
+        upns.len() - processed
+    );
+    if processed < upns.len() {
+        return Err(anyhow::anyhow!(
+            "Interrupted before {} users were processed",
+            upns.len() - processed
+        ));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, upns.len()));
+    }
 
     info!("Operation completed successfully.");
     Ok(())
@@ -0,0 +1,527 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::confirm;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, remediation, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+
+#[derive(Parser, Debug)]
+#[command(name = "risky_users")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists Identity Protection risky users and risk detections, and dismisses or confirms the risk of selected users, using the Microsoft API")]
+#[command(group(ArgGroup::new("action").multiple(true).args(["dismiss", "confirm_compromised"])))]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value = "low", value_parser = ["low", "medium", "high"], help = "List only users or detections at this risk level or above")]
+    min_risk_level: String,
+
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(1..), help = "List only risk updated or detected in this many days")]
+    days: i64,
+
+    #[arg(long, help = "List risk detections instead of risky users")]
+    detections: bool,
+
+    #[arg(long, value_name = "UPN", help = "Dismiss the risk of this user (can be repeated)")]
+    dismiss: Vec<String>,
+
+    #[arg(long, value_name = "UPN", help = "Confirm this user as compromised (can be repeated)")]
+    confirm_compromised: Vec<String>,
+
+    #[arg(long, requires = "action", help = "List the changes that would be made without making them")]
+    dry_run: bool,
+
+    #[arg(short, long, requires = "action", help = "Skip the confirmation prompt (for automation)")]
+    yes: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct RiskyUser {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "userDisplayName")]
+    user_display_name: Option<String>,
+    #[serde(rename = "riskLevel")]
+    risk_level: String,
+    #[serde(rename = "riskState")]
+    risk_state: String,
+    #[serde(rename = "riskDetail")]
+    risk_detail: Option<String>,
+    #[serde(rename = "riskLastUpdatedDateTime")]
+    risk_last_updated: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RiskDetection {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    #[serde(rename = "riskEventType")]
+    risk_event_type: String,
+    #[serde(rename = "riskLevel")]
+    risk_level: String,
+    #[serde(rename = "riskState")]
+    risk_state: String,
+    #[serde(rename = "detectedDateTime")]
+    detected: DateTime<Utc>,
+    #[serde(rename = "ipAddress")]
+    ip_address: Option<String>,
+    location: Option<Location>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Location {
+    city: Option<String>,
+    #[serde(rename = "countryOrRegion")]
+    country_or_region: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Detection {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "riskEventType")]
+    risk_event_type: String,
+    #[serde(rename = "riskLevel")]
+    risk_level: String,
+    #[serde(rename = "riskState")]
+    risk_state: String,
+    #[serde(rename = "detectedDateTime")]
+    detected: String,
+    #[serde(rename = "ipAddress")]
+    ip_address: String,
+    location: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Dismiss,
+    ConfirmCompromised,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+// Tenants without Entra ID P2 see the level of most risk as "hidden", which is listed with low
+// so that it is not dropped from the default report.
+fn risk_rank(level: &str) -> u8 {
+    match level {
+        "high" => 3,
+        "medium" => 2,
+        "low" | "hidden" => 1,
+        _ => 0,
+    }
+}
+
+// Every risky user, including ones whose risk was already remediated or dismissed, so that
+// actions can find them. The report keeps only users that are still at risk.
+async fn list_risky_users(client: &GraphClient) -> Result<Vec<RiskyUser>> {
+    let mut users: Vec<RiskyUser> = client
+        .path(&["identityProtection", "riskyUsers"])
+        .get_all()
+        .await
+        .context("Failed to fetch the risky users")?;
+    users.sort_by(|a, b| {
+        risk_rank(&b.risk_level)
+            .cmp(&risk_rank(&a.risk_level))
+            .then_with(|| a.user_principal_name.to_lowercase().cmp(&b.user_principal_name.to_lowercase()))
+    });
+    Ok(users)
+}
+
+fn at_risk<'a>(users: &'a [RiskyUser], min_risk_level: &str, since: DateTime<Utc>) -> Vec<&'a RiskyUser> {
+    users
+        .iter()
+        .filter(|user| user.risk_state == "atRisk" || user.risk_state == "confirmedCompromised")
+        .filter(|user| risk_rank(&user.risk_level) >= risk_rank(min_risk_level))
+        .filter(|user| user.risk_last_updated.is_some_and(|updated| updated >= since))
+        .collect()
+}
+
+async fn list_detections(client: &GraphClient, min_risk_level: &str, since: DateTime<Utc>) -> Result<Vec<Detection>> {
+    let mut detections: Vec<RiskDetection> = client
+        .path(&["identityProtection", "riskDetections"])
+        .filter(&format!("detectedDateTime ge {}", since.to_rfc3339_opts(SecondsFormat::Secs, true)))
+        .get_all()
+        .await
+        .context("Failed to fetch the risk detections")?;
+    detections.retain(|detection| risk_rank(&detection.risk_level) >= risk_rank(min_risk_level));
+    detections.sort_by_key(|detection| std::cmp::Reverse(detection.detected));
+    Ok(detections
+        .into_iter()
+        .map(|detection| Detection {
+            user_principal_name: detection.user_principal_name.unwrap_or_default(),
+            risk_event_type: detection.risk_event_type,
+            risk_level: detection.risk_level,
+            risk_state: detection.risk_state,
+            detected: detection.detected.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ip_address: detection.ip_address.unwrap_or_default(),
+            location: detection
+                .location
+                .map(|location| {
+                    [location.city, location.country_or_region]
+                        .into_iter()
+                        .flatten()
+                        .filter(|part| !part.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+fn render_users(users: &[&RiskyUser], output: &str, min_risk_level: &str, days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "riskyUsers": users,
+                "minRiskLevel": min_risk_level,
+                "days": days,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "userPrincipalName,displayName,riskLevel,riskState,riskDetail,riskLastUpdated")?;
+            for user in users {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    csv::field(&user.user_principal_name),
+                    csv::field(user.user_display_name.as_deref().unwrap_or_default()),
+                    user.risk_level,
+                    user.risk_state,
+                    user.risk_detail.as_deref().unwrap_or_default(),
+                    user.risk_last_updated
+                        .map(|updated| updated.to_rfc3339_opts(SecondsFormat::Secs, true))
+                        .unwrap_or_default()
+                )?;
+            }
+        }
+        _ => {
+            writeln!(out, "{:<35} {:<7} {:<21} {:<11} DETAIL", "USER", "LEVEL", "STATE", "UPDATED")?;
+            for user in users {
+                let line = format!(
+                    "{:<35} {:<7} {:<21} {:<11} {}",
+                    user.user_principal_name,
+                    user.risk_level,
+                    user.risk_state,
+                    user.risk_last_updated
+                        .map(|updated| updated.format("%Y-%m-%d").to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    user.risk_detail.as_deref().filter(|detail| *detail != "none").unwrap_or_default()
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            let level = |level: &str| users.iter().filter(|user| user.risk_level == level).count();
+            writeln!(
+                out,
+                "{} users at {} risk or above updated in the last {} days: {} high, {} medium, {} low; {} confirmed compromised",
+                users.len(),
+                min_risk_level,
+                days,
+                level("high"),
+                level("medium"),
+                level("low"),
+                users.iter().filter(|user| user.risk_state == "confirmedCompromised").count()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn render_detections(detections: &[Detection], output: &str, min_risk_level: &str, days: i64, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "riskDetections": detections,
+                "minRiskLevel": min_risk_level,
+                "days": days,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(out, "detectedDateTime,userPrincipalName,riskEventType,riskLevel,riskState,ipAddress,location")?;
+            for detection in detections {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    detection.detected,
+                    csv::field(&detection.user_principal_name),
+                    detection.risk_event_type,
+                    detection.risk_level,
+                    detection.risk_state,
+                    detection.ip_address,
+                    csv::field(&detection.location)
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<20} {:<35} {:<30} {:<7} {:<21} {:<15} LOCATION",
+                "DETECTED", "USER", "TYPE", "LEVEL", "STATE", "IP ADDRESS"
+            )?;
+            for detection in detections {
+                let line = format!(
+                    "{:<20} {:<35} {:<30} {:<7} {:<21} {:<15} {}",
+                    detection.detected,
+                    detection.user_principal_name,
+                    detection.risk_event_type,
+                    detection.risk_level,
+                    detection.risk_state,
+                    if detection.ip_address.is_empty() { "-" } else { &detection.ip_address },
+                    detection.location
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            let mut users: Vec<&str> = detections.iter().map(|detection| detection.user_principal_name.as_str()).collect();
+            users.sort_unstable();
+            users.dedup();
+            writeln!(
+                out,
+                "{} detections at {} risk or above in the last {} days, for {} users",
+                detections.len(),
+                min_risk_level,
+                days,
+                users.len()
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+// Every selected user must be a known risky user, so a typo fails before any change is made.
+fn resolve_targets<'a>(users: &'a [RiskyUser], config: &AppConfig) -> Result<Vec<(Action, &'a RiskyUser)>> {
+    let by_upn: HashMap<String, &RiskyUser> = users
+        .iter()
+        .map(|user| (user.user_principal_name.to_lowercase(), user))
+        .collect();
+    let mut targets: Vec<(Action, &RiskyUser)> = Vec::new();
+    let selected = config
+        .dismiss
+        .iter()
+        .map(|upn| (Action::Dismiss, upn))
+        .chain(config.confirm_compromised.iter().map(|upn| (Action::ConfirmCompromised, upn)));
+    for (action, upn) in selected {
+        let user = by_upn
+            .get(&upn.to_lowercase())
+            .with_context(|| format!("{} is not a risky user", upn))?;
+        if let Some((other, _)) = targets.iter().find(|(_, target)| target.id == user.id) {
+            if *other != action {
+                return Err(anyhow::anyhow!(
+                    "{} cannot be both dismissed and confirmed as compromised",
+                    user.user_principal_name
+                ));
+            }
+            continue;
+        }
+        targets.push((action, user));
+    }
+    Ok(targets)
+}
+
+fn label(action: Action) -> &'static str {
+    match action {
+        Action::Dismiss => "DISMISS",
+        Action::ConfirmCompromised => "CONFIRM",
+    }
+}
+
+async fn apply(client: &GraphClient, targets: &[(Action, &RiskyUser)], config: &AppConfig, shutdown: &ShutdownSignal) -> Result<()> {
+    println!();
+    if config.dry_run {
+        for (action, user) in targets {
+            println!("{:<8}{}", label(*action), user.user_principal_name);
+        }
+        println!();
+        println!("Processed {} of {} users: {} to change", targets.len(), targets.len(), targets.len());
+        println!("Dry run, no changes were made.");
+        return Ok(());
+    }
+    if !config.yes {
+        if !std::io::stdin().is_terminal() {
+            return Err(anyhow::anyhow!(
+                "Confirmation requires an interactive terminal. Pass --yes to run without it"
+            ));
+        }
+        let dismiss = targets.iter().filter(|(action, _)| *action == Action::Dismiss).count();
+        println!(
+            "Dismiss the risk of {} users and confirm {} users as compromised?",
+            dismiss,
+            targets.len() - dismiss
+        );
+        if !confirm()? {
+            println!("Aborted, no changes were made.");
+            return Ok(());
+        }
+    }
+
+    let _phase = client.stats().phase("update risk");
+    let mut processed = 0;
+    let mut changed = 0;
+    let mut failed = 0;
+    for (action, user) in targets {
+        if shutdown.is_requested() {
+            break;
+        }
+        processed += 1;
+        let result = match action {
+            Action::Dismiss => remediation::dismiss_user_risk(client, &user.id).await,
+            Action::ConfirmCompromised => remediation::confirm_user_compromised(client, &user.id).await,
+        };
+        match result {
+            Ok(()) => {
+                changed += 1;
+                println!("OK      {}", user.user_principal_name);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", user.user_principal_name, e);
+            }
+        }
+    }
+
+    let interrupted = targets.len() - processed;
+    for (_, user) in &targets[processed..] {
+        println!("SKIPPED {}: not started", user.user_principal_name);
+    }
+    println!();
+    println!(
+        "Processed {} of {} users: {} changed, {} failed, {} skipped",
+        processed,
+        targets.len(),
+        changed,
+        failed,
+        interrupted
+    );
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} users were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, targets.len()));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting risky_users");
+    debug!("Configuration: {:?}", config);
+
+    let acting = !config.dismiss.is_empty() || !config.confirm_compromised.is_empty();
+    if acting && config.output != "text" {
+        return Err(anyhow::anyhow!("--dismiss and --confirm-compromised report their progress as text and cannot be combined with -o {}", config.output));
+    }
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    let mut permissions = vec![if acting && !config.dry_run {
+        "IdentityRiskyUser.ReadWrite.All"
+    } else {
+        "IdentityRiskyUser.Read.All"
+    }];
+    if config.detections {
+        permissions.push("IdentityRiskEvent.Read.All");
+    }
+    client.require_permissions(&permissions)?;
+
+    let since = Utc::now() - Duration::days(config.days);
+    let phase = client.stats().phase("read risk");
+    let users = list_risky_users(&client).await?;
+    let targets = resolve_targets(&users, &config)?;
+    let report = if config.detections {
+        let detections = list_detections(&client, &config.min_risk_level, since).await?;
+        drop(phase);
+        render_detections(&detections, &config.output, &config.min_risk_level, config.days, &client.stats().summary())?
+    } else {
+        drop(phase);
+        let listed = at_risk(&users, &config.min_risk_level, since);
+        render_users(&listed, &config.output, &config.min_risk_level, config.days, &client.stats().summary())?
+    };
+    print!("{}", report);
+
+    if acting {
+        let shutdown = service::shutdown_signal();
+        let result = apply(&client, &targets, &config, &shutdown).await;
+        client.stats().summary().print();
+        result?;
+    } else if config.output != "json" {
+        client.stats().summary().print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let since: DateTime<Utc> = "2026-09-15T00:00:00Z".parse().unwrap();
+        let users = list_risky_users(&client).await.unwrap();
+        let listed = at_risk(&users, "low", since);
+        let detections = list_detections(&client, "low", since).await.unwrap();
+
+        golden::assert_reports("users", &["text", "csv", "json"], |output| render_users(&listed, output, "low", 30, &Summary::fixture()));
+        golden::assert_reports("detections", &["text", "csv", "json"], |output| render_detections(&detections, output, "low", 30, &Summary::fixture()));
+
+        let high: Vec<&str> = at_risk(&users, "high", since).iter().map(|user| user.user_principal_name.as_str()).collect();
+        assert_eq!(high, ["jane.doe@contoso.com"]);
+    }
+
+    #[test]
+    fn rejects_unknown_and_conflicting_targets() {
+        let users: Vec<RiskyUser> = serde_json::from_value(json!([
+            {"id": "1", "userPrincipalName": "Jane.Doe@contoso.com", "riskLevel": "high", "riskState": "atRisk"},
+            {"id": "2", "userPrincipalName": "john.smith@contoso.com", "riskLevel": "low", "riskState": "dismissed"}
+        ]))
+        .unwrap();
+        let config = |args: &[&str]| AppConfig::parse_from(["risky_users"].iter().chain(args));
+
+        let targets = resolve_targets(&users, &config(&["--dismiss", "john.smith@contoso.com", "--confirm-compromised", "jane.doe@contoso.com"])).unwrap();
+        let ids: Vec<(Action, &str)> = targets.iter().map(|(action, user)| (*action, user.id.as_str())).collect();
+        assert_eq!(ids, [(Action::Dismiss, "2"), (Action::ConfirmCompromised, "1")]);
+
+        assert!(resolve_targets(&users, &config(&["--dismiss", "nobody@contoso.com"])).is_err());
+        assert!(resolve_targets(&users, &config(&["--dismiss", "jane.doe@contoso.com", "--confirm-compromised", "Jane.Doe@contoso.com"])).is_err());
+    }
+}
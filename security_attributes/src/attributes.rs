@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+pub const VALUE_TYPE: &str = "#Microsoft.DirectoryServices.CustomSecurityAttributeValue";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Definition {
+    pub id: String,
+    #[serde(rename = "attributeSet")]
+    pub attribute_set: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    #[serde(rename = "isCollection", default)]
+    pub is_collection: bool,
+    #[serde(rename = "usePreDefinedValuesOnly", default)]
+    pub use_predefined_values_only: bool,
+    pub status: Option<String>,
+    pub description: Option<String>,
+    #[serde(rename = "allowedValues", default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_values: Vec<String>,
+}
+
+impl Definition {
+    pub fn key(&self) -> String {
+        format!("{}.{}", self.attribute_set, self.name)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status.as_deref().is_none_or(|status| status.eq_ignore_ascii_case("Available"))
+    }
+
+    fn scalar(&self, raw: &str) -> Result<Value> {
+        let value = match self.data_type.as_str() {
+            "Integer" => raw
+                .parse::<i32>()
+                .map(Value::from)
+                .with_context(|| format!("{:?} is not an integer", raw))?,
+            "Boolean" => match raw.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Value::Bool(true),
+                "false" | "no" | "0" => Value::Bool(false),
+                _ => return Err(anyhow::anyhow!("{:?} is not a boolean", raw)),
+            },
+            _ => Value::String(raw.to_string()),
+        };
+        if self.use_predefined_values_only
+            && !self.allowed_values.iter().any(|allowed| allowed == raw)
+        {
+            return Err(anyhow::anyhow!(
+                "{:?} is not an allowed value of {} ({})",
+                raw,
+                self.key(),
+                self.allowed_values.join(", ")
+            ));
+        }
+        Ok(value)
+    }
+
+    // An empty cell removes the attribute; collections take values separated by ';'.
+    pub fn parse(&self, raw: &str) -> Result<Value> {
+        if raw.is_empty() {
+            return Ok(Value::Null);
+        }
+        if self.is_collection {
+            return raw
+                .split(';')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| self.scalar(value))
+                .collect::<Result<Vec<Value>>>()
+                .map(Value::Array);
+        }
+        self.scalar(raw)
+    }
+
+    // Graph needs the type annotation for anything other than a single string or boolean.
+    fn odata_type(&self) -> Option<&'static str> {
+        match (self.is_collection, self.data_type.as_str()) {
+            (true, "Integer") => Some("#Collection(Int32)"),
+            (true, _) => Some("#Collection(String)"),
+            (false, "Integer") => Some("#Int32"),
+            _ => None,
+        }
+    }
+}
+
+// Current values per attribute set, without the @odata.type annotations Graph returns.
+pub fn current_values(custom_security_attributes: Option<&Value>) -> BTreeMap<String, Value> {
+    let mut values = BTreeMap::new();
+    let Some(Value::Object(sets)) = custom_security_attributes else {
+        return values;
+    };
+    for (set, attributes) in sets {
+        let Value::Object(attributes) = attributes else {
+            continue;
+        };
+        for (name, value) in attributes {
+            if !name.contains('@') {
+                values.insert(format!("{}.{}", set, name), value.clone());
+            }
+        }
+    }
+    values
+}
+
+// Builds the customSecurityAttributes PATCH body for the given attribute values, grouped by
+// attribute set.
+pub fn patch_body(values: &[(&Definition, Value)]) -> Value {
+    let mut sets: BTreeMap<&str, Map<String, Value>> = BTreeMap::new();
+    for (definition, value) in values {
+        let set = sets.entry(&definition.attribute_set).or_insert_with(|| {
+            let mut set = Map::new();
+            set.insert("@odata.type".to_string(), json!(VALUE_TYPE));
+            set
+        });
+        if let Some(odata_type) = definition.odata_type().filter(|_| !value.is_null()) {
+            set.insert(format!("{}@odata.type", definition.name), json!(odata_type));
+        }
+        set.insert(definition.name.clone(), value.clone());
+    }
+    json!({ "customSecurityAttributes": sets })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(name: &str, data_type: &str, is_collection: bool) -> Definition {
+        Definition {
+            id: format!("Classification_{}", name),
+            attribute_set: "Classification".to_string(),
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            is_collection,
+            use_predefined_values_only: false,
+            status: Some("Available".to_string()),
+            description: None,
+            allowed_values: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_values_and_builds_typed_patch() {
+        let mut level = definition("Level", "String", false);
+        level.use_predefined_values_only = true;
+        level.allowed_values = vec!["Public".to_string(), "Confidential".to_string()];
+        let projects = definition("Projects", "Integer", true);
+
+        assert!(level.parse("Secret").is_err());
+        assert!(projects.parse("12;x").is_err());
+        let body = patch_body(&[
+            (&level, level.parse("Confidential").unwrap()),
+            (&projects, projects.parse("12; 40").unwrap()),
+            (&definition("Owner", "String", false), Value::Null),
+        ]);
+        assert_eq!(
+            body,
+            json!({ "customSecurityAttributes": { "Classification": {
+                "@odata.type": VALUE_TYPE,
+                "Level": "Confidential",
+                "Projects@odata.type": "#Collection(Int32)",
+                "Projects": [12, 40],
+                "Owner": null,
+            }}})
+        );
+
+        let current = current_values(Some(&body["customSecurityAttributes"]));
+        assert_eq!(current.len(), 3);
+        assert_eq!(current["Classification.Projects"], json!([12, 40]));
+    }
+}
@@ -0,0 +1,508 @@
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::actionlog::{Change, Undo};
+use graph_common::csv::{self, Table};
+use graph_common::{client, encode, service, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::env;
+use std::fmt::Write;
+use std::io::Read;
+
+mod attributes;
+use attributes::Definition;
+
+#[derive(Parser, Debug)]
+#[command(name = "security_attributes")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists and assigns custom security attributes on users using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, global = true, help = "Enable verbose logging")]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    #[command(about = "List custom security attribute definitions")]
+    Definitions {
+        #[arg(short, long, help = "Only list attributes in this attribute set")]
+        set: Option<String>,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+        output: String,
+    },
+
+    #[command(about = "List the custom security attributes assigned to users")]
+    Assignments {
+        #[arg(short, long, value_delimiter = ',', help = "Only these users (repeatable or comma separated); all users by default")]
+        upn: Vec<String>,
+
+        #[arg(short, long, help = "Only list attributes in this attribute set")]
+        set: Option<String>,
+
+        #[arg(short, long, default_value = "text", value_parser = ["text", "json", "csv"], help = "Output format; csv can be edited and passed to assign")]
+        output: String,
+    },
+
+    #[command(about = "Assign custom security attributes to users from a CSV file")]
+    Assign {
+        #[arg(short, long, help = "CSV with a upn column and one column per attribute named <attributeSet>.<attribute> (- for stdin)")]
+        file: String,
+
+        #[arg(long, help = "Show the changes without making them")]
+        dry_run: bool,
+
+        #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of users processed at the same time")]
+        concurrency: u16,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct AllowedValue {
+    id: String,
+    #[serde(rename = "isActive", default)]
+    is_active: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    #[serde(rename = "customSecurityAttributes")]
+    custom_security_attributes: Option<Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct Assignment {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+    attributes: BTreeMap<String, Value>,
+}
+
+enum Outcome {
+    Updated,
+    Unchanged,
+    Planned(Vec<String>),
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_definitions(client: &GraphClient, set: Option<&str>) -> Result<Vec<Definition>> {
+    let mut resource = client.path(&["directory", "customSecurityAttributeDefinitions"]);
+    if let Some(set) = set {
+        resource = resource.filter(&format!("attributeSet eq {}", encode::odata_string(set)));
+    }
+    let mut definitions: Vec<Definition> = resource
+        .get_all()
+        .await
+        .context("Failed to list custom security attribute definitions")?;
+
+    // Only the active predefined values can be assigned.
+    for definition in definitions.iter_mut().filter(|definition| definition.use_predefined_values_only) {
+        let allowed: Vec<AllowedValue> = client
+            .path(&["directory", "customSecurityAttributeDefinitions", &definition.id, "allowedValues"])
+            .get_all()
+            .await
+            .with_context(|| format!("Failed to list the allowed values of {}", definition.key()))?;
+        definition.allowed_values = allowed
+            .into_iter()
+            .filter(|value| value.is_active)
+            .map(|value| value.id)
+            .collect();
+    }
+    definitions.sort_by_key(Definition::key);
+    Ok(definitions)
+}
+
+fn render_definitions(definitions: &[Definition], output: &str) -> Result<String> {
+    let mut out = String::new();
+    if output == "json" {
+        writeln!(out, "{}", serde_json::to_string_pretty(definitions)?)?;
+        return Ok(out);
+    }
+    if definitions.is_empty() {
+        writeln!(out, "No custom security attributes found")?;
+        return Ok(out);
+    }
+    writeln!(
+        out,
+        "{:<40} {:<8} {:<10} {:<10} VALUES",
+        "ATTRIBUTE", "TYPE", "COLLECTION", "STATUS"
+    )?;
+    for definition in definitions {
+        let values = if definition.use_predefined_values_only {
+            definition.allowed_values.join(", ")
+        } else {
+            "any".to_string()
+        };
+        writeln!(
+            out,
+            "{:<40} {:<8} {:<10} {:<10} {}",
+            definition.key(),
+            definition.data_type,
+            if definition.is_collection { "yes" } else { "no" },
+            definition.status.as_deref().unwrap_or("-"),
+            values
+        )?;
+    }
+    Ok(out)
+}
+
+// customSecurityAttributes is only returned when selected explicitly.
+async fn list_assignments(client: &GraphClient, upns: &[String], set: Option<&str>) -> Result<Vec<Assignment>> {
+    let users: Vec<User> = if upns.is_empty() {
+        client
+            .users()
+            .select("userPrincipalName,customSecurityAttributes")
+            .top(999)
+            .get_all()
+            .await
+            .context("Failed to list users")?
+    } else {
+        let mut users = Vec::new();
+        for upn in upns {
+            users.push(
+                client
+                    .user(upn)
+                    .resource()
+                    .select("userPrincipalName,customSecurityAttributes")
+                    .get_json::<User>()
+                    .await
+                    .with_context(|| format!("Failed to look up {}", upn))?,
+            );
+        }
+        users
+    };
+
+    let prefix = set.map(|set| format!("{}.", set.to_lowercase()));
+    Ok(users
+        .into_iter()
+        .map(|user| {
+            let mut attributes = attributes::current_values(user.custom_security_attributes.as_ref());
+            attributes.retain(|key, value| {
+                !value.is_null() && prefix.as_ref().is_none_or(|prefix| key.to_lowercase().starts_with(prefix))
+            });
+            Assignment {
+                user_principal_name: user.user_principal_name,
+                attributes,
+            }
+        })
+        .filter(|assignment| !assignment.attributes.is_empty())
+        .collect())
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        Value::Array(values) => values.iter().map(display_value).collect::<Vec<_>>().join(";"),
+        other => other.to_string(),
+    }
+}
+
+fn render_assignments(assignments: &[Assignment], output: &str) -> Result<String> {
+    let mut out = String::new();
+    match output {
+        "json" => writeln!(out, "{}", serde_json::to_string_pretty(assignments)?)?,
+        "csv" => {
+            let keys: BTreeSet<&String> = assignments
+                .iter()
+                .flat_map(|assignment| assignment.attributes.keys())
+                .collect();
+            let mut header = vec!["upn".to_string()];
+            header.extend(keys.iter().map(|key| csv::field(key)));
+            writeln!(out, "{}", header.join(","))?;
+            for assignment in assignments {
+                let mut row = vec![csv::field(&assignment.user_principal_name)];
+                row.extend(keys.iter().map(|key| {
+                    csv::field(&assignment.attributes.get(*key).map(display_value).unwrap_or_default())
+                }));
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+        _ => {
+            for assignment in assignments {
+                writeln!(out, "{}", assignment.user_principal_name)?;
+                for (key, value) in &assignment.attributes {
+                    writeln!(out, "  {:<40} {}", key, display_value(value))?;
+                }
+            }
+            writeln!(out)?;
+            writeln!(out, "{} users have custom security attributes assigned", assignments.len())?;
+        }
+    }
+    Ok(out)
+}
+
+fn read_file(source: &str) -> Result<String> {
+    if source == "-" {
+        debug!("Reading rows from stdin");
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read rows from stdin")?;
+        Ok(content)
+    } else {
+        debug!("Reading rows from {}", source);
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {}", source))
+    }
+}
+
+// Only attributes whose value differs are sent, and their previous values are recorded in
+// the action log for `mstools undo`.
+async fn assign_user(
+    client: &GraphClient,
+    upn: &str,
+    desired: &[(&Definition, Value)],
+    dry_run: bool,
+) -> Result<Outcome> {
+    let resource = client.user(upn).resource();
+    let user: User = resource
+        .clone()
+        .select("userPrincipalName,customSecurityAttributes")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to look up {}", upn))?;
+    let current = attributes::current_values(user.custom_security_attributes.as_ref());
+
+    let mut changes = Vec::new();
+    let mut previous = Vec::new();
+    for (definition, value) in desired {
+        let old = current.get(&definition.key()).cloned().unwrap_or(Value::Null);
+        if &old != value {
+            changes.push((*definition, value.clone()));
+            previous.push((*definition, old));
+        }
+    }
+    if changes.is_empty() {
+        return Ok(Outcome::Unchanged);
+    }
+    if dry_run {
+        return Ok(Outcome::Planned(
+            changes
+                .iter()
+                .zip(&previous)
+                .map(|((definition, new), (_, old))| {
+                    format!("{}: {:?} -> {:?}", definition.key(), display_value(old), display_value(new))
+                })
+                .collect(),
+        ));
+    }
+
+    let url = resource.url();
+    let body = attributes::patch_body(&changes);
+    let change = Change::new("PATCH", &url, Some(&body)).with_undo(Undo {
+        method: "PATCH".to_string(),
+        url: url.clone(),
+        body: Some(attributes::patch_body(&previous)),
+    });
+    let response = client.send_change(change).await?;
+    client::ensure_success(response)
+        .await
+        .with_context(|| format!("Failed to assign custom security attributes to {}", upn))?;
+    Ok(Outcome::Updated)
+}
+
+async fn assign(client: &GraphClient, file: &str, dry_run: bool, concurrency: usize) -> Result<()> {
+    let definitions = list_definitions(client, None).await?;
+    let table = Table::parse(&read_file(file)?)?;
+    let upn_column = table.upn_column()?;
+
+    // Every other column must name an attribute that can still be assigned.
+    let mut columns = Vec::new();
+    for (index, name) in table.header.iter().enumerate().filter(|(index, _)| *index != upn_column) {
+        let definition = definitions
+            .iter()
+            .find(|definition| definition.key().eq_ignore_ascii_case(name))
+            .with_context(|| format!("Column {} is not a custom security attribute (<attributeSet>.<attribute>)", name))?;
+        if !definition.is_active() {
+            return Err(anyhow::anyhow!("{} is deactivated and cannot be assigned", definition.key()));
+        }
+        columns.push((index, definition));
+    }
+    if columns.is_empty() {
+        return Err(anyhow::anyhow!("The file has no attribute columns"));
+    }
+
+    let mut seen = HashSet::new();
+    for record in &table.records {
+        let upn = record.get(upn_column);
+        if upn.is_empty() {
+            return Err(anyhow::anyhow!("Line {} has no UPN", record.line));
+        }
+        if !seen.insert(upn.to_lowercase()) {
+            return Err(anyhow::anyhow!("{} appears more than once (line {})", upn, record.line));
+        }
+    }
+    info!(
+        "Assigning {} attributes to {} users",
+        columns.len(),
+        table.records.len()
+    );
+
+    // Users already being processed finish; no new ones are started after an interrupt.
+    let shutdown = service::shutdown_signal();
+    let mut updated = 0;
+    let mut unchanged = 0;
+    let mut failed = 0;
+    let mut results = stream::iter(&table.records)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|record| {
+            let columns = &columns;
+            async move {
+                let desired: Result<Vec<(&Definition, Value)>> = columns
+                    .iter()
+                    .map(|(index, definition)| {
+                        definition
+                            .parse(record.get(*index))
+                            .map(|value| (*definition, value))
+                            .with_context(|| format!("Line {}", record.line))
+                    })
+                    .collect();
+                let upn = record.get(upn_column);
+                let result = match desired {
+                    Ok(desired) => assign_user(client, upn, &desired, dry_run).await,
+                    Err(e) => Err(e),
+                };
+                (record, upn, result)
+            }
+        })
+        .buffer_unordered(concurrency);
+    let phase = client.stats().phase("update users");
+    let mut finished = HashSet::new();
+    while let Some((record, upn, result)) = results.next().await {
+        finished.insert(record.line);
+        match result {
+            Ok(Outcome::Updated) => {
+                updated += 1;
+                println!("OK      {}", upn);
+            }
+            Ok(Outcome::Planned(changes)) => {
+                updated += 1;
+                println!("CHANGE  {}: {}", upn, changes.join(", "));
+            }
+            Ok(Outcome::Unchanged) => {
+                unchanged += 1;
+                println!("SKIPPED {}: unchanged", upn);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", upn, e);
+            }
+        }
+    }
+
+    let total = table.records.len();
+    let interrupted = total - finished.len();
+    if interrupted > 0 {
+        warn!("Interrupted before {} users were processed", interrupted);
+        for record in table.records.iter().filter(|record| !finished.contains(&record.line)) {
+            println!("SKIPPED {}: not started", record.get(upn_column));
+        }
+    }
+
+    println!();
+    println!(
+        "Processed {} of {} users: {} {}, {} unchanged, {} failed, {} skipped",
+        total - interrupted,
+        total,
+        updated,
+        if dry_run { "to update" } else { "updated" },
+        unchanged,
+        failed,
+        interrupted
+    );
+    if dry_run {
+        println!("Dry run, no changes were made.");
+    }
+    drop(phase);
+    client.stats().summary().print();
+    if interrupted > 0 {
+        return Err(anyhow::anyhow!("Interrupted before {} users were processed", interrupted));
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} users failed", failed, total));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting security_attributes");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&match config.command {
+        Commands::Definitions { .. } => vec!["CustomSecAttributeDefinition.Read.All"],
+        Commands::Assignments { .. } => vec!["CustomSecAttributeAssignment.Read.All", "User.Read.All"],
+        Commands::Assign { .. } => vec![
+            "CustomSecAttributeDefinition.Read.All",
+            "CustomSecAttributeAssignment.ReadWrite.All",
+            "User.Read.All",
+        ],
+    })?;
+
+    match config.command {
+        Commands::Definitions { set, output } => {
+            let definitions = list_definitions(&client, set.as_deref()).await?;
+            print!("{}", render_definitions(&definitions, &output)?);
+        }
+        Commands::Assignments { upn, set, output } => {
+            let assignments = list_assignments(&client, &upn, set.as_deref()).await?;
+            print!("{}", render_assignments(&assignments, &output)?);
+        }
+        Commands::Assign {
+            file,
+            dry_run,
+            concurrency,
+        } => {
+            assign(&client, &file, dry_run, concurrency as usize).await?;
+        }
+    }
+
+    info!("Operation completed successfully.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+
+        let definitions = list_definitions(&client, None).await.unwrap();
+        golden::assert_reports("definitions", &["text", "json"], |output| render_definitions(&definitions, output));
+
+        let assignments = list_assignments(&client, &[], None).await.unwrap();
+        golden::assert_reports("assignments", &["text", "csv", "json"], |output| render_assignments(&assignments, output));
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use clap::Parser;
 use dotenv::from_path;
+use graph_common::{encode, permissions};
 use log::{debug, info, LevelFilter};
 use regex::Regex;
 use reqwest::Client;
@@ -22,6 +23,8 @@ struct Cli {
     body: String,
     #[arg(short = 'u', long, help = "Sender email address")]
     sender: String,
+    #[arg(long, help = "Save the message as a draft in the sender mailbox for review instead of sending it")]
+    draft: bool,
     #[arg(short, long, help = "Enable verbose logging")]
     verbose: bool,
 }
@@ -51,8 +54,14 @@ async fn main() -> Result<()> {
 
     let access_token = get_access_token_from_env(cli.verbose).await?;
     debug!("Access token obtained successfully");
+    permissions::check(&access_token, &[if cli.draft { "Mail.ReadWrite" } else { "Mail.Send" }])?;
 
-    send_email(&cli, &access_token).await?;
+    if cli.draft {
+        let web_link = create_draft(&cli, &access_token).await?;
+        println!("{}", web_link);
+    } else {
+        send_email(&cli, &access_token).await?;
+    }
     info!("Operation completed successfully.");
     Ok(())
 }
@@ -97,7 +106,7 @@ async fn send_email(cli: &Cli, access_token: &str) -> Result<()> {
     let client = Client::new();
     let url = format!(
         "https://graph.microsoft.com/v1.0/users/{}/sendMail",
-        cli.sender
+        encode::path_segment(&cli.sender)
     );
     debug!("Send email URL: {}", url);
 
@@ -107,22 +116,67 @@ async fn send_email(cli: &Cli, access_token: &str) -> Result<()> {
     handle_email_response(response, &cli.email.0).await
 }
 
-fn create_email_data(cli: &Cli) -> serde_json::Value {
+async fn create_draft(cli: &Cli, access_token: &str) -> Result<String> {
+    debug!("Preparing to create draft...");
+    let client = Client::new();
+    let url = format!(
+        "https://graph.microsoft.com/v1.0/users/{}/messages",
+        encode::path_segment(&cli.sender)
+    );
+    debug!("Create draft URL: {}", url);
+
+    let response = send_email_request(&client, &url, access_token, &create_message(cli)).await?;
+    let status = response.status();
+    debug!("Response status: {:?}", status);
+    let response_text = response
+        .text()
+        .await
+        .context("Failed to get response text")?;
+    debug!("Response text: {:?}", response_text);
+
+    if !status.is_success() {
+        return Err(anyhow::anyhow!(
+            "Failed to create draft in {}: {} - {}",
+            cli.sender,
+            status,
+            response_text
+        ));
+    }
+
+    let draft: serde_json::Value =
+        serde_json::from_str(&response_text).context("Failed to parse draft response")?;
+    info!(
+        "Draft to {} created in {} with id {}",
+        cli.email.0,
+        cli.sender,
+        draft["id"].as_str().unwrap_or("unknown")
+    );
+    draft["webLink"]
+        .as_str()
+        .map(str::to_string)
+        .context("Draft response did not include a webLink")
+}
+
+fn create_message(cli: &Cli) -> serde_json::Value {
     json!({
-        "message": {
-            "subject": cli.subject,
-            "body": {
-                "contentType": "Text",
-                "content": cli.body
-            },
-            "toRecipients": [
-                {
-                    "emailAddress": {
-                        "address": cli.email.0
-                    }
-                }
-            ]
+        "subject": cli.subject,
+        "body": {
+            "contentType": "Text",
+            "content": cli.body
         },
+        "toRecipients": [
+            {
+                "emailAddress": {
+                    "address": cli.email.0
+                }
+            }
+        ]
+    })
+}
+
+fn create_email_data(cli: &Cli) -> serde_json::Value {
+    json!({
+        "message": create_message(cli),
         "saveToSentItems": "true"
     })
 }
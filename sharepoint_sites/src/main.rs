@@ -0,0 +1,297 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDate, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::reports::SiteUsage;
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "sharepoint_sites")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists SharePoint sites by storage used, with file counts and last activity, flagging large dormant sites, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, default_value_t = 180, value_parser = clap::value_parser!(i64).range(1..), help = "Sites without activity for this many days are dormant")]
+    dormant_days: i64,
+
+    #[arg(long, default_value_t = 1.0, help = "Only flag dormant sites using at least this many GB")]
+    min_size_gb: f64,
+
+    #[arg(long, help = "List only flagged sites")]
+    flagged: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphSite {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SharePointSettings {
+    #[serde(rename = "sharingCapability")]
+    sharing_capability: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SiteRow {
+    #[serde(rename = "siteId")]
+    site_id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    url: String,
+    owner: String,
+    template: String,
+    #[serde(rename = "storageUsedBytes")]
+    storage_used: Option<u64>,
+    #[serde(rename = "storageAllocatedBytes")]
+    storage_allocated: Option<u64>,
+    #[serde(rename = "fileCount")]
+    file_count: Option<u64>,
+    #[serde(rename = "activeFileCount")]
+    active_file_count: Option<u64>,
+    #[serde(rename = "pageViewCount")]
+    page_view_count: Option<u64>,
+    #[serde(rename = "lastActivity")]
+    last_activity: Option<NaiveDate>,
+    dormant: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn load_usage(client: &GraphClient) -> Result<SiteUsage> {
+    let _phase = client.stats().phase("read usage report");
+    let usage = SiteUsage::load(client).await?;
+    if !usage.is_empty() && usage.sites().all(|site| site.url.is_empty()) {
+        warn!("The site usage report conceals site URLs and owners; turn off concealed names in the Microsoft 365 admin center reports settings to see owners");
+    }
+    info!("The site usage report lists {} sites", usage.len());
+    Ok(usage)
+}
+
+// The report only has the site collection id, the middle part of a Graph site id
+// ("contoso.sharepoint.com,<site collection id>,<web id>"), so names are matched on it.
+async fn site_names(client: &GraphClient) -> Result<HashMap<String, GraphSite>> {
+    let _phase = client.stats().phase("list sites");
+    let sites: Vec<GraphSite> = client
+        .path(&["sites", "getAllSites"])
+        .select("id,displayName,webUrl")
+        .get_all()
+        .await
+        .context("Failed to list SharePoint sites")?;
+    Ok(sites
+        .into_iter()
+        .filter_map(|site| Some((site.id.split(',').nth(1)?.to_lowercase(), site)))
+        .collect())
+}
+
+async fn sharing_capability(client: &GraphClient) -> Result<String> {
+    let settings: SharePointSettings = client
+        .path(&["admin", "sharepoint", "settings"])
+        .select("sharingCapability")
+        .get_json()
+        .await
+        .context("Failed to read the SharePoint tenant settings")?;
+    Ok(settings.sharing_capability.unwrap_or_default())
+}
+
+// Sorted with the largest sites first. A site is dormant when it had no activity since the
+// cutoff, or none at all, and uses at least the minimum size.
+fn site_rows(usage: &SiteUsage, names: &HashMap<String, GraphSite>, cutoff: NaiveDate, min_size_gb: f64) -> Vec<SiteRow> {
+    let min_size = (min_size_gb * 1073741824.0) as u64;
+    let mut rows: Vec<SiteRow> = usage
+        .sites()
+        .map(|site| {
+            let graph_site = names.get(&site.site_id);
+            let url = match site.url.as_str() {
+                "" => graph_site.and_then(|graph_site| graph_site.web_url.clone()).unwrap_or_default(),
+                url => url.to_string(),
+            };
+            SiteRow {
+                site_id: site.site_id.clone(),
+                display_name: graph_site
+                    .and_then(|graph_site| graph_site.display_name.clone())
+                    .unwrap_or_default(),
+                url,
+                owner: match site.owner_principal_name.as_str() {
+                    "" => site.owner_display_name.clone(),
+                    owner => owner.to_string(),
+                },
+                template: site.template.clone(),
+                storage_used: site.storage_used,
+                storage_allocated: site.storage_allocated,
+                file_count: site.file_count,
+                active_file_count: site.active_file_count,
+                page_view_count: site.page_view_count,
+                last_activity: site.last_activity,
+                dormant: site.last_activity.is_none_or(|last| last < cutoff)
+                    && site.storage_used.is_some_and(|used| used >= min_size),
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| b.storage_used.cmp(&a.storage_used).then_with(|| a.url.cmp(&b.url)));
+    rows
+}
+
+fn gigabytes(bytes: Option<u64>) -> String {
+    bytes.map_or_else(|| "-".to_string(), |bytes| format!("{:.1}", bytes as f64 / 1073741824.0))
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn render_report(rows: &[&SiteRow], all_rows: &[SiteRow], sharing: &str, config: &AppConfig, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let dormant: Vec<&SiteRow> = all_rows.iter().filter(|row| row.dormant).collect();
+    let total = |rows: &[&SiteRow]| gigabytes(Some(rows.iter().filter_map(|row| row.storage_used).sum()));
+    match config.output.as_str() {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "sites": rows,
+                "totalSites": all_rows.len(),
+                "dormantSites": dormant.len(),
+                "dormantDays": config.dormant_days,
+                "minSizeGb": config.min_size_gb,
+                "tenantSharingCapability": sharing,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "siteId,displayName,url,owner,template,storageUsedBytes,storageAllocatedBytes,fileCount,activeFileCount,pageViewCount,lastActivity,dormant"
+            )?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    row.site_id,
+                    csv::field(&row.display_name),
+                    csv::field(&row.url),
+                    csv::field(&row.owner),
+                    csv::field(&row.template),
+                    optional(row.storage_used),
+                    optional(row.storage_allocated),
+                    optional(row.file_count),
+                    optional(row.active_file_count),
+                    optional(row.page_view_count),
+                    optional(row.last_activity),
+                    row.dormant
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<28} {:>8} {:>8} {:>7} {:<13} {:<8} URL",
+                "SITE", "SIZE GB", "FILES", "ACTIVE", "LAST ACTIVITY", "NOTE"
+            )?;
+            for row in rows {
+                let line = format!(
+                    "{:<28} {:>8} {:>8} {:>7} {:<13} {:<8} {}",
+                    if row.display_name.is_empty() { "-" } else { &row.display_name },
+                    gigabytes(row.storage_used),
+                    row.file_count.map_or_else(|| "-".to_string(), |count| count.to_string()),
+                    row.active_file_count.map_or_else(|| "-".to_string(), |count| count.to_string()),
+                    row.last_activity.map_or_else(|| "never".to_string(), |last| last.to_string()),
+                    if row.dormant { "dormant" } else { "" },
+                    row.url
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            let all: Vec<&SiteRow> = all_rows.iter().collect();
+            writeln!(
+                out,
+                "{} sites, {} GB in total; {} dormant for {} days with at least {} GB, using {} GB",
+                all_rows.len(),
+                total(&all),
+                dormant.len(),
+                config.dormant_days,
+                config.min_size_gb,
+                total(&dormant)
+            )?;
+            writeln!(out, "Tenant external sharing: {}", sharing)?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting sharepoint_sites");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Reports.Read.All", "Sites.Read.All", "SharePointTenantSettings.Read.All"])?;
+
+    let usage = load_usage(&client).await?;
+    let names = site_names(&client).await?;
+    let sharing = sharing_capability(&client).await?;
+    let cutoff = Utc::now().date_naive() - Duration::days(config.dormant_days);
+    let rows = site_rows(&usage, &names, cutoff, config.min_size_gb);
+    let listed: Vec<&SiteRow> = rows.iter().filter(|row| !config.flagged || row.dormant).collect();
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&listed, &rows, &sharing, &config, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let usage = load_usage(&client).await.unwrap();
+        let names = site_names(&client).await.unwrap();
+        let sharing = sharing_capability(&client).await.unwrap();
+        let cutoff = NaiveDate::from_ymd_opt(2026, 4, 18).unwrap();
+        let rows = site_rows(&usage, &names, cutoff, 1.0);
+        let listed: Vec<&SiteRow> = rows.iter().collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| {
+            let config = AppConfig::parse_from(["sharepoint_sites", "-o", output]);
+            render_report(&listed, &rows, &sharing, &config, &Summary::fixture())
+        });
+    }
+}
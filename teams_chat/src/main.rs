@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::cli::collect_upns;
+use graph_common::client::GRAPH_BASE_URL;
+use graph_common::{client, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[command(name = "teams_chat")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Sends a Microsoft Teams chat message to users in a 1:1 chat using the Microsoft API")]
+#[command(group(ArgGroup::new("targets").required(true).multiple(true).args(["upn", "file"])))]
+#[command(group(ArgGroup::new("content").required(true).args(["message", "message_file"])))]
+struct AppConfig {
+    #[arg(short, long, value_delimiter = ',', help = "User Principal Name (repeatable or comma separated)")]
+    upn: Vec<String>,
+
+    #[arg(short, long, help = "CSV with a upn column (- for stdin)")]
+    file: Option<String>,
+
+    #[arg(short, long, help = "Message text")]
+    message: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Read the message from a text or .html file")]
+    message_file: Option<String>,
+
+    #[arg(long, default_value = "normal", value_parser = ["normal", "high", "urgent"], help = "Importance of the message")]
+    importance: String,
+
+    #[arg(long, help = "Show the messages without sending them")]
+    dry_run: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Me {
+    id: String,
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Chat {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatMessage {
+    id: String,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn to_html(text: &str) -> String {
+    text.trim_end()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>\n")
+}
+
+fn message_content(config: &AppConfig) -> Result<String> {
+    if let Some(file) = &config.message_file {
+        let content = std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+        let html = Path::new(file)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"));
+        return Ok(if html { content } else { to_html(&content) });
+    }
+    Ok(to_html(config.message.as_deref().unwrap_or_default()))
+}
+
+fn chat_member(user: &str) -> Value {
+    json!({
+        "@odata.type": "#microsoft.graph.aadUserConversationMember",
+        "roles": ["owner"],
+        "user@odata.bind": format!("{}/users('{}')", GRAPH_BASE_URL, user),
+    })
+}
+
+// Creating a one-on-one chat that already exists returns the existing chat, so the message
+// lands in the conversation the user already has with the sender.
+async fn send(client: &GraphClient, me: &Me, upn: &str, body: &Value) -> Result<ChatMessage> {
+    let response = client
+        .path(&["chats"])
+        .post_json(&json!({
+            "chatType": "oneOnOne",
+            "members": [chat_member(&me.id), chat_member(upn)],
+        }))
+        .await?;
+    let chat: Chat = client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to open a chat with {}", upn))?;
+    let response = client.path(&["chats", &chat.id, "messages"]).post_json(body).await?;
+    client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to send the message to {}", upn))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting teams_chat");
+    debug!("Configuration: {:?}", config);
+
+    let upns = collect_upns(&config.upn, config.file.as_ref())?;
+    if upns.is_empty() {
+        return Err(anyhow::anyhow!("No users to send the message to"));
+    }
+    let body = json!({
+        "importance": config.importance,
+        "body": { "contentType": "html", "content": message_content(&config)? },
+    });
+
+    // Chat messages can only be sent by a signed-in user, not by an application.
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client = GraphClient::from_device_code(&tenant_id, &client_id)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["ChatMessage.Send", "Chat.Create|Chat.ReadWrite"])?;
+    let me: Me = client
+        .path(&["me"])
+        .select("id,userPrincipalName")
+        .get_json()
+        .await
+        .context("Failed to read the signed-in user")?;
+
+    if config.dry_run {
+        println!("POST {}/chats/<chat id>/messages", client.base_url());
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        println!();
+    }
+    let (mut sent, mut failed) = (0, 0);
+    for upn in &upns {
+        if upn.eq_ignore_ascii_case(&me.user_principal_name) {
+            println!("SKIPPED {}: that is you", upn);
+            continue;
+        }
+        if config.dry_run {
+            println!("{:<8}{}: would be sent a message", "CHANGE", upn);
+            continue;
+        }
+        match send(&client, &me, upn, &body).await {
+            Ok(message) => {
+                sent += 1;
+                println!("{:<8}{}: sent message {}", "OK", upn, message.id);
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", upn, e);
+            }
+        }
+    }
+
+    println!();
+    if config.dry_run {
+        println!("Dry run, no messages were sent.");
+        return Ok(());
+    }
+    println!("Sent to {} of {} users, {} failed", sent, upns.len(), failed);
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} of {} messages could not be sent", failed, upns.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_chat_members_by_id_or_upn() {
+        let member = chat_member("jane.doe@contoso.com");
+        assert_eq!(member["@odata.type"], "#microsoft.graph.aadUserConversationMember");
+        assert_eq!(
+            member["user@odata.bind"],
+            "https://graph.microsoft.com/v1.0/users('jane.doe@contoso.com')"
+        );
+        assert_eq!(
+            to_html("Your sessions were revoked.\nPlease sign in again & re-register <MFA>."),
+            "Your sessions were revoked.<br>\nPlease sign in again &amp; re-register &lt;MFA&gt;."
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use clap::Parser;
+use dotenv::dotenv;
+use env_logger::Builder;
+use futures::stream::{self, StreamExt};
+use graph_common::reports::TeamActivity;
+use graph_common::service::{self, ShutdownSignal};
+use graph_common::stats::Summary;
+use graph_common::{csv, GraphClient};
+use log::{debug, info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+#[command(name = "teams_inventory")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Lists every team with its owners, members, guests, archived state, and last activity, using the Microsoft API")]
+struct AppConfig {
+    #[arg(short, long, default_value = "text", value_parser = ["text", "csv", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(short, long, value_name = "DOMAIN", help = "Domain guests are expected from, including its subdomains (can be repeated)")]
+    allowed_domain: Vec<String>,
+
+    #[arg(long, help = "Only list teams with an issue")]
+    flagged: bool,
+
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u16).range(1..=50), help = "Number of requests in flight at the same time")]
+    concurrency: u16,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Group {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+    visibility: Option<String>,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Team {
+    #[serde(rename = "isArchived", default)]
+    is_archived: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Member {
+    #[serde(rename = "userPrincipalName")]
+    user_principal_name: Option<String>,
+    mail: Option<String>,
+    #[serde(rename = "userType")]
+    user_type: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct TeamRow {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+    visibility: String,
+    archived: bool,
+    #[serde(rename = "createdDateTime")]
+    created_date_time: Option<DateTime<Utc>>,
+    #[serde(rename = "lastActivity")]
+    last_activity: Option<NaiveDate>,
+    owners: usize,
+    members: usize,
+    guests: usize,
+    #[serde(rename = "guestDomains")]
+    guest_domains: Vec<String>,
+    #[serde(rename = "unexpectedDomains")]
+    unexpected_domains: Vec<String>,
+    issues: Vec<&'static str>,
+}
+
+struct Scan {
+    teams: Vec<TeamRow>,
+    total: usize,
+    checked: usize,
+    failed: usize,
+    interrupted: bool,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+async fn list_teams(client: &GraphClient) -> Result<Vec<Group>> {
+    client
+        .path(&["groups"])
+        .filter("resourceProvisioningOptions/Any(x:x eq 'Team')")
+        .select("id,displayName,visibility,createdDateTime")
+        .top(999)
+        .get_all()
+        .await
+        .context("Failed to fetch teams")
+}
+
+// A guest's mail is their home address; without one, the domain is taken from the
+// "jane_fabrikam.com#EXT#@contoso.onmicrosoft.com" form of the user principal name.
+fn guest_domain(member: &Member) -> Option<String> {
+    let address = match member.mail.as_deref() {
+        Some(mail) => mail.to_string(),
+        None => member
+            .user_principal_name
+            .as_deref()?
+            .split_once("#EXT#")?
+            .0
+            .rsplit_once('_')
+            .map(|(user, domain)| format!("{}@{}", user, domain))?,
+    };
+    address.rsplit_once('@').map(|(_, domain)| domain.to_lowercase())
+}
+
+fn is_allowed(domain: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|allowed| {
+        let allowed = allowed.to_lowercase();
+        domain == allowed || domain.ends_with(&format!(".{}", allowed))
+    })
+}
+
+async fn read_team(client: &GraphClient, group: &Group, activity: &TeamActivity, allowed: &[String]) -> Result<TeamRow> {
+    let team: Team = client
+        .path(&["teams", &group.id])
+        .select("isArchived")
+        .get_json()
+        .await
+        .with_context(|| format!("Failed to fetch the team {}", group.id))?;
+    let owners: Vec<Value> = client
+        .path(&["groups", &group.id, "owners"])
+        .select("id")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the owners of {}", group.id))?;
+    let members: Vec<Member> = client
+        .path(&["groups", &group.id, "members", "microsoft.graph.user"])
+        .select("userPrincipalName,mail,userType")
+        .top(999)
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to fetch the members of {}", group.id))?;
+
+    let guests: Vec<&Member> = members
+        .iter()
+        .filter(|member| member.user_type.as_deref() == Some("Guest"))
+        .collect();
+    let guest_domains: BTreeSet<String> = guests.iter().filter_map(|guest| guest_domain(guest)).collect();
+    let unexpected_domains: Vec<String> = if allowed.is_empty() {
+        Vec::new()
+    } else {
+        guest_domains.iter().filter(|domain| !is_allowed(domain, allowed)).cloned().collect()
+    };
+    let mut issues = Vec::new();
+    if owners.is_empty() {
+        issues.push("no owners");
+    }
+    if !unexpected_domains.is_empty() {
+        issues.push("unexpected guest domains");
+    }
+    Ok(TeamRow {
+        id: group.id.clone(),
+        display_name: group.display_name.clone().unwrap_or_default(),
+        visibility: group.visibility.clone().unwrap_or_default(),
+        archived: team.is_archived,
+        created_date_time: group.created_date_time,
+        last_activity: activity.last_activity(&group.id),
+        owners: owners.len(),
+        members: members.len() - guests.len(),
+        guests: guests.len(),
+        guest_domains: guest_domains.into_iter().collect(),
+        unexpected_domains,
+        issues,
+    })
+}
+
+async fn scan(
+    client: &GraphClient,
+    activity: &TeamActivity,
+    allowed: &[String],
+    concurrency: usize,
+    shutdown: &ShutdownSignal,
+) -> Result<Scan> {
+    let phase = client.stats().phase("list teams");
+    let groups = list_teams(client).await?;
+    drop(phase);
+    info!("Reading the owners and members of {} teams", groups.len());
+
+    let _phase = client.stats().phase("read teams");
+    let results: Vec<Result<TeamRow>> = stream::iter(&groups)
+        .take_while(|_| std::future::ready(!shutdown.is_requested()))
+        .map(|group| read_team(client, group, activity, allowed))
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let checked = results.len();
+    let mut teams = Vec::new();
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(team) => teams.push(team),
+            Err(e) => {
+                warn!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    teams.sort_by_key(|team| team.display_name.to_lowercase());
+    Ok(Scan {
+        teams,
+        total: groups.len(),
+        checked,
+        failed,
+        interrupted: checked < groups.len(),
+    })
+}
+
+fn render_report(teams: &[&TeamRow], all_teams: &[TeamRow], output: &str, run_stats: &Summary) -> Result<String> {
+    let mut out = String::new();
+    let count = |issue: &str| all_teams.iter().filter(|team| team.issues.contains(&issue)).count();
+    let (ownerless, unexpected) = (count("no owners"), count("unexpected guest domains"));
+    match output {
+        "json" => {
+            out = serde_json::to_string_pretty(&json!({
+                "teams": teams,
+                "totalTeams": all_teams.len(),
+                "archived": all_teams.iter().filter(|team| team.archived).count(),
+                "withoutOwners": ownerless,
+                "withUnexpectedGuests": unexpected,
+                "runStats": run_stats,
+            }))?;
+            out.push('\n');
+        }
+        "csv" => {
+            writeln!(
+                out,
+                "id,displayName,visibility,archived,createdDateTime,lastActivity,owners,members,guests,guestDomains,unexpectedDomains,issues"
+            )?;
+            for team in teams {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{}",
+                    team.id,
+                    csv::field(&team.display_name),
+                    team.visibility,
+                    team.archived,
+                    team.created_date_time
+                        .map(|created| created.to_rfc3339_opts(SecondsFormat::Secs, true))
+                        .unwrap_or_default(),
+                    team.last_activity.map(|last| last.to_string()).unwrap_or_default(),
+                    team.owners,
+                    team.members,
+                    team.guests,
+                    csv::field(&team.guest_domains.join(";")),
+                    csv::field(&team.unexpected_domains.join(";")),
+                    csv::field(&team.issues.join(";"))
+                )?;
+            }
+        }
+        _ => {
+            writeln!(
+                out,
+                "{:<30} {:<10} {:<13} {:>6} {:>7} {:>6}  NOTES",
+                "TEAM", "VISIBILITY", "LAST ACTIVITY", "OWNERS", "MEMBERS", "GUESTS"
+            )?;
+            for team in teams {
+                let mut notes: Vec<String> = Vec::new();
+                if team.archived {
+                    notes.push("archived".to_string());
+                }
+                for issue in &team.issues {
+                    notes.push(match *issue {
+                        "unexpected guest domains" => format!("guests from {}", team.unexpected_domains.join(", ")),
+                        issue => issue.to_string(),
+                    });
+                }
+                let line = format!(
+                    "{:<30} {:<10} {:<13} {:>6} {:>7} {:>6}  {}",
+                    team.display_name,
+                    team.visibility,
+                    team.last_activity.map_or_else(|| "-".to_string(), |last| last.to_string()),
+                    team.owners,
+                    team.members,
+                    team.guests,
+                    notes.join(", ")
+                );
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+            writeln!(
+                out,
+                "{} teams ({} archived): {} without owners, {} with guests from unexpected domains",
+                all_teams.len(),
+                all_teams.iter().filter(|team| team.archived).count(),
+                ownerless,
+                unexpected
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting teams_inventory");
+    debug!("Configuration: {:?}", config);
+
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client_secret = env::var("CLIENT_SECRET").context("CLIENT_SECRET not set in .env file")?;
+    let client = GraphClient::from_client_credentials(&tenant_id, &client_id, &client_secret)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["Group.Read.All", "Reports.Read.All"])?;
+
+    let phase = client.stats().phase("read activity report");
+    let activity = TeamActivity::load(&client).await?;
+    drop(phase);
+
+    let shutdown = service::shutdown_signal();
+    let scan = scan(&client, &activity, &config.allowed_domain, config.concurrency as usize, &shutdown).await?;
+    let listed: Vec<&TeamRow> = scan
+        .teams
+        .iter()
+        .filter(|team| !config.flagged || !team.issues.is_empty())
+        .collect();
+    let run_stats = client.stats().summary();
+    print!("{}", render_report(&listed, &scan.teams, &config.output, &run_stats)?);
+    if config.output != "json" {
+        run_stats.print();
+    }
+
+    if scan.interrupted {
+        warn!("Interrupted after reading {} of {} teams; the report above is incomplete", scan.checked, scan.total);
+        return Err(anyhow::anyhow!("Inventory interrupted"));
+    }
+    if scan.failed > 0 {
+        warn!("{} of {} teams could not be read; they are missing from the report", scan.failed, scan.checked);
+        return Err(anyhow::anyhow!("{} of {} teams could not be read", scan.failed, scan.checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_common::golden;
+
+    #[tokio::test]
+    async fn reports_match_golden_files() {
+        let client = golden::fixture_client();
+        let activity = TeamActivity::load(&client).await.unwrap();
+        let allowed = vec!["fabrikam.com".to_string()];
+        let scan = scan(&client, &activity, &allowed, 2, &ShutdownSignal::never()).await.unwrap();
+        assert_eq!((scan.total, scan.checked, scan.failed, scan.interrupted), (5, 5, 1, false));
+        let listed: Vec<&TeamRow> = scan.teams.iter().collect();
+        golden::assert_reports("report", &["text", "csv", "json"], |output| render_report(&listed, &scan.teams, output, &Summary::fixture()));
+    }
+}
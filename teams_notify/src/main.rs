@@ -0,0 +1,272 @@
+use anyhow::{Context, Result};
+use clap::{ArgGroup, Parser};
+use dotenv::dotenv;
+use env_logger::Builder;
+use graph_common::{client, GraphClient};
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[command(name = "teams_notify")]
+#[command(author = "Bryan Abbott <bryan.abbott01@pm.me>")]
+#[command(version = "1.0")]
+#[command(about = "Posts a message or an Adaptive Card to a Microsoft Teams channel using the Microsoft API")]
+#[command(group(ArgGroup::new("content").required(true).args(["message", "file", "card"])))]
+struct AppConfig {
+    #[arg(short, long, help = "Team id, or the display name of a team you are a member of")]
+    team: String,
+
+    #[arg(short = 'C', long, help = "Channel id, or the display name of a channel in the team")]
+    channel: String,
+
+    #[arg(short, long, help = "Message text")]
+    message: Option<String>,
+
+    #[arg(short, long, value_name = "FILE", help = "Read the message from a text or .html file, or from stdin with -")]
+    file: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "Post this Adaptive Card JSON file, or the card on stdin with -")]
+    card: Option<String>,
+
+    #[arg(long, conflicts_with = "card", help = "Keep the line breaks and spacing of a text message, for tables such as audit reports")]
+    pre: bool,
+
+    #[arg(short, long, conflicts_with = "reply_to", help = "Subject of the new conversation")]
+    subject: Option<String>,
+
+    #[arg(long, default_value = "normal", value_parser = ["normal", "high", "urgent"], help = "Importance of the message")]
+    importance: String,
+
+    #[arg(long, value_name = "MESSAGE_ID", help = "Reply in the thread of this message instead of starting a new conversation")]
+    reply_to: Option<String>,
+
+    #[arg(short, long, default_value = "text", value_parser = ["text", "json"], help = "Output format")]
+    output: String,
+
+    #[arg(long, help = "Print the request without posting it")]
+    dry_run: bool,
+
+    #[arg(short, long, help = "Enable verbose logging")]
+    verbose: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Named {
+    id: String,
+    #[serde(rename = "displayName")]
+    display_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatMessage {
+    id: String,
+    #[serde(rename = "webUrl")]
+    web_url: Option<String>,
+}
+
+fn setup_logger(verbose: bool) {
+    let mut builder = Builder::from_default_env();
+    builder.filter_level(if verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    });
+    builder.init();
+}
+
+fn read_input(file: &str) -> Result<String> {
+    if file == "-" {
+        let mut content = String::new();
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Failed to read stdin")?;
+        Ok(content)
+    } else {
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))
+    }
+}
+
+fn to_html(text: &str, pre: bool) -> String {
+    let escaped = text
+        .trim_end()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    if pre {
+        format!("<pre>{}</pre>", escaped)
+    } else {
+        escaped.replace('\n', "<br>\n")
+    }
+}
+
+fn message_content(config: &AppConfig) -> Result<String> {
+    match (&config.message, &config.file) {
+        (Some(message), _) => Ok(to_html(message, config.pre)),
+        (None, Some(file)) => {
+            let content = read_input(file)?;
+            let html = Path::new(file)
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"));
+            Ok(if html { content } else { to_html(&content, config.pre) })
+        }
+        (None, None) => Err(anyhow::anyhow!("--message, --file, or --card is required")),
+    }
+}
+
+// The card travels as an attachment whose content is the card JSON as a string; the body only
+// holds a placeholder that references the attachment by id.
+fn card_message(card: &str) -> Result<(String, Value)> {
+    let card: Value = serde_json::from_str(card).context("The card is not valid JSON")?;
+    if card.get("type").and_then(Value::as_str) != Some("AdaptiveCard") {
+        return Err(anyhow::anyhow!("The card must be an object with \"type\": \"AdaptiveCard\""));
+    }
+    let content = serde_json::to_string(&card)?;
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    let id = format!("{:016x}", hasher.finish());
+    Ok((
+        format!("<attachment id=\"{}\"></attachment>", id),
+        json!({
+            "id": id,
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": content,
+        }),
+    ))
+}
+
+fn message_body(config: &AppConfig) -> Result<Value> {
+    let mut body = json!({ "importance": config.importance });
+    if let Some(card) = &config.card {
+        let (placeholder, attachment) = card_message(&read_input(card)?)?;
+        body["body"] = json!({ "contentType": "html", "content": placeholder });
+        body["attachments"] = json!([attachment]);
+    } else {
+        body["body"] = json!({ "contentType": "html", "content": message_content(config)? });
+    }
+    if let Some(subject) = &config.subject {
+        body["subject"] = json!(subject);
+    }
+    Ok(body)
+}
+
+fn looks_like_guid(value: &str) -> bool {
+    value.len() == 36 && value.chars().all(|c| c.is_ascii_hexdigit() || c == '-') && value.matches('-').count() == 4
+}
+
+fn find_by_name<'a>(items: &'a [Named], name: &str, kind: &str) -> Result<&'a Named> {
+    let matches: Vec<&Named> = items
+        .iter()
+        .filter(|item| item.display_name.as_deref().is_some_and(|display_name| display_name.eq_ignore_ascii_case(name)))
+        .collect();
+    match matches.as_slice() {
+        [item] => Ok(item),
+        [] => Err(anyhow::anyhow!("No {} named \"{}\"", kind, name)),
+        _ => Err(anyhow::anyhow!("{} {}s are named \"{}\"; use the id instead", matches.len(), kind, name)),
+    }
+}
+
+// Channel messages can only be posted with a delegated token, so a team name is looked up
+// among the teams the signed-in user has joined.
+async fn resolve_team(client: &GraphClient, team: &str) -> Result<String> {
+    if looks_like_guid(team) {
+        return Ok(team.to_string());
+    }
+    let teams: Vec<Named> = client
+        .path(&["me", "joinedTeams"])
+        .get_all()
+        .await
+        .context("Failed to list your teams")?;
+    let team = find_by_name(&teams, team, "team").context("Only teams you are a member of can be found by name")?;
+    Ok(team.id.clone())
+}
+
+async fn resolve_channel(client: &GraphClient, team_id: &str, channel: &str) -> Result<String> {
+    if channel.contains("@thread") {
+        return Ok(channel.to_string());
+    }
+    let channels: Vec<Named> = client
+        .path(&["teams", team_id, "channels"])
+        .select("id,displayName")
+        .get_all()
+        .await
+        .with_context(|| format!("Failed to list the channels of team {}", team_id))?;
+    Ok(find_by_name(&channels, channel, "channel")?.id.clone())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let config = AppConfig::parse();
+    setup_logger(config.verbose);
+
+    info!("Starting teams_notify");
+    debug!("Configuration: {:?}", config);
+
+    let body = message_body(&config)?;
+    let tenant_id = env::var("TENANT_ID").context("TENANT_ID not set in .env file")?;
+    let client_id = env::var("CLIENT_ID").context("CLIENT_ID not set in .env file")?;
+    let client = GraphClient::from_device_code(&tenant_id, &client_id)
+        .await
+        .context("Failed to obtain access token")?;
+    client.require_permissions(&["ChannelMessage.Send"])?;
+
+    let team_id = resolve_team(&client, &config.team).await?;
+    let channel_id = resolve_channel(&client, &team_id, &config.channel).await?;
+    let mut request = client.path(&["teams", &team_id, "channels", &channel_id, "messages"]);
+    if let Some(reply_to) = &config.reply_to {
+        request = request.segment(reply_to).segment("replies");
+    }
+    if config.dry_run {
+        println!("POST {}", request.url());
+        println!("{}", serde_json::to_string_pretty(&body)?);
+        return Ok(());
+    }
+
+    let response = request.post_json(&body).await?;
+    let message: ChatMessage = client::parse_json(response)
+        .await
+        .with_context(|| format!("Failed to post to {} in {}", config.channel, config.team))?;
+    if config.output == "json" {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "id": message.id,
+                "teamId": team_id,
+                "channelId": channel_id,
+                "webUrl": message.web_url,
+            }))?
+        );
+    } else {
+        println!("OK      posted to {} in {}", config.channel, config.team);
+        println!("id: {}", message.id);
+        if let Some(web_url) = &message.web_url {
+            println!("link: {}", web_url);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_adaptive_card_in_attachment() {
+        let card = r#"{ "type": "AdaptiveCard", "version": "1.4", "body": [{ "type": "TextBlock", "text": "3 stale devices" }] }"#;
+        let (placeholder, attachment) = card_message(card).unwrap();
+        let id = attachment["id"].as_str().unwrap();
+        assert_eq!(placeholder, format!("<attachment id=\"{}\"></attachment>", id));
+        assert_eq!(attachment["contentType"], "application/vnd.microsoft.card.adaptive");
+        let content: Value = serde_json::from_str(attachment["content"].as_str().unwrap()).unwrap();
+        assert_eq!(content["body"][0]["text"], "3 stale devices");
+        assert!(card_message(r#"{ "type": "MessageCard" }"#).is_err());
+
+        assert_eq!(to_html("OK  <b>\n", true), "<pre>OK  &lt;b&gt;</pre>");
+        assert_eq!(to_html("line 1\nline 2", false), "line 1<br>\nline 2");
+    }
+}